@@ -0,0 +1,133 @@
+use std::hint::black_box;
+
+use criterion::{
+  criterion_group,
+  criterion_main,
+  BenchmarkId,
+  Criterion,
+  Throughput,
+};
+use rust_crate_template::ImageBuffer;
+
+const RESOLUTIONS: [(usize, usize); 3] =
+  [(640, 480), (1920, 1080), (3840, 2160)];
+
+fn label((width, height): (usize, usize)) -> String {
+  format!("{width}x{height}")
+}
+
+macro_rules! bench_fills {
+  ($c:expr, $t:ty, $name:literal, $pel:expr, $image_pel:ty) => {{
+    let mut group = $c.benchmark_group(concat!("fill/", $name));
+    for res in RESOLUTIONS {
+      let (width, height) = res;
+      group.throughput(Throughput::Elements((width * height) as u64));
+      group.bench_with_input(
+        BenchmarkId::new("empty", label(res)),
+        &res,
+        |b, _| {
+          b.iter(|| black_box(ImageBuffer::<$t, 4, true>::empty(width, height)))
+        },
+      );
+      group.bench_with_input(
+        BenchmarkId::new("with_val", label(res)),
+        &res,
+        |b, _| {
+          b.iter(|| {
+            black_box(ImageBuffer::<$t, 4, true>::with_val(
+              &$pel, width, height,
+            ))
+          })
+        },
+      );
+      group.bench_with_input(
+        BenchmarkId::new("with_data", label(res)),
+        &res,
+        |b, _| {
+          let data = vec![<$t>::default(); width * height * 4];
+          b.iter(|| {
+            black_box(
+              ImageBuffer::<$t, 4, true>::with_data(
+                data.clone(),
+                width,
+                height,
+              )
+              .unwrap(),
+            )
+          })
+        },
+      );
+      group.bench_with_input(
+        BenchmarkId::new("image::from_pixel", label(res)),
+        &res,
+        |b, _| {
+          b.iter(|| {
+            black_box(image::ImageBuffer::<$image_pel, Vec<$t>>::from_pixel(
+              width as u32,
+              height as u32,
+              image::Rgba($pel),
+            ))
+          })
+        },
+      );
+    }
+    group.finish();
+  }};
+}
+
+macro_rules! bench_iteration {
+  ($c:expr, $t:ty, $name:literal, $val:expr, $image_pel:ty) => {{
+    let mut group = $c.benchmark_group(concat!("iterate/", $name));
+    for res in RESOLUTIONS {
+      let (width, height) = res;
+      group.throughput(Throughput::Elements((width * height) as u64));
+      group.bench_with_input(
+        BenchmarkId::new("iter_no_alpha_mut", label(res)),
+        &res,
+        |b, _| {
+          let mut image = ImageBuffer::<$t, 4, true>::empty(width, height);
+          b.iter(|| {
+            for pel in image.iter_no_alpha_mut() {
+              pel[0] = $val;
+              pel[1] = $val;
+              pel[2] = $val;
+            }
+          })
+        },
+      );
+      group.bench_with_input(
+        BenchmarkId::new("image::pixels_mut", label(res)),
+        &res,
+        |b, _| {
+          let mut image = image::ImageBuffer::<$image_pel, Vec<$t>>::new(
+            width as u32,
+            height as u32,
+          );
+          b.iter(|| {
+            for pel in image.pixels_mut() {
+              pel[0] = $val;
+              pel[1] = $val;
+              pel[2] = $val;
+            }
+          })
+        },
+      );
+    }
+    group.finish();
+  }};
+}
+
+fn fills(c: &mut Criterion) {
+  bench_fills!(c, u8, "u8", [0u8, 0, 0, 255], image::Rgba<u8>);
+  bench_fills!(c, u16, "u16", [0u16, 0, 0, 65535], image::Rgba<u16>);
+  bench_fills!(c, f32, "f32", [0f32, 0.0, 0.0, 1.0], image::Rgba<f32>);
+}
+
+fn iteration(c: &mut Criterion) {
+  bench_iteration!(c, u8, "u8", black_box(128u8), image::Rgba<u8>);
+  bench_iteration!(c, u16, "u16", black_box(32768u16), image::Rgba<u16>);
+  bench_iteration!(c, f32, "f32", black_box(0.5f32), image::Rgba<f32>);
+}
+
+criterion_group!(benches, fills, iteration);
+criterion_main!(benches);
@@ -0,0 +1,86 @@
+use std::hint::black_box;
+
+use criterion::{
+  criterion_group,
+  criterion_main,
+  BenchmarkId,
+  Criterion,
+  Throughput,
+};
+use image::DynamicImage;
+use rust_crate_template::{color_space::rgb_to_cielab, ImageBuffer};
+
+const RESOLUTIONS: [(usize, usize); 3] =
+  [(640, 480), (1920, 1080), (3840, 2160)];
+
+fn label((width, height): (usize, usize)) -> String {
+  format!("{width}x{height}")
+}
+
+macro_rules! bench_as_other {
+  ($group:expr, $res:expr, $from:ty, $to:ty, $name:literal) => {{
+    let (width, height) = $res;
+    let image = ImageBuffer::<$from, 4, true>::empty(width, height);
+    $group.bench_with_input(
+      BenchmarkId::new($name, label($res)),
+      &$res,
+      |b, _| b.iter(|| black_box(image.as_other::<$to, 4, true>())),
+    );
+  }};
+}
+
+fn component_casts(c: &mut Criterion) {
+  let mut group = c.benchmark_group("convert/as_other");
+  for res in RESOLUTIONS {
+    let (width, height) = res;
+    group.throughput(Throughput::Elements((width * height) as u64));
+    bench_as_other!(group, res, u8, u16, "u8->u16");
+    bench_as_other!(group, res, u8, f32, "u8->f32");
+    bench_as_other!(group, res, u16, f32, "u16->f32");
+    bench_as_other!(group, res, f32, u8, "f32->u8");
+
+    let dynamic = DynamicImage::new_rgba8(width as u32, height as u32);
+    group.bench_with_input(
+      BenchmarkId::new("image::to_rgba16", label(res)),
+      &res,
+      |b, _| b.iter(|| black_box(dynamic.to_rgba16())),
+    );
+    group.bench_with_input(
+      BenchmarkId::new("image::to_rgba32f", label(res)),
+      &res,
+      |b, _| b.iter(|| black_box(dynamic.to_rgba32f())),
+    );
+  }
+  group.finish();
+}
+
+fn color_spaces(c: &mut Criterion) {
+  let mut group = c.benchmark_group("convert/rgb_to_cielab");
+  for res in RESOLUTIONS {
+    let (width, height) = res;
+    group.throughput(Throughput::Elements((width * height) as u64));
+    macro_rules! bench_lab {
+      ($t:ty, $name:literal, $pel:expr) => {{
+        let image = ImageBuffer::<$t, 3, false>::with_val(&$pel, width, height);
+        group.bench_with_input(
+          BenchmarkId::new($name, label(res)),
+          &res,
+          |b, _| {
+            b.iter(|| {
+              let lab: ImageBuffer<f32, 3, false> =
+                image.map_into(&mut |pel| rgb_to_cielab(pel));
+              black_box(lab)
+            })
+          },
+        );
+      }};
+    }
+    bench_lab!(u8, "u8", [12u8, 34, 56]);
+    bench_lab!(u16, "u16", [12u16, 34, 56]);
+    bench_lab!(f32, "f32", [12f32, 34.0, 56.0]);
+  }
+  group.finish();
+}
+
+criterion_group!(benches, component_casts, color_spaces);
+criterion_main!(benches);
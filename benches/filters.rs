@@ -0,0 +1,133 @@
+use std::hint::black_box;
+
+use criterion::{
+  criterion_group,
+  criterion_main,
+  BenchmarkId,
+  Criterion,
+  Throughput,
+};
+use image::DynamicImage;
+use rust_crate_template::{
+  image_buffer::BorderMode,
+  ops::blur::gaussian_blur,
+  ImageBuffer,
+  PixelContainer,
+};
+
+const RESOLUTIONS: [(usize, usize); 3] =
+  [(640, 480), (1920, 1080), (3840, 2160)];
+
+fn label((width, height): (usize, usize)) -> String {
+  format!("{width}x{height}")
+}
+
+const BOX_3X3: [f32; 9] = [1.0 / 9.0; 9];
+
+macro_rules! bench_box_blur {
+  ($group:expr, $res:expr, $t:ty, $name:literal) => {{
+    let (width, height) = $res;
+    let image = ImageBuffer::<$t, 4, true>::empty(width, height);
+    $group.bench_with_input(
+      BenchmarkId::new($name, label($res)),
+      &$res,
+      |b, _| {
+        b.iter(|| {
+          black_box(image.map_window::<3, 3>(
+            BorderMode::Clamp,
+            &mut |window| {
+              let mut sum = [0f32; 4];
+              for pel in window.iter().flatten() {
+                for (s, c) in sum.iter_mut().zip(pel.iter()) {
+                  *s += *c as f32;
+                }
+              }
+              sum.map(|s| (s / 9.0) as $t)
+            },
+          ))
+        })
+      },
+    );
+  }};
+}
+
+fn box_blur(c: &mut Criterion) {
+  let mut group = c.benchmark_group("blur/box_3x3");
+  group.sample_size(10);
+  for res in RESOLUTIONS {
+    let (width, height) = res;
+    group.throughput(Throughput::Elements((width * height) as u64));
+    bench_box_blur!(group, res, u8, "u8");
+    bench_box_blur!(group, res, u16, "u16");
+    bench_box_blur!(group, res, f32, "f32");
+
+    let dynamic = DynamicImage::new_rgba8(width as u32, height as u32);
+    group.bench_with_input(
+      BenchmarkId::new("image::filter3x3/u8", label(res)),
+      &res,
+      |b, _| b.iter(|| black_box(dynamic.filter3x3(&BOX_3X3))),
+    );
+    let dynamic = DynamicImage::new_rgba16(width as u32, height as u32);
+    group.bench_with_input(
+      BenchmarkId::new("image::filter3x3/u16", label(res)),
+      &res,
+      |b, _| b.iter(|| black_box(dynamic.filter3x3(&BOX_3X3))),
+    );
+    let dynamic = DynamicImage::new_rgba32f(width as u32, height as u32);
+    group.bench_with_input(
+      BenchmarkId::new("image::filter3x3/f32", label(res)),
+      &res,
+      |b, _| b.iter(|| black_box(dynamic.filter3x3(&BOX_3X3))),
+    );
+  }
+  group.finish();
+}
+
+const SIGMA: f32 = 2.0;
+
+macro_rules! bench_gaussian_blur {
+  ($group:expr, $res:expr, $t:ty, $name:literal) => {{
+    let (width, height) = $res;
+    let image = ImageBuffer::<$t, 4, true>::empty(width, height);
+    $group.bench_with_input(
+      BenchmarkId::new($name, label($res)),
+      &$res,
+      |b, _| b.iter(|| black_box(gaussian_blur(&image, f64::from(SIGMA)))),
+    );
+  }};
+}
+
+fn gaussian(c: &mut Criterion) {
+  let mut group = c.benchmark_group("blur/gaussian_2");
+  group.sample_size(10);
+  for res in RESOLUTIONS {
+    let (width, height) = res;
+    group.throughput(Throughput::Elements((width * height) as u64));
+    bench_gaussian_blur!(group, res, u8, "u8");
+    bench_gaussian_blur!(group, res, u16, "u16");
+    bench_gaussian_blur!(group, res, f32, "f32");
+
+    let dynamic = DynamicImage::new_rgba8(width as u32, height as u32);
+    group.bench_with_input(
+      BenchmarkId::new("image::blur/u8", label(res)),
+      &res,
+      |b, _| b.iter(|| black_box(dynamic.blur(SIGMA))),
+    );
+    let dynamic = DynamicImage::new_rgba16(width as u32, height as u32);
+    group.bench_with_input(
+      BenchmarkId::new("image::blur/u16", label(res)),
+      &res,
+      |b, _| b.iter(|| black_box(dynamic.blur(SIGMA))),
+    );
+    let dynamic = DynamicImage::new_rgba32f(width as u32, height as u32);
+    group.bench_with_input(
+      BenchmarkId::new("image::blur/f32", label(res)),
+      &res,
+      |b, _| b.iter(|| black_box(dynamic.blur(SIGMA))),
+    );
+  }
+  group.finish();
+}
+
+criterion_group!(benches, box_blur, gaussian);
+criterion_main!(benches);
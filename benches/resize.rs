@@ -0,0 +1,92 @@
+use std::hint::black_box;
+
+use criterion::{
+  criterion_group,
+  criterion_main,
+  BenchmarkId,
+  Criterion,
+  Throughput,
+};
+use image::{imageops::FilterType, DynamicImage};
+use rust_crate_template::{ops::transform, ImageBuffer};
+
+const RESOLUTIONS: [(usize, usize); 3] =
+  [(640, 480), (1920, 1080), (3840, 2160)];
+
+fn label((width, height): (usize, usize)) -> String {
+  format!("{width}x{height}")
+}
+
+macro_rules! bench_resize {
+  ($group:expr, $res:expr, $t:ty, $name:literal) => {{
+    let (width, height) = $res;
+    let image = ImageBuffer::<$t, 4, true>::empty(width, height);
+    $group.bench_with_input(
+      BenchmarkId::new($name, label($res)),
+      &$res,
+      |b, _| {
+        b.iter(|| black_box(transform::resize(&image, width / 2, height / 2)))
+      },
+    );
+  }};
+}
+
+fn resize_half(c: &mut Criterion) {
+  let mut group = c.benchmark_group("resize/half");
+  group.sample_size(10);
+  for res in RESOLUTIONS {
+    let (width, height) = res;
+    group.throughput(Throughput::Elements((width * height) as u64));
+    bench_resize!(group, res, u8, "u8");
+    bench_resize!(group, res, u16, "u16");
+    bench_resize!(group, res, f32, "f32");
+
+    let (half_width, half_height) = (width as u32 / 2, height as u32 / 2);
+    let dynamic = DynamicImage::new_rgba8(width as u32, height as u32);
+    group.bench_with_input(
+      BenchmarkId::new("image::resize_exact/u8", label(res)),
+      &res,
+      |b, _| {
+        b.iter(|| {
+          black_box(dynamic.resize_exact(
+            half_width,
+            half_height,
+            FilterType::Triangle,
+          ))
+        })
+      },
+    );
+    let dynamic = DynamicImage::new_rgba16(width as u32, height as u32);
+    group.bench_with_input(
+      BenchmarkId::new("image::resize_exact/u16", label(res)),
+      &res,
+      |b, _| {
+        b.iter(|| {
+          black_box(dynamic.resize_exact(
+            half_width,
+            half_height,
+            FilterType::Triangle,
+          ))
+        })
+      },
+    );
+    let dynamic = DynamicImage::new_rgba32f(width as u32, height as u32);
+    group.bench_with_input(
+      BenchmarkId::new("image::resize_exact/f32", label(res)),
+      &res,
+      |b, _| {
+        b.iter(|| {
+          black_box(dynamic.resize_exact(
+            half_width,
+            half_height,
+            FilterType::Triangle,
+          ))
+        })
+      },
+    );
+  }
+  group.finish();
+}
+
+criterion_group!(benches, resize_half);
+criterion_main!(benches);
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_crate_template::io::{fuzz, ImageFormat};
+
+fuzz_target!(|data: &[u8]| {
+  let _ = fuzz::decode(Some(ImageFormat::Tiff), data);
+});
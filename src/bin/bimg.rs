@@ -0,0 +1,118 @@
+//! `bimg`: a small command-line front end to the crate, built only on its
+//! public API.
+//!
+//! ```text
+//! bimg info <input>
+//! bimg convert <input> <output>
+//! bimg resize <input> <output> <width> <height>
+//! bimg crop <input> <output> <x> <y> <width> <height>
+//! bimg blur <input> <output> <sigma>
+//! bimg compare <a> <b>
+//! ```
+//!
+//! Output formats follow the file extension.
+
+use std::{process::ExitCode, str::FromStr};
+
+use rust_crate_template::{
+  io::DecodeOptions,
+  ops::{blur, compare, transform},
+  Image,
+  ImageBuffer,
+};
+
+const USAGE: &str = "\
+Usage:
+  bimg info <input>
+  bimg convert <input> <output>
+  bimg resize <input> <output> <width> <height>
+  bimg crop <input> <output> <x> <y> <width> <height>
+  bimg blur <input> <output> <sigma>
+  bimg compare <a> <b>";
+
+type Rgba = ImageBuffer<f32, 4, true>;
+
+fn open(path: &str) -> Result<Image, String> {
+  Image::open(path, &DecodeOptions::default())
+    .map_err(|e| format!("{path}: {e}"))
+}
+
+fn number<T: FromStr>(arg: &str) -> Result<T, String> {
+  arg
+    .parse()
+    .map_err(|_| format!("Not a valid number: {arg}"))
+}
+
+/// Applies `op` to the RGBA pixels of `input` and saves the result
+fn process(
+  input: &str,
+  output: &str,
+  op: impl FnOnce(&Rgba) -> Result<Rgba, String>,
+) -> Result<(), String> {
+  let image = open(input)?;
+  let pixels = image.to_rgba_f32().map_err(|e| e.to_string())?;
+  Image::from_rgba_f32_like(op(&pixels)?, &image)
+    .save(output)
+    .map_err(|e| format!("{output}: {e}"))
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+  let args: Vec<&str> = args.iter().map(String::as_str).collect();
+  match args.as_slice() {
+    ["info", input] => {
+      let image = open(input)?;
+      println!(
+        "{input}: {}x{} {} {:?}",
+        image.width(),
+        image.height(),
+        image.color_space_name(),
+        image.component_type()
+      );
+      Ok(())
+    }
+    ["convert", input, output] =>
+      open(input)?
+        .save(output)
+        .map_err(|e| format!("{output}: {e}")),
+    ["resize", input, output, width, height] => {
+      let (width, height) = (number(width)?, number(height)?);
+      process(input, output, |pixels| {
+        Ok(transform::resize(pixels, width, height))
+      })
+    }
+    ["crop", input, output, x, y, width, height] => {
+      let (x, y) = (number(x)?, number(y)?);
+      let (width, height) = (number(width)?, number(height)?);
+      process(input, output, |pixels| {
+        transform::crop(pixels, x, y, width, height).map_err(|e| e.to_string())
+      })
+    }
+    ["blur", input, output, sigma] => {
+      let sigma = number(sigma)?;
+      process(input, output, |pixels| {
+        Ok(blur::gaussian_blur(pixels, sigma))
+      })
+    }
+    ["compare", a, b] => {
+      let a = open(a)?.to_rgba_f32().map_err(|e| e.to_string())?;
+      let b = open(b)?.to_rgba_f32().map_err(|e| e.to_string())?;
+      let mse = compare::mse(&a, &b).map_err(|e| e.to_string())?;
+      let psnr = compare::psnr(&a, &b).map_err(|e| e.to_string())?;
+      println!("MSE:  {mse:.6}");
+      println!("PSNR: {psnr:.2} dB");
+      Ok(())
+    }
+    _ => Err(USAGE.to_string()),
+  }
+}
+
+fn main() -> ExitCode {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  match run(&args) {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(message) => {
+      eprintln!("{message}");
+      ExitCode::FAILURE
+    }
+  }
+}
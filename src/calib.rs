@@ -0,0 +1,1195 @@
+//! Camera calibration: finding a checkerboard or ChArUco target in photos
+//! and solving for the camera's focal length, principal point and lens
+//! distortion from several views of it, with Zhang's method refined by
+//! bundle adjustment.
+//!
+//! A plain checkerboard must be wholly in view to be found. A
+//! [`CharucoBoard`], whose white squares hold fiducial markers, identifies
+//! each corner, so partial and occluded views still count.
+//!
+//! The resulting [`Calibration`] removes lens distortion with
+//! [`undistort`], and its intrinsics give the scale needed to turn
+//! [`disparity`](crate::ops::stereo::disparity) into metric depth.
+
+use std::collections::{HashMap, VecDeque};
+
+use num_traits::ToPrimitive;
+
+use crate::{
+  codes::fiducial::{self, Dictionary},
+  error::{Error, Result},
+  ops::{
+    blur::gaussian_blur,
+    meter::luminance,
+    register::{sample, solve},
+  },
+  pixel::{component_from_f64, PixelContainer},
+  stitch::homography,
+  ImageBuffer,
+};
+
+/// Smoothing applied before looking for corners, in pixels
+const CORNER_SIGMA: f64 = 1.5;
+
+/// Radius of the circle sampled around a candidate corner to check that
+/// four squares meet there. Squares must be somewhat more than twice this
+/// size in the image to be found.
+const CORNER_RING: f64 = 4.0;
+
+/// Half-size of the window used to refine corners to subpixel precision
+const REFINE_RADIUS: isize = 4;
+
+/// Levenberg-Marquardt iterations when refining a calibration
+const REFINE_ITERATIONS: usize = 100;
+
+/// The focal lengths and principal point of a pinhole camera, in pixels.
+/// A point `(x, y, z)` in front of the camera lands at
+/// `(fx x / z + cx, fy y / z + cy)`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Intrinsics {
+  pub fx: f64,
+  pub fy: f64,
+  pub cx: f64,
+  pub cy: f64,
+}
+
+impl Intrinsics {
+  /// The camera matrix `[[fx, 0, cx], [0, fy, cy], [0, 0, 1]]`
+  pub fn matrix(&self) -> [[f64; 3]; 3] {
+    [
+      [self.fx, 0.0, self.cx],
+      [0.0, self.fy, self.cy],
+      [0.0, 0.0, 1.0],
+    ]
+  }
+}
+
+/// Brown-Conrady lens distortion: radial terms `k1`, `k2` and `k3` and
+/// tangential terms `p1` and `p2`, in the order and convention OpenCV uses
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Distortion {
+  pub k1: f64,
+  pub k2: f64,
+  pub p1: f64,
+  pub p2: f64,
+  pub k3: f64,
+}
+
+impl Distortion {
+  /// Distorts a point in normalized camera coordinates, `(x / z, y / z)`
+  pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+    let r2 = x * x + y * y;
+    let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+    (
+      x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x),
+      y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y,
+    )
+  }
+}
+
+/// The result of [`calibrate`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+  pub intrinsics: Intrinsics,
+  pub distortion: Distortion,
+  /// Root mean square distance, in pixels, between the detected corners
+  /// and where the calibration projects them
+  pub rms_error:  f64,
+}
+
+impl Calibration {
+  /// Where a point in normalized camera coordinates, `(x / z, y / z)`,
+  /// lands in the image, lens distortion included
+  pub fn project(&self, x: f64, y: f64) -> (f64, f64) {
+    let (x, y) = self.distortion.apply(x, y);
+    let k = &self.intrinsics;
+    (k.fx * x + k.cx, k.fy * y + k.cy)
+  }
+
+  /// The inverse of [`project`](Self::project): the point in normalized
+  /// camera coordinates that lands at pixel `(u, v)`, found by fixed-point
+  /// iteration, which converges for the moderate distortion of most lenses
+  pub fn unproject(&self, u: f64, v: f64) -> (f64, f64) {
+    let k = &self.intrinsics;
+    let target = ((u - k.cx) / k.fx, (v - k.cy) / k.fy);
+    let mut point = target;
+    for _ in 0..20 {
+      let (dx, dy) = self.distortion.apply(point.0, point.1);
+      point = (point.0 + target.0 - dx, point.1 + target.1 - dy);
+    }
+    point
+  }
+}
+
+/// Finds the inner corners of a checkerboard with `pattern.0` columns and
+/// `pattern.1` rows of them, where four squares meet, to subpixel
+/// precision.
+///
+/// Corners are returned row by row, starting near the top-left and running
+/// rightward and downward where the board's orientation allows. Returns
+/// `None` unless exactly the expected grid is found, which needs the whole
+/// board in view with squares at least ten pixels across.
+pub fn find_checkerboard<C: PixelContainer>(
+  image: &C,
+  pattern: (usize, usize),
+) -> Option<Vec<(f64, f64)>> {
+  let (width, height) = (image.width(), image.height());
+  let (columns, rows) = pattern;
+  if columns < 2 || rows < 2 || width < 8 || height < 8 {
+    return None;
+  }
+  let luma = ImageBuffer::<f32, 1, false>::empty(width, height)
+    .map_indexed(&mut |x, y, _| [luminance::<C>(image.get_pixel(x, y)) as f32]);
+  let smooth: Vec<f64> = gaussian_blur(&luma, CORNER_SIGMA)
+    .components()
+    .iter()
+    .map(|&v| f64::from(v))
+    .collect();
+  let corners = corner_candidates(&smooth, width, height);
+  let grid = grow_grid(&corners)?;
+
+  // Fit the grid to the pattern, transposing it if needed
+  let (min_i, max_i) = grid.keys().fold((i32::MAX, i32::MIN), |(lo, hi), k| {
+    (lo.min(k.0), hi.max(k.0))
+  });
+  let (min_j, max_j) = grid.keys().fold((i32::MAX, i32::MIN), |(lo, hi), k| {
+    (lo.min(k.1), hi.max(k.1))
+  });
+  let size = ((max_i - min_i + 1) as usize, (max_j - min_j + 1) as usize);
+  if grid.len() != columns * rows
+    || (size != (columns, rows) && size != (rows, columns))
+  {
+    return None;
+  }
+  let at = |c: usize, r: usize| -> (f64, f64) {
+    let key = if size == (columns, rows) {
+      (min_i + c as i32, min_j + r as i32)
+    } else {
+      (min_i + r as i32, min_j + c as i32)
+    };
+    corners[grid[&key]]
+  };
+  let mut ordered: Vec<Vec<(f64, f64)>> = (0..rows)
+    .map(|r| (0..columns).map(|c| at(c, r)).collect())
+    .collect();
+  if ordered[0][columns - 1].0 < ordered[0][0].0 {
+    ordered.iter_mut().for_each(|row| row.reverse());
+  }
+  if ordered[rows - 1][0].1 < ordered[0][0].1 {
+    ordered.reverse();
+  }
+  Some(ordered.concat())
+}
+
+/// Saddle points of the smoothed luminance where four squares meet,
+/// refined to subpixel precision
+fn corner_candidates(
+  smooth: &[f64],
+  width: usize,
+  height: usize,
+) -> Vec<(f64, f64)> {
+  let at = |x: usize, y: usize| smooth[y * width + x];
+  // Saddle strength: the negated determinant of the Hessian
+  let mut response = vec![0.0; width * height];
+  for y in 1..height - 1 {
+    for x in 1..width - 1 {
+      let xx = at(x + 1, y) - 2.0 * at(x, y) + at(x - 1, y);
+      let yy = at(x, y + 1) - 2.0 * at(x, y) + at(x, y - 1);
+      let xy = (at(x + 1, y + 1) - at(x + 1, y - 1) - at(x - 1, y + 1)
+        + at(x - 1, y - 1))
+        / 4.0;
+      response[y * width + x] = xy * xy - xx * yy;
+    }
+  }
+  let strongest = response.iter().copied().fold(0.0, f64::max);
+  if strongest <= 0.0 {
+    return Vec::new();
+  }
+
+  let margin = CORNER_RING.ceil() as usize + 1;
+  let mut corners = Vec::new();
+  for y in margin..height.saturating_sub(margin) {
+    for x in margin..width.saturating_sub(margin) {
+      let r = response[y * width + x];
+      if r < 0.05 * strongest {
+        continue;
+      }
+      let is_peak = (y - 2..=y + 2).all(|ny| {
+        (x - 2..=x + 2).all(|nx| {
+          let other = response[ny * width + nx];
+          other < r || (other == r && (ny, nx) >= (y, x))
+        })
+      });
+      if !is_peak || !is_x_junction(smooth, width, height, x, y) {
+        continue;
+      }
+      if let Some(corner) = refine_corner(smooth, width, height, x, y) {
+        corners.push(corner);
+      }
+    }
+  }
+  corners
+}
+
+/// Whether the ring around `(x, y)` alternates between light and dark
+/// exactly twice, as around the corner shared by four squares but not at
+/// the board's outer edge
+fn is_x_junction(
+  smooth: &[f64],
+  width: usize,
+  height: usize,
+  x: usize,
+  y: usize,
+) -> bool {
+  let ring: Vec<f64> = (0..16)
+    .filter_map(|k| {
+      let angle = k as f64 * std::f64::consts::TAU / 16.0;
+      let (sx, sy) = (
+        x as f64 + CORNER_RING * angle.cos(),
+        y as f64 + CORNER_RING * angle.sin(),
+      );
+      sample(smooth, width, height, sx, sy)
+    })
+    .collect();
+  if ring.len() < 16 {
+    return false;
+  }
+  let (lo, hi) = ring
+    .iter()
+    .fold((f64::MAX, f64::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+  if hi - lo < 0.1 {
+    return false;
+  }
+  let mid = (lo + hi) / 2.0;
+  let changes = (0..16)
+    .filter(|&k| (ring[k] > mid) != (ring[(k + 1) % 16] > mid))
+    .count();
+  changes == 4
+}
+
+/// Moves a corner to the point where the gradients around it are all
+/// orthogonal to their offsets from it, the classic saddle-point
+/// refinement. Returns `None` if it drifts away.
+fn refine_corner(
+  smooth: &[f64],
+  width: usize,
+  height: usize,
+  x: usize,
+  y: usize,
+) -> Option<(f64, f64)> {
+  let at = |x: usize, y: usize| smooth[y * width + x];
+  let (mut qx, mut qy) = (x as f64, y as f64);
+  for _ in 0..10 {
+    let (cx, cy) = (qx.round() as isize, qy.round() as isize);
+    let mut a = [[0.0; 2]; 2];
+    let mut b = [0.0; 2];
+    for py in cy - REFINE_RADIUS..=cy + REFINE_RADIUS {
+      for px in cx - REFINE_RADIUS..=cx + REFINE_RADIUS {
+        if px < 1
+          || py < 1
+          || px as usize + 1 >= width
+          || py as usize + 1 >= height
+        {
+          continue;
+        }
+        let (ux, uy) = (px as usize, py as usize);
+        let gx = (at(ux + 1, uy) - at(ux - 1, uy)) / 2.0;
+        let gy = (at(ux, uy + 1) - at(ux, uy - 1)) / 2.0;
+        let g = [[gx * gx, gx * gy], [gx * gy, gy * gy]];
+        for r in 0..2 {
+          for c in 0..2 {
+            a[r][c] += g[r][c];
+          }
+          b[r] += g[r][0] * px as f64 + g[r][1] * py as f64;
+        }
+      }
+    }
+    let [nx, ny] = solve(a, b)?;
+    let moved = (nx - qx).abs() + (ny - qy).abs();
+    (qx, qy) = (nx, ny);
+    if moved < 1e-3 {
+      break;
+    }
+  }
+  let drift = (qx - x as f64).abs().max((qy - y as f64).abs());
+  (drift <= REFINE_RADIUS as f64 / 2.0).then_some((qx, qy))
+}
+
+/// Labels corners with grid coordinates by growing outward from the one
+/// nearest their centroid, predicting each neighbor's position from the
+/// spacing of those already placed so that perspective is followed
+fn grow_grid(corners: &[(f64, f64)]) -> Option<HashMap<(i32, i32), usize>> {
+  if corners.len() < 4 {
+    return None;
+  }
+  let distance = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1);
+  let n = corners.len() as f64;
+  let centroid = corners
+    .iter()
+    .fold((0.0, 0.0), |(sx, sy), &(x, y)| (sx + x / n, sy + y / n));
+  let seed = (0..corners.len())
+    .min_by(|&a, &b| {
+      distance(corners[a], centroid).total_cmp(&distance(corners[b], centroid))
+    })
+    .unwrap_or(0);
+
+  // The seed's nearest neighbor and the nearest one across from it span
+  // the grid
+  let mut nearest: Vec<usize> =
+    (0..corners.len()).filter(|&i| i != seed).collect();
+  nearest.sort_by(|&a, &b| {
+    distance(corners[a], corners[seed])
+      .total_cmp(&distance(corners[b], corners[seed]))
+  });
+  let offset = |i: usize| {
+    (
+      corners[i].0 - corners[seed].0,
+      corners[i].1 - corners[seed].1,
+    )
+  };
+  let u = offset(nearest[0]);
+  let v = nearest[1..].iter().map(|&i| offset(i)).find(|o| {
+    let cos = (u.0 * o.0 + u.1 * o.1) / (u.0.hypot(u.1) * o.0.hypot(o.1));
+    cos.abs() < 0.5
+  })?;
+
+  let mut grid = HashMap::from([((0, 0), seed)]);
+  let mut used = vec![false; corners.len()];
+  used[seed] = true;
+  let mut queue = VecDeque::from([(0, 0)]);
+  while let Some((i, j)) = queue.pop_front() {
+    let p = corners[grid[&(i, j)]];
+    for (di, dj) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+      let key = (i + di, j + dj);
+      if grid.contains_key(&key) {
+        continue;
+      }
+      let step = match grid.get(&(i - di, j - dj)) {
+        Some(&back) => (p.0 - corners[back].0, p.1 - corners[back].1),
+        None =>
+          (
+            di as f64 * u.0 + dj as f64 * v.0,
+            di as f64 * u.1 + dj as f64 * v.1,
+          ),
+      };
+      let predicted = (p.0 + step.0, p.1 + step.1);
+      let found = (0..corners.len()).filter(|&k| !used[k]).min_by(|&a, &b| {
+        distance(corners[a], predicted)
+          .total_cmp(&distance(corners[b], predicted))
+      });
+      if let Some(k) = found {
+        if distance(corners[k], predicted) < 0.35 * step.0.hypot(step.1) {
+          used[k] = true;
+          grid.insert(key, k);
+          queue.push_back(key);
+        }
+      }
+    }
+  }
+  Some(grid)
+}
+
+/// A ChArUco board: a checkerboard with a marker in each white square, so
+/// that its corners can be told apart and found when only part of the board
+/// is in view or some of it is covered.
+///
+/// The top-left square is black. Markers take the codes of `dictionary` in
+/// order, filling the white squares row by row, and are centered in them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CharucoBoard {
+  /// Squares across and down
+  pub squares:      (usize, usize),
+  /// Side of each marker as a fraction of the side of a square
+  pub marker_scale: f64,
+  pub dictionary:   Dictionary,
+}
+
+impl CharucoBoard {
+  /// A board of `squares` with markers from the original ArUco dictionary
+  /// filling seven tenths of each white square
+  pub fn new(squares: (usize, usize)) -> Self {
+    CharucoBoard {
+      squares,
+      marker_scale: 0.7,
+      dictionary: Dictionary::aruco_original(),
+    }
+  }
+
+  /// Inner corners across and down, where four squares meet
+  pub fn corners(&self) -> (usize, usize) {
+    (
+      self.squares.0.saturating_sub(1),
+      self.squares.1.saturating_sub(1),
+    )
+  }
+
+  /// Where inner corner `id` is on the board, in squares from the board's
+  /// top-left. Corners are numbered row by row.
+  pub fn corner_position(&self, id: usize) -> (f64, f64) {
+    let columns = self.corners().0.max(1);
+    ((id % columns + 1) as f64, (id / columns + 1) as f64)
+  }
+
+  /// The square holding marker `id`, if the board has that many markers
+  fn marker_square(&self, id: usize) -> Option<(usize, usize)> {
+    let (columns, rows) = self.squares;
+    (0..rows)
+      .flat_map(|r| (0..columns).map(move |c| (c, r)))
+      .filter(|(c, r)| (c + r) % 2 == 1)
+      .nth(id)
+  }
+
+  /// Draws the board for printing, `square` pixels per square. Leave a
+  /// white margin around it when placing it.
+  pub fn render(&self, square: usize) -> ImageBuffer<u8, 1, false> {
+    let (columns, rows) = self.squares;
+    let mut image =
+      ImageBuffer::with_val(&[255], columns * square, rows * square);
+    let side = (self.marker_scale * square as f64).round() as usize;
+    let cells = self.dictionary.size() + 2;
+    let mut id = 0;
+    for r in 0..rows {
+      for c in 0..columns {
+        let (left, top) = (c * square, r * square);
+        if (c + r) % 2 == 0 {
+          for y in top..top + square {
+            for x in left..left + square {
+              image.get_pixel_mut(x, y)[0] = 0;
+            }
+          }
+          continue;
+        }
+        let Some(marker) = self.dictionary.render(id, 1) else {
+          continue;
+        };
+        id += 1;
+        let inset = (square - side.min(square)) / 2;
+        for y in 0..side {
+          for x in 0..side {
+            let cell = marker.get_pixel(x * cells / side, y * cells / side);
+            *image.get_pixel_mut(left + inset + x, top + inset + y) = *cell;
+          }
+        }
+      }
+    }
+    image
+  }
+}
+
+/// Finds the inner corners of a ChArUco `board` in `image`, to subpixel
+/// precision, as pairs of a corner's id and its position.
+///
+/// The board's markers are detected first, and each corner is predicted
+/// from the markers in the squares next to it, then refined as a
+/// checkerboard corner. Only corners next to a detected marker are found,
+/// so unlike [`find_checkerboard`] this works with part of the board
+/// hidden. Markers should leave a margin of at least five pixels in their
+/// squares.
+pub fn find_charuco<C: PixelContainer>(
+  image: &C,
+  board: &CharucoBoard,
+) -> Vec<(usize, (f64, f64))> {
+  let (width, height) = (image.width(), image.height());
+  let mut markers: HashMap<(usize, usize), [(f64, f64); 4]> = HashMap::new();
+  for marker in fiducial::detect(image, &board.dictionary) {
+    if let Some(square) = board.marker_square(marker.id) {
+      markers.insert(square, marker.corners);
+    }
+  }
+  if markers.is_empty() {
+    return Vec::new();
+  }
+  let luma = ImageBuffer::<f32, 1, false>::empty(width, height)
+    .map_indexed(&mut |x, y, _| [luminance::<C>(image.get_pixel(x, y)) as f32]);
+  let smooth: Vec<f64> = gaussian_blur(&luma, CORNER_SIGMA)
+    .components()
+    .iter()
+    .map(|&v| f64::from(v))
+    .collect();
+
+  // Marker corners on the board, clockwise from the top-left as detected
+  let half = board.marker_scale / 2.0;
+  let outline = |(c, r): (usize, usize)| {
+    let (cx, cy) = (c as f64 + 0.5, r as f64 + 0.5);
+    [
+      (cx - half, cy - half),
+      (cx + half, cy - half),
+      (cx + half, cy + half),
+      (cx - half, cy + half),
+    ]
+  };
+  let (columns, rows) = board.corners();
+  (0..columns * rows)
+    .filter_map(|id| {
+      let (i, j) = (id % columns, id / columns);
+      let pairs: Vec<homography::Match> =
+        [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)]
+          .into_iter()
+          .filter_map(|square| Some((square, markers.get(&square)?)))
+          .flat_map(|(square, corners)| {
+            outline(square).into_iter().zip(*corners)
+          })
+          .collect();
+      if pairs.is_empty() {
+        return None;
+      }
+      let h = homography::fit(&pairs)?;
+      let (bx, by) = board.corner_position(id);
+      let (x, y) = homography::apply(&h, bx, by)?;
+      if !(x >= 0.0 && y >= 0.0 && x < width as f64 && y < height as f64) {
+        return None;
+      }
+      let corner = refine_corner(
+        &smooth,
+        width,
+        height,
+        x.round() as usize,
+        y.round() as usize,
+      )?;
+      Some((id, corner))
+    })
+    .collect()
+}
+
+/// Estimates the camera's intrinsics and lens distortion from the corners
+/// [`find_checkerboard`] found in several views of the same board, taken
+/// from different angles. `square_size` only scales the board and does not
+/// affect the result.
+///
+/// An initial pinhole estimate comes from the homography of each view, as
+/// in Zhang's method, assuming square pixels with no skew, and is then
+/// refined together with the distortion and each view's pose by
+/// Levenberg-Marquardt. Fails with fewer than two views, views with the
+/// wrong number of corners, or views too similar to constrain the camera.
+pub fn calibrate(
+  views: &[Vec<(f64, f64)>],
+  pattern: (usize, usize),
+  square_size: f64,
+) -> Result<Calibration> {
+  let (columns, rows) = pattern;
+  if views.len() < 2 {
+    return Err(Error::InvalidArgument(format!(
+      "Calibration needs at least two views, not {}",
+      views.len()
+    )));
+  }
+  if let Some(view) = views.iter().find(|v| v.len() != columns * rows) {
+    return Err(Error::InvalidArgument(format!(
+      "A view has {} corners instead of {columns}x{rows}",
+      view.len()
+    )));
+  }
+  let board: Vec<(f64, f64)> = (0..rows)
+    .flat_map(|r| (0..columns).map(move |c| (c as f64, r as f64)))
+    .map(|(c, r)| (c * square_size, r * square_size))
+    .collect();
+  let views: Vec<Vec<_>> = views
+    .iter()
+    .map(|view| board.iter().copied().zip(view.iter().copied()).collect())
+    .collect();
+  calibrate_pairs(&views)
+}
+
+/// Calibrates from views given as pairs of a point on the board and where
+/// it appears in the image
+fn calibrate_pairs(views: &[Vec<homography::Match>]) -> Result<Calibration> {
+  let degenerate =
+    || Error::InvalidArgument("The views do not constrain the camera".into());
+
+  let homographies = views
+    .iter()
+    .map(|pairs| homography::fit(pairs))
+    .collect::<Option<Vec<_>>>()
+    .ok_or_else(degenerate)?;
+  let points: Vec<(f64, f64)> =
+    views.iter().flatten().map(|&(_, image)| image).collect();
+  let intrinsics =
+    initial_intrinsics(&points, &homographies).ok_or_else(degenerate)?;
+
+  let mut params = vec![
+    intrinsics.fx,
+    intrinsics.fy,
+    intrinsics.cx,
+    intrinsics.cy,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+  ];
+  for h in &homographies {
+    params.extend(initial_pose(&intrinsics, h).ok_or_else(degenerate)?);
+  }
+  let residuals = |params: &[f64]| -> Vec<f64> {
+    let calibration = calibration_from(params);
+    views
+      .iter()
+      .enumerate()
+      .flat_map(|(v, view)| {
+        let pose = &params[9 + 6 * v..][..6];
+        let rotation = rotation_from(&pose[..3]);
+        let calibration = &calibration;
+        view.iter().flat_map(move |&((bx, by), (ox, oy))| {
+          let point: [f64; 3] = [0, 1, 2]
+            .map(|k| rotation[k][0] * bx + rotation[k][1] * by + pose[3 + k]);
+          let (u, v) =
+            calibration.project(point[0] / point[2], point[1] / point[2]);
+          [u - ox, v - oy]
+        })
+      })
+      .collect()
+  };
+  let params = levenberg_marquardt(params, residuals);
+  let error = residuals(&params);
+  let mut calibration = calibration_from(&params);
+  if !params.iter().all(|p| p.is_finite()) {
+    return Err(degenerate());
+  }
+  calibration.rms_error = (error.iter().map(|e| e * e).sum::<f64>()
+    / (error.len() / 2) as f64)
+    .sqrt();
+  Ok(calibration)
+}
+
+/// [`calibrate`] from the corners [`find_charuco`] found in several views
+/// of `board`, whose squares are `square_size` across. Views may each show
+/// a different part of the board, but need at least four corners, not all
+/// in a line.
+pub fn calibrate_charuco(
+  views: &[Vec<(usize, (f64, f64))>],
+  board: &CharucoBoard,
+  square_size: f64,
+) -> Result<Calibration> {
+  if views.len() < 2 {
+    return Err(Error::InvalidArgument(format!(
+      "Calibration needs at least two views, not {}",
+      views.len()
+    )));
+  }
+  let (columns, rows) = board.corners();
+  if let Some(&(id, _)) =
+    views.iter().flatten().find(|(id, _)| *id >= columns * rows)
+  {
+    return Err(Error::InvalidArgument(format!(
+      "Corner {id} is not on the {columns}x{rows} corners of the board"
+    )));
+  }
+  let views: Vec<Vec<_>> = views
+    .iter()
+    .map(|view| {
+      view
+        .iter()
+        .map(|&(id, point)| {
+          let (bx, by) = board.corner_position(id);
+          ((bx * square_size, by * square_size), point)
+        })
+        .collect()
+    })
+    .collect();
+  calibrate_pairs(&views)
+}
+
+fn calibration_from(params: &[f64]) -> Calibration {
+  Calibration {
+    intrinsics: Intrinsics {
+      fx: params[0],
+      fy: params[1],
+      cx: params[2],
+      cy: params[3],
+    },
+    distortion: Distortion {
+      k1: params[4],
+      k2: params[5],
+      p1: params[6],
+      p2: params[7],
+      k3: params[8],
+    },
+    rms_error:  0.0,
+  }
+}
+
+/// Zhang's closed-form intrinsics from the homographies mapping the board
+/// into each view, with zero skew imposed. Image coordinates are first
+/// normalized by the spread of `points`, those of every view, to keep the
+/// system well conditioned.
+fn initial_intrinsics(
+  points: &[(f64, f64)],
+  homographies: &[homography::Homography],
+) -> Option<Intrinsics> {
+  let points = points.iter();
+  let n = points.len() as f64;
+  let (ox, oy) = points
+    .clone()
+    .fold((0.0, 0.0), |(sx, sy), &(x, y)| (sx + x / n, sy + y / n));
+  let scale = points
+    .map(|&(x, y)| (x - ox).abs().max((y - oy).abs()))
+    .fold(1e-12, f64::max);
+  let normalize = [
+    [1.0 / scale, 0.0, -ox / scale],
+    [0.0, 1.0 / scale, -oy / scale],
+    [0.0, 0.0, 1.0],
+  ];
+
+  // Each view gives two linear constraints on the image of the absolute
+  // conic, b = [B11, B12, B22, B13, B23, B33]
+  let constraint =
+    |h: &homography::Homography, i: usize, j: usize| -> [f64; 6] {
+      let (a, b) = ([0, 1, 2].map(|k| h[k][i]), [0, 1, 2].map(|k| h[k][j]));
+      [
+        a[0] * b[0],
+        a[0] * b[1] + a[1] * b[0],
+        a[1] * b[1],
+        a[2] * b[0] + a[0] * b[2],
+        a[2] * b[1] + a[1] * b[2],
+        a[2] * b[2],
+      ]
+    };
+  let mut rows = vec![[0.0, 1.0, 0.0, 0.0, 0.0, 0.0]];
+  for h in homographies {
+    let h = homography::multiply(&normalize, h);
+    let (h11, h12, h22) = (
+      constraint(&h, 0, 0),
+      constraint(&h, 0, 1),
+      constraint(&h, 1, 1),
+    );
+    rows.push(h12);
+    rows.push([0, 1, 2, 3, 4, 5].map(|k| h11[k] - h22[k]));
+  }
+  let mut normal = [[0.0; 6]; 6];
+  for row in &rows {
+    let length = row.iter().map(|v| v * v).sum::<f64>().sqrt().max(1e-300);
+    for r in 0..6 {
+      for c in 0..6 {
+        normal[r][c] += row[r] * row[c] / (length * length);
+      }
+    }
+  }
+  let b = smallest_eigenvector(normal)?;
+  let [b11, b12, b22, b13, b23, b33] = b;
+  let v0 = (b12 * b13 - b11 * b23) / (b11 * b22 - b12 * b12);
+  let lambda = b33 - (b13 * b13 + v0 * (b12 * b13 - b11 * b23)) / b11;
+  let alpha = (lambda / b11).sqrt();
+  let beta = (lambda * b11 / (b11 * b22 - b12 * b12)).sqrt();
+  let u0 = -b13 * alpha * alpha / lambda;
+  let intrinsics = Intrinsics {
+    fx: alpha * scale,
+    fy: beta * scale,
+    cx: u0 * scale + ox,
+    cy: v0 * scale + oy,
+  };
+  [intrinsics.fx, intrinsics.fy, intrinsics.cx, intrinsics.cy]
+    .iter()
+    .all(|v| v.is_finite())
+    .then_some(intrinsics)
+}
+
+/// The eigenvector of a symmetric positive semi-definite matrix with the
+/// smallest eigenvalue, by inverse iteration
+fn smallest_eigenvector<const N: usize>(
+  matrix: [[f64; N]; N],
+) -> Option<[f64; N]> {
+  let trace: f64 = (0..N).map(|i| matrix[i][i]).sum();
+  let mut shifted = matrix;
+  for (i, row) in shifted.iter_mut().enumerate() {
+    row[i] += trace * 1e-12;
+  }
+  let mut x = [1.0; N];
+  for _ in 0..50 {
+    let next = solve(shifted, x)?;
+    let length = next.iter().map(|v| v * v).sum::<f64>().sqrt();
+    x = next.map(|v| v / length);
+  }
+  Some(x)
+}
+
+/// A view's rotation, as a rotation vector, and translation from its
+/// homography, given the intrinsics
+pub(crate) fn initial_pose(
+  k: &Intrinsics,
+  h: &homography::Homography,
+) -> Option<[f64; 6]> {
+  let column = |j: usize| {
+    [
+      (h[0][j] - k.cx * h[2][j]) / k.fx,
+      (h[1][j] - k.cy * h[2][j]) / k.fy,
+      h[2][j],
+    ]
+  };
+  let norm = |v: [f64; 3]| v.iter().map(|c| c * c).sum::<f64>().sqrt();
+  let (r1, r2, t) = (column(0), column(1), column(2));
+  // The board must be in front of the camera
+  let lambda = 1.0 / norm(r1) * if t[2] < 0.0 { -1.0 } else { 1.0 };
+  let (r1, r2, t) = (
+    r1.map(|v| v * lambda),
+    r2.map(|v| v * lambda),
+    t.map(|v| v * lambda),
+  );
+
+  let r1 = r1.map(|v| v / norm(r1));
+  let dot: f64 = r1.iter().zip(&r2).map(|(a, b)| a * b).sum();
+  let r2 = [0, 1, 2].map(|i| r2[i] - dot * r1[i]);
+  let r2 = r2.map(|v| v / norm(r2));
+  let r3 = [
+    r1[1] * r2[2] - r1[2] * r2[1],
+    r1[2] * r2[0] - r1[0] * r2[2],
+    r1[0] * r2[1] - r1[1] * r2[0],
+  ];
+  let rotation = [0, 1, 2].map(|i| [r1[i], r2[i], r3[i]]);
+  let [rx, ry, rz] = rotation_vector(&rotation);
+  let pose = [rx, ry, rz, t[0], t[1], t[2]];
+  pose.iter().all(|v| v.is_finite()).then_some(pose)
+}
+
+/// Rodrigues' formula: the rotation matrix turning by the length of `r`
+/// around its direction
+pub(crate) fn rotation_from(r: &[f64]) -> [[f64; 3]; 3] {
+  let theta = (r[0] * r[0] + r[1] * r[1] + r[2] * r[2]).sqrt();
+  if theta < 1e-12 {
+    return [[1.0, -r[2], r[1]], [r[2], 1.0, -r[0]], [-r[1], r[0], 1.0]];
+  }
+  let k = [r[0] / theta, r[1] / theta, r[2] / theta];
+  let (sin, cos) = theta.sin_cos();
+  let cross = [[0.0, -k[2], k[1]], [k[2], 0.0, -k[0]], [-k[1], k[0], 0.0]];
+  [0, 1, 2].map(|i| {
+    [0, 1, 2].map(|j| {
+      let identity = if i == j { cos } else { 0.0 };
+      identity + (1.0 - cos) * k[i] * k[j] + sin * cross[i][j]
+    })
+  })
+}
+
+/// The inverse of [`rotation_from`] for rotations short of half a turn
+fn rotation_vector(r: &[[f64; 3]; 3]) -> [f64; 3] {
+  let axis = [r[2][1] - r[1][2], r[0][2] - r[2][0], r[1][0] - r[0][1]];
+  let cos = ((r[0][0] + r[1][1] + r[2][2] - 1.0) / 2.0).clamp(-1.0, 1.0);
+  let theta = cos.acos();
+  let factor = if theta < 1e-9 {
+    0.5
+  } else {
+    theta / (2.0 * theta.sin())
+  };
+  axis.map(|v| v * factor)
+}
+
+/// Minimizes the sum of squared residuals over the parameters, with a
+/// forward-difference Jacobian
+pub(crate) fn levenberg_marquardt(
+  mut params: Vec<f64>,
+  residuals: impl Fn(&[f64]) -> Vec<f64>,
+) -> Vec<f64> {
+  let cost = |r: &[f64]| r.iter().map(|v| v * v).sum::<f64>();
+  let mut current = residuals(&params);
+  let mut damping = 1e-3;
+  for _ in 0..REFINE_ITERATIONS {
+    let jacobian: Vec<Vec<f64>> = (0..params.len())
+      .map(|p| {
+        let step = 1e-7 * params[p].abs().max(1e-2);
+        let mut moved = params.clone();
+        moved[p] += step;
+        residuals(&moved)
+          .iter()
+          .zip(&current)
+          .map(|(a, b)| (a - b) / step)
+          .collect()
+      })
+      .collect();
+    let n = params.len();
+    let mut normal = vec![vec![0.0; n]; n];
+    let mut gradient = vec![0.0; n];
+    for a in 0..n {
+      gradient[a] = -jacobian[a]
+        .iter()
+        .zip(&current)
+        .map(|(j, r)| j * r)
+        .sum::<f64>();
+      for b in a..n {
+        let v: f64 = jacobian[a]
+          .iter()
+          .zip(&jacobian[b])
+          .map(|(x, y)| x * y)
+          .sum();
+        normal[a][b] = v;
+        normal[b][a] = v;
+      }
+    }
+    let mut improved = false;
+    while damping < 1e12 {
+      let mut damped = normal.clone();
+      for (i, row) in damped.iter_mut().enumerate() {
+        row[i] += damping * normal[i][i].max(1e-12);
+      }
+      let Some(delta) = solve_dense(damped, gradient.clone()) else {
+        damping *= 10.0;
+        continue;
+      };
+      let trial: Vec<f64> =
+        params.iter().zip(&delta).map(|(p, d)| p + d).collect();
+      let next = residuals(&trial);
+      if cost(&next) < cost(&current) {
+        let gain = cost(&current) - cost(&next);
+        (params, current) = (trial, next);
+        damping = (damping / 10.0).max(1e-12);
+        improved = gain > 1e-12 * cost(&current).max(1e-12);
+        break;
+      }
+      damping *= 10.0;
+    }
+    if !improved {
+      break;
+    }
+  }
+  params
+}
+
+/// Gaussian elimination with partial pivoting on a system of any size
+fn solve_dense(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+  let n = b.len();
+  for col in 0..n {
+    let pivot =
+      (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+    if a[pivot][col].abs() < 1e-300 {
+      return None;
+    }
+    a.swap(col, pivot);
+    b.swap(col, pivot);
+    let b_col = b[col];
+    let (upper, lower) = a.split_at_mut(col + 1);
+    let pivot_row = &upper[col];
+    for (row, rhs) in lower.iter_mut().zip(&mut b[col + 1..]) {
+      let factor = row[col] / pivot_row[col];
+      for (v, p) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+        *v -= factor * p;
+      }
+      *rhs -= factor * b_col;
+    }
+  }
+  let mut x = vec![0.0; n];
+  for row in (0..n).rev() {
+    let tail: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+    x[row] = (b[row] - tail) / a[row][row];
+  }
+  Some(x)
+}
+
+/// Removes lens distortion, resampling `image` with bilinear interpolation
+/// so that straight lines in the scene are straight in the result. The
+/// camera matrix is kept, and pixels that map outside the image are left
+/// at zero.
+pub fn undistort<C: PixelContainer + Clone>(
+  image: &C,
+  calibration: &Calibration,
+) -> C {
+  let (width, height) = (image.width(), image.height());
+  let channels = C::NUM_COMPONENTS;
+  let planes: Vec<Vec<f64>> = (0..channels)
+    .map(|c| {
+      image
+        .components()
+        .iter()
+        .skip(c)
+        .step_by(channels)
+        .map(|v| v.to_f64().unwrap_or_default())
+        .collect()
+    })
+    .collect();
+
+  let k = &calibration.intrinsics;
+  let mut result = image.clone();
+  for (i, pel) in result
+    .components_mut()
+    .chunks_exact_mut(channels)
+    .enumerate()
+  {
+    let x = ((i % width) as f64 - k.cx) / k.fx;
+    let y = ((i / width) as f64 - k.cy) / k.fy;
+    let (sx, sy) = calibration.project(x, y);
+    for (c, value) in pel.iter_mut().enumerate() {
+      let v = sample(&planes[c], width, height, sx, sy).unwrap_or_default();
+      *value = component_from_f64(v);
+    }
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn camera() -> Calibration {
+    Calibration {
+      intrinsics: Intrinsics {
+        fx: 420.0,
+        fy: 410.0,
+        cx: 165.0,
+        cy: 118.0,
+      },
+      distortion: Distortion {
+        k1: -0.12,
+        k2: 0.03,
+        ..Default::default()
+      },
+      rms_error:  0.0,
+    }
+  }
+
+  /// Where the camera sees the board's corners in a few poses
+  fn views(pattern: (usize, usize)) -> Vec<Vec<(f64, f64)>> {
+    let poses = [
+      [0.1, -0.2, 0.05, -3.0, -2.0, 14.0],
+      [-0.3, 0.1, -0.1, -2.5, -2.5, 12.0],
+      [0.25, 0.3, 0.2, -3.5, -1.5, 15.0],
+      [-0.1, -0.35, 0.0, -2.0, -2.0, 13.0],
+    ];
+    let calibration = camera();
+    poses
+      .iter()
+      .map(|pose| {
+        let rotation = rotation_from(&pose[..3]);
+        (0..pattern.1)
+          .flat_map(|r| (0..pattern.0).map(move |c| (c as f64, r as f64)))
+          .map(|(bx, by)| {
+            let p = [0, 1, 2]
+              .map(|k| rotation[k][0] * bx + rotation[k][1] * by + pose[3 + k]);
+            calibration.project(p[0] / p[2], p[1] / p[2])
+          })
+          .collect()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn calibrate_recovers_camera() {
+    let found = calibrate(&views((7, 5)), (7, 5), 1.0).unwrap();
+    let truth = camera();
+    assert!(found.rms_error < 1e-3);
+    assert!((found.intrinsics.fx - truth.intrinsics.fx).abs() < 0.5);
+    assert!((found.intrinsics.cy - truth.intrinsics.cy).abs() < 0.5);
+    assert!((found.distortion.k1 - truth.distortion.k1).abs() < 0.01);
+    assert!(calibrate(&views((7, 5))[..1], (7, 5), 1.0).is_err());
+
+    // Undistorting the distorted view of a point moves it back to where a
+    // pinhole camera would see it
+    let mut image = ImageBuffer::<f32, 1, false>::empty(330, 236);
+    let (x, y) = truth.project(0.2, 0.15);
+    image.components_mut()[y.round() as usize * 330 + x.round() as usize] = 1.0;
+    let straight = undistort(&image, &found);
+    let k = found.intrinsics;
+    let (px, py) = (0.2 * k.fx + k.cx, 0.15 * k.fy + k.cy);
+    let (bright, _) = straight
+      .components()
+      .iter()
+      .enumerate()
+      .max_by(|a, b| a.1.total_cmp(b.1))
+      .unwrap();
+    assert!(((bright % 330) as f64 - px).abs() < 1.5);
+    assert!(((bright / 330) as f64 - py).abs() < 1.5);
+  }
+
+  #[test]
+  fn finds_checkerboard_corners() {
+    // An 8x6-square board, with 7x5 inner corners, seen in perspective
+    let board_to_image: homography::Homography =
+      [[22.0, 3.0, 40.0], [-2.0, 20.0, 30.0], [0.0004, 0.0006, 1.0]];
+    let image_to_board = homography::invert(&board_to_image).unwrap();
+    let image = ImageBuffer::<u8, 1, false>::empty(260, 200).map_indexed(
+      &mut |x, y, _| {
+        let mut sum = 0.0;
+        for k in 0..16 {
+          let offset = |k: usize| (k as f64 + 0.5) / 4.0 - 0.5;
+          let (sx, sy) = (x as f64 + offset(k % 4), y as f64 + offset(k / 4));
+          let (bx, by) = homography::apply(&image_to_board, sx, sy).unwrap();
+          let on_board = (-1.0..7.0).contains(&bx) && (-1.0..5.0).contains(&by);
+          let dark =
+            on_board && (bx.floor() + by.floor()).rem_euclid(2.0) == 0.0;
+          sum += if dark { 20.0 } else { 230.0 };
+        }
+        [(sum / 16.0) as u8]
+      },
+    );
+    let corners = find_checkerboard(&image, (7, 5)).unwrap();
+    assert_eq!(corners.len(), 35);
+    for (i, &(x, y)) in corners.iter().enumerate() {
+      let (ex, ey) =
+        homography::apply(&board_to_image, (i % 7) as f64, (i / 7) as f64)
+          .unwrap();
+      assert!((x - ex).hypot(y - ey) < 0.3, "corner {i}");
+    }
+    assert!(find_checkerboard(&image, (6, 5)).is_none());
+  }
+
+  #[test]
+  fn finds_charuco_corners() {
+    // A 5x4-square board, 40 pixels a square, seen in mild perspective with
+    // its top-left square covered
+    let board = CharucoBoard::new((5, 4));
+    let printed = board.render(40);
+    let board_to_image: homography::Homography = [
+      [1.3, 0.1, 30.0],
+      [-0.05, 1.25, 25.0],
+      [0.00015, 0.0001, 1.0],
+    ];
+    let image_to_board = homography::invert(&board_to_image).unwrap();
+    let image = ImageBuffer::<u8, 1, false>::empty(300, 260).map_indexed(
+      &mut |x, y, _| {
+        let mut sum = 0.0;
+        for k in 0..16 {
+          let offset = |k: usize| (k as f64 + 0.5) / 4.0 - 0.5;
+          let (sx, sy) = (x as f64 + offset(k % 4), y as f64 + offset(k / 4));
+          let (bx, by) = homography::apply(&image_to_board, sx, sy).unwrap();
+          let covered = (0.0..45.0).contains(&bx) && (0.0..45.0).contains(&by);
+          let on_board =
+            (0.0..200.0).contains(&bx) && (0.0..160.0).contains(&by);
+          sum += if covered {
+            128.0
+          } else if on_board {
+            let v = printed.get_pixel(bx as usize, by as usize)[0];
+            if v == 0 {
+              20.0
+            } else {
+              230.0
+            }
+          } else {
+            230.0
+          };
+        }
+        [(sum / 16.0) as u8]
+      },
+    );
+    let corners = find_charuco(&image, &board);
+    let ids: Vec<usize> = corners.iter().map(|&(id, _)| id).collect();
+    assert_eq!(ids, (1..12).collect::<Vec<_>>());
+    for &(id, (x, y)) in &corners {
+      let (bx, by) = board.corner_position(id);
+      let (ex, ey) =
+        homography::apply(&board_to_image, bx * 40.0, by * 40.0).unwrap();
+      assert!((x - ex).hypot(y - ey) < 0.5, "corner {id}");
+    }
+  }
+
+  #[test]
+  fn calibrate_charuco_from_partial_views() {
+    // The corners of an 8x6-square board, each view missing a different
+    // part of it
+    let board = CharucoBoard::new((8, 6));
+    let views: Vec<Vec<(usize, (f64, f64))>> = views((7, 5))
+      .into_iter()
+      .enumerate()
+      .map(|(v, corners)| {
+        corners
+          .into_iter()
+          .enumerate()
+          .filter(|(id, _)| (id % 7 + v) % 4 != 0)
+          .collect()
+      })
+      .collect();
+    let found = calibrate_charuco(&views, &board, 1.0).unwrap();
+    let truth = camera();
+    assert!(found.rms_error < 1e-3);
+    assert!((found.intrinsics.fx - truth.intrinsics.fx).abs() < 0.5);
+    assert!((found.distortion.k1 - truth.distortion.k1).abs() < 0.01);
+    assert!(calibrate_charuco(&views[..1], &board, 1.0).is_err());
+    assert!(
+      calibrate_charuco(&vec![vec![(35, (0.0, 0.0))]; 2], &board, 1.0).is_err()
+    );
+  }
+}
@@ -0,0 +1,55 @@
+//! Names for the channels of a buffer.
+//!
+//! Buffers may have any number of channels; multispectral captures and
+//! layered composites often have 5 to 16. Naming them lets conversions pick
+//! channels by meaning rather than by position.
+
+use crate::error::{Error, Result};
+
+/// The name of each channel of a buffer, in storage order
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ChannelSemantics {
+  names: Vec<String>,
+}
+
+impl ChannelSemantics {
+  /// Names channels in order. Names must be unique.
+  pub fn new<S: Into<String>>(
+    names: impl IntoIterator<Item = S>,
+  ) -> Result<Self> {
+    let names: Vec<String> = names.into_iter().map(Into::into).collect();
+    for (i, name) in names.iter().enumerate() {
+      if names[..i].contains(name) {
+        return Err(Error::Channel(format!("Duplicate channel name {name:?}")));
+      }
+    }
+    Ok(ChannelSemantics {
+      names,
+    })
+  }
+
+  pub fn rgb() -> Self { Self::new(["R", "G", "B"]).expect("Names are unique") }
+
+  pub fn rgba() -> Self {
+    Self::new(["R", "G", "B", "A"]).expect("Names are unique")
+  }
+
+  pub fn cmyk() -> Self {
+    Self::new(["C", "M", "Y", "K"]).expect("Names are unique")
+  }
+
+  pub fn names(&self) -> &[String] { &self.names }
+
+  pub fn len(&self) -> usize { self.names.len() }
+
+  pub fn is_empty(&self) -> bool { self.names.is_empty() }
+
+  /// Position of the channel called `name`
+  pub fn index_of(&self, name: &str) -> Result<usize> {
+    self
+      .names
+      .iter()
+      .position(|n| n == name)
+      .ok_or_else(|| Error::Channel(format!("No channel named {name:?}")))
+  }
+}
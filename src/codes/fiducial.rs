@@ -0,0 +1,642 @@
+//! Square fiducial markers in the style of ArUco: a black border around a
+//! grid of black and white cells encoding an ID.
+//!
+//! Only the original ArUco markers are built in, as
+//! [`Dictionary::aruco_original`]. Other ArUco dictionaries can be loaded
+//! with [`Dictionary::new`]. AprilTag markers are not supported: no
+//! AprilTag family is built in, and reading published AprilTag codes
+//! through [`Dictionary::new`] has not been checked against real tags.
+//!
+//! Markers are found as dark quadrilaterals in an adaptively thresholded
+//! copy of the image, their cells are read through the perspective of each
+//! quadrilateral, and the bits are matched against a [`Dictionary`] in all
+//! four rotations. With a [`Calibration`] of the camera, the corners of a
+//! marker of known size give its pose.
+
+use std::collections::VecDeque;
+
+use crate::{
+  calib::{
+    initial_pose,
+    levenberg_marquardt,
+    rotation_from,
+    Calibration,
+    Intrinsics,
+  },
+  error::{Error, Result},
+  ops::{analysis::luminance_plane, register::sample},
+  pixel::PixelContainer,
+  stitch::homography,
+  ImageBuffer,
+};
+
+/// How much darker than its neighborhood a pixel must be to be part of a
+/// marker's border, in normalized luminance
+const THRESHOLD_OFFSET: f64 = 0.03;
+
+/// Smallest side, in pixels, of a quadrilateral considered as a marker
+const MIN_SIDE: f64 = 8.0;
+
+/// The two-bit rows of the original ArUco markers: each row of five cells
+/// is one of these words, so each encodes two bits of the ID
+const ARUCO_WORDS: [u64; 4] = [0b10000, 0b10111, 0b01001, 0b01110];
+
+/// A set of marker codes, each a `size` by `size` grid of cells inside the
+/// black border. Codes are stored row by row, most significant bit first,
+/// with set bits for white cells.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dictionary {
+  size:           usize,
+  codes:          Vec<u64>,
+  max_correction: u32,
+}
+
+impl Dictionary {
+  /// A dictionary of `codes`, each `size` cells square, accepting reads
+  /// with up to `max_correction` wrong bits. Use this to load published
+  /// tables such as the other ArUco dictionaries, none of which are built
+  /// in. Fails unless the codes fit in 64 bits.
+  pub fn new(
+    size: usize,
+    codes: Vec<u64>,
+    max_correction: u32,
+  ) -> Result<Self> {
+    if size == 0 || size * size > 64 {
+      return Err(Error::InvalidArgument(format!(
+        "Marker codes of {size}x{size} cells do not fit in 64 bits"
+      )));
+    }
+    Ok(Dictionary {
+      size,
+      codes,
+      max_correction,
+    })
+  }
+
+  /// The 1024 markers of the original ArUco library, 5x5 cells with each
+  /// row carrying two bits of the ID. One wrong bit is corrected.
+  pub fn aruco_original() -> Self {
+    let codes = (0..1024u64)
+      .map(|id| {
+        (0..5).fold(0, |code, row| {
+          (code << 5) | ARUCO_WORDS[(id >> (2 * (4 - row)) & 3) as usize]
+        })
+      })
+      .collect();
+    Dictionary {
+      size: 5,
+      codes,
+      max_correction: 1,
+    }
+  }
+
+  /// Number of cells along each side of the code, not counting the border
+  pub fn size(&self) -> usize { self.size }
+
+  /// Number of markers in the dictionary
+  pub fn len(&self) -> usize { self.codes.len() }
+
+  pub fn is_empty(&self) -> bool { self.codes.is_empty() }
+
+  /// Draws marker `id` with its black border, `cell` pixels per cell, for
+  /// printing. Leave a white margin around it when placing it.
+  pub fn render(
+    &self,
+    id: usize,
+    cell: usize,
+  ) -> Option<ImageBuffer<u8, 1, false>> {
+    let bits = self.grid(*self.codes.get(id)?);
+    let cells = self.size + 2;
+    Some(ImageBuffer::empty(cells * cell, cells * cell).map_indexed(
+      &mut |x, y, _| {
+        let (cx, cy) = (x / cell.max(1), y / cell.max(1));
+        let inside =
+          (1..=self.size).contains(&cx) && (1..=self.size).contains(&cy);
+        [if inside && bits[(cy - 1) * self.size + cx - 1] {
+          255
+        } else {
+          0
+        }]
+      },
+    ))
+  }
+
+  /// The cells of a code, row by row, true for white
+  fn grid(&self, code: u64) -> Vec<bool> {
+    let n = self.size * self.size;
+    (0..n).map(|i| code >> (n - 1 - i) & 1 == 1).collect()
+  }
+
+  /// The nearest code to the cells read, and how many bits differ, if it
+  /// is within the correction limit
+  fn identify(&self, cells: &[bool]) -> Option<(usize, u32)> {
+    let read = cells
+      .iter()
+      .fold(0u64, |code, &white| (code << 1) | u64::from(white));
+    self
+      .codes
+      .iter()
+      .enumerate()
+      .map(|(id, code)| (id, (code ^ read).count_ones()))
+      .min_by_key(|&(_, distance)| distance)
+      .filter(|&(_, distance)| distance <= self.max_correction)
+  }
+}
+
+/// A marker found by [`detect`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Marker {
+  /// Index of the marker's code in the dictionary
+  pub id:        usize,
+  /// The outer corners of the black border, clockwise in the image from the
+  /// marker's own top-left
+  pub corners:   [(f64, f64); 4],
+  /// Number of bits that were read wrong and corrected
+  pub corrected: u32,
+}
+
+/// Position and orientation of a marker relative to the camera, from
+/// [`estimate_pose`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+  /// The rotation from marker to camera coordinates, as a vector along its
+  /// axis whose length is the angle in radians
+  pub rotation:    [f64; 3],
+  /// The marker's center in camera coordinates, in the units of its size
+  pub translation: [f64; 3],
+}
+
+impl Pose {
+  /// The rotation as a matrix
+  pub fn rotation_matrix(&self) -> [[f64; 3]; 3] {
+    rotation_from(&self.rotation)
+  }
+}
+
+/// Finds the markers of `dictionary` in `image`.
+///
+/// A marker needs a white margin around its border and sides of at least
+/// eight pixels, with cells about three pixels across or more to be read
+/// reliably.
+pub fn detect<C: PixelContainer>(
+  image: &C,
+  dictionary: &Dictionary,
+) -> Vec<Marker> {
+  let (width, height) = (image.width(), image.height());
+  if width < 3 || height < 3 {
+    return Vec::new();
+  }
+  let luma: Vec<f64> = luminance_plane(image)
+    .components()
+    .iter()
+    .map(|&v| f64::from(v))
+    .collect();
+  let dark = threshold(&luma, width, height);
+
+  let mut label = vec![false; width * height];
+  let mut markers = Vec::new();
+  for start in 0..width * height {
+    if !dark[start] || label[start] {
+      continue;
+    }
+    let pixels = flood(&dark, &mut label, width, height, start);
+    let Some(quad) = quadrilateral(&pixels, &dark, width, height) else {
+      continue;
+    };
+    if let Some(marker) = read(&luma, width, height, quad, dictionary) {
+      markers.push(marker);
+    }
+  }
+  markers
+}
+
+/// Marks pixels darker than the mean of their neighborhood, which spans a
+/// tenth of the image so that whole marker borders stand out
+fn threshold(luma: &[f64], width: usize, height: usize) -> Vec<bool> {
+  let radius = (width.min(height) / 10).max(4);
+  let stride = width + 1;
+  let mut table = vec![0.0; stride * (height + 1)];
+  for y in 0..height {
+    let mut row = 0.0;
+    for x in 0..width {
+      row += luma[y * width + x];
+      table[(y + 1) * stride + x + 1] = table[y * stride + x + 1] + row;
+    }
+  }
+  (0..width * height)
+    .map(|i| {
+      let (x, y) = (i % width, i / width);
+      let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+      let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+      let sum = table[y1 * stride + x1]
+        - table[y0 * stride + x1]
+        - table[y1 * stride + x0]
+        + table[y0 * stride + x0];
+      let mean = sum / ((x1 - x0) * (y1 - y0)) as f64;
+      luma[i] < mean - THRESHOLD_OFFSET
+    })
+    .collect()
+}
+
+/// The 4-connected component of dark pixels containing `start`, as
+/// coordinates
+fn flood(
+  dark: &[bool],
+  label: &mut [bool],
+  width: usize,
+  height: usize,
+  start: usize,
+) -> Vec<(usize, usize)> {
+  let mut pixels = Vec::new();
+  let mut queue = VecDeque::from([start]);
+  label[start] = true;
+  while let Some(i) = queue.pop_front() {
+    let (x, y) = (i % width, i / width);
+    pixels.push((x, y));
+    let neighbors = [
+      (x > 0).then(|| i - 1),
+      (x + 1 < width).then(|| i + 1),
+      (y > 0).then(|| i - width),
+      (y + 1 < height).then(|| i + width),
+    ];
+    for n in neighbors.into_iter().flatten() {
+      if dark[n] && !label[n] {
+        label[n] = true;
+        queue.push_back(n);
+      }
+    }
+  }
+  pixels
+}
+
+/// The corners of the quadrilateral outlining a component, clockwise in
+/// the image, if the component is one. Each side is fitted to the
+/// component's boundary for subpixel corners.
+fn quadrilateral(
+  pixels: &[(usize, usize)],
+  dark: &[bool],
+  width: usize,
+  height: usize,
+) -> Option<[(f64, f64); 4]> {
+  let touches_edge = pixels
+    .iter()
+    .any(|&(x, y)| x == 0 || y == 0 || x + 1 == width || y + 1 == height);
+  if pixels.len() < 4 * MIN_SIDE as usize || touches_edge {
+    return None;
+  }
+  let points: Vec<(f64, f64)> =
+    pixels.iter().map(|&(x, y)| (x as f64, y as f64)).collect();
+  let hull = convex_hull(points);
+  let hull_area = area(&hull);
+
+  // The two hull vertices farthest apart are opposite corners, and the
+  // farthest on each side of their diagonal are the other two
+  let distance = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1);
+  let (a, c) = (0..hull.len())
+    .flat_map(|i| (i + 1..hull.len()).map(move |j| (i, j)))
+    .max_by(|&(i, j), &(k, l)| {
+      distance(hull[i], hull[j]).total_cmp(&distance(hull[k], hull[l]))
+    })?;
+  let (a, c) = (hull[a], hull[c]);
+  let side =
+    |p: (f64, f64)| (c.0 - a.0) * (p.1 - a.1) - (c.1 - a.1) * (p.0 - a.0);
+  let b = *hull.iter().max_by(|p, q| side(**p).total_cmp(&side(**q)))?;
+  let d = *hull.iter().min_by(|p, q| side(**p).total_cmp(&side(**q)))?;
+  let mut quad = [a, b, c, d];
+  if area(&quad) < 0.0 {
+    quad.reverse();
+  }
+  let quad_area = area(&quad);
+  let shortest = (0..4)
+    .map(|i| distance(quad[i], quad[(i + 1) % 4]))
+    .fold(f64::MAX, f64::min);
+  if shortest < MIN_SIDE || quad_area < 0.9 * hull_area {
+    return None;
+  }
+
+  // Fit each side to the boundary pixels along its middle, moved out half
+  // a pixel to the edge between dark and light
+  let boundary: Vec<(f64, f64)> = pixels
+    .iter()
+    .filter(|&&(x, y)| {
+      !dark[y * width + x - 1]
+        || !dark[y * width + x + 1]
+        || !dark[(y - 1) * width + x]
+        || !dark[(y + 1) * width + x]
+    })
+    .map(|&(x, y)| (x as f64, y as f64))
+    .collect();
+  let center = (
+    quad.iter().map(|p| p.0).sum::<f64>() / 4.0,
+    quad.iter().map(|p| p.1).sum::<f64>() / 4.0,
+  );
+  let lines: Vec<((f64, f64), (f64, f64))> = (0..4)
+    .map(|i| {
+      let (p, q) = (quad[i], quad[(i + 1) % 4]);
+      let length = distance(p, q);
+      let direction = ((q.0 - p.0) / length, (q.1 - p.1) / length);
+      let near: Vec<(f64, f64)> = boundary
+        .iter()
+        .copied()
+        .filter(|&(x, y)| {
+          let t = ((x - p.0) * direction.0 + (y - p.1) * direction.1) / length;
+          let off = ((x - p.0) * direction.1 - (y - p.1) * direction.0).abs();
+          (0.15..0.85).contains(&t) && off < 2.5
+        })
+        .collect();
+      let (point, direction) = fit_line(&near).unwrap_or((p, direction));
+      let mut normal = (direction.1, -direction.0);
+      if normal.0 * (point.0 - center.0) + normal.1 * (point.1 - center.1) < 0.0
+      {
+        normal = (-normal.0, -normal.1);
+      }
+      (
+        (point.0 + 0.5 * normal.0, point.1 + 0.5 * normal.1),
+        direction,
+      )
+    })
+    .collect();
+  let mut corners = quad;
+  for (i, corner) in corners.iter_mut().enumerate() {
+    if let Some(p) = intersect(lines[(i + 3) % 4], lines[i]) {
+      if distance(p, *corner) < 3.0 {
+        *corner = p;
+      }
+    }
+  }
+  Some(corners)
+}
+
+/// Andrew's monotone chain, returning the hull clockwise in the image
+fn convex_hull(mut points: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+  points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+  points.dedup();
+  if points.len() < 3 {
+    return points;
+  }
+  let cross = |o: (f64, f64), a: (f64, f64), b: (f64, f64)| {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+  };
+  let mut hull: Vec<(f64, f64)> = Vec::with_capacity(2 * points.len());
+  for pass in [points.clone(), points.into_iter().rev().collect()] {
+    let start = hull.len();
+    for p in pass {
+      while hull.len() >= start + 2
+        && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0
+      {
+        hull.pop();
+      }
+      hull.push(p);
+    }
+    hull.pop();
+  }
+  hull
+}
+
+/// Signed area of a polygon, positive when clockwise in the image
+fn area(polygon: &[(f64, f64)]) -> f64 {
+  (0..polygon.len())
+    .map(|i| {
+      let (p, q) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+      p.0 * q.1 - q.0 * p.1
+    })
+    .sum::<f64>()
+    / 2.0
+}
+
+/// The total least squares line through `points`, as a point on it and a
+/// unit direction
+fn fit_line(points: &[(f64, f64)]) -> Option<((f64, f64), (f64, f64))> {
+  if points.len() < 3 {
+    return None;
+  }
+  let n = points.len() as f64;
+  let (mx, my) = points
+    .iter()
+    .fold((0.0, 0.0), |(sx, sy), &(x, y)| (sx + x / n, sy + y / n));
+  let (mut xx, mut xy, mut yy) = (0.0, 0.0, 0.0);
+  for &(x, y) in points {
+    xx += (x - mx) * (x - mx);
+    xy += (x - mx) * (y - my);
+    yy += (y - my) * (y - my);
+  }
+  let angle = 0.5 * (2.0 * xy).atan2(xx - yy);
+  Some(((mx, my), (angle.cos(), angle.sin())))
+}
+
+fn intersect(
+  (p, d): ((f64, f64), (f64, f64)),
+  (q, e): ((f64, f64), (f64, f64)),
+) -> Option<(f64, f64)> {
+  let denominator = d.0 * e.1 - d.1 * e.0;
+  if denominator.abs() < 1e-9 {
+    return None;
+  }
+  let t = ((q.0 - p.0) * e.1 - (q.1 - p.1) * e.0) / denominator;
+  Some((p.0 + t * d.0, p.1 + t * d.1))
+}
+
+/// Reads the cells inside a quadrilateral and identifies the marker, with
+/// its corners rotated to start at the marker's top-left
+fn read(
+  luma: &[f64],
+  width: usize,
+  height: usize,
+  quad: [(f64, f64); 4],
+  dictionary: &Dictionary,
+) -> Option<Marker> {
+  let size = dictionary.size;
+  let cells = (size + 2) as f64;
+  let square = [(0.0, 0.0), (cells, 0.0), (cells, cells), (0.0, cells)];
+  let pairs: Vec<_> = square.into_iter().zip(quad).collect();
+  let h = homography::fit(&pairs)?;
+
+  // Average the middle of each cell, away from blur at its edges
+  let mut values = Vec::with_capacity((size + 2) * (size + 2));
+  for cy in 0..size + 2 {
+    for cx in 0..size + 2 {
+      let mut sum = 0.0;
+      for k in 0..9 {
+        let u = cx as f64 + 0.3 + 0.2 * (k % 3) as f64;
+        let v = cy as f64 + 0.3 + 0.2 * (k / 3) as f64;
+        let (x, y) = homography::apply(&h, u, v)?;
+        sum += sample(luma, width, height, x, y)?;
+      }
+      values.push(sum / 9.0);
+    }
+  }
+  let is_border = |i: usize| {
+    let (cx, cy) = (i % (size + 2), i / (size + 2));
+    cx == 0 || cy == 0 || cx == size + 1 || cy == size + 1
+  };
+  let border: Vec<f64> = (0..values.len())
+    .filter(|&i| is_border(i))
+    .map(|i| values[i])
+    .collect();
+  let dark = border.iter().sum::<f64>() / border.len() as f64;
+  let light = values.iter().copied().fold(f64::MIN, f64::max);
+  let threshold = (dark + light) / 2.0;
+  if light - dark < 0.1 || border.iter().any(|&v| v > threshold) {
+    return None;
+  }
+  let mut grid: Vec<bool> = (0..values.len())
+    .filter(|&i| !is_border(i))
+    .map(|i| values[i] > threshold)
+    .collect();
+
+  // Rotating the cells clockwise `turns` times moves the marker's top-left
+  // from corner `(4 - turns) % 4` of the quadrilateral to the first
+  let mut best: Option<(usize, u32, usize)> = None;
+  for turns in 0..4 {
+    if let Some((id, distance)) = dictionary.identify(&grid) {
+      if best.is_none_or(|(_, d, _)| distance < d) {
+        best = Some((id, distance, turns));
+      }
+    }
+    grid = (0..size * size)
+      .map(|i| grid[(size - 1 - i % size) * size + i / size])
+      .collect();
+  }
+  let (id, corrected, turns) = best?;
+  let first = (4 - turns) % 4;
+  Some(Marker {
+    id,
+    corners: [0, 1, 2, 3].map(|k| quad[(first + k) % 4]),
+    corrected,
+  })
+}
+
+/// Estimates where a marker with sides `size` long is relative to the
+/// camera, from its corners and the camera's calibration. The marker's
+/// axes run right and down along its face and into it, with the origin at
+/// its center.
+///
+/// A planar estimate from the homography of the corners is refined to
+/// minimize their reprojection error. Returns `None` for degenerate
+/// corners.
+pub fn estimate_pose(
+  marker: &Marker,
+  size: f64,
+  calibration: &Calibration,
+) -> Option<Pose> {
+  let half = size / 2.0;
+  let model = [(-half, -half), (half, -half), (half, half), (-half, half)];
+  let normalized = marker.corners.map(|(u, v)| calibration.unproject(u, v));
+  let pairs: Vec<_> = model.into_iter().zip(normalized).collect();
+  let h = homography::fit(&pairs)?;
+  let identity = Intrinsics {
+    fx: 1.0,
+    fy: 1.0,
+    cx: 0.0,
+    cy: 0.0,
+  };
+  let initial = initial_pose(&identity, &h)?;
+  let pose = levenberg_marquardt(initial.to_vec(), |pose| {
+    let rotation = rotation_from(&pose[..3]);
+    model
+      .iter()
+      .zip(&marker.corners)
+      .flat_map(|(&(mx, my), &(u, v))| {
+        let p = [0, 1, 2]
+          .map(|k| rotation[k][0] * mx + rotation[k][1] * my + pose[3 + k]);
+        let (x, y) = calibration.project(p[0] / p[2], p[1] / p[2]);
+        [x - u, y - v]
+      })
+      .collect()
+  });
+  pose.iter().all(|v| v.is_finite()).then(|| {
+    Pose {
+      rotation:    [pose[0], pose[1], pose[2]],
+      translation: [pose[3], pose[4], pose[5]],
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::calib::Distortion;
+
+  #[test]
+  fn detects_rotated_markers_in_perspective() {
+    let dictionary = Dictionary::aruco_original();
+    assert_eq!(dictionary.len(), 1024);
+    let marker = dictionary.render(300, 1).unwrap();
+
+    // The marker's top-left lands at the bottom-right of the image, as if
+    // it had been turned half around
+    let quad = [(150.0, 130.0), (60.0, 140.0), (50.0, 40.0), (140.0, 50.0)];
+    let square = [(0.0, 0.0), (7.0, 0.0), (7.0, 7.0), (0.0, 7.0)];
+    let pairs: Vec<_> = square.into_iter().zip(quad).collect();
+    let to_marker =
+      homography::invert(&homography::fit(&pairs).unwrap()).unwrap();
+    let image = ImageBuffer::<u8, 1, false>::empty(200, 180).map_indexed(
+      &mut |x, y, _| {
+        let mut sum = 0.0;
+        for k in 0..16 {
+          let offset = |k: usize| (k as f64 + 0.5) / 4.0 - 0.5;
+          let (u, v) = homography::apply(
+            &to_marker,
+            x as f64 + offset(k % 4),
+            y as f64 + offset(k / 4),
+          )
+          .unwrap();
+          let inside = (0.0..7.0).contains(&u) && (0.0..7.0).contains(&v);
+          sum += if inside {
+            f64::from(marker.get_pixel(u as usize, v as usize)[0])
+          } else {
+            255.0
+          };
+        }
+        [(sum / 16.0) as u8]
+      },
+    );
+
+    let found = detect(&image, &dictionary);
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].id, found[0].corrected), (300, 0));
+    for (corner, expected) in found[0].corners.iter().zip(quad) {
+      assert!((corner.0 - expected.0).hypot(corner.1 - expected.1) < 0.5);
+    }
+    assert!(detect(&image, &Dictionary::new(5, vec![0], 0).unwrap()).is_empty());
+  }
+
+  #[test]
+  fn pose_from_projected_corners() {
+    let calibration = Calibration {
+      intrinsics: Intrinsics {
+        fx: 500.0,
+        fy: 500.0,
+        cx: 160.0,
+        cy: 120.0,
+      },
+      distortion: Distortion {
+        k1: -0.1,
+        ..Default::default()
+      },
+      rms_error:  0.0,
+    };
+    let truth = Pose {
+      rotation:    [0.3, -0.2, 0.4],
+      translation: [0.05, -0.02, 0.8],
+    };
+    let rotation = truth.rotation_matrix();
+    let corners = [(-0.05, -0.05), (0.05, -0.05), (0.05, 0.05), (-0.05, 0.05)]
+      .map(|(mx, my)| {
+        let p = [0, 1, 2].map(|k| {
+          rotation[k][0] * mx + rotation[k][1] * my + truth.translation[k]
+        });
+        calibration.project(p[0] / p[2], p[1] / p[2])
+      });
+    let marker = Marker {
+      id: 0,
+      corners,
+      corrected: 0,
+    };
+    let pose = estimate_pose(&marker, 0.1, &calibration).unwrap();
+    for k in 0..3 {
+      assert!((pose.translation[k] - truth.translation[k]).abs() < 1e-4);
+      assert!((pose.rotation[k] - truth.rotation[k]).abs() < 1e-3);
+    }
+  }
+}
@@ -0,0 +1,4 @@
+//! Machine-readable markers placed in a scene, found and decoded from
+//! photos of it.
+
+pub mod fiducial;
@@ -0,0 +1,535 @@
+//! Single colors, for drawing, filling and padding: 8-bit and
+//! floating-point sRGB with straight alpha, parsed from CSS notation or
+//! built from HSL and HWB.
+//!
+//! Raw `[T; 4]` arrays convert to [`RgbaF32`] with their components scaled
+//! by [`PixelComponent::WHITE`], so APIs taking `impl Into<RgbaF32>` accept
+//! either.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+  error::{Error, Result},
+  pixel::{component_from_f64, PixelComponent},
+  ImageBuffer,
+};
+
+/// An 8-bit sRGB color with straight alpha
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Rgba8 {
+  pub r: u8,
+  pub g: u8,
+  pub b: u8,
+  pub a: u8,
+}
+
+/// A floating-point sRGB color with straight alpha, each component from
+/// `0` to `1`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RgbaF32 {
+  pub r: f32,
+  pub g: f32,
+  pub b: f32,
+  pub a: f32,
+}
+
+impl Rgba8 {
+  pub const BLACK: Rgba8 = Rgba8::rgb(0, 0, 0);
+  pub const BLUE: Rgba8 = Rgba8::rgb(0, 0, 255);
+  pub const CYAN: Rgba8 = Rgba8::rgb(0, 255, 255);
+  pub const GRAY: Rgba8 = Rgba8::rgb(128, 128, 128);
+  pub const GREEN: Rgba8 = Rgba8::rgb(0, 128, 0);
+  pub const MAGENTA: Rgba8 = Rgba8::rgb(255, 0, 255);
+  pub const ORANGE: Rgba8 = Rgba8::rgb(255, 165, 0);
+  pub const RED: Rgba8 = Rgba8::rgb(255, 0, 0);
+  pub const TRANSPARENT: Rgba8 = Rgba8::new(0, 0, 0, 0);
+  pub const WHITE: Rgba8 = Rgba8::rgb(255, 255, 255);
+  pub const YELLOW: Rgba8 = Rgba8::rgb(255, 255, 0);
+
+  pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+    Rgba8 {
+      r,
+      g,
+      b,
+      a,
+    }
+  }
+
+  /// An opaque color
+  pub const fn rgb(r: u8, g: u8, b: u8) -> Self { Rgba8::new(r, g, b, 255) }
+
+  pub const fn with_alpha(self, a: u8) -> Self {
+    Rgba8 {
+      a,
+      ..self
+    }
+  }
+
+  /// A color from its hue in degrees and its saturation and lightness from
+  /// `0` to `1`
+  pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Self {
+    RgbaF32::from_hsl(hue, saturation, lightness).into()
+  }
+
+  /// A color from its hue in degrees and its whiteness and blackness from
+  /// `0` to `1`
+  pub fn from_hwb(hue: f32, whiteness: f32, blackness: f32) -> Self {
+    RgbaF32::from_hwb(hue, whiteness, blackness).into()
+  }
+
+  /// Parses a CSS color: hex notation (`#rgb`, `#rgba`, `#rrggbb` or
+  /// `#rrggbbaa`), the `rgb()`, `rgba()`, `hsl()`, `hsla()` and `hwb()`
+  /// functions, or a named color. Fails with [`Error::InvalidArgument`].
+  pub fn parse(css: &str) -> Result<Self> {
+    RgbaF32::parse(css).map(Into::into)
+  }
+
+  /// The color in CSS hex notation, with alpha only if it is not opaque
+  pub fn to_hex(self) -> String {
+    let Rgba8 {
+      r,
+      g,
+      b,
+      a,
+    } = self;
+    if a == u8::MAX {
+      format!("#{r:02x}{g:02x}{b:02x}")
+    } else {
+      format!("#{r:02x}{g:02x}{b:02x}{a:02x}")
+    }
+  }
+}
+
+impl RgbaF32 {
+  pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+    RgbaF32 {
+      r,
+      g,
+      b,
+      a,
+    }
+  }
+
+  /// An opaque color
+  pub const fn rgb(r: f32, g: f32, b: f32) -> Self {
+    RgbaF32::new(r, g, b, 1.0)
+  }
+
+  pub const fn with_alpha(self, a: f32) -> Self {
+    RgbaF32 {
+      a,
+      ..self
+    }
+  }
+
+  /// A color from its hue in degrees and its saturation and lightness from
+  /// `0` to `1`
+  pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Self {
+    let (s, l) = (saturation.clamp(0.0, 1.0), lightness.clamp(0.0, 1.0));
+    let channel = |n: f32| {
+      let k = (n + hue.rem_euclid(360.0) / 30.0) % 12.0;
+      let a = s * l.min(1.0 - l);
+      l - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+    RgbaF32::rgb(channel(0.0), channel(8.0), channel(4.0))
+  }
+
+  /// A color from its hue in degrees and its whiteness and blackness from
+  /// `0` to `1`. Whiteness and blackness adding up to more than one give a
+  /// gray.
+  pub fn from_hwb(hue: f32, whiteness: f32, blackness: f32) -> Self {
+    let (w, b) = (whiteness.clamp(0.0, 1.0), blackness.clamp(0.0, 1.0));
+    if w + b >= 1.0 {
+      let gray = w / (w + b);
+      return RgbaF32::rgb(gray, gray, gray);
+    }
+    let pure = RgbaF32::from_hsl(hue, 1.0, 0.5);
+    let mix = |c: f32| c * (1.0 - w - b) + w;
+    RgbaF32::rgb(mix(pure.r), mix(pure.g), mix(pure.b))
+  }
+
+  /// Parses a CSS color, as [`Rgba8::parse`] does, without rounding to
+  /// 8 bits
+  pub fn parse(css: &str) -> Result<Self> {
+    parse(css.trim()).ok_or_else(|| {
+      Error::InvalidArgument(format!("Invalid color `{}`", css.trim()))
+    })
+  }
+
+  /// This color as a pixel of `image`: color images take its RGB
+  /// components and others its Rec. 709 luma, and images with alpha take
+  /// its alpha in their last channel. Any other channels are zero.
+  pub fn to_pixel<T: PixelComponent, const N: usize, const A: bool>(
+    self,
+    image: &ImageBuffer<T, N, A>,
+  ) -> [T; N] {
+    let white = image.white();
+    let colors = if A { N - 1 } else { N };
+    let mut pixel = [T::zero(); N];
+    let rgb = [self.r, self.g, self.b].map(f64::from);
+    if colors >= 3 {
+      for (out, v) in pixel.iter_mut().zip(rgb) {
+        *out = component_from_f64(v * white);
+      }
+    } else if colors >= 1 {
+      let luma = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+      pixel[0] = component_from_f64(luma * white);
+    }
+    if A {
+      pixel[N - 1] = component_from_f64(f64::from(self.a) * white);
+    }
+    pixel
+  }
+}
+
+impl From<Rgba8> for RgbaF32 {
+  fn from(c: Rgba8) -> Self {
+    let f = |v: u8| f32::from(v) / 255.0;
+    RgbaF32::new(f(c.r), f(c.g), f(c.b), f(c.a))
+  }
+}
+
+impl From<RgbaF32> for Rgba8 {
+  fn from(c: RgbaF32) -> Self {
+    let f = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    Rgba8::new(f(c.r), f(c.g), f(c.b), f(c.a))
+  }
+}
+
+impl From<[u8; 4]> for Rgba8 {
+  fn from([r, g, b, a]: [u8; 4]) -> Self { Rgba8::new(r, g, b, a) }
+}
+
+impl From<Rgba8> for [u8; 4] {
+  fn from(c: Rgba8) -> Self { [c.r, c.g, c.b, c.a] }
+}
+
+impl From<RgbaF32> for [f32; 4] {
+  fn from(c: RgbaF32) -> Self { [c.r, c.g, c.b, c.a] }
+}
+
+impl<T: PixelComponent> From<[T; 4]> for RgbaF32 {
+  /// Scales components of any type by their [`PixelComponent::WHITE`]
+  fn from(components: [T; 4]) -> Self {
+    let white = T::WHITE.to_f64().unwrap_or(1.0);
+    let [r, g, b, a] =
+      components.map(|v| (v.to_f64().unwrap_or_default() / white) as f32);
+    RgbaF32::new(r, g, b, a)
+  }
+}
+
+impl FromStr for Rgba8 {
+  type Err = Error;
+
+  fn from_str(css: &str) -> Result<Self> { Rgba8::parse(css) }
+}
+
+impl FromStr for RgbaF32 {
+  type Err = Error;
+
+  fn from_str(css: &str) -> Result<Self> { RgbaF32::parse(css) }
+}
+
+impl fmt::Display for Rgba8 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.to_hex())
+  }
+}
+
+fn parse(css: &str) -> Option<RgbaF32> {
+  if let Some(hex) = css.strip_prefix('#') {
+    return parse_hex(hex).map(Into::into);
+  }
+  if let Some((function, arguments)) =
+    css.strip_suffix(')').and_then(|css| css.split_once('('))
+  {
+    return parse_function(&function.trim().to_ascii_lowercase(), arguments);
+  }
+  let name = css.to_ascii_lowercase();
+  NAMED_COLORS
+    .binary_search_by_key(&name.as_str(), |(name, _)| name)
+    .ok()
+    .map(|i| NAMED_COLORS[i].1.into())
+}
+
+fn parse_hex(hex: &str) -> Option<Rgba8> {
+  let digits = hex
+    .chars()
+    .map(|c| c.to_digit(16).map(|d| d as u8))
+    .collect::<Option<Vec<u8>>>()?;
+  let channels: Vec<u8> = match digits.len() {
+    3 | 4 => digits.iter().map(|d| d * 17).collect(),
+    6 | 8 => digits.chunks(2).map(|p| p[0] * 16 + p[1]).collect(),
+    _ => return None,
+  };
+  Some(Rgba8::new(
+    channels[0],
+    channels[1],
+    channels[2],
+    channels.get(3).copied().unwrap_or(u8::MAX),
+  ))
+}
+
+/// A number, or a percentage of `hundred_percent`
+fn number(value: &str, hundred_percent: f32) -> Option<f32> {
+  match value.strip_suffix('%') {
+    Some(percent) =>
+      Some(percent.parse::<f32>().ok()? / 100.0 * hundred_percent),
+    None => value.parse().ok(),
+  }
+}
+
+/// An angle in degrees, from a plain number or one with a CSS angle unit
+fn hue(value: &str) -> Option<f32> {
+  let units = [
+    ("deg", 1.0),
+    ("grad", 0.9),
+    ("rad", 180.0 / std::f32::consts::PI),
+    ("turn", 360.0),
+  ];
+  for (unit, scale) in units {
+    if let Some(number) = value.strip_suffix(unit) {
+      return Some(number.parse::<f32>().ok()? * scale);
+    }
+  }
+  value.parse().ok()
+}
+
+/// Parses the arguments of `rgb()`, `hsl()` or `hwb()` in either the comma
+/// separated or the space separated syntax, with optional alpha
+fn parse_function(function: &str, arguments: &str) -> Option<RgbaF32> {
+  let parts: Vec<&str> = arguments
+    .split(|c: char| c.is_whitespace() || c == ',' || c == '/')
+    .filter(|p| !p.is_empty())
+    .collect();
+  if !(3..=4).contains(&parts.len()) {
+    return None;
+  }
+  let alpha = match parts.get(3) {
+    Some(alpha) => number(alpha, 1.0)?.clamp(0.0, 1.0),
+    None => 1.0,
+  };
+  let color = match function {
+    "rgb" | "rgba" => {
+      let channel = |p: &str| Some(number(p, 255.0)?.clamp(0.0, 255.0) / 255.0);
+      RgbaF32::rgb(channel(parts[0])?, channel(parts[1])?, channel(parts[2])?)
+    }
+    "hsl" | "hsla" =>
+      RgbaF32::from_hsl(
+        hue(parts[0])?,
+        number(parts[1], 1.0)?,
+        number(parts[2], 1.0)?,
+      ),
+    "hwb" =>
+      RgbaF32::from_hwb(
+        hue(parts[0])?,
+        number(parts[1], 1.0)?,
+        number(parts[2], 1.0)?,
+      ),
+    _ => return None,
+  };
+  Some(color.with_alpha(alpha))
+}
+
+/// The CSS named colors, sorted by name
+const NAMED_COLORS: &[(&str, Rgba8)] = &[
+  ("aliceblue", Rgba8::rgb(240, 248, 255)),
+  ("antiquewhite", Rgba8::rgb(250, 235, 215)),
+  ("aqua", Rgba8::rgb(0, 255, 255)),
+  ("aquamarine", Rgba8::rgb(127, 255, 212)),
+  ("azure", Rgba8::rgb(240, 255, 255)),
+  ("beige", Rgba8::rgb(245, 245, 220)),
+  ("bisque", Rgba8::rgb(255, 228, 196)),
+  ("black", Rgba8::rgb(0, 0, 0)),
+  ("blanchedalmond", Rgba8::rgb(255, 235, 205)),
+  ("blue", Rgba8::rgb(0, 0, 255)),
+  ("blueviolet", Rgba8::rgb(138, 43, 226)),
+  ("brown", Rgba8::rgb(165, 42, 42)),
+  ("burlywood", Rgba8::rgb(222, 184, 135)),
+  ("cadetblue", Rgba8::rgb(95, 158, 160)),
+  ("chartreuse", Rgba8::rgb(127, 255, 0)),
+  ("chocolate", Rgba8::rgb(210, 105, 30)),
+  ("coral", Rgba8::rgb(255, 127, 80)),
+  ("cornflowerblue", Rgba8::rgb(100, 149, 237)),
+  ("cornsilk", Rgba8::rgb(255, 248, 220)),
+  ("crimson", Rgba8::rgb(220, 20, 60)),
+  ("cyan", Rgba8::rgb(0, 255, 255)),
+  ("darkblue", Rgba8::rgb(0, 0, 139)),
+  ("darkcyan", Rgba8::rgb(0, 139, 139)),
+  ("darkgoldenrod", Rgba8::rgb(184, 134, 11)),
+  ("darkgray", Rgba8::rgb(169, 169, 169)),
+  ("darkgreen", Rgba8::rgb(0, 100, 0)),
+  ("darkgrey", Rgba8::rgb(169, 169, 169)),
+  ("darkkhaki", Rgba8::rgb(189, 183, 107)),
+  ("darkmagenta", Rgba8::rgb(139, 0, 139)),
+  ("darkolivegreen", Rgba8::rgb(85, 107, 47)),
+  ("darkorange", Rgba8::rgb(255, 140, 0)),
+  ("darkorchid", Rgba8::rgb(153, 50, 204)),
+  ("darkred", Rgba8::rgb(139, 0, 0)),
+  ("darksalmon", Rgba8::rgb(233, 150, 122)),
+  ("darkseagreen", Rgba8::rgb(143, 188, 143)),
+  ("darkslateblue", Rgba8::rgb(72, 61, 139)),
+  ("darkslategray", Rgba8::rgb(47, 79, 79)),
+  ("darkslategrey", Rgba8::rgb(47, 79, 79)),
+  ("darkturquoise", Rgba8::rgb(0, 206, 209)),
+  ("darkviolet", Rgba8::rgb(148, 0, 211)),
+  ("deeppink", Rgba8::rgb(255, 20, 147)),
+  ("deepskyblue", Rgba8::rgb(0, 191, 255)),
+  ("dimgray", Rgba8::rgb(105, 105, 105)),
+  ("dimgrey", Rgba8::rgb(105, 105, 105)),
+  ("dodgerblue", Rgba8::rgb(30, 144, 255)),
+  ("firebrick", Rgba8::rgb(178, 34, 34)),
+  ("floralwhite", Rgba8::rgb(255, 250, 240)),
+  ("forestgreen", Rgba8::rgb(34, 139, 34)),
+  ("fuchsia", Rgba8::rgb(255, 0, 255)),
+  ("gainsboro", Rgba8::rgb(220, 220, 220)),
+  ("ghostwhite", Rgba8::rgb(248, 248, 255)),
+  ("gold", Rgba8::rgb(255, 215, 0)),
+  ("goldenrod", Rgba8::rgb(218, 165, 32)),
+  ("gray", Rgba8::rgb(128, 128, 128)),
+  ("green", Rgba8::rgb(0, 128, 0)),
+  ("greenyellow", Rgba8::rgb(173, 255, 47)),
+  ("grey", Rgba8::rgb(128, 128, 128)),
+  ("honeydew", Rgba8::rgb(240, 255, 240)),
+  ("hotpink", Rgba8::rgb(255, 105, 180)),
+  ("indianred", Rgba8::rgb(205, 92, 92)),
+  ("indigo", Rgba8::rgb(75, 0, 130)),
+  ("ivory", Rgba8::rgb(255, 255, 240)),
+  ("khaki", Rgba8::rgb(240, 230, 140)),
+  ("lavender", Rgba8::rgb(230, 230, 250)),
+  ("lavenderblush", Rgba8::rgb(255, 240, 245)),
+  ("lawngreen", Rgba8::rgb(124, 252, 0)),
+  ("lemonchiffon", Rgba8::rgb(255, 250, 205)),
+  ("lightblue", Rgba8::rgb(173, 216, 230)),
+  ("lightcoral", Rgba8::rgb(240, 128, 128)),
+  ("lightcyan", Rgba8::rgb(224, 255, 255)),
+  ("lightgoldenrodyellow", Rgba8::rgb(250, 250, 210)),
+  ("lightgray", Rgba8::rgb(211, 211, 211)),
+  ("lightgreen", Rgba8::rgb(144, 238, 144)),
+  ("lightgrey", Rgba8::rgb(211, 211, 211)),
+  ("lightpink", Rgba8::rgb(255, 182, 193)),
+  ("lightsalmon", Rgba8::rgb(255, 160, 122)),
+  ("lightseagreen", Rgba8::rgb(32, 178, 170)),
+  ("lightskyblue", Rgba8::rgb(135, 206, 250)),
+  ("lightslategray", Rgba8::rgb(119, 136, 153)),
+  ("lightslategrey", Rgba8::rgb(119, 136, 153)),
+  ("lightsteelblue", Rgba8::rgb(176, 196, 222)),
+  ("lightyellow", Rgba8::rgb(255, 255, 224)),
+  ("lime", Rgba8::rgb(0, 255, 0)),
+  ("limegreen", Rgba8::rgb(50, 205, 50)),
+  ("linen", Rgba8::rgb(250, 240, 230)),
+  ("magenta", Rgba8::rgb(255, 0, 255)),
+  ("maroon", Rgba8::rgb(128, 0, 0)),
+  ("mediumaquamarine", Rgba8::rgb(102, 205, 170)),
+  ("mediumblue", Rgba8::rgb(0, 0, 205)),
+  ("mediumorchid", Rgba8::rgb(186, 85, 211)),
+  ("mediumpurple", Rgba8::rgb(147, 112, 219)),
+  ("mediumseagreen", Rgba8::rgb(60, 179, 113)),
+  ("mediumslateblue", Rgba8::rgb(123, 104, 238)),
+  ("mediumspringgreen", Rgba8::rgb(0, 250, 154)),
+  ("mediumturquoise", Rgba8::rgb(72, 209, 204)),
+  ("mediumvioletred", Rgba8::rgb(199, 21, 133)),
+  ("midnightblue", Rgba8::rgb(25, 25, 112)),
+  ("mintcream", Rgba8::rgb(245, 255, 250)),
+  ("mistyrose", Rgba8::rgb(255, 228, 225)),
+  ("moccasin", Rgba8::rgb(255, 228, 181)),
+  ("navajowhite", Rgba8::rgb(255, 222, 173)),
+  ("navy", Rgba8::rgb(0, 0, 128)),
+  ("oldlace", Rgba8::rgb(253, 245, 230)),
+  ("olive", Rgba8::rgb(128, 128, 0)),
+  ("olivedrab", Rgba8::rgb(107, 142, 35)),
+  ("orange", Rgba8::rgb(255, 165, 0)),
+  ("orangered", Rgba8::rgb(255, 69, 0)),
+  ("orchid", Rgba8::rgb(218, 112, 214)),
+  ("palegoldenrod", Rgba8::rgb(238, 232, 170)),
+  ("palegreen", Rgba8::rgb(152, 251, 152)),
+  ("paleturquoise", Rgba8::rgb(175, 238, 238)),
+  ("palevioletred", Rgba8::rgb(219, 112, 147)),
+  ("papayawhip", Rgba8::rgb(255, 239, 213)),
+  ("peachpuff", Rgba8::rgb(255, 218, 185)),
+  ("peru", Rgba8::rgb(205, 133, 63)),
+  ("pink", Rgba8::rgb(255, 192, 203)),
+  ("plum", Rgba8::rgb(221, 160, 221)),
+  ("powderblue", Rgba8::rgb(176, 224, 230)),
+  ("purple", Rgba8::rgb(128, 0, 128)),
+  ("rebeccapurple", Rgba8::rgb(102, 51, 153)),
+  ("red", Rgba8::rgb(255, 0, 0)),
+  ("rosybrown", Rgba8::rgb(188, 143, 143)),
+  ("royalblue", Rgba8::rgb(65, 105, 225)),
+  ("saddlebrown", Rgba8::rgb(139, 69, 19)),
+  ("salmon", Rgba8::rgb(250, 128, 114)),
+  ("sandybrown", Rgba8::rgb(244, 164, 96)),
+  ("seagreen", Rgba8::rgb(46, 139, 87)),
+  ("seashell", Rgba8::rgb(255, 245, 238)),
+  ("sienna", Rgba8::rgb(160, 82, 45)),
+  ("silver", Rgba8::rgb(192, 192, 192)),
+  ("skyblue", Rgba8::rgb(135, 206, 235)),
+  ("slateblue", Rgba8::rgb(106, 90, 205)),
+  ("slategray", Rgba8::rgb(112, 128, 144)),
+  ("slategrey", Rgba8::rgb(112, 128, 144)),
+  ("snow", Rgba8::rgb(255, 250, 250)),
+  ("springgreen", Rgba8::rgb(0, 255, 127)),
+  ("steelblue", Rgba8::rgb(70, 130, 180)),
+  ("tan", Rgba8::rgb(210, 180, 140)),
+  ("teal", Rgba8::rgb(0, 128, 128)),
+  ("thistle", Rgba8::rgb(216, 191, 216)),
+  ("tomato", Rgba8::rgb(255, 99, 71)),
+  ("transparent", Rgba8::TRANSPARENT),
+  ("turquoise", Rgba8::rgb(64, 224, 208)),
+  ("violet", Rgba8::rgb(238, 130, 238)),
+  ("wheat", Rgba8::rgb(245, 222, 179)),
+  ("white", Rgba8::rgb(255, 255, 255)),
+  ("whitesmoke", Rgba8::rgb(245, 245, 245)),
+  ("yellow", Rgba8::rgb(255, 255, 0)),
+  ("yellowgreen", Rgba8::rgb(154, 205, 50)),
+];
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_and_converts_colors() {
+    let orange = Rgba8::rgb(255, 165, 0);
+    for css in [
+      "orange",
+      " Orange ",
+      "#ffa500",
+      "#FFA500FF",
+      "rgb(255, 165, 0)",
+      "rgb(100% 64.7% 0%)",
+      "hsl(38.8deg 100% 50%)",
+      "hwb(38.8 0% 0%)",
+    ] {
+      assert_eq!(css.parse::<Rgba8>().unwrap(), orange, "{css}");
+    }
+    assert_eq!(
+      Rgba8::parse("rgba(0, 0, 255, 0.5)").unwrap(),
+      Rgba8::BLUE.with_alpha(128)
+    );
+    assert_eq!(Rgba8::parse("#0f08").unwrap(), Rgba8::new(0, 255, 0, 136));
+    assert_eq!(
+      Rgba8::parse("hsl(0.5turn, 100%, 25%)").unwrap(),
+      Rgba8::rgb(0, 128, 128)
+    );
+    assert_eq!(Rgba8::from_hwb(0.0, 0.6, 0.6), Rgba8::GRAY);
+    for css in ["#12345", "rgb(1, 2)", "hsv(0, 0, 0)", "notacolor"] {
+      assert!(Rgba8::parse(css).is_err(), "{css}");
+    }
+    assert!(NAMED_COLORS.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(Rgba8::ORANGE.to_string(), "#ffa500");
+    assert_eq!(Rgba8::RED.with_alpha(0x80).to_hex(), "#ff000080");
+
+    let gray = ImageBuffer::<u16, 2, true>::empty(1, 1);
+    let teal = RgbaF32::from(Rgba8::rgb(0, 128, 128));
+    assert_eq!(teal.to_pixel(&gray), [25902, 65535]);
+    assert_eq!(
+      RgbaF32::from([0.5f32, 0.0, 1.0, 1.0]),
+      RgbaF32::rgb(0.5, 0.0, 1.0)
+    );
+    assert_eq!(
+      Rgba8::from(RgbaF32::from([255u8, 0, 0, 51])),
+      Rgba8::RED.with_alpha(51)
+    );
+  }
+}
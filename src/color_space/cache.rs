@@ -0,0 +1,187 @@
+//! Lookup tables that replace the per-pixel arithmetic of the conversions in
+//! [`color_space`](super) for 8-bit sources.
+
+use std::sync::OnceLock;
+
+use super::rgb_to_hsv;
+use crate::image_buffer::ImageBuffer;
+
+/// Entries in the cube root table, which spans the XYZ values an 8-bit RGB
+/// pixel can produce
+const CUBE_ROOT_STEPS: usize = 4096;
+const CUBE_ROOT_LIMIT: f32 = 1.1;
+
+/// Lab tables: each channel's contribution to X, Y and Z, and the companding
+/// function sampled for linear interpolation
+struct LabTables {
+  xyz:  [[[f32; 256]; 3]; 3],
+  cube: Vec<f32>,
+}
+
+/// HSV tables: saturation by maximum and spread, and hue by spread and
+/// difference of the other two channels for each of the three sectors
+struct HsvTables {
+  saturation: Vec<u8>,
+  hue:        [Vec<u8>; 3],
+}
+
+/// Tables for converting 8-bit RGB frames, built on first use and then
+/// shared by every later frame. Building them costs about as much as
+/// converting one small image per pixel; keep a cache around, or use
+/// [`ConversionCache::shared`], when converting video or many images.
+#[derive(Default)]
+pub struct ConversionCache {
+  lab: OnceLock<LabTables>,
+  hsv: OnceLock<HsvTables>,
+}
+
+impl ConversionCache {
+  pub fn new() -> Self { Self::default() }
+
+  /// A process-wide cache
+  pub fn shared() -> &'static ConversionCache {
+    static SHARED: OnceLock<ConversionCache> = OnceLock::new();
+    SHARED.get_or_init(ConversionCache::new)
+  }
+
+  fn lab_tables(&self) -> &LabTables {
+    self.lab.get_or_init(|| {
+      let matrix = [
+        [0.4124564, 0.3575761, 0.1804375],
+        [0.2126729, 0.7151522, 0.0721750],
+        [0.0193339, 0.119192, 0.9503041],
+      ];
+      let mut xyz = [[[0.0; 256]; 3]; 3];
+      for (row, weights) in xyz.iter_mut().zip(matrix) {
+        for (channel, weight) in row.iter_mut().zip(weights) {
+          for (v, entry) in channel.iter_mut().enumerate() {
+            *entry = weight * v as f32 / 255.0;
+          }
+        }
+      }
+      let cube = (0..=CUBE_ROOT_STEPS)
+        .map(|i| {
+          let t = i as f32 * CUBE_ROOT_LIMIT / CUBE_ROOT_STEPS as f32;
+          if t > 0.008856 {
+            t.cbrt()
+          } else {
+            7.787 * t + 16.0 / 116.0
+          }
+        })
+        .collect();
+      LabTables {
+        xyz,
+        cube,
+      }
+    })
+  }
+
+  fn hsv_tables(&self) -> &HsvTables {
+    self.hsv.get_or_init(|| {
+      let mut saturation = vec![0; 256 * 256];
+      let mut hue =
+        [vec![0; 256 * 511], vec![0; 256 * 511], vec![0; 256 * 511]];
+      for max in 0..256usize {
+        for delta in 0..=max {
+          let min = (max - delta) as u8;
+          saturation[max * 256 + delta] =
+            rgb_to_hsv::<u8, u8>(&[max as u8, min, min])[1];
+        }
+      }
+      for delta in 1..256i32 {
+        for diff in -delta..=delta {
+          let i = delta as usize * 511 + (diff + 255) as usize;
+          for (sector, table) in hue.iter_mut().enumerate() {
+            let h = (diff as f64 / delta as f64 + 2.0 * sector as f64)
+              .rem_euclid(6.0);
+            table[i] = super::from_unit::<u8>(h / 6.0);
+          }
+        }
+      }
+      HsvTables {
+        saturation,
+        hue,
+      }
+    })
+  }
+
+  /// [`rgb_to_cielab`](super::rgb_to_cielab) for a whole 8-bit image, with
+  /// table lookups in place of the per-pixel matrix product and cube roots.
+  /// Results agree with it to within a few thousandths.
+  pub fn rgb_to_lab(
+    &self,
+    image: &ImageBuffer<u8, 3, false>,
+  ) -> ImageBuffer<f32, 3, false> {
+    let tables = self.lab_tables();
+    let scale = CUBE_ROOT_STEPS as f32 / CUBE_ROOT_LIMIT;
+    let compand = |t: f32| {
+      let position = (t * scale).clamp(0.0, CUBE_ROOT_STEPS as f32 - 1.0);
+      let i = position as usize;
+      let fraction = position - i as f32;
+      tables.cube[i] + (tables.cube[i + 1] - tables.cube[i]) * fraction
+    };
+    image.map_into(&mut |pel| {
+      let [x, y, z] = tables.xyz.each_ref().map(|row| {
+        row[0][pel[0] as usize]
+          + row[1][pel[1] as usize]
+          + row[2][pel[2] as usize]
+      });
+      let (x, y, z) = (compand(x), compand(y), compand(z));
+      [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+    })
+  }
+
+  /// [`rgb_to_hsv`] for a whole 8-bit image, with table lookups in place of
+  /// the per-pixel divisions. Components match it to within one level.
+  pub fn rgb_to_hsv(
+    &self,
+    image: &ImageBuffer<u8, 3, false>,
+  ) -> ImageBuffer<u8, 3, false> {
+    let tables = self.hsv_tables();
+    image.map_into(&mut |&[r, g, b]| {
+      let max = r.max(g).max(b);
+      let delta = (max - r.min(g).min(b)) as usize;
+      if delta == 0 {
+        return [0, 0, max];
+      }
+      let (sector, diff) = if max == r {
+        (0, g as i32 - b as i32)
+      } else if max == g {
+        (1, b as i32 - r as i32)
+      } else {
+        (2, r as i32 - g as i32)
+      };
+      let hue = tables.hue[sector][delta * 511 + (diff + 255) as usize];
+      [hue, tables.saturation[max as usize * 256 + delta], max]
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{color_space::rgb_to_cielab, pixel::PixelContainer};
+
+  #[test]
+  fn tables_match_per_pixel_conversions() {
+    let image =
+      ImageBuffer::<u8, 3, false>::empty(64, 64).map_indexed(&mut |x, y, _| {
+        [(x * 4) as u8, (y * 4) as u8, ((x * 7 + y * 3) % 256) as u8]
+      });
+    let cache = ConversionCache::shared();
+
+    let lab = cache.rgb_to_lab(&image);
+    let expected: ImageBuffer<f32, 3, false> =
+      image.map_into(&mut |pel| rgb_to_cielab(pel));
+    for (a, b) in lab.components().iter().zip(expected.components()) {
+      assert!((a - b).abs() < 0.01, "{a} vs {b}");
+    }
+
+    let hsv = cache.rgb_to_hsv(&image);
+    let expected: ImageBuffer<u8, 3, false> =
+      image.map_into(&mut |pel| rgb_to_hsv(pel));
+    for (a, b) in hsv.components().iter().zip(expected.components()) {
+      assert!(a.abs_diff(*b) <= 1, "{a} vs {b}");
+    }
+  }
+}
@@ -0,0 +1,119 @@
+//! Bringing colors outside the sRGB gamut back inside it when converting to
+//! 8-bit RGB.
+//!
+//! Clipping each channel on its own, which is what a plain cast does, shifts
+//! the hue of saturated colors and flattens gradients that run out of gamut
+//! into bands. The other mappings move such colors toward gray or scale them
+//! down as a whole, which keeps their hue.
+
+use super::{cielab_to_rgb, linear_to_srgb};
+use crate::{image_buffer::ImageBuffer, pixel::component_from_f64};
+
+/// How colors outside the sRGB gamut are brought inside it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum GamutMapping {
+  /// Clamps each channel on its own. Fastest, but shifts hues.
+  #[default]
+  Clip,
+  /// Keeps luminance and hue, reducing saturation just enough to fit
+  Compress,
+  /// Scales bright colors down as a whole so their hue and saturation
+  /// survive, at the cost of brightness. Colors below black are compressed
+  /// toward gray.
+  PreserveHighlights,
+}
+
+/// Luminance weights of the sRGB primaries
+const LUMA: [f64; 3] = [0.2126, 0.7152, 0.0722];
+
+/// Maps one RGB color with channels nominally between 0 and 1 into that
+/// range
+pub fn map_pixel(rgb: [f64; 3], mapping: GamutMapping) -> [f64; 3] {
+  let clip = |rgb: [f64; 3]| rgb.map(|v| v.clamp(0.0, 1.0));
+  if rgb.iter().all(|v| (0.0..=1.0).contains(v)) {
+    return rgb;
+  }
+  match mapping {
+    GamutMapping::Clip => clip(rgb),
+    GamutMapping::Compress => clip(toward_gray(rgb, true)),
+    GamutMapping::PreserveHighlights => {
+      let rgb = toward_gray(rgb, false);
+      let max = rgb.iter().fold(0.0f64, |m, &v| m.max(v));
+      if max > 1.0 {
+        clip(rgb.map(|v| v / max))
+      } else {
+        clip(rgb)
+      }
+    }
+  }
+}
+
+/// Moves `rgb` toward the gray of the same luminance until no channel is
+/// below 0 or, if `upper`, above 1
+fn toward_gray(rgb: [f64; 3], upper: bool) -> [f64; 3] {
+  let luma: f64 = rgb.iter().zip(LUMA).map(|(v, w)| v * w).sum();
+  let gray = luma.clamp(0.0, 1.0);
+  let mut t = 1.0f64;
+  for v in rgb {
+    if v < 0.0 {
+      t = t.min(gray / (gray - v));
+    } else if upper && v > 1.0 {
+      t = t.min((1.0 - gray) / (v - gray));
+    }
+  }
+  rgb.map(|v| gray + t * (v - gray))
+}
+
+/// Encodes a linear-light float image as 8-bit sRGB, mapping colors outside
+/// the gamut as `mapping` says before the transfer curve is applied
+pub fn linear_to_srgb8(
+  image: &ImageBuffer<f32, 3, false>,
+  mapping: GamutMapping,
+) -> ImageBuffer<u8, 3, false> {
+  image.map_into(&mut |pel| {
+    map_pixel(pel.map(f64::from), mapping)
+      .map(|v| component_from_f64(linear_to_srgb(v) * 255.0))
+  })
+}
+
+/// Converts a Lab image, as produced by
+/// [`rgb_to_cielab`](super::rgb_to_cielab), to 8-bit RGB, mapping colors
+/// outside the gamut as `mapping` says
+pub fn lab_to_srgb8(
+  image: &ImageBuffer<f32, 3, false>,
+  mapping: GamutMapping,
+) -> ImageBuffer<u8, 3, false> {
+  image.map_into(&mut |pel| {
+    let rgb = cielab_to_rgb::<f32, f64>(pel).map(|v| v / 255.0);
+    map_pixel(rgb, mapping).map(|v| component_from_f64(v * 255.0))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::pixel::PixelContainer;
+
+  #[test]
+  fn mappings_keep_hue_that_clipping_loses() {
+    let orange = [1.6, 0.8, 0.2];
+    assert_eq!(map_pixel(orange, GamutMapping::Clip), [1.0, 0.8, 0.2]);
+    let highlights = map_pixel(orange, GamutMapping::PreserveHighlights);
+    assert!((highlights[1] / highlights[0] - 0.5).abs() < 1e-9);
+
+    let compressed = map_pixel(orange, GamutMapping::Compress);
+    assert!(compressed.iter().all(|v| (0.0..=1.0).contains(v)));
+    assert!(compressed[0] > compressed[1] && compressed[1] > compressed[2]);
+    // In-gamut colors pass through untouched
+    assert_eq!(
+      map_pixel([0.3, 0.2, 0.1], GamutMapping::Compress),
+      [0.3, 0.2, 0.1]
+    );
+
+    let image = ImageBuffer::<f32, 3, false>::with_val(&[1.0, -0.2, 0.0], 2, 2);
+    let clipped = linear_to_srgb8(&image, GamutMapping::Clip);
+    assert_eq!(clipped.get_pixel(0, 0), &[255, 0, 0]);
+    let compressed = linear_to_srgb8(&image, GamutMapping::Compress);
+    assert!(compressed.get_pixel(0, 0)[2] > 0);
+  }
+}
@@ -0,0 +1,132 @@
+//! Color conversion through ICC profiles, for matching a real display or
+//! printing process. Requires the `icc` feature.
+
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+
+use crate::{
+  error::{Error, Result},
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// A parsed ICC color profile
+#[derive(Clone, Debug)]
+pub struct IccProfile {
+  profile: ColorProfile,
+}
+
+impl IccProfile {
+  /// Parses an ICC profile, such as one embedded in a TIFF or JPEG file or
+  /// supplied by a print shop
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+    let profile = ColorProfile::new_from_slice(bytes)
+      .map_err(|e| Error::Decode(format!("Invalid ICC profile: {e}")))?;
+    Ok(IccProfile {
+      profile,
+    })
+  }
+
+  /// Whether the profile describes a CMYK process
+  pub fn is_cmyk(&self) -> bool {
+    self.profile.color_space == DataColorSpace::Cmyk
+  }
+
+  fn require_cmyk(&self) -> Result<()> {
+    if !self.is_cmyk() {
+      return Err(Error::Unsupported(
+        "ICC profile does not describe a CMYK process".to_string(),
+      ));
+    }
+    Ok(())
+  }
+
+  /// The standard sRGB profile
+  pub fn srgb() -> Self {
+    IccProfile {
+      profile: ColorProfile::new_srgb(),
+    }
+  }
+}
+
+fn transform<
+  T: PixelComponent,
+  const N: usize,
+  const A: bool,
+  const M: usize,
+  const B: bool,
+>(
+  image: &ImageBuffer<T, N, A>,
+  from: (&IccProfile, Layout),
+  to: (&IccProfile, Layout),
+) -> Result<ImageBuffer<T, M, B>> {
+  let executor = from
+    .0
+    .profile
+    .create_transform_f32(
+      from.1,
+      &to.0.profile,
+      to.1,
+      TransformOptions::default(),
+    )
+    .map_err(|e| Error::Unsupported(format!("ICC transform: {e}")))?;
+
+  let white = T::WHITE.to_f64().unwrap_or(1.0);
+  let src: Vec<f32> = image
+    .components()
+    .iter()
+    .map(|c| (c.to_f64().unwrap_or_default() / white) as f32)
+    .collect();
+  let mut dst = vec![0f32; image.width * image.height * M];
+  executor
+    .transform(&src, &mut dst)
+    .map_err(|e| Error::Unsupported(format!("ICC transform: {e}")))?;
+
+  let mut result =
+    ImageBuffer::try_empty(image.width, image.height, &Limits::none())?;
+  for (c, &v) in result.components_mut().iter_mut().zip(&dst) {
+    *c = component_from_f64(f64::from(v) * white);
+  }
+  Ok(result)
+}
+
+/// Converts CMYK to RGB using the profile describing the printing process
+/// and the profile of the RGB space to produce
+pub fn cmyk_to_rgb<T: PixelComponent>(
+  image: &ImageBuffer<T, 4, false>,
+  cmyk: &IccProfile,
+  rgb: &IccProfile,
+) -> Result<ImageBuffer<T, 3, false>> {
+  cmyk.require_cmyk()?;
+  // moxcms reads 4-channel CMYK through the RGBA layout
+  transform(image, (cmyk, Layout::Rgba), (rgb, Layout::Rgb))
+}
+
+/// Converts RGB to CMYK for the printing process described by `cmyk`
+pub fn rgb_to_cmyk<T: PixelComponent>(
+  image: &ImageBuffer<T, 3, false>,
+  rgb: &IccProfile,
+  cmyk: &IccProfile,
+) -> Result<ImageBuffer<T, 4, false>> {
+  cmyk.require_cmyk()?;
+  transform(image, (rgb, Layout::Rgb), (cmyk, Layout::Rgba))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn icc_rejects_non_cmyk_profile() {
+    assert!(matches!(
+      IccProfile::from_bytes(b"not a profile"),
+      Err(Error::Decode(_))
+    ));
+    let image = ImageBuffer::<u8, 4, false>::empty(2, 2);
+    let srgb = IccProfile::srgb();
+    assert!(!srgb.is_cmyk());
+    assert!(matches!(
+      cmyk_to_rgb(&image, &srgb, &srgb),
+      Err(Error::Unsupported(_))
+    ));
+  }
+}
@@ -0,0 +1,331 @@
+use num_traits::NumCast;
+
+use crate::{
+  image_buffer::ImageBuffer,
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+};
+
+mod cache;
+pub mod gamut;
+pub mod primaries;
+pub mod transfer;
+#[cfg(feature = "icc")]
+pub mod icc;
+
+pub use cache::ConversionCache;
+pub use primaries::RgbSpace;
+pub use transfer::{Hdr10Metadata, TransferFunction};
+
+#[derive(Clone)]
+pub enum ColorSpace<T: PixelComponent> {
+  Rgba(ImageBuffer<T, 4, true>),
+  Rgb(ImageBuffer<T, 3, false>),
+  Hsv(ImageBuffer<T, 3, false>),
+  Cielab(ImageBuffer<T, 3, false>),
+  /// Ink coverage for cyan, magenta, yellow and black, where white is full
+  /// coverage
+  Cmyk(ImageBuffer<T, 4, false>),
+}
+
+/// The variants of [`ColorSpace`], without their data
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpaceKind {
+  Rgba,
+  Rgb,
+  Hsv,
+  Cielab,
+  Cmyk,
+}
+
+impl<T: PixelComponent> ColorSpace<T> {
+  pub fn kind(&self) -> ColorSpaceKind {
+    match self {
+      ColorSpace::Rgba(_) => ColorSpaceKind::Rgba,
+      ColorSpace::Rgb(_) => ColorSpaceKind::Rgb,
+      ColorSpace::Hsv(_) => ColorSpaceKind::Hsv,
+      ColorSpace::Cielab(_) => ColorSpaceKind::Cielab,
+      ColorSpace::Cmyk(_) => ColorSpaceKind::Cmyk,
+    }
+  }
+
+  /// The image as RGB, dropping any alpha channel
+  pub fn to_rgb(&self) -> ImageBuffer<T, 3, false> {
+    match self {
+      ColorSpace::Rgb(buf) => buf.clone(),
+      ColorSpace::Rgba(buf) => buf.map_into(&mut |&[r, g, b, _]| [r, g, b]),
+      ColorSpace::Hsv(buf) => buf.map_into(&mut |pel| hsv_to_rgb(pel)),
+      ColorSpace::Cielab(buf) => buf.map_into(&mut |pel| cielab_to_rgb(pel)),
+      ColorSpace::Cmyk(buf) => buf.map_into(&mut |pel| cmyk_to_rgb(pel)),
+    }
+  }
+
+  /// The image converted to `kind`, through RGB unless it already is one.
+  /// Converting to RGBA adds an opaque alpha channel.
+  pub fn convert_to(&self, kind: ColorSpaceKind) -> ColorSpace<T> {
+    if self.kind() == kind {
+      return self.clone();
+    }
+    let rgb = self.to_rgb();
+    match kind {
+      ColorSpaceKind::Rgb => ColorSpace::Rgb(rgb),
+      ColorSpaceKind::Rgba =>
+        ColorSpace::Rgba(rgb.map_into(&mut |&[r, g, b]| [r, g, b, T::WHITE])),
+      ColorSpaceKind::Hsv =>
+        ColorSpace::Hsv(rgb.map_into(&mut |pel| rgb_to_hsv(pel))),
+      ColorSpaceKind::Cielab =>
+        ColorSpace::Cielab(rgb.map_into(&mut |pel| rgb_to_cielab(pel))),
+      ColorSpaceKind::Cmyk =>
+        ColorSpace::Cmyk(rgb.map_into(&mut |pel| rgb_to_cmyk(pel))),
+    }
+  }
+}
+
+/// Decodes an sRGB-encoded value between 0 and 1 to linear light
+pub(crate) fn srgb_to_linear(v: f64) -> f64 {
+  let v = v.clamp(0.0, 1.0);
+  if v <= 0.040_45 {
+    v / 12.92
+  } else {
+    ((v + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+/// Encodes a linear-light value between 0 and 1 with the sRGB curve
+pub(crate) fn linear_to_srgb(v: f64) -> f64 {
+  let v = v.clamp(0.0, 1.0);
+  if v <= 0.003_130_8 {
+    v * 12.92
+  } else {
+    1.055 * v.powf(1.0 / 2.4) - 0.055
+  }
+}
+
+fn unit<T: PixelComponent>(value: T) -> f64 {
+  value.to_f64().unwrap_or_default() / T::WHITE.to_f64().unwrap_or(1.0)
+}
+
+fn from_unit<T: PixelComponent>(value: f64) -> T {
+  component_from_f64(value * T::WHITE.to_f64().unwrap_or(1.0))
+}
+
+/// Converts one RGB pixel to CMYK with full gray component replacement,
+/// ignoring ink and paper characteristics
+///
+/// Use the `icc` module for conversions that match a real printing process.
+pub fn rgb_to_cmyk<T1: PixelComponent, T2: PixelComponent>(
+  rgb: &<ImageBuffer<T1, 3, false> as PixelContainer>::OnePixel,
+) -> <ImageBuffer<T2, 4, false> as PixelContainer>::OnePixel {
+  let [r, g, b] = rgb.map(unit);
+  let k = 1.0 - r.max(g).max(b);
+  if k >= 1.0 {
+    return [T2::zero(), T2::zero(), T2::zero(), T2::WHITE];
+  }
+  let ink = |v: f64| from_unit((1.0 - v - k) / (1.0 - k));
+  [ink(r), ink(g), ink(b), from_unit(k)]
+}
+
+/// Converts one CMYK pixel to RGB, the inverse of [`rgb_to_cmyk`]
+pub fn cmyk_to_rgb<T1: PixelComponent, T2: PixelComponent>(
+  cmyk: &<ImageBuffer<T1, 4, false> as PixelContainer>::OnePixel,
+) -> <ImageBuffer<T2, 3, false> as PixelContainer>::OnePixel {
+  let [c, m, y, k] = cmyk.map(unit);
+  let channel = |v: f64| from_unit((1.0 - v) * (1.0 - k));
+  [channel(c), channel(m), channel(y)]
+}
+
+/// Converts one RGB pixel to Y'CbCr in the limited range of BT.601, the
+/// inverse of [`ycbcr_to_rgb`]
+pub fn rgb_to_ycbcr<T1: PixelComponent, T2: PixelComponent>(
+  rgb: &<ImageBuffer<T1, 3, false> as PixelContainer>::OnePixel,
+) -> <ImageBuffer<T2, 3, false> as PixelContainer>::OnePixel {
+  let [r, g, b] = rgb.map(unit);
+  let y = 0.299 * r + 0.587 * g + 0.114 * b;
+  let chroma = |c: f64| c * 224.0 / 255.0 + 128.0 / 255.0;
+  [
+    from_unit(y * 219.0 / 255.0 + 16.0 / 255.0),
+    from_unit(chroma((b - y) / 1.772)),
+    from_unit(chroma((r - y) / 1.402)),
+  ]
+}
+
+/// Converts one Y'CbCr pixel in the limited range of BT.601, as produced by
+/// webcams and standard-definition video, to RGB
+pub fn ycbcr_to_rgb<T1: PixelComponent, T2: PixelComponent>(
+  ycbcr: &<ImageBuffer<T1, 3, false> as PixelContainer>::OnePixel,
+) -> <ImageBuffer<T2, 3, false> as PixelContainer>::OnePixel {
+  let [y, cb, cr] = ycbcr.map(unit);
+  let y = (y - 16.0 / 255.0) * 255.0 / 219.0;
+  let chroma = |c: f64| (c - 128.0 / 255.0) * 255.0 / 224.0;
+  let (cb, cr) = (chroma(cb), chroma(cr));
+  [
+    from_unit(y + 1.402 * cr),
+    from_unit(y - 0.344_136 * cb - 0.714_136 * cr),
+    from_unit(y + 1.772 * cb),
+  ]
+}
+
+/// Converts one RGB pixel to HSV. Hue is a fraction of a full turn from red,
+/// scaled like the other components, so that white is a full turn.
+pub fn rgb_to_hsv<T1: PixelComponent, T2: PixelComponent>(
+  rgb: &<ImageBuffer<T1, 3, false> as PixelContainer>::OnePixel,
+) -> <ImageBuffer<T2, 3, false> as PixelContainer>::OnePixel {
+  let [r, g, b] = rgb.map(unit);
+  let max = r.max(g).max(b);
+  let delta = max - r.min(g).min(b);
+  let hue = if delta <= 0.0 {
+    0.0
+  } else if max == r {
+    ((g - b) / delta).rem_euclid(6.0)
+  } else if max == g {
+    (b - r) / delta + 2.0
+  } else {
+    (r - g) / delta + 4.0
+  };
+  let saturation = if max > 0.0 { delta / max } else { 0.0 };
+  [from_unit(hue / 6.0), from_unit(saturation), from_unit(max)]
+}
+
+/// Converts one HSV pixel to RGB, the inverse of [`rgb_to_hsv`]
+pub fn hsv_to_rgb<T1: PixelComponent, T2: PixelComponent>(
+  hsv: &<ImageBuffer<T1, 3, false> as PixelContainer>::OnePixel,
+) -> <ImageBuffer<T2, 3, false> as PixelContainer>::OnePixel {
+  let [h, s, v] = hsv.map(unit);
+  let channel = |n: f64| {
+    let k = (n + h * 6.0) % 6.0;
+    from_unit(v - v * s * k.min(4.0 - k).clamp(0.0, 1.0))
+  };
+  [channel(5.0), channel(3.0), channel(1.0)]
+}
+
+pub fn rgb_to_cielab<T1: PixelComponent, T2: PixelComponent>(
+  rgb: &<ImageBuffer<T1, 3, false> as PixelContainer>::OnePixel,
+) -> <ImageBuffer<T2, 3, false> as PixelContainer>::OnePixel {
+  // This is not correct :sweaty:
+  let r = <f32 as NumCast>::from(rgb[0]).unwrap_or_default() / 255.0;
+  let g = <f32 as NumCast>::from(rgb[1]).unwrap_or_default() / 255.0;
+  let b = <f32 as NumCast>::from(rgb[2]).unwrap_or_default() / 255.0;
+  let x = r * 0.4124564 + g * 0.3575761 + b * 0.1804375;
+  let y = r * 0.2126729 + g * 0.7151522 + b * 0.0721750;
+  let z = r * 0.0193339 + g * 0.119192 + b * 0.9503041;
+  let x = if x > 0.008856 {
+    x.powf(1.0 / 3.0)
+  } else {
+    7.787 * x + 16.0 / 116.0
+  };
+  let y = if y > 0.008856 {
+    y.powf(1.0 / 3.0)
+  } else {
+    7.787 * y + 16.0 / 116.0
+  };
+  let z = if z > 0.008856 {
+    z.powf(1.0 / 3.0)
+  } else {
+    7.787 * z + 16.0 / 116.0
+  };
+  let l = <T2 as NumCast>::from(116.0 * y - 16.0).unwrap_or_default();
+  let a = <T2 as NumCast>::from(500.0 * (x - y)).unwrap_or_default();
+  let b = <T2 as NumCast>::from(200.0 * (y - z)).unwrap_or_default();
+  [l, a, b]
+}
+
+/// Converts one pixel produced by [`rgb_to_cielab`] back to RGB
+pub fn cielab_to_rgb<T1: PixelComponent, T2: PixelComponent>(
+  lab: &<ImageBuffer<T1, 3, false> as PixelContainer>::OnePixel,
+) -> <ImageBuffer<T2, 3, false> as PixelContainer>::OnePixel {
+  let [l, a, b] = lab.map(|v| v.to_f64().unwrap_or_default());
+  let y = (l + 16.0) / 116.0;
+  let x = a / 500.0 + y;
+  let z = y - b / 200.0;
+  let [x, y, z] = [x, y, z].map(|f| {
+    if f * f * f > 0.008856 {
+      f * f * f
+    } else {
+      (f - 16.0 / 116.0) / 7.787
+    }
+  });
+  let channel = |v: f64| component_from_f64(v * 255.0);
+  [
+    channel(x * 3.240_454_2 - y * 1.537_138_5 - z * 0.498_531_4),
+    channel(-x * 0.969_266 + y * 1.876_010_8 + z * 0.041_556),
+    channel(x * 0.055_643_4 - y * 0.204_025_9 + z * 1.057_225_2),
+  ]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn example_convert_rgb_u8_to_lab_f32() {
+    const WIDTH: usize = 4;
+    const HEIGHT: usize = 4;
+    let one_pel = [1u8, 2u8, 3u8];
+    let image = ImageBuffer::<u8, 3, false>::with_val(&one_pel, WIDTH, HEIGHT);
+    let lab_image: ImageBuffer<f32, 3, false> =
+      image.map_into(&mut |pel| rgb_to_cielab(pel));
+    for pel in lab_image.iter() {
+      // TODO this is not right :-)
+      assert_eq!(pel, &[6.586956, -2.9099135, -7.0868254])
+    }
+  }
+
+  #[test]
+  fn rgb_cmyk_round_trip_u8() {
+    let cmyk: [u8; 4] = rgb_to_cmyk::<u8, u8>(&[255, 128, 0]);
+    assert_eq!(cmyk, [0, 127, 255, 0]);
+    assert_eq!(rgb_to_cmyk::<u8, f32>(&[0, 0, 0]), [0.0, 0.0, 0.0, 1.0]);
+    let image = ImageBuffer::<u8, 3, false>::empty(4, 4)
+      .map_indexed(&mut |x, y, _| [(x * 60) as u8, (y * 60) as u8, 200]);
+    let round_trip: ImageBuffer<u8, 3, false> = image
+      .map_into::<_, u8, 4, false>(&mut |pel| rgb_to_cmyk(pel))
+      .map_into(&mut |pel| cmyk_to_rgb(pel));
+    assert_eq!(round_trip.components(), image.components());
+  }
+
+  #[test]
+  fn ycbcr_limited_range() {
+    assert_eq!(ycbcr_to_rgb::<u8, u8>(&[16, 128, 128]), [0, 0, 0]);
+    assert_eq!(ycbcr_to_rgb::<u8, u8>(&[235, 128, 128]), [255, 255, 255]);
+    // BT.601 red, rounded to whole levels
+    assert_eq!(ycbcr_to_rgb::<u8, u8>(&[81, 90, 240]), [254, 0, 0]);
+    assert_eq!(rgb_to_ycbcr::<u8, u8>(&[255, 0, 0]), [81, 90, 240]);
+  }
+
+  #[test]
+  fn rgb_hsv_round_trip() {
+    assert_eq!(rgb_to_hsv::<u8, f32>(&[0, 255, 0]), [1.0 / 3.0, 1.0, 1.0]);
+    assert_eq!(rgb_to_hsv::<u8, u8>(&[128, 128, 128]), [0, 0, 128]);
+    let image = ImageBuffer::<u8, 3, false>::empty(8, 8)
+      .map_indexed(&mut |x, y, _| [(x * 36) as u8, (y * 36) as u8, 90]);
+    let round_trip: ImageBuffer<u8, 3, false> = image
+      .map_into::<_, f32, 3, false>(&mut |pel| rgb_to_hsv(pel))
+      .map_into(&mut |pel| hsv_to_rgb(pel));
+    assert_eq!(round_trip.components(), image.components());
+  }
+
+  #[test]
+  fn convert_whole_buffers() {
+    let image = ImageBuffer::<u8, 3, false>::empty(8, 8)
+      .map_indexed(&mut |x, y, _| [(x * 36) as u8, (y * 36) as u8, 90]);
+    let rgb = ColorSpace::Rgb(image.clone());
+    for kind in [
+      ColorSpaceKind::Rgba,
+      ColorSpaceKind::Hsv,
+      ColorSpaceKind::Cmyk,
+    ] {
+      let converted = rgb.convert_to(kind);
+      assert_eq!(converted.kind(), kind);
+      let back = converted.to_rgb();
+      for (a, b) in back.components().iter().zip(image.components()) {
+        assert!(a.abs_diff(*b) <= 2, "{kind:?}: {a} vs {b}");
+      }
+    }
+
+    // a and b go negative, so Lab needs signed components
+    let rgb = ColorSpace::Rgb(image.as_other::<f32, 3, false>());
+    let lab = rgb.convert_to(ColorSpaceKind::Cielab);
+    for (a, b) in lab.to_rgb().components().iter().zip(image.components()) {
+      assert!((a - *b as f32).abs() < 1e-2, "{a} vs {b}");
+    }
+  }
+}
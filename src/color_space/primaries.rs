@@ -0,0 +1,221 @@
+//! RGB spaces described by their primaries, white point and transfer curve,
+//! and conversion between them.
+//!
+//! The same RGB numbers mean different colors in different spaces: a fully
+//! saturated Display P3 red lies outside sRGB. Treating wide-gamut pixels as
+//! sRGB desaturates them, so conversions go through CIE XYZ with the
+//! matrices each [`RgbSpace`] derives.
+
+use super::{
+  gamut::{map_pixel, GamutMapping},
+  transfer::TransferFunction,
+};
+use crate::{
+  image_buffer::ImageBuffer,
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+};
+
+type Matrix = [[f64; 3]; 3];
+
+/// Chromaticity of the D65 white point, used by all the predefined spaces
+pub const D65: [f64; 2] = [0.3127, 0.3290];
+
+/// An RGB color space: the chromaticities of its primaries and white point,
+/// and its transfer curve
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RgbSpace {
+  pub red:      [f64; 2],
+  pub green:    [f64; 2],
+  pub blue:     [f64; 2],
+  pub white:    [f64; 2],
+  pub transfer: TransferFunction,
+}
+
+impl Default for RgbSpace {
+  fn default() -> Self { RgbSpace::SRGB }
+}
+
+impl RgbSpace {
+  pub const ADOBE_RGB: RgbSpace = RgbSpace {
+    red:      [0.64, 0.33],
+    green:    [0.21, 0.71],
+    blue:     [0.15, 0.06],
+    white:    D65,
+    transfer: TransferFunction::Gamma(563.0 / 256.0),
+  };
+  /// The space of recent phone and laptop displays and of most photos taken
+  /// with phones
+  pub const DISPLAY_P3: RgbSpace = RgbSpace {
+    red:      [0.680, 0.320],
+    green:    [0.265, 0.690],
+    blue:     [0.150, 0.060],
+    white:    D65,
+    transfer: TransferFunction::Srgb,
+  };
+  /// sRGB primaries with linear values, as used for compositing
+  pub const LINEAR_SRGB: RgbSpace = RgbSpace {
+    transfer: TransferFunction::Linear,
+    ..RgbSpace::SRGB
+  };
+  pub const REC_2020: RgbSpace = RgbSpace {
+    red:      [0.708, 0.292],
+    green:    [0.170, 0.797],
+    blue:     [0.131, 0.046],
+    white:    D65,
+    transfer: TransferFunction::Rec709,
+  };
+  /// Rec.2020 primaries with the HLG curve of broadcast HDR
+  pub const REC_2100_HLG: RgbSpace = RgbSpace {
+    transfer: TransferFunction::Hlg,
+    ..RgbSpace::REC_2020
+  };
+  /// Rec.2020 primaries with the PQ curve of HDR10
+  pub const REC_2100_PQ: RgbSpace = RgbSpace {
+    transfer: TransferFunction::Pq,
+    ..RgbSpace::REC_2020
+  };
+  pub const SRGB: RgbSpace = RgbSpace {
+    red:      [0.64, 0.33],
+    green:    [0.30, 0.60],
+    blue:     [0.15, 0.06],
+    white:    D65,
+    transfer: TransferFunction::Srgb,
+  };
+
+  /// The matrix taking linear RGB in this space to CIE XYZ, with the white
+  /// point at a Y of 1
+  pub fn to_xyz(&self) -> Matrix {
+    let xyz = |[x, y]: [f64; 2]| [x / y, 1.0, (1.0 - x - y) / y];
+    let primaries = [xyz(self.red), xyz(self.green), xyz(self.blue)];
+    let columns = transpose(primaries);
+    let scale = multiply_vector(&invert(&columns), xyz(self.white));
+    let mut matrix = columns;
+    for row in matrix.iter_mut() {
+      for (v, s) in row.iter_mut().zip(scale) {
+        *v *= s;
+      }
+    }
+    matrix
+  }
+
+  /// The matrix taking linear RGB in this space to linear RGB in `to`,
+  /// adapting white points with the Bradford transform where they differ
+  pub fn matrix_to(&self, to: &RgbSpace) -> Matrix {
+    let mut xyz = self.to_xyz();
+    if self.white != to.white {
+      xyz = multiply(&bradford(self.white, to.white), &xyz);
+    }
+    multiply(&invert(&to.to_xyz()), &xyz)
+  }
+
+  /// Converts one color with encoded components between 0 and 1 to `to`,
+  /// mapping colors outside its gamut as `mapping` says
+  pub fn convert_pixel(
+    &self,
+    to: &RgbSpace,
+    matrix: &Matrix,
+    rgb: [f64; 3],
+    mapping: GamutMapping,
+  ) -> [f64; 3] {
+    let linear = multiply_vector(matrix, rgb.map(|v| self.transfer.decode(v)));
+    map_pixel(linear, mapping).map(|v| to.transfer.encode(v))
+  }
+}
+
+/// Converts the first three components of every pixel of `image` from
+/// `from` to `to`, leaving any others, such as alpha, unchanged. Images
+/// with fewer than three components are returned as they are.
+pub fn convert_buffer<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  from: &RgbSpace,
+  to: &RgbSpace,
+  mapping: GamutMapping,
+) -> ImageBuffer<T, N, A> {
+  if N < 3 {
+    return image.clone();
+  }
+  let white = T::WHITE.to_f64().unwrap_or(1.0);
+  let matrix = from.matrix_to(to);
+  image.map(&mut |pel| {
+    let mut result = *pel;
+    let rgb = [0, 1, 2].map(|c| pel[c].to_f64().unwrap_or_default() / white);
+    let converted = from.convert_pixel(to, &matrix, rgb, mapping);
+    for (out, v) in result.iter_mut().zip(converted) {
+      *out = component_from_f64(v * white);
+    }
+    result
+  })
+}
+
+fn transpose(m: Matrix) -> Matrix {
+  [0, 1, 2].map(|r| [0, 1, 2].map(|c| m[c][r]))
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+  [0, 1, 2].map(|r| [0, 1, 2].map(|c| (0..3).map(|k| a[r][k] * b[k][c]).sum()))
+}
+
+fn multiply_vector(m: &Matrix, v: [f64; 3]) -> [f64; 3] {
+  m.map(|row| row.iter().zip(v).map(|(a, b)| a * b).sum())
+}
+
+fn invert(m: &Matrix) -> Matrix {
+  let cofactor = |r: usize, c: usize| {
+    let (r1, r2) = ((r + 1) % 3, (r + 2) % 3);
+    let (c1, c2) = ((c + 1) % 3, (c + 2) % 3);
+    m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+  };
+  let det: f64 = (0..3).map(|c| m[0][c] * cofactor(0, c)).sum();
+  [0, 1, 2].map(|r| [0, 1, 2].map(|c| cofactor(c, r) / det))
+}
+
+/// Chromatic adaptation from one white point to another
+fn bradford(from: [f64; 2], to: [f64; 2]) -> Matrix {
+  const CONE: Matrix = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+  ];
+  let xyz = |[x, y]: [f64; 2]| [x / y, 1.0, (1.0 - x - y) / y];
+  let (source, target) = (
+    multiply_vector(&CONE, xyz(from)),
+    multiply_vector(&CONE, xyz(to)),
+  );
+  let mut scale = [[0.0; 3]; 3];
+  for i in 0..3 {
+    scale[i][i] = target[i] / source[i];
+  }
+  multiply(&invert(&CONE), &multiply(&scale, &CONE))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn p3_red_is_outside_srgb() {
+    let to_xyz = RgbSpace::SRGB.to_xyz();
+    assert!((to_xyz[1][0] - 0.2126).abs() < 1e-3);
+    assert!((to_xyz[0][0] - 0.4124).abs() < 1e-3);
+
+    let p3 = RgbSpace::DISPLAY_P3;
+    let matrix = p3.matrix_to(&RgbSpace::SRGB);
+    let red = p3.convert_pixel(
+      &RgbSpace::SRGB,
+      &matrix,
+      [1.0, 0.0, 0.0],
+      GamutMapping::Clip,
+    );
+    assert!(matrix[0][0] > 1.0 && matrix[1][0] < 0.0);
+    assert!((red[0] - 1.0).abs() < 1e-9 && red[1] == 0.0 && red[2] == 0.0);
+    // An sRGB color survives the trip through a wider space
+    let image = ImageBuffer::<u8, 4, true>::with_val(&[200, 120, 40, 7], 2, 2);
+    let wide = convert_buffer(&image, &RgbSpace::SRGB, &p3, GamutMapping::Clip);
+    assert_ne!(wide.get_pixel(0, 0), image.get_pixel(0, 0));
+    let back = convert_buffer(&wide, &p3, &RgbSpace::SRGB, GamutMapping::Clip);
+    for (a, b) in back.components().iter().zip(image.components()) {
+      assert!(a.abs_diff(*b) <= 1, "{a} vs {b}");
+    }
+    assert_eq!(back.get_pixel(0, 0)[3], 7);
+  }
+}
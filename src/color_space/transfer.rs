@@ -0,0 +1,246 @@
+//! Transfer functions relating encoded RGB values to linear light, including
+//! the PQ and HLG curves of HDR video, and HDR10 light level metadata.
+
+use super::{
+  gamut::{map_pixel, GamutMapping},
+  linear_to_srgb,
+  srgb_to_linear,
+  RgbSpace,
+};
+use crate::{image_buffer::ImageBuffer, pixel::component_from_f64};
+
+/// Luminance in nits that PQ encodes as 1
+pub const PQ_PEAK_NITS: f64 = 10_000.0;
+
+/// Nominal peak luminance in nits of an HLG display, which HLG scene light
+/// of 1 is shown at
+pub const HLG_PEAK_NITS: f64 = 1_000.0;
+
+/// Luminance in nits of SDR white within an HDR signal, per ITU-R BT.2408
+pub const SDR_WHITE_NITS: f64 = 203.0;
+
+const PQ_M1: f64 = 2610.0 / 16384.0;
+const PQ_M2: f64 = 2523.0 / 4096.0 * 128.0;
+const PQ_C1: f64 = 3424.0 / 4096.0;
+const PQ_C2: f64 = 2413.0 / 4096.0 * 32.0;
+const PQ_C3: f64 = 2392.0 / 4096.0 * 32.0;
+
+const HLG_A: f64 = 0.178_832_77;
+const HLG_B: f64 = 0.284_668_92;
+const HLG_C: f64 = 0.559_910_73;
+
+/// The curve relating encoded RGB values to linear light
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferFunction {
+  /// The piecewise sRGB curve, also used by Display P3
+  Srgb,
+  /// The BT.709 camera curve, also used by Rec.2020
+  Rec709,
+  /// A pure power law with this exponent
+  Gamma(f64),
+  /// Values are linear light already
+  Linear,
+  /// SMPTE ST 2084, the curve of HDR10. Linear 1 is [`PQ_PEAK_NITS`].
+  Pq,
+  /// Hybrid log-gamma of ITU-R BT.2100, decoding to scene light
+  Hlg,
+}
+
+impl TransferFunction {
+  /// Linear light for an encoded value between 0 and 1
+  pub fn decode(&self, v: f64) -> f64 {
+    match *self {
+      TransferFunction::Srgb => srgb_to_linear(v),
+      TransferFunction::Rec709 => {
+        let v = v.clamp(0.0, 1.0);
+        if v < 0.081 {
+          v / 4.5
+        } else {
+          ((v + 0.099) / 1.099).powf(1.0 / 0.45)
+        }
+      }
+      TransferFunction::Gamma(gamma) => v.clamp(0.0, 1.0).powf(gamma),
+      TransferFunction::Linear => v,
+      TransferFunction::Pq => pq_decode(v),
+      TransferFunction::Hlg => hlg_decode(v),
+    }
+  }
+
+  /// The encoded value for linear light between 0 and 1
+  pub fn encode(&self, v: f64) -> f64 {
+    match *self {
+      TransferFunction::Srgb => linear_to_srgb(v),
+      TransferFunction::Rec709 => {
+        let v = v.clamp(0.0, 1.0);
+        if v < 0.018 {
+          v * 4.5
+        } else {
+          1.099 * v.powf(0.45) - 0.099
+        }
+      }
+      TransferFunction::Gamma(gamma) => v.clamp(0.0, 1.0).powf(1.0 / gamma),
+      TransferFunction::Linear => v,
+      TransferFunction::Pq => pq_encode(v),
+      TransferFunction::Hlg => hlg_encode(v),
+    }
+  }
+
+  /// Luminance in nits of linear light 1, for the curves that define one
+  pub fn peak_nits(&self) -> Option<f64> {
+    match self {
+      TransferFunction::Pq => Some(PQ_PEAK_NITS),
+      TransferFunction::Hlg => Some(HLG_PEAK_NITS),
+      _ => None,
+    }
+  }
+}
+
+/// Decodes a PQ signal to linear light, where 1 is [`PQ_PEAK_NITS`]
+pub fn pq_decode(v: f64) -> f64 {
+  let p = v.clamp(0.0, 1.0).powf(1.0 / PQ_M2);
+  ((p - PQ_C1).max(0.0) / (PQ_C2 - PQ_C3 * p)).powf(1.0 / PQ_M1)
+}
+
+/// Encodes linear light, where 1 is [`PQ_PEAK_NITS`], as a PQ signal
+pub fn pq_encode(v: f64) -> f64 {
+  let y = v.clamp(0.0, 1.0).powf(PQ_M1);
+  ((PQ_C1 + PQ_C2 * y) / (1.0 + PQ_C3 * y)).powf(PQ_M2)
+}
+
+/// Decodes an HLG signal to scene light between 0 and 1
+pub fn hlg_decode(v: f64) -> f64 {
+  let v = v.clamp(0.0, 1.0);
+  if v <= 0.5 {
+    v * v / 3.0
+  } else {
+    (((v - HLG_C) / HLG_A).exp() + HLG_B) / 12.0
+  }
+}
+
+/// Encodes scene light between 0 and 1 as an HLG signal
+pub fn hlg_encode(v: f64) -> f64 {
+  let v = v.clamp(0.0, 1.0);
+  if v <= 1.0 / 12.0 {
+    (3.0 * v).sqrt()
+  } else {
+    HLG_A * (12.0 * v - HLG_B).ln() + HLG_C
+  }
+}
+
+/// Light levels of HDR10 content, in nits
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Hdr10Metadata {
+  /// Maximum content light level: the brightest component of any pixel
+  pub max_cll:  f64,
+  /// Maximum frame-average light level. For one image, the average over its
+  /// pixels of their brightest component.
+  pub max_fall: f64,
+}
+
+impl Hdr10Metadata {
+  /// Measures the light levels of an image encoded with `transfer`, which
+  /// must be [`Pq`](TransferFunction::Pq) or [`Hlg`](TransferFunction::Hlg)
+  /// for the result to be in nits
+  pub fn measure(
+    image: &ImageBuffer<f32, 3, false>,
+    transfer: TransferFunction,
+  ) -> Self {
+    let peak = transfer.peak_nits().unwrap_or(1.0);
+    let (mut max_cll, mut total) = (0.0f64, 0.0);
+    for pel in image.iter() {
+      let level = pel
+        .iter()
+        .map(|&v| transfer.decode(f64::from(v)) * peak)
+        .fold(0.0, f64::max);
+      max_cll = max_cll.max(level);
+      total += level;
+    }
+    let count = (image.width * image.height).max(1) as f64;
+    Hdr10Metadata {
+      max_cll,
+      max_fall: total / count,
+    }
+  }
+
+  /// Combines the levels of consecutive frames into those of the sequence
+  pub fn merge(&self, other: &Hdr10Metadata) -> Self {
+    Hdr10Metadata {
+      max_cll:  self.max_cll.max(other.max_cll),
+      max_fall: self.max_fall.max(other.max_fall),
+    }
+  }
+}
+
+/// Tone maps an HDR frame in `space`, a PQ or HLG space such as
+/// [`RgbSpace::REC_2100_PQ`], to 8-bit sRGB.
+///
+/// Light at [`SDR_WHITE_NITS`] becomes sRGB white before compression, and
+/// highlights up to the `max_cll` of `metadata`, or the peak of the curve
+/// without it, roll off smoothly instead of clipping. The curve is applied
+/// to the brightest channel of each pixel so that hues stay put.
+pub fn hdr_to_sdr(
+  image: &ImageBuffer<f32, 3, false>,
+  space: &RgbSpace,
+  metadata: Option<&Hdr10Metadata>,
+) -> ImageBuffer<u8, 3, false> {
+  let peak = space.transfer.peak_nits().unwrap_or(SDR_WHITE_NITS);
+  let max_cll = metadata.map_or(peak, |m| m.max_cll).max(SDR_WHITE_NITS);
+  let white = max_cll / SDR_WHITE_NITS;
+  let matrix = space.matrix_to(&RgbSpace::LINEAR_SRGB);
+  image.map_into(&mut |pel| {
+    let linear = pel.map(|v| space.transfer.decode(f64::from(v)));
+    let mut rgb = [0, 1, 2].map(|r| {
+      let row = matrix[r];
+      (0..3).map(|c| row[c] * linear[c]).sum::<f64>() * peak / SDR_WHITE_NITS
+    });
+    let level = rgb.iter().fold(0.0f64, |m, &v| m.max(v));
+    if level > 0.0 {
+      // Extended Reinhard, reaching 1 at `white`
+      let mapped = level * (1.0 + level / (white * white)) / (1.0 + level);
+      rgb = rgb.map(|v| v * mapped / level);
+    }
+    map_pixel(rgb, GamutMapping::Compress)
+      .map(|v| component_from_f64(linear_to_srgb(v) * 255.0))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::pixel::PixelContainer;
+
+  #[test]
+  fn pq_and_hlg_round_trip() {
+    // 100 nits sits just above half the PQ signal range
+    assert!((pq_encode(100.0 / PQ_PEAK_NITS) - 0.508).abs() < 1e-3);
+    assert!((hlg_encode(1.0) - 1.0).abs() < 1e-6);
+    assert!((hlg_encode(1.0 / 12.0) - 0.5).abs() < 1e-9);
+    for v in [0.0, 0.01, 0.2, 0.5, 0.9, 1.0] {
+      assert!((pq_decode(pq_encode(v)) - v).abs() < 1e-9);
+      assert!((hlg_decode(hlg_encode(v)) - v).abs() < 1e-9);
+    }
+  }
+
+  #[test]
+  fn measure_and_tone_map() {
+    let pq = TransferFunction::Pq;
+    let signal = |nits: f64| pq.encode(nits / PQ_PEAK_NITS) as f32;
+    let image =
+      ImageBuffer::<f32, 3, false>::empty(2, 1).map_indexed(&mut |x, _, _| {
+        if x == 0 {
+          [signal(1000.0), signal(500.0), 0.0]
+        } else {
+          [signal(100.0); 3]
+        }
+      });
+    let metadata = Hdr10Metadata::measure(&image, pq);
+    assert!((metadata.max_cll - 1000.0).abs() < 0.5);
+    assert!((metadata.max_fall - 550.0).abs() < 0.5);
+
+    let sdr = hdr_to_sdr(&image, &RgbSpace::REC_2100_PQ, Some(&metadata));
+    let [r, g, b] = *sdr.get_pixel(0, 0);
+    assert!(r == 255 && g > b, "{r} {g} {b}");
+    let gray = sdr.get_pixel(1, 0);
+    assert!(gray[0] < 200 && gray[0] == gray[1] && gray[1] == gray[2]);
+  }
+}
@@ -0,0 +1,222 @@
+//! Conversion of camera frames, in the pixel formats V4L2 and UVC devices
+//! deliver, to RGB.
+//!
+//! Each conversion writes into a buffer the caller provides, whose size
+//! gives the frame size, so a capture loop can recycle a few buffers and
+//! never allocate per frame. Strides are the `bytesperline` that V4L2
+//! reports for the format, and may include padding.
+
+use crate::{
+  color_space::ycbcr_to_rgb,
+  error::{Error, Result},
+  pixel::PixelContainer,
+  ImageBuffer,
+};
+
+/// An 8-bit RGB frame
+pub type Rgb8 = ImageBuffer<u8, 3, false>;
+
+/// Camera pixel formats that can be converted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+  /// 4:2:2 Y'CbCr with two pixels packed as Y0, Cb, Y1, Cr
+  Yuyv,
+  /// 4:2:0 Y'CbCr with a luma plane followed by interleaved Cb and Cr at
+  /// half resolution
+  Nv12,
+  /// A JPEG per frame; needs the `jpeg` feature
+  Mjpeg,
+}
+
+impl PixelFormat {
+  /// The format with the given V4L2 four-character code, such as
+  /// `v4l2_fourcc('Y', 'U', 'Y', 'V')`
+  pub fn from_fourcc(fourcc: u32) -> Option<Self> {
+    match &fourcc.to_le_bytes() {
+      b"YUYV" | b"YUY2" => Some(PixelFormat::Yuyv),
+      b"NV12" => Some(PixelFormat::Nv12),
+      b"MJPG" => Some(PixelFormat::Mjpeg),
+      _ => None,
+    }
+  }
+}
+
+/// Converts a frame in `format` into `out`. `stride` is ignored for MJPEG.
+pub fn convert_into(
+  format: PixelFormat,
+  bytes: &[u8],
+  stride: usize,
+  out: &mut Rgb8,
+) -> Result<()> {
+  match format {
+    PixelFormat::Yuyv => yuyv_into(bytes, stride, out),
+    PixelFormat::Nv12 => nv12_into(bytes, stride, out),
+    #[cfg(feature = "jpeg")]
+    PixelFormat::Mjpeg => mjpeg_into(bytes, out),
+    #[cfg(not(feature = "jpeg"))]
+    PixelFormat::Mjpeg =>
+      Err(Error::Unsupported(
+        "MJPEG frames need the jpeg feature".to_string(),
+      )),
+  }
+}
+
+fn check_len(format: &str, bytes: &[u8], expected: usize) -> Result<()> {
+  if bytes.len() < expected {
+    return Err(Error::Decode(format!(
+      "{format} frame is {} bytes, expected {expected}",
+      bytes.len()
+    )));
+  }
+  Ok(())
+}
+
+/// Converts a YUYV frame the size of `out`. Fails if `stride` is too small
+/// for a row or `bytes` holds too few rows.
+pub fn yuyv_into(bytes: &[u8], stride: usize, out: &mut Rgb8) -> Result<()> {
+  let (width, height) = (out.width, out.height);
+  let row_len = width.div_ceil(2) * 4;
+  if stride < row_len {
+    return Err(Error::InvalidArgument(format!(
+      "A stride of {stride} bytes is too small for {width} YUYV pixels"
+    )));
+  }
+  check_len("YUYV", bytes, stride * height.saturating_sub(1) + row_len)?;
+  out.apply_indexed(&mut |x, y, _| {
+    let i = y * stride + x / 2 * 4;
+    let luma = bytes[i + x % 2 * 2];
+    ycbcr_to_rgb::<u8, u8>(&[luma, bytes[i + 1], bytes[i + 3]])
+  });
+  Ok(())
+}
+
+/// Converts an NV12 frame the size of `out`, whose luma and chroma rows are
+/// both `stride` bytes apart
+pub fn nv12_into(bytes: &[u8], stride: usize, out: &mut Rgb8) -> Result<()> {
+  let (width, height) = (out.width, out.height);
+  let row_len = width.div_ceil(2) * 2;
+  if stride < row_len {
+    return Err(Error::InvalidArgument(format!(
+      "A stride of {stride} bytes is too small for {width} NV12 pixels"
+    )));
+  }
+  let chroma_start = stride * height;
+  let chroma_rows = height.div_ceil(2);
+  check_len(
+    "NV12",
+    bytes,
+    chroma_start + stride * chroma_rows.saturating_sub(1) + row_len,
+  )?;
+  out.apply_indexed(&mut |x, y, _| {
+    let c = chroma_start + y / 2 * stride + x / 2 * 2;
+    ycbcr_to_rgb::<u8, u8>(&[bytes[y * stride + x], bytes[c], bytes[c + 1]])
+  });
+  Ok(())
+}
+
+/// An APP0 segment that marks a JPEG as an MJPEG frame
+#[cfg(feature = "jpeg")]
+const AVI1: [u8; 9] = [0xff, 0xe0, 0, 7, b'A', b'V', b'I', b'1', 0];
+
+/// Whether the JPEG in `bytes` defines Huffman tables before its scan
+#[cfg(feature = "jpeg")]
+fn has_huffman_tables(bytes: &[u8]) -> bool {
+  let mut pos = 2;
+  while let Some(&[0xff, marker, high, low]) = bytes.get(pos..pos + 4) {
+    match marker {
+      0xc4 => return true,
+      0xda => return false,
+      _ => pos += 2 + usize::from(u16::from_be_bytes([high, low])),
+    }
+  }
+  false
+}
+
+/// Decodes an MJPEG frame into `out`, which must have the frame's size.
+///
+/// Many UVC cameras leave the Huffman tables out of their frames and rely on
+/// the standard ones from the JPEG specification; frames without tables are
+/// marked so that the decoder supplies them. Decoding allocates working
+/// memory inside the JPEG decoder.
+#[cfg(feature = "jpeg")]
+pub fn mjpeg_into(bytes: &[u8], out: &mut Rgb8) -> Result<()> {
+  use std::borrow::Cow;
+
+  use crate::io::{decode_with_format, ImageFormat};
+
+  if !bytes.starts_with(&[0xff, 0xd8]) {
+    return Err(Error::Decode("MJPEG frame is not a JPEG".to_string()));
+  }
+  let frame = if has_huffman_tables(bytes) {
+    Cow::Borrowed(bytes)
+  } else {
+    Cow::Owned([&bytes[..2], &AVI1, &bytes[2..]].concat())
+  };
+  let image = decode_with_format(&frame, ImageFormat::Jpeg)?;
+  if (image.width(), image.height()) != (out.width, out.height) {
+    return Err(Error::DimensionMismatch {
+      expected: (out.width, out.height),
+      actual:   (image.width(), image.height()),
+    });
+  }
+  let rgba = image.to_rgba_f32()?;
+  for (pel, rgba) in out.iter_pixels_mut().zip(rgba.iter_pixels()) {
+    *pel = [0, 1, 2].map(|c| (rgba[c] * 255.0).round() as u8);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn yuyv_and_nv12_decode_to_rgb() {
+    let mut out = Rgb8::empty(2, 2);
+    // Black and white, then two red pixels; a padding byte per row
+    let yuyv = [16, 128, 235, 128, 0, 81, 90, 81, 240, 0];
+    yuyv_into(&yuyv, 5, &mut out).unwrap();
+    assert_eq!(out.get_pixel(0, 0), &[0, 0, 0]);
+    assert_eq!(out.get_pixel(1, 0), &[255, 255, 255]);
+    assert_eq!(out.get_pixel(1, 1), &[254, 0, 0]);
+    assert!(yuyv_into(&yuyv[..8], 5, &mut out).is_err());
+
+    let nv12 = [81, 81, 81, 81, 90, 240];
+    nv12_into(&nv12, 2, &mut out).unwrap();
+    assert!(out.iter_pixels().all(|p| p == &[254, 0, 0]));
+    assert_eq!(
+      PixelFormat::from_fourcc(u32::from_le_bytes(*b"NV12")),
+      Some(PixelFormat::Nv12)
+    );
+  }
+
+  #[cfg(feature = "jpeg")]
+  #[test]
+  fn mjpeg_without_huffman_tables() {
+    use crate::{color_space::ColorSpace, io, Image};
+
+    let source = Rgb8::with_val(&[200, 40, 40], 16, 8);
+    let image = Image::new_u8(ColorSpace::Rgb(source));
+    let jpeg = io::encode(&image, io::ImageFormat::Jpeg).unwrap();
+    // Drop the DHT segments, as many cameras do
+    let mut stripped = jpeg[..2].to_vec();
+    let mut pos = 2;
+    while jpeg[pos + 1] != 0xda {
+      let end = pos
+        + 2
+        + usize::from(u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]));
+      if jpeg[pos + 1] != 0xc4 {
+        stripped.extend_from_slice(&jpeg[pos..end]);
+      }
+      pos = end;
+    }
+    stripped.extend_from_slice(&jpeg[pos..]);
+    assert!(!has_huffman_tables(&stripped));
+
+    let mut out = Rgb8::empty(16, 8);
+    convert_into(PixelFormat::Mjpeg, &stripped, 0, &mut out).unwrap();
+    let pel = out.get_pixel(4, 4);
+    assert!(pel[0].abs_diff(200) < 8 && pel[1].abs_diff(40) < 8);
+    assert!(mjpeg_into(&jpeg, &mut Rgb8::empty(4, 4)).is_err());
+  }
+}
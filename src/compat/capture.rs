@@ -0,0 +1,236 @@
+//! Conversion of screen captures from the layouts that operating systems
+//! return them in, one call per layout. Each platform is behind its own
+//! feature: `capture-windows`, `capture-macos` and `capture-x11`. Only the
+//! bytes are converted; no platform APIs are called, so the functions can
+//! be used on any target.
+//!
+//! Rows may be padded: each function takes the distance between the starts
+//! of consecutive rows in bytes, as the platform reports it.
+
+use crate::{
+  error::{Error, Result},
+  pixel::PixelContainer,
+  ImageBuffer,
+};
+
+/// Checks that `bytes` holds `height` rows of `row_len` bytes, `stride`
+/// bytes apart
+fn check_layout(
+  format: &str,
+  bytes: &[u8],
+  height: usize,
+  row_len: usize,
+  stride: usize,
+) -> Result<()> {
+  if stride < row_len {
+    return Err(Error::InvalidArgument(format!(
+      "{format} rows of {row_len} bytes do not fit a stride of {stride}"
+    )));
+  }
+  let expected = match height {
+    0 => 0,
+    _ =>
+      (height - 1)
+        .checked_mul(stride)
+        .and_then(|n| n.checked_add(row_len))
+        .unwrap_or(usize::MAX),
+  };
+  if bytes.len() < expected {
+    return Err(Error::Decode(format!(
+      "{format} data is {} bytes, expected {expected}",
+      bytes.len()
+    )));
+  }
+  Ok(())
+}
+
+/// Converts the pixels of a 32-bit device-independent bitmap, as returned by
+/// `GetDIBits` or a DXGI desktop duplication, to RGBA. `height` follows
+/// `BITMAPINFOHEADER`: positive for bitmaps stored bottom-up, negative for
+/// top-down. The alpha byte of GDI captures is undefined, so every pixel is
+/// made opaque.
+#[cfg(feature = "capture-windows")]
+pub fn from_dib(
+  bytes: &[u8],
+  width: usize,
+  height: i32,
+  stride: usize,
+) -> Result<ImageBuffer<u8, 4, true>> {
+  let rows = height.unsigned_abs() as usize;
+  check_layout("DIB", bytes, rows, width * 4, stride)?;
+  let mut image = ImageBuffer::try_empty(width, rows, &Limits::none())?;
+  image.apply_indexed(&mut |x, y, _| {
+    let row = if height > 0 { rows - 1 - y } else { y };
+    let i = row * stride + x * 4;
+    [bytes[i + 2], bytes[i + 1], bytes[i], 255]
+  });
+  Ok(image)
+}
+
+/// Byte layout of a 32-bit `CGImage`, from its bitmap info
+#[cfg(feature = "capture-macos")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CgLayout {
+  /// RGBA with premultiplied alpha, `kCGImageAlphaPremultipliedLast` in
+  /// big-endian byte order
+  RgbaPremultiplied,
+  /// BGRA with premultiplied alpha, `kCGImageAlphaPremultipliedFirst` with
+  /// `kCGBitmapByteOrder32Little`, which is what `CGDisplayCreateImage` and
+  /// ScreenCaptureKit produce
+  BgraPremultiplied,
+  /// RGB followed by an unused byte, `kCGImageAlphaNoneSkipLast`
+  Rgbx,
+}
+
+/// Converts the data of a 32-bit `CGImage`, as copied out of its data
+/// provider, to RGBA with straight alpha. `bytes_per_row` is
+/// `CGImageGetBytesPerRow`.
+#[cfg(feature = "capture-macos")]
+pub fn from_cgimage(
+  bytes: &[u8],
+  width: usize,
+  height: usize,
+  bytes_per_row: usize,
+  layout: CgLayout,
+) -> Result<ImageBuffer<u8, 4, true>> {
+  check_layout("CGImage", bytes, height, width * 4, bytes_per_row)?;
+  let mut image = ImageBuffer::try_empty(width, height, &Limits::none())?;
+  image.apply_indexed(&mut |x, y, _| {
+    let i = y * bytes_per_row + x * 4;
+    let p = &bytes[i..i + 4];
+    let (rgb, alpha) = match layout {
+      CgLayout::RgbaPremultiplied => ([p[0], p[1], p[2]], p[3]),
+      CgLayout::BgraPremultiplied => ([p[2], p[1], p[0]], p[3]),
+      CgLayout::Rgbx => return [p[0], p[1], p[2], 255],
+    };
+    let straight = |c: u8| {
+      match alpha {
+        0 => 0,
+        _ =>
+          ((u32::from(c) * 255 + u32::from(alpha) / 2) / u32::from(alpha))
+            .min(255) as u8,
+      }
+    };
+    [straight(rgb[0]), straight(rgb[1]), straight(rgb[2]), alpha]
+  });
+  Ok(image)
+}
+
+/// Layout of a ZPixmap `XImage`, from its fields of the same names
+#[cfg(feature = "capture-x11")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ZPixmapFormat {
+  /// 16, 24 or 32
+  pub bits_per_pixel: u32,
+  pub red_mask:       u32,
+  pub green_mask:     u32,
+  pub blue_mask:      u32,
+  /// Whether `byte_order` is `LSBFirst`, as on x86 servers
+  pub lsb_first:      bool,
+}
+
+#[cfg(feature = "capture-x11")]
+impl Default for ZPixmapFormat {
+  /// The 24-bit TrueColor visual of most servers, stored in 32 bits
+  fn default() -> Self {
+    ZPixmapFormat {
+      bits_per_pixel: 32,
+      red_mask:       0x00ff_0000,
+      green_mask:     0x0000_ff00,
+      blue_mask:      0x0000_00ff,
+      lsb_first:      true,
+    }
+  }
+}
+
+/// Converts the data of a ZPixmap `XImage`, as returned by `XGetImage` or
+/// `xcb_get_image`, to RGB. Channels narrower than 8 bits, as in 16-bit
+/// visuals, are scaled up to fill the range.
+#[cfg(feature = "capture-x11")]
+pub fn from_zpixmap(
+  bytes: &[u8],
+  width: usize,
+  height: usize,
+  bytes_per_line: usize,
+  format: ZPixmapFormat,
+) -> Result<ImageBuffer<u8, 3, false>> {
+  let pixel_len = match format.bits_per_pixel {
+    16 | 24 | 32 => format.bits_per_pixel as usize / 8,
+    bits =>
+      return Err(Error::Unsupported(format!(
+        "ZPixmaps with {bits} bits per pixel are not supported"
+      ))),
+  };
+  check_layout("ZPixmap", bytes, height, width * pixel_len, bytes_per_line)?;
+  let channel = |value: u32, mask: u32| {
+    if mask == 0 {
+      return 0;
+    }
+    let max = mask >> mask.trailing_zeros();
+    ((value & mask) >> mask.trailing_zeros()) * 255 / max
+  };
+  let mut image = ImageBuffer::try_empty(width, height, &Limits::none())?;
+  image.apply_indexed(&mut |x, y, _| {
+    let i = y * bytes_per_line + x * pixel_len;
+    let p = &bytes[i..i + pixel_len];
+    let value = if format.lsb_first {
+      p.iter().rev().fold(0u32, |v, &b| v << 8 | u32::from(b))
+    } else {
+      p.iter().fold(0u32, |v, &b| v << 8 | u32::from(b))
+    };
+    [
+      channel(value, format.red_mask) as u8,
+      channel(value, format.green_mask) as u8,
+      channel(value, format.blue_mask) as u8,
+    ]
+  });
+  Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[cfg(feature = "capture-windows")]
+  #[test]
+  fn dib_rows_are_flipped() {
+    // Two rows of one pixel, padded to 8 bytes, bottom row first
+    let bytes = [1, 2, 3, 0, 9, 9, 9, 9, 4, 5, 6, 0];
+    let image = from_dib(&bytes, 1, 2, 8).unwrap();
+    assert_eq!(image.get_pixel(0, 0), &[6, 5, 4, 255]);
+    assert_eq!(image.get_pixel(0, 1), &[3, 2, 1, 255]);
+    let top_down = from_dib(&bytes, 1, -2, 8).unwrap();
+    assert_eq!(top_down.get_pixel(0, 0), &[3, 2, 1, 255]);
+    assert!(from_dib(&bytes, 1, 3, 8).is_err());
+  }
+
+  #[cfg(feature = "capture-macos")]
+  #[test]
+  fn cgimage_is_unpremultiplied() {
+    let bytes = [0, 64, 100, 128, 10, 20, 30, 99];
+    let image =
+      from_cgimage(&bytes, 2, 1, 8, CgLayout::BgraPremultiplied).unwrap();
+    assert_eq!(image.get_pixel(0, 0), &[199, 128, 0, 128]);
+    let rgbx = from_cgimage(&bytes, 2, 1, 8, CgLayout::Rgbx).unwrap();
+    assert_eq!(rgbx.get_pixel(1, 0), &[10, 20, 30, 255]);
+  }
+
+  #[cfg(feature = "capture-x11")]
+  #[test]
+  fn zpixmap_masks_are_applied() {
+    let bytes = [0x30, 0x20, 0x10, 0xff];
+    let image = from_zpixmap(&bytes, 1, 1, 4, ZPixmapFormat::default());
+    assert_eq!(image.unwrap().get_pixel(0, 0), &[0x10, 0x20, 0x30]);
+
+    // Pure red in RGB565, big-endian
+    let rgb565 = ZPixmapFormat {
+      bits_per_pixel: 16,
+      red_mask:       0xf800,
+      green_mask:     0x07e0,
+      blue_mask:      0x001f,
+      lsb_first:      false,
+    };
+    let image = from_zpixmap(&[0xf8, 0x00], 1, 1, 2, rgb565).unwrap();
+    assert_eq!(image.get_pixel(0, 0), &[255, 0, 0]);
+  }
+}
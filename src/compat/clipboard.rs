@@ -0,0 +1,86 @@
+//! Copying images to and from the system clipboard, with the `clipboard`
+//! feature.
+//!
+//! The clipboard is accessed through `arboard`, which offers images in the
+//! formats other applications on each platform look for: DIB on Windows,
+//! TIFF on macOS and PNG on X11 and Wayland. Images pass through the
+//! clipboard as 8-bit RGBA.
+
+use std::borrow::Cow;
+
+use arboard::{Clipboard, ImageData};
+
+use crate::{
+  color_space::ColorSpace,
+  error::{Error, Result},
+  Image,
+  ImageBuffer,
+  PixelContainer,
+};
+
+fn clipboard_error(e: arboard::Error) -> Error {
+  match e {
+    arboard::Error::ContentNotAvailable =>
+      Error::Decode("The clipboard holds no image".to_string()),
+    arboard::Error::ClipboardNotSupported =>
+      Error::Unsupported("No clipboard is available".to_string()),
+    e => Error::Io(std::io::Error::other(e)),
+  }
+}
+
+fn to_image_data(image: &Image) -> Result<ImageData<'static>> {
+  let rgba = image.to_rgba_f32()?.as_other_scaled::<u8>();
+  Ok(ImageData {
+    width:  rgba.width,
+    height: rgba.height,
+    bytes:  Cow::Owned(rgba.components().to_vec()),
+  })
+}
+
+fn from_image_data(data: ImageData) -> Result<Image> {
+  let buffer = ImageBuffer::try_with_data(
+    data.bytes.into_owned(),
+    data.width,
+    data.height,
+  )
+  .map_err(|e| Error::Decode(e.to_string()))?;
+  Ok(Image::new_u8(ColorSpace::Rgba(buffer)))
+}
+
+impl Image {
+  /// Reads the image on the clipboard. Fails if the clipboard holds no
+  /// image or cannot be opened.
+  pub fn from_clipboard() -> Result<Image> {
+    let mut clipboard = Clipboard::new().map_err(clipboard_error)?;
+    from_image_data(clipboard.get_image().map_err(clipboard_error)?)
+  }
+
+  /// Puts the image on the clipboard, replacing its contents. On X11 and
+  /// Wayland the clipboard is served by this process, so the image stays
+  /// available only while the process runs. Fails for HSV and CIELAB
+  /// images, and if the clipboard cannot be opened.
+  pub fn to_clipboard(&self) -> Result<()> {
+    let mut clipboard = Clipboard::new().map_err(clipboard_error)?;
+    clipboard
+      .set_image(to_image_data(self)?)
+      .map_err(clipboard_error)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn images_convert_to_clipboard_data_and_back() {
+    let rgb = ImageBuffer::<u16, 3, false>::with_val(&[65535, 0, 32896], 2, 1);
+    let data = to_image_data(&Image::new_u16(ColorSpace::Rgb(rgb))).unwrap();
+    assert_eq!((data.width, data.height), (2, 1));
+    assert_eq!(&data.bytes[..4], &[255, 0, 128, 255]);
+
+    let image = from_image_data(data).unwrap();
+    assert_eq!(image.color_space_name(), "RGBA");
+    let rgba = image.to_rgba_f32().unwrap();
+    assert_eq!(rgba.get_pixel(1, 0)[3], 1.0);
+  }
+}
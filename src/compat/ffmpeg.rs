@@ -0,0 +1,284 @@
+//! Reading frames from video files and writing image sequences to video,
+//! with the `ffmpeg` feature.
+//!
+//! The work is done by the `ffmpeg` and `ffprobe` programs, which must be
+//! on the `PATH`; frames pass through pipes as raw 8-bit RGBA, so any
+//! container and codec they support can be used. Video is read at its
+//! average frame rate, duplicating or dropping frames of variable-rate
+//! files as needed, so that timestamps follow from the frame number.
+
+use std::{
+  borrow::Borrow,
+  ffi::OsStr,
+  io::{Read, Write},
+  path::Path,
+  process::{Child, ChildStdout, Command, Stdio},
+  time::Duration,
+};
+
+use crate::{
+  color_space::ColorSpace,
+  error::{Error, Result},
+  limits::Limits,
+  video::{check_dimensions, Frame},
+  Image,
+  ImageBuffer,
+  PixelContainer,
+};
+
+/// Size and rate of the first video stream of a file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VideoInfo {
+  pub width:      usize,
+  pub height:     usize,
+  /// Frames per second
+  pub frame_rate: f64,
+}
+
+fn spawn_error(program: &str, e: std::io::Error) -> Error {
+  if e.kind() == std::io::ErrorKind::NotFound {
+    Error::Unsupported(format!("{program} is not installed"))
+  } else {
+    Error::Io(e)
+  }
+}
+
+/// Parses a rate such as `30000/1001` or `25`
+fn parse_rate(rate: &str) -> Option<f64> {
+  let rate = match rate.split_once('/') {
+    Some((num, den)) =>
+      num.trim().parse::<f64>().ok()? / den.trim().parse::<f64>().ok()?,
+    None => rate.trim().parse().ok()?,
+  };
+  (rate.is_finite() && rate > 0.0).then_some(rate)
+}
+
+/// Parses `ffprobe`'s `width,height,avg_frame_rate` line
+fn parse_probe(output: &str) -> Result<VideoInfo> {
+  let invalid =
+    || Error::Decode(format!("Unexpected ffprobe output: {}", output.trim()));
+  let mut fields = output.lines().next().ok_or_else(invalid)?.split(',');
+  let mut next = || fields.next().ok_or_else(invalid);
+  let width = next()?.trim().parse().map_err(|_| invalid())?;
+  let height = next()?.trim().parse().map_err(|_| invalid())?;
+  let frame_rate = parse_rate(next()?).ok_or_else(invalid)?;
+  Ok(VideoInfo {
+    width,
+    height,
+    frame_rate,
+  })
+}
+
+/// Reads the size and frame rate of the first video stream in `path`
+pub fn probe(path: impl AsRef<Path>) -> Result<VideoInfo> {
+  let output = Command::new("ffprobe")
+    .args(["-v", "error", "-select_streams", "v:0", "-show_entries"])
+    .args(["stream=width,height,avg_frame_rate", "-of", "csv=p=0"])
+    .arg(path.as_ref())
+    .output()
+    .map_err(|e| spawn_error("ffprobe", e))?;
+  if !output.status.success() {
+    return Err(Error::Decode(
+      String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    ));
+  }
+  parse_probe(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Frames decoded from a video file, returned by [`decode_video`]
+pub struct VideoFrames {
+  child:      Child,
+  stdout:     ChildStdout,
+  info:       VideoInfo,
+  /// Bytes in each RGBA frame
+  frame_len:  usize,
+  max_frames: Option<usize>,
+  index:      usize,
+}
+
+impl VideoFrames {
+  pub fn info(&self) -> VideoInfo { self.info }
+}
+
+impl Iterator for VideoFrames {
+  type Item = Result<Frame<Image>>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let mut data = vec![0; self.frame_len];
+    let mut filled = 0;
+    while filled < data.len() {
+      match self.stdout.read(&mut data[filled..]) {
+        Ok(0) => break,
+        Ok(n) => filled += n,
+        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+        Err(e) => return Some(Err(e.into())),
+      }
+    }
+    if filled < data.len() {
+      // A partial frame only arrives if ffmpeg stopped in the middle of one
+      return (filled > 0)
+        .then(|| Err(Error::Decode("Video ends mid-frame".to_string())));
+    }
+    if self.max_frames.is_some_and(|max| self.index >= max) {
+      return Some(Err(Error::LimitExceeded(format!(
+        "Video has more than the limit of {} frames",
+        self.index
+      ))));
+    }
+    let timestamp =
+      Duration::from_secs_f64(self.index as f64 / self.info.frame_rate);
+    self.index += 1;
+    let image =
+      ImageBuffer::try_with_data(data, self.info.width, self.info.height)
+        .map(|buffer| Image::new_u8(ColorSpace::Rgba(buffer)));
+    Some(image.map(|image| {
+      Frame {
+        image,
+        timestamp,
+      }
+    }))
+  }
+}
+
+impl Drop for VideoFrames {
+  fn drop(&mut self) {
+    // Stop decoding frames nobody will read
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
+
+/// Bytes in each RGBA frame of a video with `info`, failing if frames would
+/// be over `limits`
+fn frame_len(info: &VideoInfo, limits: &Limits) -> Result<usize> {
+  limits.check_image(info.width, info.height, 4)
+}
+
+/// Starts decoding the first video stream of `path` into RGBA frames.
+///
+/// The frame size reported by `ffprobe` is checked against `limits` before
+/// `ffmpeg` starts, and the frames stop with [`Error::LimitExceeded`] after
+/// `limits.max_frames`.
+pub fn decode_video(
+  path: impl AsRef<Path>,
+  limits: &Limits,
+) -> Result<VideoFrames> {
+  let path = path.as_ref();
+  let info = probe(path)?;
+  let frame_len = frame_len(&info, limits)?;
+  let mut child = Command::new("ffmpeg")
+    .args(["-v", "error", "-i"])
+    .arg(path)
+    .args(["-map", "0:v:0", "-fps_mode", "cfr", "-f", "rawvideo"])
+    .args(["-pix_fmt", "rgba", "-"])
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::null())
+    .spawn()
+    .map_err(|e| spawn_error("ffmpeg", e))?;
+  let stdout = child.stdout.take().ok_or_else(|| {
+    Error::Io(std::io::Error::other("ffmpeg output is not available"))
+  })?;
+  Ok(VideoFrames {
+    child,
+    stdout,
+    info,
+    frame_len,
+    max_frames: limits.max_frames,
+    index: 0,
+  })
+}
+
+/// Encodes `images` as a video at `frame_rate` frames per second, in the
+/// format `ffmpeg` picks for the extension of `path`, which is overwritten.
+/// `codec_args` are passed to `ffmpeg` before the output path, for example
+/// `["-c:v", "libx264", "-crf", "18"]`. Fails unless all images have the
+/// same size, or if `ffmpeg` fails.
+pub fn encode_video<I, S>(
+  images: impl IntoIterator<Item = I>,
+  frame_rate: f64,
+  path: impl AsRef<Path>,
+  codec_args: impl IntoIterator<Item = S>,
+) -> Result<()>
+where
+  I: Borrow<Image>,
+  S: AsRef<OsStr>,
+{
+  let mut images = images.into_iter().peekable();
+  let Some(first) = images.peek() else {
+    return Err(Error::InvalidArgument("No frames to encode".to_string()));
+  };
+  let size = (first.borrow().width(), first.borrow().height());
+  let mut child = Command::new("ffmpeg")
+    .args(["-v", "error", "-y", "-f", "rawvideo", "-pix_fmt", "rgba"])
+    .arg("-s")
+    .arg(format!("{}x{}", size.0, size.1))
+    .arg("-r")
+    .arg(frame_rate.to_string())
+    .args(["-i", "-", "-pix_fmt", "yuv420p"])
+    .args(codec_args)
+    .arg(path.as_ref())
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|e| spawn_error("ffmpeg", e))?;
+  let written = child
+    .stdin
+    .take()
+    .ok_or_else(|| {
+      Error::Io(std::io::Error::other("ffmpeg input is not available"))
+    })
+    .and_then(|stdin| write_frames(stdin, images, size));
+  let output = child.wait_with_output()?;
+  written?;
+  if !output.status.success() {
+    return Err(Error::Io(std::io::Error::other(
+      String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    )));
+  }
+  Ok(())
+}
+
+/// Writes each image as raw RGBA, closing `stdin` at the end so that
+/// ffmpeg finishes the file
+fn write_frames<I: Borrow<Image>>(
+  mut stdin: impl Write,
+  images: impl Iterator<Item = I>,
+  size: (usize, usize),
+) -> Result<()> {
+  for image in images {
+    let rgba = image.borrow().to_rgba_f32()?.as_other_scaled::<u8>();
+    check_dimensions(size, &rgba)?;
+    stdin.write_all(rgba.components())?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_probe_output() {
+    let info = parse_probe("1920,1080,30000/1001\n").unwrap();
+    assert_eq!((info.width, info.height), (1920, 1080));
+    assert!((info.frame_rate - 29.97).abs() < 0.01);
+    assert_eq!(parse_rate("25"), Some(25.0));
+    assert_eq!(parse_rate("0/0"), None);
+    assert!(parse_probe("").is_err());
+    assert!(parse_probe("640,x,25").is_err());
+
+    assert_eq!(
+      frame_len(&info, &Limits::default()).unwrap(),
+      1920 * 1080 * 4
+    );
+    let huge = parse_probe(&format!("{},{},25", usize::MAX / 2, 3)).unwrap();
+    assert!(matches!(
+      frame_len(&huge, &Limits::none()),
+      Err(Error::LimitExceeded(_))
+    ));
+    let tall = parse_probe("20000,20000,25").unwrap();
+    assert!(frame_len(&tall, &Limits::default()).is_err());
+  }
+}
@@ -0,0 +1,73 @@
+//! Conversion of 8-bit RGBA buffers to the image types of GUI frameworks,
+//! for previewing results. Each framework is behind a feature of the same
+//! name: `egui`, `iced` and `slint`. All of them take straight, not
+//! premultiplied, alpha, as this crate stores it.
+
+use crate::ImageBuffer;
+#[cfg(any(feature = "egui", feature = "slint"))]
+use crate::PixelContainer;
+
+/// An 8-bit RGBA buffer, the layout every framework accepts
+pub type Rgba8 = ImageBuffer<u8, 4, true>;
+
+/// Copies `image` into an egui `ColorImage`, ready for
+/// `Context::load_texture`
+#[cfg(feature = "egui")]
+pub fn to_egui(image: &Rgba8) -> epaint::ColorImage {
+  epaint::ColorImage::from_rgba_unmultiplied(
+    [image.width, image.height],
+    image.components(),
+  )
+}
+
+/// Turns `image` into an iced image handle without copying its pixels
+#[cfg(feature = "iced")]
+pub fn into_iced(image: Rgba8) -> iced_core::image::Handle {
+  let (width, height) = (image.width as u32, image.height as u32);
+  iced_core::image::Handle::from_rgba(width, height, image.into_components())
+}
+
+/// Copies `image` into a Slint image, ready to assign to an `Image`
+/// element's `source`
+#[cfg(feature = "slint")]
+pub fn to_slint(image: &Rgba8) -> slint::Image {
+  let buffer = slint::SharedPixelBuffer::<slint::Rgba8Pixel>::clone_from_slice(
+    image.components(),
+    image.width as u32,
+    image.height as u32,
+  );
+  slint::Image::from_rgba8(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+  #[cfg(any(feature = "egui", feature = "iced"))]
+  use super::*;
+
+  #[cfg(feature = "egui")]
+  #[test]
+  fn egui_image_matches() {
+    let image = Rgba8::with_val(&[255, 0, 0, 255], 3, 2);
+    let color = to_egui(&image);
+    assert_eq!(color.size, [3, 2]);
+    assert_eq!(color.pixels[5], epaint::Color32::RED);
+  }
+
+  #[cfg(feature = "iced")]
+  #[test]
+  fn iced_handle_keeps_pixels() {
+    let image = Rgba8::with_val(&[1, 2, 3, 4], 2, 2);
+    match into_iced(image) {
+      iced_core::image::Handle::Rgba {
+        width,
+        height,
+        pixels,
+        ..
+      } => {
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(&pixels[..4], &[1, 2, 3, 4]);
+      }
+      _ => panic!("Expected an RGBA handle"),
+    }
+  }
+}
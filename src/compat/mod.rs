@@ -0,0 +1,15 @@
+//! Interoperability with pixel layouts used by other systems.
+
+#[cfg(any(
+  feature = "capture-windows",
+  feature = "capture-macos",
+  feature = "capture-x11"
+))]
+pub mod capture;
+pub mod camera;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+#[cfg(feature = "ffmpeg")]
+pub mod ffmpeg;
+#[cfg(any(feature = "egui", feature = "iced", feature = "slint"))]
+pub mod gui;
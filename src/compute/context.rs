@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, SeedableRng};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+
+use super::ExecutionPolicy;
+use crate::error::{Error, Result};
+
+/// Resources an operation may use: where it runs, which threads do the CPU
+/// work and how that work is split up.
+///
+/// One context can be built up front and handed to every call, so that
+/// resource use is decided in one place. The default runs CPU work on
+/// rayon's global pool and picks the GPU by [`ExecutionPolicy::Auto`].
+#[derive(Clone, Debug)]
+pub struct ExecutionContext {
+  /// Whether to run on the CPU or the GPU
+  pub policy:        ExecutionPolicy,
+  /// Pool for CPU work, or `None` for rayon's global pool
+  pub thread_pool:   Option<Arc<ThreadPool>>,
+  /// Whether CPU kernels may work on several values at once with SIMD
+  /// instructions, where they have such a path. The color matrix and
+  /// conversion kernels then compute in `f32` eight pixels at a time rather
+  /// than in `f64` one at a time, which can round differently.
+  pub simd:          bool,
+  /// Rows in each band of an image that CPU work is split into. Smaller
+  /// bands balance better across threads; larger ones have less overhead.
+  /// Chained neighborhood operations in [`lazy`](crate::lazy) run on square
+  /// tiles of this size, which should fit in the CPU's L2 cache.
+  pub tile_size:     usize,
+  /// Whether outputs must be bit-identical across runs, thread counts and
+  /// machines. Work stays on the CPU without SIMD, and [`rng`](Self::rng)
+  /// is seeded with [`seed`](Self::seed).
+  pub deterministic: bool,
+  /// Seed for [`rng`](Self::rng) in deterministic mode
+  pub seed:          u64,
+}
+
+impl Default for ExecutionContext {
+  fn default() -> Self {
+    ExecutionContext {
+      policy:        ExecutionPolicy::default(),
+      thread_pool:   None,
+      simd:          true,
+      tile_size:     64,
+      deterministic: false,
+      seed:          0,
+    }
+  }
+}
+
+impl ExecutionContext {
+  /// A context that does its CPU work on a new pool of `threads` threads
+  pub fn with_threads(threads: usize) -> Result<Self> {
+    let pool = ThreadPoolBuilder::new()
+      .num_threads(threads)
+      .build()
+      .map_err(|e| {
+        Error::InvalidArgument(format!("Cannot start {threads} threads: {e}"))
+      })?;
+    Ok(ExecutionContext {
+      thread_pool: Some(Arc::new(pool)),
+      ..Self::default()
+    })
+  }
+
+  /// A context that keeps all work on the calling thread and the CPU
+  pub fn single_threaded() -> Self {
+    ExecutionContext {
+      policy: ExecutionPolicy::Cpu,
+      tile_size: usize::MAX,
+      ..Self::default()
+    }
+  }
+
+  /// A context whose outputs are reproducible, seeding random numbers with
+  /// `seed`
+  pub fn deterministic(seed: u64) -> Self {
+    ExecutionContext {
+      deterministic: true,
+      seed,
+      ..Self::default()
+    }
+  }
+
+  /// Where operations actually run. Deterministic contexts resolve
+  /// [`ExecutionPolicy::Auto`] to the CPU, since GPU arithmetic varies
+  /// between devices, and reject [`ExecutionPolicy::Gpu`].
+  pub fn effective_policy(&self) -> Result<ExecutionPolicy> {
+    match (self.deterministic, self.policy) {
+      (false, policy) => Ok(policy),
+      (true, ExecutionPolicy::Gpu) =>
+        Err(Error::InvalidArgument(
+          "GPU execution is not deterministic".to_string(),
+        )),
+      (true, _) => Ok(ExecutionPolicy::Cpu),
+    }
+  }
+
+  /// Whether CPU kernels take their SIMD paths: when [`simd`](Self::simd)
+  /// allows it and the context is not deterministic, since rounding in
+  /// those paths depends on how the compiler vectorizes them
+  pub fn simd_enabled(&self) -> bool { self.simd && !self.deterministic }
+
+  /// A random number generator for operations that need one: seeded from
+  /// [`seed`](Self::seed) in deterministic mode, otherwise from the
+  /// operating system
+  pub fn rng(&self) -> StdRng {
+    if self.deterministic {
+      StdRng::seed_from_u64(self.seed)
+    } else {
+      StdRng::from_entropy()
+    }
+  }
+
+  /// Runs `op` in this context's thread pool
+  pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+    match &self.thread_pool {
+      Some(pool) => pool.install(op),
+      None => op(),
+    }
+  }
+
+  /// Calls `op` on bands of [`tile_size`](Self::tile_size) rows of `data`,
+  /// an image with `row_len` values per row, in parallel. `op` also gets
+  /// the index of the band's first row.
+  pub(crate) fn for_each_band<T: Send>(
+    &self,
+    data: &mut [T],
+    row_len: usize,
+    op: impl Fn(usize, &mut [T]) + Send + Sync,
+  ) {
+    if data.is_empty() || row_len == 0 {
+      return;
+    }
+    let rows = self.tile_size.clamp(1, data.len() / row_len);
+    if rows * row_len >= data.len() {
+      return op(0, data);
+    }
+    self.install(|| {
+      data
+        .par_chunks_mut(rows * row_len)
+        .enumerate()
+        .for_each(|(i, band)| op(i * rows, band))
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bands_cover_every_row_once() {
+    let context = ExecutionContext {
+      tile_size: 3,
+      ..ExecutionContext::with_threads(2).unwrap()
+    };
+    let mut rows = vec![0usize; 10 * 4];
+    context.for_each_band(&mut rows, 4, |first, band| {
+      for (i, v) in band.iter_mut().enumerate() {
+        *v += first + i / 4;
+      }
+    });
+    let expected: Vec<usize> = (0..40).map(|i| i / 4).collect();
+    assert_eq!(rows, expected);
+  }
+}
@@ -0,0 +1,280 @@
+//! wgpu implementations of the passes behind [`compute`](super).
+//!
+//! Images go to the GPU as interleaved `f32` components. Each call uploads
+//! its input, runs its passes in one submission and blocks until the result
+//! has been read back.
+
+use std::sync::{mpsc, OnceLock};
+
+use wgpu::util::DeviceExt;
+
+use crate::error::{Error, Result};
+
+pub(super) struct Gpu {
+  device:    wgpu::Device,
+  queue:     wgpu::Queue,
+  separable: wgpu::ComputePipeline,
+  pointwise: wgpu::ComputePipeline,
+}
+
+static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+
+/// Flattens per-position taps into the `ranges` and `taps` tables read by
+/// `separable.wgsl`
+fn tap_tables(taps: &[Vec<(usize, f64)>]) -> (Vec<[u32; 2]>, Vec<[u32; 2]>) {
+  let mut ranges = Vec::with_capacity(taps.len());
+  let mut flat = Vec::new();
+  for position in taps {
+    ranges.push([flat.len() as u32, position.len() as u32]);
+    flat.extend(
+      position
+        .iter()
+        .map(|&(i, w)| [i as u32, (w as f32).to_bits()]),
+    );
+  }
+  (ranges, flat)
+}
+
+impl Gpu {
+  /// The first adapter wgpu finds, set up on first use. `None` if there is
+  /// none or it cannot run the shaders.
+  pub(super) fn get() -> Option<&'static Gpu> {
+    GPU.get_or_init(|| pollster::block_on(Self::new())).as_ref()
+  }
+
+  async fn new() -> Option<Gpu> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+    let adapter = instance
+      .request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        ..Default::default()
+      })
+      .await?;
+    // Large images need the biggest storage buffers the adapter allows
+    let descriptor = wgpu::DeviceDescriptor {
+      label: Some("better-images"),
+      required_limits: adapter.limits(),
+      ..Default::default()
+    };
+    let (device, queue) =
+      adapter.request_device(&descriptor, None).await.ok()?;
+    let pipeline = |label, source: &str| {
+      let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label:  Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+      });
+      device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label:               Some(label),
+        layout:              None,
+        module:              &module,
+        entry_point:         Some("main"),
+        compilation_options: Default::default(),
+        cache:               None,
+      })
+    };
+    let separable = pipeline("separable", include_str!("separable.wgsl"));
+    let pointwise = pipeline("pointwise", include_str!("pointwise.wgsl"));
+    Some(Gpu {
+      device,
+      queue,
+      separable,
+      pointwise,
+    })
+  }
+
+  /// Fails with [`Error::Unsupported`] if a buffer of `components` `f32`s
+  /// or `workgroups` in one dimension exceed the device limits
+  fn check_fits(&self, components: usize, workgroups: usize) -> Result<()> {
+    let limits = self.device.limits();
+    let bytes = components as u64 * 4;
+    if bytes > u64::from(limits.max_storage_buffer_binding_size)
+      || bytes > limits.max_buffer_size
+      || workgroups > limits.max_compute_workgroups_per_dimension as usize
+    {
+      return Err(Error::Unsupported(
+        "The image is too large for the GPU".to_string(),
+      ));
+    }
+    Ok(())
+  }
+
+  fn storage(&self, contents: &[u8]) -> wgpu::Buffer {
+    self
+      .device
+      .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: None,
+        contents,
+        usage: wgpu::BufferUsages::STORAGE,
+      })
+  }
+
+  fn uniform(&self, contents: &[u8]) -> wgpu::Buffer {
+    self
+      .device
+      .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: None,
+        contents,
+        usage: wgpu::BufferUsages::UNIFORM,
+      })
+  }
+
+  fn output(&self, components: usize) -> wgpu::Buffer {
+    self.device.create_buffer(&wgpu::BufferDescriptor {
+      label:              None,
+      size:               components as u64 * 4,
+      usage:              wgpu::BufferUsages::STORAGE
+        | wgpu::BufferUsages::COPY_SRC,
+      mapped_at_creation: false,
+    })
+  }
+
+  fn dispatch(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    pipeline: &wgpu::ComputePipeline,
+    buffers: &[&wgpu::Buffer],
+    workgroups: (u32, u32),
+  ) {
+    let entries: Vec<wgpu::BindGroupEntry> = buffers
+      .iter()
+      .enumerate()
+      .map(|(i, buffer)| {
+        wgpu::BindGroupEntry {
+          binding:  i as u32,
+          resource: buffer.as_entire_binding(),
+        }
+      })
+      .collect();
+    let bind_group =
+      self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label:   None,
+        layout:  &pipeline.get_bind_group_layout(0),
+        entries: &entries,
+      });
+    let mut pass =
+      encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, &bind_group, &[]);
+    pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+  }
+
+  /// Submits `encoder`, whose passes leave `components` results in
+  /// `output`, and reads them back
+  fn finish(
+    &self,
+    mut encoder: wgpu::CommandEncoder,
+    output: &wgpu::Buffer,
+    components: usize,
+  ) -> Result<Vec<f32>> {
+    let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+      label:              None,
+      size:               components as u64 * 4,
+      usage:              wgpu::BufferUsages::MAP_READ
+        | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    encoder.copy_buffer_to_buffer(output, 0, &staging, 0, staging.size());
+    self.queue.submit([encoder.finish()]);
+
+    let slice = staging.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+      let _ = sender.send(result);
+    });
+    self.device.poll(wgpu::Maintain::Wait);
+    receiver
+      .recv()
+      .map_err(|e| Error::Unsupported(format!("GPU readback failed: {e}")))?
+      .map_err(|e| Error::Unsupported(format!("GPU readback failed: {e}")))?;
+    let result = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    staging.unmap();
+    Ok(result)
+  }
+
+  /// Filters `src`, `size` pixels of `channels` components, along rows with
+  /// `horizontal` and then along columns with `vertical`. The result is
+  /// `horizontal.len()` by `vertical.len()` pixels.
+  pub(super) fn separable(
+    &self,
+    src: &[f32],
+    size: (usize, usize),
+    channels: usize,
+    horizontal: &[Vec<(usize, f64)>],
+    vertical: &[Vec<(usize, f64)>],
+  ) -> Result<Vec<f32>> {
+    let (width, height) = (horizontal.len(), vertical.len());
+    let rows = width * size.1 * channels;
+    let components = width * height * channels;
+    for (w, h) in [(width, size.1), (width, height)] {
+      self.check_fits(w * h * channels, w.max(h).div_ceil(16))?;
+    }
+    self.check_fits(src.len(), 0)?;
+
+    let source = self.storage(bytemuck::cast_slice(src));
+    let between = self.output(rows);
+    let output = self.output(components);
+    let mut encoder = self.device.create_command_encoder(&Default::default());
+    let passes = [
+      (horizontal, size.0, (width, size.1), 0, &source, &between),
+      (vertical, width, (width, height), 1, &between, &output),
+    ];
+    for (taps, src_width, (w, h), vertical, from, to) in passes {
+      let (ranges, taps) = tap_tables(taps);
+      let params = [
+        src_width as u32,
+        w as u32,
+        h as u32,
+        channels as u32,
+        vertical,
+        0,
+        0,
+        0,
+      ];
+      self.dispatch(
+        &mut encoder,
+        &self.separable,
+        &[
+          &self.uniform(bytemuck::cast_slice(&params)),
+          &self.storage(bytemuck::cast_slice(&ranges)),
+          &self.storage(bytemuck::cast_slice(&taps)),
+          from,
+          to,
+        ],
+        (w.div_ceil(16) as u32, h.div_ceil(16) as u32),
+      );
+    }
+    self.finish(encoder, &output, components)
+  }
+
+  /// Multiplies each pixel of `src`, `channels` components each, by
+  /// `matrix`: `channels` rows of `channels + 1` values, the last being an
+  /// offset
+  pub(super) fn pointwise(
+    &self,
+    src: &[f32],
+    channels: usize,
+    matrix: &[f32],
+  ) -> Result<Vec<f32>> {
+    let count = src.len() / channels;
+    let groups = count.div_ceil(256);
+    let max = self.device.limits().max_compute_workgroups_per_dimension;
+    let workgroups = (groups.min(max as usize), groups.div_ceil(max as usize));
+    self.check_fits(src.len(), workgroups.1)?;
+
+    let output = self.output(src.len());
+    let params = [count as u32, channels as u32, 0, 0];
+    let mut encoder = self.device.create_command_encoder(&Default::default());
+    self.dispatch(
+      &mut encoder,
+      &self.pointwise,
+      &[
+        &self.uniform(bytemuck::cast_slice(&params)),
+        &self.storage(bytemuck::cast_slice(matrix)),
+        &self.storage(bytemuck::cast_slice(src)),
+        &output,
+      ],
+      (workgroups.0 as u32, workgroups.1 as u32),
+    );
+    self.finish(encoder, &output, src.len())
+  }
+}
@@ -0,0 +1,491 @@
+//! Operations that can run on the GPU.
+//!
+//! Each function takes an [`ExecutionContext`] saying where to run, and on
+//! how many threads and in what size bands CPU work is split. The GPU
+//! implementations are wgpu compute shaders, built with the `gpu-compute`
+//! feature. Pixels are uploaded as `f32`, so GPU results match the CPU ones
+//! up to `f32` rounding.
+
+use crate::{
+  error::{Error, Result},
+  image_buffer::ImageBuffer,
+  ops::{blur, transform, ResampleOptions},
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  progress::Progress,
+};
+
+mod context;
+#[cfg(feature = "gpu-compute")]
+mod gpu;
+
+pub use context::ExecutionContext;
+
+/// Images with at least this many pixels, counting the larger of input and
+/// output, go to the GPU under [`ExecutionPolicy::Auto`]. Below it the
+/// upload and download cost more than they save.
+pub const AUTO_GPU_PIXELS: usize = 1 << 21;
+
+/// Where an operation runs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ExecutionPolicy {
+  Cpu,
+  /// On the GPU, failing with [`Error::Unsupported`] if there is none or the
+  /// image does not fit in its buffers
+  Gpu,
+  /// On the GPU for images of at least [`AUTO_GPU_PIXELS`] pixels if one is
+  /// available, otherwise on the CPU
+  #[default]
+  Auto,
+}
+
+/// Whether a GPU can be used, setting it up on the first call
+#[cfg(feature = "gpu-compute")]
+pub fn gpu_available() -> bool { gpu::Gpu::get().is_some() }
+
+/// Whether a GPU can be used. Always false without the `gpu-compute`
+/// feature.
+#[cfg(not(feature = "gpu-compute"))]
+pub fn gpu_available() -> bool { false }
+
+/// Runs `op` if `policy` picks the GPU for an image of `pixels` pixels.
+/// `None` means the caller should run on the CPU.
+#[cfg(feature = "gpu-compute")]
+fn on_gpu<R>(
+  policy: ExecutionPolicy,
+  pixels: usize,
+  op: impl FnOnce(&gpu::Gpu) -> Result<R>,
+) -> Option<Result<R>> {
+  match policy {
+    ExecutionPolicy::Cpu => None,
+    ExecutionPolicy::Auto if pixels < AUTO_GPU_PIXELS => None,
+    ExecutionPolicy::Auto =>
+      gpu::Gpu::get()
+        .map(op)
+        .filter(|result| !matches!(result, Err(Error::Unsupported(_)))),
+    ExecutionPolicy::Gpu =>
+      Some(gpu::Gpu::get().map_or_else(
+        || Err(Error::Unsupported("No GPU is available".to_string())),
+        op,
+      )),
+  }
+}
+
+/// Fails if `policy` requires a GPU, which is never available without the
+/// `gpu-compute` feature
+#[cfg(not(feature = "gpu-compute"))]
+fn require_cpu(policy: ExecutionPolicy) -> Result<()> {
+  if policy == ExecutionPolicy::Gpu {
+    return Err(Error::Unsupported("GPU support is not enabled".to_string()));
+  }
+  Ok(())
+}
+
+#[cfg(feature = "gpu-compute")]
+fn to_f32<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+) -> Vec<f32> {
+  image
+    .components()
+    .iter()
+    .map(|v| v.to_f32().unwrap_or_default())
+    .collect()
+}
+
+#[cfg(feature = "gpu-compute")]
+fn from_f32<T: PixelComponent, const N: usize, const A: bool>(
+  data: Vec<f32>,
+  width: usize,
+  height: usize,
+) -> Result<ImageBuffer<T, N, A>> {
+  let data = data
+    .into_iter()
+    .map(|v| component_from_f64(f64::from(v)))
+    .collect();
+  ImageBuffer::try_with_data(data, width, height)
+}
+
+/// Resamples `image` as [`transform::resize`] does
+pub fn resize<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  width: usize,
+  height: usize,
+  context: &ExecutionContext,
+) -> Result<ImageBuffer<T, N, A>> {
+  let policy = context.effective_policy()?;
+  #[cfg(feature = "gpu-compute")]
+  if image.width > 0 && image.height > 0 && width > 0 && height > 0 {
+    let pixels = (image.width * image.height).max(width * height);
+    if let Some(result) = on_gpu(policy, pixels, |gpu| {
+      let data = gpu.separable(
+        &to_f32(image),
+        (image.width, image.height),
+        N,
+        &transform::weights(image.width, width),
+        &transform::weights(image.height, height),
+      )?;
+      from_f32(data, width, height)
+    }) {
+      return result;
+    }
+  }
+  #[cfg(not(feature = "gpu-compute"))]
+  require_cpu(policy)?;
+  transform::resize_in(
+    image,
+    width,
+    height,
+    &ResampleOptions::default(),
+    context,
+    &Progress::new(),
+  )
+}
+
+/// Taps of a Gaussian `kernel` centered on each of `len` positions,
+/// repeating the edge samples
+#[cfg(feature = "gpu-compute")]
+fn blur_taps(len: usize, kernel: &[f64]) -> Vec<Vec<(usize, f64)>> {
+  let radius = (kernel.len() / 2) as isize;
+  (0..len as isize)
+    .map(|i| {
+      kernel
+        .iter()
+        .enumerate()
+        .map(|(k, &w)| {
+          let j = (i + k as isize - radius).clamp(0, len as isize - 1);
+          (j as usize, w)
+        })
+        .collect()
+    })
+    .collect()
+}
+
+/// Blurs `image` as [`blur::gaussian_blur`] does
+pub fn gaussian_blur<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  sigma: f64,
+  context: &ExecutionContext,
+) -> Result<ImageBuffer<T, N, A>> {
+  let policy = context.effective_policy()?;
+  #[cfg(feature = "gpu-compute")]
+  if sigma > 0.0 && image.width > 0 && image.height > 0 {
+    let kernel = blur::kernel(sigma);
+    if let Some(result) = on_gpu(policy, image.width * image.height, |gpu| {
+      let data = gpu.separable(
+        &to_f32(image),
+        (image.width, image.height),
+        N,
+        &blur_taps(image.width, &kernel),
+        &blur_taps(image.height, &kernel),
+      )?;
+      from_f32(data, image.width, image.height)
+    }) {
+      return result;
+    }
+  }
+  #[cfg(not(feature = "gpu-compute"))]
+  require_cpu(policy)?;
+  Ok(blur::gaussian_blur_in(
+    image,
+    sigma,
+    &ResampleOptions::default(),
+    context,
+  ))
+}
+
+/// Applies `matrix`, `N` rows of `N + 1` values ending in an offset, to
+/// every pixel of `image`
+fn apply_matrix<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  matrix: &[f32],
+  context: &ExecutionContext,
+) -> Result<Vec<f64>> {
+  let policy = context.effective_policy()?;
+  #[cfg(feature = "gpu-compute")]
+  if image.width > 0 && image.height > 0 {
+    if let Some(result) = on_gpu(policy, image.width * image.height, |gpu| {
+      gpu.pointwise(&to_f32(image), N, matrix)
+    }) {
+      return Ok(result?.into_iter().map(f64::from).collect());
+    }
+  }
+  #[cfg(not(feature = "gpu-compute"))]
+  require_cpu(policy)?;
+  let mut result = vec![0.0; image.components().len()];
+  let row_len = image.width * N;
+  context.for_each_band(&mut result, row_len, |first, band| {
+    let source = &image.components()[first * row_len..];
+    if context.simd_enabled() {
+      matrix_lanes::<T, N>(band, source, matrix);
+    } else {
+      matrix_scalar::<T, N>(band, source, matrix);
+    }
+  });
+  Ok(result)
+}
+
+/// Pixels [`matrix_lanes`] works on at once, enough to fill the widest
+/// common vector registers with `f32`s
+const LANES: usize = 8;
+
+/// Applies `matrix` to each pixel of `source` in turn, in `f64`
+fn matrix_scalar<T: PixelComponent, const N: usize>(
+  out: &mut [f64],
+  source: &[T],
+  matrix: &[f32],
+) {
+  for (out, pel) in out.chunks_exact_mut(N).zip(source.chunks_exact(N)) {
+    for (v, row) in out.iter_mut().zip(matrix.chunks_exact(N + 1)) {
+      let dot: f64 = row
+        .iter()
+        .zip(pel)
+        .map(|(m, c)| f64::from(*m) * c.to_f64().unwrap_or_default())
+        .sum();
+      *v = dot + f64::from(row[N]);
+    }
+  }
+}
+
+/// Applies `matrix` to [`LANES`] pixels at a time in `f32`, with each
+/// channel gathered into an array the compiler turns into vector
+/// instructions. The pixels left over go through [`matrix_scalar`].
+fn matrix_lanes<T: PixelComponent, const N: usize>(
+  out: &mut [f64],
+  source: &[T],
+  matrix: &[f32],
+) {
+  let block = N * LANES;
+  let rest = out.len() / block * block;
+  for (out, pels) in out[..rest]
+    .chunks_exact_mut(block)
+    .zip(source.chunks_exact(block))
+  {
+    let mut channels = [[0.0f32; LANES]; N];
+    for (i, pel) in pels.chunks_exact(N).enumerate() {
+      for (channel, c) in channels.iter_mut().zip(pel) {
+        channel[i] = c.to_f32().unwrap_or_default();
+      }
+    }
+    for (r, row) in matrix.chunks_exact(N + 1).enumerate() {
+      let mut sum = [row[N]; LANES];
+      for (m, channel) in row.iter().zip(&channels) {
+        for (s, c) in sum.iter_mut().zip(channel) {
+          *s += m * c;
+        }
+      }
+      for (i, s) in sum.into_iter().enumerate() {
+        out[i * N + r] = f64::from(s);
+      }
+    }
+  }
+  matrix_scalar::<T, N>(&mut out[rest..], &source[rest..], matrix);
+}
+
+/// Transforms the color channels of `image` by a 3×4 affine `matrix`: each
+/// row gives the weights of red, green and blue in one output channel, then
+/// an offset as a fraction of white. Alpha is passed through.
+///
+/// Fails with [`Error::Channel`] unless `image` has three color channels.
+pub fn color_matrix<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  matrix: &[[f64; 4]; 3],
+  context: &ExecutionContext,
+) -> Result<ImageBuffer<T, N, A>> {
+  if N != 3 + usize::from(A) {
+    return Err(Error::Channel(format!(
+      "A color matrix needs three color channels, not {}",
+      N - usize::from(A)
+    )));
+  }
+  let mut full = vec![0.0; N * (N + 1)];
+  for r in 0..N {
+    let row = &mut full[r * (N + 1)..(r + 1) * (N + 1)];
+    match matrix.get(r) {
+      Some(weights) => {
+        row[..3]
+          .iter_mut()
+          .zip(weights)
+          .for_each(|(m, &w)| *m = w as f32);
+        row[N] = (weights[3] * image.white()) as f32;
+      }
+      None => row[r] = 1.0,
+    }
+  }
+  let data = apply_matrix(image, &full, context)?
+    .into_iter()
+    .map(component_from_f64)
+    .collect();
+  ImageBuffer::try_with_data(data, image.width, image.height)
+}
+
+/// Converts `image` to another component type as
+/// [`ImageBuffer::as_other_scaled`] does
+pub fn convert<
+  T: PixelComponent,
+  U: PixelComponent,
+  const N: usize,
+  const A: bool,
+>(
+  image: &ImageBuffer<T, N, A>,
+  context: &ExecutionContext,
+) -> Result<ImageBuffer<U, N, A>> {
+  let scale = ImageBuffer::<U, N, A>::empty(0, 0).white() / image.white();
+  let mut matrix = vec![0.0; N * (N + 1)];
+  for c in 0..N {
+    matrix[c * (N + 2)] = scale as f32;
+  }
+  let data = apply_matrix(image, &matrix, context)?
+    .into_iter()
+    .map(component_from_f64)
+    .collect();
+  ImageBuffer::try_with_data(data, image.width, image.height)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn contexts_agree_with_cpu_ops() {
+    let mut image = ImageBuffer::<u8, 3, false>::empty(7, 5);
+    for (i, c) in image.components_mut().iter_mut().enumerate() {
+      *c = (i * 37 % 256) as u8;
+    }
+    let single = ExecutionContext::single_threaded();
+    let banded = ExecutionContext {
+      tile_size: 2,
+      ..ExecutionContext::with_threads(3).unwrap()
+    };
+    let gpu = ExecutionContext {
+      policy: ExecutionPolicy::Gpu,
+      ..ExecutionContext::default()
+    };
+    let scalar = ExecutionContext {
+      simd: false,
+      ..ExecutionContext::default()
+    };
+    let mut contexts = vec![banded, scalar];
+    if gpu_available() {
+      contexts.push(gpu);
+    } else {
+      assert!(matches!(
+        resize(&image, 4, 9, &gpu),
+        Err(Error::Unsupported(_))
+      ));
+    }
+    let matrix = [
+      [0.5, 0.5, 0.0, 0.1],
+      [0.0, 1.0, 0.0, 0.0],
+      [0.2, 0.2, 0.6, -0.1],
+    ];
+    let close = |a: &ImageBuffer<u8, 3, false>,
+                 b: &ImageBuffer<u8, 3, false>| {
+      a.components()
+        .iter()
+        .zip(b.components())
+        .all(|(x, y)| x.abs_diff(*y) <= 1)
+    };
+    for context in &contexts {
+      assert!(close(
+        &resize(&image, 4, 9, context).unwrap(),
+        &resize(&image, 4, 9, &single).unwrap()
+      ));
+      assert!(close(
+        &gaussian_blur(&image, 1.5, context).unwrap(),
+        &gaussian_blur(&image, 1.5, &single).unwrap()
+      ));
+      assert!(close(
+        &color_matrix(&image, &matrix, context).unwrap(),
+        &color_matrix(&image, &matrix, &single).unwrap()
+      ));
+      let converted: ImageBuffer<u16, 3, false> =
+        convert(&image, context).unwrap();
+      assert_eq!(
+        converted.components(),
+        image.as_other_scaled::<u16>().components()
+      );
+    }
+    assert_eq!(
+      resize(&image, 4, 9, &single).unwrap().components(),
+      transform::resize(&image, 4, 9).components()
+    );
+  }
+
+  #[test]
+  fn color_matrix_swaps_and_offsets_channels() {
+    let image = ImageBuffer::<u8, 4, true>::with_val(&[10, 20, 30, 40], 2, 2);
+    let matrix = [
+      [0.0, 0.0, 1.0, 0.0],
+      [0.0, 1.0, 0.0, 0.1],
+      [1.0, 0.0, 0.0, 0.0],
+    ];
+    let context = ExecutionContext::default();
+    let result = color_matrix(&image, &matrix, &context).unwrap();
+    assert_eq!(*result.get_pixel(1, 1), [30, 46, 10, 40]);
+    let gray = ImageBuffer::<u8, 1, false>::empty(2, 2);
+    assert!(color_matrix(&gray, &matrix, &context).is_err());
+  }
+
+  /// Noise, resize, blur and a color matrix under `context`, reduced to an
+  /// FNV-1a hash of the output bytes
+  fn golden_hash(context: &ExecutionContext) -> u64 {
+    let mut image = ImageBuffer::<u8, 3, false>::empty(16, 12);
+    for (i, c) in image.components_mut().iter_mut().enumerate() {
+      *c = (i * 29 % 256) as u8;
+    }
+    crate::generate::noise::gaussian(&mut image, 0.05, &mut context.rng());
+    let image = resize(&image, 23, 9, context).unwrap();
+    let image = gaussian_blur(&image, 1.2, context).unwrap();
+    let matrix = [
+      [0.9, 0.1, 0.0, 0.02],
+      [0.1, 0.8, 0.1, 0.0],
+      [0.0, 0.3, 0.7, -0.02],
+    ];
+    let image = color_matrix(&image, &matrix, context).unwrap();
+    image
+      .components()
+      .iter()
+      .fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+      })
+  }
+
+  #[test]
+  fn deterministic_outputs_match_golden_hash() {
+    let golden = 13_858_675_257_347_072_652;
+    for threads in [1, 2, 5] {
+      let context = ExecutionContext {
+        thread_pool: ExecutionContext::with_threads(threads)
+          .unwrap()
+          .thread_pool,
+        tile_size: threads,
+        ..ExecutionContext::deterministic(7)
+      };
+      assert_eq!(golden_hash(&context), golden);
+    }
+    // Deterministic mode takes the scalar paths even with SIMD allowed
+    let deterministic = ExecutionContext::deterministic(7);
+    assert!(deterministic.simd && !deterministic.simd_enabled());
+    let scalar = ExecutionContext {
+      simd: false,
+      ..ExecutionContext::default()
+    };
+    let image = ImageBuffer::<u16, 3, false>::empty(9, 2)
+      .map_indexed(&mut |x, y, _| [(x * 7001 + y * 13) as u16; 3]);
+    let matrix = [
+      [0.3, 0.3, 0.3, 0.0],
+      [0.1, 0.7, 0.2, 0.01],
+      [0.0, 0.0, 1.1, 0.0],
+    ];
+    assert_eq!(
+      color_matrix(&image, &matrix, &deterministic)
+        .unwrap()
+        .components(),
+      color_matrix(&image, &matrix, &scalar).unwrap().components()
+    );
+    let gpu = ExecutionContext {
+      policy: ExecutionPolicy::Gpu,
+      ..deterministic
+    };
+    assert!(gpu.effective_policy().is_err());
+  }
+}
@@ -0,0 +1,33 @@
+// Multiplies every pixel by an affine matrix with one row per channel and
+// the offset in the last column.
+
+struct Params {
+  count:    u32,
+  channels: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> matrix: array<f32>;
+@group(0) @binding(2) var<storage, read> src: array<f32>;
+@group(0) @binding(3) var<storage, read_write> dst: array<f32>;
+
+@compute @workgroup_size(256)
+fn main(
+  @builtin(global_invocation_id) id: vec3<u32>,
+  @builtin(num_workgroups) groups: vec3<u32>,
+) {
+  let pixel = id.y * groups.x * 256u + id.x;
+  if (pixel >= params.count) {
+    return;
+  }
+  let n = params.channels;
+  let base = pixel * n;
+  for (var r = 0u; r < n; r++) {
+    let row = r * (n + 1u);
+    var sum = matrix[row + n];
+    for (var k = 0u; k < n; k++) {
+      sum += matrix[row + k] * src[base + k];
+    }
+    dst[base + r] = sum;
+  }
+}
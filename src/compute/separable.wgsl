@@ -0,0 +1,41 @@
+// One pass of a separable filter: each output sample is a weighted sum of
+// source samples along one axis, listed per output position in `taps`.
+
+struct Params {
+  src_width:  u32,
+  dst_width:  u32,
+  dst_height: u32,
+  channels:   u32,
+  vertical:   u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+// Start and length in `taps` for each output position along the axis
+@group(0) @binding(1) var<storage, read> ranges: array<vec2<u32>>;
+// Source position and the bits of its `f32` weight
+@group(0) @binding(2) var<storage, read> taps: array<vec2<u32>>;
+@group(0) @binding(3) var<storage, read> src: array<f32>;
+@group(0) @binding(4) var<storage, read_write> dst: array<f32>;
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+  if (id.x >= params.dst_width || id.y >= params.dst_height) {
+    return;
+  }
+  var range = ranges[id.x];
+  if (params.vertical == 1u) {
+    range = ranges[id.y];
+  }
+  for (var c = 0u; c < params.channels; c++) {
+    var sum = 0.0;
+    for (var t = range.x; t < range.x + range.y; t++) {
+      let tap = taps[t];
+      var pixel = id.y * params.src_width + tap.x;
+      if (params.vertical == 1u) {
+        pixel = tap.x * params.src_width + id.x;
+      }
+      sum += bitcast<f32>(tap.y) * src[pixel * params.channels + c];
+    }
+    dst[(id.y * params.dst_width + id.x) * params.channels + c] = sum;
+  }
+}
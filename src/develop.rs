@@ -0,0 +1,266 @@
+//! Developing raw sensor data into a viewable image.
+//!
+//! [`develop`] takes the single-channel mosaic read off a Bayer sensor and
+//! runs the usual develop steps in order: black-level subtraction, white
+//! balance, demosaicing, the camera-to-sRGB color matrix, sRGB encoding with
+//! an optional tone curve, and sharpening. [`RawDevelopSettings`] configures
+//! every step.
+
+use crate::{
+  color_space::{linear_to_srgb, ColorSpace},
+  error::{Error, Result},
+  limits::Limits,
+  ops::{lut::Lut1d, matting::box_mean},
+  pixel::{component_from_f64, PixelContainer},
+  Image,
+  ImageBuffer,
+};
+
+/// Layout of the color filter array, named by the colors of the top-left
+/// 2x2 block in reading order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CfaPattern {
+  #[default]
+  Rggb,
+  Bggr,
+  Grbg,
+  Gbrg,
+}
+
+impl CfaPattern {
+  /// Color filtered at `(x, y)`: 0 for red, 1 for green and 2 for blue
+  pub fn color_at(self, x: usize, y: usize) -> usize {
+    let block = match self {
+      CfaPattern::Rggb => [0, 1, 1, 2],
+      CfaPattern::Bggr => [2, 1, 1, 0],
+      CfaPattern::Grbg => [1, 0, 2, 1],
+      CfaPattern::Gbrg => [1, 2, 0, 1],
+    };
+    block[(y % 2) * 2 + x % 2]
+  }
+}
+
+/// Settings for [`develop`]
+#[derive(Clone, Debug)]
+pub struct RawDevelopSettings {
+  pub pattern:       CfaPattern,
+  /// Sensor value recorded for no light
+  pub black_level:   u16,
+  /// Sensor value at which pixels saturate
+  pub white_level:   u16,
+  /// Gains for red, green and blue, applied before demosaicing
+  pub white_balance: [f64; 3],
+  /// Converts white-balanced camera RGB to linear sRGB. Each row should sum
+  /// to one so that neutral colors stay neutral.
+  pub color_matrix:  [[f64; 3]; 3],
+  /// Applied after sRGB encoding, with one curve for all channels or one
+  /// each for red, green and blue
+  pub tone_curve:    Option<Lut1d>,
+  /// Strength of the unsharp mask applied last; zero disables sharpening
+  pub sharpen:       f64,
+}
+
+impl Default for RawDevelopSettings {
+  fn default() -> Self {
+    RawDevelopSettings {
+      pattern:       CfaPattern::Rggb,
+      black_level:   0,
+      white_level:   u16::MAX,
+      white_balance: [1.0; 3],
+      color_matrix:  [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+      tone_curve:    None,
+      sharpen:       0.0,
+    }
+  }
+}
+
+/// Black-level subtracted, normalized and white-balanced sensor values
+fn linearize(
+  raw: &ImageBuffer<u16, 1, false>,
+  settings: &RawDevelopSettings,
+) -> Vec<f64> {
+  let black = f64::from(settings.black_level);
+  let range = f64::from(settings.white_level) - black;
+  raw
+    .iter_pixels()
+    .enumerate()
+    .map(|(i, pel)| {
+      let color = settings.pattern.color_at(i % raw.width, i / raw.width);
+      let v = ((f64::from(pel[0]) - black) / range).clamp(0.0, 1.0);
+      v * settings.white_balance[color]
+    })
+    .collect()
+}
+
+/// Bilinear demosaicing: missing colors at each pixel are the mean of the
+/// neighbors in its 3x3 window that filter that color
+fn demosaic(
+  mosaic: &[f64],
+  width: usize,
+  height: usize,
+  pattern: CfaPattern,
+) -> Vec<[f64; 3]> {
+  let mut rgb = Vec::with_capacity(mosaic.len());
+  for y in 0..height {
+    for x in 0..width {
+      let mut sum = [0.0; 3];
+      let mut count = [0usize; 3];
+      for ny in y.saturating_sub(1)..(y + 2).min(height) {
+        for nx in x.saturating_sub(1)..(x + 2).min(width) {
+          let color = pattern.color_at(nx, ny);
+          sum[color] += mosaic[ny * width + nx];
+          count[color] += 1;
+        }
+      }
+      let own = pattern.color_at(x, y);
+      rgb.push([0, 1, 2].map(|c| {
+        if c == own {
+          mosaic[y * width + x]
+        } else if count[c] > 0 {
+          sum[c] / count[c] as f64
+        } else {
+          0.0
+        }
+      }));
+    }
+  }
+  rgb
+}
+
+/// Develops a Bayer mosaic into an sRGB image.
+///
+/// `raw` holds one sensor value per pixel, laid out as described by
+/// [`RawDevelopSettings::pattern`]. The result is 16-bit RGB.
+///
+/// Fails if the white level is not above the black level, or if the tone
+/// curve has neither one nor three curves.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(width = raw.width, height = raw.height)
+  )
+)]
+pub fn develop(
+  raw: &ImageBuffer<u16, 1, false>,
+  settings: &RawDevelopSettings,
+) -> Result<Image> {
+  if settings.white_level <= settings.black_level {
+    return Err(Error::InvalidArgument(format!(
+      "White level {} is not above black level {}",
+      settings.white_level, settings.black_level
+    )));
+  }
+  if let Some(curve) = &settings.tone_curve {
+    if curve.channels() != 1 && curve.channels() != 3 {
+      return Err(Error::Channel(format!(
+        "Tone curve has {} curves for 3 color channels",
+        curve.channels()
+      )));
+    }
+  }
+  let (width, height) = (raw.width, raw.height);
+
+  let mosaic = linearize(raw, settings);
+  let camera = demosaic(&mosaic, width, height, settings.pattern);
+  let mut planes = [0, 1, 2].map(|_| Vec::with_capacity(camera.len()));
+  for pel in camera {
+    for (c, row) in settings.color_matrix.iter().enumerate() {
+      let linear: f64 = row.iter().zip(pel).map(|(m, v)| m * v).sum();
+      let mut v = linear_to_srgb(linear);
+      if let Some(curve) = &settings.tone_curve {
+        v = curve.evaluate(c, v);
+      }
+      planes[c].push(v);
+    }
+  }
+
+  if settings.sharpen != 0.0 {
+    for plane in &mut planes {
+      let blurred = box_mean(plane, width, height, 1);
+      for (v, b) in plane.iter_mut().zip(blurred) {
+        *v += settings.sharpen * (*v - b);
+      }
+    }
+  }
+
+  let mut rgb =
+    ImageBuffer::<u16, 3, false>::try_empty(width, height, &Limits::none())?;
+  for (i, out) in rgb.iter_pixels_mut().enumerate() {
+    *out = [0, 1, 2].map(|c| {
+      component_from_f64(planes[c][i].clamp(0.0, 1.0) * f64::from(u16::MAX))
+    });
+  }
+  Ok(Image::new_u16(ColorSpace::Rgb(rgb)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::image::Implementation;
+
+  fn rgb(image: &Image) -> &ImageBuffer<u16, 3, false> {
+    match &image.imp {
+      Implementation::U16(imp) =>
+        match &imp.data {
+          ColorSpace::Rgb(buf) => buf,
+          _ => panic!("Expected RGB"),
+        },
+      _ => panic!("Expected u16"),
+    }
+  }
+
+  #[test]
+  fn develop_flat_field() {
+    // A gray card shot under light that is weak in red and blue
+    let settings = RawDevelopSettings {
+      pattern: CfaPattern::Grbg,
+      black_level: 64,
+      white_level: 1023,
+      white_balance: [2.0, 1.0, 1.5],
+      ..Default::default()
+    };
+    let raw =
+      ImageBuffer::<u16, 1, false>::empty(8, 6).map_indexed(&mut |x, y, _| {
+        let signal = [240.0, 480.0, 320.0][settings.pattern.color_at(x, y)];
+        [64 + signal as u16]
+      });
+
+    let developed = develop(&raw, &settings).unwrap();
+    let expected = linear_to_srgb(480.0 / 959.0) * 65535.0;
+    for pel in rgb(&developed).iter_pixels() {
+      for &v in pel {
+        assert!((f64::from(v) - expected).abs() < 2.0, "{pel:?}");
+      }
+    }
+
+    let sharpened = develop(
+      &raw,
+      &RawDevelopSettings {
+        sharpen: 1.0,
+        tone_curve: Some(Lut1d::from_curve(&[(0.0, 1.0), (1.0, 0.0)]).unwrap()),
+        ..settings.clone()
+      },
+    )
+    .unwrap();
+    let inverted = 65535.0 - expected;
+    assert!(
+      (f64::from(rgb(&sharpened).get_pixel(3, 3)[1]) - inverted).abs() < 20.0
+    );
+  }
+
+  #[test]
+  fn develop_rejects_bad_levels() {
+    let raw = ImageBuffer::<u16, 1, false>::empty(2, 2);
+    let settings = RawDevelopSettings {
+      black_level: 512,
+      white_level: 512,
+      ..Default::default()
+    };
+    assert!(matches!(
+      develop(&raw, &settings),
+      Err(Error::InvalidArgument(_))
+    ));
+  }
+}
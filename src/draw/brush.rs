@@ -0,0 +1,252 @@
+//! Painting strokes with round brushes, as the brush tools of paint
+//! programs do.
+//!
+//! A stroke is a polyline of points sampled from a mouse or pen, each with
+//! the pen's pressure. The brush stamps dabs along it, `spacing` apart, and
+//! how overlapping dabs add up within the stroke is up to its
+//! [`Accumulation`]. Pressure can shrink the dabs, thin their flow, or both.
+
+use crate::{
+  draw::path::{paint_pixel, Paint},
+  pixel::PixelComponent,
+  ImageBuffer,
+};
+
+/// How the dabs of one stroke combine where they overlap
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Accumulation {
+  /// Dabs build up coverage over each other, but the stroke as a whole is
+  /// no more opaque than the brush's opacity
+  #[default]
+  Wash,
+  /// Each pixel takes the coverage of the strongest dab over it, so
+  /// overlaps don't darken
+  Max,
+  /// Each dab is painted onto the image as it is stamped, so slow or
+  /// overlapping strokes keep building past the brush's opacity, as an
+  /// airbrush does
+  Buildup,
+}
+
+/// A round brush
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Brush {
+  /// In pixels, at full pressure
+  pub radius:          f64,
+  /// Share of the radius painted at full strength, between 0 and 1. The
+  /// rest fades out smoothly, so 0 is the softest brush and 1 the hardest.
+  pub hardness:        f64,
+  /// Distance between dabs, as a fraction of their diameter
+  pub spacing:         f64,
+  /// Coverage of each dab, between 0 and 1
+  pub flow:            f64,
+  /// Most coverage of a stroke, between 0 and 1, unless accumulating by
+  /// [`Accumulation::Buildup`]
+  pub opacity:         f64,
+  /// How much low pressure shrinks the dabs, between 0 for not at all and
+  /// 1 for down to nothing at no pressure
+  pub pressure_radius: f64,
+  /// How much low pressure thins the flow, likewise
+  pub pressure_flow:   f64,
+  pub accumulation:    Accumulation,
+}
+
+impl Brush {
+  /// A fully opaque brush of `radius` pixels, fading out over its outer
+  /// half, whose size follows the pen's pressure
+  pub fn new(radius: f64) -> Self {
+    Brush {
+      radius,
+      hardness: 0.5,
+      spacing: 0.25,
+      flow: 1.0,
+      opacity: 1.0,
+      pressure_radius: 1.0,
+      pressure_flow: 0.0,
+      accumulation: Accumulation::Wash,
+    }
+  }
+
+  /// Strength of a dab of `radius` at `distance` from its center, between
+  /// 0 and 1, before flow and opacity
+  pub fn shape(&self, distance: f64, radius: f64) -> f64 {
+    let inner = radius * self.hardness.clamp(0.0, 1.0);
+    if distance >= radius {
+      0.0
+    } else if distance <= inner {
+      1.0
+    } else {
+      let t = (radius - distance) / (radius - inner);
+      t * t * (3.0 - 2.0 * t)
+    }
+  }
+
+  /// Radius and flow of a dab at `pressure`, between 0 and 1
+  fn at_pressure(&self, pressure: f64) -> (f64, f64) {
+    let lack = 1.0 - pressure.clamp(0.0, 1.0);
+    let scale = |amount: f64| 1.0 - amount.clamp(0.0, 1.0) * lack;
+    (
+      self.radius * scale(self.pressure_radius),
+      self.flow.clamp(0.0, 1.0) * scale(self.pressure_flow),
+    )
+  }
+
+  /// Centers, radii and flows of the dabs along `points`
+  fn dabs(&self, points: &[StrokePoint]) -> Vec<(f64, f64, f64, f64)> {
+    let dab = |p: StrokePoint| {
+      let (radius, flow) = self.at_pressure(p.pressure);
+      (p.x, p.y, radius, flow)
+    };
+    let Some(&first) = points.first() else {
+      return Vec::new();
+    };
+    let mut dabs = vec![dab(first)];
+    // Distance along the stroke to the next dab
+    let mut ahead = self.step(dab(first).2);
+    for pair in points.windows(2) {
+      let (from, to) = (pair[0], pair[1]);
+      let length = (to.x - from.x).hypot(to.y - from.y);
+      let mut along = 0.0;
+      while along + ahead <= length {
+        along += ahead;
+        let t = along / length;
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        let next = dab(StrokePoint {
+          x:        lerp(from.x, to.x),
+          y:        lerp(from.y, to.y),
+          pressure: lerp(from.pressure, to.pressure),
+        });
+        ahead = self.step(next.2);
+        dabs.push(next);
+      }
+      ahead -= length - along;
+    }
+    dabs
+  }
+
+  /// Distance to the dab after one of `radius`, at least half a pixel
+  fn step(&self, radius: f64) -> f64 { (self.spacing * 2.0 * radius).max(0.5) }
+}
+
+/// A point of a stroke
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StrokePoint {
+  pub x:        f64,
+  pub y:        f64,
+  /// Between 0 and 1
+  pub pressure: f64,
+}
+
+impl StrokePoint {
+  /// A point at full pressure, as from a mouse
+  pub fn new(x: f64, y: f64) -> Self {
+    StrokePoint {
+      x,
+      y,
+      pressure: 1.0,
+    }
+  }
+}
+
+impl From<(f64, f64)> for StrokePoint {
+  fn from((x, y): (f64, f64)) -> Self { StrokePoint::new(x, y) }
+}
+
+/// Paints a stroke through `points` into `image` with `brush`, in a color,
+/// gradient or pattern, blending over what is there
+pub fn stroke<T: PixelComponent>(
+  image: &mut ImageBuffer<T, 4, true>,
+  points: &[StrokePoint],
+  brush: &Brush,
+  paint: impl Into<Paint>,
+) {
+  let (width, height) = (image.width, image.height);
+  let paint = paint.into();
+  let opacity = brush.opacity.clamp(0.0, 1.0);
+  let mut coverage = match brush.accumulation {
+    Accumulation::Buildup => Vec::new(),
+    Accumulation::Wash | Accumulation::Max => vec![0.0; width * height],
+  };
+  for (cx, cy, radius, flow) in brush.dabs(points) {
+    let span = |c: f64, limit: usize| {
+      let low = (c - radius).floor().max(0.0) as usize;
+      low..((c + radius).ceil().max(0.0) as usize).min(limit)
+    };
+    for y in span(cy, height) {
+      for x in span(cx, width) {
+        let distance = (x as f64 + 0.5 - cx).hypot(y as f64 + 0.5 - cy);
+        let strength = brush.shape(distance, radius) * flow;
+        if strength <= 0.0 {
+          continue;
+        }
+        match brush.accumulation {
+          Accumulation::Buildup =>
+            paint_pixel(image, &paint, x, y, strength * opacity),
+          Accumulation::Max => {
+            let c = &mut coverage[y * width + x];
+            *c = f64::max(*c, strength);
+          }
+          Accumulation::Wash => {
+            let c = &mut coverage[y * width + x];
+            *c += strength * (1.0 - *c);
+          }
+        }
+      }
+    }
+  }
+  for (i, c) in coverage.into_iter().enumerate() {
+    if c > 0.0 {
+      paint_pixel(image, &paint, i % width, i / width, c * opacity);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{color::Rgba8, pixel::PixelContainer};
+
+  #[test]
+  fn strokes_space_dabs_and_accumulate() {
+    let points: Vec<StrokePoint> = vec![(2.0, 5.5).into(), (18.0, 5.5).into()];
+    let brush = Brush {
+      hardness: 1.0,
+      flow: 0.5,
+      ..Brush::new(2.0)
+    };
+    assert_eq!(brush.dabs(&points).len(), 17);
+
+    let canvas = ImageBuffer::<u8, 4, true>::empty(20, 11);
+    let alpha = |opacity, accumulation| {
+      let mut image = canvas.clone();
+      let brush = Brush {
+        opacity,
+        accumulation,
+        ..brush
+      };
+      stroke(&mut image, &points, &brush, Rgba8::rgb(255, 0, 0));
+      image.get_pixel(10, 5)[3]
+    };
+    // Four dabs overlap each pixel on the line
+    assert_eq!(alpha(1.0, Accumulation::Max), 128);
+    assert_eq!(alpha(1.0, Accumulation::Wash), 239);
+    assert_eq!(alpha(0.5, Accumulation::Wash), 120);
+    assert!(alpha(0.5, Accumulation::Buildup) > 128);
+
+    // Light pressure draws thinner
+    let light = [
+      StrokePoint {
+        pressure: 0.25,
+        ..StrokePoint::new(10.0, 5.5)
+      },
+      StrokePoint {
+        pressure: 0.25,
+        ..StrokePoint::new(11.0, 5.5)
+      },
+    ];
+    let mut image = canvas.clone();
+    stroke(&mut image, &light, &Brush::new(4.0), Rgba8::rgb(0, 0, 255));
+    assert!(image.get_pixel(10, 5)[3] > 0);
+    assert_eq!(image.get_pixel(10, 7)[3], 0);
+  }
+}
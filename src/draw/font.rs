@@ -0,0 +1,107 @@
+//! A tiny bitmap font for labeling overlays and legends, three pixels wide
+//! and five tall, drawn at whole multiples of that size.
+//!
+//! It covers digits, letters, shown in capitals, and the punctuation of
+//! numbers and short labels. Other characters are left blank.
+
+use crate::{
+  pixel::{PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+pub(crate) const GLYPH_HEIGHT: usize = 5;
+
+/// Horizontal distance between characters, including a pixel of space
+pub(crate) const ADVANCE: usize = 4;
+
+/// Rows of a glyph, top first, three bits each with the leftmost pixel
+/// highest
+fn glyph(c: char) -> Option<[u8; GLYPH_HEIGHT]> {
+  Some(match c.to_ascii_uppercase() {
+    '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+    '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+    '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+    '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+    '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+    '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+    '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+    '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+    '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+    '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+    'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+    'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+    'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+    'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+    'E' => [0b111, 0b100, 0b111, 0b100, 0b111],
+    'F' => [0b111, 0b100, 0b111, 0b100, 0b100],
+    'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+    'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+    'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+    'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+    'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+    'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+    'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+    'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+    'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+    'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+    'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+    'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+    'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+    'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+    'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+    'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+    'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+    'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+    'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+    'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+    '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+    ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+    ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+    '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+    '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+    '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+    '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+    '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+    '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+    '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+    ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+    _ => return None,
+  })
+}
+
+/// Width of `text` drawn at `scale`, in pixels
+pub(crate) fn text_width(text: &str, scale: usize) -> usize {
+  (text.chars().count() * ADVANCE).saturating_sub(1) * scale
+}
+
+/// Draws `text` in `color` with its top-left corner at `(x, y)`, each
+/// pixel of the font `scale` pixels across. Whatever falls outside the
+/// image is cut off.
+pub(crate) fn draw_text<T: PixelComponent, const N: usize, const A: bool>(
+  image: &mut ImageBuffer<T, N, A>,
+  (x, y): (isize, isize),
+  text: &str,
+  color: [T; N],
+  scale: usize,
+) {
+  let (width, height) = (image.width as isize, image.height as isize);
+  for (i, c) in text.chars().enumerate() {
+    let Some(rows) = glyph(c) else {
+      continue;
+    };
+    let left = x + (i * ADVANCE * scale) as isize;
+    for (row, bits) in rows.iter().enumerate() {
+      for column in (0..3).filter(|column| bits & (0b100 >> column) != 0) {
+        for dy in 0..scale {
+          for dx in 0..scale {
+            let px = left + (column * scale + dx) as isize;
+            let py = y + (row * scale + dy) as isize;
+            if px >= 0 && py >= 0 && px < width && py < height {
+              *image.get_pixel_mut(px as usize, py as usize) = color;
+            }
+          }
+        }
+      }
+    }
+  }
+}
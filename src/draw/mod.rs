@@ -0,0 +1,12 @@
+//! Drawing vector graphics, brush strokes and plots of analysis results
+//! into image buffers, for overlays, for authoring masks and for painting
+//! tools.
+
+pub mod brush;
+pub(crate) mod font;
+pub mod overlay;
+pub mod path;
+pub mod pattern;
+pub mod polygon;
+
+pub use polygon::fill_polygon;
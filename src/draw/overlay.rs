@@ -0,0 +1,277 @@
+//! Drawing analysis results over images: detection boxes with their
+//! labels, keypoints, optical flow and the contour lines of scalar fields.
+//!
+//! Colors cycle through the ten of [`PALETTE`], which stay apart from each
+//! other on most photos, so results can be told apart without styling them:
+//! boxes take a color per label, keypoints one per index, so that matched
+//! points in two images share colors, and contour lines one per level. Flow
+//! arrows are colored by their direction instead.
+
+use crate::{
+  color::{Rgba8, RgbaF32},
+  draw::{
+    font::{draw_text, text_width, GLYPH_HEIGHT},
+    path::{fill, FillRule, LineCap, Path},
+  },
+  ops::sprites::Rect,
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// Tableau's ten categorical colors
+pub const PALETTE: [Rgba8; 10] = [
+  Rgba8::rgb(0x4e, 0x79, 0xa7),
+  Rgba8::rgb(0xf2, 0x8e, 0x2b),
+  Rgba8::rgb(0xe1, 0x57, 0x59),
+  Rgba8::rgb(0x76, 0xb7, 0xb2),
+  Rgba8::rgb(0x59, 0xa1, 0x4f),
+  Rgba8::rgb(0xed, 0xc9, 0x48),
+  Rgba8::rgb(0xb0, 0x7a, 0xa1),
+  Rgba8::rgb(0xff, 0x9d, 0xa7),
+  Rgba8::rgb(0x9c, 0x75, 0x5f),
+  Rgba8::rgb(0xba, 0xb0, 0xac),
+];
+
+/// Width of drawn lines, in pixels
+const LINE_WIDTH: f64 = 2.0;
+
+/// Scale of label text over the font's own three by five pixels
+const TEXT_SCALE: usize = 2;
+
+/// The `i`th color of [`PALETTE`], starting over after the last
+pub fn palette_color(i: usize) -> Rgba8 { PALETTE[i % PALETTE.len()] }
+
+fn draw_lines<T: PixelComponent>(
+  image: &mut ImageBuffer<T, 4, true>,
+  path: &Path,
+  width: f64,
+  color: impl Into<RgbaF32>,
+) {
+  let outline = path.stroke(width, LineCap::Round);
+  fill(image, &outline, color.into(), FillRule::NonZero);
+}
+
+/// Outlines each of `boxes` with its label above it, or just inside it at
+/// the top of the image. Boxes with the same label share a color.
+pub fn boxes<T: PixelComponent>(
+  image: &mut ImageBuffer<T, 4, true>,
+  boxes: &[(Rect, &str)],
+) {
+  let mut labels: Vec<&str> = Vec::new();
+  let white = component_from_f64(image.white());
+  for &(rect, label) in boxes {
+    let index = labels.iter().position(|l| *l == label).unwrap_or_else(|| {
+      labels.push(label);
+      labels.len() - 1
+    });
+    let color = palette_color(index);
+    let (x, y) = (rect.x as f64, rect.y as f64);
+    let (right, bottom) = (x + rect.width as f64, y + rect.height as f64);
+    let outline = Path::new()
+      .move_to(x, y)
+      .line_to(right, y)
+      .line_to(right, bottom)
+      .line_to(x, bottom)
+      .close();
+    draw_lines(image, &outline, LINE_WIDTH, color);
+    if label.is_empty() {
+      continue;
+    }
+
+    let tag_height = (GLYPH_HEIGHT + 2) * TEXT_SCALE;
+    let tag_width = (text_width(label, TEXT_SCALE) + 2 * TEXT_SCALE) as f64;
+    let top = if rect.y >= tag_height {
+      y - tag_height as f64
+    } else {
+      y
+    };
+    let (left, top) = (x - LINE_WIDTH / 2.0, top.max(0.0));
+    let tag = Path::new()
+      .move_to(left, top)
+      .line_to(left + tag_width, top)
+      .line_to(left + tag_width, top + tag_height as f64)
+      .line_to(left, top + tag_height as f64)
+      .close();
+    fill(image, &tag, color, FillRule::NonZero);
+    let origin = (
+      (left as isize).max(0) + TEXT_SCALE as isize,
+      top as isize + TEXT_SCALE as isize,
+    );
+    draw_text(image, origin, label, [white; 4], TEXT_SCALE);
+  }
+}
+
+/// Circles each of `points` with a ring of `radius` pixels around a dot
+pub fn keypoints<T: PixelComponent>(
+  image: &mut ImageBuffer<T, 4, true>,
+  points: &[(f64, f64)],
+  radius: f64,
+) {
+  for (i, &(x, y)) in points.iter().enumerate() {
+    let color = palette_color(i);
+    let ring = Path::new().ellipse((x, y), (radius, radius));
+    draw_lines(image, &ring, LINE_WIDTH / 2.0, color);
+    let dot = Path::new().ellipse((x, y), (1.0, 1.0));
+    fill(image, &dot, color, FillRule::NonZero);
+  }
+}
+
+/// The fully saturated color of hue `turn`, from 0 for red around to 1
+fn hue(turn: f64) -> RgbaF32 {
+  let h = turn.rem_euclid(1.0) * 6.0;
+  let channel = |center: f64| (2.0 - (h - center).abs()).clamp(0.0, 1.0);
+  RgbaF32 {
+    r: ((h - 3.0).abs() - 1.0).clamp(0.0, 1.0) as f32,
+    g: channel(2.0) as f32,
+    b: channel(4.0) as f32,
+    a: 1.0,
+  }
+}
+
+/// Draws arrows for the optical flow `flow`, holding each pixel's motion
+/// in x and y, every `step` pixels and `scale` times as long as the
+/// motion. Arrows are colored by direction, around the hues from red for
+/// rightwards, and those shorter than a pixel are left out.
+pub fn flow_field<T: PixelComponent>(
+  image: &mut ImageBuffer<T, 4, true>,
+  flow: &ImageBuffer<f32, 2, false>,
+  step: usize,
+  scale: f64,
+) {
+  let step = step.max(1);
+  for y in (step / 2..flow.height).step_by(step) {
+    for x in (step / 2..flow.width).step_by(step) {
+      let [dx, dy] = flow.get_pixel(x, y).map(|v| f64::from(v) * scale);
+      let length = dx.hypot(dy);
+      if !(length >= 1.0 && length.is_finite()) {
+        continue;
+      }
+      let (x0, y0) = (x as f64 + 0.5, y as f64 + 0.5);
+      let (x1, y1) = (x0 + dx, y0 + dy);
+      let angle = dy.atan2(dx);
+      let head = (length / 3.0).min(4.0);
+      let barb = |turn: f64| {
+        let a = angle + std::f64::consts::PI + turn;
+        (x1 + head * a.cos(), y1 + head * a.sin())
+      };
+      let (left, right) = (barb(0.5), barb(-0.5));
+      let arrow = Path::new()
+        .move_to(x0, y0)
+        .line_to(x1, y1)
+        .move_to(left.0, left.1)
+        .line_to(x1, y1)
+        .line_to(right.0, right.1);
+      let color = hue(angle / std::f64::consts::TAU);
+      draw_lines(image, &arrow, LINE_WIDTH / 2.0, color);
+    }
+  }
+}
+
+/// Draws the lines along which `field` takes each of `levels`, found by
+/// marching squares between pixel centers, each level in its own color
+pub fn contour_lines<T: PixelComponent, U: PixelComponent>(
+  image: &mut ImageBuffer<T, 4, true>,
+  field: &ImageBuffer<U, 1, false>,
+  levels: &[f64],
+) {
+  let value =
+    |x: usize, y: usize| field.get_pixel(x, y)[0].to_f64().unwrap_or_default();
+  for (i, &level) in levels.iter().enumerate() {
+    let mut path = Path::new();
+    for y in 0..field.height.saturating_sub(1) {
+      for x in 0..field.width.saturating_sub(1) {
+        // Corners clockwise from the top left, and the edges after each
+        let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+        let values = corners.map(|(x, y)| value(x, y));
+        let mut crossings = Vec::with_capacity(4);
+        for k in 0..4 {
+          let (a, b) = (values[k], values[(k + 1) % 4]);
+          if (a >= level) == (b >= level) {
+            continue;
+          }
+          let t = (level - a) / (b - a);
+          let (p, q) = (corners[k], corners[(k + 1) % 4]);
+          crossings.push((
+            p.0 as f64 + 0.5 + t * (q.0 as f64 - p.0 as f64),
+            p.1 as f64 + 0.5 + t * (q.1 as f64 - p.1 as f64),
+          ));
+        }
+        let pairs: &[(usize, usize)] = match crossings.len() {
+          2 => &[(0, 1)],
+          // A saddle: the lines cut off the two corners on the other side
+          // of the level from the center
+          4 if (values.iter().sum::<f64>() / 4.0 >= level)
+            == (values[0] >= level) =>
+            &[(0, 1), (2, 3)],
+          4 => &[(0, 3), (1, 2)],
+          _ => &[],
+        };
+        for &(a, b) in pairs {
+          let (a, b) = (crossings[a], crossings[b]);
+          path = path.move_to(a.0, a.1).line_to(b.0, b.1);
+        }
+      }
+    }
+    draw_lines(image, &path, LINE_WIDTH / 2.0, palette_color(i));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn overlays_draw_in_cycling_colors() {
+    let canvas = ImageBuffer::<u8, 4, true>::empty(60, 60);
+    let rgb = |image: &ImageBuffer<u8, 4, true>, x, y| {
+      let [r, g, b, _] = *image.get_pixel(x, y);
+      Rgba8::rgb(r, g, b)
+    };
+
+    let mut image = canvas.clone();
+    let rect = |x, y| {
+      Rect {
+        x,
+        y,
+        width: 16,
+        height: 16,
+      }
+    };
+    let found = [
+      (rect(2, 30), "cat"),
+      (rect(35, 35), "dog"),
+      (rect(30, 2), "cat"),
+    ];
+    boxes(&mut image, &found);
+    assert_eq!(rgb(&image, 2, 40), PALETTE[0]);
+    assert_eq!(rgb(&image, 35, 45), PALETTE[1]);
+    assert_eq!(rgb(&image, 46, 17), PALETTE[0]);
+    // Labels sit above their boxes, or inside at the top of the image,
+    // with white text
+    assert_eq!(rgb(&image, 5, 17), PALETTE[0]);
+    assert_eq!(image.get_pixel(5, 18), &[255, 255, 255, 255]);
+    assert_eq!(image.get_pixel(33, 4), &[255, 255, 255, 255]);
+
+    let mut image = canvas.clone();
+    keypoints(&mut image, &[(10.0, 10.0), (30.0, 30.0)], 4.0);
+    assert_eq!(rgb(&image, 10, 10), PALETTE[0]);
+    assert_eq!(rgb(&image, 30, 30), PALETTE[1]);
+    assert_eq!(image.get_pixel(12, 10)[3], 0);
+
+    let mut flow = ImageBuffer::<f32, 2, false>::empty(40, 40);
+    *flow.get_pixel_mut(15, 15) = [8.0, 0.0];
+    let mut image = canvas.clone();
+    flow_field(&mut image, &flow, 10, 1.0);
+    assert_eq!(rgb(&image, 19, 15), Rgba8::rgb(255, 0, 0));
+    assert_eq!(image.get_pixel(15, 25)[3], 0);
+
+    // A cone's contours are rings
+    let cone = ImageBuffer::<f32, 1, false>::empty(40, 40)
+      .map_indexed(&mut |x, y, _| [(x as f32 - 20.0).hypot(y as f32 - 20.0)]);
+    let mut image = canvas.clone();
+    contour_lines(&mut image, &cone, &[5.0, 10.0]);
+    assert_eq!(rgb(&image, 25, 20), PALETTE[0]);
+    assert_eq!(rgb(&image, 20, 30), PALETTE[1]);
+    assert_eq!(image.get_pixel(20, 20)[3], 0);
+  }
+}
@@ -0,0 +1,787 @@
+//! Paths of straight and Bézier segments, filled with anti-aliasing.
+//!
+//! Coordinates are in pixels with the origin at the top-left corner of the
+//! image, so pixel `(x, y)` covers the square from `(x, y)` to
+//! `(x + 1, y + 1)`, as in SVG and most 2D graphics APIs.
+
+use crate::{
+  color::{Rgba8, RgbaF32},
+  draw::{pattern::Pattern, polygon::scan},
+  error::{Error, Result},
+  generate::gradient::Gradient,
+  ops::{
+    blend::{composite, BlendMode},
+    mask::Mask,
+  },
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// Greatest distance, in pixels, between a curve and the lines it is
+/// flattened into
+const FLATNESS: f64 = 0.1;
+
+/// Control point distance for a quarter ellipse drawn as a cubic curve
+pub(crate) const KAPPA: f64 = 0.552_284_749_830_793_4;
+
+/// One step of a [`Path`]. Curves start from the end of the previous
+/// segment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segment {
+  /// Starts a new subpath at a point
+  MoveTo(f64, f64),
+  LineTo(f64, f64),
+  /// A quadratic Bézier curve with one control point, then the end
+  QuadTo(f64, f64, f64, f64),
+  /// A cubic Bézier curve with two control points, then the end
+  CubicTo(f64, f64, f64, f64, f64, f64),
+  /// Joins the subpath back to its start
+  Close,
+}
+
+/// Which regions enclosed by a path are inside it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FillRule {
+  /// Inside wherever the path winds around a point a non-zero number of
+  /// times, counting direction. Nested subpaths drawn the same way fill
+  /// solid.
+  #[default]
+  NonZero,
+  /// Inside wherever a ray from a point crosses the path an odd number of
+  /// times. Nested subpaths leave holes.
+  EvenOdd,
+}
+
+/// How [`Path::stroke`] finishes the ends of open subpaths
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineCap {
+  /// Square ends exactly at the end points
+  #[default]
+  Butt,
+  /// Half circles around the end points
+  Round,
+  /// Square ends half the stroke width past the end points
+  Square,
+}
+
+/// A sequence of subpaths made of lines and Bézier curves
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Path {
+  segments: Vec<Segment>,
+}
+
+impl Path {
+  pub fn new() -> Self { Self::default() }
+
+  pub fn segments(&self) -> &[Segment] { &self.segments }
+
+  pub fn move_to(mut self, x: f64, y: f64) -> Self {
+    self.segments.push(Segment::MoveTo(x, y));
+    self
+  }
+
+  pub fn line_to(mut self, x: f64, y: f64) -> Self {
+    self.segments.push(Segment::LineTo(x, y));
+    self
+  }
+
+  pub fn quad_to(mut self, cx: f64, cy: f64, x: f64, y: f64) -> Self {
+    self.segments.push(Segment::QuadTo(cx, cy, x, y));
+    self
+  }
+
+  pub fn cubic_to(
+    mut self,
+    c1x: f64,
+    c1y: f64,
+    c2x: f64,
+    c2y: f64,
+    x: f64,
+    y: f64,
+  ) -> Self {
+    self
+      .segments
+      .push(Segment::CubicTo(c1x, c1y, c2x, c2y, x, y));
+    self
+  }
+
+  pub fn close(mut self) -> Self {
+    self.segments.push(Segment::Close);
+    self
+  }
+
+  /// Appends the ellipse around `center` with radii `radii` as a closed
+  /// subpath of four cubic curves, turning clockwise on screen
+  pub fn ellipse(self, center: (f64, f64), radii: (f64, f64)) -> Self {
+    let ((cx, cy), (rx, ry)) = (center, radii);
+    let (kx, ky) = (rx * KAPPA, ry * KAPPA);
+    self
+      .move_to(cx + rx, cy)
+      .cubic_to(cx + rx, cy + ky, cx + kx, cy + ry, cx, cy + ry)
+      .cubic_to(cx - kx, cy + ry, cx - rx, cy + ky, cx - rx, cy)
+      .cubic_to(cx - rx, cy - ky, cx - kx, cy - ry, cx, cy - ry)
+      .cubic_to(cx + kx, cy - ry, cx + rx, cy - ky, cx + rx, cy)
+      .close()
+  }
+
+  /// Parses the path data of an SVG `d` attribute, such as
+  /// `"M 10 10 h 20 a 5 5 0 0 1 0 10 z"`. All commands are supported, in
+  /// absolute and relative forms; elliptical arcs become cubic curves.
+  pub fn parse_svg(data: &str) -> Result<Self> { SvgParser::new(data).parse() }
+
+  /// The path with every point mapped by the affine transform
+  /// `[a, b, c, d, e, f]`, which takes `(x, y)` to
+  /// `(a x + c y + e, b x + d y + f)` as an SVG `matrix()` does
+  pub fn transform(&self, matrix: [f64; 6]) -> Path {
+    let [a, b, c, d, e, f] = matrix;
+    let map = |x: f64, y: f64| (a * x + c * y + e, b * x + d * y + f);
+    let segments = self
+      .segments
+      .iter()
+      .map(|segment| {
+        match *segment {
+          Segment::MoveTo(x, y) => {
+            let (x, y) = map(x, y);
+            Segment::MoveTo(x, y)
+          }
+          Segment::LineTo(x, y) => {
+            let (x, y) = map(x, y);
+            Segment::LineTo(x, y)
+          }
+          Segment::QuadTo(cx, cy, x, y) => {
+            let ((cx, cy), (x, y)) = (map(cx, cy), map(x, y));
+            Segment::QuadTo(cx, cy, x, y)
+          }
+          Segment::CubicTo(c1x, c1y, c2x, c2y, x, y) => {
+            let ((c1x, c1y), (c2x, c2y)) = (map(c1x, c1y), map(c2x, c2y));
+            let (x, y) = map(x, y);
+            Segment::CubicTo(c1x, c1y, c2x, c2y, x, y)
+          }
+          Segment::Close => Segment::Close,
+        }
+      })
+      .collect();
+    Path {
+      segments,
+    }
+  }
+
+  /// The outline of everything within `width / 2` of the path, to be
+  /// filled with [`FillRule::NonZero`]. Corners are always rounded, and
+  /// `cap` shapes the ends of subpaths that are not closed.
+  pub fn stroke(&self, width: f64, cap: LineCap) -> Path {
+    let half = width / 2.0;
+    let mut outline = Path::new();
+    if half <= 0.0 {
+      return outline;
+    }
+    for (mut points, closed) in self.subpaths() {
+      points.dedup();
+      if closed && points.len() > 1 && points.first() == points.last() {
+        points.pop();
+      }
+      let n = points.len();
+      if n < 2 {
+        continue;
+      }
+      let segments = if closed { n } else { n - 1 };
+      for i in 0..segments {
+        let (mut a, mut b) = (points[i], points[(i + 1) % n]);
+        let length = (b.0 - a.0).hypot(b.1 - a.1);
+        let (dx, dy) = ((b.0 - a.0) / length, (b.1 - a.1) / length);
+        if cap == LineCap::Square && !closed {
+          if i == 0 {
+            a = (a.0 - dx * half, a.1 - dy * half);
+          }
+          if i == segments - 1 {
+            b = (b.0 + dx * half, b.1 + dy * half);
+          }
+        }
+        let (nx, ny) = (-dy * half, dx * half);
+        outline = outline.polygon(&[
+          (a.0 + nx, a.1 + ny),
+          (b.0 + nx, b.1 + ny),
+          (b.0 - nx, b.1 - ny),
+          (a.0 - nx, a.1 - ny),
+        ]);
+      }
+      let ends = closed || cap == LineCap::Round;
+      for (i, &(x, y)) in points.iter().enumerate() {
+        if ends || (i > 0 && i < n - 1) {
+          outline = outline.polygon(&circle(x, y, half));
+        }
+      }
+    }
+    outline
+  }
+
+  /// Appends a closed polygon, turned so that its winding is positive and
+  /// it unions with the others under [`FillRule::NonZero`]
+  fn polygon(mut self, points: &[(f64, f64)]) -> Self {
+    let doubled: f64 = (0..points.len())
+      .map(|i| {
+        let (p, q) = (points[i], points[(i + 1) % points.len()]);
+        p.0 * q.1 - q.0 * p.1
+      })
+      .sum();
+    let mut ordered = points.to_vec();
+    if doubled < 0.0 {
+      ordered.reverse();
+    }
+    self
+      .segments
+      .push(Segment::MoveTo(ordered[0].0, ordered[0].1));
+    for &(x, y) in &ordered[1..] {
+      self.segments.push(Segment::LineTo(x, y));
+    }
+    self.close()
+  }
+
+  /// The subpaths flattened into polylines, with whether each was closed
+  fn subpaths(&self) -> Vec<(Vec<(f64, f64)>, bool)> {
+    let mut subpaths = Vec::new();
+    let mut current: Vec<(f64, f64)> = Vec::new();
+    let mut start = (0.0, 0.0);
+    for segment in &self.segments {
+      let from = current.last().copied().unwrap_or(start);
+      match *segment {
+        Segment::MoveTo(x, y) => {
+          if current.len() > 1 {
+            subpaths.push((std::mem::take(&mut current), false));
+          }
+          current.clear();
+          start = (x, y);
+          current.push(start);
+        }
+        Segment::LineTo(x, y) => {
+          if current.is_empty() {
+            current.push(start);
+          }
+          current.push((x, y));
+        }
+        Segment::QuadTo(cx, cy, x, y) => {
+          // Degree elevation to the equivalent cubic
+          let c1 = (
+            from.0 + 2.0 / 3.0 * (cx - from.0),
+            from.1 + 2.0 / 3.0 * (cy - from.1),
+          );
+          let c2 = (x + 2.0 / 3.0 * (cx - x), y + 2.0 / 3.0 * (cy - y));
+          if current.is_empty() {
+            current.push(start);
+          }
+          flatten_cubic(from, c1, c2, (x, y), &mut current);
+        }
+        Segment::CubicTo(c1x, c1y, c2x, c2y, x, y) => {
+          if current.is_empty() {
+            current.push(start);
+          }
+          flatten_cubic(from, (c1x, c1y), (c2x, c2y), (x, y), &mut current);
+        }
+        Segment::Close => {
+          if current.len() > 1 {
+            subpaths.push((std::mem::take(&mut current), true));
+          }
+          current.clear();
+        }
+      }
+    }
+    if current.len() > 1 {
+      subpaths.push((current, false));
+    }
+    subpaths
+  }
+}
+
+/// A polygon close enough to a circle that its edges stay within
+/// [`FLATNESS`] of it
+fn circle(x: f64, y: f64, radius: f64) -> Vec<(f64, f64)> {
+  let step = 2.0 * (1.0 - FLATNESS / radius).clamp(-1.0, 1.0).acos();
+  let sides = (std::f64::consts::TAU / step).ceil().clamp(8.0, 256.0) as usize;
+  (0..sides)
+    .map(|i| {
+      let angle = std::f64::consts::TAU * i as f64 / sides as f64;
+      (x + radius * angle.cos(), y + radius * angle.sin())
+    })
+    .collect()
+}
+
+/// Appends points along a cubic Bézier curve, excluding its start, close
+/// enough together that the chords stay within [`FLATNESS`] of it
+fn flatten_cubic(
+  p0: (f64, f64),
+  p1: (f64, f64),
+  p2: (f64, f64),
+  p3: (f64, f64),
+  out: &mut Vec<(f64, f64)>,
+) {
+  // The second differences bound how far the curve strays from a chord
+  let dd = |a: (f64, f64), b: (f64, f64), c: (f64, f64)| {
+    (a.0 - 2.0 * b.0 + c.0).hypot(a.1 - 2.0 * b.1 + c.1)
+  };
+  let bend = dd(p0, p1, p2).max(dd(p1, p2, p3));
+  let steps = ((0.75 * bend / FLATNESS).sqrt().ceil() as usize).clamp(1, 1000);
+  for i in 1..=steps {
+    let t = i as f64 / steps as f64;
+    let u = 1.0 - t;
+    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+    out.push((
+      a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+      a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+    ));
+  }
+}
+
+/// How much of each pixel of a `width` by `height` image `path` covers,
+/// from `0` to `255`, usable as a [`Mask`]
+pub fn rasterize(
+  path: &Path,
+  width: usize,
+  height: usize,
+  rule: FillRule,
+) -> Mask {
+  let coverage = coverage(path, width, height, rule);
+  let mut mask = Mask::empty(width, height);
+  for (out, c) in mask.components_mut().iter_mut().zip(coverage) {
+    *out = (c * 255.0).round() as u8;
+  }
+  mask
+}
+
+/// What [`fill`] covers a path with
+#[derive(Clone, Debug, PartialEq)]
+pub enum Paint {
+  Solid(RgbaF32),
+  /// A gradient in the coordinates of the image being drawn on
+  Gradient(Gradient),
+  /// An image, tiled or stamped, also in the image's coordinates
+  Pattern(Pattern),
+}
+
+impl Paint {
+  /// The color at the center of pixel `(x, y)`
+  fn color_at(&self, x: usize, y: usize) -> RgbaF32 {
+    match self {
+      Paint::Solid(color) => *color,
+      Paint::Gradient(gradient) =>
+        gradient.sample(x as f64 + 0.5, y as f64 + 0.5),
+      Paint::Pattern(pattern) => pattern.sample(x as f64 + 0.5, y as f64 + 0.5),
+    }
+  }
+}
+
+impl From<RgbaF32> for Paint {
+  fn from(color: RgbaF32) -> Self { Paint::Solid(color) }
+}
+
+impl From<Rgba8> for Paint {
+  fn from(color: Rgba8) -> Self { Paint::Solid(color.into()) }
+}
+
+impl<T: PixelComponent> From<[T; 4]> for Paint {
+  fn from(color: [T; 4]) -> Self { Paint::Solid(color.into()) }
+}
+
+impl From<Gradient> for Paint {
+  fn from(gradient: Gradient) -> Self { Paint::Gradient(gradient) }
+}
+
+impl From<Pattern> for Paint {
+  fn from(pattern: Pattern) -> Self { Paint::Pattern(pattern) }
+}
+
+/// Fills `path` into `image` with a color, gradient or pattern, blending over
+/// what is there with the paint's alpha times the coverage of each pixel
+pub fn fill<T: PixelComponent>(
+  image: &mut ImageBuffer<T, 4, true>,
+  path: &Path,
+  paint: impl Into<Paint>,
+  rule: FillRule,
+) {
+  let coverage = coverage(path, image.width, image.height, rule);
+  let (width, paint) = (image.width, paint.into());
+  for (i, c) in coverage.into_iter().enumerate() {
+    if c > 0.0 {
+      paint_pixel(image, &paint, i % width, i / width, c);
+    }
+  }
+}
+
+/// Blends `paint` over pixel `(x, y)` of `image` at `coverage`
+pub(crate) fn paint_pixel<T: PixelComponent>(
+  image: &mut ImageBuffer<T, 4, true>,
+  paint: &Paint,
+  x: usize,
+  y: usize,
+  coverage: f64,
+) {
+  let white = image.white();
+  let color = paint.color_at(x, y);
+  let mut source = [color.r, color.g, color.b, color.a].map(f64::from);
+  source[3] *= coverage;
+  let pel = image.get_pixel_mut(x, y);
+  let backdrop =
+    [0, 1, 2, 3].map(|k| pel[k].to_f64().unwrap_or_default() / white);
+  let out = composite(backdrop, source, BlendMode::Normal);
+  for (c, v) in pel.iter_mut().zip(out) {
+    *c = component_from_f64(v * white);
+  }
+}
+
+/// The fraction of each pixel inside the path, by anti-aliased scanline
+fn coverage(
+  path: &Path,
+  width: usize,
+  height: usize,
+  rule: FillRule,
+) -> Vec<f64> {
+  let mut coverage = vec![0.0; width * height];
+  let edges = path.subpaths().into_iter().flat_map(|(polygon, _)| {
+    let n = polygon.len();
+    (0..n)
+      .map(move |i| (polygon[i], polygon[(i + 1) % n]))
+      .collect::<Vec<_>>()
+  });
+  scan(edges, width, height, rule, true, |y, row| {
+    coverage[y * width..(y + 1) * width].copy_from_slice(row);
+  });
+  coverage
+}
+
+/// A recursive-descent reader for SVG path data
+struct SvgParser<'a> {
+  data:     &'a [u8],
+  position: usize,
+}
+
+impl<'a> SvgParser<'a> {
+  fn new(data: &'a str) -> Self {
+    SvgParser {
+      data:     data.as_bytes(),
+      position: 0,
+    }
+  }
+
+  fn error(&self, what: &str) -> Error {
+    Error::Decode(format!(
+      "Invalid SVG path: {what} at byte {}",
+      self.position
+    ))
+  }
+
+  fn skip_separators(&mut self) {
+    while self
+      .data
+      .get(self.position)
+      .is_some_and(|c| c.is_ascii_whitespace() || *c == b',')
+    {
+      self.position += 1;
+    }
+  }
+
+  /// Whether a number follows, so a command repeats implicitly
+  fn number_follows(&mut self) -> bool {
+    self.skip_separators();
+    self
+      .data
+      .get(self.position)
+      .is_some_and(|c| c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.'))
+  }
+
+  fn number(&mut self) -> Result<f64> {
+    self.skip_separators();
+    let start = self.position;
+    let at = |p: usize| self.data.get(p).copied().unwrap_or(0);
+    let mut end = start;
+    if matches!(at(end), b'-' | b'+') {
+      end += 1;
+    }
+    let mut seen_dot = false;
+    while at(end).is_ascii_digit() || (at(end) == b'.' && !seen_dot) {
+      seen_dot |= at(end) == b'.';
+      end += 1;
+    }
+    if matches!(at(end), b'e' | b'E') {
+      let mut exponent = end + 1;
+      if matches!(at(exponent), b'-' | b'+') {
+        exponent += 1;
+      }
+      if at(exponent).is_ascii_digit() {
+        end = exponent;
+        while at(end).is_ascii_digit() {
+          end += 1;
+        }
+      }
+    }
+    let text = std::str::from_utf8(&self.data[start..end]).unwrap_or_default();
+    let value = text.parse().map_err(|_| self.error("expected a number"))?;
+    self.position = end;
+    Ok(value)
+  }
+
+  /// An arc flag, which may be written without a separator after it
+  fn flag(&mut self) -> Result<bool> {
+    self.skip_separators();
+    let flag = match self.data.get(self.position) {
+      Some(b'0') => false,
+      Some(b'1') => true,
+      _ => return Err(self.error("expected an arc flag")),
+    };
+    self.position += 1;
+    Ok(flag)
+  }
+
+  fn parse(mut self) -> Result<Path> {
+    let mut path = Path::new();
+    let mut current = (0.0, 0.0);
+    let mut start = (0.0, 0.0);
+    // The control point to reflect for smooth curves, if the previous
+    // segment was the matching kind of curve
+    let mut last_cubic: Option<(f64, f64)> = None;
+    let mut last_quad: Option<(f64, f64)> = None;
+
+    self.skip_separators();
+    while self.position < self.data.len() {
+      let command = self.data[self.position];
+      if !command.is_ascii_alphabetic() {
+        return Err(self.error("expected a command"));
+      }
+      self.position += 1;
+      let relative = command.is_ascii_lowercase();
+      let origin =
+        |current: (f64, f64)| if relative { current } else { (0.0, 0.0) };
+      let mut first = true;
+      loop {
+        let o = origin(current);
+        let (mut cubic, mut quad) = (None, None);
+        match command.to_ascii_uppercase() {
+          b'M' => {
+            let point = (o.0 + self.number()?, o.1 + self.number()?);
+            // Coordinates after the first pair are implicit lines
+            path = if first {
+              start = point;
+              path.move_to(point.0, point.1)
+            } else {
+              path.line_to(point.0, point.1)
+            };
+            current = point;
+          }
+          b'L' => {
+            current = (o.0 + self.number()?, o.1 + self.number()?);
+            path = path.line_to(current.0, current.1);
+          }
+          b'H' => {
+            current.0 = o.0 + self.number()?;
+            path = path.line_to(current.0, current.1);
+          }
+          b'V' => {
+            current.1 = o.1 + self.number()?;
+            path = path.line_to(current.0, current.1);
+          }
+          b'C' | b'S' => {
+            let c1 = if command.eq_ignore_ascii_case(&b'C') {
+              (o.0 + self.number()?, o.1 + self.number()?)
+            } else {
+              let c = last_cubic.unwrap_or(current);
+              (2.0 * current.0 - c.0, 2.0 * current.1 - c.1)
+            };
+            let c2 = (o.0 + self.number()?, o.1 + self.number()?);
+            let end = (o.0 + self.number()?, o.1 + self.number()?);
+            path = path.cubic_to(c1.0, c1.1, c2.0, c2.1, end.0, end.1);
+            (current, cubic) = (end, Some(c2));
+          }
+          b'Q' | b'T' => {
+            let c = if command.eq_ignore_ascii_case(&b'Q') {
+              (o.0 + self.number()?, o.1 + self.number()?)
+            } else {
+              let c = last_quad.unwrap_or(current);
+              (2.0 * current.0 - c.0, 2.0 * current.1 - c.1)
+            };
+            let end = (o.0 + self.number()?, o.1 + self.number()?);
+            path = path.quad_to(c.0, c.1, end.0, end.1);
+            (current, quad) = (end, Some(c));
+          }
+          b'A' => {
+            let (rx, ry, rotation) =
+              (self.number()?, self.number()?, self.number()?);
+            let (large, sweep) = (self.flag()?, self.flag()?);
+            let end = (o.0 + self.number()?, o.1 + self.number()?);
+            for [c1x, c1y, c2x, c2y, x, y] in
+              arc(current, rx, ry, rotation, large, sweep, end)
+            {
+              path = path.cubic_to(c1x, c1y, c2x, c2y, x, y);
+            }
+            current = end;
+          }
+          b'Z' => {
+            path = path.close();
+            current = start;
+          }
+          _ => return Err(self.error("unknown command")),
+        }
+        (last_cubic, last_quad) = (cubic, quad);
+        first = false;
+        if command.eq_ignore_ascii_case(&b'Z') || !self.number_follows() {
+          break;
+        }
+      }
+      self.skip_separators();
+    }
+    Ok(path)
+  }
+}
+
+/// Converts an SVG elliptical arc from `from` to `to` into cubic Bézier
+/// segments of at most a quarter turn each, following the endpoint to
+/// center conversion in the SVG specification
+fn arc(
+  from: (f64, f64),
+  rx: f64,
+  ry: f64,
+  rotation: f64,
+  large: bool,
+  sweep: bool,
+  to: (f64, f64),
+) -> Vec<[f64; 6]> {
+  let (mut rx, mut ry) = (rx.abs(), ry.abs());
+  if from == to {
+    return Vec::new();
+  }
+  if rx == 0.0 || ry == 0.0 {
+    return vec![[from.0, from.1, to.0, to.1, to.0, to.1]];
+  }
+  let (sin, cos) = rotation.to_radians().sin_cos();
+  let (dx, dy) = ((from.0 - to.0) / 2.0, (from.1 - to.1) / 2.0);
+  let (x1, y1) = (cos * dx + sin * dy, -sin * dx + cos * dy);
+  // Scale up radii too small to reach the end point
+  let lambda = (x1 / rx).powi(2) + (y1 / ry).powi(2);
+  if lambda > 1.0 {
+    rx *= lambda.sqrt();
+    ry *= lambda.sqrt();
+  }
+  let numerator = (rx * ry).powi(2) - (rx * y1).powi(2) - (ry * x1).powi(2);
+  let denominator = (rx * y1).powi(2) + (ry * x1).powi(2);
+  let mut factor = (numerator / denominator).max(0.0).sqrt();
+  if large == sweep {
+    factor = -factor;
+  }
+  let (cx1, cy1) = (factor * rx * y1 / ry, -factor * ry * x1 / rx);
+  let center = (
+    cos * cx1 - sin * cy1 + (from.0 + to.0) / 2.0,
+    sin * cx1 + cos * cy1 + (from.1 + to.1) / 2.0,
+  );
+  let angle = |ux: f64, uy: f64| uy.atan2(ux);
+  let start = angle((x1 - cx1) / rx, (y1 - cy1) / ry);
+  let mut delta = angle((-x1 - cx1) / rx, (-y1 - cy1) / ry) - start;
+  let tau = std::f64::consts::TAU;
+  if sweep && delta < 0.0 {
+    delta += tau;
+  } else if !sweep && delta > 0.0 {
+    delta -= tau;
+  }
+
+  let pieces = (delta.abs() / (tau / 4.0)).ceil().max(1.0) as usize;
+  let step = delta / pieces as f64;
+  let k = 4.0 / 3.0 * (step / 4.0).tan();
+  let point = |t: f64| {
+    let (s, c) = t.sin_cos();
+    let (x, y) = (rx * c, ry * s);
+    (center.0 + cos * x - sin * y, center.1 + sin * x + cos * y)
+  };
+  let tangent = |t: f64| {
+    let (s, c) = t.sin_cos();
+    let (x, y) = (-rx * s, ry * c);
+    (cos * x - sin * y, sin * x + cos * y)
+  };
+  (0..pieces)
+    .map(|i| {
+      let (t0, t1) = (start + i as f64 * step, start + (i + 1) as f64 * step);
+      let (p0, p1) = (point(t0), if i + 1 == pieces { to } else { point(t1) });
+      let (d0, d1) = (tangent(t0), tangent(t1));
+      [
+        p0.0 + k * d0.0,
+        p0.1 + k * d0.1,
+        p1.0 - k * d1.0,
+        p1.1 - k * d1.1,
+        p1.0,
+        p1.1,
+      ]
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn fills_paths_with_antialiasing_and_fill_rules() {
+    let square = Path::new()
+      .move_to(2.0, 2.0)
+      .line_to(6.5, 2.0)
+      .line_to(6.5, 6.0)
+      .line_to(2.0, 6.0)
+      .close();
+    let mask = rasterize(&square, 8, 8, FillRule::NonZero);
+    assert_eq!(mask.get_pixel(3, 3), &[255]);
+    assert_eq!(mask.get_pixel(6, 3), &[128]);
+    assert_eq!(mask.get_pixel(1, 3), &[0]);
+    assert_eq!(mask.get_pixel(3, 6), &[0]);
+
+    // Two nested squares drawn the same way round
+    let nested = Path::parse_svg("M0,0 H10 V10 H0 Z m3 3 h4 v4 h-4 z").unwrap();
+    assert_eq!(nested.segments().len(), 10);
+    let solid = rasterize(&nested, 10, 10, FillRule::NonZero);
+    let ring = rasterize(&nested, 10, 10, FillRule::EvenOdd);
+    assert_eq!(
+      (solid.get_pixel(5, 5), ring.get_pixel(5, 5)),
+      (&[255], &[0])
+    );
+    assert_eq!(ring.get_pixel(1, 5), &[255]);
+
+    // A circle of radius 8 from two arcs
+    let circle =
+      Path::parse_svg("M2 10a8 8 0 1 0 16 0A8 8 0 1 0 2 10z").unwrap();
+    let disk = rasterize(&circle, 20, 20, FillRule::NonZero);
+    let area: f64 = disk
+      .components()
+      .iter()
+      .map(|&v| f64::from(v) / 255.0)
+      .sum();
+    // Flattening inscribes polygons in curves, losing a little area
+    let circle_area = std::f64::consts::PI * 64.0;
+    assert!(area < circle_area && area > 0.985 * circle_area);
+    assert!(Path::parse_svg("M0 0 L1").is_err());
+    assert!(Path::parse_svg("M0 0 X1 1").is_err());
+
+    // A line stroked 2 pixels wide, ending at x = 8 or a pixel past it
+    let line = Path::new().move_to(2.0, 5.0).line_to(8.0, 5.0);
+    let butt =
+      rasterize(&line.stroke(2.0, LineCap::Butt), 10, 10, FillRule::NonZero);
+    let capped = line
+      .stroke(2.0, LineCap::Square)
+      .transform([1.0, 0.0, 0.0, 1.0, 0.0, 1.0]);
+    let capped = rasterize(&capped, 10, 10, FillRule::NonZero);
+    assert_eq!((butt.get_pixel(7, 4), butt.get_pixel(8, 4)), (&[255], &[0]));
+    assert_eq!(
+      (capped.get_pixel(8, 5), capped.get_pixel(9, 5)),
+      (&[255], &[0])
+    );
+
+    let mut image =
+      ImageBuffer::<u8, 4, true>::with_val(&[0, 0, 255, 255], 8, 8);
+    fill(
+      &mut image,
+      &square,
+      Rgba8::RED.with_alpha(128),
+      FillRule::NonZero,
+    );
+    assert_eq!(image.get_pixel(3, 3), &[128, 0, 127, 255]);
+    assert_eq!(image.get_pixel(0, 0), &[0, 0, 255, 255]);
+    let fade = Gradient::linear((2.0, 0.0), (6.5, 0.0))
+      .stop(0.0, Rgba8::WHITE)
+      .stop(1.0, Rgba8::BLACK);
+    fill(&mut image, &square, fade, FillRule::NonZero);
+    assert_eq!(image.get_pixel(2, 3), &[227, 227, 227, 255]);
+    assert_eq!(image.get_pixel(0, 0), &[0, 0, 255, 255]);
+  }
+}
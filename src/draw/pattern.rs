@@ -0,0 +1,203 @@
+//! Images used as paint, tiled across a shape or stamped once, such as the
+//! checkerboard editors show behind transparent pixels.
+
+use crate::{
+  color::RgbaF32,
+  pixel::{PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// An image to [`fill`](crate::draw::path::fill) shapes with, placed on
+/// the image being drawn on by an affine transform and repeated in every
+/// direction unless [`repeat`](Pattern::repeat) is turned off:
+///
+/// ```
+/// use rust_crate_template::{
+///   color::Rgba8,
+///   draw::{path::*, pattern::*},
+///   ImageBuffer,
+/// };
+///
+/// let mut image = ImageBuffer::<u8, 4, true>::empty(64, 64);
+/// let square = Path::parse_svg("M0 0 H64 V64 H0 Z").unwrap();
+/// let checks = Pattern::checkerboard(8, Rgba8::WHITE, Rgba8::GRAY);
+/// fill(&mut image, &square, checks, FillRule::NonZero);
+/// ```
+///
+/// Pixels are sampled nearest-neighbor, keeping hard edges hard.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+  tile:   Tile,
+  /// From pattern to image coordinates, as in
+  /// [`Path::transform`](crate::draw::path::Path::transform)
+  matrix: [f64; 6],
+  repeat: bool,
+}
+
+/// What one repetition of a [`Pattern`] holds
+#[derive(Clone, Debug, PartialEq)]
+enum Tile {
+  /// The colors of an image, row by row
+  Image {
+    width:  usize,
+    height: usize,
+    colors: Vec<RgbaF32>,
+  },
+  /// Two colors in squares of `cell` pixels, computed when sampled so that
+  /// large cells cost no memory
+  Checkerboard { cell: usize, colors: [RgbaF32; 2] },
+}
+
+impl Pattern {
+  /// A pattern of `image`, its top left corner at the origin
+  pub fn new<T: PixelComponent>(image: &ImageBuffer<T, 4, true>) -> Self {
+    let white = image.white();
+    let colors = image
+      .components()
+      .chunks_exact(4)
+      .map(|pel| {
+        let [r, g, b, a] = [0, 1, 2, 3]
+          .map(|k| (pel[k].to_f64().unwrap_or_default() / white) as f32);
+        RgbaF32::new(r, g, b, a)
+      })
+      .collect();
+    Pattern {
+      tile:   Tile::Image {
+        width: image.width,
+        height: image.height,
+        colors,
+      },
+      matrix: [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+      repeat: true,
+    }
+  }
+
+  /// Squares of `cell` pixels alternating between two colors, starting
+  /// with `first` at the origin
+  pub fn checkerboard(
+    cell: usize,
+    first: impl Into<RgbaF32>,
+    second: impl Into<RgbaF32>,
+  ) -> Self {
+    Pattern {
+      tile:   Tile::Checkerboard {
+        cell:   cell.max(1),
+        colors: [first.into(), second.into()],
+      },
+      matrix: [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+      repeat: true,
+    }
+  }
+
+  /// Places the pattern with the affine transform `[a, b, c, d, e, f]`
+  /// from its own coordinates to the image's, replacing any earlier
+  /// transform or offset
+  pub fn transform(mut self, matrix: [f64; 6]) -> Self {
+    self.matrix = matrix;
+    self
+  }
+
+  /// Moves the pattern by `(x, y)` pixels, after its transform
+  pub fn offset(mut self, x: f64, y: f64) -> Self {
+    self.matrix[4] += x;
+    self.matrix[5] += y;
+    self
+  }
+
+  /// Whether the pattern tiles the plane, or is drawn once and transparent
+  /// elsewhere, for stamping
+  pub fn repeat(mut self, repeat: bool) -> Self {
+    self.repeat = repeat;
+    self
+  }
+
+  /// The color of the pattern at a point of the image, in pixels.
+  /// Transforms that cannot be inverted leave it transparent.
+  pub fn sample(&self, x: f64, y: f64) -> RgbaF32 {
+    let [a, b, c, d, e, f] = self.matrix;
+    let determinant = a * d - b * c;
+    if determinant == 0.0 {
+      return RgbaF32::default();
+    }
+    let (x, y) = (x - e, y - f);
+    let u = ((d * x - c * y) / determinant).floor();
+    let v = ((a * y - b * x) / determinant).floor();
+    // One repetition covers two cells of a checkerboard each way
+    let (width, height) = match &self.tile {
+      Tile::Image {
+        width,
+        height,
+        ..
+      } => (*width as f64, *height as f64),
+      Tile::Checkerboard {
+        cell, ..
+      } => (*cell as f64 * 2.0, *cell as f64 * 2.0),
+    };
+    if width == 0.0 || height == 0.0 {
+      return RgbaF32::default();
+    }
+    let (u, v) = if self.repeat {
+      (u.rem_euclid(width), v.rem_euclid(height))
+    } else if (0.0..width).contains(&u) && (0.0..height).contains(&v) {
+      (u, v)
+    } else {
+      return RgbaF32::default();
+    };
+    match &self.tile {
+      Tile::Image {
+        width,
+        colors,
+        ..
+      } => colors[v as usize * width + u as usize],
+      Tile::Checkerboard {
+        cell,
+        colors,
+      } => {
+        let cell = *cell as f64;
+        colors[((u / cell).floor() + (v / cell).floor()) as usize % 2]
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::color::Rgba8;
+
+  #[test]
+  fn patterns_tile_and_stamp_under_transforms() {
+    let checks = Pattern::checkerboard(2, Rgba8::WHITE, Rgba8::BLACK);
+    let at = |p: &Pattern, x: usize, y: usize| {
+      Rgba8::from(p.sample(x as f64 + 0.5, y as f64 + 0.5))
+    };
+    assert_eq!(at(&checks, 1, 1), Rgba8::WHITE);
+    assert_eq!(at(&checks, 2, 1), Rgba8::BLACK);
+    assert_eq!(at(&checks, 4, 5), Rgba8::WHITE);
+    let shifted = checks.clone().offset(1.0, 0.0);
+    assert_eq!(at(&shifted, 2, 1), Rgba8::WHITE);
+    // Doubled in size, the cells are 4 pixels across
+    let scaled = checks.transform([2.0, 0.0, 0.0, 2.0, 0.0, 0.0]);
+    assert_eq!(at(&scaled, 3, 0), Rgba8::WHITE);
+    assert_eq!(at(&scaled, 4, 0), Rgba8::BLACK);
+
+    let mut tile = ImageBuffer::<u8, 4, true>::empty(2, 1);
+    tile
+      .components_mut()
+      .copy_from_slice(&[255, 0, 0, 255, 0, 0, 255, 128]);
+    let stamp = Pattern::new(&tile).offset(3.0, 3.0).repeat(false);
+    assert_eq!(at(&stamp, 3, 3), Rgba8::RED);
+    assert_eq!(at(&stamp, 4, 3), Rgba8::BLUE.with_alpha(128));
+    assert_eq!(at(&stamp, 5, 3), Rgba8::TRANSPARENT);
+    assert_eq!(at(&stamp, 2, 3), Rgba8::TRANSPARENT);
+
+    // Huge cells need no table of colors
+    let huge = Pattern::checkerboard(usize::MAX, Rgba8::WHITE, Rgba8::BLACK);
+    assert_eq!(at(&huge, 1_000_000, 5), Rgba8::WHITE);
+    let once =
+      Pattern::checkerboard(50_000, Rgba8::WHITE, Rgba8::BLACK).repeat(false);
+    assert_eq!(at(&once, 50_000, 0), Rgba8::BLACK);
+    assert_eq!(at(&once, 99_999, 50_000), Rgba8::WHITE);
+    assert_eq!(at(&once, 100_000, 0), Rgba8::TRANSPARENT);
+  }
+}
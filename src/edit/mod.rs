@@ -0,0 +1,276 @@
+//! Undo and redo for interactive editing, the selections edits are confined
+//! to, and retouching brushes.
+//!
+//! A [`History`] records each [`ImageOp`] applied to an image together with
+//! its result. Results are shared behind [`Arc`]s, so handing a snapshot to
+//! a display or export thread costs nothing, and undoing is a matter of
+//! stepping back to an earlier one. The recorded operations can also be
+//! replayed onto another image, such as the full-resolution original of a
+//! preview.
+
+use std::{fmt, sync::Arc};
+
+use crate::{error::Result, ops::ImageOp, Image};
+
+pub mod retouch;
+pub mod selection;
+
+pub use retouch::{clone_stamp, heal};
+pub use selection::Selection;
+
+struct Step {
+  op:     Arc<dyn ImageOp>,
+  /// The image after this step, unless dropped to save memory
+  result: Option<Arc<Image>>,
+}
+
+/// The edits made to an image, with undo and redo
+pub struct History {
+  original:      Arc<Image>,
+  steps:         Vec<Step>,
+  /// Number of steps currently applied; the rest can be redone
+  position:      usize,
+  max_snapshots: usize,
+}
+
+impl fmt::Debug for History {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("History")
+      .field(
+        "steps",
+        &self.steps.iter().map(|s| s.op.name()).collect::<Vec<_>>(),
+      )
+      .field("position", &self.position)
+      .finish()
+  }
+}
+
+impl History {
+  /// Starts a history with nothing applied to `original`
+  pub fn new(original: Image) -> Self {
+    History {
+      original:      Arc::new(original),
+      steps:         Vec::new(),
+      position:      0,
+      max_snapshots: usize::MAX,
+    }
+  }
+
+  /// Keeps the results of at most `count` steps, dropping the oldest first.
+  /// Undoing to a step whose result was dropped recomputes it from the
+  /// nearest earlier one, trading time for memory.
+  pub fn set_max_snapshots(&mut self, count: usize) {
+    self.max_snapshots = count;
+    self.trim();
+  }
+
+  /// The image before any edits
+  pub fn original(&self) -> &Arc<Image> { &self.original }
+
+  /// The image with all applied steps
+  pub fn current(&self) -> &Arc<Image> {
+    self.steps[..self.position]
+      .last()
+      .and_then(|step| step.result.as_ref())
+      .unwrap_or(&self.original)
+  }
+
+  /// The operations currently applied, oldest first
+  pub fn operations(&self) -> impl Iterator<Item = &Arc<dyn ImageOp>> {
+    self.steps[..self.position].iter().map(|step| &step.op)
+  }
+
+  pub fn can_undo(&self) -> bool { self.position > 0 }
+
+  pub fn can_redo(&self) -> bool { self.position < self.steps.len() }
+
+  /// Applies `op` to the current image, discarding any steps that could
+  /// have been redone
+  pub fn apply(&mut self, op: Arc<dyn ImageOp>) -> Result<&Arc<Image>> {
+    let result = op.apply(self.current())?;
+    self.steps.truncate(self.position);
+    self.steps.push(Step {
+      op,
+      result: Some(Arc::new(result)),
+    });
+    self.position += 1;
+    self.trim();
+    Ok(self.current())
+  }
+
+  /// Steps back one edit. Returns `false` if there was nothing to undo.
+  /// If the earlier result has to be recomputed and that fails, the history
+  /// is left where it was.
+  pub fn undo(&mut self) -> Result<bool> {
+    if !self.can_undo() {
+      return Ok(false);
+    }
+    self.restore(self.position - 1)?;
+    self.position -= 1;
+    self.trim();
+    Ok(true)
+  }
+
+  /// Reapplies the last undone edit. Returns `false` if there was nothing
+  /// to redo. If the result has to be recomputed and that fails, the
+  /// history is left where it was.
+  pub fn redo(&mut self) -> Result<bool> {
+    if !self.can_redo() {
+      return Ok(false);
+    }
+    self.restore(self.position + 1)?;
+    self.position += 1;
+    self.trim();
+    Ok(true)
+  }
+
+  /// Applies the current operations, in order, to `image`
+  pub fn replay(&self, image: &Image) -> Result<Image> {
+    self
+      .operations()
+      .try_fold(image.clone(), |image, op| op.apply(&image))
+  }
+
+  /// Recomputes the result of the step that is current at `position` if it
+  /// was dropped
+  fn restore(&mut self, position: usize) -> Result<()> {
+    let Some(target) = position.checked_sub(1) else {
+      return Ok(());
+    };
+    if self.steps[target].result.is_some() {
+      return Ok(());
+    }
+    let start = self.steps[..target]
+      .iter()
+      .rposition(|step| step.result.is_some());
+    let mut image = start
+      .and_then(|i| self.steps[i].result.clone())
+      .unwrap_or_else(|| self.original.clone());
+    for step in &self.steps[start.map_or(0, |i| i + 1)..=target] {
+      image = Arc::new(step.op.apply(&image)?);
+    }
+    self.steps[target].result = Some(image);
+    Ok(())
+  }
+
+  /// Drops the oldest results beyond the snapshot limit, always keeping the
+  /// current one
+  fn trim(&mut self) {
+    let current = self.position.checked_sub(1);
+    let mut kept = 0;
+    for (i, step) in self.steps.iter_mut().enumerate().rev() {
+      if step.result.is_none() {
+        continue;
+      }
+      if Some(i) == current || kept < self.max_snapshots {
+        kept += 1;
+      } else {
+        step.result = None;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use super::*;
+  use crate::{
+    color_space::ColorSpace,
+    error::Error,
+    ImageBuffer,
+    PixelContainer,
+  };
+
+  /// Adds its amount to every component of an 8-bit RGB image
+  struct Add(u8);
+
+  impl ImageOp for Add {
+    fn name(&self) -> &str { "add" }
+
+    fn apply(&self, image: &Image) -> Result<Image> {
+      let rgba = image.to_rgba_f32()?;
+      let mut rgb = ImageBuffer::<u8, 3, false>::empty(rgba.width, rgba.height);
+      for (out, pel) in rgb.iter_pixels_mut().zip(rgba.iter_pixels()) {
+        *out = [0, 1, 2].map(|c| (pel[c] * 255.0).round() as u8 + self.0);
+      }
+      Ok(Image::new_u8(ColorSpace::Rgb(rgb)))
+    }
+  }
+
+  /// Fails once it has been applied `limit` times
+  struct Flaky {
+    applied: AtomicUsize,
+    limit:   usize,
+  }
+
+  impl ImageOp for Flaky {
+    fn name(&self) -> &str { "flaky" }
+
+    fn apply(&self, image: &Image) -> Result<Image> {
+      if self.applied.fetch_add(1, Ordering::SeqCst) >= self.limit {
+        return Err(Error::Unsupported("flaky".to_string()));
+      }
+      Ok(image.clone())
+    }
+  }
+
+  fn blank(size: usize) -> Image {
+    Image::new_u8(ColorSpace::Rgb(ImageBuffer::empty(size, size)))
+  }
+
+  fn value(image: &Image) -> f32 {
+    image.to_rgba_f32().unwrap().get_pixel(0, 0)[0] * 255.0
+  }
+
+  #[test]
+  fn undo_redo_and_replay() {
+    let mut history = History::new(blank(2));
+    history.set_max_snapshots(1);
+    for amount in [1, 2, 4] {
+      history.apply(Arc::new(Add(amount))).unwrap();
+    }
+    assert_eq!(value(history.current()), 7.0);
+
+    // Dropped snapshots are recomputed on the way back
+    assert!(history.undo().unwrap());
+    assert_eq!(value(history.current()), 3.0);
+    assert!(history.undo().unwrap());
+    assert_eq!(value(history.current()), 1.0);
+    assert!(history.redo().unwrap());
+    assert_eq!(value(history.current()), 3.0);
+
+    // A new edit discards what could have been redone
+    let shared = Arc::clone(history.current());
+    history.apply(Arc::new(Add(10))).unwrap();
+    assert!(!history.can_redo());
+    assert_eq!(value(history.current()), 13.0);
+    assert_eq!(value(&shared), 3.0);
+
+    let full = history.replay(&blank(8)).unwrap();
+    assert_eq!((full.width(), value(&full)), (8, 13.0));
+    while history.undo().unwrap() {}
+    assert_eq!(value(history.current()), 0.0);
+  }
+  #[test]
+  fn failed_restore_keeps_position() {
+    let mut history = History::new(blank(2));
+    history.set_max_snapshots(0);
+    history.apply(Arc::new(Add(1))).unwrap();
+    history
+      .apply(Arc::new(Flaky {
+        applied: 0.into(),
+        limit:   1,
+      }))
+      .unwrap();
+    history.apply(Arc::new(Add(2))).unwrap();
+
+    // Undoing recomputes the flaky step, which fails
+    assert!(history.undo().is_err());
+    assert_eq!(value(history.current()), 3.0);
+    assert!(history.can_undo() && !history.can_redo());
+    assert!(history.undo().is_err());
+    assert_eq!(history.operations().count(), 3);
+  }
+}
@@ -0,0 +1,289 @@
+//! Retouching brushes: cloning one part of an image over another, and
+//! healing blemishes with texture from elsewhere in it.
+//!
+//! Both paint a single dab of a [`Brush`] in place, at full pressure, so a
+//! stroke is a dab at each point along it, spaced a fraction of the brush's
+//! radius apart.
+
+use crate::{
+  draw::brush::Brush,
+  error::{Error, Result},
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// Over-relaxation of the iterations fitting healed pixels to their
+/// surroundings, which converge several times faster than plain averaging
+const OMEGA: f64 = 1.8;
+
+/// The pixels of a `width` by `height` image a dab of `brush` at `center`
+/// touches, with the brush's strength at each. Dabs are at full pressure
+/// and flow.
+fn dab(
+  brush: &Brush,
+  center: (f64, f64),
+  width: usize,
+  height: usize,
+) -> Vec<(usize, usize, f64)> {
+  let (xs, ys) = (
+    span(center.0, brush.radius, width),
+    span(center.1, brush.radius, height),
+  );
+  let opacity = brush.opacity.clamp(0.0, 1.0);
+  ys.flat_map(|y| xs.clone().map(move |x| (x, y)))
+    .filter_map(|(x, y)| {
+      let distance =
+        (x as f64 + 0.5 - center.0).hypot(y as f64 + 0.5 - center.1);
+      let weight = brush.shape(distance, brush.radius) * opacity;
+      (weight > 0.0).then_some((x, y, weight))
+    })
+    .collect()
+}
+
+/// Pixels within `reach` of `c` along an axis of `limit` pixels
+fn span(c: f64, reach: f64, limit: usize) -> std::ops::Range<usize> {
+  let low = (c - reach).floor().max(0.0) as usize;
+  let high = ((c + reach).ceil().max(0.0) as usize).min(limit);
+  low..high
+}
+
+fn to_f64<T: PixelComponent, const N: usize>(pel: &[T; N]) -> [f64; N] {
+  pel.map(|c| c.to_f64().unwrap_or_default())
+}
+
+/// Moves `pel` towards `value` by `weight`
+fn paint<T: PixelComponent, const N: usize>(
+  pel: &mut [T; N],
+  value: [f64; N],
+  weight: f64,
+) {
+  for (c, v) in pel.iter_mut().zip(value) {
+    let old = c.to_f64().unwrap_or_default();
+    *c = component_from_f64(old + (v - old) * weight);
+  }
+}
+
+/// Paints a dab of `brush` at `dst_point` with the pixels as far from it as
+/// `src_point` is, as a clone stamp tool does. The source is read before
+/// anything is painted, so the two may overlap; where it falls outside the
+/// image, nothing is painted.
+pub fn clone_stamp<T: PixelComponent, const N: usize, const A: bool>(
+  image: &mut ImageBuffer<T, N, A>,
+  src_point: (f64, f64),
+  dst_point: (f64, f64),
+  brush: &Brush,
+) {
+  let dx = (src_point.0 - dst_point.0).round() as isize;
+  let dy = (src_point.1 - dst_point.1).round() as isize;
+  let (width, height) = (image.width, image.height);
+  let sources: Vec<_> = dab(brush, dst_point, width, height)
+    .into_iter()
+    .filter_map(|(x, y, weight)| {
+      let (sx, sy) = (x as isize + dx, y as isize + dy);
+      let inside =
+        sx >= 0 && sy >= 0 && (sx as usize) < width && (sy as usize) < height;
+      inside.then(|| {
+        (
+          x,
+          y,
+          weight,
+          to_f64(image.get_pixel(sx as usize, sy as usize)),
+        )
+      })
+    })
+    .collect();
+  for (x, y, weight, value) in sources {
+    paint(image.get_pixel_mut(x, y), value, weight);
+  }
+}
+
+/// Heals a dab of `brush` at `center`, as a spot healing brush does: the
+/// texture is taken from the part of the image nearby whose surroundings
+/// best match the dab's, in both their detail and their gradients, and its
+/// shading is then fitted to the dab's surroundings so that no edge shows.
+/// Fails if the image has no room for a source clear of the dab.
+pub fn heal<T: PixelComponent, const N: usize, const A: bool>(
+  image: &mut ImageBuffer<T, N, A>,
+  center: (f64, f64),
+  brush: &Brush,
+) -> Result<()> {
+  let (width, height) = (image.width, image.height);
+  let dab = dab(brush, center, width, height);
+  if dab.is_empty() {
+    return Ok(());
+  }
+  let radius = brush.radius;
+  // The ring around the dab matched against candidate sources
+  let border = (radius / 2.0).max(2.0);
+  let (xs, ys) = (
+    span(center.0, radius + border + 1.0, width),
+    span(center.1, radius + border + 1.0, height),
+  );
+  let ring: Vec<(usize, usize)> = ys
+    .clone()
+    .flat_map(|y| xs.clone().map(move |x| (x, y)))
+    .filter(|&(x, y)| {
+      let distance =
+        (x as f64 + 0.5 - center.0).hypot(y as f64 + 0.5 - center.1);
+      distance >= radius && distance <= radius + border
+    })
+    .collect();
+  let at = |x: usize, y: usize, (ox, oy): (isize, isize)| {
+    to_f64(
+      image.get_pixel((x as isize + ox) as usize, (y as isize + oy) as usize),
+    )
+  };
+  let ring_mean = |offset| {
+    let mut mean = [0.0; N];
+    for &(x, y) in &ring {
+      for (m, v) in mean.iter_mut().zip(at(x, y, offset)) {
+        *m += v / ring.len().max(1) as f64;
+      }
+    }
+    mean
+  };
+  let dst_mean = ring_mean((0, 0));
+
+  // Detail, as differences from the ring's mean, and gradients, as
+  // differences from the next pixel across and down
+  let mismatch = |offset: (isize, isize)| {
+    let src_mean = ring_mean(offset);
+    let mut cost = 0.0;
+    for &(x, y) in &ring {
+      let (dst, src) = (at(x, y, (0, 0)), at(x, y, offset));
+      for k in 0..N {
+        cost += ((dst[k] - dst_mean[k]) - (src[k] - src_mean[k])).powi(2);
+      }
+      for (nx, ny) in [(x + 1, y), (x, y + 1)] {
+        if !xs.contains(&nx) || !ys.contains(&ny) {
+          continue;
+        }
+        let (dst_next, src_next) = (at(nx, ny, (0, 0)), at(nx, ny, offset));
+        for k in 0..N {
+          cost += ((dst_next[k] - dst[k]) - (src_next[k] - src[k])).powi(2);
+        }
+      }
+    }
+    cost
+  };
+  let step = ((radius / 4.0).round() as usize).max(1);
+  let range = (4.0 * radius).ceil().max(8.0) as isize;
+  let fits = |start: usize, end: usize, offset: isize, limit: usize| {
+    start as isize + offset >= 0 && end as isize + offset <= limit as isize
+  };
+  let mut best: Option<((isize, isize), f64)> = None;
+  for oy in (-range..=range).step_by(step) {
+    for ox in (-range..=range).step_by(step) {
+      let clear = (ox as f64).hypot(oy as f64) >= 2.0 * radius;
+      if !clear
+        || !fits(xs.start, xs.end, ox, width)
+        || !fits(ys.start, ys.end, oy, height)
+      {
+        continue;
+      }
+      let cost = mismatch((ox, oy));
+      if best.is_none_or(|(_, lowest)| cost < lowest) {
+        best = Some(((ox, oy), cost));
+      }
+    }
+  }
+  let Some((offset, _)) = best else {
+    return Err(Error::InvalidArgument(format!(
+      "The {width}x{height} image has no room to heal a radius of {radius} \
+       from"
+    )));
+  };
+
+  // Solves for pixels with the source's gradients that meet the
+  // surroundings at the edge of the dab, as in Poisson image editing,
+  // starting from the source shifted to the surroundings' average
+  let src_mean = ring_mean(offset);
+  let mut indices = vec![None; xs.len() * ys.len()];
+  for (i, &(x, y, _)) in dab.iter().enumerate() {
+    indices[(y - ys.start) * xs.len() + x - xs.start] = Some(i);
+  }
+  let index = |x: usize, y: usize| {
+    let inside = xs.contains(&x) && ys.contains(&y);
+    inside
+      .then(|| indices[(y - ys.start) * xs.len() + x - xs.start])
+      .flatten()
+  };
+  let mut values: Vec<[f64; N]> = Vec::with_capacity(dab.len());
+  let mut system = Vec::with_capacity(dab.len());
+  for &(x, y, _) in &dab {
+    let src = at(x, y, offset);
+    let mut guidance = [0.0; N];
+    let mut neighbors = Vec::with_capacity(4);
+    let candidates = [
+      x.checked_sub(1).map(|x| (x, y)),
+      y.checked_sub(1).map(|y| (x, y)),
+      (x + 1 < width).then_some((x + 1, y)),
+      (y + 1 < height).then_some((x, y + 1)),
+    ];
+    for (nx, ny) in candidates.into_iter().flatten() {
+      let src_next = at(nx, ny, offset);
+      for k in 0..N {
+        guidance[k] += src[k] - src_next[k];
+      }
+      neighbors.push(match index(nx, ny) {
+        Some(j) => (Some(j), [0.0; N]),
+        None => (None, at(nx, ny, (0, 0))),
+      });
+    }
+    values.push(std::array::from_fn(|k| src[k] - src_mean[k] + dst_mean[k]));
+    system.push((neighbors, guidance));
+  }
+  let iterations = (8.0 * radius).ceil().max(32.0) as usize;
+  for _ in 0..iterations {
+    for (i, (neighbors, guidance)) in system.iter().enumerate() {
+      let mut sum = *guidance;
+      for (free, fixed) in neighbors {
+        let value = free.map_or(*fixed, |j| values[j]);
+        for k in 0..N {
+          sum[k] += value[k];
+        }
+      }
+      for k in 0..N {
+        let target = sum[k] / neighbors.len() as f64;
+        values[i][k] += OMEGA * (target - values[i][k]);
+      }
+    }
+  }
+  for (&(x, y, weight), value) in dab.iter().zip(values) {
+    paint(image.get_pixel_mut(x, y), value, weight);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn stamps_and_heals_in_place() {
+    let mut image = ImageBuffer::<u8, 3, false>::empty(64, 32)
+      .map_indexed(&mut |x, y, _| [(x * 3 + y) as u8; 3]);
+    *image.get_pixel_mut(10, 10) = [255, 0, 0];
+    let hard = Brush {
+      hardness: 1.0,
+      ..Brush::new(2.0)
+    };
+    clone_stamp(&mut image, (10.5, 10.5), (40.5, 20.5), &hard);
+    assert_eq!(image.get_pixel(40, 20), &[255, 0, 0]);
+    assert_eq!(image.get_pixel(41, 20), &[(33 + 10) as u8; 3]);
+    assert_eq!(image.get_pixel(43, 20), &[(43 * 3 + 20) as u8; 3]);
+
+    // A blemish on a smooth ramp heals back into the ramp
+    for (x, y) in [(32, 16), (33, 16), (32, 17), (31, 15)] {
+      *image.get_pixel_mut(x, y) = [0, 0, 0];
+    }
+    heal(&mut image, (32.5, 16.5), &Brush::new(5.0)).unwrap();
+    for (x, y) in [(32, 16), (33, 16), (32, 17), (31, 15)] {
+      let expected = (x * 3 + y) as i32;
+      let healed = image.get_pixel(x, y)[0] as i32;
+      assert!((healed - expected).abs() <= 2, "{healed} at ({x}, {y})");
+    }
+    let mut small = ImageBuffer::<u8, 3, false>::empty(12, 12);
+    assert!(heal(&mut small, (6.0, 6.0), &Brush::new(5.0)).is_err());
+  }
+}
@@ -0,0 +1,350 @@
+//! Selections: the part of an image an edit applies to, built from shapes
+//! and combined like sets.
+//!
+//! A [`Selection`] holds how much of each pixel is selected as an 8-bit
+//! [`Mask`], so shapes keep anti-aliased edges and masks from
+//! [`ops::mask`](crate::ops::mask) or matting can be selected as they are.
+//! The set operations act on these amounts: union takes the larger,
+//! intersection the smaller, and subtraction what is left of the first
+//! after removing the second.
+
+use std::collections::HashMap;
+
+use crate::{
+  draw::path::{rasterize, FillRule, Path},
+  error::Result,
+  ops::{
+    mask::{Mask, THRESHOLD},
+    sprites::Rect,
+  },
+  pixel::PixelContainer,
+  video::check_dimensions,
+};
+
+/// Part of a `width` by `height` image, selected to some degree per pixel
+#[derive(Clone, Debug)]
+pub struct Selection {
+  mask: Mask,
+}
+
+impl Selection {
+  /// Nothing selected
+  pub fn none(width: usize, height: usize) -> Self {
+    Selection {
+      mask: Mask::empty(width, height),
+    }
+  }
+
+  /// The whole image selected
+  pub fn all(width: usize, height: usize) -> Self {
+    Selection {
+      mask: Mask::with_val(&[255], width, height),
+    }
+  }
+
+  /// Selects the pixels of `mask`, to the degree of its values
+  pub fn from_mask(mask: Mask) -> Self {
+    Selection {
+      mask,
+    }
+  }
+
+  /// The pixels inside `rect`, cut off at the edges of the image
+  pub fn rect(width: usize, height: usize, rect: Rect) -> Self {
+    let mut selection = Selection::none(width, height);
+    for y in rect.y..(rect.y + rect.height).min(height) {
+      for x in rect.x..(rect.x + rect.width).min(width) {
+        selection.mask.get_pixel_mut(x, y)[0] = 255;
+      }
+    }
+    selection
+  }
+
+  /// The ellipse around `center` with radii `radii`, in pixels, with
+  /// anti-aliased edges
+  pub fn ellipse(
+    width: usize,
+    height: usize,
+    center: (f64, f64),
+    radii: (f64, f64),
+  ) -> Self {
+    let path = Path::new().ellipse(center, radii);
+    Selection::from_path(width, height, &path)
+  }
+
+  /// The polygon through `points`, as drawn with a lasso tool. Where the
+  /// outline crosses itself, overlapping loops alternate in and out.
+  pub fn lasso(width: usize, height: usize, points: &[(f64, f64)]) -> Self {
+    let mut path = Path::new();
+    for (i, &(x, y)) in points.iter().enumerate() {
+      path = if i == 0 {
+        path.move_to(x, y)
+      } else {
+        path.line_to(x, y)
+      };
+    }
+    Selection::from_path(width, height, &path.close())
+  }
+
+  fn from_path(width: usize, height: usize, path: &Path) -> Self {
+    Selection {
+      mask: rasterize(path, width, height, FillRule::EvenOdd),
+    }
+  }
+
+  pub fn width(&self) -> usize { self.mask.width }
+
+  pub fn height(&self) -> usize { self.mask.height }
+
+  /// How much of each pixel is selected
+  pub fn as_mask(&self) -> &Mask { &self.mask }
+
+  pub fn into_mask(self) -> Mask { self.mask }
+
+  /// Whether pixel `(x, y)` counts as selected, being at least half
+  /// selected. Pixels outside the image are not.
+  pub fn contains(&self, x: usize, y: usize) -> bool {
+    x < self.width()
+      && y < self.height()
+      && self.mask.get_pixel(x, y)[0] >= THRESHOLD
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.mask.components().iter().all(|&v| v == 0)
+  }
+
+  /// The smallest rectangle holding every pixel selected at all, if any
+  pub fn bounds(&self) -> Option<Rect> {
+    let width = self.width();
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (i, _) in self
+      .mask
+      .components()
+      .iter()
+      .enumerate()
+      .filter(|(_, &v)| v > 0)
+    {
+      let (x, y) = (i % width, i / width);
+      bounds = Some(match bounds {
+        None => (x, y, x, y),
+        Some((left, top, right, bottom)) =>
+          (left.min(x), top.min(y), right.max(x), bottom.max(y)),
+      });
+    }
+    bounds.map(|(left, top, right, bottom)| {
+      Rect {
+        x:      left,
+        y:      top,
+        width:  right - left + 1,
+        height: bottom - top + 1,
+      }
+    })
+  }
+
+  fn combine(
+    &self,
+    other: &Selection,
+    f: impl Fn(u8, u8) -> u8,
+  ) -> Result<Self> {
+    check_dimensions((self.width(), self.height()), &other.mask)?;
+    let mut mask = self.mask.clone();
+    for (a, &b) in mask
+      .components_mut()
+      .iter_mut()
+      .zip(other.mask.components())
+    {
+      *a = f(*a, b);
+    }
+    Ok(Selection {
+      mask,
+    })
+  }
+
+  /// What either selection selects. Fails unless both are the same size,
+  /// as do the other set operations.
+  pub fn union(&self, other: &Selection) -> Result<Self> {
+    self.combine(other, u8::max)
+  }
+
+  /// What both selections select
+  pub fn intersect(&self, other: &Selection) -> Result<Self> {
+    self.combine(other, u8::min)
+  }
+
+  /// What this selection selects and `other` does not
+  pub fn subtract(&self, other: &Selection) -> Result<Self> {
+    self.combine(other, |a, b| {
+      ((u16::from(a) * u16::from(255 - b) + 127) / 255) as u8
+    })
+  }
+
+  /// Everything this selection leaves out
+  pub fn invert(&self) -> Self {
+    let mut mask = self.mask.clone();
+    mask.components_mut().iter_mut().for_each(|v| *v = 255 - *v);
+    Selection {
+      mask,
+    }
+  }
+
+  /// The selection moved by the affine transform `[a, b, c, d, e, f]`, as in
+  /// [`Path::transform`], on a canvas of the same size. Amounts are
+  /// interpolated bilinearly; parts moved off the canvas are lost.
+  pub fn transform(&self, matrix: [f64; 6]) -> Self {
+    let [a, b, c, d, e, f] = matrix;
+    let determinant = a * d - b * c;
+    let (width, height) = (self.width(), self.height());
+    if determinant == 0.0 {
+      return Selection::none(width, height);
+    }
+    let value = |x: isize, y: isize| {
+      if x < 0 || y < 0 || x >= width as isize || y >= height as isize {
+        0.0
+      } else {
+        f64::from(self.mask.get_pixel(x as usize, y as usize)[0])
+      }
+    };
+    let mask = Mask::empty(width, height).map_indexed(&mut |x, y, _| {
+      // The pixel center mapped back into the original selection
+      let (x, y) = (x as f64 + 0.5 - e, y as f64 + 0.5 - f);
+      let u = (d * x - c * y) / determinant - 0.5;
+      let v = (a * y - b * x) / determinant - 0.5;
+      let (x0, y0) = (u.floor(), v.floor());
+      let (fx, fy) = (u - x0, v - y0);
+      let (x0, y0) = (x0 as isize, y0 as isize);
+      let top = value(x0, y0) * (1.0 - fx) + value(x0 + 1, y0) * fx;
+      let bottom = value(x0, y0 + 1) * (1.0 - fx) + value(x0 + 1, y0 + 1) * fx;
+      [(top * (1.0 - fy) + bottom * fy).round() as u8]
+    });
+    Selection {
+      mask,
+    }
+  }
+
+  /// The edges between selected and unselected pixels, for drawing
+  /// "marching ants". Each loop is closed, runs along pixel edges through
+  /// the corners given, and goes clockwise around what is selected, so
+  /// holes run counterclockwise. Pixels count as selected as in
+  /// [`contains`](Selection::contains).
+  pub fn outline(&self) -> Vec<Vec<(usize, usize)>> {
+    let (width, height) = (self.width(), self.height());
+    let inside = |x: isize, y: isize| {
+      x >= 0 && y >= 0 && self.contains(x as usize, y as usize)
+    };
+    // Directed edges keyed by their start corner, with the selection on
+    // their right in image coordinates
+    let mut edges: HashMap<(usize, usize), Vec<(usize, usize)>> =
+      HashMap::new();
+    let mut count = 0;
+    for y in 0..height {
+      for x in 0..width {
+        if !self.contains(x, y) {
+          continue;
+        }
+        let (ix, iy) = (x as isize, y as isize);
+        let sides = [
+          (!inside(ix, iy - 1), (x, y), (x + 1, y)),
+          (!inside(ix + 1, iy), (x + 1, y), (x + 1, y + 1)),
+          (!inside(ix, iy + 1), (x + 1, y + 1), (x, y + 1)),
+          (!inside(ix - 1, iy), (x, y + 1), (x, y)),
+        ];
+        for (open, from, to) in sides {
+          if open {
+            edges.entry(from).or_default().push(to);
+            count += 1;
+          }
+        }
+      }
+    }
+
+    let mut loops = Vec::new();
+    while count > 0 {
+      let Some(&start) = edges.keys().min() else {
+        break;
+      };
+      let mut corners = vec![start];
+      let mut at = start;
+      while let Some(next) = edges.get_mut(&at).and_then(|ends| ends.pop()) {
+        if edges.get(&at).is_some_and(|ends| ends.is_empty()) {
+          edges.remove(&at);
+        }
+        count -= 1;
+        at = next;
+        if at == start {
+          break;
+        }
+        corners.push(at);
+      }
+      loops.push(simplify(corners));
+    }
+    loops
+  }
+}
+
+/// Drops the corners of a closed loop that lie on a straight run
+fn simplify(corners: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+  let n = corners.len();
+  (0..n)
+    .filter(|&i| {
+      let (a, b, c) =
+        (corners[(i + n - 1) % n], corners[i], corners[(i + 1) % n]);
+      let turn = (b.0 as isize - a.0 as isize) * (c.1 as isize - b.1 as isize)
+        - (b.1 as isize - a.1 as isize) * (c.0 as isize - b.0 as isize);
+      turn != 0
+    })
+    .map(|i| corners[i])
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn selections_combine_transform_and_outline() {
+    let square = |x, y, size| {
+      Rect {
+        x,
+        y,
+        width: size,
+        height: size,
+      }
+    };
+    let a = Selection::rect(8, 8, square(0, 0, 4));
+    let b = Selection::rect(8, 8, square(2, 2, 4));
+    let union = a.union(&b).unwrap();
+    assert!(
+      union.contains(0, 0) && union.contains(5, 5) && !union.contains(5, 0)
+    );
+    let both = a.intersect(&b).unwrap();
+    assert_eq!(both.bounds(), Some(square(2, 2, 2)));
+    let notch = a.subtract(&b).unwrap();
+    assert!(notch.contains(1, 1) && !notch.contains(3, 3));
+    assert!(a.invert().contains(7, 7) && !a.invert().contains(0, 0));
+    assert!(a.union(&Selection::none(4, 4)).is_err());
+
+    let moved = a.transform([1.0, 0.0, 0.0, 1.0, 3.0, 1.0]);
+    assert_eq!(moved.bounds(), Some(square(3, 1, 4)));
+
+    let disk = Selection::ellipse(20, 20, (10.0, 10.0), (6.0, 6.0));
+    assert!(
+      disk.contains(10, 10) && disk.contains(15, 10) && !disk.contains(16, 10)
+    );
+    let triangle =
+      Selection::lasso(10, 10, &[(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)]);
+    assert!(triangle.contains(2, 2) && !triangle.contains(8, 8));
+
+    // An L shape has six corners; a ring has an outer and an inner loop
+    assert_eq!(
+      notch.outline(),
+      vec![vec![(0, 0), (4, 0), (4, 2), (2, 2), (2, 4), (0, 4)]]
+    );
+    let ring = Selection::rect(6, 6, square(0, 0, 6))
+      .subtract(&Selection::rect(6, 6, square(2, 2, 2)))
+      .unwrap();
+    let outline = ring.outline();
+    assert_eq!(outline.len(), 2);
+    assert!(outline.contains(&vec![(2, 2), (2, 4), (4, 4), (4, 2)]));
+    assert!(Selection::none(3, 3).outline().is_empty());
+  }
+}
@@ -0,0 +1,76 @@
+use std::fmt;
+
+/// Errors returned by the fallible operations in this crate
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+  /// The input could not be decoded as the expected format
+  Decode(String),
+  /// A buffer's dimensions do not match what the operation requires
+  DimensionMismatch {
+    expected: (usize, usize),
+    actual:   (usize, usize),
+  },
+  /// Channel names are unknown or do not match a buffer's channels
+  Channel(String),
+  /// The format or operation is not supported, or its feature is disabled
+  Unsupported(String),
+  /// An argument is out of range or inconsistent
+  InvalidArgument(String),
+  /// The input exceeds a configured decoding limit
+  LimitExceeded(String),
+  /// The operation was stopped through its
+  /// [`Progress`](crate::progress::Progress) handle
+  Cancelled,
+  /// Reading or writing the underlying stream failed
+  Io(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Decode(msg) => write!(f, "Decode error: {msg}"),
+      Error::DimensionMismatch {
+        expected,
+        actual,
+      } =>
+        write!(
+          f,
+          "Dimension mismatch: expected {}x{}, got {}x{}",
+          expected.0, expected.1, actual.0, actual.1
+        ),
+      Error::Channel(msg) => write!(f, "Channel error: {msg}"),
+      Error::Unsupported(msg) => write!(f, "Unsupported: {msg}"),
+      Error::InvalidArgument(msg) => write!(f, "Invalid argument: {msg}"),
+      Error::LimitExceeded(msg) => write!(f, "Limit exceeded: {msg}"),
+      Error::Cancelled => write!(f, "Operation cancelled"),
+      Error::Io(e) => write!(f, "I/O error: {e}"),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<std::io::Error> for Error {
+  fn from(e: std::io::Error) -> Self { Error::Io(e) }
+}
+
+impl From<image::ImageError> for Error {
+  fn from(e: image::ImageError) -> Self {
+    match e {
+      image::ImageError::IoError(e) => Error::Io(e),
+      image::ImageError::Unsupported(e) => Error::Unsupported(e.to_string()),
+      image::ImageError::Limits(e) => Error::LimitExceeded(e.to_string()),
+      e => Error::Decode(e.to_string()),
+    }
+  }
+}
@@ -0,0 +1,320 @@
+//! Color gradients with any number of stops, along a line, out from a
+//! center, or around one, for backgrounds and for filling shapes with
+//! [`draw::path::fill`](crate::draw::path::fill).
+
+use crate::{
+  color::RgbaF32,
+  color_space::{linear_to_srgb, srgb_to_linear},
+  pixel::PixelComponent,
+  ImageBuffer,
+  PixelContainer,
+};
+
+/// The color space colors are mixed in between stops
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+  /// Mixes the encoded sRGB values, as CSS and most design tools do by
+  /// default. Midpoints between saturated colors come out dark.
+  #[default]
+  Srgb,
+  /// Mixes linear light, as physically blending the colors would
+  LinearRgb,
+  /// Mixes in the perceptually uniform Oklab space, which keeps midpoints
+  /// bright and changes evenly to the eye
+  Oklab,
+}
+
+/// Where the colors of a gradient run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+  /// From the first stop at `start` to the last at `end`, constant across
+  /// the line between them
+  Linear {
+    start: (f64, f64),
+    end:   (f64, f64),
+  },
+  /// From the first stop at `center` to the last at `radius` pixels out
+  Radial { center: (f64, f64), radius: f64 },
+  /// Once around `center`, clockwise on screen from `angle` degrees, where
+  /// zero points right
+  Conic { center: (f64, f64), angle: f64 },
+}
+
+/// How a gradient continues past its first and last stops
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Extend {
+  /// Keeps the color of the nearest end
+  #[default]
+  Pad,
+  /// Starts over from the first stop
+  Repeat,
+  /// Runs back and forth between the ends
+  Reflect,
+}
+
+/// A gradient between any number of color stops, built like a
+/// [`Recipe`](crate::recipe::Recipe):
+///
+/// ```
+/// use rust_crate_template::{color::Rgba8, generate::gradient::*};
+///
+/// let sunset = Gradient::linear((0.0, 0.0), (0.0, 100.0))
+///   .stop(0.0, Rgba8::parse("midnightblue").unwrap())
+///   .stop(0.6, Rgba8::parse("orchid").unwrap())
+///   .stop(1.0, Rgba8::ORANGE)
+///   .interpolation(Interpolation::Oklab);
+/// let image = sunset.render::<u8, 3, false>(200, 100);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradient {
+  shape:         Shape,
+  /// Offsets and colors, in order of offset
+  stops:         Vec<(f64, RgbaF32)>,
+  interpolation: Interpolation,
+  extend:        Extend,
+}
+
+impl Gradient {
+  pub fn new(shape: Shape) -> Self {
+    Gradient {
+      shape,
+      stops: Vec::new(),
+      interpolation: Interpolation::default(),
+      extend: Extend::default(),
+    }
+  }
+
+  pub fn linear(start: (f64, f64), end: (f64, f64)) -> Self {
+    Gradient::new(Shape::Linear {
+      start,
+      end,
+    })
+  }
+
+  pub fn radial(center: (f64, f64), radius: f64) -> Self {
+    Gradient::new(Shape::Radial {
+      center,
+      radius,
+    })
+  }
+
+  pub fn conic(center: (f64, f64), angle: f64) -> Self {
+    Gradient::new(Shape::Conic {
+      center,
+      angle,
+    })
+  }
+
+  /// Adds a color at `offset`, where `0` is the start of the gradient and
+  /// `1` its end. Stops at the same offset make a hard edge, the later one
+  /// taking over.
+  pub fn stop(mut self, offset: f64, color: impl Into<RgbaF32>) -> Self {
+    let at = self.stops.partition_point(|(o, _)| *o <= offset);
+    self.stops.insert(at, (offset, color.into()));
+    self
+  }
+
+  pub fn interpolation(mut self, interpolation: Interpolation) -> Self {
+    self.interpolation = interpolation;
+    self
+  }
+
+  pub fn extend(mut self, extend: Extend) -> Self {
+    self.extend = extend;
+    self
+  }
+
+  pub fn shape(&self) -> Shape { self.shape }
+
+  pub fn stops(&self) -> &[(f64, RgbaF32)] { &self.stops }
+
+  /// The color at `offset` along the gradient. Gradients without stops are
+  /// transparent.
+  pub fn color_at(&self, offset: f64) -> RgbaF32 {
+    let (Some(first), Some(last)) = (self.stops.first(), self.stops.last())
+    else {
+      return RgbaF32::default();
+    };
+    let (from, to) = (first.0, last.0);
+    let span = to - from;
+    let t = if span <= 0.0 {
+      offset
+    } else {
+      let phase = (offset - from) / span;
+      from
+        + span
+          * match self.extend {
+            Extend::Pad => phase,
+            Extend::Repeat => phase.rem_euclid(1.0),
+            Extend::Reflect => 1.0 - (phase.rem_euclid(2.0) - 1.0).abs(),
+          }
+    };
+    let next = self.stops.partition_point(|(o, _)| *o <= t);
+    if next == 0 {
+      return first.1;
+    }
+    if next == self.stops.len() {
+      return last.1;
+    }
+    let ((o0, c0), (o1, c1)) = (self.stops[next - 1], self.stops[next]);
+    self.mix(c0, c1, (t - o0) / (o1 - o0))
+  }
+
+  /// The color of the gradient at a point, in pixels
+  pub fn sample(&self, x: f64, y: f64) -> RgbaF32 {
+    let offset = match self.shape {
+      Shape::Linear {
+        start,
+        end,
+      } => {
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+        let length = dx * dx + dy * dy;
+        if length == 0.0 {
+          0.0
+        } else {
+          ((x - start.0) * dx + (y - start.1) * dy) / length
+        }
+      }
+      Shape::Radial {
+        center,
+        radius,
+      } => {
+        let distance = (x - center.0).hypot(y - center.1);
+        if radius <= 0.0 {
+          1.0
+        } else {
+          distance / radius
+        }
+      }
+      Shape::Conic {
+        center,
+        angle,
+      } => {
+        let turn = (y - center.1).atan2(x - center.0).to_degrees() - angle;
+        turn.rem_euclid(360.0) / 360.0
+      }
+    };
+    self.color_at(offset)
+  }
+
+  /// Renders the gradient into a new image, sampling pixel centers. Colors
+  /// become pixels as [`RgbaF32::to_pixel`] describes.
+  pub fn render<T: PixelComponent, const N: usize, const A: bool>(
+    &self,
+    width: usize,
+    height: usize,
+  ) -> ImageBuffer<T, N, A> {
+    let format = ImageBuffer::<T, N, A>::empty(0, 0);
+    ImageBuffer::empty(width, height).map_indexed(&mut |x, y, _| {
+      self
+        .sample(x as f64 + 0.5, y as f64 + 0.5)
+        .to_pixel(&format)
+    })
+  }
+
+  /// Mixes two colors with premultiplied alpha, so that fading to
+  /// transparent does not pass through the transparent color's hue
+  fn mix(&self, a: RgbaF32, b: RgbaF32, t: f64) -> RgbaF32 {
+    let to_space = |c: RgbaF32| {
+      let rgb = [c.r, c.g, c.b].map(f64::from);
+      let alpha = f64::from(c.a);
+      let v = match self.interpolation {
+        Interpolation::Srgb => rgb,
+        Interpolation::LinearRgb => rgb.map(srgb_to_linear),
+        Interpolation::Oklab => oklab(rgb.map(srgb_to_linear)),
+      };
+      [v[0] * alpha, v[1] * alpha, v[2] * alpha, alpha]
+    };
+    let (pa, pb) = (to_space(a), to_space(b));
+    let m: Vec<f64> = (0..4).map(|k| pa[k] + (pb[k] - pa[k]) * t).collect();
+    let alpha = m[3];
+    if alpha <= 0.0 {
+      return RgbaF32::default();
+    }
+    let v = [m[0] / alpha, m[1] / alpha, m[2] / alpha];
+    let rgb = match self.interpolation {
+      Interpolation::Srgb => v,
+      Interpolation::LinearRgb => v.map(linear_to_srgb),
+      Interpolation::Oklab => linear_from_oklab(v).map(linear_to_srgb),
+    };
+    let [r, g, b] = rgb.map(|c| c.clamp(0.0, 1.0) as f32);
+    RgbaF32::new(r, g, b, alpha as f32)
+  }
+}
+
+/// Linear sRGB to Oklab, after Björn Ottosson
+fn oklab([r, g, b]: [f64; 3]) -> [f64; 3] {
+  let l =
+    (0.412_221_470_8 * r + 0.536_332_536_3 * g + 0.051_445_992_9 * b).cbrt();
+  let m =
+    (0.211_903_498_2 * r + 0.680_699_545_1 * g + 0.107_396_956_6 * b).cbrt();
+  let s =
+    (0.088_302_461_9 * r + 0.281_718_837_6 * g + 0.629_978_700_5 * b).cbrt();
+  [
+    0.210_454_255_3 * l + 0.793_617_785_0 * m - 0.004_072_046_8 * s,
+    1.977_998_495_1 * l - 2.428_592_205_0 * m + 0.450_593_709_9 * s,
+    0.025_904_037_1 * l + 0.782_771_766_2 * m - 0.808_675_766_0 * s,
+  ]
+}
+
+fn linear_from_oklab([lightness, a, b]: [f64; 3]) -> [f64; 3] {
+  let l = (lightness + 0.396_337_777_4 * a + 0.215_803_757_3 * b).powi(3);
+  let m = (lightness - 0.105_561_345_8 * a - 0.063_854_172_8 * b).powi(3);
+  let s = (lightness - 0.089_484_177_5 * a - 1.291_485_548_0 * b).powi(3);
+  [
+    4.076_741_662_1 * l - 3.307_711_591_3 * m + 0.230_969_929_2 * s,
+    -1.268_438_004_6 * l + 2.609_757_401_1 * m - 0.341_319_396_5 * s,
+    -0.004_196_086_3 * l - 0.703_418_614_7 * m + 1.707_614_701_0 * s,
+  ]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::color::Rgba8;
+
+  #[test]
+  fn gradients_mix_stops_in_each_space() {
+    let two = |interpolation| {
+      Gradient::linear((0.0, 0.0), (10.0, 0.0))
+        .stop(0.0, Rgba8::RED)
+        .stop(1.0, Rgba8::rgb(0, 255, 0))
+        .interpolation(interpolation)
+    };
+    let midpoint =
+      |interpolation| Rgba8::from(two(interpolation).sample(5.0, 3.0));
+    assert_eq!(midpoint(Interpolation::Srgb), Rgba8::rgb(128, 128, 0));
+    assert_eq!(midpoint(Interpolation::LinearRgb), Rgba8::rgb(188, 188, 0));
+    let oklab = midpoint(Interpolation::Oklab);
+    assert!(oklab.r > 128 && oklab.g > 128 && oklab.b < 30);
+
+    // Three stops, with a hard edge and fading out premultiplied
+    let stepped = Gradient::radial((0.0, 0.0), 4.0)
+      .stop(1.0, Rgba8::BLUE.with_alpha(0))
+      .stop(0.0, Rgba8::WHITE)
+      .stop(0.5, Rgba8::WHITE)
+      .stop(0.5, Rgba8::BLUE);
+    assert_eq!(stepped.stops().len(), 4);
+    assert_eq!(Rgba8::from(stepped.sample(1.9, 0.0)), Rgba8::WHITE);
+    assert_eq!(Rgba8::from(stepped.sample(2.0, 0.0)), Rgba8::BLUE);
+    assert_eq!(
+      Rgba8::from(stepped.sample(3.0, 0.0)),
+      Rgba8::BLUE.with_alpha(128)
+    );
+    assert_eq!(stepped.sample(9.0, 0.0).a, 0.0);
+
+    let reflected = two(Interpolation::Srgb).extend(Extend::Reflect);
+    assert_eq!(reflected.sample(15.0, 0.0), reflected.sample(5.0, 0.0));
+    assert_eq!(reflected.sample(-2.0, 0.0), reflected.sample(2.0, 0.0));
+    let repeated = two(Interpolation::Srgb).extend(Extend::Repeat);
+    assert_eq!(repeated.sample(12.0, 0.0), repeated.sample(2.0, 0.0));
+
+    // Clockwise from the top, so a quarter of the way round on the right
+    let conic = Gradient::conic((5.0, 5.0), -90.0)
+      .stop(0.0, Rgba8::BLACK)
+      .stop(1.0, Rgba8::WHITE);
+    assert_eq!(Rgba8::from(conic.sample(10.0, 5.0)), Rgba8::rgb(64, 64, 64));
+    let image = conic.render::<u8, 1, false>(10, 10);
+    assert!(image.get_pixel(5, 0)[0] < 20 && image.get_pixel(4, 0)[0] > 235);
+  }
+}
@@ -0,0 +1,69 @@
+//! Masks for graduated and vignette-style local adjustments, in the format
+//! of [`ops::mask`](crate::ops::mask).
+
+use crate::{ops::mask::Mask, pixel::PixelContainer};
+
+/// Fills a mask with `f(x, y)` at pixel centers, clamped between 0 and 1
+fn from_fn(width: usize, height: usize, f: impl Fn(f64, f64) -> f64) -> Mask {
+  Mask::empty(width, height).map_indexed(&mut |x, y, _| {
+    let v = f(x as f64 + 0.5, y as f64 + 0.5).clamp(0.0, 1.0);
+    [(v * 255.0).round() as u8]
+  })
+}
+
+/// A linear gradient, fully selected on the side of `start` and fading to
+/// unselected at `end`, like a graduated filter. Points are in pixels.
+pub fn linear_gradient(
+  width: usize,
+  height: usize,
+  start: (f64, f64),
+  end: (f64, f64),
+) -> Mask {
+  let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+  let length = dx * dx + dy * dy;
+  from_fn(width, height, |x, y| {
+    if length == 0.0 {
+      return 1.0;
+    }
+    1.0 - ((x - start.0) * dx + (y - start.1) * dy) / length
+  })
+}
+
+/// A radial gradient, fully selected within `inner` pixels of `center` and
+/// fading to unselected at `outer`
+pub fn radial_gradient(
+  width: usize,
+  height: usize,
+  center: (f64, f64),
+  inner: f64,
+  outer: f64,
+) -> Mask {
+  from_fn(width, height, |x, y| {
+    let distance = (x - center.0).hypot(y - center.1);
+    if distance <= inner {
+      1.0
+    } else if outer <= inner {
+      0.0
+    } else {
+      (outer - distance) / (outer - inner)
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn gradients_fade_between_their_ends() {
+    let linear = linear_gradient(10, 1, (0.0, 0.0), (10.0, 0.0));
+    assert_eq!(linear.get_pixel(0, 0), &[242]);
+    assert_eq!(linear.get_pixel(9, 0), &[13]);
+
+    let radial = radial_gradient(9, 9, (4.5, 4.5), 1.0, 4.0);
+    assert_eq!(radial.get_pixel(4, 4), &[255]);
+    assert_eq!(radial.get_pixel(0, 4), &[0]);
+    let ring = radial.get_pixel(2, 4)[0];
+    assert!(ring > 0 && ring < 255);
+  }
+}
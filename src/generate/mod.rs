@@ -0,0 +1,6 @@
+//! Synthesis of image content, such as gradients, masks, test patterns and
+//! noise.
+
+pub mod gradient;
+pub mod mask;
+pub mod noise;
@@ -0,0 +1,132 @@
+//! Synthetic noise, for building denoising test sets and simulating sensors.
+//!
+//! Every function adds noise to an existing buffer in place, drawing from the
+//! supplied random number generator; seed it (for example with
+//! `rand::rngs::StdRng::seed_from_u64`, or through a deterministic
+//! [`ExecutionContext`](crate::compute::ExecutionContext)) for reproducible
+//! results. Noise
+//! strengths are fractions of white, and alpha is left untouched.
+
+use num_traits::{ToPrimitive, Zero};
+use rand::Rng;
+use rand_distr::{Distribution, Normal, Poisson};
+
+use crate::pixel::{component_from_f64, PixelComponent, PixelContainer};
+
+/// Replaces every color component with `f(value)`, working between 0 and 1
+fn per_component<C: PixelContainer>(
+  image: &mut C,
+  mut f: impl FnMut(f64) -> f64,
+) {
+  let white = C::Component::WHITE.to_f64().unwrap_or(1.0);
+  for pel in image.components_mut().chunks_exact_mut(C::NUM_COMPONENTS) {
+    for value in pel.iter_mut().take(C::NUM_NONALPHA_COMPONENTS) {
+      let v = f(value.to_f64().unwrap_or_default() / white);
+      *value = component_from_f64(v * white);
+    }
+  }
+}
+
+/// Adds zero-mean Gaussian noise with standard deviation `sigma`, like the
+/// read noise of a camera sensor
+pub fn gaussian<C: PixelContainer, R: Rng + ?Sized>(
+  image: &mut C,
+  sigma: f64,
+  rng: &mut R,
+) {
+  let Ok(normal) = Normal::new(0.0, sigma.abs()) else {
+    return;
+  };
+  per_component(image, |v| v + normal.sample(rng));
+}
+
+/// Simulates photon shot noise: each component is treated as an average of
+/// `scale` photons at white, and replaced by a Poisson-distributed count.
+/// Lower scales mean fewer photons and stronger noise.
+pub fn poisson<C: PixelContainer, R: Rng + ?Sized>(
+  image: &mut C,
+  scale: f64,
+  rng: &mut R,
+) {
+  if scale <= 0.0 {
+    return;
+  }
+  per_component(image, |v| {
+    let photons = v * scale;
+    match Poisson::new(photons) {
+      Ok(poisson) => poisson.sample(rng) / scale,
+      // Zero or negative light yields no photons
+      Err(_) => 0.0,
+    }
+  });
+}
+
+/// Sets each pixel, with probability `p`, to black or white with equal odds,
+/// like dead and stuck pixels or transmission errors
+pub fn salt_pepper<C: PixelContainer, R: Rng + ?Sized>(
+  image: &mut C,
+  p: f64,
+  rng: &mut R,
+) {
+  let p = p.clamp(0.0, 1.0);
+  for pel in image.components_mut().chunks_exact_mut(C::NUM_COMPONENTS) {
+    if !rng.gen_bool(p) {
+      continue;
+    }
+    let value = if rng.gen_bool(0.5) {
+      C::Component::WHITE
+    } else {
+      C::Component::zero()
+    };
+    for c in pel.iter_mut().take(C::NUM_NONALPHA_COMPONENTS) {
+      *c = value;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::*;
+  use crate::ImageBuffer;
+
+  fn stats(values: impl Iterator<Item = f64> + Clone) -> (f64, f64) {
+    let n = values.clone().count() as f64;
+    let mean = values.clone().sum::<f64>() / n;
+    let var = values.map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    (mean, var.sqrt())
+  }
+
+  #[test]
+  fn noise_matches_requested_strength() {
+    let gray = ImageBuffer::<f32, 1, false>::empty(64, 64).map(&mut |_| [0.5]);
+
+    let mut noisy = gray.clone();
+    gaussian(&mut noisy, 0.1, &mut StdRng::seed_from_u64(7));
+    let (mean, std) = stats(noisy.components().iter().map(|&v| f64::from(v)));
+    assert!((mean - 0.5).abs() < 0.01 && (std - 0.1).abs() < 0.01);
+    let mut again = gray.clone();
+    gaussian(&mut again, 0.1, &mut StdRng::seed_from_u64(7));
+    assert_eq!(again.components(), noisy.components());
+
+    let mut shot = gray.clone();
+    poisson(&mut shot, 100.0, &mut StdRng::seed_from_u64(7));
+    let (mean, std) = stats(shot.components().iter().map(|&v| f64::from(v)));
+    // 50 photons on average: standard deviation sqrt(50) / 100
+    assert!((mean - 0.5).abs() < 0.01 && (std - 0.0707).abs() < 0.01);
+  }
+
+  #[test]
+  fn salt_pepper_sets_extremes() {
+    let mut image = ImageBuffer::<u8, 4, true>::empty(50, 50)
+      .map(&mut |_| [128, 128, 128, 200]);
+    salt_pepper(&mut image, 0.2, &mut StdRng::seed_from_u64(1));
+    let changed: Vec<_> = image.iter_pixels().filter(|p| p[0] != 128).collect();
+    let share = changed.len() as f64 / 2500.0;
+    assert!((share - 0.2).abs() < 0.03, "{share}");
+    assert!(changed
+      .iter()
+      .all(|p| (p[..3] == [0; 3] || p[..3] == [255; 3]) && p[3] == 200));
+  }
+}
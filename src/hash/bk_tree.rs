@@ -0,0 +1,90 @@
+use super::ImageHash;
+
+struct Node<T> {
+  hash:     ImageHash,
+  value:    T,
+  /// Subtrees keyed by their distance from this node's hash
+  children: Vec<(u32, Node<T>)>,
+}
+
+/// A BK-tree: values indexed by [`ImageHash`] for finding those within a
+/// given distance of a hash without comparing against all of them. By the
+/// triangle inequality, a subtree at distance `d` from a node can only hold
+/// matches for a query at distance `q` from it when `d` is within the
+/// tolerance of `q`, so the other subtrees are skipped.
+pub struct BkTree<T> {
+  root: Option<Node<T>>,
+  len:  usize,
+}
+
+impl<T> Default for BkTree<T> {
+  fn default() -> Self {
+    BkTree {
+      root: None,
+      len:  0,
+    }
+  }
+}
+
+impl<T> BkTree<T> {
+  pub fn new() -> Self { Self::default() }
+
+  pub fn len(&self) -> usize { self.len }
+
+  pub fn is_empty(&self) -> bool { self.len == 0 }
+
+  /// Adds `value` under `hash`. Equal hashes are kept as separate entries.
+  pub fn insert(&mut self, hash: ImageHash, value: T) {
+    self.len += 1;
+    let mut node = match &mut self.root {
+      Some(node) => node,
+      None => {
+        self.root = Some(Node {
+          hash,
+          value,
+          children: Vec::new(),
+        });
+        return;
+      }
+    };
+    loop {
+      let d = node.hash.distance(hash);
+      match node.children.iter().position(|(cd, _)| *cd == d) {
+        Some(i) => node = &mut node.children[i].1,
+        None => {
+          node.children.push((
+            d,
+            Node {
+              hash,
+              value,
+              children: Vec::new(),
+            },
+          ));
+          return;
+        }
+      }
+    }
+  }
+
+  /// Entries whose hash is at most `tolerance` bits from `hash`, in no
+  /// particular order
+  pub fn find(&self, hash: ImageHash, tolerance: u32) -> Vec<(ImageHash, &T)> {
+    let mut found = Vec::new();
+    let mut pending: Vec<&Node<T>> = self.root.iter().collect();
+    while let Some(node) = pending.pop() {
+      let d = node.hash.distance(hash);
+      if d <= tolerance {
+        found.push((node.hash, &node.value));
+      }
+      let range = d.saturating_sub(tolerance)..=d + tolerance;
+      pending.extend(
+        node
+          .children
+          .iter()
+          .filter(|(cd, _)| range.contains(cd))
+          .map(|(_, child)| child),
+      );
+    }
+    found
+  }
+}
@@ -0,0 +1,157 @@
+//! Perceptual hashing, for finding images that look alike even after
+//! resizing, recompression or small edits.
+//!
+//! Hashes are compared by the number of bits in which they differ. Copies
+//! of the same picture are usually within 10 bits of each other, and
+//! unrelated pictures around 32 apart.
+
+mod bk_tree;
+
+pub use bk_tree::BkTree;
+use rayon::prelude::*;
+
+use crate::{
+  ops::{analysis::luminance_plane, transform::resize},
+  pixel::PixelContainer,
+};
+
+/// Side of the downscaled luminance that is transformed
+const SAMPLE_SIZE: usize = 32;
+/// Side of the block of lowest frequencies that makes up the hash
+const HASH_SIZE: usize = 8;
+
+/// A 64-bit perceptual hash
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageHash(pub u64);
+
+impl ImageHash {
+  /// Number of bits in which the hashes differ
+  pub fn distance(self, other: ImageHash) -> u32 {
+    (self.0 ^ other.0).count_ones()
+  }
+}
+
+/// Hashes `image` by its structure at low frequencies, as in pHash: the
+/// luminance is shrunk to 32x32 pixels and transformed with a DCT, and each
+/// bit records whether one of the 8x8 lowest frequencies is above their
+/// median. Color, size and fine detail do not affect the hash.
+pub fn perceptual_hash<C: PixelContainer>(image: &C) -> ImageHash {
+  let small = resize(&luminance_plane(image), SAMPLE_SIZE, SAMPLE_SIZE);
+  let samples: Vec<f64> = small.iter_pixels().map(|p| p[0] as f64).collect();
+  let basis: Vec<Vec<f64>> = (0..HASH_SIZE)
+    .map(|k| {
+      (0..SAMPLE_SIZE)
+        .map(|n| {
+          let angle = std::f64::consts::PI * (n as f64 + 0.5) * k as f64
+            / SAMPLE_SIZE as f64;
+          angle.cos()
+        })
+        .collect()
+    })
+    .collect();
+  // Rows first, then the columns of the result
+  let rows: Vec<[f64; HASH_SIZE]> = samples
+    .chunks_exact(SAMPLE_SIZE)
+    .map(|row| {
+      std::array::from_fn(|k| {
+        row.iter().zip(&basis[k]).map(|(a, b)| a * b).sum()
+      })
+    })
+    .collect();
+  let mut coefficients = Vec::with_capacity(HASH_SIZE * HASH_SIZE);
+  for column_basis in &basis {
+    for u in 0..HASH_SIZE {
+      let sum = rows
+        .iter()
+        .zip(column_basis)
+        .map(|(row, b)| row[u] * b)
+        .sum();
+      coefficients.push(sum);
+    }
+  }
+  // The DC term only reflects overall brightness
+  let mut sorted = coefficients[1..].to_vec();
+  sorted.sort_by(f64::total_cmp);
+  let median = sorted[sorted.len() / 2];
+  let bits = coefficients
+    .iter()
+    .enumerate()
+    .filter(|&(_, &c)| c > median)
+    .fold(0u64, |bits, (i, _)| bits | 1 << i);
+  ImageHash(bits)
+}
+
+/// Groups `images` that are near-duplicates of each other, meaning their
+/// [`perceptual_hash`]es differ in at most `threshold` bits. Images are
+/// linked transitively, so a group can contain images further apart than
+/// `threshold` if others lie between them.
+///
+/// Returns the indices of each group of two or more images, in ascending
+/// order, with the groups ordered by their first index. Hashing runs in
+/// parallel, and neighbors are found with a [`BkTree`] rather than by
+/// comparing every pair.
+pub fn cluster<C: PixelContainer + Sync>(
+  images: &[C],
+  threshold: u32,
+) -> Vec<Vec<usize>> {
+  let hashes: Vec<ImageHash> = images.par_iter().map(perceptual_hash).collect();
+  let mut tree = BkTree::new();
+  for (i, &hash) in hashes.iter().enumerate() {
+    tree.insert(hash, i);
+  }
+
+  // Union-find over the images, joining each to its neighbors
+  let mut parent: Vec<usize> = (0..images.len()).collect();
+  fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+      parent[i] = parent[parent[i]];
+      i = parent[i];
+    }
+    i
+  }
+  for (i, &hash) in hashes.iter().enumerate() {
+    for (_, &j) in tree.find(hash, threshold) {
+      let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+      parent[a.max(b)] = a.min(b);
+    }
+  }
+
+  let mut groups: Vec<Vec<usize>> = vec![Vec::new(); images.len()];
+  for i in 0..images.len() {
+    let r = root(&mut parent, i);
+    groups[r].push(i);
+  }
+  groups.retain(|group| group.len() > 1);
+  groups
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ops::blur::gaussian_blur, ImageBuffer};
+
+  fn pattern(seed: usize) -> ImageBuffer<u8, 3, false> {
+    ImageBuffer::<u8, 3, false>::empty(64, 48).map_indexed(&mut |x, y, _| {
+      let (fx, fy) = (x as f64 * 0.05 * seed as f64, y as f64 * 0.08);
+      let v = (127.0 + 120.0 * (fx + fy * seed as f64).sin() * fy.cos()) as u8;
+      [v, v / 2, 255 - v]
+    })
+  }
+
+  #[test]
+  fn near_duplicates_are_grouped() {
+    let a = pattern(1);
+    let b = pattern(2);
+    let images = vec![
+      a.clone(),
+      b.clone(),
+      gaussian_blur(&a, 1.0),
+      pattern(3),
+      resize(&gaussian_blur(&b, 0.7), 64, 48),
+    ];
+    let hashes: Vec<ImageHash> = images.iter().map(perceptual_hash).collect();
+    assert!(hashes[0].distance(hashes[2]) <= 8);
+    assert!(hashes[0].distance(hashes[1]) > 16);
+    assert_eq!(cluster(&images, 10), vec![vec![0, 2], vec![1, 4]]);
+  }
+}
@@ -1,5 +1,12 @@
-use crate::color_space::ColorSpace;
-use crate::pixel::PixelComponent;
+use crate::color_space::{
+    cmyk_to_rgb, gamut::GamutMapping, primaries::convert_buffer, ColorSpace, Hdr10Metadata,
+    RgbSpace,
+};
+use crate::error::{Error, Result};
+use crate::image_buffer::{ImageBuffer, Origin};
+use crate::io::{DateTime, LatLon};
+use crate::limits::Limits;
+use crate::pixel::{PixelComponent, PixelContainer};
 
 pub trait ImageFactory: PixelComponent {
     fn create(data: ColorSpace<Self>) -> Image;
@@ -35,6 +42,7 @@ impl ImageFactory for f64 {
     }
 }
 
+#[derive(Clone)]
 pub struct ImageImpl<T: PixelComponent> {
     pub(crate) data: ColorSpace<T>,
 }
@@ -46,6 +54,7 @@ impl<T: PixelComponent> ImageImpl<T> {
             ColorSpace::Rgb(buf) => buf.width,
             ColorSpace::Hsv(buf) => buf.width,
             ColorSpace::Cielab(buf) => buf.width,
+            ColorSpace::Cmyk(buf) => buf.width,
         }
     }
 
@@ -55,10 +64,63 @@ impl<T: PixelComponent> ImageImpl<T> {
             ColorSpace::Rgb(buf) => buf.height,
             ColorSpace::Hsv(buf) => buf.height,
             ColorSpace::Cielab(buf) => buf.height,
+            ColorSpace::Cmyk(buf) => buf.height,
+        }
+    }
+
+    pub fn color_space_name(&self) -> &'static str {
+        match &self.data {
+            ColorSpace::Rgba(_) => "RGBA",
+            ColorSpace::Rgb(_) => "RGB",
+            ColorSpace::Hsv(_) => "HSV",
+            ColorSpace::Cielab(_) => "CIELAB",
+            ColorSpace::Cmyk(_) => "CMYK",
+        }
+    }
+
+    pub fn to_rgba_f32(&self) -> Result<ImageBuffer<f32, 4, true>> {
+        let white = T::WHITE.to_f64().unwrap_or(1.0);
+        let unit = |v: T| (v.to_f64().unwrap_or_default() / white) as f32;
+        match &self.data {
+            ColorSpace::Rgba(buf) => Ok(buf.as_other_scaled()),
+            ColorSpace::Rgb(buf) => {
+                let mut rgba = ImageBuffer::try_empty(buf.width, buf.height, &Limits::none())?;
+                for (out, pel) in rgba.iter_pixels_mut().zip(buf.iter_pixels()) {
+                    *out = [unit(pel[0]), unit(pel[1]), unit(pel[2]), 1.0];
+                }
+                Ok(rgba)
+            }
+            ColorSpace::Cmyk(buf) => {
+                let mut rgba = ImageBuffer::try_empty(buf.width, buf.height, &Limits::none())?;
+                for (out, pel) in rgba.iter_pixels_mut().zip(buf.iter_pixels()) {
+                    let [r, g, b] = cmyk_to_rgb::<T, f32>(pel);
+                    *out = [r, g, b, 1.0];
+                }
+                Ok(rgba)
+            }
+            ColorSpace::Hsv(_) | ColorSpace::Cielab(_) => {
+                let rgb = self.data.to_rgb();
+                let mut rgba = ImageBuffer::try_empty(rgb.width, rgb.height, &Limits::none())?;
+                for (out, pel) in rgba.iter_pixels_mut().zip(rgb.iter_pixels()) {
+                    *out = [unit(pel[0]), unit(pel[1]), unit(pel[2]), 1.0];
+                }
+                Ok(rgba)
+            }
         }
     }
 }
 
+/// Type of the components an [`Image`] stores
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ComponentType {
+    U8,
+    U16,
+    U32,
+    F32,
+    F64,
+}
+
+#[derive(Clone)]
 pub enum Implementation {
     U8(ImageImpl<u8>),
     U16(ImageImpl<u16>),
@@ -88,9 +150,35 @@ impl Implementation {
         }
     }
 
+    pub fn color_space_name(&self) -> &'static str {
+        match self {
+            Implementation::U8(imp) => imp.color_space_name(),
+            Implementation::U16(imp) => imp.color_space_name(),
+            Implementation::U32(imp) => imp.color_space_name(),
+            Implementation::F32(imp) => imp.color_space_name(),
+            Implementation::F64(imp) => imp.color_space_name(),
+        }
+    }
+
+    pub fn to_rgba_f32(&self) -> Result<ImageBuffer<f32, 4, true>> {
+        match self {
+            Implementation::U8(imp) => imp.to_rgba_f32(),
+            Implementation::U16(imp) => imp.to_rgba_f32(),
+            Implementation::U32(imp) => imp.to_rgba_f32(),
+            Implementation::F32(imp) => imp.to_rgba_f32(),
+            Implementation::F64(imp) => imp.to_rgba_f32(),
+        }
+    }
 }
+#[derive(Clone)]
 pub struct Image {
-    pub(crate) imp: Implementation
+    pub(crate) imp: Implementation,
+    pub(crate) source_origin: Origin,
+    pub(crate) rgb_space: RgbSpace,
+    pub(crate) hdr_metadata: Option<Hdr10Metadata>,
+    pub(crate) dpi: Option<(f64, f64)>,
+    pub(crate) gps: Option<LatLon>,
+    pub(crate) taken_at: Option<DateTime>,
 }
 
 
@@ -101,27 +189,57 @@ impl Image {
 
     pub fn new_u8(data: ColorSpace<u8>) -> Self {
         Self {
-            imp: Implementation::U8(ImageImpl { data })
+            imp: Implementation::U8(ImageImpl { data }),
+            source_origin: Origin::TopLeft,
+            rgb_space: RgbSpace::SRGB,
+            hdr_metadata: None,
+            dpi: None,
+            gps: None,
+            taken_at: None,
         }
     }
     pub fn new_u16(data: ColorSpace<u16>) -> Self {
         Self {
-            imp: Implementation::U16(ImageImpl { data })
+            imp: Implementation::U16(ImageImpl { data }),
+            source_origin: Origin::TopLeft,
+            rgb_space: RgbSpace::SRGB,
+            hdr_metadata: None,
+            dpi: None,
+            gps: None,
+            taken_at: None,
         }
     }
     pub fn new_u32(data: ColorSpace<u32>) -> Self {
         Self {
-            imp: Implementation::U32(ImageImpl { data })
+            imp: Implementation::U32(ImageImpl { data }),
+            source_origin: Origin::TopLeft,
+            rgb_space: RgbSpace::SRGB,
+            hdr_metadata: None,
+            dpi: None,
+            gps: None,
+            taken_at: None,
         }
     }
     pub fn new_f32(data: ColorSpace<f32>) -> Self {
         Self {
-            imp: Implementation::F32(ImageImpl { data })
+            imp: Implementation::F32(ImageImpl { data }),
+            source_origin: Origin::TopLeft,
+            rgb_space: RgbSpace::SRGB,
+            hdr_metadata: None,
+            dpi: None,
+            gps: None,
+            taken_at: None,
         }
     }
     pub fn new_f64(data: ColorSpace<f64>) -> Self {
         Self {
-            imp: Implementation::F64(ImageImpl { data })
+            imp: Implementation::F64(ImageImpl { data }),
+            source_origin: Origin::TopLeft,
+            rgb_space: RgbSpace::SRGB,
+            hdr_metadata: None,
+            dpi: None,
+            gps: None,
+            taken_at: None,
         }
     }
 
@@ -132,6 +250,169 @@ impl Image {
     pub fn height(&self) -> usize {
         self.imp.height()
     }
+
+    /// Row order of the data this image was decoded from. The pixels
+    /// themselves are always stored top-down.
+    pub fn source_origin(&self) -> Origin {
+        self.source_origin
+    }
+
+    pub fn component_type(&self) -> ComponentType {
+        match self.imp {
+            Implementation::U8(_) => ComponentType::U8,
+            Implementation::U16(_) => ComponentType::U16,
+            Implementation::U32(_) => ComponentType::U32,
+            Implementation::F32(_) => ComponentType::F32,
+            Implementation::F64(_) => ComponentType::F64,
+        }
+    }
+
+    /// Name of the color space the pixels are stored in, such as `"RGB"` or
+    /// `"CMYK"`
+    pub fn color_space_name(&self) -> &'static str {
+        self.imp.color_space_name()
+    }
+
+    /// The RGB space the pixel values are in, sRGB unless assigned
+    pub fn rgb_space(&self) -> RgbSpace {
+        self.rgb_space
+    }
+
+    /// Declares which RGB space the pixel values are in, without changing
+    /// them. Use this when the values are known to be in another space than
+    /// recorded, such as a Display P3 photo whose profile was lost.
+    pub fn assign_space(&mut self, space: RgbSpace) {
+        self.rgb_space = space;
+    }
+
+    /// Light levels of HDR content, if known
+    pub fn hdr_metadata(&self) -> Option<Hdr10Metadata> {
+        self.hdr_metadata
+    }
+
+    /// Records the light levels of HDR content, as measured with
+    /// [`Hdr10Metadata::measure`] or read from a video stream
+    pub fn set_hdr_metadata(&mut self, metadata: Option<Hdr10Metadata>) {
+        self.hdr_metadata = metadata;
+    }
+
+    /// Print resolution in dots per inch, horizontally and vertically, if
+    /// known
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        self.dpi
+    }
+
+    pub fn set_dpi(&mut self, dpi: Option<(f64, f64)>) {
+        self.dpi = dpi;
+    }
+
+    /// Width and height in millimeters when printed at [`dpi`](Self::dpi)
+    pub fn physical_size_mm(&self) -> Option<(f64, f64)> {
+        let (x, y) = self.dpi?;
+        Some((self.width() as f64 / x * 25.4, self.height() as f64 / y * 25.4))
+    }
+
+    /// Where the photo was taken, from its Exif data, if known
+    pub fn gps(&self) -> Option<LatLon> {
+        self.gps
+    }
+
+    /// Records where the photo was taken, to be written to PNG and JPEG
+    /// files along with [`taken_at`](Self::taken_at)
+    pub fn set_gps(&mut self, gps: Option<LatLon>) {
+        self.gps = gps;
+    }
+
+    /// When the photo was taken, from its Exif data, if known
+    pub fn taken_at(&self) -> Option<DateTime> {
+        self.taken_at
+    }
+
+    pub fn set_taken_at(&mut self, taken_at: Option<DateTime>) {
+        self.taken_at = taken_at;
+    }
+
+    /// Copies everything but the pixels from `other`: row order, RGB space,
+    /// HDR and print metadata, and where and when the photo was taken. Operations that build a new image from an
+    /// old one call this so that nothing is lost on the way.
+    pub(crate) fn inherit_metadata(&mut self, other: &Image) {
+        self.source_origin = other.source_origin;
+        self.rgb_space = other.rgb_space;
+        self.hdr_metadata = other.hdr_metadata;
+        self.dpi = other.dpi;
+        self.gps = other.gps;
+        self.taken_at = other.taken_at;
+    }
+
+    /// Converts the pixel values to `space`, so that they keep showing the
+    /// same colors, mapping colors outside its gamut as `mapping` says.
+    /// Alpha is kept. Fails for images not stored as RGB or RGBA.
+    pub fn convert_space(&self, space: RgbSpace, mapping: GamutMapping) -> Result<Image> {
+        fn convert<T: ImageFactory>(
+            data: &ColorSpace<T>,
+            from: &RgbSpace,
+            to: &RgbSpace,
+            mapping: GamutMapping,
+        ) -> Result<Image> {
+            match data {
+                ColorSpace::Rgb(buf) => Ok(Image::new(ColorSpace::Rgb(convert_buffer(
+                    buf, from, to, mapping,
+                )))),
+                ColorSpace::Rgba(buf) => Ok(Image::new(ColorSpace::Rgba(convert_buffer(
+                    buf, from, to, mapping,
+                )))),
+                _ => Err(Error::Unsupported(
+                    "Only RGB and RGBA images can change RGB space".to_string(),
+                )),
+            }
+        }
+        let from = &self.rgb_space;
+        let mut image = match &self.imp {
+            Implementation::U8(imp) => convert(&imp.data, from, &space, mapping),
+            Implementation::U16(imp) => convert(&imp.data, from, &space, mapping),
+            Implementation::U32(imp) => convert(&imp.data, from, &space, mapping),
+            Implementation::F32(imp) => convert(&imp.data, from, &space, mapping),
+            Implementation::F64(imp) => convert(&imp.data, from, &space, mapping),
+        }?;
+        image.inherit_metadata(self);
+        image.rgb_space = space;
+        Ok(image)
+    }
+
+    /// Copies the pixels into an RGBA buffer with components between 0 and
+    /// 1, opaque if the image has no alpha channel. The values stay in the
+    /// image's [`rgb_space`](Self::rgb_space).
+    pub fn to_rgba_f32(&self) -> Result<ImageBuffer<f32, 4, true>> {
+        self.imp.to_rgba_f32()
+    }
+
+    /// The reverse of [`to_rgba_f32`](Self::to_rgba_f32): builds an image from
+    /// RGBA components between 0 and 1 with the component type of `like`,
+    /// keeping the alpha channel only if `like` has one. Images processed as
+    /// RGBA can so be handed back in the format they came in.
+    pub fn from_rgba_f32_like(rgba: ImageBuffer<f32, 4, true>, like: &Image) -> Image {
+        let alpha = like.color_space_name() == "RGBA";
+        let mut image = match like.component_type() {
+            ComponentType::U8 => from_rgba::<u8>(rgba, alpha),
+            ComponentType::U16 => from_rgba::<u16>(rgba, alpha),
+            ComponentType::U32 => from_rgba::<u32>(rgba, alpha),
+            ComponentType::F32 => from_rgba::<f32>(rgba, alpha),
+            ComponentType::F64 => from_rgba::<f64>(rgba, alpha),
+        };
+        image.inherit_metadata(like);
+        image
+    }
+}
+
+fn from_rgba<T: ImageFactory>(rgba: ImageBuffer<f32, 4, true>, alpha: bool) -> Image {
+    if alpha {
+        return Image::new(ColorSpace::Rgba(rgba.as_other_scaled::<T>()));
+    }
+    let mut rgb = ImageBuffer::<f32, 3, false>::empty(rgba.width, rgba.height);
+    for (out, pel) in rgb.iter_pixels_mut().zip(rgba.iter_pixels()) {
+        *out = [pel[0], pel[1], pel[2]];
+    }
+    Image::new(ColorSpace::Rgb(rgb.as_other_scaled::<T>()))
 }
 
 #[cfg(test)]
@@ -145,17 +426,26 @@ use super::*;
   fn new_rgba_u8() {
     let img = Image::new::<u8>(ColorSpace::Rgba(ImageBuffer::empty(4, 4)));
     match img.imp {
-      Implementation::U8(cs) => {
-        match cs {
-            ImageImpl { data: ColorSpace::Rgba(buf) } => {
-                assert_eq!(buf.width, 4);
-                assert_eq!(buf.height, 4);
-            },
-            _ => panic!("Wrong type"),
-        }
+      Implementation::U8(ImageImpl {
+        data: ColorSpace::Rgba(buf),
+      }) => {
+        assert_eq!(buf.width, 4);
+        assert_eq!(buf.height, 4);
       }
       _ => panic!("Wrong type"),
     }
   }
 
+  #[test]
+  fn assign_and_convert_space() {
+    let mut img = Image::new_u8(ColorSpace::Rgb(ImageBuffer::with_val(&[255, 0, 0], 2, 2)));
+    img.assign_space(RgbSpace::DISPLAY_P3);
+    let srgb = img.convert_space(RgbSpace::SRGB, GamutMapping::Clip).unwrap();
+    assert_eq!(srgb.rgb_space(), RgbSpace::SRGB);
+    let back = srgb.convert_space(RgbSpace::DISPLAY_P3, GamutMapping::Clip).unwrap();
+    let rgba = back.to_rgba_f32().unwrap();
+    // Red in P3 is more saturated than sRGB can hold, so it comes back duller
+    assert!(rgba.components()[1] > 0.1);
+  }
+
 }
\ No newline at end of file
@@ -1,8 +1,69 @@
-use std::slice::{ArrayChunks, ArrayChunksMut};
+use std::slice::{Iter, IterMut};
 
 use num_traits::NumCast;
 
-use crate::pixel::{PixelComponent, PixelContainer};
+use crate::{
+  channel_semantics::ChannelSemantics,
+  error::{Error, Result as CrateResult},
+  image_buffer_mut::ImageBufferMut,
+  limits::Limits,
+  pixel::{
+    component_from_f64,
+    fill_repeating,
+    is_integer,
+    PixelComponent,
+    PixelContainer,
+  },
+};
+
+/// How neighborhood operations sample coordinates that fall outside the image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BorderMode {
+  /// Repeats the nearest edge pixel (`aaa|abc|ccc`)
+  #[default]
+  Clamp,
+  /// Mirrors the image at its edges, including the edge pixel (`cba|abc|cba`)
+  Reflect,
+  /// Wraps around to the opposite edge (`abc|abc|abc`)
+  Wrap,
+}
+
+impl BorderMode {
+  /// Maps a possibly out-of-range coordinate onto `0..len`
+  ///
+  /// `len` must be nonzero.
+  pub fn resolve(self, i: isize, len: usize) -> usize {
+    let len = len as isize;
+    let resolved = match self {
+      BorderMode::Clamp => i.clamp(0, len - 1),
+      BorderMode::Reflect => {
+        let period = 2 * len;
+        let i = i.rem_euclid(period);
+        if i < len {
+          i
+        } else {
+          period - 1 - i
+        }
+      }
+      BorderMode::Wrap => i.rem_euclid(len),
+    };
+    resolved as usize
+  }
+}
+
+/// Which corner the first row of pixel data starts at
+///
+/// Buffers in this crate are always top-down. Bottom-up data, such as OpenGL
+/// readbacks or most BMP files, is converted with
+/// [`PixelContainer::flip_to_top_down`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Origin {
+  /// Rows are stored top to bottom
+  #[default]
+  TopLeft,
+  /// Rows are stored bottom to top
+  BottomLeft,
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct ImageBuffer<
@@ -13,6 +74,19 @@ pub struct ImageBuffer<
   data:       Vec<Component>,
   pub width:  usize,
   pub height: usize,
+  bit_depth:  Option<u32>,
+  channels:   Option<ChannelSemantics>,
+}
+
+/// Pixels in a `width` by `height` image, panicking if the count overflows
+fn pixel_count(width: usize, height: usize) -> usize {
+  width
+    .checked_mul(height)
+    .unwrap_or_else(|| overflow(width, height))
+}
+
+fn overflow(width: usize, height: usize) -> ! {
+  panic!("A {width}x{height} image does not fit in memory")
 }
 
 impl<
@@ -21,6 +95,7 @@ impl<
     const HAS_ALPHA: bool,
   > PixelContainer for ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>
 {
+  type Component = Component;
   type OnePixel = [Component; COMPONENTS_PER_PEL];
   type OnePlane = ImageBuffer<Component, 1, false>;
   type PixelBuffer = Vec<Component>;
@@ -45,6 +120,14 @@ impl<
   fn width(&self) -> usize { self.width }
 
   fn height(&self) -> usize { self.height }
+
+  fn components(&self) -> &[Component] { &self.data }
+
+  fn components_mut(&mut self) -> &mut [Component] { &mut self.data }
+
+  fn new_plane(&self) -> Self::OnePlane {
+    ImageBuffer::empty(self.width, self.height)
+  }
 }
 
 impl<
@@ -58,8 +141,10 @@ impl<
     width: usize,
     height: usize,
   ) -> Result<Self, &'static str> {
-    let expected_vec_elements = width * height * COMPONENTS_PER_PEL;
-    if data.len() != expected_vec_elements {
+    let expected_vec_elements = width
+      .checked_mul(height)
+      .and_then(|pixels| pixels.checked_mul(COMPONENTS_PER_PEL));
+    if expected_vec_elements != Some(data.len()) {
       return Err(
         "Data vector length does not match width, height, and number of \
          components per pixel",
@@ -69,37 +154,228 @@ impl<
       data,
       width,
       height,
+      bit_depth: None,
+      channels: None,
     })
   }
 
+  /// Like [`with_data`](Self::with_data), but reports a length mismatch as
+  /// the crate [`Error`]
+  pub fn try_with_data(
+    data: <Self as PixelContainer>::PixelBuffer,
+    width: usize,
+    height: usize,
+  ) -> CrateResult<Self> {
+    let len = data.len();
+    Self::with_data(data, width, height).map_err(|_| {
+      Error::InvalidArgument(format!(
+        "{len} components do not make a {width}x{height} image with \
+         {COMPONENTS_PER_PEL} components per pixel"
+      ))
+    })
+  }
+
+  /// Allocates a zeroed buffer
+  ///
+  /// Panics if the buffer size overflows or cannot be allocated; see
+  /// [`try_empty`](Self::try_empty) for a fallible version.
   pub fn empty(width: usize, height: usize) -> Self {
+    let len = pixel_count(width, height)
+      .checked_mul(COMPONENTS_PER_PEL)
+      .unwrap_or_else(|| overflow(width, height));
     ImageBuffer {
-      data: vec![Component::zero(); width * height * COMPONENTS_PER_PEL],
+      data: vec![Component::zero(); len],
       width,
       height,
+      bit_depth: None,
+      channels: None,
     }
   }
 
+  /// Like [`empty`](Self::empty), but fails instead of allocating a buffer
+  /// that exceeds `limits` or does not fit in memory
+  pub fn try_empty(
+    width: usize,
+    height: usize,
+    limits: &Limits,
+  ) -> CrateResult<Self> {
+    let bytes = limits.check_image(
+      width,
+      height,
+      COMPONENTS_PER_PEL * std::mem::size_of::<Component>(),
+    )?;
+    let len = bytes / std::mem::size_of::<Component>().max(1);
+    let mut data = Vec::new();
+    data
+      .try_reserve_exact(len)
+      .map_err(|e| Error::LimitExceeded(e.to_string()))?;
+    data.resize(len, Component::zero());
+    Ok(ImageBuffer {
+      data,
+      width,
+      height,
+      bit_depth: None,
+      channels: None,
+    })
+  }
+
+  /// Allocates a buffer filled with `one_pel`
+  ///
+  /// Panics if the buffer size overflows or cannot be allocated; see
+  /// [`try_with_val`](Self::try_with_val) for a fallible version.
   pub fn with_val(
     one_pel: &<Self as PixelContainer>::OnePixel,
     width: usize,
     height: usize,
   ) -> Self {
-    let mut result = ImageBuffer {
-      data: vec![Component::zero(); width * height * COMPONENTS_PER_PEL],
+    let pixels = pixel_count(width, height);
+    if pixels.checked_mul(COMPONENTS_PER_PEL).is_none() {
+      overflow(width, height);
+    }
+    ImageBuffer {
+      data: one_pel.repeat(pixels),
       width,
       height,
-    };
+      bit_depth: None,
+      channels: None,
+    }
+  }
 
-    for pel in result.iter_with_alpha_mut() {
-      for (c1, c2) in pel.iter_mut().zip(one_pel.iter()) {
-        *c1 = *c2;
-      }
+  /// Like [`with_val`](Self::with_val), but fails instead of allocating a
+  /// buffer that exceeds `limits` or does not fit in memory
+  pub fn try_with_val(
+    one_pel: &<Self as PixelContainer>::OnePixel,
+    width: usize,
+    height: usize,
+    limits: &Limits,
+  ) -> CrateResult<Self> {
+    let mut result = Self::try_empty(width, height, limits)?;
+    fill_repeating(&mut result.data, one_pel);
+    Ok(result)
+  }
+
+  /// Takes the components out of the buffer without copying them
+  pub fn into_components(self) -> Vec<Component> { self.data }
+
+  /// Number of significant bits in each component
+  ///
+  /// Defaults to the full width of the component type. Deep color data such
+  /// as 10-bit video stored in `u16` uses fewer.
+  pub fn bit_depth(&self) -> u32 {
+    self
+      .bit_depth
+      .unwrap_or(8 * std::mem::size_of::<Component>() as u32)
+  }
+
+  /// Records that components use only the low `bits` bits, without changing
+  /// the data
+  ///
+  /// Panics if `bits` is zero or wider than the component type, or if the
+  /// components are floating point.
+  pub fn set_bit_depth(&mut self, bits: u32) {
+    assert!(
+      is_integer::<Component>(),
+      "Bit depth only applies to integer components"
+    );
+    assert!(
+      bits > 0 && bits <= 8 * std::mem::size_of::<Component>() as u32,
+      "Bit depth must fit in the component type"
+    );
+    self.bit_depth = Some(bits);
+  }
+
+  /// Value of a fully-saturated component at this buffer's bit depth
+  pub fn white(&self) -> f64 {
+    if is_integer::<Component>() {
+      2f64.powi(self.bit_depth() as i32) - 1.0
+    } else {
+      1.0
     }
+  }
 
+  /// Returns a copy rescaled so that white maps to white at `bits` bits per
+  /// component
+  pub fn to_bit_depth(&self, bits: u32) -> Self {
+    let mut result = self.clone();
+    result.set_bit_depth(bits);
+    let scale = result.white() / self.white();
+    for c in &mut result.data {
+      *c = component_from_f64(c.to_f64().unwrap_or_default() * scale);
+    }
     result
   }
 
+  /// Converts to another component type, scaling so that white at this
+  /// buffer's bit depth maps to white in the new type
+  ///
+  /// Unlike [`as_other`](Self::as_other), which copies values unchanged, an
+  /// 8-bit `255` becomes `65535` in `u16` and `1.0` in `f32`.
+  pub fn as_other_scaled<NewComponent: PixelComponent>(
+    &self,
+  ) -> ImageBuffer<NewComponent, COMPONENTS_PER_PEL, HAS_ALPHA> {
+    let mut result = ImageBuffer::empty(self.width, self.height);
+    let scale = result.white() / self.white();
+    for (new, c) in result.data.iter_mut().zip(&self.data) {
+      *new = component_from_f64(c.to_f64().unwrap_or_default() * scale);
+    }
+    result
+  }
+
+  /// Names of this buffer's channels, if they have been set
+  pub fn channel_semantics(&self) -> Option<&ChannelSemantics> {
+    self.channels.as_ref()
+  }
+
+  /// Names this buffer's channels. There must be one name per channel.
+  pub fn set_channel_semantics(
+    &mut self,
+    channels: ChannelSemantics,
+  ) -> CrateResult<()> {
+    if channels.len() != COMPONENTS_PER_PEL {
+      return Err(Error::Channel(format!(
+        "{} channel names given for {COMPONENTS_PER_PEL} channels",
+        channels.len()
+      )));
+    }
+    self.channels = Some(channels);
+    Ok(())
+  }
+
+  /// Copies the channels called `names`, in that order, into a new buffer
+  ///
+  /// Fails if this buffer's channels are not named, or if a name is unknown.
+  pub fn select_channels<
+    const NEW_COMPONENTS_PER_PEL: usize,
+    const NEW_HAS_ALPHA: bool,
+  >(
+    &self,
+    names: &[&str; NEW_COMPONENTS_PER_PEL],
+  ) -> CrateResult<ImageBuffer<Component, NEW_COMPONENTS_PER_PEL, NEW_HAS_ALPHA>>
+  {
+    let channels = self
+      .channels
+      .as_ref()
+      .ok_or_else(|| Error::Channel("Channels are not named".to_string()))?;
+    let mut indices = [0; NEW_COMPONENTS_PER_PEL];
+    for (i, name) in indices.iter_mut().zip(names) {
+      *i = channels.index_of(name)?;
+    }
+
+    let mut result = ImageBuffer::<
+      Component,
+      NEW_COMPONENTS_PER_PEL,
+      NEW_HAS_ALPHA,
+    >::try_empty(self.width, self.height, &Limits::none())?;
+    for (pel, new_pel) in self.iter_pixels().zip(result.iter_pixels_mut()) {
+      for (c, &i) in new_pel.iter_mut().zip(&indices) {
+        *c = pel[i];
+      }
+    }
+    result.bit_depth = self.bit_depth;
+    result.channels = Some(ChannelSemantics::new(names.iter().copied())?);
+    Ok(result)
+  }
+
   pub fn as_other<
     NewComponent: PixelComponent,
     const NEW_COMPONENTS_PER_PEL: usize,
@@ -122,26 +398,6 @@ impl<
     result
   }
 
-  /// Applies the given pixel mapping function and returns a new image buffer of
-  /// the same type, with the result.
-  ///
-  /// This
-  ///
-  /// ```F``` is a function that operates on all channels of one pixel at a
-  /// time.
-  pub fn map<F>(&self, map_fn: &mut F) -> Self
-  where F: FnMut(
-      &<Self as PixelContainer>::OnePixel,
-    ) -> <Self as PixelContainer>::OnePixel {
-    let mut result = self.clone();
-
-    for (pel, new_pel) in self.iter().zip(result.iter_mut()) {
-      *new_pel = map_fn(pel);
-    }
-
-    result
-  }
-
   /// Applies the given pixel mapping function, which can generate any type of
   /// iamge buffer, and returns a new buffer with the result
   pub fn map_into<
@@ -175,75 +431,80 @@ impl<
     result
   }
 
-  /// Applies the given pixel mapping function in place, on the current mutable
-  /// instance
+  /// Splits the buffer into at most `n` disjoint horizontal bands of mutable
+  /// rows, top to bottom.
   ///
-  /// ```F``` is a function that operates on all channels of one pixel at a
-  /// time.
-  pub fn apply<F>(&mut self, map_fn: &mut F)
-  where F: FnMut(
-      &<Self as PixelContainer>::OnePixel,
-    ) -> <Self as PixelContainer>::OnePixel {
-    for pel in self.iter_mut() {
-      *pel = map_fn(pel);
+  /// Every band but the last has the same number of rows. Bands borrow
+  /// disjoint parts of the buffer, so they can be handed to separate threads.
+  /// `n` is clamped to `1..=height`, and an image with no rows yields no bands.
+  pub fn split_rows_mut(
+    &mut self,
+    n: usize,
+  ) -> Vec<ImageBufferMut<'_, Component, COMPONENTS_PER_PEL, HAS_ALPHA>> {
+    let width = self.width;
+    let height = self.height;
+    if width == 0 || height == 0 {
+      return Vec::new();
     }
-  }
-
-  pub fn get_plane_const<const I: usize>(
-    &self,
-  ) -> <Self as PixelContainer>::OnePlane {
-    let mut result =
-      <Self as PixelContainer>::OnePlane::empty(self.width, self.height);
 
-    for (pel, new_pel) in self.iter().zip(result.iter_mut()) {
-      *new_pel = [pel[I]];
-    }
+    let rows_per_band = height.div_ceil(n.clamp(1, height));
+    let band_len = rows_per_band * width * COMPONENTS_PER_PEL;
 
-    result
+    self
+      .components_mut()
+      .chunks_mut(band_len)
+      .enumerate()
+      .map(|(i, band)| {
+        let rows = band.len() / (width * COMPONENTS_PER_PEL);
+        ImageBufferMut::new(band, width, rows, i * rows_per_band)
+      })
+      .collect()
   }
+}
 
-  pub fn get_alpha(&self) -> Option<<Self as PixelContainer>::OnePlane> {
-    if !HAS_ALPHA {
-      return None;
-    }
-
-    Some(self.get_plane(COMPONENTS_PER_PEL-1).unwrap_or_default())
+impl<const COMPONENTS_PER_PEL: usize, const HAS_ALPHA: bool>
+  ImageBuffer<u16, COMPONENTS_PER_PEL, HAS_ALPHA>
+{
+  /// Builds a buffer from big-endian 16-bit samples, as sent over the network
+  /// or stored in PNG files
+  pub fn from_be_bytes(
+    bytes: &[u8],
+    width: usize,
+    height: usize,
+  ) -> Result<Self, &'static str> {
+    Self::from_bytes(bytes, width, height, u16::from_be_bytes)
   }
 
-  pub fn get_plane(
-    &self,
-    i: usize,
-  ) -> Result<<Self as PixelContainer>::OnePlane, &'static str> {
-    if i >= COMPONENTS_PER_PEL {
-      return Err("Plane index out of bounds");
-    }
-
-    let mut result =
-      <Self as PixelContainer>::OnePlane::empty(self.width, self.height);
-
-    for (pel, new_pel) in self.iter().zip(result.iter_mut()) {
-      *new_pel = [pel[i]];
-    }
-
-    Ok(result)
+  /// Builds a buffer from little-endian 16-bit samples
+  pub fn from_le_bytes(
+    bytes: &[u8],
+    width: usize,
+    height: usize,
+  ) -> Result<Self, &'static str> {
+    Self::from_bytes(bytes, width, height, u16::from_le_bytes)
   }
 
-  pub fn put_plane_const<const I: usize>(
-    &mut self,
-    plane: &<Self as PixelContainer>::OnePlane,
-  ) {
-    for (pel, new_pel) in self.iter_mut().zip(plane.iter()) {
-      pel[I] = new_pel[0];
+  fn from_bytes(
+    bytes: &[u8],
+    width: usize,
+    height: usize,
+    from_bytes: fn([u8; 2]) -> u16,
+  ) -> Result<Self, &'static str> {
+    if !bytes.len().is_multiple_of(2) {
+      return Err("Byte slice length is not a whole number of 16-bit samples");
     }
-  }
-
-  pub fn put_plane(
-    &mut self,
-    i: usize,
-    plane: &<Self as PixelContainer>::OnePlane,
-  ) {
-    for (pel, new_pel) in self.iter_mut().zip(plane.iter()) {
-      pel[i] = new_pel[0];
+    let data = bytes
+      .chunks_exact(2)
+      .map(|b| from_bytes([b[0], b[1]]))
+      .collect();
+    Self::with_data(data, width, height)
+  }
+
+  /// Reverses the byte order of every component, converting between big- and
+  /// little-endian data that was loaded as native-endian
+  pub fn swap_bytes(&mut self) {
+    for c in &mut self.data {
+      *c = c.swap_bytes();
     }
   }
 }
@@ -255,7 +516,7 @@ pub struct ImageBufferIterator<
   const HAS_ALPHA: bool,
   const SKIP_ALPHA: bool,
 > {
-  iterator: ArrayChunks<'a, Component, COMPONENT_STRIDE>,
+  iterator: Iter<'a, [Component; COMPONENT_STRIDE]>,
 }
 
 pub struct ImagebufferIteratorMut<
@@ -265,7 +526,39 @@ pub struct ImagebufferIteratorMut<
   const HAS_ALPHA: bool,
   const SKIP_ALPHA: bool,
 > {
-  iterator: ArrayChunksMut<'a, Component, COMPONENT_STRIDE>,
+  iterator: IterMut<'a, [Component; COMPONENT_STRIDE]>,
+}
+
+impl<
+    'a,
+    Component: PixelComponent,
+    const COMPONENT_STRIDE: usize,
+    const HAS_ALPHA: bool,
+    const SKIP_ALPHA: bool,
+  >
+  ImageBufferIterator<'a, Component, COMPONENT_STRIDE, HAS_ALPHA, SKIP_ALPHA>
+{
+  pub(crate) fn new(data: &'a [Component]) -> Self {
+    ImageBufferIterator {
+      iterator: data.as_chunks::<COMPONENT_STRIDE>().0.iter(),
+    }
+  }
+}
+
+impl<
+    'a,
+    Component: PixelComponent,
+    const COMPONENT_STRIDE: usize,
+    const HAS_ALPHA: bool,
+    const SKIP_ALPHA: bool,
+  >
+  ImagebufferIteratorMut<'a, Component, COMPONENT_STRIDE, HAS_ALPHA, SKIP_ALPHA>
+{
+  pub(crate) fn new(data: &'a mut [Component]) -> Self {
+    ImagebufferIteratorMut {
+      iterator: data.as_chunks_mut::<COMPONENT_STRIDE>().0.iter_mut(),
+    }
+  }
 }
 
 impl<
@@ -293,36 +586,28 @@ impl<
     &'a self,
   ) -> ImageBufferIterator<'a, Component, COMPONENTS_PER_PEL, HAS_ALPHA, true>
   {
-    ImageBufferIterator {
-      iterator: self.data.array_chunks::<COMPONENTS_PER_PEL>(),
-    }
+    ImageBufferIterator::new(&self.data)
   }
 
   pub fn iter_no_alpha_mut(
     &'a mut self,
   ) -> ImagebufferIteratorMut<'a, Component, COMPONENTS_PER_PEL, HAS_ALPHA, true>
   {
-    ImagebufferIteratorMut {
-      iterator: self.data.array_chunks_mut::<COMPONENTS_PER_PEL>(),
-    }
+    ImagebufferIteratorMut::new(&mut self.data)
   }
 
   pub fn iter_with_alpha(
     &'a self,
   ) -> ImageBufferIterator<'a, Component, COMPONENTS_PER_PEL, HAS_ALPHA, false>
   {
-    ImageBufferIterator {
-      iterator: self.data.array_chunks::<COMPONENTS_PER_PEL>(),
-    }
+    ImageBufferIterator::new(&self.data)
   }
 
   pub fn iter_with_alpha_mut(
     &'a mut self,
   ) -> ImagebufferIteratorMut<'a, Component, COMPONENTS_PER_PEL, HAS_ALPHA, false>
   {
-    ImagebufferIteratorMut {
-      iterator: self.data.array_chunks_mut::<COMPONENTS_PER_PEL>(),
-    }
+    ImagebufferIteratorMut::new(&mut self.data)
   }
 }
 
@@ -380,12 +665,10 @@ impl<
 
 #[cfg(test)]
 mod tests {
-
-  use std::hint::black_box;
-  use image::{DynamicImage, GenericImage};
-  use test::Bencher;
+  use proptest::prelude::*;
 
   use super::*;
+  use crate::testing::{arb_image_buffer, assert_approx_eq};
 
   #[test]
   fn new_rgba_u8_with_data() {
@@ -409,6 +692,63 @@ mod tests {
     }
   }
 
+  #[test]
+  fn try_empty_honors_limits() {
+    let image =
+      ImageBuffer::<u16, 3, false>::try_empty(4, 2, &Limits::default())
+        .unwrap();
+    assert_eq!(image.data.len(), 4 * 2 * 3);
+    let limits = Limits {
+      max_bytes: Some(47),
+      ..Limits::none()
+    };
+    assert!(matches!(
+      ImageBuffer::<u16, 3, false>::try_empty(4, 2, &limits),
+      Err(Error::LimitExceeded(_))
+    ));
+    assert!(ImageBuffer::<u8, 4, true>::try_empty(
+      100_000,
+      100_000,
+      &Limits::default()
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn fallible_constructors_report_errors() {
+    let filled =
+      ImageBuffer::<u8, 2, true>::try_with_val(&[3, 9], 2, 1, &Limits::none())
+        .unwrap();
+    assert_eq!(filled.data, [3, 9, 3, 9]);
+    assert!(matches!(
+      ImageBuffer::<u8, 3, false>::try_with_data(vec![0; 5], 1, 2),
+      Err(Error::InvalidArgument(_))
+    ));
+    assert!(
+      ImageBuffer::<u8, 3, false>::with_data(vec![], usize::MAX, 2).is_err()
+    );
+    assert!(matches!(
+      ImageBuffer::<u16, 4, true>::try_empty(
+        usize::MAX / 2,
+        3,
+        &Limits::none()
+      ),
+      Err(Error::LimitExceeded(_))
+    ));
+  }
+
+  #[test]
+  #[should_panic(expected = "does not fit in memory")]
+  fn empty_panics_on_overflow() {
+    ImageBuffer::<u8, 4, true>::empty(usize::MAX / 2, 3);
+  }
+
+  #[test]
+  #[should_panic(expected = "does not fit in memory")]
+  fn with_val_panics_on_overflow() {
+    ImageBuffer::<u8, 2, false>::with_val(&[1, 2], usize::MAX / 4, 3);
+  }
+
   #[test]
   fn new_rgba_u8_with_val() {
     const WIDTH: usize = 4;
@@ -455,138 +795,99 @@ mod tests {
     }
   }
 
-  #[bench]
-  fn bench_new_rgba_u8_with_data(b: &mut Bencher) {
-    const WIDTH: usize = 1920;
-    const HEIGHT: usize = 1080;
-    const RGBA_CPP: usize = 4;
-    let data = vec![0u8; WIDTH * HEIGHT * RGBA_CPP];
-    b.iter(|| {
-      black_box(ImageBuffer::<u8, 4, true>::with_data(data.clone(), WIDTH, HEIGHT)
-        .unwrap());
-    });
+  #[test]
+  fn border_mode_resolve() {
+    assert_eq!(BorderMode::Clamp.resolve(-2, 4), 0);
+    assert_eq!(BorderMode::Clamp.resolve(5, 4), 3);
+    assert_eq!(BorderMode::Reflect.resolve(-1, 4), 0);
+    assert_eq!(BorderMode::Reflect.resolve(-2, 4), 1);
+    assert_eq!(BorderMode::Reflect.resolve(4, 4), 3);
+    assert_eq!(BorderMode::Wrap.resolve(-1, 4), 3);
+    assert_eq!(BorderMode::Wrap.resolve(4, 4), 0);
   }
 
-  #[bench]
-  fn bench_new_rgba_u8_empty(b: &mut Bencher) {
-    const WIDTH: usize = 1920;
-    const HEIGHT: usize = 1080;
-    b.iter(|| {
-      black_box(ImageBuffer::<u8, 4, true>::empty(WIDTH, HEIGHT));
-    });
+  #[test]
+  fn map_indexed_gray_u8() {
+    let image = ImageBuffer::<u8, 1, false>::empty(3, 2);
+    let result = image.map_indexed(&mut |x, y, _| [(y * 10 + x) as u8]);
+    assert_eq!(result.data, vec![0, 1, 2, 10, 11, 12]);
   }
 
-  #[bench]
-  fn bench_new_rgba_u8_with_val(b: &mut Bencher) {
-    const WIDTH: usize = 1920;
-    const HEIGHT: usize = 1080;
-    let one_pel = [0u8, 0u8, 0u8, 255];
-    b.iter(|| {
-      black_box(ImageBuffer::<u8, 4, true>::with_val(&one_pel, WIDTH, HEIGHT));
+  #[test]
+  fn map_window_box_sum_gray_u8() {
+    let image =
+      ImageBuffer::<u8, 1, false>::with_data(vec![1, 2, 3, 4], 2, 2).unwrap();
+    let clamped = image.map_window::<3, 3>(BorderMode::Clamp, &mut |w| {
+      [w.iter().flatten().map(|pel| pel[0]).sum()]
     });
-  }
-
-  #[bench]
-  fn bench_new_rgba_u8_dynamic_image_empty(b: &mut Bencher) {
-    const WIDTH: u32 = 1920;
-    const HEIGHT: u32 = 1080;
-    b.iter(|| {
-      black_box(DynamicImage::new_rgba8(WIDTH, HEIGHT));
+    assert_eq!(clamped.data, vec![18, 21, 24, 27]);
+    let wrapped = image.map_window::<3, 3>(BorderMode::Wrap, &mut |w| {
+      [w[1][1][0] + w[0][0][0]]
     });
+    assert_eq!(wrapped.data, vec![5, 5, 5, 5]);
   }
 
-  #[bench]
-  fn bench_new_rgba_u8_dynamic_image_with_data(b: &mut Bencher) {
-    const WIDTH: u32 = 1920;
-    const HEIGHT: u32 = 1080;
-    const RGBA_CPP: u32 = 4;
-    let data = vec![0u8; WIDTH as usize * HEIGHT as usize * RGBA_CPP as usize];
-    let buf = image::ImageBuffer::from_vec(WIDTH, HEIGHT, data).unwrap();
-    b.iter(|| black_box(DynamicImage::ImageRgba8(buf.clone())));
-  }
-
-  #[bench]
-  fn bench_new_rgba_u8_dynamic_image_with_val(b: &mut Bencher) {
-    const WIDTH: u32 = 1920;
-    const HEIGHT: u32 = 1080;
-    let one_pel = image::Rgba([0u8, 0u8, 0u8, 255]);
-    let buf = image::ImageBuffer::from_pixel(WIDTH, HEIGHT, one_pel);
-    b.iter(|| black_box(DynamicImage::ImageRgba8(buf.clone())));
+  proptest! {
+    #[test]
+    fn as_other_u8_u16_round_trip(
+      image in arb_image_buffer::<u8, 4, true>(8, 8)
+    ) {
+      let round_trip = image.as_other::<u16, 4, true>().as_other::<u8, 4, true>();
+      assert_approx_eq(&round_trip, &image, 0.0);
+    }
   }
 
-  #[bench]
-  fn bench_iteration_rgba_u8_assignment_no_alpha(b: &mut Bencher) {
-    const WIDTH: usize = 1920;
-    const HEIGHT: usize = 1080;
-    const RGBA_CPP: usize = 4;
-    let data = vec![0u8; WIDTH * HEIGHT * RGBA_CPP];
-    let mut image =
-      ImageBuffer::<u8, 4, true>::with_data(data, WIDTH, HEIGHT).unwrap();
-    let mut new_val: u8 = 0;
-    b.iter(|| {
-      new_val = new_val.wrapping_add(1);
-      for pel in image.iter_no_alpha_mut() {
-        pel[0] = new_val;
-        pel[1] = new_val;
-        pel[2] = new_val;
-      }
-    });
+  #[test]
+  fn from_be_and_le_bytes_u16() {
+    let bytes = [0x01, 0x02, 0xab, 0xcd];
+    let be = ImageBuffer::<u16, 1, false>::from_be_bytes(&bytes, 2, 1).unwrap();
+    let mut le =
+      ImageBuffer::<u16, 1, false>::from_le_bytes(&bytes, 2, 1).unwrap();
+    assert_eq!(be.pixels(), &vec![0x0102, 0xabcd]);
+    le.swap_bytes();
+    assert_eq!(le.pixels(), be.pixels());
+    assert!(ImageBuffer::<u16, 1, false>::from_be_bytes(&bytes[..3], 2, 1)
+      .is_err());
+    assert!(ImageBuffer::<u16, 2, false>::from_be_bytes(&bytes, 2, 1)
+      .is_err());
   }
 
-  #[bench]
-  fn bench_iteration_rgba_u8_assignment_with_alpha_skip_alpha(b: &mut Bencher) {
-    const WIDTH: usize = 1920;
-    const HEIGHT: usize = 1080;
-    const RGBA_CPP: usize = 4;
-    let data = vec![0u8; WIDTH * HEIGHT * RGBA_CPP];
-    let mut image =
-      ImageBuffer::<u8, 4, true>::with_data(data, WIDTH, HEIGHT).unwrap();
-    let mut new_val: u8 = 0;
-    b.iter(|| {
-      new_val = new_val.wrapping_add(1);
-      for pel in image.iter_with_alpha_mut() {
-        pel[0] = new_val;
-        pel[1] = new_val;
-        pel[2] = new_val;
-      }
-    });
+  #[test]
+  fn bit_depth_scaling_u16() {
+    let mut image = ImageBuffer::<u16, 1, false>::with_val(&[1023], 1, 1);
+    assert_eq!(image.bit_depth(), 16);
+    image.set_bit_depth(10);
+    assert_eq!(image.white(), 1023.0);
+    assert_eq!(image.to_bit_depth(12).pixels(), &vec![4095]);
+    assert_eq!(image.as_other_scaled::<u8>().pixels(), &vec![255]);
+    assert_eq!(image.as_other_scaled::<f32>().pixels(), &vec![1.0]);
   }
 
-  #[bench]
-  fn bench_iteration_rgba_u8_assignment_with_alpha_assign_alpha(
-    b: &mut Bencher,
-  ) {
-    const WIDTH: usize = 1920;
-    const HEIGHT: usize = 1080;
-    const RGBA_CPP: usize = 4;
-    let data = vec![0u8; WIDTH * HEIGHT * RGBA_CPP];
+  #[test]
+  fn select_channels_by_name_multispectral_u16() {
     let mut image =
-      ImageBuffer::<u8, 4, true>::with_data(data, WIDTH, HEIGHT).unwrap();
-    let mut new_val: u8 = 0;
-    b.iter(|| {
-      new_val = new_val.wrapping_add(1);
-      for pel in image.iter_with_alpha_mut() {
-        pel[0] = new_val;
-        pel[1] = new_val;
-        pel[2] = new_val;
-        pel[3] = 255;
-      }
-    });
-  }
-
-  #[bench]
-  fn bench_iteration_rgba_u8_assignment_dynamic_image(b: &mut Bencher) {
-    const WIDTH: u32 = 1920;
-    const HEIGHT: u32 = 1080;
-    let mut image = DynamicImage::new_rgba8(WIDTH, HEIGHT);
-    let mut new_val: u8 = 0;
-    b.iter(|| {
-      new_val = new_val.wrapping_add(1);
-      for y in 0..HEIGHT {
-        for x in 0..WIDTH {
-          image.put_pixel(x, y, image::Rgba([new_val, new_val, new_val, 255]));
-        }
-      }
-    });
+      ImageBuffer::<u16, 6, false>::with_val(&[0, 1, 2, 3, 4, 5], 2, 2);
+    assert!(image.select_channels::<1, false>(&["NIR"]).is_err());
+    let names = ChannelSemantics::new(["UV", "B", "G", "R", "NIR", "SWIR"]);
+    image.set_channel_semantics(names.unwrap()).unwrap();
+    assert!(image
+      .set_channel_semantics(ChannelSemantics::rgb())
+      .is_err());
+
+    let false_color = image.select_channels::<3, false>(&["NIR", "R", "G"]);
+    let false_color = false_color.unwrap();
+    assert_eq!(false_color.get_pixel(1, 1), &[4, 3, 2]);
+    assert_eq!(
+      false_color
+        .channel_semantics()
+        .unwrap()
+        .index_of("R")
+        .unwrap(),
+      1
+    );
+    assert!(matches!(
+      image.select_channels::<1, false>(&["X"]),
+      Err(Error::Channel(_))
+    ));
   }
 }
@@ -0,0 +1,120 @@
+use crate::{
+  image_buffer::ImageBuffer,
+  pixel::{PixelComponent, PixelContainer},
+};
+
+/// A mutable view over a horizontal band of rows borrowed from an
+/// [`ImageBuffer`], as produced by [`ImageBuffer::split_rows_mut`].
+///
+/// Coordinates passed to a band are relative to its first row; `y_offset`
+/// records where that row lies in the parent image.
+#[derive(Debug)]
+pub struct ImageBufferMut<
+  'a,
+  Component: PixelComponent,
+  const COMPONENTS_PER_PEL: usize,
+  const HAS_ALPHA: bool,
+> {
+  data:         &'a mut [Component],
+  pub width:    usize,
+  pub height:   usize,
+  pub y_offset: usize,
+}
+
+impl<
+    'a,
+    Component: PixelComponent,
+    const COMPONENTS_PER_PEL: usize,
+    const HAS_ALPHA: bool,
+  > PixelContainer
+  for ImageBufferMut<'a, Component, COMPONENTS_PER_PEL, HAS_ALPHA>
+{
+  type Component = Component;
+  type OnePixel = [Component; COMPONENTS_PER_PEL];
+  type OnePlane = ImageBuffer<Component, 1, false>;
+  type PixelBuffer = &'a mut [Component];
+
+  const ALPHA_IDX: Option<usize> = if HAS_ALPHA {
+    Some(COMPONENTS_PER_PEL - 1)
+  } else {
+    None
+  };
+  const HAS_ALPHA: bool = HAS_ALPHA;
+  const NUM_COMPONENTS: usize = COMPONENTS_PER_PEL;
+  const NUM_NONALPHA_COMPONENTS: usize = if HAS_ALPHA {
+    COMPONENTS_PER_PEL - 1
+  } else {
+    COMPONENTS_PER_PEL
+  };
+
+  fn pixels(&self) -> &Self::PixelBuffer { &self.data }
+
+  fn pixels_mut(&mut self) -> &mut Self::PixelBuffer { &mut self.data }
+
+  fn width(&self) -> usize { self.width }
+
+  fn height(&self) -> usize { self.height }
+
+  fn components(&self) -> &[Component] { self.data }
+
+  fn components_mut(&mut self) -> &mut [Component] { self.data }
+
+  fn new_plane(&self) -> Self::OnePlane {
+    ImageBuffer::empty(self.width, self.height)
+  }
+}
+
+impl<
+    'a,
+    Component: PixelComponent,
+    const COMPONENTS_PER_PEL: usize,
+    const HAS_ALPHA: bool,
+  > ImageBufferMut<'a, Component, COMPONENTS_PER_PEL, HAS_ALPHA>
+{
+  pub(crate) fn new(
+    data: &'a mut [Component],
+    width: usize,
+    height: usize,
+    y_offset: usize,
+  ) -> Self {
+    debug_assert_eq!(data.len(), width * height * COMPONENTS_PER_PEL);
+    ImageBufferMut {
+      data,
+      width,
+      height,
+      y_offset,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::thread;
+
+  use super::*;
+
+  #[test]
+  fn split_rows_mut_band_sizes() {
+    let mut image = ImageBuffer::<u8, 3, false>::empty(4, 5);
+    let bands = image.split_rows_mut(2);
+    assert_eq!(bands.len(), 2);
+    assert_eq!((bands[0].height, bands[0].y_offset), (3, 0));
+    assert_eq!((bands[1].height, bands[1].y_offset), (2, 3));
+    assert_eq!(image.split_rows_mut(0).len(), 1);
+    assert_eq!(image.split_rows_mut(10).len(), 5);
+  }
+
+  #[test]
+  fn split_rows_mut_threaded_fill() {
+    let mut image = ImageBuffer::<u8, 1, false>::empty(3, 4);
+    thread::scope(|s| {
+      for mut band in image.split_rows_mut(4) {
+        s.spawn(move || {
+          let y = band.y_offset as u8;
+          band.apply(&mut |_| [y]);
+        });
+      }
+    });
+    assert_eq!(image.pixels(), &vec![0, 0, 0, 1, 1, 1, 2, 2, 2, 3, 3, 3]);
+  }
+}
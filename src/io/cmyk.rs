@@ -0,0 +1,102 @@
+//! Native CMYK decoding. The `image` crate converts CMYK files to RGB while
+//! decoding, so TIFF and JPEG files are checked for CMYK data first and read
+//! with the underlying codecs directly.
+
+use super::{buffer, ColorSpace, Error, ImageFormat};
+use crate::{error::Result, Image};
+
+/// Decodes `bytes` into [`ColorSpace::Cmyk`] if the file holds CMYK data,
+/// or returns `None` to fall back to the regular decoders
+pub(super) fn decode(
+  bytes: &[u8],
+  format: ImageFormat,
+) -> Result<Option<Image>> {
+  match format {
+    #[cfg(feature = "tiff")]
+    ImageFormat::Tiff => tiff(bytes),
+    #[cfg(feature = "jpeg")]
+    ImageFormat::Jpeg => jpeg(bytes),
+    _ => Ok(None),
+  }
+}
+
+#[cfg(feature = "tiff")]
+fn tiff(bytes: &[u8]) -> Result<Option<Image>> {
+  use tiff::{decoder::DecodingResult, ColorType};
+
+  let err = |e: tiff::TiffError| Error::Decode(e.to_string());
+  let mut decoder =
+    tiff::decoder::Decoder::new(std::io::Cursor::new(bytes)).map_err(err)?;
+  let (width, height) = decoder.dimensions().map_err(err)?;
+  if !matches!(decoder.colortype().map_err(err)?, ColorType::CMYK(8 | 16)) {
+    return Ok(None);
+  }
+  let image = match decoder.read_image().map_err(err)? {
+    DecodingResult::U8(data) =>
+      Image::new_u8(ColorSpace::Cmyk(buffer(data, width, height)?)),
+    DecodingResult::U16(data) =>
+      Image::new_u16(ColorSpace::Cmyk(buffer(data, width, height)?)),
+    _ => return Ok(None),
+  };
+  Ok(Some(image))
+}
+
+#[cfg(feature = "jpeg")]
+fn jpeg(bytes: &[u8]) -> Result<Option<Image>> {
+  use zune_core::{
+    bytestream::ZCursor,
+    colorspace::ColorSpace as Zune,
+    options::DecoderOptions,
+  };
+  use zune_jpeg::JpegDecoder;
+
+  let err = |e: zune_jpeg::errors::DecodeErrors| Error::Decode(e.to_string());
+  let mut decoder = JpegDecoder::new(ZCursor::new(bytes));
+  decoder.decode_headers().map_err(err)?;
+  if !matches!(decoder.input_colorspace(), Some(Zune::CMYK | Zune::YCCK)) {
+    return Ok(None);
+  }
+
+  let options = DecoderOptions::default()
+    .set_strict_mode(false)
+    .jpeg_set_out_colorspace(Zune::CMYK);
+  let mut decoder = JpegDecoder::new_with_options(ZCursor::new(bytes), options);
+  let data = decoder.decode().map_err(err)?;
+  let (width, height) = decoder
+    .dimensions()
+    .expect("Dimensions are known once the image is decoded");
+  Ok(Some(Image::new_u8(ColorSpace::Cmyk(buffer(
+    data,
+    width as u32,
+    height as u32,
+  )?))))
+}
+
+#[cfg(all(test, feature = "tiff"))]
+mod tests {
+  use std::io::Cursor;
+
+  use tiff::encoder::{colortype::CMYK8, TiffEncoder};
+
+  use super::*;
+  use crate::PixelContainer;
+
+  #[test]
+  fn decode_cmyk_tiff_keeps_ink_values() {
+    let mut bytes = Cursor::new(Vec::new());
+    TiffEncoder::new(&mut bytes)
+      .unwrap()
+      .write_image::<CMYK8>(2, 1, &[10, 20, 30, 40, 50, 60, 70, 80])
+      .unwrap();
+    let image = super::super::decode(bytes.get_ref()).unwrap();
+    match image.imp {
+      crate::image::Implementation::U8(imp) =>
+        match imp.data {
+          ColorSpace::Cmyk(buf) =>
+            assert_eq!(buf.components(), &[10, 20, 30, 40, 50, 60, 70, 80]),
+          _ => panic!("Wrong color space"),
+        },
+      _ => panic!("Wrong component type"),
+    }
+  }
+}
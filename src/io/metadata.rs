@@ -0,0 +1,823 @@
+//! Reading, writing and removal of metadata in encoded files without
+//! decoding the pixels.
+
+use std::fmt;
+
+use crate::error::{Error, Result};
+
+/// Removes metadata that can identify the author, camera or location from
+/// an encoded JPEG or PNG file, leaving the compressed pixels untouched so
+/// that no quality is lost.
+///
+/// JPEG files lose their application segments, such as Exif, XMP and
+/// Photoshop data, and comments, wherever they occur, as well as anything
+/// after the end of the image. PNG files lose text, Exif, timestamp and
+/// unrecognized ancillary chunks. Only what is needed to display the image
+/// as before is kept: JFIF and Adobe segments, ICC profiles and the PNG
+/// color, transparency and physical size chunks. Fails for other formats and
+/// truncated files.
+pub fn strip_metadata(bytes: &[u8]) -> Result<Vec<u8>> {
+  if bytes.starts_with(&[0xff, 0xd8]) {
+    strip_jpeg(bytes)
+  } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+    strip_png(bytes)
+  } else {
+    Err(Error::Unsupported(
+      "Metadata can only be stripped from JPEG and PNG files".to_string(),
+    ))
+  }
+}
+
+fn truncated() -> Error { Error::Decode("File is truncated".to_string()) }
+
+fn strip_jpeg(bytes: &[u8]) -> Result<Vec<u8>> {
+  let mut out = bytes[..2].to_vec();
+  let mut pos = 2;
+  loop {
+    let marker = *bytes.get(pos + 1).ok_or_else(truncated)?;
+    if bytes[pos] != 0xff {
+      return Err(Error::Decode("Expected a JPEG marker".to_string()));
+    }
+    match marker {
+      // Fill bytes before a marker
+      0xff => {
+        pos += 1;
+        continue;
+      }
+      // The end of the image; anything after it is dropped
+      0xd9 => {
+        out.extend_from_slice(&bytes[pos..pos + 2]);
+        return Ok(out);
+      }
+      // Markers without a length
+      0x01 | 0xd0..=0xd7 => {
+        out.extend_from_slice(&bytes[pos..pos + 2]);
+        pos += 2;
+        continue;
+      }
+      _ => {}
+    }
+    let header = bytes.get(pos + 2..pos + 4).ok_or_else(truncated)?;
+    let length = usize::from(u16::from_be_bytes([header[0], header[1]]));
+    // The length counts its own two bytes
+    if length < 2 {
+      return Err(Error::Decode("Invalid JPEG segment length".to_string()));
+    }
+    let end = pos + 2 + length;
+    let segment = bytes.get(pos..end).ok_or_else(truncated)?;
+    let payload = &segment[4..];
+    let keep = match marker {
+      0xe0 => payload.starts_with(b"JFIF\0") || payload.starts_with(b"JFXX\0"),
+      0xe2 => payload.starts_with(b"ICC_PROFILE\0"),
+      0xee => payload.starts_with(b"Adobe"),
+      0xe1..=0xef | 0xfe => false,
+      _ => true,
+    };
+    if keep {
+      out.extend_from_slice(segment);
+    }
+    pos = end;
+    // The entropy-coded data of a scan runs to the next marker other than
+    // a stuffed zero or a restart. Progressive and multi-scan files have
+    // more segments after it, which may hold metadata too.
+    if marker == 0xda {
+      let data_end = (pos..bytes.len().saturating_sub(1))
+        .find(|&i| {
+          bytes[i] == 0xff && !matches!(bytes[i + 1], 0x00 | 0xd0..=0xd7 | 0xff)
+        })
+        .unwrap_or(bytes.len());
+      out.extend_from_slice(&bytes[pos..data_end]);
+      if data_end == bytes.len() {
+        return Ok(out);
+      }
+      pos = data_end;
+    }
+  }
+}
+
+/// Ancillary PNG chunks that affect how the image is displayed or printed
+const PNG_DISPLAY_CHUNKS: [&[u8; 4]; 10] = [
+  b"tRNS", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT", b"pHYs", b"acTL",
+  b"fcTL", b"fdAT",
+];
+
+fn strip_png(bytes: &[u8]) -> Result<Vec<u8>> {
+  let mut out = bytes[..8].to_vec();
+  let mut pos = 8;
+  while pos < bytes.len() {
+    let header = bytes.get(pos..pos + 8).ok_or_else(truncated)?;
+    let length =
+      u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let kind: &[u8; 4] = header[4..8].try_into().unwrap_or(&[0; 4]);
+    let end = pos + 12 + length as usize;
+    let chunk = bytes.get(pos..end).ok_or_else(truncated)?;
+    // Critical chunks have an uppercase first letter
+    if kind[0].is_ascii_uppercase() || PNG_DISPLAY_CHUNKS.contains(&kind) {
+      out.extend_from_slice(chunk);
+    }
+    pos = end;
+    if kind == b"IEND" {
+      break;
+    }
+  }
+  Ok(out)
+}
+
+const INCH_MM: f64 = 25.4;
+
+/// Resolution in dots per inch, horizontally and vertically, recorded in
+/// the JFIF segment of a JPEG file or the `pHYs` chunk of a PNG file. Files
+/// that only give an aspect ratio, or nothing at all, have none.
+pub(crate) fn read_dpi(bytes: &[u8]) -> Option<(f64, f64)> {
+  if bytes.starts_with(&[0xff, 0xd8]) {
+    // JFIF must directly follow the start of image
+    let segment = bytes.get(2..18)?;
+    if segment[..2] != [0xff, 0xe0] || &segment[4..9] != b"JFIF\0" {
+      return None;
+    }
+    let density =
+      |i: usize| f64::from(u16::from_be_bytes([segment[i], segment[i + 1]]));
+    let scale = match segment[11] {
+      1 => 1.0,
+      2 => INCH_MM / 10.0,
+      _ => return None,
+    };
+    Some((density(12) * scale, density(14) * scale))
+  } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+    let mut pos = 8;
+    while let Some(header) = bytes.get(pos..pos + 8) {
+      let len =
+        u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+      match &header[4..] {
+        b"pHYs" => {
+          let data = bytes.get(pos + 8..pos + 17)?;
+          let per_meter = |i: usize| {
+            f64::from(u32::from_be_bytes([
+              data[i],
+              data[i + 1],
+              data[i + 2],
+              data[i + 3],
+            ]))
+          };
+          return (data[8] == 1).then(|| {
+            let scale = INCH_MM / 1000.0;
+            (per_meter(0) * scale, per_meter(4) * scale)
+          });
+        }
+        b"IDAT" | b"IEND" => return None,
+        _ => pos = pos.saturating_add(12).saturating_add(len as usize),
+      }
+    }
+    None
+  } else {
+    None
+  }
+}
+
+/// Records a resolution in dots per inch in an encoded JPEG or PNG file,
+/// replacing any it has. Other formats are returned unchanged.
+pub(crate) fn write_dpi(bytes: &[u8], (x, y): (f64, f64)) -> Result<Vec<u8>> {
+  if bytes.starts_with(&[0xff, 0xd8]) {
+    let density = |v: f64| (v.round().clamp(1.0, 65535.0) as u16).to_be_bytes();
+    let mut jfif = vec![0xff, 0xe0, 0, 16];
+    jfif.extend_from_slice(b"JFIF\0");
+    jfif.extend_from_slice(&[1, 1, 1]);
+    jfif.extend_from_slice(&density(x));
+    jfif.extend_from_slice(&density(y));
+    jfif.extend_from_slice(&[0, 0]);
+    let mut out = bytes[..2].to_vec();
+    out.extend_from_slice(&jfif);
+    // Replace the encoder's JFIF segment, if any
+    let rest = match bytes.get(2..6) {
+      Some([0xff, 0xe0, hi, lo]) if bytes.get(6..11) == Some(b"JFIF\0") =>
+        bytes
+          .get(2 + 2 + usize::from(u16::from_be_bytes([*hi, *lo]))..)
+          .ok_or_else(truncated)?,
+      _ => &bytes[2..],
+    };
+    out.extend_from_slice(rest);
+    Ok(out)
+  } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+    let per_meter = |v: f64| {
+      ((v * 1000.0 / INCH_MM).round().clamp(0.0, u32::MAX as f64) as u32)
+        .to_be_bytes()
+    };
+    let mut data = per_meter(x).to_vec();
+    data.extend_from_slice(&per_meter(y));
+    data.push(1);
+    replace_png_chunk(bytes, b"pHYs", &data)
+  } else {
+    Ok(bytes.to_vec())
+  }
+}
+
+/// Replaces the chunks of `kind` in an encoded PNG file with one holding
+/// `data`, placed right after the header, where any chunk is allowed
+fn replace_png_chunk(
+  bytes: &[u8],
+  kind: &[u8; 4],
+  data: &[u8],
+) -> Result<Vec<u8>> {
+  let mut typed = kind.to_vec();
+  typed.extend_from_slice(data);
+  let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+  chunk.extend_from_slice(&typed);
+  chunk.extend_from_slice(&crc32(&typed).to_be_bytes());
+
+  let mut out = bytes[..8].to_vec();
+  let mut pos = 8;
+  while pos < bytes.len() {
+    let header = bytes.get(pos..pos + 8).ok_or_else(truncated)?;
+    let length =
+      u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let end = pos + 12 + length as usize;
+    let existing = bytes.get(pos..end).ok_or_else(truncated)?;
+    if &header[4..8] != kind {
+      out.extend_from_slice(existing);
+    }
+    if &header[4..8] == b"IHDR" {
+      out.extend_from_slice(&chunk);
+    }
+    pos = end;
+  }
+  Ok(out)
+}
+
+/// A position on Earth in degrees, positive to the north and east
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatLon {
+  pub latitude:  f64,
+  pub longitude: f64,
+}
+
+/// A date and time of day as shown by the clock of the camera, which Exif
+/// records without a time zone
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+  pub year:   u16,
+  pub month:  u8,
+  pub day:    u8,
+  pub hour:   u8,
+  pub minute: u8,
+  pub second: u8,
+}
+
+impl DateTime {
+  /// Parses Exif's `YYYY:MM:DD HH:MM:SS`. Cameras whose clock was never set
+  /// leave it blank or zeroed, which gives none.
+  fn from_exif(text: &[u8]) -> Option<Self> {
+    let text = std::str::from_utf8(text).ok()?.trim_end_matches('\0');
+    let separators = [(4, b':'), (7, b':'), (10, b' '), (13, b':'), (16, b':')];
+    if text.len() != 19
+      || separators.iter().any(|&(i, c)| text.as_bytes()[i] != c)
+    {
+      return None;
+    }
+    let field = |at: usize, len: usize| text.get(at..at + len)?.parse().ok();
+    let time = DateTime {
+      year:   field(0, 4)?,
+      month:  field(5, 2)? as u8,
+      day:    field(8, 2)? as u8,
+      hour:   field(11, 2)? as u8,
+      minute: field(14, 2)? as u8,
+      second: field(17, 2)? as u8,
+    };
+    let valid = (1..=12).contains(&time.month)
+      && (1..=31).contains(&time.day)
+      && time.hour < 24
+      && time.minute < 60
+      && time.second <= 60;
+    valid.then_some(time)
+  }
+
+  fn to_exif(self) -> String {
+    format!(
+      "{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+      self.year, self.month, self.day, self.hour, self.minute, self.second
+    )
+  }
+}
+
+impl fmt::Display for DateTime {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+      self.year, self.month, self.day, self.hour, self.minute, self.second
+    )
+  }
+}
+
+/// Fields pointing to the Exif and GPS directories from the first one
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+
+/// When the file was last changed, in the first directory, and when the
+/// photo was taken and digitized, in the Exif directory
+const DATE_TIME: u16 = 0x0132;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const DATE_TIME_DIGITIZED: u16 = 0x9004;
+
+const GPS_VERSION: u16 = 0x0000;
+const GPS_LATITUDE_REF: u16 = 0x0001;
+const GPS_LATITUDE: u16 = 0x0002;
+const GPS_LONGITUDE_REF: u16 = 0x0003;
+const GPS_LONGITUDE: u16 = 0x0004;
+
+/// Field types written here
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+
+/// Bytes per value of a field type, or 0 for unknown types
+fn type_size(kind: u16) -> usize {
+  match kind {
+    1 | 2 | 6 | 7 => 1,
+    3 | 8 => 2,
+    4 | 9 | 11 => 4,
+    5 | 10 | 12 => 8,
+    _ => 0,
+  }
+}
+
+/// Exif data: a TIFF header and directories of tagged fields
+struct Tiff<'a> {
+  data:       &'a [u8],
+  big_endian: bool,
+}
+
+/// A field of a directory, with its values in the file's byte order
+struct Field<'a> {
+  tag:   u16,
+  kind:  u16,
+  value: &'a [u8],
+}
+
+impl<'a> Tiff<'a> {
+  fn new(data: &'a [u8]) -> Option<Self> {
+    let big_endian = match data.get(..4)? {
+      b"MM\0*" => true,
+      b"II*\0" => false,
+      _ => return None,
+    };
+    Some(Self {
+      data,
+      big_endian,
+    })
+  }
+
+  fn u16(&self, bytes: &[u8]) -> Option<u16> {
+    let bytes = [*bytes.first()?, *bytes.get(1)?];
+    Some(if self.big_endian {
+      u16::from_be_bytes(bytes)
+    } else {
+      u16::from_le_bytes(bytes)
+    })
+  }
+
+  fn u32(&self, bytes: &[u8]) -> Option<u32> {
+    let bytes: [u8; 4] = bytes.get(..4)?.try_into().ok()?;
+    Some(if self.big_endian {
+      u32::from_be_bytes(bytes)
+    } else {
+      u32::from_le_bytes(bytes)
+    })
+  }
+
+  /// The fields of the directory at `offset`, leaving out those whose
+  /// values lie outside the data
+  fn directory(&self, offset: usize) -> Option<Vec<Field<'a>>> {
+    let data = self.data;
+    let count = usize::from(self.u16(data.get(offset..)?)?);
+    let entries = data.get(offset + 2..offset + 2 + 12 * count)?;
+    Some(
+      entries
+        .chunks_exact(12)
+        .filter_map(|entry| {
+          let kind = self.u16(&entry[2..])?;
+          let count = self.u32(&entry[4..])? as usize;
+          let size = type_size(kind).checked_mul(count)?;
+          let value = if size <= 4 {
+            &entry[8..8 + size]
+          } else {
+            let at = self.u32(&entry[8..])? as usize;
+            data.get(at..at.checked_add(size)?)?
+          };
+          Some(Field {
+            tag: self.u16(entry)?,
+            kind,
+            value,
+          })
+        })
+        .collect(),
+    )
+  }
+
+  /// The directory that a field of `fields` tagged `tag` points to
+  fn subdirectory(&self, fields: &[Field], tag: u16) -> Option<Vec<Field<'a>>> {
+    let pointer = field(fields, tag)?;
+    self.directory(self.u32(pointer.value)? as usize)
+  }
+
+  /// Three rational values, such as degrees, minutes and seconds
+  fn rationals(&self, field: &Field) -> Option<[f64; 3]> {
+    if field.kind != RATIONAL || field.value.len() < 24 {
+      return None;
+    }
+    Some([0, 8, 16].map(|at| {
+      let part = |i| self.u32(&field.value[i..]).map_or(0.0, f64::from);
+      let (numerator, denominator) = (part(at), part(at + 4));
+      // Unknown parts are written as 0/0
+      if denominator == 0.0 {
+        0.0
+      } else {
+        numerator / denominator
+      }
+    }))
+  }
+}
+
+fn field<'a, 'b>(fields: &'b [Field<'a>], tag: u16) -> Option<&'b Field<'a>> {
+  fields.iter().find(|field| field.tag == tag)
+}
+
+/// The Exif data of a JPEG, PNG or TIFF file, from its TIFF header on
+fn find_exif(bytes: &[u8]) -> Option<&[u8]> {
+  if bytes.starts_with(&[0xff, 0xd8]) {
+    let mut pos = 2;
+    while let Some(&[0xff, marker, hi, lo]) = bytes.get(pos..pos + 4) {
+      // Fill bytes before a marker
+      if marker == 0xff {
+        pos += 1;
+        continue;
+      }
+      // Metadata comes before the start of scan
+      if marker == 0xda || marker == 0xd9 {
+        return None;
+      }
+      let end = pos + 2 + usize::from(u16::from_be_bytes([hi, lo]));
+      let payload = bytes.get(pos + 4..end)?;
+      if marker == 0xe1 && payload.starts_with(b"Exif\0\0") {
+        return Some(&payload[6..]);
+      }
+      pos = end;
+    }
+    None
+  } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+    let mut pos = 8;
+    while let Some(header) = bytes.get(pos..pos + 8) {
+      let len =
+        u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+      let data = pos + 8..(pos + 8).saturating_add(len as usize);
+      match &header[4..] {
+        b"eXIf" => return bytes.get(data),
+        b"IEND" => return None,
+        _ => pos = data.end.saturating_add(4),
+      }
+    }
+    None
+  } else {
+    Tiff::new(bytes).map(|_| bytes)
+  }
+}
+
+/// Where a photo was taken, from the GPS fields of the Exif data of a JPEG,
+/// PNG or TIFF file
+pub(crate) fn read_gps(bytes: &[u8]) -> Option<LatLon> {
+  let tiff = Tiff::new(find_exif(bytes)?)?;
+  let first = tiff.directory(tiff.u32(&tiff.data[4..])? as usize)?;
+  let gps = tiff.subdirectory(&first, GPS_IFD)?;
+  let coordinate = |tag: u16, reference: u16, negative: u8| {
+    let [degrees, minutes, seconds] = tiff.rationals(field(&gps, tag)?)?;
+    let magnitude = degrees + minutes / 60.0 + seconds / 3600.0;
+    let reference = field(&gps, reference).and_then(|f| f.value.first());
+    Some(if reference == Some(&negative) {
+      -magnitude
+    } else {
+      magnitude
+    })
+  };
+  let position = LatLon {
+    latitude:  coordinate(GPS_LATITUDE, GPS_LATITUDE_REF, b'S')?,
+    longitude: coordinate(GPS_LONGITUDE, GPS_LONGITUDE_REF, b'W')?,
+  };
+  (position.latitude.abs() <= 90.0 && position.longitude.abs() <= 180.0)
+    .then_some(position)
+}
+
+/// When a photo was taken, from the Exif data of a JPEG, PNG or TIFF file.
+/// Files without the original time fall back to when they were digitized,
+/// then to when they were last changed.
+pub(crate) fn read_taken_at(bytes: &[u8]) -> Option<DateTime> {
+  let tiff = Tiff::new(find_exif(bytes)?)?;
+  let first = tiff.directory(tiff.u32(&tiff.data[4..])? as usize)?;
+  let exif = tiff.subdirectory(&first, EXIF_IFD).unwrap_or_default();
+  let time =
+    |fields: &[Field], tag| DateTime::from_exif(field(fields, tag)?.value);
+  time(&exif, DATE_TIME_ORIGINAL)
+    .or_else(|| time(&exif, DATE_TIME_DIGITIZED))
+    .or_else(|| time(&first, DATE_TIME))
+}
+
+/// A directory at `offset` holding `fields`, each a tag, type and values,
+/// followed by the values too long to fit in it, all big-endian
+fn write_directory(fields: &[(u16, u16, Vec<u8>)], offset: usize) -> Vec<u8> {
+  let mut out = (fields.len() as u16).to_be_bytes().to_vec();
+  let mut values = Vec::new();
+  let values_offset = offset + 2 + 12 * fields.len() + 4;
+  for (tag, kind, value) in fields {
+    out.extend_from_slice(&tag.to_be_bytes());
+    out.extend_from_slice(&kind.to_be_bytes());
+    let count = value.len() / type_size(*kind);
+    out.extend_from_slice(&(count as u32).to_be_bytes());
+    if value.len() <= 4 {
+      out.extend_from_slice(value);
+      out.extend_from_slice(&[0; 4][value.len()..]);
+    } else {
+      let at = values_offset + values.len();
+      out.extend_from_slice(&(at as u32).to_be_bytes());
+      values.extend_from_slice(value);
+      // Values start on word boundaries
+      if values.len() % 2 == 1 {
+        values.push(0);
+      }
+    }
+  }
+  out.extend_from_slice(&[0; 4]);
+  out.extend(values);
+  out
+}
+
+/// Exif data holding just a position and the time a photo was taken
+fn write_exif(gps: Option<LatLon>, taken_at: Option<DateTime>) -> Vec<u8> {
+  let ascii = |text: &str| {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+  };
+  // Degrees, minutes and thousandths of seconds, rounded as a whole so that
+  // the seconds carry over into the minutes rather than reaching 60
+  let rationals = |degrees: f64| {
+    let thousandths = (degrees.abs() * 3_600_000.0).round() as u32;
+    [
+      (thousandths / 3_600_000, 1u32),
+      (thousandths / 60_000 % 60, 1),
+      (thousandths % 60_000, 1000),
+    ]
+    .into_iter()
+    .flat_map(|(numerator, denominator)| {
+      [numerator.to_be_bytes(), denominator.to_be_bytes()]
+    })
+    .flatten()
+    .collect()
+  };
+
+  let mut exif = Vec::new();
+  if let Some(time) = taken_at {
+    exif.push((DATE_TIME_ORIGINAL, ASCII, ascii(&time.to_exif())));
+  }
+  let mut location = Vec::new();
+  if let Some(LatLon {
+    latitude,
+    longitude,
+  }) = gps
+  {
+    let north = if latitude < 0.0 { "S" } else { "N" };
+    let east = if longitude < 0.0 { "W" } else { "E" };
+    location.extend([
+      (GPS_VERSION, BYTE, vec![2, 3, 0, 0]),
+      (GPS_LATITUDE_REF, ASCII, ascii(north)),
+      (GPS_LATITUDE, RATIONAL, rationals(latitude)),
+      (GPS_LONGITUDE_REF, ASCII, ascii(east)),
+      (GPS_LONGITUDE, RATIONAL, rationals(longitude)),
+    ]);
+  }
+
+  let directories: Vec<_> = [(EXIF_IFD, exif), (GPS_IFD, location)]
+    .into_iter()
+    .filter(|(_, fields)| !fields.is_empty())
+    .collect();
+  let mut pointers = Vec::new();
+  let mut rest = Vec::new();
+  let first_len = 2 + 12 * directories.len() + 4;
+  for (tag, fields) in &directories {
+    let offset = 8 + first_len + rest.len();
+    pointers.push((*tag, LONG, (offset as u32).to_be_bytes().to_vec()));
+    rest.extend(write_directory(fields, offset));
+  }
+  let mut out = b"MM\0*\0\0\0\x08".to_vec();
+  out.extend(write_directory(&pointers, 8));
+  out.extend(rest);
+  out
+}
+
+/// Records where and when a photo was taken in an encoded JPEG or PNG file,
+/// replacing the Exif data it has. Other formats fail with
+/// [`Error::Unsupported`].
+pub(crate) fn write_geotag(
+  bytes: &[u8],
+  gps: Option<LatLon>,
+  taken_at: Option<DateTime>,
+) -> Result<Vec<u8>> {
+  let exif = write_exif(gps, taken_at);
+  if bytes.starts_with(&[0xff, 0xd8]) {
+    let mut app1 = vec![0xff, 0xe1];
+    app1.extend_from_slice(&((2 + 6 + exif.len()) as u16).to_be_bytes());
+    app1.extend_from_slice(b"Exif\0\0");
+    app1.extend_from_slice(&exif);
+
+    let mut out = bytes[..2].to_vec();
+    let mut written = false;
+    let mut pos = 2;
+    loop {
+      let marker = *bytes.get(pos + 1).ok_or_else(truncated)?;
+      if bytes[pos] != 0xff {
+        return Err(Error::Decode("Expected a JPEG marker".to_string()));
+      }
+      if marker == 0xff {
+        pos += 1;
+        continue;
+      }
+      let header = bytes.get(pos + 2..pos + 4).ok_or_else(truncated)?;
+      let end =
+        pos + 2 + usize::from(u16::from_be_bytes([header[0], header[1]]));
+      let segment = bytes.get(pos..end).ok_or_else(truncated)?;
+      let payload = segment.get(4..).unwrap_or_default();
+      // Exif follows JFIF, which must come first
+      let jfif = marker == 0xe0 && payload.starts_with(b"JFIF\0");
+      if !written && !jfif {
+        out.extend_from_slice(&app1);
+        written = true;
+      }
+      if !(marker == 0xe1 && payload.starts_with(b"Exif\0\0")) {
+        out.extend_from_slice(segment);
+      }
+      pos = end;
+      if marker == 0xda {
+        out.extend_from_slice(&bytes[pos..]);
+        return Ok(out);
+      }
+    }
+  } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+    replace_png_chunk(bytes, b"eXIf", &exif)
+  } else {
+    Err(Error::Unsupported(
+      "Only JPEG and PNG files can record a position or time".to_string(),
+    ))
+  }
+}
+
+/// The CRC-32 that PNG chunks end with
+fn crc32(data: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for &byte in data {
+    crc ^= u32::from(byte);
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 {
+        (crc >> 1) ^ 0xedb8_8320
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  !crc
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn strips_jpeg_and_png_metadata() {
+    let mut jpeg = vec![0xff, 0xd8];
+    jpeg.extend_from_slice(&[0xff, 0xe0, 0, 7, b'J', b'F', b'I', b'F', 0]);
+    jpeg.extend_from_slice(&[0xff, 0xe1, 0, 8, b'E', b'x', b'i', b'f', 0, 0]);
+    jpeg.extend_from_slice(&[0xff, 0xfe, 0, 4, b'h', b'i']);
+    jpeg.extend_from_slice(&[0xff, 0xda, 0, 2, 1, 2, 3, 0xff, 0xd9]);
+    let clean = strip_metadata(&jpeg).unwrap();
+    assert_eq!(clean.len(), jpeg.len() - 16);
+    assert!(!clean.windows(4).any(|w| w == b"Exif"));
+    // Segments too short to hold their own length, or cut off
+    assert!(strip_metadata(&[0xff, 0xd8, 0xff, 0xe1, 0, 0]).is_err());
+    assert!(strip_metadata(&[0xff, 0xd8, 0xff, 0xe1, 0, 1, 0xff]).is_err());
+    assert!(strip_metadata(&[0xff, 0xd8, 0xff, 0xe1, 0, 9, b'E']).is_err());
+
+    // A progressive file with metadata between its scans, whose data holds
+    // stuffed zeros and restart markers
+    let mut progressive = vec![0xff, 0xd8];
+    progressive.extend_from_slice(&[0xff, 0xc2, 0, 3, 8]);
+    progressive.extend_from_slice(&[0xff, 0xda, 0, 3, 1, 0xff, 0, 2]);
+    progressive.extend_from_slice(&[0xff, 0xd0, 3, 0xff, 0xff, 0xd1, 4]);
+    let first_scan = progressive.clone();
+    progressive.extend_from_slice(&[0xff, 0xe1, 0, 6, b'E', b'x', b'i', b'f']);
+    progressive.extend_from_slice(&[0xff, 0xfe, 0, 4, b'h', b'i']);
+    let mut expected = first_scan.clone();
+    let second_scan = [0xff, 0xc4, 0, 3, 9, 0xff, 0xda, 0, 3, 5, 6, 0xff, 0xd9];
+    progressive.extend_from_slice(&second_scan);
+    expected.extend_from_slice(&second_scan);
+    progressive.extend_from_slice(b"trailer");
+    assert_eq!(strip_metadata(&progressive).unwrap(), expected);
+
+    let chunk = |kind: &[u8], data: &[u8]| {
+      let mut c = (data.len() as u32).to_be_bytes().to_vec();
+      c.extend_from_slice(kind);
+      c.extend_from_slice(data);
+      c.extend_from_slice(&[0; 4]);
+      c
+    };
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png.extend(chunk(b"IHDR", &[0; 13]));
+    png.extend(chunk(b"tEXt", b"Author\0someone"));
+    png.extend(chunk(b"gAMA", &[0; 4]));
+    png.extend(chunk(b"IDAT", &[1, 2, 3]));
+    png.extend(chunk(b"IEND", &[]));
+    let clean = strip_metadata(&png).unwrap();
+    assert_eq!(clean.len(), png.len() - 26);
+    assert!(strip_metadata(&png[..20]).is_err());
+    assert!(strip_metadata(b"GIF89a").is_err());
+  }
+
+  #[test]
+  fn dpi_round_trips_through_headers() {
+    assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    let jpeg = [0xff, 0xd8, 0xff, 0xda, 0, 2, 0xff, 0xd9];
+    let with_dpi = write_dpi(&jpeg, (300.0, 150.0)).unwrap();
+    assert_eq!(read_dpi(&with_dpi), Some((300.0, 150.0)));
+    let again = write_dpi(&with_dpi, (72.0, 72.0)).unwrap();
+    assert_eq!(again.len(), with_dpi.len());
+    assert_eq!(read_dpi(&again), Some((72.0, 72.0)));
+    assert_eq!(read_dpi(&jpeg), None);
+  }
+
+  #[test]
+  fn geotags_round_trip_through_exif() {
+    let place = LatLon {
+      latitude:  -33.856_784,
+      longitude: 151.215_297,
+    };
+    let time = DateTime {
+      year:   2024,
+      month:  3,
+      day:    9,
+      hour:   17,
+      minute: 5,
+      second: 42,
+    };
+    let close = |a: LatLon| {
+      (a.latitude - place.latitude).abs() < 1e-6
+        && (a.longitude - place.longitude).abs() < 1e-6
+    };
+
+    let jpeg = [0xff, 0xd8, 0xff, 0xda, 0, 2, 0xff, 0xd9];
+    let with_dpi = write_dpi(&jpeg, (300.0, 300.0)).unwrap();
+    let tagged = write_geotag(&with_dpi, Some(place), Some(time)).unwrap();
+    assert!(close(read_gps(&tagged).unwrap()));
+    assert_eq!(read_taken_at(&tagged), Some(time));
+    assert_eq!(read_dpi(&tagged), Some((300.0, 300.0)));
+    // Writing again replaces the Exif data
+    let moved = write_geotag(&tagged, None, Some(time)).unwrap();
+    assert_eq!(read_gps(&moved), None);
+    assert_eq!(read_taken_at(&moved), Some(time));
+    assert_eq!(time.to_string(), "2024-03-09 17:05:42");
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (kind, data) in [(&b"IHDR"[..], &[0; 13][..]), (b"IEND", &[])] {
+      png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+      png.extend_from_slice(kind);
+      png.extend_from_slice(data);
+      png.extend_from_slice(&[0; 4]);
+    }
+    let tagged = write_geotag(&png, Some(place), None).unwrap();
+    assert!(close(read_gps(&tagged).unwrap()));
+    assert_eq!(read_taken_at(&tagged), None);
+
+    // Seconds that round up to 60 carry over into the minutes and degrees
+    let edge = LatLon {
+      latitude:  1.0 - 1e-9,
+      longitude: 0.0,
+    };
+    let exif = write_exif(Some(edge), None);
+    let one_degree: Vec<u8> = [1u32, 1, 0, 1, 0, 1000]
+      .iter()
+      .flat_map(|v| v.to_be_bytes())
+      .collect();
+    assert!(exif.windows(24).any(|w| w == one_degree));
+
+    // Other formats cannot hold the tags
+    let tiff_file = b"MM\0*\0\0\0\x08\0\0\0\0\0\0";
+    assert!(matches!(
+      write_geotag(tiff_file, Some(place), None),
+      Err(Error::Unsupported(_))
+    ));
+
+    // Little-endian Exif as cameras write it, with an unset clock
+    let mut tiff = b"II*\0\x08\0\0\0\x01\0".to_vec();
+    tiff.extend_from_slice(&[0x32, 0x01, 2, 0, 20, 0, 0, 0, 26, 0, 0, 0]);
+    tiff.extend_from_slice(&[0; 4]);
+    tiff.extend_from_slice(b"    :  :     :  :  \0");
+    assert_eq!(read_taken_at(&tiff), None);
+    tiff.splice(26..45, *b"2001:02:03 04:05:06");
+    assert_eq!(read_taken_at(&tiff).map(|t| t.second), Some(6));
+  }
+}
@@ -0,0 +1,717 @@
+//! Decoding of encoded image files into [`Image`]s, and encoding them back.
+//!
+//! Each codec is behind a crate feature of the same name (`png`, `jpeg`,
+//! `tiff`, `bmp`). Decoders never panic on malformed input; every failure is
+//! reported as an [`Error`].
+//!
+//! CMYK TIFF and JPEG files decode to [`ColorSpace::Cmyk`]; everything else
+//! decodes to RGB or RGBA. 16-bit samples are converted from the file's byte
+//! order (big-endian for PNG, either for TIFF) to native-endian `u16`s.
+//! Decoded pixels are always top-down. Formats that store rows bottom-up are
+//! flipped while decoding, and the original order is kept in
+//! [`Image::source_origin`].
+
+use std::{io::Cursor, path::Path};
+
+use image::{metadata::Orientation, ImageDecoder};
+
+use crate::{
+  color_space::{cmyk_to_rgb, ColorSpace},
+  error::{Error, Result},
+  image::Implementation,
+  image_buffer::Origin,
+  pixel::{PixelComponent, PixelContainer},
+  progress::Progress,
+  Image,
+  ImageBuffer,
+};
+
+#[cfg(any(feature = "jpeg", feature = "tiff"))]
+mod cmyk;
+mod metadata;
+mod options;
+pub mod packed;
+#[cfg(feature = "ora")]
+pub mod ora;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+mod plugin;
+#[cfg(feature = "psd")]
+pub mod psd;
+#[cfg(feature = "svg")]
+pub mod svg;
+
+pub use metadata::{strip_metadata, DateTime, LatLon};
+pub use options::{DecodeOptions, TargetColorSpace};
+pub use plugin::{
+  codec_for_data,
+  codec_for_extension,
+  codec_names,
+  register_codec,
+  CodecPlugin,
+};
+
+/// Encoded file formats known to this crate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+  Png,
+  Jpeg,
+  Tiff,
+  Bmp,
+}
+
+impl ImageFormat {
+  /// Guesses the format from the leading bytes of an encoded file
+  pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+    match image::guess_format(bytes).ok()? {
+      image::ImageFormat::Png => Some(ImageFormat::Png),
+      image::ImageFormat::Jpeg => Some(ImageFormat::Jpeg),
+      image::ImageFormat::Tiff => Some(ImageFormat::Tiff),
+      image::ImageFormat::Bmp => Some(ImageFormat::Bmp),
+      _ => None,
+    }
+  }
+
+  /// Whether the codec for this format was compiled in
+  pub fn is_enabled(self) -> bool {
+    match self {
+      ImageFormat::Png => cfg!(feature = "png"),
+      ImageFormat::Jpeg => cfg!(feature = "jpeg"),
+      ImageFormat::Tiff => cfg!(feature = "tiff"),
+      ImageFormat::Bmp => cfg!(feature = "bmp"),
+    }
+  }
+
+  /// Guesses the format from a file extension, ignoring case
+  pub fn from_extension(extension: &str) -> Option<Self> {
+    match extension.to_ascii_lowercase().as_str() {
+      "png" => Some(ImageFormat::Png),
+      "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+      "tif" | "tiff" => Some(ImageFormat::Tiff),
+      "bmp" => Some(ImageFormat::Bmp),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn to_image_format(self) -> image::ImageFormat {
+    match self {
+      ImageFormat::Png => image::ImageFormat::Png,
+      ImageFormat::Jpeg => image::ImageFormat::Jpeg,
+      ImageFormat::Tiff => image::ImageFormat::Tiff,
+      ImageFormat::Bmp => image::ImageFormat::Bmp,
+    }
+  }
+}
+
+/// Decodes an encoded image, detecting its format from the data
+pub fn decode(bytes: &[u8]) -> Result<Image> {
+  decode_with_options(bytes, None, &DecodeOptions::default())
+}
+
+/// Decodes an encoded image of the given format
+pub fn decode_with_format(bytes: &[u8], format: ImageFormat) -> Result<Image> {
+  decode_with_options(bytes, Some(format), &DecodeOptions::default())
+}
+
+/// Decodes an encoded image with the given settings, detecting its format
+/// from the data unless `format` is given.
+///
+/// Fails with [`Error::LimitExceeded`] if the header announces an image
+/// beyond [`DecodeOptions::limits`], before decoding any pixels.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(bytes = bytes.len(), format, width, height)
+  )
+)]
+pub fn decode_with_options(
+  bytes: &[u8],
+  format: Option<ImageFormat>,
+  options: &DecodeOptions,
+) -> Result<Image> {
+  let format = match format.or_else(|| ImageFormat::from_magic(bytes)) {
+    Some(format) => format,
+    None => {
+      let codec = codec_for_data(bytes).ok_or_else(|| {
+        Error::Unsupported("Unrecognized image format".to_string())
+      })?;
+      let image = codec.decode(bytes, options)?;
+      options.limits.check_dimensions(image.width(), image.height())?;
+      return options::apply(image, Orientation::NoTransforms, options);
+    }
+  };
+  if !format.is_enabled() {
+    return Err(Error::Unsupported(format!(
+      "{format:?} support is not enabled"
+    )));
+  }
+  let mut decoder = image::ImageReader::with_format(
+    Cursor::new(bytes),
+    format.to_image_format(),
+  )
+  .into_decoder()?;
+  let (width, height) = decoder.dimensions();
+  #[cfg(feature = "tracing")]
+  tracing::Span::current()
+    .record("format", tracing::field::debug(format))
+    .record("width", width)
+    .record("height", height);
+  options.limits.check_image(
+    width as usize,
+    height as usize,
+    usize::from(decoder.color_type().bytes_per_pixel()),
+  )?;
+  options.limits.check_frames(frame_count(bytes, format))?;
+  decoder.set_limits(options.limits.to_image_limits())?;
+  // Malformed Exif data is not worth failing the decode over
+  let orientation = if options.apply_orientation {
+    decoder.orientation().unwrap_or(Orientation::NoTransforms)
+  } else {
+    Orientation::NoTransforms
+  };
+
+  #[cfg(any(feature = "jpeg", feature = "tiff"))]
+  if let Some(mut image) = cmyk::decode(bytes, format)? {
+    image.dpi = metadata::read_dpi(bytes);
+    image.gps = metadata::read_gps(bytes);
+    image.taken_at = metadata::read_taken_at(bytes);
+    return options::apply(image, orientation, options);
+  }
+  let decoded = image::DynamicImage::from_decoder(decoder)?;
+  let mut image = from_dynamic(decoded)?;
+  image.source_origin = source_origin(bytes, format);
+  image.dpi = metadata::read_dpi(bytes);
+  image.gps = metadata::read_gps(bytes);
+  image.taken_at = metadata::read_taken_at(bytes);
+  options::apply(image, orientation, options)
+}
+
+/// Frames in an animated file, read from its header. Only the first frame
+/// is decoded, but the count is still held to [`Limits::max_frames`].
+///
+/// [`Limits::max_frames`]: crate::Limits::max_frames
+fn frame_count(bytes: &[u8], format: ImageFormat) -> usize {
+  if format != ImageFormat::Png {
+    return 1;
+  }
+  // APNG announces its frame count in an `acTL` chunk ahead of the image
+  // data
+  let mut pos = 8;
+  while let Some(header) = bytes.get(pos..pos + 8) {
+    let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    match &header[4..] {
+      b"acTL" =>
+        return bytes
+          .get(pos + 8..pos + 12)
+          .map_or(1, |n| u32::from_be_bytes([n[0], n[1], n[2], n[3]]) as usize),
+      b"IDAT" | b"IEND" => break,
+      _ => pos = pos.saturating_add(12).saturating_add(len as usize),
+    }
+  }
+  1
+}
+
+/// Row order of the encoded data. The `image` crate already flips bottom-up
+/// files, so this is only recorded, not applied.
+fn source_origin(bytes: &[u8], format: ImageFormat) -> Origin {
+  match format {
+    // BITMAPCOREHEADER heights are unsigned and always bottom-up; in later
+    // headers a negative height marks a top-down file
+    ImageFormat::Bmp =>
+      match bytes.get(14..18) {
+        Some([12, 0, 0, 0]) => Origin::BottomLeft,
+        Some(_) =>
+          match bytes.get(22..26) {
+            Some(&[a, b, c, d]) if i32::from_le_bytes([a, b, c, d]) < 0 =>
+              Origin::TopLeft,
+            _ => Origin::BottomLeft,
+          },
+        None => Origin::BottomLeft,
+      },
+    _ => Origin::TopLeft,
+  }
+}
+
+impl Image {
+  /// Reads and decodes the image file at `path`, detecting its format from
+  /// the file contents
+  pub fn open(
+    path: impl AsRef<Path>,
+    options: &DecodeOptions,
+  ) -> Result<Image> {
+    decode_with_options(&std::fs::read(path)?, None, options)
+  }
+
+  /// Encodes the image in the format named by the extension of `path`, as
+  /// described for [`encode`], and writes it there. Extensions the crate
+  /// does not know are looked up among the registered [`CodecPlugin`]s.
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let bytes = match extension.and_then(ImageFormat::from_extension) {
+      Some(format) => encode(self, format)?,
+      None =>
+        extension
+          .and_then(codec_for_extension)
+          .ok_or_else(|| {
+            Error::Unsupported(format!(
+              "Cannot tell the image format of {}",
+              path.display()
+            ))
+          })?
+          .encode(self)?,
+    };
+    std::fs::write(path, bytes)?;
+    Ok(())
+  }
+}
+
+/// Color components of `data` with its alpha channel, if any, converting
+/// CMYK to RGB
+fn rgb_parts<T: PixelComponent>(
+  data: &ColorSpace<T>,
+) -> Result<(bool, Vec<T>)> {
+  match data {
+    ColorSpace::Rgb(buf) => Ok((false, buf.components().to_vec())),
+    ColorSpace::Rgba(buf) => Ok((true, buf.components().to_vec())),
+    ColorSpace::Cmyk(buf) =>
+      Ok((
+        false,
+        buf.iter_pixels().flat_map(cmyk_to_rgb::<T, T>).collect(),
+      )),
+    ColorSpace::Hsv(_) | ColorSpace::Cielab(_) =>
+      Err(Error::Unsupported(
+        "Only RGB, RGBA and CMYK images can be encoded".to_string(),
+      )),
+  }
+}
+
+/// Converts an [`Image`] into an `image` crate buffer for encoding
+fn to_dynamic(image: &Image) -> Result<image::DynamicImage> {
+  use image::DynamicImage as D;
+
+  let too_large = || Error::Unsupported("Image is too large to encode".into());
+  let width = u32::try_from(image.width()).map_err(|_| too_large())?;
+  let height = u32::try_from(image.height()).map_err(|_| too_large())?;
+  let mismatch = || Error::Decode("Pixel data does not match size".into());
+  Ok(match &image.imp {
+    Implementation::U8(imp) =>
+      match rgb_parts(&imp.data)? {
+        (true, data) =>
+          D::ImageRgba8(
+            image::RgbaImage::from_raw(width, height, data)
+              .ok_or_else(mismatch)?,
+          ),
+        (false, data) =>
+          D::ImageRgb8(
+            image::RgbImage::from_raw(width, height, data)
+              .ok_or_else(mismatch)?,
+          ),
+      },
+    Implementation::U16(imp) =>
+      match rgb_parts(&imp.data)? {
+        (true, data) =>
+          D::ImageRgba16(
+            image::ImageBuffer::from_raw(width, height, data)
+              .ok_or_else(mismatch)?,
+          ),
+        (false, data) =>
+          D::ImageRgb16(
+            image::ImageBuffer::from_raw(width, height, data)
+              .ok_or_else(mismatch)?,
+          ),
+      },
+    Implementation::F32(imp) =>
+      match rgb_parts(&imp.data)? {
+        (true, data) =>
+          D::ImageRgba32F(
+            image::Rgba32FImage::from_raw(width, height, data)
+              .ok_or_else(mismatch)?,
+          ),
+        (false, data) =>
+          D::ImageRgb32F(
+            image::Rgb32FImage::from_raw(width, height, data)
+              .ok_or_else(mismatch)?,
+          ),
+      },
+    Implementation::U32(_) | Implementation::F64(_) =>
+      D::ImageRgba32F(
+        image::Rgba32FImage::from_raw(
+          width,
+          height,
+          image.to_rgba_f32()?.components().to_vec(),
+        )
+        .ok_or_else(mismatch)?,
+      ),
+  })
+}
+
+/// Encodes an image as `format`.
+///
+/// Components are converted to what the format can store: 8 bits for JPEG
+/// and BMP, and 8 or 16 bits for PNG and TIFF, with floating-point images
+/// stored at 16 bits. JPEG drops alpha, and CMYK images are written as RGB.
+/// Besides the pixels, only the [`dpi`](Image::dpi) and, as Exif, the
+/// [`gps`](Image::gps) position and [`taken_at`](Image::taken_at) time are
+/// written, to PNG and JPEG files; there is no text or other metadata.
+/// Images with a position or time fail with [`Error::Unsupported`] in other
+/// formats, rather than losing them.
+pub fn encode(image: &Image, format: ImageFormat) -> Result<Vec<u8>> {
+  encode_with_progress(image, format, &Progress::new())
+}
+
+/// [`encode`], reporting to `progress` between converting the pixels,
+/// compressing them and writing metadata, and failing with
+/// [`Error::Cancelled`] if it is cancelled at any of those points
+pub fn encode_with_progress(
+  image: &Image,
+  format: ImageFormat,
+  progress: &Progress,
+) -> Result<Vec<u8>> {
+  if !format.is_enabled() {
+    return Err(Error::Unsupported(format!(
+      "{format:?} support is not enabled"
+    )));
+  }
+  progress.report(0.0)?;
+  let dynamic = to_dynamic(image)?;
+  let alpha = dynamic.color().has_alpha();
+  let float = matches!(
+    dynamic,
+    image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)
+  );
+  let dynamic = match format {
+    ImageFormat::Jpeg => image::DynamicImage::ImageRgb8(dynamic.to_rgb8()),
+    ImageFormat::Bmp if alpha =>
+      image::DynamicImage::ImageRgba8(dynamic.to_rgba8()),
+    ImageFormat::Bmp => image::DynamicImage::ImageRgb8(dynamic.to_rgb8()),
+    _ if float && alpha =>
+      image::DynamicImage::ImageRgba16(dynamic.to_rgba16()),
+    _ if float => image::DynamicImage::ImageRgb16(dynamic.to_rgb16()),
+    _ => dynamic,
+  };
+  // Compression takes most of the time
+  progress.report(0.2)?;
+  let mut bytes = Vec::new();
+  dynamic.write_to(&mut Cursor::new(&mut bytes), format.to_image_format())?;
+  progress.report(0.9)?;
+  if let Some(dpi) = image.dpi {
+    bytes = metadata::write_dpi(&bytes, dpi)?;
+  }
+  if image.gps.is_some() || image.taken_at.is_some() {
+    bytes = metadata::write_geotag(&bytes, image.gps, image.taken_at)?;
+  }
+  progress.report(1.0)?;
+  Ok(bytes)
+}
+
+fn buffer<T: PixelComponent, const N: usize, const A: bool>(
+  data: Vec<T>,
+  width: u32,
+  height: u32,
+) -> Result<ImageBuffer<T, N, A>> {
+  ImageBuffer::try_with_data(data, width as usize, height as usize)
+    .map_err(|e| Error::Decode(e.to_string()))
+}
+
+fn gray_to_rgb<T: Copy>(data: &[T]) -> Vec<T> {
+  data.iter().flat_map(|&v| [v, v, v]).collect()
+}
+
+fn gray_alpha_to_rgba<T: Copy>(data: &[T]) -> Vec<T> {
+  data
+    .chunks_exact(2)
+    .flat_map(|pel| [pel[0], pel[0], pel[0], pel[1]])
+    .collect()
+}
+
+/// Converts a decoded `image` crate buffer into an [`Image`], expanding gray
+/// images to RGB
+fn from_dynamic(decoded: image::DynamicImage) -> Result<Image> {
+  use image::DynamicImage as D;
+
+  let (width, height) = (decoded.width(), decoded.height());
+  let image = match decoded {
+    D::ImageLuma8(b) =>
+      Image::new_u8(ColorSpace::Rgb(buffer(
+        gray_to_rgb(b.as_raw()),
+        width,
+        height,
+      )?)),
+    D::ImageLumaA8(b) =>
+      Image::new_u8(ColorSpace::Rgba(buffer(
+        gray_alpha_to_rgba(b.as_raw()),
+        width,
+        height,
+      )?)),
+    D::ImageRgb8(b) =>
+      Image::new_u8(ColorSpace::Rgb(buffer(b.into_raw(), width, height)?)),
+    D::ImageRgba8(b) =>
+      Image::new_u8(ColorSpace::Rgba(buffer(b.into_raw(), width, height)?)),
+    D::ImageLuma16(b) =>
+      Image::new_u16(ColorSpace::Rgb(buffer(
+        gray_to_rgb(b.as_raw()),
+        width,
+        height,
+      )?)),
+    D::ImageLumaA16(b) =>
+      Image::new_u16(ColorSpace::Rgba(buffer(
+        gray_alpha_to_rgba(b.as_raw()),
+        width,
+        height,
+      )?)),
+    D::ImageRgb16(b) =>
+      Image::new_u16(ColorSpace::Rgb(buffer(b.into_raw(), width, height)?)),
+    D::ImageRgba16(b) =>
+      Image::new_u16(ColorSpace::Rgba(buffer(b.into_raw(), width, height)?)),
+    D::ImageRgb32F(b) =>
+      Image::new_f32(ColorSpace::Rgb(buffer(b.into_raw(), width, height)?)),
+    D::ImageRgba32F(b) =>
+      Image::new_f32(ColorSpace::Rgba(buffer(b.into_raw(), width, height)?)),
+    other =>
+      Image::new_f32(ColorSpace::Rgba(buffer(
+        other.into_rgba32f().into_raw(),
+        width,
+        height,
+      )?)),
+  };
+  Ok(image)
+}
+
+/// Entry points for the targets under `fuzz/`. Not part of the public API.
+#[doc(hidden)]
+pub mod fuzz {
+  use super::*;
+
+  /// Decodes `data` as `format`. Any panic reached from here is a bug: all
+  /// malformed input must come back as an [`Error`].
+  pub fn decode(format: Option<ImageFormat>, data: &[u8]) -> Result<()> {
+    let image = match format {
+      Some(format) => decode_with_format(data, format)?,
+      None => super::decode(data)?,
+    };
+    let _ = (image.width(), image.height());
+    Ok(())
+  }
+}
+
+#[cfg(all(test, feature = "png"))]
+mod tests {
+  use std::io::Cursor;
+
+  use super::*;
+  use crate::PixelContainer;
+
+  fn encoded_png() -> Vec<u8> {
+    let source = image::GrayImage::from_pixel(3, 2, image::Luma([7u8]));
+    let mut bytes = Vec::new();
+    source
+      .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+      .unwrap();
+    bytes
+  }
+
+  #[test]
+  fn decode_gray_png_as_rgb() {
+    let image = decode(&encoded_png()).unwrap();
+    assert_eq!((image.width(), image.height()), (3, 2));
+    assert_eq!(image.source_origin(), Origin::TopLeft);
+    assert_eq!(
+      ImageFormat::from_magic(&encoded_png()),
+      Some(ImageFormat::Png)
+    );
+  }
+
+  #[test]
+  fn decode_malformed_input_is_an_error() {
+    assert!(matches!(
+      decode(b"not an image"),
+      Err(Error::Unsupported(_))
+    ));
+    let truncated = &encoded_png()[..20];
+    assert!(matches!(
+      decode_with_format(truncated, ImageFormat::Png),
+      Err(Error::Decode(_) | Error::Io(_))
+    ));
+  }
+
+  #[test]
+  fn decode_options_orient_convert_and_limit() {
+    use image::ImageEncoder;
+
+    // Exif block holding just orientation 6: rotate 90 degrees clockwise
+    let mut exif = b"MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+    exif.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+    exif.extend_from_slice(&[0; 4]);
+    let source =
+      image::GrayImage::from_fn(3, 2, |x, y| image::Luma([(y * 3 + x) as u8]));
+    let mut bytes = Vec::new();
+    let mut encoder = image::codecs::png::PngEncoder::new(&mut bytes);
+    encoder.set_exif_metadata(exif).unwrap();
+    encoder
+      .write_image(source.as_raw(), 3, 2, image::ExtendedColorType::L8)
+      .unwrap();
+
+    let options = DecodeOptions {
+      target_color_space: Some(TargetColorSpace::Rgba),
+      ..Default::default()
+    };
+    match decode_with_options(&bytes, None, &options).unwrap().imp {
+      crate::image::Implementation::U8(imp) =>
+        match imp.data {
+          ColorSpace::Rgba(buf) => {
+            assert_eq!((buf.width, buf.height), (2, 3));
+            let firsts: Vec<u8> = buf.iter_pixels().map(|p| p[0]).collect();
+            assert_eq!(firsts, [3, 0, 4, 1, 5, 2]);
+            assert_eq!(buf.get_pixel(0, 0)[3], 255);
+          }
+          _ => panic!("Wrong color space"),
+        },
+      _ => panic!("Wrong component type"),
+    }
+    let upright = DecodeOptions {
+      apply_orientation: false,
+      ..Default::default()
+    };
+    assert_eq!(
+      decode_with_options(&bytes, None, &upright).unwrap().width(),
+      3
+    );
+
+    let limited = DecodeOptions {
+      limits: crate::Limits {
+        max_width: Some(2),
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    assert!(matches!(
+      decode_with_options(&bytes, None, &limited),
+      Err(Error::LimitExceeded(_))
+    ));
+  }
+
+  #[test]
+  fn encode_round_trip() {
+    let image = decode(&encoded_png()).unwrap();
+    let bytes = encode(&image, ImageFormat::Png).unwrap();
+    let decoded = decode(&bytes).unwrap();
+    assert_eq!(decoded.color_space_name(), "RGB");
+    let progress = Progress::new();
+    progress.cancel();
+    assert!(matches!(
+      encode_with_progress(&image, ImageFormat::Png, &progress),
+      Err(Error::Cancelled)
+    ));
+    assert_eq!(
+      decoded.to_rgba_f32().unwrap().get_pixel(2, 1)[0],
+      7.0 / 255.0
+    );
+
+    let mut float = Image::new_f64(ColorSpace::Rgb(ImageBuffer::with_val(
+      &[0.5, 0.25, 1.0],
+      2,
+      2,
+    )));
+    float.set_dpi(Some((300.0, 150.0)));
+    let taken_at = DateTime {
+      year:   2023,
+      month:  12,
+      day:    31,
+      hour:   23,
+      minute: 59,
+      second: 0,
+    };
+    float.set_taken_at(Some(taken_at));
+    float.set_gps(Some(LatLon {
+      latitude:  48.0,
+      longitude: -122.5,
+    }));
+    let decoded = decode(&encode(&float, ImageFormat::Png).unwrap()).unwrap();
+    assert_eq!(decoded.component_type(), crate::image::ComponentType::U16);
+    let (x, y) = decoded.dpi().unwrap();
+    assert!((x - 300.0).abs() < 0.05 && (y - 150.0).abs() < 0.05);
+    assert_eq!(decoded.taken_at(), Some(taken_at));
+    assert_eq!(decoded.gps().map(|p| p.longitude), Some(-122.5));
+    assert_eq!(ImageFormat::from_extension("JPG"), Some(ImageFormat::Jpeg));
+  }
+
+  /// A made-up format: `TINY`, width, height, then gray bytes
+  struct Tiny;
+
+  impl CodecPlugin for Tiny {
+    fn name(&self) -> &str { "tiny" }
+
+    fn extensions(&self) -> &[&str] { &["tiny"] }
+
+    fn matches(&self, bytes: &[u8]) -> bool { bytes.starts_with(b"TINY") }
+
+    fn decode(&self, bytes: &[u8], _: &DecodeOptions) -> Result<Image> {
+      let (width, height) = (bytes[4] as usize, bytes[5] as usize);
+      let gray = ImageBuffer::<u8, 1, false>::try_with_data(
+        bytes[6..].to_vec(),
+        width,
+        height,
+      )?;
+      let mut rgb = ImageBuffer::<u8, 3, false>::empty(width, height);
+      for (out, pel) in rgb.iter_pixels_mut().zip(gray.iter_pixels()) {
+        *out = [pel[0]; 3];
+      }
+      Ok(Image::new_u8(ColorSpace::Rgb(rgb)))
+    }
+  }
+
+  #[test]
+  fn plugin_codecs_decode_unknown_formats() {
+    register_codec(std::sync::Arc::new(Tiny));
+    assert!(codec_names().contains(&"tiny".to_string()));
+    let image = decode(b"TINY\x02\x01\x10\x20").unwrap();
+    assert_eq!((image.width(), image.height()), (2, 1));
+    let options = DecodeOptions {
+      target_color_space: Some(TargetColorSpace::Rgba),
+      ..DecodeOptions::default()
+    };
+    let image =
+      decode_with_options(b"TINY\x02\x01\x10\x20", None, &options).unwrap();
+    assert_eq!(image.color_space_name(), "RGBA");
+    assert!(matches!(
+      codec_for_extension("TINY").unwrap().encode(&image),
+      Err(Error::Unsupported(_))
+    ));
+  }
+
+  #[test]
+  fn bmp_source_origin_from_header() {
+    let mut header = vec![0u8; 26];
+    header[..2].copy_from_slice(b"BM");
+    header[14] = 40;
+    header[22..26].copy_from_slice(&2i32.to_le_bytes());
+    assert_eq!(source_origin(&header, ImageFormat::Bmp), Origin::BottomLeft);
+    header[22..26].copy_from_slice(&(-2i32).to_le_bytes());
+    assert_eq!(source_origin(&header, ImageFormat::Bmp), Origin::TopLeft);
+    assert_eq!(source_origin(&header, ImageFormat::Png), Origin::TopLeft);
+  }
+
+  #[test]
+  fn decode_16_bit_png_is_native_endian() {
+    let source = image::ImageBuffer::<image::Luma<u16>, _>::from_pixel(
+      1,
+      1,
+      image::Luma([0x0102u16]),
+    );
+    let mut bytes = Vec::new();
+    source
+      .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+      .unwrap();
+    match decode(&bytes).unwrap().imp {
+      crate::image::Implementation::U16(imp) =>
+        match imp.data {
+          ColorSpace::Rgb(buf) => assert_eq!(buf.components(), &[0x0102; 3]),
+          _ => panic!("Wrong color space"),
+        },
+      _ => panic!("Wrong component type"),
+    }
+  }
+}
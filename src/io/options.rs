@@ -0,0 +1,183 @@
+//! Settings applied while decoding: orientation, output color space and
+//! resource limits.
+
+use image::metadata::Orientation;
+
+use super::ColorSpace;
+use crate::{
+  color_space::ColorSpaceKind,
+  error::Result,
+  image::{ImageFactory, Implementation},
+  limits::Limits,
+  pixel::{PixelComponent, PixelContainer},
+  Image,
+  ImageBuffer,
+};
+
+/// Color spaces decoded images can be converted to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TargetColorSpace {
+  Rgb,
+  /// RGB with alpha, opaque where the file has no alpha channel
+  Rgba,
+  Cmyk,
+}
+
+/// Settings for [`decode_with_options`](super::decode_with_options) and
+/// [`Image::open`]
+#[derive(Clone, Debug)]
+pub struct DecodeOptions {
+  /// Rotates and flips the image upright as its Exif orientation says
+  pub apply_orientation:  bool,
+  /// Converts the decoded image to this color space, instead of keeping the
+  /// one it was stored in
+  pub target_color_space: Option<TargetColorSpace>,
+  /// Checked against the file header before any pixels are decoded, and
+  /// passed on to the codecs. Guards against decompression bombs in
+  /// untrusted input.
+  pub limits:             Limits,
+}
+
+impl Default for DecodeOptions {
+  fn default() -> Self {
+    DecodeOptions {
+      apply_orientation:  true,
+      target_color_space: None,
+      limits:             Limits::default(),
+    }
+  }
+}
+
+/// Whether `orientation` turns the image a quarter turn, swapping its
+/// axes
+fn transposes(orientation: Orientation) -> bool {
+  matches!(
+    orientation,
+    Orientation::Rotate90
+      | Orientation::Rotate270
+      | Orientation::Rotate90FlipH
+      | Orientation::Rotate270FlipH
+  )
+}
+
+/// Rearranges `image` so that it displays upright
+fn orient<T: PixelComponent, const N: usize, const A: bool>(
+  image: ImageBuffer<T, N, A>,
+  orientation: Orientation,
+) -> ImageBuffer<T, N, A> {
+  if orientation == Orientation::NoTransforms {
+    return image;
+  }
+  let (width, height) = (image.width, image.height);
+  let (out_width, out_height) = if transposes(orientation) {
+    (height, width)
+  } else {
+    (width, height)
+  };
+  ImageBuffer::empty(out_width, out_height).map_indexed(&mut |x, y, _| {
+    let (sx, sy) = match orientation {
+      Orientation::NoTransforms => (x, y),
+      Orientation::Rotate90 => (y, height - 1 - x),
+      Orientation::Rotate180 => (width - 1 - x, height - 1 - y),
+      Orientation::Rotate270 => (width - 1 - y, x),
+      Orientation::FlipHorizontal => (width - 1 - x, y),
+      Orientation::FlipVertical => (x, height - 1 - y),
+      Orientation::Rotate90FlipH => (y, x),
+      Orientation::Rotate270FlipH => (width - 1 - y, height - 1 - x),
+    };
+    *image.get_pixel(sx, sy)
+  })
+}
+
+fn orient_data<T: PixelComponent>(
+  data: ColorSpace<T>,
+  orientation: Orientation,
+) -> ColorSpace<T> {
+  match data {
+    ColorSpace::Rgba(buf) => ColorSpace::Rgba(orient(buf, orientation)),
+    ColorSpace::Rgb(buf) => ColorSpace::Rgb(orient(buf, orientation)),
+    ColorSpace::Hsv(buf) => ColorSpace::Hsv(orient(buf, orientation)),
+    ColorSpace::Cielab(buf) => ColorSpace::Cielab(orient(buf, orientation)),
+    ColorSpace::Cmyk(buf) => ColorSpace::Cmyk(orient(buf, orientation)),
+  }
+}
+
+impl From<TargetColorSpace> for ColorSpaceKind {
+  fn from(target: TargetColorSpace) -> Self {
+    match target {
+      TargetColorSpace::Rgb => ColorSpaceKind::Rgb,
+      TargetColorSpace::Rgba => ColorSpaceKind::Rgba,
+      TargetColorSpace::Cmyk => ColorSpaceKind::Cmyk,
+    }
+  }
+}
+
+fn finish<T: ImageFactory>(
+  mut data: ColorSpace<T>,
+  orientation: Orientation,
+  options: &DecodeOptions,
+) -> Result<Image> {
+  if options.apply_orientation {
+    data = orient_data(data, orientation);
+  }
+  if let Some(target) = options.target_color_space {
+    data = data.convert_to(target.into());
+  }
+  Ok(Image::new(data))
+}
+
+/// Applies the orientation and color space settings to a decoded image
+pub(super) fn apply(
+  mut image: Image,
+  orientation: Orientation,
+  options: &DecodeOptions,
+) -> Result<Image> {
+  // Take the pixels, leaving the metadata to be inherited
+  let empty = Image::new_u8(ColorSpace::Rgb(ImageBuffer::empty(0, 0)));
+  let mut result = match std::mem::replace(&mut image.imp, empty.imp) {
+    Implementation::U8(imp) => finish(imp.data, orientation, options),
+    Implementation::U16(imp) => finish(imp.data, orientation, options),
+    Implementation::U32(imp) => finish(imp.data, orientation, options),
+    Implementation::F32(imp) => finish(imp.data, orientation, options),
+    Implementation::F64(imp) => finish(imp.data, orientation, options),
+  }?;
+  result.inherit_metadata(&image);
+  // Rotating by a quarter turn swaps the axes the resolutions apply to
+  if options.apply_orientation && transposes(orientation) {
+    result.dpi = image.dpi.map(|(x, y)| (y, x));
+  }
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn orientation_swaps_resolution_axes() {
+    let mut square = Image::new_u8(ColorSpace::Rgb(ImageBuffer::empty(4, 4)));
+    square.set_dpi(Some((300.0, 150.0)));
+    square.set_taken_at(Some(crate::io::DateTime {
+      year:   2024,
+      month:  5,
+      day:    6,
+      hour:   7,
+      minute: 8,
+      second: 9,
+    }));
+    let options = DecodeOptions::default();
+    let turned =
+      apply(square.clone(), Orientation::Rotate90, &options).unwrap();
+    assert_eq!(turned.dpi(), Some((150.0, 300.0)));
+    assert_eq!(turned.taken_at(), square.taken_at());
+    let flipped =
+      apply(square.clone(), Orientation::FlipHorizontal, &options).unwrap();
+    assert_eq!(flipped.dpi(), Some((300.0, 150.0)));
+    let unturned = DecodeOptions {
+      apply_orientation: false,
+      ..Default::default()
+    };
+    let kept = apply(square, Orientation::Rotate270, &unturned).unwrap();
+    assert_eq!(kept.dpi(), Some((300.0, 150.0)));
+  }
+}
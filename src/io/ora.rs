@@ -0,0 +1,319 @@
+//! Reading and writing OpenRaster (`.ora`) documents, the layered format
+//! of Krita, GIMP and MyPaint, with the `ora` feature.
+//!
+//! A document is a ZIP archive of one PNG per layer and a `stack.xml`
+//! listing them. Nested stacks are dissolved into their layers, with the
+//! stacks' opacity and visibility applied to each. OpenRaster has no layer
+//! masks, so masks are multiplied into the layers' alpha when writing.
+
+use std::{
+  collections::HashMap,
+  io::{Cursor, Read, Write},
+};
+
+use image::{ImageDecoder, ImageEncoder};
+use xml::reader::{EventReader, XmlEvent};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+  error::{Error, Result},
+  layers::{Layer, LayerStack},
+  limits::Limits,
+  ops::{blend::BlendMode, transform::resize},
+  pixel::PixelContainer,
+  ImageBuffer,
+};
+
+const MIMETYPE: &[u8] = b"image/openraster";
+
+/// Largest side of the thumbnail stored with a document
+const THUMBNAIL_SIZE: usize = 256;
+
+/// `composite-op` values of the OpenRaster specification, with Krita's
+/// name for the mode it lacks
+const COMPOSITE_OPS: [(&str, BlendMode); 14] = [
+  ("svg:src-over", BlendMode::Normal),
+  ("svg:multiply", BlendMode::Multiply),
+  ("svg:screen", BlendMode::Screen),
+  ("svg:overlay", BlendMode::Overlay),
+  ("svg:darken", BlendMode::Darken),
+  ("svg:lighten", BlendMode::Lighten),
+  ("svg:color-dodge", BlendMode::ColorDodge),
+  ("svg:color-burn", BlendMode::ColorBurn),
+  ("svg:plus", BlendMode::LinearDodge),
+  ("krita:linear_burn", BlendMode::LinearBurn),
+  ("svg:hue", BlendMode::Hue),
+  ("svg:saturation", BlendMode::Saturation),
+  ("svg:color", BlendMode::Color),
+  ("svg:luminosity", BlendMode::Luminosity),
+];
+
+fn invalid(e: impl std::fmt::Display) -> Error {
+  Error::Decode(format!("Invalid OpenRaster document: {e}"))
+}
+
+/// Decompresses entry `name`, failing once it grows past the largest
+/// buffer `limits` allow, so that a small archive cannot expand to fill
+/// memory
+fn read_entry(
+  archive: &mut ZipArchive<Cursor<&[u8]>>,
+  name: &str,
+  limits: &Limits,
+) -> Result<Vec<u8>> {
+  let entry = archive.by_name(name).map_err(invalid)?;
+  let limit = limits.max_bytes.unwrap_or(u64::MAX);
+  let mut bytes = Vec::new();
+  entry
+    .take(limit.saturating_add(1))
+    .read_to_end(&mut bytes)?;
+  if bytes.len() as u64 > limit {
+    return Err(Error::LimitExceeded(format!(
+      "{name} decompresses to more than the limit of {limit} bytes"
+    )));
+  }
+  Ok(bytes)
+}
+
+fn decode_png(
+  data: &[u8],
+  limits: &Limits,
+) -> Result<ImageBuffer<u8, 4, true>> {
+  let decoder = image::codecs::png::PngDecoder::new(Cursor::new(data))?;
+  let (width, height) = decoder.dimensions();
+  let (width, height) = (width as usize, height as usize);
+  limits.check_image(width, height, 4)?;
+  let rgba = image::DynamicImage::from_decoder(decoder)?.to_rgba8();
+  ImageBuffer::try_with_data(rgba.into_raw(), width, height)
+}
+
+fn encode_png(image: &ImageBuffer<u8, 4, true>) -> Result<Vec<u8>> {
+  let mut bytes = Vec::new();
+  image::codecs::png::PngEncoder::new(&mut bytes).write_image(
+    image.components(),
+    image.width as u32,
+    image.height as u32,
+    image::ExtendedColorType::Rgba8,
+  )?;
+  Ok(bytes)
+}
+
+/// Reads an OpenRaster document. Composite operations this crate lacks
+/// become [`BlendMode::Normal`]. Fails with [`Error::LimitExceeded`] if a
+/// layer, or any file in the archive once decompressed, is over `limits`.
+pub fn read_ora(data: &[u8], limits: &Limits) -> Result<LayerStack<u8>> {
+  let mut archive = ZipArchive::new(Cursor::new(data)).map_err(invalid)?;
+  let stack_xml = read_entry(&mut archive, "stack.xml", limits)?;
+  let mut stack = None;
+  // Opacity and visibility of the enclosing stacks
+  let mut groups: Vec<(f64, bool)> = Vec::new();
+  let mut layers = Vec::new();
+  for event in EventReader::new(stack_xml.as_slice()) {
+    match event.map_err(invalid)? {
+      XmlEvent::StartElement {
+        name,
+        attributes,
+        ..
+      } => {
+        let attributes: HashMap<&str, &str> = attributes
+          .iter()
+          .map(|a| (a.name.local_name.as_str(), a.value.as_str()))
+          .collect();
+        let number = |key: &str| {
+          attributes
+            .get(key)
+            .and_then(|v| v.trim().parse::<f64>().ok())
+        };
+        let (outer_opacity, outer_visible) =
+          groups.last().copied().unwrap_or((1.0, true));
+        let opacity = outer_opacity * number("opacity").unwrap_or(1.0);
+        let visible =
+          outer_visible && attributes.get("visibility") != Some(&"hidden");
+        match name.local_name.as_str() {
+          "image" => {
+            let size = |key| number(key).map(|v| v.max(0.0) as usize);
+            let (Some(width), Some(height)) = (size("w"), size("h")) else {
+              return Err(invalid("the image has no size"));
+            };
+            limits.check_image(width, height, 4)?;
+            stack = Some(LayerStack::new(width, height));
+          }
+          "stack" => groups.push((opacity, visible)),
+          "layer" => {
+            let Some(src) = attributes.get("src") else {
+              return Err(invalid("a layer has no source"));
+            };
+            let image =
+              decode_png(&read_entry(&mut archive, src, limits)?, limits)?;
+            let mut layer = Layer::new(image);
+            layer.name = attributes.get("name").unwrap_or(&"").to_string();
+            layer.offset = (
+              number("x").unwrap_or_default() as isize,
+              number("y").unwrap_or_default() as isize,
+            );
+            layer.opacity = opacity.clamp(0.0, 1.0);
+            layer.visible = visible;
+            layer.blend_mode = attributes
+              .get("composite-op")
+              .and_then(|op| COMPOSITE_OPS.iter().find(|(name, _)| name == op))
+              .map_or(BlendMode::Normal, |(_, mode)| *mode);
+            layers.push(layer);
+          }
+          _ => {}
+        }
+      }
+      XmlEvent::EndElement {
+        name,
+      } if name.local_name == "stack" => {
+        groups.pop();
+      }
+      _ => {}
+    }
+  }
+  let mut stack = stack.ok_or_else(|| invalid("stack.xml has no image"))?;
+  // Listed from the top down
+  layers.reverse();
+  stack.layers = layers;
+  Ok(stack)
+}
+
+/// Escapes text for an XML attribute value
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// Writes `stack` as an OpenRaster document, with the flattened image and a
+/// thumbnail of it for file browsers
+pub fn write_ora(stack: &LayerStack<u8>) -> Result<Vec<u8>> {
+  let merged = stack.flatten()?;
+  let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+  let deflated = SimpleFileOptions::default();
+  let stored = deflated.compression_method(CompressionMethod::Stored);
+  let mut file = |name: &str, options, bytes: &[u8]| -> Result<()> {
+    zip
+      .start_file(name, options)
+      .map_err(std::io::Error::from)?;
+    zip.write_all(bytes)?;
+    Ok(())
+  };
+  // Must come first and uncompressed, so the type can be sniffed
+  file("mimetype", stored, MIMETYPE)?;
+
+  let mut xml = format!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<image version=\"0.0.5\" \
+     w=\"{}\" h=\"{}\">\n<stack>\n",
+    stack.width, stack.height
+  );
+  for (i, layer) in stack.layers.iter().enumerate().rev() {
+    if layer.image.width == 0 || layer.image.height == 0 {
+      continue;
+    }
+    let mut image = layer.image.clone();
+    if let Some(mask) = &layer.mask {
+      crate::video::check_dimensions((image.width, image.height), mask)?;
+      for (pel, m) in image
+        .components_mut()
+        .chunks_exact_mut(4)
+        .zip(mask.components())
+      {
+        pel[3] = (u16::from(pel[3]) * u16::from(*m) / 255) as u8;
+      }
+    }
+    let src = format!("data/layer{i}.png");
+    file(&src, stored, &encode_png(&image)?)?;
+    let op = COMPOSITE_OPS
+      .iter()
+      .find(|(_, mode)| *mode == layer.blend_mode)
+      .map_or("svg:src-over", |(name, _)| name);
+    xml += &format!(
+      "<layer name=\"{}\" src=\"{src}\" x=\"{}\" y=\"{}\" opacity=\"{}\" \
+       visibility=\"{}\" composite-op=\"{op}\"/>\n",
+      escape(&layer.name),
+      layer.offset.0,
+      layer.offset.1,
+      layer.opacity.clamp(0.0, 1.0),
+      if layer.visible { "visible" } else { "hidden" },
+    );
+  }
+  xml += "</stack>\n</image>\n";
+  file("stack.xml", deflated, xml.as_bytes())?;
+
+  if merged.width > 0 && merged.height > 0 {
+    let scale =
+      (THUMBNAIL_SIZE as f64 / merged.width.max(merged.height) as f64).min(1.0);
+    let thumbnail = resize(
+      &merged,
+      ((merged.width as f64 * scale).round() as usize).max(1),
+      ((merged.height as f64 * scale).round() as usize).max(1),
+    );
+    file("mergedimage.png", stored, &encode_png(&merged)?)?;
+    file("Thumbnails/thumbnail.png", stored, &encode_png(&thumbnail)?)?;
+  }
+  let cursor = zip.finish().map_err(std::io::Error::from)?;
+  Ok(cursor.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ops::mask::Mask;
+
+  #[test]
+  fn ora_round_trips_layers() {
+    let mut stack = LayerStack::new(3, 2);
+    let mut bottom =
+      Layer::new(ImageBuffer::with_val(&[10, 20, 30, 255], 3, 2));
+    bottom.name = "Paper & <ink>".to_string();
+    stack.push(bottom);
+    let mut top = Layer::new(ImageBuffer::with_val(&[200, 0, 0, 255], 2, 1));
+    top.offset = (-1, 1);
+    top.opacity = 0.5;
+    top.blend_mode = BlendMode::Multiply;
+    top.visible = false;
+    top.mask = Some(Mask::try_with_data(vec![255, 51], 2, 1).unwrap());
+    stack.push(top);
+
+    let bytes = write_ora(&stack).unwrap();
+    assert_eq!(&bytes[30..38], b"mimetype");
+    let read = read_ora(&bytes, &Limits::default()).unwrap();
+    assert_eq!((read.width, read.height, read.layers.len()), (3, 2, 2));
+    let [bottom, top] = &read.layers[..] else {
+      unreachable!()
+    };
+    assert_eq!(bottom.name, "Paper & <ink>");
+    assert_eq!(
+      bottom.image.components(),
+      stack.layers[0].image.components()
+    );
+    assert_eq!(
+      (top.offset, top.opacity, top.visible),
+      ((-1, 1), 0.5, false)
+    );
+    assert_eq!(top.blend_mode, BlendMode::Multiply);
+    assert_eq!(top.image.get_pixel(1, 0), &[200, 0, 0, 51]);
+    assert!(read_ora(b"PK not really", &Limits::default()).is_err());
+  }
+
+  #[test]
+  fn ora_entries_are_limited_when_decompressed() {
+    // A megabyte of spaces deflates to about a kilobyte
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default()
+      .compression_method(CompressionMethod::Deflated);
+    zip.start_file("stack.xml", options).unwrap();
+    zip.write_all(&[b' '; 1 << 20]).unwrap();
+    let bytes = zip.finish().unwrap().into_inner();
+    assert!(bytes.len() < 1 << 14);
+    let limits = Limits {
+      max_bytes: Some(1 << 16),
+      ..Limits::none()
+    };
+    assert!(matches!(
+      read_ora(&bytes, &limits),
+      Err(Error::LimitExceeded(_))
+    ));
+  }
+}
@@ -0,0 +1,205 @@
+//! Packing and unpacking of 10-bit YCbCr video layouts.
+//!
+//! Unpacked frames are `ImageBuffer<u16, 3, false>` holding Y, Cb and Cr at a
+//! [bit depth](ImageBuffer::bit_depth) of 10, with the subsampled chroma
+//! repeated for every pixel it covers. Packing averages chroma back down.
+//! Rows are assumed to be tightly packed, apart from the padding that V210
+//! itself requires.
+
+use crate::{
+  error::{Error, Result},
+  limits::Limits,
+  pixel::PixelContainer,
+  ImageBuffer,
+};
+
+/// A 10-bit YCbCr frame
+pub type Ycbcr10 = ImageBuffer<u16, 3, false>;
+
+const BITS: u32 = 10;
+
+fn at_10_bits(image: &Ycbcr10) -> Ycbcr10 {
+  if image.bit_depth() == BITS {
+    image.clone()
+  } else {
+    image.to_bit_depth(BITS)
+  }
+}
+
+fn check_len(format: &str, bytes: &[u8], expected: usize) -> Result<()> {
+  if bytes.len() < expected {
+    return Err(Error::Decode(format!(
+      "{format} data is {} bytes, expected {expected}",
+      bytes.len()
+    )));
+  }
+  Ok(())
+}
+
+/// Bytes in one row of a V210 frame: groups of 6 pixels packed into 16
+/// bytes, with the row padded to a multiple of 128 bytes
+pub fn v210_stride(width: usize) -> usize { width.div_ceil(48) * 128 }
+
+/// Unpacks a P010 frame: a plane of 16-bit little-endian luma samples
+/// followed by a half-resolution plane of interleaved Cb/Cr samples, with
+/// the 10 significant bits stored in the high bits of each sample
+pub fn unpack_p010(
+  bytes: &[u8],
+  width: usize,
+  height: usize,
+) -> Result<Ycbcr10> {
+  let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+  let luma_len = width.saturating_mul(height).saturating_mul(2);
+  let chroma_len = cw.saturating_mul(ch).saturating_mul(4);
+  check_len("P010", bytes, luma_len.saturating_add(chroma_len))?;
+  let sample =
+    |i: usize| u16::from_le_bytes([bytes[2 * i], bytes[2 * i + 1]]) >> 6;
+
+  let mut image = Ycbcr10::try_empty(width, height, &Limits::none())?;
+  image.set_bit_depth(BITS);
+  image.apply_indexed(&mut |x, y, _| {
+    let c = width * height + 2 * ((y / 2) * cw + x / 2);
+    [sample(y * width + x), sample(c), sample(c + 1)]
+  });
+  Ok(image)
+}
+
+/// Packs a frame into P010, averaging each 2x2 block of chroma
+pub fn pack_p010(image: &Ycbcr10) -> Vec<u8> {
+  let image = at_10_bits(image);
+  let (width, height) = (image.width, image.height);
+  let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+  let mut out = Vec::with_capacity((width * height + cw * ch * 2) * 2);
+
+  for pel in image.iter_pixels() {
+    out.extend_from_slice(&(pel[0] << 6).to_le_bytes());
+  }
+  for cy in 0..ch {
+    for cx in 0..cw {
+      let mut sum = [0u32; 2];
+      let mut count = 0;
+      for y in 2 * cy..(2 * cy + 2).min(height) {
+        for x in 2 * cx..(2 * cx + 2).min(width) {
+          let pel = image.get_pixel(x, y);
+          sum[0] += u32::from(pel[1]);
+          sum[1] += u32::from(pel[2]);
+          count += 1;
+        }
+      }
+      for s in sum {
+        let avg = ((s + count / 2) / count) as u16;
+        out.extend_from_slice(&(avg << 6).to_le_bytes());
+      }
+    }
+  }
+  out
+}
+
+/// Unpacks a V210 frame: 4:2:2 samples in the order Cb, Y, Cr, Y, three to
+/// each little-endian 32-bit word, with rows [padded](v210_stride)
+pub fn unpack_v210(
+  bytes: &[u8],
+  width: usize,
+  height: usize,
+) -> Result<Ycbcr10> {
+  let stride = v210_stride(width);
+  check_len("V210", bytes, stride.saturating_mul(height))?;
+  let sample = |y: usize, i: usize| {
+    let at = y * stride + (i / 3) * 4;
+    let word = u32::from_le_bytes([
+      bytes[at],
+      bytes[at + 1],
+      bytes[at + 2],
+      bytes[at + 3],
+    ]);
+    ((word >> (10 * (i % 3))) & 0x3ff) as u16
+  };
+
+  let mut image = Ycbcr10::try_empty(width, height, &Limits::none())?;
+  image.set_bit_depth(BITS);
+  image.apply_indexed(&mut |x, y, _| {
+    let pair = 4 * (x / 2);
+    [
+      sample(y, pair + 1 + 2 * (x % 2)),
+      sample(y, pair),
+      sample(y, pair + 2),
+    ]
+  });
+  Ok(image)
+}
+
+/// Packs a frame into V210, averaging the chroma of each pair of pixels
+pub fn pack_v210(image: &Ycbcr10) -> Vec<u8> {
+  let image = at_10_bits_with_even_width(image);
+  let stride = v210_stride(image.width);
+  let mut out = vec![0; stride * image.height];
+
+  for (y, row) in out.chunks_exact_mut(stride.max(1)).enumerate() {
+    let mut samples = Vec::with_capacity(2 * image.width);
+    for x in (0..image.width).step_by(2) {
+      let (a, b) = (image.get_pixel(x, y), image.get_pixel(x + 1, y));
+      let avg = |i: usize| (a[i] + b[i]).div_ceil(2);
+      samples.extend([avg(1), a[0], avg(2), b[0]]);
+    }
+    for (word, group) in row.chunks_exact_mut(4).zip(samples.chunks(3)) {
+      let packed = group
+        .iter()
+        .enumerate()
+        .fold(0u32, |w, (i, &s)| w | u32::from(s & 0x3ff) << (10 * i));
+      word.copy_from_slice(&packed.to_le_bytes());
+    }
+  }
+  out
+}
+
+/// V210 stores pixels in pairs; an odd last column is paired with a copy of
+/// itself
+fn at_10_bits_with_even_width(image: &Ycbcr10) -> Ycbcr10 {
+  let image = at_10_bits(image);
+  if image.width.is_multiple_of(2) {
+    return image;
+  }
+  let mut padded = Ycbcr10::empty(image.width + 1, image.height);
+  padded.set_bit_depth(BITS);
+  padded
+    .apply_indexed(&mut |x, y, _| *image.get_pixel(x.min(image.width - 1), y));
+  padded
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn frame(width: usize, height: usize) -> Ycbcr10 {
+    let mut image = Ycbcr10::empty(width, height)
+      .map_indexed(&mut |x, y, _| [(x * 100 + y) as u16, 512, 300]);
+    image.set_bit_depth(BITS);
+    image
+  }
+
+  #[test]
+  fn p010_round_trip() {
+    let image = frame(3, 3);
+    let packed = pack_p010(&image);
+    assert_eq!(packed.len(), (9 + 4 * 2) * 2);
+    assert_eq!(&packed[2..4], &(100u16 << 6).to_le_bytes());
+    let unpacked = unpack_p010(&packed, 3, 3).unwrap();
+    assert_eq!(unpacked.bit_depth(), 10);
+    assert_eq!(unpacked.components(), image.components());
+    assert!(matches!(
+      unpack_p010(&packed[1..], 3, 3),
+      Err(Error::Decode(_))
+    ));
+  }
+
+  #[test]
+  fn v210_round_trip() {
+    let image = frame(7, 2);
+    let packed = pack_v210(&image);
+    assert_eq!(packed.len(), 2 * 128);
+    // First word: Cb0, Y0, Cr0
+    assert_eq!(&packed[..4], &(512u32 | 300 << 20).to_le_bytes());
+    let unpacked = unpack_v210(&packed, 7, 2).unwrap();
+    assert_eq!(unpacked.components(), image.components());
+  }
+}
@@ -0,0 +1,256 @@
+//! Rendering the pages of PDF documents to RGBA images, with the `pdf`
+//! feature.
+//!
+//! The work is done by Poppler's `pdfinfo` and `pdftoppm` programs, which
+//! must be on the `PATH` at run time; the feature links no PDF library.
+//! Pages are rendered within their crop box, as PDF viewers show them, and
+//! are opaque: unpainted areas come out white.
+
+use std::{
+  io::{BufRead, BufReader, Read},
+  path::Path,
+  process::{Command, Stdio},
+};
+
+use crate::{
+  error::{Error, Result},
+  limits::Limits,
+  pixel::PixelContainer,
+  ImageBuffer,
+};
+
+fn spawn_error(program: &str, e: std::io::Error) -> Error {
+  if e.kind() == std::io::ErrorKind::NotFound {
+    Error::Unsupported(format!("{program} is not installed"))
+  } else {
+    Error::Io(e)
+  }
+}
+
+/// The error a Poppler program reported on `stderr`
+fn program_error(stderr: &[u8]) -> Error {
+  Error::Decode(String::from_utf8_lossy(stderr).trim().to_string())
+}
+
+/// Runs a Poppler program and returns its output, or its error message as
+/// an [`Error::Decode`] if it fails
+fn run(command: &mut Command, program: &str) -> Result<Vec<u8>> {
+  let output = command.output().map_err(|e| spawn_error(program, e))?;
+  if !output.status.success() {
+    return Err(program_error(&output.stderr));
+  }
+  Ok(output.stdout)
+}
+
+/// Parses the `Pages:` line of `pdfinfo`'s output
+fn parse_page_count(output: &str) -> Result<usize> {
+  output
+    .lines()
+    .find_map(|line| line.strip_prefix("Pages:"))
+    .and_then(|count| count.trim().parse().ok())
+    .ok_or_else(|| Error::Decode("pdfinfo did not report a page count".into()))
+}
+
+/// Number of pages in the PDF document at `path`
+pub fn page_count(path: impl AsRef<Path>) -> Result<usize> {
+  let output = run(
+    Command::new("pdfinfo").arg("--").arg(path.as_ref()),
+    "pdfinfo",
+  )?;
+  parse_page_count(&String::from_utf8_lossy(&output))
+}
+
+/// Parses the crop box and rotation of a page from the output of
+/// `pdfinfo -box`, giving the page's size in points as it is shown, or
+/// `None` if the page is not listed
+fn parse_page_size(output: &str) -> Option<(f64, f64)> {
+  let field = |name: &str| {
+    output.lines().find_map(|line| {
+      let rest = line.strip_prefix("Page")?.trim_start();
+      let (_, rest) = rest.split_once(char::is_whitespace)?;
+      rest.trim_start().strip_prefix(name)
+    })
+  };
+  let corners: Vec<f64> = field("CropBox:")?
+    .split_whitespace()
+    .filter_map(|v| v.parse().ok())
+    .collect();
+  let [x0, y0, x1, y1] = corners[..] else {
+    return None;
+  };
+  let (width, height) = ((x1 - x0).abs(), (y1 - y0).abs());
+  let rotation: i64 = field("rot:")
+    .and_then(|r| r.trim().parse().ok())
+    .unwrap_or(0);
+  Some(if rotation.rem_euclid(180) == 90 {
+    (height, width)
+  } else {
+    (width, height)
+  })
+}
+
+/// Reads one whitespace-terminated field of a PPM header, skipping
+/// whitespace and comments before it
+fn header_field(reader: &mut impl BufRead) -> Result<String> {
+  let invalid = || Error::Decode("Unexpected pdftoppm output".to_string());
+  let mut field = String::new();
+  let mut in_comment = false;
+  loop {
+    let mut byte = [0];
+    if reader.read(&mut byte)? == 0 {
+      return Err(invalid());
+    }
+    match byte[0] {
+      b'\n' if in_comment => in_comment = false,
+      _ if in_comment => {}
+      b'#' if field.is_empty() => in_comment = true,
+      c if c.is_ascii_whitespace() =>
+        if !field.is_empty() {
+          // The whitespace byte ending the last field is consumed, as the
+          // pixels follow it directly
+          return Ok(field);
+        },
+      c if field.len() < 20 => field.push(char::from(c)),
+      _ => return Err(invalid()),
+    }
+  }
+}
+
+/// Decodes the binary PPM image `pdftoppm` writes, adding opaque alpha.
+/// Fails before allocating if the image is over `limits`.
+fn parse_ppm(
+  reader: impl Read,
+  limits: &Limits,
+) -> Result<ImageBuffer<u8, 4, true>> {
+  let invalid = || Error::Decode("Unexpected pdftoppm output".to_string());
+  let mut reader = BufReader::new(reader);
+  if header_field(&mut reader)? != "P6" {
+    return Err(invalid());
+  }
+  let mut number = || -> Result<usize> {
+    header_field(&mut reader)?.parse().map_err(|_| invalid())
+  };
+  let (width, height, max) = (number()?, number()?, number()?);
+  if max != 255 {
+    return Err(invalid());
+  }
+  let mut image = ImageBuffer::try_empty(width, height, limits)?;
+  let mut row = vec![0; width * 3];
+  for out in image.components_mut().chunks_exact_mut((width * 4).max(1)) {
+    reader.read_exact(&mut row).map_err(|_| invalid())?;
+    for (rgba, rgb) in out.chunks_exact_mut(4).zip(row.chunks_exact(3)) {
+      rgba.copy_from_slice(&[rgb[0], rgb[1], rgb[2], u8::MAX]);
+    }
+  }
+  Ok(image)
+}
+
+/// Renders page `page` of the PDF document at `path` at `dpi` pixels per
+/// inch. Pages are numbered from 1, as in PDF viewers; a US Letter page at
+/// 72 DPI is 612 by 792 pixels.
+///
+/// The page's size is checked against `limits` before it is rendered, and
+/// the pixels are read from `pdftoppm` as it writes them.
+///
+/// Fails with [`Error::Unsupported`] if Poppler is not installed, with
+/// [`Error::LimitExceeded`] if the page would be too large at `dpi`, and
+/// with [`Error::Decode`] if the file cannot be read or has no such page.
+pub fn render_page(
+  path: impl AsRef<Path>,
+  page: usize,
+  dpi: f64,
+  limits: &Limits,
+) -> Result<ImageBuffer<u8, 4, true>> {
+  if page == 0 {
+    return Err(Error::InvalidArgument(
+      "PDF pages are numbered from 1".to_string(),
+    ));
+  }
+  if !(dpi.is_finite() && dpi > 0.0) {
+    return Err(Error::InvalidArgument(format!("Invalid DPI {dpi}")));
+  }
+  let path = path.as_ref();
+  let page = page.to_string();
+  let info = run(
+    Command::new("pdfinfo")
+      .args(["-box", "-f", &page, "-l", &page, "--"])
+      .arg(path),
+    "pdfinfo",
+  )?;
+  let (width, height) = parse_page_size(&String::from_utf8_lossy(&info))
+    .ok_or_else(|| Error::Decode(format!("PDF has no page {page}")))?;
+  let pixels = |points: f64| (points / 72.0 * dpi).ceil() as usize;
+  limits.check_image(pixels(width), pixels(height), 4)?;
+
+  let mut child = Command::new("pdftoppm")
+    .args(["-cropbox", "-r"])
+    .arg(dpi.to_string())
+    .args(["-f", &page, "-l", &page, "--"])
+    .arg(path)
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|e| spawn_error("pdftoppm", e))?;
+  // Drain the messages on another thread so a chatty renderer cannot
+  // block on a full pipe while the pixels are read
+  let stderr = child.stderr.take().map(|mut stderr| {
+    std::thread::spawn(move || {
+      let mut messages = Vec::new();
+      let _ = stderr.read_to_end(&mut messages);
+      messages
+    })
+  });
+  let image = match child.stdout.take() {
+    Some(stdout) => parse_ppm(stdout, limits),
+    None => Err(Error::Decode("pdftoppm gave no output".to_string())),
+  };
+  if image.is_err() {
+    let _ = child.kill();
+  }
+  let status = child.wait()?;
+  let messages = stderr
+    .and_then(|thread| thread.join().ok())
+    .unwrap_or_default();
+  match image {
+    Err(Error::LimitExceeded(e)) => Err(Error::LimitExceeded(e)),
+    _ if !status.success() => Err(program_error(&messages)),
+    image => image,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_poppler_output() {
+    let info =
+      "Producer:       LibreOffice\nPages:          12\nEncrypted: no\n";
+    assert_eq!(parse_page_count(info).unwrap(), 12);
+    assert!(parse_page_count("Title: x\n").is_err());
+
+    let mut ppm = b"P6\n# pdftoppm\n2 1\n255\n".to_vec();
+    ppm.extend([255, 0, 0, 10, 20, 30]);
+    let limits = Limits::default();
+    let image = parse_ppm(&ppm[..], &limits).unwrap();
+    assert_eq!(image.components(), &[255, 0, 0, 255, 10, 20, 30, 255]);
+    assert!(parse_ppm(&ppm[..ppm.len() - 1], &limits).is_err());
+    assert!(parse_ppm(&b"P5\n1 1\n255\n\0"[..], &limits).is_err());
+    let small = Limits {
+      max_width: Some(1),
+      ..Limits::none()
+    };
+    assert!(matches!(
+      parse_ppm(&b"P6\n99999 99999\n255\n"[..], &small),
+      Err(Error::LimitExceeded(_))
+    ));
+    assert!(render_page("missing.pdf", 0, 72.0, &limits).is_err());
+
+    let info =
+      "Pages:          2\nPage    2 size: 612 x 792 pts (letter)\nPage    2 \
+       rot:  90\nPage    2 MediaBox:     0.00     0.00   612.00   \
+       792.00\nPage    2 CropBox:     10.00    20.00   602.00   772.00\n";
+    assert_eq!(parse_page_size(info), Some((752.0, 592.0)));
+    assert_eq!(parse_page_size("Pages:          1\n"), None);
+  }
+}
@@ -0,0 +1,74 @@
+//! Codecs supplied by other crates.
+//!
+//! A [`CodecPlugin`] registered with [`register_codec`] is tried for data
+//! that none of the built-in formats recognize, and for file extensions
+//! they do not cover.
+
+use std::sync::{Arc, RwLock};
+
+use super::DecodeOptions;
+use crate::{
+  error::{Error, Result},
+  Image,
+};
+
+/// An image format implemented outside this crate
+pub trait CodecPlugin: Send + Sync {
+  /// Unique name of the format, such as `"qoi"`
+  fn name(&self) -> &str;
+
+  /// File extensions, without the dot, that select this codec on save
+  fn extensions(&self) -> &[&str];
+
+  /// Whether `bytes` look like this format, usually from a magic number
+  fn matches(&self, bytes: &[u8]) -> bool;
+
+  /// Decodes `bytes`. Codecs should honor `options.limits` before
+  /// allocating; the orientation and color space settings are applied
+  /// afterwards by the caller.
+  fn decode(&self, bytes: &[u8], options: &DecodeOptions) -> Result<Image>;
+
+  /// Encodes `image`. Decode-only codecs can keep the default, which fails.
+  fn encode(&self, image: &Image) -> Result<Vec<u8>> {
+    let _ = image;
+    Err(Error::Unsupported(format!(
+      "The {} codec cannot encode",
+      self.name()
+    )))
+  }
+}
+
+static CODECS: RwLock<Vec<Arc<dyn CodecPlugin>>> = RwLock::new(Vec::new());
+
+/// Registers `codec`, replacing any codec with the same name
+pub fn register_codec(codec: Arc<dyn CodecPlugin>) {
+  let mut codecs = CODECS.write().unwrap_or_else(|e| e.into_inner());
+  codecs.retain(|existing| existing.name() != codec.name());
+  codecs.push(codec);
+}
+
+/// Names of all registered codecs, in registration order
+pub fn codec_names() -> Vec<String> {
+  let codecs = CODECS.read().unwrap_or_else(|e| e.into_inner());
+  codecs.iter().map(|c| c.name().to_string()).collect()
+}
+
+/// The first registered codec that recognizes `bytes`
+pub fn codec_for_data(bytes: &[u8]) -> Option<Arc<dyn CodecPlugin>> {
+  let codecs = CODECS.read().unwrap_or_else(|e| e.into_inner());
+  codecs.iter().find(|c| c.matches(bytes)).cloned()
+}
+
+/// The first registered codec that handles files ending in `extension`,
+/// ignoring case
+pub fn codec_for_extension(extension: &str) -> Option<Arc<dyn CodecPlugin>> {
+  let codecs = CODECS.read().unwrap_or_else(|e| e.into_inner());
+  codecs
+    .iter()
+    .find(|c| {
+      c.extensions()
+        .iter()
+        .any(|ext| ext.eq_ignore_ascii_case(extension))
+    })
+    .cloned()
+}
@@ -0,0 +1,522 @@
+//! Reading Photoshop documents into [`LayerStack`]s, with the `psd`
+//! feature.
+//!
+//! RGB and grayscale documents with 8 or 16 bits per channel are read, with
+//! channels stored raw or RLE compressed as Photoshop saves them; 16-bit
+//! samples are reduced to 8 bits. Each pixel layer keeps its name, offset,
+//! opacity, blend mode, visibility and layer mask. Groups are dissolved into
+//! the layers they contain, and text and adjustment layers keep only their
+//! rasterized pixels. Documents saved without layers become a single layer
+//! of their composite image.
+
+use crate::{
+  error::{Error, Result},
+  layers::{Layer, LayerStack},
+  limits::Limits,
+  ops::{blend::BlendMode, mask::Mask},
+  pixel::PixelContainer,
+  ImageBuffer,
+};
+
+fn truncated() -> Error { Error::Decode("Truncated PSD file".to_string()) }
+
+/// Big-endian fields read from a slice
+struct Reader<'a> {
+  data:     &'a [u8],
+  position: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn new(data: &'a [u8]) -> Self {
+    Reader {
+      data,
+      position: 0,
+    }
+  }
+
+  fn remaining(&self) -> usize { self.data.len() - self.position }
+
+  fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+    let end = self
+      .position
+      .checked_add(len)
+      .filter(|&end| end <= self.data.len())
+      .ok_or_else(truncated)?;
+    let bytes = &self.data[self.position..end];
+    self.position = end;
+    Ok(bytes)
+  }
+
+  fn u8(&mut self) -> Result<u8> { Ok(self.bytes(1)?[0]) }
+
+  fn u16(&mut self) -> Result<u16> {
+    let b = self.bytes(2)?;
+    Ok(u16::from_be_bytes([b[0], b[1]]))
+  }
+
+  fn u32(&mut self) -> Result<u32> {
+    let b = self.bytes(4)?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+  }
+
+  fn i32(&mut self) -> Result<i32> { Ok(self.u32()? as i32) }
+
+  /// A block preceded by its length in a 32-bit field
+  fn section(&mut self) -> Result<Reader<'a>> {
+    let len = self.u32()? as usize;
+    Ok(Reader::new(self.bytes(len)?))
+  }
+}
+
+/// Layout of the samples in every channel of a document
+#[derive(Clone, Copy)]
+struct Format {
+  /// 1 for grayscale or 3 for RGB
+  colors: usize,
+  /// Bytes per sample
+  depth:  usize,
+}
+
+/// The top, left, bottom and right edges of a layer or mask on the canvas
+#[derive(Clone, Copy, Default)]
+struct Bounds([i32; 4]);
+
+impl Bounds {
+  fn read(reader: &mut Reader) -> Result<Self> {
+    Ok(Bounds([
+      reader.i32()?,
+      reader.i32()?,
+      reader.i32()?,
+      reader.i32()?,
+    ]))
+  }
+
+  fn size(&self) -> (usize, usize) {
+    let [top, left, bottom, right] = self.0.map(i64::from);
+    (
+      (right - left).max(0) as usize,
+      (bottom - top).max(0) as usize,
+    )
+  }
+}
+
+struct LayerMask {
+  bounds:   Bounds,
+  /// Value of the mask outside its bounds
+  default:  u8,
+  disabled: bool,
+}
+
+struct Record {
+  name:       String,
+  bounds:     Bounds,
+  /// Channel ids and the lengths of their data
+  channels:   Vec<(i16, usize)>,
+  blend_mode: BlendMode,
+  opacity:    u8,
+  visible:    bool,
+  mask:       Option<LayerMask>,
+  /// Marks the start or end of a group rather than holding pixels
+  divider:    bool,
+}
+
+/// The blend mode for a Photoshop blend mode key, or `Normal` for modes
+/// this crate lacks
+fn blend_mode(key: &[u8]) -> BlendMode {
+  match key {
+    b"mul " => BlendMode::Multiply,
+    b"scrn" => BlendMode::Screen,
+    b"over" => BlendMode::Overlay,
+    b"dark" => BlendMode::Darken,
+    b"lite" => BlendMode::Lighten,
+    b"div " => BlendMode::ColorDodge,
+    b"idiv" => BlendMode::ColorBurn,
+    b"lddg" => BlendMode::LinearDodge,
+    b"lbrn" => BlendMode::LinearBurn,
+    b"hue " => BlendMode::Hue,
+    b"sat " => BlendMode::Saturation,
+    b"colr" => BlendMode::Color,
+    b"lum " => BlendMode::Luminosity,
+    _ => BlendMode::Normal,
+  }
+}
+
+fn read_record(reader: &mut Reader) -> Result<Record> {
+  let bounds = Bounds::read(reader)?;
+  let channels = (0..reader.u16()?)
+    .map(|_| Ok((reader.u16()? as i16, reader.u32()? as usize)))
+    .collect::<Result<Vec<_>>>()?;
+  if reader.bytes(4)? != b"8BIM" {
+    return Err(Error::Decode("Bad PSD layer record signature".to_string()));
+  }
+  let blend_mode = blend_mode(reader.bytes(4)?);
+  let opacity = reader.u8()?;
+  let _clipping = reader.u8()?;
+  let flags = reader.u8()?;
+  let _filler = reader.u8()?;
+
+  let mut extra = reader.section()?;
+  let mut mask_data = extra.section()?;
+  let mask = if mask_data.remaining() >= 18 {
+    let bounds = Bounds::read(&mut mask_data)?;
+    let default = mask_data.u8()?;
+    let flags = mask_data.u8()?;
+    Some(LayerMask {
+      bounds,
+      default,
+      disabled: flags & 2 != 0,
+    })
+  } else {
+    None
+  };
+  let _blending_ranges = extra.section()?;
+  // A Pascal string padded to a multiple of four bytes
+  let len = usize::from(extra.u8()?);
+  let mut name = String::from_utf8_lossy(extra.bytes(len)?).into_owned();
+  extra.bytes((len + 4) / 4 * 4 - 1 - len)?;
+
+  let mut divider = false;
+  while extra.remaining() >= 12 {
+    let _signature = extra.bytes(4)?;
+    let key = extra.bytes(4)?;
+    let len = extra.u32()? as usize;
+    let Ok(data) = extra.bytes(len) else {
+      break;
+    };
+    let mut data = Reader::new(data);
+    match key {
+      b"luni" => {
+        let count = data.u32()? as usize;
+        let units =
+          (0..count).map(|_| data.u16()).collect::<Result<Vec<_>>>()?;
+        name = String::from_utf16_lossy(&units);
+      }
+      b"lsct" => divider = (1..=3).contains(&data.u32()?),
+      _ => {}
+    }
+  }
+  Ok(Record {
+    name,
+    bounds,
+    channels,
+    blend_mode,
+    opacity,
+    visible: flags & 2 == 0,
+    mask,
+    divider,
+  })
+}
+
+/// Expands one row of PackBits run-length encoding onto `out`
+fn unpack_bits(data: &[u8], len: usize, out: &mut Vec<u8>) -> Result<()> {
+  let corrupt = || Error::Decode("Corrupt RLE data in PSD file".to_string());
+  let end = out.len() + len;
+  let mut i = 0;
+  while out.len() < end && i < data.len() {
+    let header = data[i] as i8;
+    i += 1;
+    if header >= 0 {
+      let run = header as usize + 1;
+      out.extend_from_slice(data.get(i..i + run).ok_or_else(corrupt)?);
+      i += run;
+    } else if header != -128 {
+      let run = (1 - isize::from(header)) as usize;
+      let value = *data.get(i).ok_or_else(corrupt)?;
+      out.extend(std::iter::repeat_n(value, run));
+      i += 1;
+    }
+  }
+  if out.len() != end {
+    return Err(corrupt());
+  }
+  Ok(())
+}
+
+/// Reads `planes` channels of `width` by `height` samples sharing one
+/// compression field, as in the composite image, reduced to 8 bits
+fn read_planes(
+  reader: &mut Reader,
+  width: usize,
+  height: usize,
+  planes: usize,
+  format: Format,
+) -> Result<Vec<Vec<u8>>> {
+  let row = width * format.depth;
+  let size = row.checked_mul(height).ok_or_else(truncated)?;
+  let raw = match reader.u16()? {
+    0 =>
+      (0..planes)
+        .map(|_| Ok(reader.bytes(size)?.to_vec()))
+        .collect::<Result<Vec<_>>>()?,
+    1 => {
+      let counts = (0..planes * height)
+        .map(|_| reader.u16().map(usize::from))
+        .collect::<Result<Vec<_>>>()?;
+      let mut planes = Vec::new();
+      for counts in counts.chunks(height.max(1)) {
+        let mut plane = Vec::new();
+        for &count in counts {
+          unpack_bits(reader.bytes(count)?, row, &mut plane)?;
+        }
+        planes.push(plane);
+      }
+      planes
+    }
+    2 | 3 =>
+      return Err(Error::Unsupported(
+        "ZIP-compressed PSD channels are not supported".to_string(),
+      )),
+    compression =>
+      return Err(Error::Decode(format!(
+        "Unknown PSD compression {compression}"
+      ))),
+  };
+  Ok(
+    raw
+      .into_iter()
+      .map(|plane| {
+        match format.depth {
+          2 => plane.chunks_exact(2).map(|s| s[0]).collect(),
+          _ => plane,
+        }
+      })
+      .collect(),
+  )
+}
+
+/// Builds a layer from its record and the data of its channels
+fn read_layer(
+  record: Record,
+  data: &mut Reader,
+  format: Format,
+  limits: &Limits,
+) -> Result<Option<Layer<u8>>> {
+  let (width, height) = record.bounds.size();
+  let mut image = ImageBuffer::<u8, 4, true>::try_empty(width, height, limits)?;
+  image
+    .components_mut()
+    .iter_mut()
+    .skip(3)
+    .step_by(4)
+    .for_each(|a| *a = 255);
+  let mut mask = None;
+  for &(id, len) in &record.channels {
+    let mut channel = Reader::new(data.bytes(len)?);
+    let target = match id {
+      -1 => 3,
+      0..=2 if (id as usize) < format.colors => id as usize,
+      -2 => {
+        let Some(layer_mask) = &record.mask else {
+          continue;
+        };
+        let (w, h) = layer_mask.bounds.size();
+        mask = read_planes(&mut channel, w, h, 1, format)?.pop();
+        continue;
+      }
+      _ => continue,
+    };
+    let Some(plane) =
+      read_planes(&mut channel, width, height, 1, format)?.pop()
+    else {
+      continue;
+    };
+    for (pel, &v) in image.components_mut().chunks_exact_mut(4).zip(&plane) {
+      if format.colors == 1 && target == 0 {
+        pel[..3].fill(v);
+      } else {
+        pel[target] = v;
+      }
+    }
+  }
+  if record.divider || width == 0 || height == 0 {
+    return Ok(None);
+  }
+
+  let [top, left, ..] = record.bounds.0;
+  let mut layer = Layer::new(image);
+  layer.name = record.name;
+  layer.offset = (left as isize, top as isize);
+  layer.opacity = f64::from(record.opacity) / 255.0;
+  layer.blend_mode = record.blend_mode;
+  layer.visible = record.visible;
+  if let (Some(layer_mask), Some(values)) = (&record.mask, mask) {
+    if !layer_mask.disabled {
+      // The mask has its own bounds, outside which it takes its default
+      let mut full =
+        Mask::try_with_val(&[layer_mask.default], width, height, limits)?;
+      let [mask_top, mask_left, ..] = layer_mask.bounds.0;
+      let (w, h) = layer_mask.bounds.size();
+      for (i, &v) in values.iter().enumerate().take(w * h) {
+        let x = i64::from(mask_left) + (i % w) as i64 - i64::from(left);
+        let y = i64::from(mask_top) + (i / w) as i64 - i64::from(top);
+        if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+          full.get_pixel_mut(x as usize, y as usize)[0] = v;
+        }
+      }
+      layer.mask = Some(full);
+    }
+  }
+  Ok(Some(layer))
+}
+
+/// Reads a Photoshop (`.psd`) document. Fails with [`Error::Unsupported`]
+/// for large document (`.psb`) files, color modes other than RGB and
+/// grayscale, and ZIP-compressed channels.
+pub fn read_psd(data: &[u8], limits: &Limits) -> Result<LayerStack<u8>> {
+  let mut reader = Reader::new(data);
+  if reader.bytes(4).ok() != Some(b"8BPS") {
+    return Err(Error::Decode("Not a Photoshop document".to_string()));
+  }
+  match reader.u16()? {
+    1 => {}
+    2 =>
+      return Err(Error::Unsupported(
+        "Large document (PSB) files are not supported".to_string(),
+      )),
+    version =>
+      return Err(Error::Decode(format!("Unknown PSD version {version}"))),
+  }
+  reader.bytes(6)?;
+  let channels = usize::from(reader.u16()?);
+  let height = reader.u32()? as usize;
+  let width = reader.u32()? as usize;
+  let depth = reader.u16()?;
+  let colors = match reader.u16()? {
+    1 => 1,
+    3 => 3,
+    mode =>
+      return Err(Error::Unsupported(format!(
+        "PSD color mode {mode} is not supported; only RGB and grayscale are"
+      ))),
+  };
+  if depth != 8 && depth != 16 {
+    return Err(Error::Unsupported(format!(
+      "{depth}-bit PSD files are not supported"
+    )));
+  }
+  limits.check_image(width, height, 4)?;
+  let format = Format {
+    colors,
+    depth: usize::from(depth / 8),
+  };
+  let _color_mode_data = reader.section()?;
+  let _resources = reader.section()?;
+
+  let mut stack = LayerStack::new(width, height);
+  let mut layer_and_mask = reader.section()?;
+  if layer_and_mask.remaining() > 0 {
+    let mut info = layer_and_mask.section()?;
+    if info.remaining() > 0 {
+      // Negative when the first alpha channel is the composite's
+      let count = (info.u16()? as i16).unsigned_abs();
+      let records = (0..count)
+        .map(|_| read_record(&mut info))
+        .collect::<Result<Vec<_>>>()?;
+      for record in records {
+        if let Some(layer) = read_layer(record, &mut info, format, limits)? {
+          stack.push(layer);
+        }
+      }
+    }
+  }
+
+  if stack.layers.is_empty() {
+    let planes = read_planes(&mut reader, width, height, channels, format)?;
+    if planes.len() < colors {
+      return Err(truncated());
+    }
+    let mut image =
+      ImageBuffer::<u8, 4, true>::try_empty(width, height, limits)?;
+    for (i, pel) in image.components_mut().chunks_exact_mut(4).enumerate() {
+      for (k, c) in pel[..3].iter_mut().enumerate() {
+        *c = planes[k.min(colors - 1)][i];
+      }
+      pel[3] = 255;
+    }
+    let mut layer = Layer::new(image);
+    layer.name = "Background".to_string();
+    stack.push(layer);
+  }
+  Ok(stack)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reads_layers_with_masks_and_modes() {
+    let be32 = |v: i32| v.to_be_bytes().to_vec();
+    // A 3x1 RGB canvas
+    let mut psd = b"8BPS\0\x01\0\0\0\0\0\0\0\x03".to_vec();
+    psd.extend([be32(1), be32(3), vec![0, 8, 0, 3]].concat());
+    psd.extend([be32(0), be32(0)].concat());
+
+    let mut records = vec![0, 2];
+    let mut pixels = Vec::new();
+    // A raw layer over the whole canvas
+    records.extend([be32(0), be32(0), be32(1), be32(3), vec![0, 4]].concat());
+    for (id, samples) in [(-1i16, [255, 255, 0]), (0, [10, 20, 30])]
+      .into_iter()
+      .chain([(1, [0, 0, 0]), (2, [200, 200, 200])])
+    {
+      records.extend(id.to_be_bytes());
+      records.extend(be32(5));
+      pixels.extend([0, 0].into_iter().chain(samples));
+    }
+    records.extend(b"8BIMnorm\xff\0\0\0");
+    records
+      .extend([be32(12), be32(0), be32(0), vec![2], b"bg\0".to_vec()].concat());
+    // A hidden one-pixel layer at x = 1, multiplied at half opacity, with an
+    // RLE mask covering x = 2 and a Unicode name
+    records.extend([be32(0), be32(1), be32(1), be32(2), vec![0, 2]].concat());
+    for id in [0i16, -2] {
+      records.extend(id.to_be_bytes());
+      records.extend(be32(6));
+      pixels.extend([0, 1, 0, 2, 0, 99]);
+    }
+    records.extend(b"8BIMmul \x80\0\x02\0");
+    let luni = [
+      b"8BIMluni".to_vec(),
+      be32(8),
+      be32(2),
+      vec![0, b'T', 0, 0xf8],
+    ];
+    let mask = [
+      be32(20),
+      be32(0),
+      be32(2),
+      be32(1),
+      be32(3),
+      vec![77, 0, 0, 0],
+    ];
+    records.extend(be32(20 + 8 + 4 + 20));
+    records.extend(mask.concat());
+    records.extend([be32(0), vec![0, 0, 0, 0]].concat());
+    records.extend(luni.concat());
+
+    let info = [records, pixels].concat();
+    psd.extend(be32(info.len() as i32 + 4));
+    psd.extend(be32(info.len() as i32));
+    psd.extend(info);
+
+    let stack = read_psd(&psd, &Limits::default()).unwrap();
+    let [bg, top] = &stack.layers[..] else {
+      panic!("Expected two layers");
+    };
+    assert_eq!(bg.name, "bg");
+    assert_eq!(bg.image.get_pixel(1, 0), &[20, 0, 200, 255]);
+    assert_eq!(bg.image.get_pixel(2, 0), &[30, 0, 200, 0]);
+    assert_eq!((top.name.as_str(), top.offset), ("Tø", (1, 0)));
+    assert_eq!((top.blend_mode, top.visible), (BlendMode::Multiply, false));
+    assert_eq!(top.image.get_pixel(0, 0), &[99, 0, 0, 255]);
+    assert_eq!(top.mask.as_ref().unwrap().components(), &[77]);
+    assert!((top.opacity - 128.0 / 255.0).abs() < 1e-9);
+
+    assert!(read_psd(&psd[..40], &Limits::default()).is_err());
+    assert!(matches!(
+      read_psd(b"8BPS\0\x02", &Limits::default()),
+      Err(Error::Unsupported(_))
+    ));
+  }
+}
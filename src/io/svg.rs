@@ -0,0 +1,586 @@
+//! Rendering SVG documents to RGBA images, with the `svg` feature.
+//!
+//! This covers the flat vector art of icons and logos: the shapes (`path`,
+//! `rect`, `circle`, `ellipse`, `line`, `polyline`, `polygon`) inside nested
+//! `g` and `svg` groups, with transforms, solid fills and strokes, opacity,
+//! and the `viewBox` and `preserveAspectRatio` of the root element. Styling
+//! comes from presentation attributes and `style` attributes.
+//!
+//! Everything else is skipped: text, embedded images, gradients and
+//! patterns, clipping, masks, filters, `use` references and stylesheets.
+//! Paints that refer to a gradient use their fallback color, if any. Group
+//! opacity is applied to each shape separately, and stroke corners are
+//! always rounded.
+
+use std::collections::HashMap;
+
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::{
+  color::Rgba8,
+  draw::path::{fill, FillRule, LineCap, Path, KAPPA},
+  error::{Error, Result},
+  limits::Limits,
+  ImageBuffer,
+};
+
+/// Elements whose content is never drawn directly
+const SKIPPED: &[&str] = &[
+  "clipPath",
+  "defs",
+  "desc",
+  "filter",
+  "foreignObject",
+  "image",
+  "linearGradient",
+  "marker",
+  "mask",
+  "metadata",
+  "pattern",
+  "radialGradient",
+  "script",
+  "style",
+  "symbol",
+  "text",
+  "title",
+];
+
+/// Settings for [`render_svg`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SvgOptions {
+  /// Width of the image in pixels. If only one of the width and height is
+  /// given, the other follows the document's aspect ratio.
+  pub width:  Option<usize>,
+  pub height: Option<usize>,
+  /// Resolution used when no size is given, in pixels per inch. At the
+  /// default of 96, one CSS pixel of the document is one image pixel.
+  pub dpi:    f64,
+  /// Checked against the size of the image before it is allocated
+  pub limits: Limits,
+}
+
+impl Default for SvgOptions {
+  fn default() -> Self {
+    SvgOptions {
+      width:  None,
+      height: None,
+      dpi:    96.0,
+      limits: Limits::default(),
+    }
+  }
+}
+
+/// Renders an SVG document to a transparent image, with straight alpha.
+///
+/// Fails with [`Error::Decode`] if the document is not well-formed XML,
+/// its root is not an `svg` element, it has no size, or it contains
+/// malformed path data.
+pub fn render_svg(
+  data: &[u8],
+  options: &SvgOptions,
+) -> Result<ImageBuffer<u8, 4, true>> {
+  let mut image = None;
+  let mut stack: Vec<State> = Vec::new();
+  // Depth within an element that is not drawn
+  let mut skip = 0;
+  for event in EventReader::new(data) {
+    match event.map_err(|e| Error::Decode(format!("Invalid SVG: {e}")))? {
+      XmlEvent::StartElement {
+        name,
+        attributes,
+        ..
+      } => {
+        if skip > 0 {
+          skip += 1;
+          continue;
+        }
+        let attributes: HashMap<&str, &str> = attributes
+          .iter()
+          .map(|a| (a.name.local_name.as_str(), a.value.as_str()))
+          .collect();
+        let element = name.local_name.as_str();
+        let parent = match stack.last() {
+          Some(parent) => *parent,
+          None if element == "svg" => {
+            let (canvas, viewport) = canvas(&attributes, options)?;
+            image = Some(canvas);
+            State {
+              transform: viewport,
+              ..State::default()
+            }
+          }
+          None => {
+            return Err(Error::Decode(format!(
+              "Expected an SVG document, found a <{element}> element"
+            )));
+          }
+        };
+        let Some(state) = parent.child(&attributes) else {
+          skip = 1;
+          continue;
+        };
+        if SKIPPED.contains(&element) {
+          skip = 1;
+          continue;
+        }
+        stack.push(state);
+        if let (Some(image), Some(shape)) =
+          (image.as_mut(), shape(element, &attributes)?)
+        {
+          state.draw(image, &shape);
+        }
+      }
+      XmlEvent::EndElement {
+        ..
+      } =>
+        if skip > 0 {
+          skip -= 1;
+        } else {
+          stack.pop();
+        },
+      _ => {}
+    }
+  }
+  image.ok_or_else(|| Error::Decode("Empty SVG document".to_string()))
+}
+
+/// Allocates the image for a root `svg` element, and returns it with the
+/// transform from the document's user space to its pixels
+fn canvas(
+  attributes: &HashMap<&str, &str>,
+  options: &SvgOptions,
+) -> Result<(ImageBuffer<u8, 4, true>, [f64; 6])> {
+  let view_box = attributes
+    .get("viewBox")
+    .map(|v| numbers(v))
+    .filter(|v| v.len() == 4 && v[2] > 0.0 && v[3] > 0.0);
+  let width = attributes.get("width").and_then(|v| length(v));
+  let height = attributes.get("height").and_then(|v| length(v));
+  let (natural_width, natural_height) = match (width, height, &view_box) {
+    (Some(w), Some(h), _) => (w, h),
+    (Some(w), None, Some(v)) => (w, w * v[3] / v[2]),
+    (None, Some(h), Some(v)) => (h * v[2] / v[3], h),
+    (None, None, Some(v)) => (v[2], v[3]),
+    _ => {
+      return Err(Error::Decode(
+        "SVG document needs a width and height or a viewBox".to_string(),
+      ));
+    }
+  };
+  if natural_width <= 0.0 || natural_height <= 0.0 {
+    return Err(Error::Decode("SVG document has no area".to_string()));
+  }
+
+  let pixels = |v: f64| (v.round() as usize).max(1);
+  let scale = options.dpi / 96.0;
+  let (w, h) = match (options.width, options.height) {
+    (Some(w), Some(h)) => (w, h),
+    (Some(w), None) => (w, pixels(w as f64 * natural_height / natural_width)),
+    (None, Some(h)) => (pixels(h as f64 * natural_width / natural_height), h),
+    (None, None) =>
+      (
+        pixels(natural_width * scale),
+        pixels(natural_height * scale),
+      ),
+  };
+  let image = ImageBuffer::try_empty(w, h, &options.limits)?;
+
+  let view_box =
+    view_box.unwrap_or(vec![0.0, 0.0, natural_width, natural_height]);
+  let (sx, sy) = (w as f64 / view_box[2], h as f64 / view_box[3]);
+  let mut modes = attributes
+    .get("preserveAspectRatio")
+    .map_or("", |v| v)
+    .split_whitespace();
+  let align = modes.next().unwrap_or("xMidYMid");
+  if align == "none" {
+    let transform = [sx, 0.0, 0.0, sy, -view_box[0] * sx, -view_box[1] * sy];
+    return Ok((image, transform));
+  }
+  let s = if modes.next() == Some("slice") {
+    sx.max(sy)
+  } else {
+    sx.min(sy)
+  };
+  let fraction = |axis: &str| {
+    if align.contains(&format!("{axis}Min")) {
+      0.0
+    } else if align.contains(&format!("{axis}Max")) {
+      1.0
+    } else {
+      0.5
+    }
+  };
+  let tx = -view_box[0] * s + fraction("x") * (w as f64 - view_box[2] * s);
+  let ty = -view_box[1] * s + fraction("Y") * (h as f64 - view_box[3] * s);
+  Ok((image, [s, 0.0, 0.0, s, tx, ty]))
+}
+
+/// A fill or stroke
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Paint {
+  None,
+  Color(Rgba8),
+  /// The inherited `color` property
+  CurrentColor,
+}
+
+/// The styling in effect for an element, inherited by its children
+#[derive(Clone, Copy, Debug)]
+struct State {
+  /// From user space to image pixels
+  transform:      [f64; 6],
+  color:          Rgba8,
+  fill:           Paint,
+  fill_opacity:   f64,
+  fill_rule:      FillRule,
+  stroke:         Paint,
+  stroke_opacity: f64,
+  stroke_width:   f64,
+  line_cap:       LineCap,
+  /// The product of the opacities of the element and its ancestors
+  opacity:        f64,
+  visible:        bool,
+}
+
+impl Default for State {
+  fn default() -> Self {
+    State {
+      transform:      [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+      color:          Rgba8::BLACK,
+      fill:           Paint::Color(Rgba8::BLACK),
+      fill_opacity:   1.0,
+      fill_rule:      FillRule::NonZero,
+      stroke:         Paint::None,
+      stroke_opacity: 1.0,
+      stroke_width:   1.0,
+      line_cap:       LineCap::Butt,
+      opacity:        1.0,
+      visible:        true,
+    }
+  }
+}
+
+impl State {
+  /// The state of a child element with these attributes, or `None` if it
+  /// is not displayed. Declarations in a `style` attribute override
+  /// presentation attributes, and invalid values are ignored.
+  fn child(&self, attributes: &HashMap<&str, &str>) -> Option<State> {
+    let mut state = *self;
+    if let Some(transform) = attributes.get("transform") {
+      state.transform = multiply(state.transform, parse_transform(transform));
+    }
+    let declarations = attributes.get("style").into_iter().flat_map(|style| {
+      style.split(';').filter_map(|declaration| {
+        let (property, value) = declaration.split_once(':')?;
+        Some((property.trim(), value.trim()))
+      })
+    });
+    let properties: HashMap<&str, &str> = attributes
+      .iter()
+      .map(|(k, v)| (*k, v.trim()))
+      .chain(declarations)
+      .collect();
+    for (&property, &value) in &properties {
+      match property {
+        "display" if value == "none" => return None,
+        "visibility" => state.visible = value == "visible",
+        "color" =>
+          if let Ok(color) = Rgba8::parse(value) {
+            state.color = color;
+          },
+        "fill" => state.fill = parse_paint(value).unwrap_or(state.fill),
+        "stroke" => state.stroke = parse_paint(value).unwrap_or(state.stroke),
+        "fill-opacity" =>
+          state.fill_opacity = fraction(value).unwrap_or(state.fill_opacity),
+        "stroke-opacity" =>
+          state.stroke_opacity = fraction(value).unwrap_or(state.stroke_opacity),
+        "opacity" => state.opacity *= fraction(value).unwrap_or(1.0),
+        "fill-rule" =>
+          state.fill_rule = match value {
+            "evenodd" => FillRule::EvenOdd,
+            _ => FillRule::NonZero,
+          },
+        "stroke-width" =>
+          state.stroke_width = length(value).unwrap_or(state.stroke_width),
+        "stroke-linecap" =>
+          state.line_cap = match value {
+            "round" => LineCap::Round,
+            "square" => LineCap::Square,
+            _ => LineCap::Butt,
+          },
+        _ => {}
+      }
+    }
+    Some(state)
+  }
+
+  /// The color of `paint` with the given opacity applied
+  fn resolve(&self, paint: Paint, opacity: f64) -> Option<Rgba8> {
+    let color = match paint {
+      Paint::None => return None,
+      Paint::Color(color) => color,
+      Paint::CurrentColor => self.color,
+    };
+    let alpha = (f64::from(color.a) * opacity * self.opacity).round() as u8;
+    (alpha > 0).then_some(color.with_alpha(alpha))
+  }
+
+  /// Fills and then strokes a shape given in user space
+  fn draw(&self, image: &mut ImageBuffer<u8, 4, true>, shape: &Path) {
+    if !self.visible {
+      return;
+    }
+    if let Some(color) = self.resolve(self.fill, self.fill_opacity) {
+      fill(
+        image,
+        &shape.transform(self.transform),
+        color,
+        self.fill_rule,
+      );
+    }
+    if let Some(color) = self.resolve(self.stroke, self.stroke_opacity) {
+      let outline = shape.stroke(self.stroke_width, self.line_cap);
+      fill(
+        image,
+        &outline.transform(self.transform),
+        color,
+        FillRule::NonZero,
+      );
+    }
+  }
+}
+
+/// The outline of a shape element in user space, or `None` for elements
+/// that are not shapes or have no area
+fn shape(
+  element: &str,
+  attributes: &HashMap<&str, &str>,
+) -> Result<Option<Path>> {
+  let get = |name: &str| attributes.get(name).and_then(|v| length(v));
+  let value = |name: &str| get(name).unwrap_or(0.0);
+  let path = match element {
+    "path" => Path::parse_svg(attributes.get("d").map_or("", |d| d))?,
+    "rect" => {
+      let (x, y, w, h) =
+        (value("x"), value("y"), value("width"), value("height"));
+      if w <= 0.0 || h <= 0.0 {
+        return Ok(None);
+      }
+      let (rx, ry) = match (get("rx"), get("ry")) {
+        (Some(rx), Some(ry)) => (rx, ry),
+        (Some(r), None) | (None, Some(r)) => (r, r),
+        (None, None) => (0.0, 0.0),
+      };
+      let (rx, ry) = (rx.clamp(0.0, w / 2.0), ry.clamp(0.0, h / 2.0));
+      let (kx, ky) = (rx * (1.0 - KAPPA), ry * (1.0 - KAPPA));
+      let (right, bottom) = (x + w, y + h);
+      Path::new()
+        .move_to(x + rx, y)
+        .line_to(right - rx, y)
+        .cubic_to(right - kx, y, right, y + ky, right, y + ry)
+        .line_to(right, bottom - ry)
+        .cubic_to(right, bottom - ky, right - kx, bottom, right - rx, bottom)
+        .line_to(x + rx, bottom)
+        .cubic_to(x + kx, bottom, x, bottom - ky, x, bottom - ry)
+        .line_to(x, y + ry)
+        .cubic_to(x, y + ky, x + kx, y, x + rx, y)
+        .close()
+    }
+    "circle" | "ellipse" => {
+      let (cx, cy) = (value("cx"), value("cy"));
+      let (rx, ry) = if element == "circle" {
+        (value("r"), value("r"))
+      } else {
+        (value("rx"), value("ry"))
+      };
+      if rx <= 0.0 || ry <= 0.0 {
+        return Ok(None);
+      }
+      Path::new().ellipse((cx, cy), (rx, ry))
+    }
+    "line" =>
+      Path::new()
+        .move_to(value("x1"), value("y1"))
+        .line_to(value("x2"), value("y2")),
+    "polyline" | "polygon" => {
+      let points = numbers(attributes.get("points").map_or("", |p| p));
+      let mut pairs = points.chunks_exact(2);
+      let Some(first) = pairs.next() else {
+        return Ok(None);
+      };
+      let path = pairs
+        .fold(Path::new().move_to(first[0], first[1]), |path, p| {
+          path.line_to(p[0], p[1])
+        });
+      if element == "polygon" {
+        path.close()
+      } else {
+        path
+      }
+    }
+    _ => return Ok(None),
+  };
+  Ok(Some(path))
+}
+
+/// The numbers in a list separated by whitespace or commas
+fn numbers(list: &str) -> Vec<f64> {
+  list
+    .split(|c: char| c.is_whitespace() || c == ',')
+    .filter_map(|n| n.parse().ok())
+    .collect()
+}
+
+/// A length in user units, which are CSS pixels. Percentages and
+/// font-relative units are not supported.
+fn length(value: &str) -> Option<f64> {
+  let value = value.trim();
+  let split = value
+    .find(|c: char| c.is_ascii_alphabetic() || c == '%')
+    .unwrap_or(value.len());
+  let (number, unit) = value.split_at(split);
+  let number: f64 = number.trim().parse().ok()?;
+  let scale = match unit {
+    "" | "px" => 1.0,
+    "in" => 96.0,
+    "cm" => 96.0 / 2.54,
+    "mm" => 96.0 / 25.4,
+    "pt" => 96.0 / 72.0,
+    "pc" => 16.0,
+    _ => return None,
+  };
+  Some(number * scale)
+}
+
+/// An opacity, as a number or a percentage, clamped to `0..=1`
+fn fraction(value: &str) -> Option<f64> {
+  let value = value.trim();
+  let fraction = match value.strip_suffix('%') {
+    Some(percent) => percent.trim().parse::<f64>().ok()? / 100.0,
+    None => value.parse().ok()?,
+  };
+  Some(fraction.clamp(0.0, 1.0))
+}
+
+fn parse_paint(value: &str) -> Option<Paint> {
+  match value {
+    "none" => Some(Paint::None),
+    "currentColor" => Some(Paint::CurrentColor),
+    // Gradients and patterns are unsupported, so use the fallback color
+    _ if value.starts_with("url(") => {
+      let fallback = value.split_once(')').map_or("", |(_, f)| f.trim());
+      if fallback.is_empty() {
+        Some(Paint::None)
+      } else {
+        parse_paint(fallback)
+      }
+    }
+    _ => Rgba8::parse(value).ok().map(Paint::Color),
+  }
+}
+
+/// The transform applying `inner` and then `outer`
+fn multiply(outer: [f64; 6], inner: [f64; 6]) -> [f64; 6] {
+  let [a, b, c, d, e, f] = outer;
+  let [a2, b2, c2, d2, e2, f2] = inner;
+  [
+    a * a2 + c * b2,
+    b * a2 + d * b2,
+    a * c2 + c * d2,
+    b * c2 + d * d2,
+    a * e2 + c * f2 + e,
+    b * e2 + d * f2 + f,
+  ]
+}
+
+/// The transform described by a `transform` attribute, ignoring any
+/// malformed functions
+fn parse_transform(value: &str) -> [f64; 6] {
+  let mut transform = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+  for function in value.split(')') {
+    let Some((name, arguments)) = function.split_once('(') else {
+      continue;
+    };
+    let name = name.trim_matches(|c: char| c.is_whitespace() || c == ',');
+    let args = numbers(arguments);
+    let tan = |degrees: f64| degrees.to_radians().tan();
+    let local = match (name, args.as_slice()) {
+      ("matrix", &[a, b, c, d, e, f]) => [a, b, c, d, e, f],
+      ("translate", &[x]) => [1.0, 0.0, 0.0, 1.0, x, 0.0],
+      ("translate", &[x, y]) => [1.0, 0.0, 0.0, 1.0, x, y],
+      ("scale", &[s]) => [s, 0.0, 0.0, s, 0.0, 0.0],
+      ("scale", &[sx, sy]) => [sx, 0.0, 0.0, sy, 0.0, 0.0],
+      ("rotate", &[angle, ref center @ ..])
+        if matches!(center.len(), 0 | 2) =>
+      {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let rotation = [cos, sin, -sin, cos, 0.0, 0.0];
+        match center {
+          &[x, y] =>
+            multiply(
+              multiply([1.0, 0.0, 0.0, 1.0, x, y], rotation),
+              [1.0, 0.0, 0.0, 1.0, -x, -y],
+            ),
+          _ => rotation,
+        }
+      }
+      ("skewX", &[angle]) => [1.0, 0.0, tan(angle), 1.0, 0.0, 0.0],
+      ("skewY", &[angle]) => [1.0, tan(angle), 0.0, 1.0, 0.0, 0.0],
+      _ => continue,
+    };
+    transform = multiply(transform, local);
+  }
+  transform
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::pixel::PixelContainer;
+
+  const ICON: &str = r##"<?xml version="1.0"?>
+    <svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"
+         viewBox="0 0 40 20">
+      <defs><rect width="40" height="20" fill="blue"/></defs>
+      <rect x="4" y="4" width="12" height="12" fill="#f00"/>
+      <g transform="translate(20 0)" style="opacity: 0.5">
+        <circle cx="10" cy="10" r="6" fill="none" stroke="rgb(0, 0, 255)"
+                stroke-width="6"/>
+      </g>
+      <text x="0" y="10">Hidden</text>
+    </svg>"##;
+
+  #[test]
+  fn renders_svg_shapes() {
+    let image = render_svg(ICON.as_bytes(), &SvgOptions::default()).unwrap();
+    assert_eq!((image.width, image.height), (20, 10));
+    // The red square covers 2 to 8 after the viewBox halves everything
+    assert_eq!(image.get_pixel(4, 4), &[255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(9, 4), &[0, 0, 0, 0]);
+    // The ring around (15, 5) runs from 1.5 to 4.5 pixels out
+    assert_eq!(image.get_pixel(15, 2), &[0, 0, 255, 128]);
+    assert_eq!(image.get_pixel(15, 5), &[0, 0, 0, 0]);
+
+    let options = SvgOptions {
+      dpi: 192.0,
+      ..Default::default()
+    };
+    let large = render_svg(ICON.as_bytes(), &options).unwrap();
+    assert_eq!((large.width, large.height), (40, 20));
+    assert_eq!(large.get_pixel(8, 8), &[255, 0, 0, 255]);
+    let options = SvgOptions {
+      height: Some(30),
+      ..Default::default()
+    };
+    let tall = render_svg(ICON.as_bytes(), &options).unwrap();
+    assert_eq!((tall.width, tall.height), (60, 30));
+
+    assert!(render_svg(b"<html/>", &SvgOptions::default()).is_err());
+    assert!(render_svg(b"<svg width='4'>", &SvgOptions::default()).is_err());
+    assert_eq!(
+      parse_paint("url(#g) #0f08"),
+      Some(Paint::Color(Rgba8::new(0, 255, 0, 136)))
+    );
+  }
+}
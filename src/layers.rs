@@ -0,0 +1,258 @@
+//! Stacks of RGBA layers composited into one image, as in image editors.
+//!
+//! Each [`Layer`] is placed on the canvas at an offset, may be cut out by a
+//! mask, and is blended onto the layers beneath it with one of the
+//! [`BlendMode`]s of [`ops::blend`](crate::ops::blend) at some opacity.
+//! After an edit only the pixels that changed need compositing again; see
+//! [`LayerStack::recomposite`].
+
+use std::ops::Range;
+
+use crate::{
+  error::{Error, Result},
+  limits::Limits,
+  ops::{
+    blend::{composite, BlendMode},
+    mask::Mask,
+    sprites::Rect,
+  },
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  video::check_dimensions,
+  ImageBuffer,
+};
+
+/// One layer of a [`LayerStack`]
+#[derive(Clone, Debug)]
+pub struct Layer<T: PixelComponent> {
+  /// Shown in editors, and kept when reading and writing layered files
+  pub name:       String,
+  pub image:      ImageBuffer<T, 4, true>,
+  /// Position of the image's top-left corner on the canvas, which may be
+  /// outside it
+  pub offset:     (isize, isize),
+  /// Between 0 and 1, multiplying the image's alpha
+  pub opacity:    f64,
+  pub blend_mode: BlendMode,
+  /// Also multiplies the image's alpha, and must be the same size as it
+  pub mask:       Option<Mask>,
+  /// Hidden layers are skipped
+  pub visible:    bool,
+}
+
+impl<T: PixelComponent> Layer<T> {
+  /// An unnamed, fully opaque and visible layer at the origin, blended
+  /// normally
+  pub fn new(image: ImageBuffer<T, 4, true>) -> Self {
+    Layer {
+      name: String::new(),
+      image,
+      offset: (0, 0),
+      opacity: 1.0,
+      blend_mode: BlendMode::Normal,
+      mask: None,
+      visible: true,
+    }
+  }
+
+  /// The part of the canvas the layer covers, if any
+  fn bounds(&self, width: usize, height: usize) -> Option<Rect> {
+    let (x, y) = self.offset;
+    let left = x.clamp(0, width as isize) as usize;
+    let top = y.clamp(0, height as isize) as usize;
+    let right = (x + self.image.width as isize).clamp(0, width as isize);
+    let bottom = (y + self.image.height as isize).clamp(0, height as isize);
+    let (right, bottom) = (right as usize, bottom as usize);
+    (right > left && bottom > top).then_some(Rect {
+      x:      left,
+      y:      top,
+      width:  right - left,
+      height: bottom - top,
+    })
+  }
+}
+
+/// Layers over a canvas of fixed size, from the bottom up
+#[derive(Clone, Debug)]
+pub struct LayerStack<T: PixelComponent> {
+  pub width:  usize,
+  pub height: usize,
+  pub layers: Vec<Layer<T>>,
+}
+
+impl<T: PixelComponent> LayerStack<T> {
+  /// An empty, transparent canvas
+  pub fn new(width: usize, height: usize) -> Self {
+    LayerStack {
+      width,
+      height,
+      layers: Vec::new(),
+    }
+  }
+
+  /// Adds a layer on top of the others
+  pub fn push(&mut self, layer: Layer<T>) { self.layers.push(layer); }
+
+  /// Composites every visible layer into a new image the size of the
+  /// canvas. Fails if a layer's mask is not the size of its image.
+  pub fn flatten(&self) -> Result<ImageBuffer<T, 4, true>> {
+    self.flatten_range(0..self.layers.len())
+  }
+
+  /// Composites the layers in `range` alone, over transparency, such as to
+  /// merge a group of layers into one. Layers blended with modes other than
+  /// [`BlendMode::Normal`] blend only with the other layers in the range.
+  pub fn flatten_range(
+    &self,
+    range: Range<usize>,
+  ) -> Result<ImageBuffer<T, 4, true>> {
+    let mut canvas =
+      ImageBuffer::try_empty(self.width, self.height, &Limits::none())?;
+    let whole = Rect {
+      x:      0,
+      y:      0,
+      width:  self.width,
+      height: self.height,
+    };
+    self.composite_into(&mut canvas, whole, range)?;
+    Ok(canvas)
+  }
+
+  /// Replaces the layers in `range` with a single layer covering the
+  /// canvas, composited from them
+  pub fn merge(&mut self, range: Range<usize>) -> Result<()> {
+    let merged = Layer::new(self.flatten_range(range.clone())?);
+    self.layers.splice(range, [merged]);
+    Ok(())
+  }
+
+  /// Composites the stack again within `dirty` only, updating `canvas`, an
+  /// earlier result of [`flatten`](LayerStack::flatten), after layers have
+  /// changed there. Parts of `dirty` beyond the canvas are ignored.
+  pub fn recomposite(
+    &self,
+    canvas: &mut ImageBuffer<T, 4, true>,
+    dirty: Rect,
+  ) -> Result<()> {
+    check_dimensions((self.width, self.height), canvas)?;
+    let right = (dirty.x + dirty.width).min(self.width);
+    let bottom = (dirty.y + dirty.height).min(self.height);
+    if right <= dirty.x || bottom <= dirty.y {
+      return Ok(());
+    }
+    let region = Rect {
+      x:      dirty.x,
+      y:      dirty.y,
+      width:  right - dirty.x,
+      height: bottom - dirty.y,
+    };
+    for y in region.y..bottom {
+      let row = (y * self.width + region.x) * 4;
+      canvas.components_mut()[row..row + region.width * 4].fill(T::zero());
+    }
+    self.composite_into(canvas, region, 0..self.layers.len())
+  }
+
+  /// Composites the layers in `range` onto `canvas` within `region`, which
+  /// must lie on the canvas
+  fn composite_into(
+    &self,
+    canvas: &mut ImageBuffer<T, 4, true>,
+    region: Rect,
+    range: Range<usize>,
+  ) -> Result<()> {
+    let layers = self.layers.get(range.clone()).ok_or_else(|| {
+      Error::InvalidArgument(format!(
+        "Layers {range:?} are beyond the {} in the stack",
+        self.layers.len()
+      ))
+    })?;
+    let white = canvas.white();
+    for layer in layers.iter().filter(|layer| layer.visible) {
+      if let Some(mask) = &layer.mask {
+        check_dimensions((layer.image.width, layer.image.height), mask)?;
+      }
+      let Some(bounds) = layer.bounds(self.width, self.height) else {
+        continue;
+      };
+      let left = bounds.x.max(region.x);
+      let top = bounds.y.max(region.y);
+      let right = (bounds.x + bounds.width).min(region.x + region.width);
+      let bottom = (bounds.y + bounds.height).min(region.y + region.height);
+      let scale = layer.image.white();
+      for y in top..bottom {
+        for x in left..right {
+          let lx = (x as isize - layer.offset.0) as usize;
+          let ly = (y as isize - layer.offset.1) as usize;
+          let pel = layer.image.get_pixel(lx, ly);
+          let mut source =
+            [0, 1, 2, 3].map(|k| pel[k].to_f64().unwrap_or_default() / scale);
+          source[3] *= layer.opacity.clamp(0.0, 1.0);
+          if let Some(mask) = &layer.mask {
+            source[3] *= f64::from(mask.get_pixel(lx, ly)[0]) / 255.0;
+          }
+          if source[3] <= 0.0 {
+            continue;
+          }
+          let out = canvas.get_pixel_mut(x, y);
+          let backdrop =
+            [0, 1, 2, 3].map(|k| out[k].to_f64().unwrap_or_default() / white);
+          let result = composite(backdrop, source, layer.blend_mode);
+          for (c, v) in out.iter_mut().zip(result) {
+            *c = component_from_f64(v * white);
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn stacks_flatten_merge_and_recomposite() {
+    let mut stack = LayerStack::<u8>::new(4, 4);
+    stack.push(Layer::new(ImageBuffer::with_val(
+      &[200, 100, 50, 255],
+      4,
+      4,
+    )));
+    let mut top =
+      Layer::new(ImageBuffer::with_val(&[255, 255, 255, 255], 2, 2));
+    top.offset = (3, -1);
+    top.blend_mode = BlendMode::Multiply;
+    stack.push(top);
+    let mut masked = Layer::new(ImageBuffer::with_val(&[0, 0, 255, 255], 2, 1));
+    masked.offset = (0, 3);
+    masked.opacity = 0.5;
+    masked.mask = Some(Mask::try_with_data(vec![255, 0], 2, 1).unwrap());
+    stack.push(masked);
+
+    let flat = stack.flatten().unwrap();
+    assert_eq!(flat.get_pixel(3, 0), &[200, 100, 50, 255]);
+    assert_eq!(flat.get_pixel(0, 3), &[100, 50, 153, 255]);
+    assert_eq!(flat.get_pixel(1, 3), &[200, 100, 50, 255]);
+
+    // Hiding the bottom layer and recompositing its corner
+    let mut canvas = flat.clone();
+    stack.layers[0].visible = false;
+    let corner = Rect {
+      x:      2,
+      y:      0,
+      width:  9,
+      height: 2,
+    };
+    stack.recomposite(&mut canvas, corner).unwrap();
+    assert_eq!(canvas.get_pixel(3, 0), &[255, 255, 255, 255]);
+    assert_eq!(canvas.get_pixel(2, 0), &[0, 0, 0, 0]);
+    assert_eq!(canvas.get_pixel(1, 0), &[200, 100, 50, 255]);
+
+    stack.layers[0].visible = true;
+    stack.merge(0..2).unwrap();
+    assert_eq!(stack.layers.len(), 2);
+    assert_eq!(stack.flatten().unwrap().components(), flat.components());
+    assert!(stack.flatten_range(1..3).is_err());
+  }
+}
@@ -0,0 +1,479 @@
+//! Deferred image operations that are fused into as few passes as possible.
+//!
+//! Operations on an [`ImageExpr`] only record themselves in a graph.
+//! [`compute`](ImageExpr::compute) then evaluates the graph: every run of
+//! point operations, including ones combining several images, becomes a
+//! single pass over the pixels writing one output buffer. Only operations
+//! that need whole images, added with [`global`](ImageExpr::global), are
+//! evaluated on their own, and only once where several branches of a pass
+//! read their result.
+//!
+//! Chains of neighborhood operations, added with
+//! [`neighborhood`](ImageExpr::neighborhood), run tile by tile instead of
+//! image by image. Each tile is computed through the whole chain with a
+//! halo wide enough for every step, so intermediate results stay in cache
+//! rather than going through memory once per step.
+
+use std::{borrow::Cow, collections::HashMap, sync::Arc};
+
+use crate::{
+  compute::ExecutionContext,
+  error::{Error, Result},
+  image_buffer::BorderMode,
+  limits::Limits,
+  pixel::{PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+type PointFn<T, const N: usize> = Arc<dyn Fn(&[T; N]) -> [T; N] + Send + Sync>;
+type ZipFn<T, const N: usize> =
+  Arc<dyn Fn(&[T; N], &[T; N]) -> [T; N] + Send + Sync>;
+type GlobalFn<T, const N: usize, const A: bool> =
+  Arc<dyn Fn(&ImageBuffer<T, N, A>) -> ImageBuffer<T, N, A> + Send + Sync>;
+type WindowFn<T, const N: usize> =
+  Arc<dyn Fn(&Window<T, N>) -> [T; N] + Send + Sync>;
+
+/// A pixel of the fused pass, computed from its index
+type Kernel<'a, T, const N: usize> = Box<dyn Fn(usize) -> [T; N] + Sync + 'a>;
+
+enum Node<T: PixelComponent, const N: usize, const A: bool> {
+  Source(Arc<ImageBuffer<T, N, A>>),
+  Map(ImageExpr<T, N, A>, PointFn<T, N>),
+  Zip(ImageExpr<T, N, A>, ImageExpr<T, N, A>, ZipFn<T, N>),
+  Global(ImageExpr<T, N, A>, GlobalFn<T, N, A>),
+  Window(ImageExpr<T, N, A>, usize, BorderMode, WindowFn<T, N>),
+}
+
+/// A rectangle of an image held by one step of a tiled chain
+#[derive(Clone, Copy)]
+struct Region {
+  x:      usize,
+  y:      usize,
+  width:  usize,
+  height: usize,
+}
+
+/// The pixels around one pixel, as seen by a
+/// [`neighborhood`](ImageExpr::neighborhood) operation
+pub struct Window<'a, T, const N: usize> {
+  data:   &'a [T],
+  region: Region,
+  size:   (usize, usize),
+  center: (usize, usize),
+  radius: usize,
+  border: BorderMode,
+}
+
+impl<T: PixelComponent, const N: usize> Window<'_, T, N> {
+  /// The pixel `dx` across and `dy` down from the center. Offsets are
+  /// limited to the radius; pixels past the edges of the image are found
+  /// with the border mode.
+  pub fn get(&self, dx: isize, dy: isize) -> [T; N] {
+    let r = self.radius as isize;
+    let resolve = |center: usize, d: isize, len: usize| {
+      self.border.resolve(center as isize + d.clamp(-r, r), len)
+    };
+    let x = resolve(self.center.0, dx, self.size.0) - self.region.x;
+    let y = resolve(self.center.1, dy, self.size.1) - self.region.y;
+    let i = (y * self.region.width + x) * N;
+    std::array::from_fn(|c| self.data[i + c])
+  }
+
+  /// How far from the center [`get`](Self::get) can look
+  pub fn radius(&self) -> usize { self.radius }
+
+  /// Position of the center in the image
+  pub fn position(&self) -> (usize, usize) { self.center }
+}
+
+/// One step of a tiled chain
+enum Stage<'a, T, const N: usize> {
+  Map(&'a PointFn<T, N>),
+  Window(usize, BorderMode, &'a WindowFn<T, N>),
+}
+
+impl<T: PixelComponent, const N: usize> Stage<'_, T, N> {
+  fn radius(&self) -> usize {
+    match self {
+      Stage::Map(_) => 0,
+      Stage::Window(radius, ..) => *radius,
+    }
+  }
+}
+
+/// An image defined by operations still to be carried out. Cloning is cheap
+/// and shares the recorded operations.
+pub struct ImageExpr<T: PixelComponent, const N: usize, const A: bool> {
+  node: Arc<Node<T, N, A>>,
+}
+
+impl<T: PixelComponent, const N: usize, const A: bool> Clone
+  for ImageExpr<T, N, A>
+{
+  fn clone(&self) -> Self {
+    ImageExpr {
+      node: self.node.clone(),
+    }
+  }
+}
+
+/// Buffers the fused pass reads, keyed by the node that produced them
+type Inputs<T, const N: usize, const A: bool> =
+  HashMap<*const Node<T, N, A>, Arc<ImageBuffer<T, N, A>>>;
+
+impl<T: PixelComponent, const N: usize, const A: bool> ImageExpr<T, N, A> {
+  fn new(node: Node<T, N, A>) -> Self {
+    ImageExpr {
+      node: Arc::new(node),
+    }
+  }
+
+  pub fn source(image: ImageBuffer<T, N, A>) -> Self {
+    Self::from_arc(Arc::new(image))
+  }
+
+  /// An expression reading a shared buffer, which is not copied
+  pub fn from_arc(image: Arc<ImageBuffer<T, N, A>>) -> Self {
+    Self::new(Node::Source(image))
+  }
+
+  /// Applies `f` to every pixel
+  pub fn map(
+    &self,
+    f: impl Fn(&[T; N]) -> [T; N] + Send + Sync + 'static,
+  ) -> Self {
+    Self::new(Node::Map(self.clone(), Arc::new(f)))
+  }
+
+  /// Combines each pixel with the pixel at the same position in `other`,
+  /// which must turn out to be the same size
+  pub fn zip_with(
+    &self,
+    other: &ImageExpr<T, N, A>,
+    f: impl Fn(&[T; N], &[T; N]) -> [T; N] + Send + Sync + 'static,
+  ) -> Self {
+    Self::new(Node::Zip(self.clone(), other.clone(), Arc::new(f)))
+  }
+
+  /// Applies an operation that needs the whole image, such as a blur or a
+  /// resize. Point operations before and after it are fused separately.
+  pub fn global(
+    &self,
+    f: impl Fn(&ImageBuffer<T, N, A>) -> ImageBuffer<T, N, A>
+      + Send
+      + Sync
+      + 'static,
+  ) -> Self {
+    Self::new(Node::Global(self.clone(), Arc::new(f)))
+  }
+
+  /// Computes each pixel from the pixels up to `radius` away from it, such
+  /// as for a convolution or a morphological operation, reading past the
+  /// edges as `border` says.
+  ///
+  /// Consecutive neighborhood and point operations are evaluated together,
+  /// one tile of [`tile_size`](ExecutionContext::tile_size) square at a
+  /// time. Chains using [`BorderMode::Wrap`] read across the whole image and
+  /// are evaluated in one piece.
+  pub fn neighborhood(
+    &self,
+    radius: usize,
+    border: BorderMode,
+    f: impl Fn(&Window<T, N>) -> [T; N] + Send + Sync + 'static,
+  ) -> Self {
+    Self::new(Node::Window(self.clone(), radius, border, Arc::new(f)))
+  }
+
+  /// Evaluates the expression. Fails if images combined with
+  /// [`zip_with`](Self::zip_with) differ in size.
+  pub fn compute(&self) -> Result<ImageBuffer<T, N, A>> {
+    self.compute_in(&ExecutionContext::default())
+  }
+
+  /// [`compute`](Self::compute), splitting each pass into bands of rows as
+  /// `context` says
+  pub fn compute_in(
+    &self,
+    context: &ExecutionContext,
+  ) -> Result<ImageBuffer<T, N, A>> {
+    let image = self.materialize(&mut HashMap::new(), context)?;
+    Ok(Arc::try_unwrap(image).unwrap_or_else(|image| (*image).clone()))
+  }
+
+  /// Evaluates this node, reusing and adding to what has been evaluated in
+  /// `inputs`
+  fn materialize(
+    &self,
+    inputs: &mut Inputs<T, N, A>,
+    context: &ExecutionContext,
+  ) -> Result<Arc<ImageBuffer<T, N, A>>> {
+    let (width, height) = self.gather(inputs, context)?;
+    if let Some(image) = inputs.get(&Arc::as_ptr(&self.node)) {
+      return Ok(image.clone());
+    }
+    let kernel = self.kernel(inputs);
+    let mut result = ImageBuffer::try_empty(width, height, &Limits::none())?;
+    context.for_each_band(
+      result.components_mut(),
+      (width * N).max(1),
+      |first, band| {
+        let start = first * width;
+        for (i, pel) in band.chunks_exact_mut(N).enumerate() {
+          pel.copy_from_slice(&kernel(start + i));
+        }
+      },
+    );
+    Ok(Arc::new(result))
+  }
+
+  /// Evaluates the whole-image operations below this node, once each, and
+  /// returns the size of the fused pass
+  fn gather(
+    &self,
+    inputs: &mut Inputs<T, N, A>,
+    context: &ExecutionContext,
+  ) -> Result<(usize, usize)> {
+    let key = Arc::as_ptr(&self.node);
+    if let Some(image) = inputs.get(&key) {
+      return Ok((image.width, image.height));
+    }
+    let image = match &*self.node {
+      Node::Source(image) => image.clone(),
+      Node::Global(input, f) =>
+        Arc::new(f(&*input.materialize(inputs, context)?)),
+      Node::Window(..) => {
+        // Collect the chain of steps that can run tile by tile
+        let mut stages = Vec::new();
+        let mut node = self;
+        loop {
+          if !stages.is_empty() && inputs.contains_key(&Arc::as_ptr(&node.node))
+          {
+            break;
+          }
+          match &*node.node {
+            Node::Map(input, f) => {
+              stages.push(Stage::Map(f));
+              node = input;
+            }
+            Node::Window(input, radius, border, f) => {
+              stages.push(Stage::Window(*radius, *border, f));
+              node = input;
+            }
+            _ => break,
+          }
+        }
+        stages.reverse();
+        let base = node.materialize(inputs, context)?;
+        Arc::new(run_tiled(&base, &stages, context))
+      }
+      Node::Map(input, _) => return input.gather(inputs, context),
+      Node::Zip(a, b, _) => {
+        let expected = a.gather(inputs, context)?;
+        let actual = b.gather(inputs, context)?;
+        if expected != actual {
+          return Err(Error::DimensionMismatch {
+            expected,
+            actual,
+          });
+        }
+        return Ok(expected);
+      }
+    };
+    let size = (image.width, image.height);
+    inputs.insert(key, image);
+    Ok(size)
+  }
+
+  /// The fused pass for this node, reading what [`gather`](Self::gather)
+  /// evaluated
+  fn kernel<'a>(&'a self, inputs: &'a Inputs<T, N, A>) -> Kernel<'a, T, N> {
+    if let Some(image) = inputs.get(&Arc::as_ptr(&self.node)) {
+      return Box::new(move |i| {
+        *image.get_pixel(i % image.width, i / image.width)
+      });
+    }
+    match &*self.node {
+      Node::Map(input, f) => {
+        let input = input.kernel(inputs);
+        Box::new(move |i| f(&input(i)))
+      }
+      Node::Zip(a, b, f) => {
+        let (a, b) = (a.kernel(inputs), b.kernel(inputs));
+        Box::new(move |i| f(&a(i), &b(i)))
+      }
+      Node::Source(_) | Node::Global(..) | Node::Window(..) =>
+        unreachable!("Gathered before the fused pass"),
+    }
+  }
+}
+
+/// Runs `stages` over `base` one tile at a time, computing each step on the
+/// tile plus a halo as wide as the neighborhoods of the steps after it
+fn run_tiled<T: PixelComponent, const N: usize, const A: bool>(
+  base: &ImageBuffer<T, N, A>,
+  stages: &[Stage<T, N>],
+  context: &ExecutionContext,
+) -> ImageBuffer<T, N, A> {
+  let (width, height) = (base.width, base.height);
+  let wraps = stages
+    .iter()
+    .any(|s| matches!(s, Stage::Window(_, BorderMode::Wrap, _)));
+  let tile = if wraps {
+    usize::MAX
+  } else {
+    context.tile_size.max(1)
+  };
+  let context = ExecutionContext {
+    tile_size: tile,
+    ..context.clone()
+  };
+  // Halo each step's output needs for the steps after it
+  let mut halos = vec![0; stages.len()];
+  for s in (0..stages.len().saturating_sub(1)).rev() {
+    halos[s] = halos[s + 1] + stages[s + 1].radius();
+  }
+  let mut result = ImageBuffer::empty(width, height);
+  context.for_each_band(result.components_mut(), width * N, |first, band| {
+    let rows = band.len() / (width * N);
+    for x0 in (0..width).step_by(tile.min(width)) {
+      let tile_width = tile.min(width - x0);
+      let mut region = Region {
+        x: 0,
+        y: 0,
+        width,
+        height,
+      };
+      let mut data = Cow::Borrowed(base.components());
+      for (stage, &halo) in stages.iter().zip(&halos) {
+        let (x, y) = (x0.saturating_sub(halo), first.saturating_sub(halo));
+        let next = Region {
+          x,
+          y,
+          width: (x0 + tile_width + halo).min(width) - x,
+          height: (first + rows + halo).min(height) - y,
+        };
+        let mut out = Vec::with_capacity(next.width * next.height * N);
+        for py in next.y..next.y + next.height {
+          for px in next.x..next.x + next.width {
+            let window = Window {
+              data: &data,
+              region,
+              size: (width, height),
+              center: (px, py),
+              radius: stage.radius(),
+              border: BorderMode::Clamp,
+            };
+            out.extend(match stage {
+              Stage::Map(f) => f(&window.get(0, 0)),
+              Stage::Window(_, border, f) =>
+                f(&Window {
+                  border: *border,
+                  ..window
+                }),
+            });
+          }
+        }
+        region = next;
+        data = Cow::Owned(out);
+      }
+      // The last step's region is the tile itself
+      for (row, src) in data.chunks_exact(tile_width * N).enumerate() {
+        let start = (row * width + x0) * N;
+        band[start..start + tile_width * N].copy_from_slice(src);
+      }
+    }
+  });
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use super::*;
+
+  #[test]
+  fn chained_ops_fuse_and_shared_nodes_run_once() {
+    let image = ImageBuffer::<u8, 1, false>::empty(4, 3)
+      .map_indexed(&mut |x, y, _| [(x + y * 4) as u8]);
+    let expr = ImageExpr::source(image.clone());
+    let chained = (0..5).fold(expr.clone(), |e, _| e.map(|&[v]| [v + 1]));
+    let expected = image.map(&mut |&[v]| [v + 5]);
+    assert_eq!(
+      chained.compute().unwrap().components(),
+      expected.components()
+    );
+
+    // A whole-image step used twice is still evaluated once
+    let runs = Arc::new(AtomicUsize::new(0));
+    let counter = runs.clone();
+    let doubled = expr.global(move |image| {
+      counter.fetch_add(1, Ordering::SeqCst);
+      image.map(&mut |&[v]| [v * 2])
+    });
+    let sum = doubled
+      .map(|&[v]| [v / 2])
+      .zip_with(&doubled, |&[a], &[b]| [a + b]);
+    let result = sum.compute().unwrap();
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    assert_eq!(result.get_pixel(3, 2), &[33]);
+
+    let small = ImageExpr::source(ImageBuffer::<u8, 1, false>::empty(1, 1));
+    assert!(matches!(
+      expr.zip_with(&small, |a, _| *a).compute(),
+      Err(Error::DimensionMismatch { .. })
+    ));
+  }
+
+  #[test]
+  fn chained_neighborhoods_match_untiled() {
+    let image = ImageBuffer::<u8, 1, false>::empty(23, 17)
+      .map_indexed(&mut |x, y, _| [((x * 37 + y * 11) % 251) as u8]);
+    let blur = |w: &Window<u8, 1>| {
+      let mut sum = 0u32;
+      for dy in -1..=1 {
+        for dx in -1..=1 {
+          sum += u32::from(w.get(dx, dy)[0]);
+        }
+      }
+      [(sum / 9) as u8]
+    };
+    let sharpen = |w: &Window<u8, 1>| {
+      let edges = [(-2, 0), (2, 0), (0, -2), (0, 2)]
+        .map(|(dx, dy)| i32::from(w.get(dx, dy)[0]))
+        .iter()
+        .sum::<i32>();
+      [(5 * i32::from(w.get(0, 0)[0]) - edges).clamp(0, 255) as u8]
+    };
+    for border in [BorderMode::Clamp, BorderMode::Reflect, BorderMode::Wrap] {
+      let expr = ImageExpr::source(image.clone())
+        .neighborhood(1, border, blur)
+        .map(|&[v]| [v / 2 + 10])
+        .neighborhood(2, border, sharpen);
+      let whole = expr.compute_in(&ExecutionContext::single_threaded());
+      let tiled = expr.compute_in(&ExecutionContext {
+        tile_size: 5,
+        ..ExecutionContext::default()
+      });
+      assert_eq!(tiled.unwrap().components(), whole.unwrap().components());
+    }
+
+    // Compare a single step against a direct computation
+    let blurred = ImageExpr::source(image.clone())
+      .neighborhood(1, BorderMode::Clamp, blur)
+      .compute()
+      .unwrap();
+    let expected = image.map_indexed(&mut |x, y, _| {
+      let mut sum = 0u32;
+      for dy in -1..=1isize {
+        for dx in -1..=1isize {
+          let sx = BorderMode::Clamp.resolve(x as isize + dx, 23);
+          let sy = BorderMode::Clamp.resolve(y as isize + dy, 17);
+          sum += u32::from(image.get_pixel(sx, sy)[0]);
+        }
+      }
+      [(sum / 9) as u8]
+    });
+    assert_eq!(blurred.components(), expected.components());
+  }
+}
@@ -1,13 +1,42 @@
-#![feature(array_chunks)]
-#![feature(test)]
-extern crate test;
-
+pub mod calib;
+pub mod channel_semantics;
+pub mod codes;
+pub mod color;
 pub mod color_space;
+pub mod compat;
+pub mod compute;
+pub mod develop;
+pub mod draw;
+pub mod edit;
+pub mod error;
+pub mod generate;
+pub mod hash;
 pub mod image_buffer;
+pub mod image_buffer_mut;
 pub mod image;
+pub mod io;
+pub mod layers;
+pub mod lazy;
+pub mod limits;
+pub mod metrics;
+pub mod ops;
 pub mod pixel;
+pub mod progress;
+pub mod recipe;
+pub mod stack_image_buffer;
+pub mod stitch;
+pub mod video;
+pub mod watermark;
+pub mod yuv;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
+pub use channel_semantics::ChannelSemantics;
+pub use error::Error;
 pub use image_buffer::ImageBuffer;
+pub use image_buffer_mut::ImageBufferMut;
+pub use limits::Limits;
 pub use pixel::PixelContainer;
+pub use stack_image_buffer::StackImageBuffer;
 pub use image::ImageFactory;
 pub use image::Image;
@@ -0,0 +1,137 @@
+//! Caps on the resources a decode or allocation may use.
+//!
+//! Untrusted files can announce enormous dimensions in a few header bytes.
+//! Checking [`Limits`] before allocating turns such files into an
+//! [`Error::LimitExceeded`] instead of an out-of-memory abort.
+
+use crate::error::{Error, Result};
+
+/// Largest pixel allocation allowed by default, matching the `image` crate
+const DEFAULT_MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Resource limits enforced by the decoders and by
+/// [`ImageBuffer::try_empty`](crate::ImageBuffer::try_empty). `None` leaves
+/// a resource unlimited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+  pub max_width:  Option<usize>,
+  pub max_height: Option<usize>,
+  /// Largest pixel buffer, in bytes
+  pub max_bytes:  Option<u64>,
+  /// Most frames accepted from an animated file
+  pub max_frames: Option<usize>,
+}
+
+impl Default for Limits {
+  /// Caps allocations at 512 MiB and leaves everything else unlimited
+  fn default() -> Self {
+    Limits {
+      max_width:  None,
+      max_height: None,
+      max_bytes:  Some(DEFAULT_MAX_BYTES),
+      max_frames: None,
+    }
+  }
+}
+
+impl Limits {
+  /// Limits that accept anything
+  pub fn none() -> Self {
+    Limits {
+      max_width:  None,
+      max_height: None,
+      max_bytes:  None,
+      max_frames: None,
+    }
+  }
+
+  pub fn check_dimensions(&self, width: usize, height: usize) -> Result<()> {
+    if self.max_width.is_some_and(|max| width > max)
+      || self.max_height.is_some_and(|max| height > max)
+    {
+      return Err(Error::LimitExceeded(format!(
+        "Image is {width}x{height}, larger than the limit of {}x{}",
+        describe(self.max_width),
+        describe(self.max_height)
+      )));
+    }
+    Ok(())
+  }
+
+  pub fn check_bytes(&self, bytes: u64) -> Result<()> {
+    match self.max_bytes {
+      Some(max) if bytes > max =>
+        Err(Error::LimitExceeded(format!(
+          "Image needs {bytes} bytes, more than the limit of {max}"
+        ))),
+      _ => Ok(()),
+    }
+  }
+
+  pub fn check_frames(&self, frames: usize) -> Result<()> {
+    match self.max_frames {
+      Some(max) if frames > max =>
+        Err(Error::LimitExceeded(format!(
+          "Animation has {frames} frames, more than the limit of {max}"
+        ))),
+      _ => Ok(()),
+    }
+  }
+
+  /// Checks the dimensions of an image and the size of its buffer, at
+  /// `bytes_per_pixel` bytes per pixel. Returns the buffer size in bytes.
+  pub fn check_image(
+    &self,
+    width: usize,
+    height: usize,
+    bytes_per_pixel: usize,
+  ) -> Result<usize> {
+    self.check_dimensions(width, height)?;
+    let bytes = width
+      .checked_mul(height)
+      .and_then(|pixels| pixels.checked_mul(bytes_per_pixel))
+      .ok_or_else(|| {
+        Error::LimitExceeded(format!(
+          "A {width}x{height} image does not fit in memory"
+        ))
+      })?;
+    self.check_bytes(bytes as u64)?;
+    Ok(bytes)
+  }
+
+  /// The equivalent limits for the `image` crate's decoders
+  pub(crate) fn to_image_limits(self) -> image::Limits {
+    let clamp = |v: usize| u32::try_from(v).unwrap_or(u32::MAX);
+    let mut limits = image::Limits::no_limits();
+    limits.max_image_width = self.max_width.map(clamp);
+    limits.max_image_height = self.max_height.map(clamp);
+    limits.max_alloc = self.max_bytes;
+    limits
+  }
+}
+
+fn describe(max: Option<usize>) -> String {
+  max.map_or_else(|| "any".to_string(), |max| max.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn limits_reject_oversized_images() {
+    let limits = Limits {
+      max_width: Some(100),
+      max_frames: Some(2),
+      ..Default::default()
+    };
+    assert_eq!(limits.check_image(100, 50, 4).unwrap(), 20_000);
+    assert!(matches!(
+      limits.check_image(101, 1, 4),
+      Err(Error::LimitExceeded(_))
+    ));
+    assert!(Limits::default().check_image(100_000, 100_000, 3).is_err());
+    assert!(Limits::none().check_image(usize::MAX, 2, 1).is_err());
+    assert!(limits.check_frames(3).is_err());
+  }
+}
@@ -0,0 +1,5 @@
+//! Scalar measurements of image content, for deciding automatically what to
+//! keep. Diagnostic maps of the same properties are in
+//! [`ops::analysis`](crate::ops::analysis).
+
+pub mod quality;
@@ -0,0 +1,226 @@
+//! No-reference quality scores: sharpness, exposure and how natural the
+//! image statistics look.
+
+use crate::{
+  ops::{analysis::luminance_plane, blur::gaussian_blur},
+  pixel::PixelContainer,
+};
+
+/// Variance of the Laplacian of the luminance, with white at 1. Sharp
+/// images have strong second derivatives at their edges and score high;
+/// blurred ones score low. Scores depend on content, so thresholds should be
+/// chosen for a particular kind of footage.
+pub fn blur_score<C: PixelContainer>(image: &C) -> f64 {
+  let plane = luminance_plane(image);
+  let (width, height) = (plane.width, plane.height);
+  if width == 0 || height == 0 {
+    return 0.0;
+  }
+  let at = |x: isize, y: isize| {
+    let x = x.clamp(0, width as isize - 1) as usize;
+    let y = y.clamp(0, height as isize - 1) as usize;
+    plane.get_pixel(x, y)[0] as f64
+  };
+  let laplacian: Vec<f64> = (0..width * height)
+    .map(|i| {
+      let (x, y) = ((i % width) as isize, (i / width) as isize);
+      at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y)
+    })
+    .collect();
+  variance(&laplacian)
+}
+
+fn variance(values: &[f64]) -> f64 {
+  let n = values.len().max(1) as f64;
+  let mean = values.iter().sum::<f64>() / n;
+  values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n
+}
+
+/// Result of [`exposure`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exposure {
+  /// Percentage of pixels whose luminance is within the threshold of white
+  pub overexposed:  f64,
+  /// Percentage of pixels whose luminance is within the threshold of black
+  pub underexposed: f64,
+}
+
+/// Percentages of pixels of `image` that are blown out or crushed, counting
+/// luminance within `threshold` of white or black, with white at 1
+pub fn exposure<C: PixelContainer>(image: &C, threshold: f64) -> Exposure {
+  let plane = luminance_plane(image);
+  let n = (plane.width * plane.height).max(1) as f64;
+  let count = |f: &dyn Fn(f64) -> bool| {
+    plane.iter_pixels().filter(|p| f(p[0] as f64)).count() as f64
+  };
+  Exposure {
+    overexposed:  100.0 * count(&|l| l >= 1.0 - threshold) / n,
+    underexposed: 100.0 * count(&|l| l <= threshold) / n,
+  }
+}
+
+/// Shape of the distribution of normalized luminance in undistorted natural
+/// images, which is close to Gaussian
+pub const NATURAL_SHAPE: f64 = 2.0;
+
+/// Result of [`naturalness`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Naturalness {
+  /// Shape parameter of a generalized Gaussian fitted to the normalized
+  /// luminance: lower for blur and compression, which leave flat areas,
+  /// higher for clipping and posterization
+  pub shape:       f64,
+  /// Variance of the normalized luminance
+  pub variance:    f64,
+  /// Shape parameters fitted to products of horizontally, vertically and
+  /// diagonally (down-right, then down-left) neighboring values, which
+  /// capture directional distortions
+  pub pair_shapes: [f64; 4],
+  /// Estimated quality from 0 (natural) to 100 (heavily distorted)
+  pub score:       f64,
+}
+
+/// Natural logarithm of the gamma function, by the Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+  const G: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+  ];
+  let x = x - 1.0;
+  let t = x + 7.5;
+  let sum = G[0]
+    + G[1..]
+      .iter()
+      .enumerate()
+      .map(|(i, g)| g / (x + i as f64 + 1.0))
+      .sum::<f64>();
+  0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Shape parameter of the generalized Gaussian with the same ratio of mean
+/// square to squared mean magnitude as `values`, by moment matching
+fn ggd_shape(values: &[f64]) -> f64 {
+  let n = values.len().max(1) as f64;
+  let mean_abs = values.iter().map(|v| v.abs()).sum::<f64>() / n;
+  let mean_sq = values.iter().map(|v| v * v).sum::<f64>() / n;
+  if mean_abs <= 0.0 {
+    return 0.0;
+  }
+  let ratio = mean_sq / (mean_abs * mean_abs);
+  // The ratio falls monotonically with the shape
+  let ratio_of = |a: f64| {
+    (ln_gamma(1.0 / a) + ln_gamma(3.0 / a) - 2.0 * ln_gamma(2.0 / a)).exp()
+  };
+  let (mut low, mut high) = (0.1f64, 10.0f64);
+  for _ in 0..50 {
+    let mid = (low * high).sqrt();
+    if ratio_of(mid) > ratio {
+      low = mid;
+    } else {
+      high = mid;
+    }
+  }
+  (low * high).sqrt()
+}
+
+/// Estimates quality without a reference in the manner of BRISQUE: the
+/// luminance is normalized by its local mean and contrast, and the shapes of
+/// the resulting distributions are compared with those of natural images.
+///
+/// BRISQUE maps its features to a score with a model trained on human
+/// ratings; this instead scores how far [`Naturalness::shape`] is from
+/// [`NATURAL_SHAPE`], and reports the features for a trained model to use.
+pub fn naturalness<C: PixelContainer>(image: &C) -> Naturalness {
+  let plane = luminance_plane(image);
+  let (width, height) = (plane.width, plane.height);
+  let squares = plane.map(&mut |p| [p[0] * p[0]]);
+  let mean = gaussian_blur(&plane, 7.0 / 6.0);
+  let mean_sq = gaussian_blur(&squares, 7.0 / 6.0);
+  let mscn: Vec<f64> = plane
+    .components()
+    .iter()
+    .zip(mean.components().iter().zip(mean_sq.components()))
+    .map(|(&v, (&mu, &sq))| {
+      let sigma = (sq as f64 - (mu as f64).powi(2)).max(0.0).sqrt();
+      (v as f64 - mu as f64) / (sigma + 1.0 / 255.0)
+    })
+    .collect();
+  let pairs = |dx: isize, dy: isize| {
+    let mut products = Vec::new();
+    for y in 0..height as isize {
+      for x in 0..width as isize {
+        let (nx, ny) = (x + dx, y + dy);
+        if (0..width as isize).contains(&nx) && ny < height as isize {
+          products.push(
+            mscn[y as usize * width + x as usize]
+              * mscn[ny as usize * width + nx as usize],
+          );
+        }
+      }
+    }
+    ggd_shape(&products)
+  };
+  let shape = ggd_shape(&mscn);
+  let score = if shape > 0.0 {
+    100.0 * (1.0 - (-2.0 * (shape / NATURAL_SHAPE).ln().abs()).exp())
+  } else {
+    100.0
+  };
+  Naturalness {
+    shape,
+    variance: variance(&mscn),
+    pair_shapes: [pairs(1, 0), pairs(0, 1), pairs(1, 1), pairs(-1, 1)],
+    score,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::{rngs::StdRng, Rng, SeedableRng};
+  use rand_distr::StandardNormal;
+
+  use super::*;
+  use crate::ImageBuffer;
+
+  type Plane = ImageBuffer<f32, 1, false>;
+
+  fn checkerboard() -> Plane {
+    Plane::empty(32, 32)
+      .map_indexed(&mut |x, y, _| [((x / 4 + y / 4) % 2) as f32])
+  }
+
+  #[test]
+  fn sharp_images_score_higher() {
+    let sharp = checkerboard();
+    let soft = gaussian_blur(&sharp, 2.0);
+    assert!(blur_score(&sharp) > 10.0 * blur_score(&soft));
+
+    let exposure = exposure(&sharp, 0.02);
+    assert_eq!((exposure.overexposed, exposure.underexposed), (50.0, 50.0));
+  }
+
+  #[test]
+  fn distortion_raises_the_score() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let samples: Vec<f64> =
+      (0..10_000).map(|_| rng.sample(StandardNormal)).collect();
+    assert!((ggd_shape(&samples) - 2.0).abs() < 0.1);
+
+    let noise = Plane::empty(64, 64).map(&mut |_| {
+      let v: f64 = rng.sample(StandardNormal);
+      [(0.5 + 0.1 * v) as f32]
+    });
+    let natural = naturalness(&noise);
+    assert!(natural.pair_shapes.iter().all(|&s| s > 0.0));
+    // Posterizing leaves a few levels, far from Gaussian
+    let coarse = noise.map(&mut |p| [(p[0] * 2.0).round() / 2.0]);
+    assert!(naturalness(&coarse).score > natural.score);
+  }
+}
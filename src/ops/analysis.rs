@@ -0,0 +1,159 @@
+//! Diagnostic overlays of the kind shown over a camera preview.
+//!
+//! Each map is an 8-bit RGBA image the size of its source, meant to be
+//! composited over it: marked pixels are opaque and the rest are
+//! transparent, except for [`saturation_map`], which is opaque throughout.
+
+use crate::{
+  ops::{blur::gaussian_blur, meter::luminance},
+  pixel::{Pixel, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// An 8-bit RGBA overlay
+pub type Overlay = ImageBuffer<u8, 4, true>;
+
+/// Marks blown highlights
+pub const CLIPPED_HIGHLIGHT: [u8; 4] = [255, 0, 0, 255];
+/// Marks crushed shadows
+pub const CLIPPED_SHADOW: [u8; 4] = [0, 64, 255, 255];
+/// Marks in-focus edges
+pub const FOCUS_PEAK: [u8; 4] = [0, 255, 0, 255];
+
+fn unit<T: PixelComponent>(value: T) -> f64 {
+  value.to_f64().unwrap_or_default() / T::WHITE.to_f64().unwrap_or(1.0)
+}
+
+/// Relative luminance of every pixel of `image`
+pub(crate) fn luminance_plane<C: PixelContainer>(
+  image: &C,
+) -> ImageBuffer<f32, 1, false> {
+  let mut plane = ImageBuffer::empty(image.width(), image.height());
+  for (out, pel) in plane.iter_pixels_mut().zip(image.iter_pixels()) {
+    *out = [luminance::<C>(pel) as f32];
+  }
+  plane
+}
+
+/// Marks pixels with any color channel within `threshold` of white as blown
+/// highlights, and pixels with every color channel within `threshold` of
+/// black as crushed shadows. A `threshold` of zero marks only values that
+/// are exactly white or black.
+pub fn clipping_map<C: PixelContainer>(image: &C, threshold: f64) -> Overlay {
+  let mut map = Overlay::empty(image.width(), image.height());
+  for (out, pel) in map.iter_pixels_mut().zip(image.iter_pixels()) {
+    let colors = &pel.components()[..C::NUM_NONALPHA_COMPONENTS];
+    if colors.iter().any(|&c| unit(c) >= 1.0 - threshold) {
+      *out = CLIPPED_HIGHLIGHT;
+    } else if colors.iter().all(|&c| unit(c) <= threshold) {
+      *out = CLIPPED_SHADOW;
+    }
+  }
+  map
+}
+
+/// Color of `t`, between 0 and 1, on a heat scale running from black
+/// through red and yellow to white
+fn heat(t: f64) -> [u8; 4] {
+  let channel = |start: f64| ((t * 3.0 - start).clamp(0.0, 1.0) * 255.0) as u8;
+  [channel(0.0), channel(1.0), channel(2.0), 255]
+}
+
+/// Shows the saturation of each pixel, as in HSV, on a heat scale from black
+/// for grays to white for fully saturated colors. Images without three color
+/// channels are black.
+pub fn saturation_map<C: PixelContainer>(image: &C) -> Overlay {
+  let mut map = Overlay::empty(image.width(), image.height());
+  for (out, pel) in map.iter_pixels_mut().zip(image.iter_pixels()) {
+    let colors = &pel.components()[..C::NUM_NONALPHA_COMPONENTS];
+    let saturation = if colors.len() >= 3 {
+      let values = colors[..3].iter().map(|&c| unit(c));
+      let max = values.clone().fold(0.0, f64::max);
+      let min = values.fold(1.0, f64::min);
+      if max > 0.0 {
+        (max - min) / max
+      } else {
+        0.0
+      }
+    } else {
+      0.0
+    };
+    *out = heat(saturation);
+  }
+  map
+}
+
+/// Gradient magnitude of `plane` by the Sobel operator, repeating edge
+/// pixels beyond the border
+pub(crate) fn sobel(plane: &ImageBuffer<f32, 1, false>) -> Vec<f64> {
+  let (width, height) = (plane.width, plane.height);
+  let at = |x: isize, y: isize| {
+    let x = x.clamp(0, width as isize - 1) as usize;
+    let y = y.clamp(0, height as isize - 1) as usize;
+    plane.get_pixel(x, y)[0] as f64
+  };
+  (0..width * height)
+    .map(|i| {
+      let (x, y) = ((i % width) as isize, (i / width) as isize);
+      let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+        - at(x - 1, y - 1)
+        - 2.0 * at(x - 1, y)
+        - at(x - 1, y + 1);
+      let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+        - at(x - 1, y - 1)
+        - 2.0 * at(x, y - 1)
+        - at(x + 1, y - 1);
+      gx.hypot(gy)
+    })
+    .collect()
+}
+
+/// Marks the sharpest edges, where the luminance gradient is at least a
+/// quarter of its strongest in the frame, the way a camera shows what is in
+/// focus. The luminance is first blurred by `sigma` pixels so that noise
+/// and fine texture are not mistaken for edges.
+pub fn focus_peaking<C: PixelContainer>(image: &C, sigma: f64) -> Overlay {
+  let mut map = Overlay::empty(image.width(), image.height());
+  let plane = gaussian_blur(&luminance_plane(image), sigma);
+  let gradient = sobel(&plane);
+  let strongest = gradient.iter().copied().fold(0.0, f64::max);
+  if strongest <= f64::EPSILON {
+    return map;
+  }
+  for (out, g) in map.iter_pixels_mut().zip(gradient) {
+    if g >= strongest / 4.0 {
+      *out = FOCUS_PEAK;
+    }
+  }
+  map
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn maps_mark_clipping_saturation_and_edges() {
+    let mut image = ImageBuffer::<u8, 3, false>::with_val(&[128; 3], 8, 8);
+    *image.get_pixel_mut(0, 0) = [255, 100, 100];
+    *image.get_pixel_mut(1, 0) = [2, 1, 0];
+    *image.get_pixel_mut(2, 0) = [255, 0, 0];
+    let clipping = clipping_map(&image, 0.01);
+    assert_eq!(clipping.get_pixel(0, 0), &CLIPPED_HIGHLIGHT);
+    assert_eq!(clipping.get_pixel(1, 0), &CLIPPED_SHADOW);
+    assert_eq!(clipping.get_pixel(4, 4)[3], 0);
+
+    let saturation = saturation_map(&image);
+    assert_eq!(saturation.get_pixel(2, 0), &[255; 4]);
+    assert_eq!(saturation.get_pixel(4, 4), &[0, 0, 0, 255]);
+
+    let edge = ImageBuffer::<f32, 1, false>::empty(8, 8)
+      .map_indexed(&mut |x, _, _| [if x < 4 { 0.0 } else { 1.0 }]);
+    let peaks = focus_peaking(&edge, 0.5);
+    assert_eq!(peaks.get_pixel(4, 2), &FOCUS_PEAK);
+    assert_eq!(peaks.get_pixel(0, 2)[3], 0);
+    assert!(focus_peaking(&image.map(&mut |_| [9; 3]), 1.0)
+      .iter_pixels()
+      .all(|p| p[3] == 0));
+  }
+}
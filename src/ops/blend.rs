@@ -0,0 +1,245 @@
+//! Blending one RGBA layer over another with the blend modes of design
+//! tools, following the W3C Compositing and Blending specification.
+//!
+//! Modes work on the stored, gamma-encoded values, as Photoshop and CSS do.
+//! The blended color is then composited source-over with the layer's alpha.
+
+use crate::{
+  error::Result,
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  video::check_dimensions,
+  ImageBuffer,
+};
+
+/// How the colors of a layer combine with those beneath it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+  /// The layer's color, ignoring the backdrop
+  #[default]
+  Normal,
+  /// Product of the colors, which darkens
+  Multiply,
+  /// Inverse of the product of the inverses, which lightens
+  Screen,
+  /// Multiplies dark backdrops and screens light ones
+  Overlay,
+  /// The darker of each channel
+  Darken,
+  /// The lighter of each channel
+  Lighten,
+  /// Brightens the backdrop by dividing by the layer's inverse
+  ColorDodge,
+  /// Darkens the backdrop by dividing its inverse by the layer
+  ColorBurn,
+  /// Sum of the colors, also known as Add
+  LinearDodge,
+  /// Sum of the colors less white
+  LinearBurn,
+  /// The layer's hue with the backdrop's saturation and luminosity
+  Hue,
+  /// The layer's saturation with the backdrop's hue and luminosity
+  Saturation,
+  /// The layer's hue and saturation with the backdrop's luminosity
+  Color,
+  /// The layer's luminosity with the backdrop's hue and saturation
+  Luminosity,
+}
+
+impl BlendMode {
+  /// Blends a `source` color over a `backdrop` color, with components
+  /// between 0 and 1, before any compositing
+  pub fn blend(self, backdrop: [f64; 3], source: [f64; 3]) -> [f64; 3] {
+    let separable =
+      |f: fn(f64, f64) -> f64| [0, 1, 2].map(|k| f(backdrop[k], source[k]));
+    match self {
+      BlendMode::Normal => source,
+      BlendMode::Multiply => separable(|b, s| b * s),
+      BlendMode::Screen => separable(screen),
+      BlendMode::Overlay => separable(|b, s| hard_light(s, b)),
+      BlendMode::Darken => separable(f64::min),
+      BlendMode::Lighten => separable(f64::max),
+      BlendMode::ColorDodge =>
+        separable(|b, s| {
+          if b <= 0.0 {
+            0.0
+          } else if s >= 1.0 {
+            1.0
+          } else {
+            (b / (1.0 - s)).min(1.0)
+          }
+        }),
+      BlendMode::ColorBurn =>
+        separable(|b, s| {
+          if b >= 1.0 {
+            1.0
+          } else if s <= 0.0 {
+            0.0
+          } else {
+            1.0 - ((1.0 - b) / s).min(1.0)
+          }
+        }),
+      BlendMode::LinearDodge => separable(|b, s| (b + s).min(1.0)),
+      BlendMode::LinearBurn => separable(|b, s| (b + s - 1.0).max(0.0)),
+      BlendMode::Hue =>
+        with_luminosity(
+          with_saturation(source, saturation(backdrop)),
+          luminosity(backdrop),
+        ),
+      BlendMode::Saturation =>
+        with_luminosity(
+          with_saturation(backdrop, saturation(source)),
+          luminosity(backdrop),
+        ),
+      BlendMode::Color => with_luminosity(source, luminosity(backdrop)),
+      BlendMode::Luminosity => with_luminosity(backdrop, luminosity(source)),
+    }
+  }
+}
+
+fn screen(b: f64, s: f64) -> f64 { b + s - b * s }
+
+fn hard_light(b: f64, s: f64) -> f64 {
+  if s <= 0.5 {
+    b * 2.0 * s
+  } else {
+    screen(b, 2.0 * s - 1.0)
+  }
+}
+
+fn luminosity([r, g, b]: [f64; 3]) -> f64 { 0.3 * r + 0.59 * g + 0.11 * b }
+
+fn saturation(c: [f64; 3]) -> f64 {
+  c.iter().copied().fold(f64::MIN, f64::max)
+    - c.iter().copied().fold(f64::MAX, f64::min)
+}
+
+/// Shifts `c` to luminosity `l`, then pulls any channel outside 0 to 1
+/// back towards the gray of the same luminosity
+fn with_luminosity(c: [f64; 3], l: f64) -> [f64; 3] {
+  let d = l - luminosity(c);
+  let c = c.map(|v| v + d);
+  let l = luminosity(c);
+  let low = c.iter().copied().fold(f64::MAX, f64::min);
+  let high = c.iter().copied().fold(f64::MIN, f64::max);
+  c.map(|v| {
+    let mut v = v;
+    if low < 0.0 {
+      v = l + (v - l) * l / (l - low);
+    }
+    if high > 1.0 {
+      v = l + (v - l) * (1.0 - l) / (high - l);
+    }
+    v
+  })
+}
+
+/// Stretches `c` to saturation `s`, keeping the order of its channels
+fn with_saturation(c: [f64; 3], s: f64) -> [f64; 3] {
+  let mut order = [0, 1, 2];
+  order.sort_by(|&i, &j| c[i].total_cmp(&c[j]));
+  let [min, mid, max] = order;
+  let mut result = [0.0; 3];
+  if c[max] > c[min] {
+    result[mid] = (c[mid] - c[min]) * s / (c[max] - c[min]);
+    result[max] = s;
+  }
+  result
+}
+
+/// Blends `layer` over `backdrop` with `mode`, at `opacity` between 0 and 1
+/// on top of the layer's own alpha. Fails unless both are the same size.
+pub fn blend<T: PixelComponent>(
+  backdrop: &ImageBuffer<T, 4, true>,
+  layer: &ImageBuffer<T, 4, true>,
+  mode: BlendMode,
+  opacity: f64,
+) -> Result<ImageBuffer<T, 4, true>> {
+  check_dimensions((backdrop.width, backdrop.height), layer)?;
+  let (white, scale) = (backdrop.white(), layer.white());
+  let opacity = opacity.clamp(0.0, 1.0);
+  let mut result = backdrop.clone();
+  for (pel, over) in result
+    .components_mut()
+    .chunks_exact_mut(4)
+    .zip(layer.components().chunks_exact(4))
+  {
+    let b = [0, 1, 2, 3].map(|k| pel[k].to_f64().unwrap_or_default() / white);
+    let mut s =
+      [0, 1, 2, 3].map(|k| over[k].to_f64().unwrap_or_default() / scale);
+    s[3] *= opacity;
+    let out = composite(b, s, mode);
+    for (c, v) in pel.iter_mut().zip(out) {
+      *c = component_from_f64(v * white);
+    }
+  }
+  Ok(result)
+}
+
+/// Composites one straight-alpha RGBA `source` color over `backdrop` with
+/// `mode`, components between 0 and 1
+pub(crate) fn composite(
+  backdrop: [f64; 4],
+  source: [f64; 4],
+  mode: BlendMode,
+) -> [f64; 4] {
+  let (ab, a_s) = (backdrop[3], source[3]);
+  let alpha = a_s + ab * (1.0 - a_s);
+  if alpha <= 0.0 {
+    return backdrop;
+  }
+  let blended = mode.blend(
+    [backdrop[0], backdrop[1], backdrop[2]],
+    [source[0], source[1], source[2]],
+  );
+  let mut out = [0.0, 0.0, 0.0, alpha];
+  for k in 0..3 {
+    // Where the backdrop is transparent the layer shows unblended
+    let s = (1.0 - ab) * source[k] + ab * blended[k].clamp(0.0, 1.0);
+    out[k] = (a_s * s + ab * (1.0 - a_s) * backdrop[k]) / alpha;
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn blend_modes_match_design_tools() {
+    let (b, s) = ([0.5, 0.25, 1.0], [0.5, 1.0, 0.0]);
+    assert_eq!(BlendMode::Multiply.blend(b, s), [0.25, 0.25, 0.0]);
+    assert_eq!(BlendMode::Screen.blend(b, s), [0.75, 1.0, 1.0]);
+    assert_eq!(BlendMode::LinearBurn.blend(b, s), [0.0, 0.25, 0.0]);
+    assert_eq!(BlendMode::ColorDodge.blend(b, s), [1.0, 1.0, 1.0]);
+    assert_eq!(BlendMode::ColorBurn.blend(b, s), [0.0, 0.25, 1.0]);
+
+    // Non-separable modes trade hue and luminosity between the layers
+    let gray = [0.5; 3];
+    let red = [1.0, 0.0, 0.0];
+    let colored = BlendMode::Color.blend(gray, red);
+    assert!((luminosity(colored) - 0.5).abs() < 1e-9);
+    assert!(colored[0] == 1.0 && colored[1] == colored[2]);
+    let lit = BlendMode::Luminosity.blend(red, gray);
+    assert!((luminosity(lit) - 0.5).abs() < 1e-9);
+    assert_eq!(BlendMode::Saturation.blend(red, gray), [0.3; 3]);
+    let hued = BlendMode::Hue.blend(gray, red);
+    assert!(hued.iter().all(|v| (v - 0.5).abs() < 1e-9));
+
+    let backdrop =
+      ImageBuffer::<u8, 4, true>::with_val(&[200, 100, 50, 255], 2, 1);
+    let mut layer = ImageBuffer::<u8, 4, true>::empty(2, 1);
+    layer.components_mut()[4..].copy_from_slice(&[255, 255, 255, 255]);
+    let result = blend(&backdrop, &layer, BlendMode::Multiply, 0.5).unwrap();
+    assert_eq!(result.get_pixel(0, 0), &[200, 100, 50, 255]);
+    assert_eq!(result.get_pixel(1, 0), &[200, 100, 50, 255]);
+    let result = blend(&backdrop, &layer, BlendMode::Screen, 0.5).unwrap();
+    assert_eq!(result.get_pixel(1, 0), &[227, 178, 153, 255]);
+    assert!(blend(
+      &backdrop,
+      &ImageBuffer::empty(1, 1),
+      BlendMode::Normal,
+      1.0
+    )
+    .is_err());
+  }
+}
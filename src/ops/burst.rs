@@ -0,0 +1,286 @@
+//! Merging a handheld burst of frames into one clean frame, in the manner
+//! of HDR+ (Hasinoff et al.).
+//!
+//! Each frame is aligned with the first, the reference, tile by tile, and
+//! the aligned tiles are averaged with a robust weight that falls off where
+//! a tile differs from the reference by more than noise would explain. Tiles
+//! spoiled by motion or misalignment therefore contribute little, which
+//! avoids ghosting, while well-aligned tiles average away noise.
+
+use num_traits::ToPrimitive;
+
+use crate::{
+  error::{Error, Result},
+  ops::{meter::luminance, register::phase_correlation},
+  pixel::{component_from_f64, PixelContainer},
+  video::check_dimensions,
+};
+
+/// Settings for [`burst_merge`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BurstOptions {
+  /// Width and height of the tiles aligned and merged independently. Tiles
+  /// overlap by half and are blended with a raised cosine window.
+  pub tile_size:     usize,
+  /// How far, in pixels, each tile's alignment may move from the alignment
+  /// of the whole frame
+  pub search_radius: usize,
+  /// Standard deviation of the noise in each frame, in component values,
+  /// or `None` to estimate it from the reference
+  pub noise:         Option<f64>,
+  /// How many times the noise variance a tile may differ by and still be
+  /// weighted heavily. Higher values remove more noise and risk more
+  /// ghosting.
+  pub strength:      f64,
+}
+
+impl Default for BurstOptions {
+  fn default() -> Self {
+    BurstOptions {
+      tile_size:     16,
+      search_radius: 4,
+      noise:         None,
+      strength:      8.0,
+    }
+  }
+}
+
+/// Merges `frames`, a burst of the same scene, into one frame with less
+/// noise, aligned with the first.
+///
+/// Each frame is first aligned as a whole by phase correlation, then each
+/// tile by searching within [`search_radius`](BurstOptions::search_radius)
+/// for the offset that best matches the reference. Aligned tiles are
+/// averaged with the reference, each weighted by `c σ² / (c σ² + d)`, a
+/// Wiener-style shrinkage where `d` is the mean squared difference of the
+/// tile from the reference, `σ` the noise level and `c` the
+/// [`strength`](BurstOptions::strength). Fails for an empty burst or if
+/// the frames differ in size.
+pub fn burst_merge<C: PixelContainer + Clone>(
+  frames: &[C],
+  options: &BurstOptions,
+) -> Result<C> {
+  let reference = frames
+    .first()
+    .ok_or_else(|| Error::InvalidArgument("The burst is empty".to_string()))?;
+  let (width, height) = (reference.width(), reference.height());
+  for frame in frames {
+    check_dimensions((width, height), frame)?;
+  }
+  if frames.len() == 1 || width == 0 || height == 0 {
+    return Ok(reference.clone());
+  }
+  let channels = C::NUM_COMPONENTS;
+  let data: Vec<Vec<f64>> = frames
+    .iter()
+    .map(|f| {
+      f.components()
+        .iter()
+        .map(|c| c.to_f64().unwrap_or_default())
+        .collect()
+    })
+    .collect();
+  let lumas: Vec<Vec<f64>> = frames
+    .iter()
+    .map(|f| f.iter_pixels().map(luminance::<C>).collect())
+    .collect();
+  let global: Vec<(isize, isize)> = frames
+    .iter()
+    .map(|f| {
+      let (dx, dy) = phase_correlation(reference, f)?.transform.offset();
+      Ok((dx.round() as isize, dy.round() as isize))
+    })
+    .collect::<Result<_>>()?;
+  let noise = options
+    .noise
+    .unwrap_or_else(|| estimate_noise(&data[0], width, channels));
+  let threshold = (options.strength * noise * noise).max(f64::EPSILON);
+
+  let tile = options.tile_size.max(2);
+  let stride = tile / 2;
+  // Raised cosine weights; overlapping by half, they sum to one
+  let window: Vec<f64> = (0..tile)
+    .map(|i| {
+      (std::f64::consts::PI * (i as f64 + 0.5) / tile as f64)
+        .sin()
+        .powi(2)
+    })
+    .collect();
+  let clamp = |v: isize, len: usize| v.clamp(0, len as isize - 1) as usize;
+  let at = |x: isize, y: isize| clamp(y, height) * width + clamp(x, width);
+  let radius = options.search_radius as isize;
+
+  let mut sum = vec![0.0; width * height * channels];
+  let mut weight = vec![0.0; width * height];
+  let origins = |len: usize| (-(stride as isize)..len as isize).step_by(stride);
+  for oy in origins(height) {
+    for ox in origins(width) {
+      // Pixels of the tile inside the image, with their window weights
+      let pixels: Vec<(isize, isize, f64)> = (0..tile)
+        .flat_map(|ty| (0..tile).map(move |tx| (tx, ty)))
+        .map(|(tx, ty)| {
+          (ox + tx as isize, oy + ty as isize, window[tx] * window[ty])
+        })
+        .filter(|&(x, y, _)| {
+          x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height
+        })
+        .collect();
+      if pixels.is_empty() {
+        continue;
+      }
+      let reference_tile: Vec<f64> = pixels
+        .iter()
+        .flat_map(|&(x, y, _)| {
+          let i = at(x, y) * channels;
+          data[0][i..i + channels].iter().copied()
+        })
+        .collect();
+      let mut merged = reference_tile.clone();
+      let mut total = 1.0;
+      for k in 1..frames.len() {
+        let (gx, gy) = global[k];
+        let cost = |dx: isize, dy: isize| -> f64 {
+          pixels
+            .iter()
+            .map(|&(x, y, _)| {
+              (lumas[0][at(x, y)] - lumas[k][at(x + dx, y + dy)]).abs()
+            })
+            .sum()
+        };
+        let (_, (dx, dy)) = (-radius..=radius)
+          .flat_map(|sy| (-radius..=radius).map(move |sx| (gx + sx, gy + sy)))
+          .map(|(dx, dy)| (cost(dx, dy), (dx, dy)))
+          .min_by(|a, b| a.0.total_cmp(&b.0))
+          .unwrap_or((0.0, (gx, gy)));
+
+        let aligned: Vec<f64> = pixels
+          .iter()
+          .flat_map(|&(x, y, _)| {
+            let i = at(x + dx, y + dy) * channels;
+            data[k][i..i + channels].iter().copied()
+          })
+          .collect();
+        let distance = aligned
+          .iter()
+          .zip(&reference_tile)
+          .map(|(a, r)| (a - r).powi(2))
+          .sum::<f64>()
+          / aligned.len() as f64;
+        let w = threshold / (threshold + distance);
+        for (m, a) in merged.iter_mut().zip(&aligned) {
+          *m += w * a;
+        }
+        total += w;
+      }
+      for (j, &(x, y, win)) in pixels.iter().enumerate() {
+        let p = at(x, y);
+        for c in 0..channels {
+          sum[p * channels + c] += win * merged[j * channels + c] / total;
+        }
+        weight[p] += win;
+      }
+    }
+  }
+
+  let mut result = reference.clone();
+  for (i, value) in result.components_mut().iter_mut().enumerate() {
+    *value =
+      component_from_f64(sum[i] / weight[i / channels].max(f64::EPSILON));
+  }
+  Ok(result)
+}
+
+/// Standard deviation of the noise in interleaved `data`, from the median
+/// absolute difference between horizontally adjacent values, which mostly
+/// reflects noise in all but the busiest images
+fn estimate_noise(data: &[f64], width: usize, channels: usize) -> f64 {
+  let row = width * channels;
+  let mut diffs: Vec<f64> = data
+    .chunks_exact(row.max(1))
+    .flat_map(|r| {
+      r.iter()
+        .zip(&r[channels.min(r.len())..])
+        .map(|(a, b)| (a - b).abs())
+    })
+    .collect();
+  if diffs.is_empty() {
+    return 0.0;
+  }
+  let mid = diffs.len() / 2;
+  let (_, median, _) = diffs.select_nth_unstable_by(mid, f64::total_cmp);
+  // The difference of two samples has twice the variance of one, and the
+  // median absolute deviation is 0.6745 standard deviations
+  *median / 0.6745 / std::f64::consts::SQRT_2
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::{rngs::StdRng, Rng, SeedableRng};
+  use rand_distr::StandardNormal;
+
+  use super::*;
+  use crate::ImageBuffer;
+
+  #[test]
+  fn merging_a_shifted_burst_reduces_noise() {
+    let scene = |x: f64, y: f64| {
+      let blob = (-((x - 30.0).powi(2) + (y - 20.0).powi(2)) / 80.0).exp();
+      [
+        0.2 + 0.5 * blob + 0.004 * x,
+        0.3 + 0.3 * blob,
+        0.5 - 0.003 * y,
+      ]
+    };
+    let clean = ImageBuffer::<f32, 3, false>::empty(48, 40)
+      .map_indexed(&mut |x, y, _| scene(x as f64, y as f64).map(|v| v as f32));
+    let mut rng = StdRng::seed_from_u64(3);
+    let shifts = [(0, 0), (2, -1), (-1, 3), (3, 2), (-2, -2), (1, 1)];
+    let frames: Vec<_> = shifts
+      .iter()
+      .map(|&(sx, sy)| {
+        clean.map_indexed(&mut |x, y, _| {
+          let v = scene((x as isize + sx) as f64, (y as isize + sy) as f64);
+          v.map(|v| (v + 0.03 * rng.sample::<f64, _>(StandardNormal)) as f32)
+        })
+      })
+      .collect();
+    let error = |image: &ImageBuffer<f32, 3, false>| {
+      // Skip the border, where shifted frames have no data to contribute
+      let mut sum = 0.0;
+      for y in 4..36 {
+        for x in 4..44 {
+          for c in 0..3 {
+            let d = image.get_pixel(x, y)[c] - clean.get_pixel(x, y)[c];
+            sum += f64::from(d * d);
+          }
+        }
+      }
+      sum
+    };
+    let merged = burst_merge(&frames, &BurstOptions::default()).unwrap();
+    assert!(
+      error(&merged) < 0.4 * error(&frames[0]),
+      "{} {}",
+      error(&merged),
+      error(&frames[0])
+    );
+    assert!(
+      (estimate_noise(
+        &frames[0]
+          .components()
+          .iter()
+          .map(|&v| f64::from(v))
+          .collect::<Vec<_>>(),
+        48,
+        3
+      ) - 0.03)
+        .abs()
+        < 0.01
+    );
+    assert!(burst_merge::<ImageBuffer<f32, 3, false>>(
+      &[],
+      &BurstOptions::default()
+    )
+    .is_err());
+  }
+}
@@ -0,0 +1,141 @@
+//! Reinhard et al.'s color transfer: giving one image the overall palette of
+//! another by matching channel statistics in the decorrelated lαβ space.
+
+use num_traits::ToPrimitive;
+
+use crate::pixel::{component_from_f64, PixelComponent, PixelContainer};
+
+/// Floor applied before taking logarithms, so black maps to a finite value
+const LOG_FLOOR: f64 = 1e-4;
+
+const RGB_TO_LMS: [[f64; 3]; 3] = [
+  [0.3811, 0.5783, 0.0402],
+  [0.1967, 0.7244, 0.0782],
+  [0.0241, 0.1288, 0.8444],
+];
+
+const LMS_TO_RGB: [[f64; 3]; 3] = [
+  [4.4679, -3.5873, 0.1193],
+  [-1.2186, 2.3809, -0.1624],
+  [0.0497, -0.2439, 1.2045],
+];
+
+fn multiply(m: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+  m.map(|row| row.iter().zip(v).map(|(a, b)| a * b).sum())
+}
+
+fn rgb_to_lab(rgb: [f64; 3]) -> [f64; 3] {
+  let [l, m, s] = multiply(&RGB_TO_LMS, rgb).map(|v| v.max(LOG_FLOOR).log10());
+  [
+    (l + m + s) / 3f64.sqrt(),
+    (l + m - 2.0 * s) / 6f64.sqrt(),
+    (l - m) / 2f64.sqrt(),
+  ]
+}
+
+fn lab_to_rgb([l, a, b]: [f64; 3]) -> [f64; 3] {
+  let (l, a, b) = (l / 3f64.sqrt(), a / 6f64.sqrt(), b / 2f64.sqrt());
+  let lms = [l + a + b, l + a - b, l - 2.0 * a].map(|v| 10f64.powf(v));
+  multiply(&LMS_TO_RGB, lms)
+}
+
+/// The color channels of every pixel, between 0 and 1, in the space the
+/// statistics are matched in
+fn samples<C: PixelContainer>(image: &C) -> Vec<[f64; 3]> {
+  let white = C::Component::WHITE.to_f64().unwrap_or(1.0);
+  let unit = |v: C::Component| v.to_f64().unwrap_or_default() / white;
+  image
+    .components()
+    .chunks_exact(C::NUM_COMPONENTS)
+    .map(|pel| {
+      if C::NUM_NONALPHA_COMPONENTS >= 3 {
+        rgb_to_lab([unit(pel[0]), unit(pel[1]), unit(pel[2])])
+      } else {
+        [unit(pel[0]), 0.0, 0.0]
+      }
+    })
+    .collect()
+}
+
+/// Mean and standard deviation of each channel
+fn statistics(samples: &[[f64; 3]]) -> [(f64, f64); 3] {
+  let n = samples.len().max(1) as f64;
+  [0, 1, 2].map(|c| {
+    let mean = samples.iter().map(|s| s[c]).sum::<f64>() / n;
+    let variance =
+      samples.iter().map(|s| (s[c] - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+  })
+}
+
+/// Recolors `image` so that its palette and mood match `reference`.
+///
+/// Each lαβ channel of `image` is shifted and scaled to the mean and
+/// standard deviation of the same channel in `reference`. Images with fewer
+/// than three color channels have their brightness distribution matched
+/// instead. Alpha is passed through.
+pub fn color_transfer<C, R>(image: &C, reference: &R) -> C
+where
+  C: PixelContainer + Clone,
+  R: PixelContainer,
+{
+  let source = samples(image);
+  let from = statistics(&source);
+  let to = statistics(&samples(reference));
+  let white = C::Component::WHITE.to_f64().unwrap_or(1.0);
+
+  let mut result = image.clone();
+  for (pel, lab) in result
+    .components_mut()
+    .chunks_exact_mut(C::NUM_COMPONENTS)
+    .zip(source)
+  {
+    let matched: [f64; 3] = [0, 1, 2].map(|c| {
+      let ((mean, std), (ref_mean, ref_std)) = (from[c], to[c]);
+      let gain = if std > f64::EPSILON {
+        ref_std / std
+      } else {
+        1.0
+      };
+      (lab[c] - mean) * gain + ref_mean
+    });
+    if C::NUM_NONALPHA_COMPONENTS >= 3 {
+      for (out, v) in pel.iter_mut().zip(lab_to_rgb(matched)) {
+        *out = component_from_f64(v.clamp(0.0, 1.0) * white);
+      }
+    } else {
+      pel[0] = component_from_f64(matched[0].clamp(0.0, 1.0) * white);
+    }
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ImageBuffer;
+
+  fn means(image: &ImageBuffer<u8, 3, false>) -> [f64; 3] {
+    let n = (image.width * image.height) as f64;
+    [0, 1, 2]
+      .map(|c| image.iter_pixels().map(|p| f64::from(p[c])).sum::<f64>() / n)
+  }
+
+  #[test]
+  fn color_transfer_adopts_reference_palette() {
+    let cool = ImageBuffer::<u8, 3, false>::empty(16, 16)
+      .map_indexed(&mut |x, y, _| [20 + x as u8 * 2, 40 + y as u8 * 2, 150]);
+    let warm = ImageBuffer::<u8, 3, false>::empty(8, 8)
+      .map_indexed(&mut |x, y, _| [200, 120 + x as u8 * 4, 60 + y as u8 * 3]);
+
+    let graded = color_transfer(&cool, &warm);
+    for (got, want) in means(&graded).iter().zip(means(&warm)) {
+      assert!((got - want).abs() < 12.0, "{:?}", means(&graded));
+    }
+
+    let unchanged = color_transfer(&cool, &cool);
+    for (a, b) in unchanged.components().iter().zip(cool.components()) {
+      assert!(a.abs_diff(*b) <= 1);
+    }
+  }
+}
@@ -0,0 +1,289 @@
+//! False color for single-channel data such as depth, disparity, heat or
+//! detection scores, with the colormaps of scientific plotting.
+//!
+//! [`ColorMap::Viridis`] is perceptually uniform and reads in grayscale and
+//! to color-blind viewers, so it is the usual choice. [`ColorMap::Turbo`]
+//! shows more detail at a glance, and [`ColorMap::Jet`] is there for
+//! matching older tools. A [`legend`] shows which value each color stands
+//! for.
+
+use crate::{
+  draw::font::{draw_text, text_width, GLYPH_HEIGHT},
+  generate::gradient::Gradient,
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// Viridis, as in matplotlib, sampled at ten evenly spaced points
+const VIRIDIS: [[u8; 3]; 10] = [
+  [0x44, 0x01, 0x54],
+  [0x48, 0x28, 0x78],
+  [0x3e, 0x49, 0x89],
+  [0x31, 0x68, 0x8e],
+  [0x26, 0x82, 0x8e],
+  [0x1f, 0x9e, 0x89],
+  [0x35, 0xb7, 0x79],
+  [0x6e, 0xce, 0x58],
+  [0xb5, 0xde, 0x2b],
+  [0xfd, 0xe7, 0x25],
+];
+
+/// Coefficients of the polynomial approximation of Turbo published with it,
+/// from the constant term up, for red, green and blue
+const TURBO: [[f64; 6]; 3] = [
+  [
+    0.135_721_38,
+    4.615_392_60,
+    -42.660_322_58,
+    132.131_082_34,
+    -152.942_393_96,
+    59.286_379_43,
+  ],
+  [
+    0.091_402_61,
+    2.194_188_39,
+    4.842_966_58,
+    -14.185_033_33,
+    4.277_298_57,
+    2.829_566_04,
+  ],
+  [
+    0.106_673_30,
+    12.641_946_08,
+    -60.582_048_36,
+    110.362_767_71,
+    -89.903_109_12,
+    27.348_249_73,
+  ],
+];
+
+/// Width of the color bar of a [`legend`], in pixels
+const BAR_WIDTH: usize = 8;
+
+/// Colors for values from low to high
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ColorMap {
+  /// Dark purple through teal to yellow
+  #[default]
+  Viridis,
+  /// Google's improved rainbow, dark blue through green to dark red
+  Turbo,
+  /// MATLAB's former default rainbow, dark blue through green to dark red
+  Jet,
+  /// Black to white
+  Gray,
+  /// Any gradient, from its first stop for the lowest values to its last
+  /// for the highest. Its shape is ignored.
+  Gradient(Gradient),
+}
+
+impl ColorMap {
+  /// The color for `t` between 0 and 1, as RGB components between 0 and 1
+  pub fn color(&self, t: f64) -> [f64; 3] {
+    let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+    match self {
+      ColorMap::Viridis => {
+        let at = t * (VIRIDIS.len() - 1) as f64;
+        let i = (at as usize).min(VIRIDIS.len() - 2);
+        let f = at - i as f64;
+        [0, 1, 2].map(|k| {
+          let (a, b) = (f64::from(VIRIDIS[i][k]), f64::from(VIRIDIS[i + 1][k]));
+          (a + (b - a) * f) / 255.0
+        })
+      }
+      ColorMap::Turbo =>
+        TURBO.map(|c| {
+          c.iter()
+            .rev()
+            .fold(0.0, |sum, a| sum * t + a)
+            .clamp(0.0, 1.0)
+        }),
+      ColorMap::Jet =>
+        [3.0, 2.0, 1.0]
+          .map(|center| (1.5 - (4.0 * t - center).abs()).clamp(0.0, 1.0)),
+      ColorMap::Gray => [t; 3],
+      ColorMap::Gradient(gradient) => {
+        let (first, last) = gradient
+          .stops()
+          .first()
+          .zip(gradient.stops().last())
+          .map_or((0.0, 1.0), |(first, last)| (first.0, last.0));
+        let color = gradient.color_at(first + (last - first) * t);
+        [color.r, color.g, color.b].map(f64::from)
+      }
+    }
+  }
+}
+
+/// The lowest and highest finite values of `gray`, if any
+pub fn value_range<T: PixelComponent>(
+  gray: &ImageBuffer<T, 1, false>,
+) -> Option<(f64, f64)> {
+  gray
+    .components()
+    .iter()
+    .filter_map(|v| v.to_f64().filter(|v| v.is_finite()))
+    .fold(None, |range, v| {
+      Some(range.map_or((v, v), |(lo, hi): (f64, f64)| (lo.min(v), hi.max(v))))
+    })
+}
+
+/// Colors `gray` with `map`, stretched over the range of its values. Values
+/// that are not finite, such as the holes of a depth map, come out black.
+pub fn colormap<T: PixelComponent>(
+  gray: &ImageBuffer<T, 1, false>,
+  map: &ColorMap,
+) -> ImageBuffer<T, 3, false> {
+  let range = value_range(gray).unwrap_or((0.0, 1.0));
+  colormap_range(gray, map, range)
+}
+
+/// Colors `gray` with `map`, with the values of `range` taking its first
+/// and last colors. Values outside it take the nearer one.
+pub fn colormap_range<T: PixelComponent>(
+  gray: &ImageBuffer<T, 1, false>,
+  map: &ColorMap,
+  range: (f64, f64),
+) -> ImageBuffer<T, 3, false> {
+  let (lo, hi) = range;
+  let white = gray.white();
+  let mut rgb = ImageBuffer::empty(gray.width, gray.height);
+  for (pel, v) in rgb
+    .components_mut()
+    .chunks_exact_mut(3)
+    .zip(gray.components())
+  {
+    let Some(v) = v.to_f64().filter(|v| v.is_finite()) else {
+      continue;
+    };
+    let t = if hi > lo { (v - lo) / (hi - lo) } else { 0.0 };
+    for (c, value) in pel.iter_mut().zip(map.color(t)) {
+      *c = component_from_f64(value * white);
+    }
+  }
+  rgb
+}
+
+/// Labels for the legend, at most about four significant digits
+fn label(value: f64) -> String {
+  let magnitude = value.abs();
+  if magnitude != 0.0 && !(0.01..10_000.0).contains(&magnitude) {
+    format!("{value:.1e}")
+  } else {
+    let text = format!("{value:.2}");
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+  }
+}
+
+/// A color bar `height` pixels tall, for showing beside an image colored
+/// with `map` over `range`. The highest value is at the top, and the ends
+/// of the range, and its middle where there is room, are labeled in white
+/// on black.
+pub fn legend<T: PixelComponent>(
+  map: &ColorMap,
+  range: (f64, f64),
+  height: usize,
+) -> ImageBuffer<T, 3, false> {
+  let (lo, hi) = range;
+  let height = height.max(7);
+  let mut labels = vec![(1, label(hi)), (height - GLYPH_HEIGHT - 1, label(lo))];
+  if height >= 40 {
+    labels.push((height / 2 - 3, label((lo + hi) / 2.0)));
+  }
+  let text = labels.iter().map(|(_, text)| text_width(text, 1)).max();
+  let width = BAR_WIDTH + 3 + text.unwrap_or(0) + 1;
+  let mut legend = ImageBuffer::<T, 3, false>::empty(width, height);
+  let white = legend.white();
+  for y in 0..height {
+    let t = 1.0 - y as f64 / (height - 1) as f64;
+    let color = map.color(t).map(|v| component_from_f64(v * white));
+    for x in 0..BAR_WIDTH {
+      *legend.get_pixel_mut(x, y) = color;
+    }
+  }
+  let ink = [component_from_f64(white); 3];
+  for (top, text) in labels {
+    // A tick from the bar to the label
+    *legend.get_pixel_mut(BAR_WIDTH, top + 2) = ink;
+    draw_text(
+      &mut legend,
+      ((BAR_WIDTH + 2) as isize, top as isize),
+      &text,
+      ink,
+      1,
+    );
+  }
+  legend
+}
+
+/// `image` with a [`legend`] for `map` over `range` to its right, separated
+/// by a few black pixels
+pub fn with_legend<T: PixelComponent>(
+  image: &ImageBuffer<T, 3, false>,
+  map: &ColorMap,
+  range: (f64, f64),
+) -> ImageBuffer<T, 3, false> {
+  let legend = legend::<T>(map, range, image.height);
+  let left = image.width + 4;
+  let mut out = ImageBuffer::empty(left + legend.width, legend.height);
+  for y in 0..image.height {
+    for x in 0..image.width {
+      *out.get_pixel_mut(x, y) = *image.get_pixel(x, y);
+    }
+  }
+  for y in 0..legend.height {
+    for x in 0..legend.width {
+      *out.get_pixel_mut(left + x, y) = *legend.get_pixel(x, y);
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn colormaps_match_their_references() {
+    let to_u8 = |c: [f64; 3]| c.map(|v| (v * 255.0).round() as u8);
+    assert_eq!(to_u8(ColorMap::Viridis.color(0.0)), [0x44, 0x01, 0x54]);
+    assert_eq!(to_u8(ColorMap::Viridis.color(1.0)), [0xfd, 0xe7, 0x25]);
+    assert_eq!(ColorMap::Jet.color(0.5), [0.5, 1.0, 0.5]);
+    assert_eq!(to_u8(ColorMap::Turbo.color(0.0)), [35, 23, 27]);
+    let ramp = Gradient::linear((0.0, 0.0), (1.0, 0.0))
+      .stop(0.2, [0u8, 0, 0, 255])
+      .stop(0.4, [255u8, 0, 0, 255]);
+    assert_eq!(ColorMap::Gradient(ramp).color(0.5), [0.5, 0.0, 0.0]);
+
+    // Depths stretched over their range, with a hole
+    let depth = ImageBuffer::<f32, 1, false>::try_with_data(
+      vec![2.0, 4.0, 3.0, f32::NAN],
+      4,
+      1,
+    )
+    .unwrap();
+    assert_eq!(value_range(&depth), Some((2.0, 4.0)));
+    let rgb = colormap(&depth, &ColorMap::Gray);
+    assert_eq!(
+      rgb.components(),
+      &[0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.5, 0.5, 0.5, 0.0, 0.0, 0.0]
+    );
+
+    assert_eq!(label(1234.5678), "1234.57");
+    assert_eq!(label(0.5), "0.5");
+    assert_eq!(label(250_000.0), "2.5e5");
+    let gray = ImageBuffer::<u8, 1, false>::with_val(&[9], 10, 50);
+    let shown = with_legend(
+      &colormap(&gray, &ColorMap::Viridis),
+      &ColorMap::Viridis,
+      (0.0, 255.0),
+    );
+    assert_eq!(
+      (shown.width, shown.height),
+      (10 + 4 + BAR_WIDTH + 3 + 20, 50)
+    );
+    assert_eq!(shown.get_pixel(14, 0), &[0xfd, 0xe7, 0x25]);
+    assert_eq!(shown.get_pixel(14, 49), &[0x44, 0x01, 0x54]);
+    assert!(shown.components().contains(&255));
+  }
+}
@@ -0,0 +1,62 @@
+//! Full-reference comparison of two images of the same size.
+
+use num_traits::ToPrimitive;
+
+use crate::{
+  error::{Error, Result},
+  pixel::{PixelComponent, PixelContainer},
+  video::check_dimensions,
+};
+
+/// Mean squared difference between the components of `a` and `b`, with
+/// white at 1, so that images of different component types can be compared.
+/// Alpha is compared like the other channels.
+///
+/// Fails if the images differ in size or number of channels.
+pub fn mse<A: PixelContainer, B: PixelContainer>(a: &A, b: &B) -> Result<f64> {
+  check_dimensions((a.width(), a.height()), b)?;
+  if A::NUM_COMPONENTS != B::NUM_COMPONENTS {
+    return Err(Error::Channel(format!(
+      "Cannot compare {} channels with {}",
+      A::NUM_COMPONENTS,
+      B::NUM_COMPONENTS
+    )));
+  }
+  let white_a = A::Component::WHITE.to_f64().unwrap_or(1.0);
+  let white_b = B::Component::WHITE.to_f64().unwrap_or(1.0);
+  let total: f64 = a
+    .components()
+    .iter()
+    .zip(b.components())
+    .map(|(x, y)| {
+      let x = x.to_f64().unwrap_or_default() / white_a;
+      let y = y.to_f64().unwrap_or_default() / white_b;
+      (x - y).powi(2)
+    })
+    .sum();
+  Ok(total / a.components().len().max(1) as f64)
+}
+
+/// Peak signal-to-noise ratio of `b` against `a`, in decibels. Identical
+/// images give infinity.
+pub fn psnr<A: PixelContainer, B: PixelContainer>(a: &A, b: &B) -> Result<f64> {
+  Ok(-10.0 * mse(a, b)?.log10())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ImageBuffer;
+
+  #[test]
+  fn compare_across_component_types() {
+    let a = ImageBuffer::<u8, 3, false>::with_val(&[255, 0, 51], 2, 2);
+    let b = ImageBuffer::<f32, 3, false>::with_val(&[1.0, 0.0, 0.2], 2, 2);
+    assert!(mse(&a, &b).unwrap() < 1e-12);
+    assert_eq!(psnr(&a, &a).unwrap(), f64::INFINITY);
+
+    let c = ImageBuffer::<f32, 3, false>::with_val(&[0.9, 0.1, 0.2], 2, 2);
+    assert!((psnr(&b, &c).unwrap() - 21.76).abs() < 0.01);
+    assert!(mse(&a, &ImageBuffer::<u8, 4, true>::empty(2, 2)).is_err());
+  }
+}
@@ -0,0 +1,315 @@
+//! Tracing the outlines of shapes in binary masks as point lists, and
+//! measuring and simplifying them.
+//!
+//! Points are `(x, y)` pixel coordinates, and outlines pass through the
+//! centers of the shapes' edge pixels. Masks follow the conventions of
+//! [`mask`](super::mask), with shapes 8-connected and holes 4-connected.
+
+use super::mask::{selected, Mask};
+
+/// A pixel position, `(x, y)`
+pub type Point = (usize, usize);
+
+/// The outline of a shape or of a hole in one, from [`find_contours`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Contour {
+  /// The edge pixels in order around the outline, counter-clockwise on
+  /// screen for outer borders and clockwise for holes
+  pub points: Vec<Point>,
+  /// Whether this is the border of a hole rather than of a shape
+  pub hole:   bool,
+  /// Index of the contour directly enclosing this one: the shape around a
+  /// hole, or the hole around a shape. `None` for outermost shapes.
+  pub parent: Option<usize>,
+}
+
+/// The eight neighbors of a pixel as `(row, column)` steps, clockwise on
+/// screen from east
+const NEIGHBORS: [(isize, isize); 8] = [
+  (0, 1),
+  (1, 1),
+  (1, 0),
+  (1, -1),
+  (0, -1),
+  (-1, -1),
+  (-1, 0),
+  (-1, 1),
+];
+
+/// Traces the borders of every shape and hole in `mask` by Suzuki and
+/// Abe's border following, recording how they nest.
+///
+/// Contours are listed in the order their first pixel is met scanning the
+/// mask row by row, so every contour comes after its parent.
+pub fn find_contours(mask: &Mask) -> Vec<Contour> {
+  let (width, height) = (mask.width, mask.height);
+  // Labels on a copy padded with a frame of unselected pixels: 0 outside
+  // shapes, 1 inside, and ±(border number) on traced borders
+  let (w, h) = (width + 2, height + 2);
+  let mut f = vec![0i64; w * h];
+  for (i, &on) in selected(mask).iter().enumerate() {
+    f[(i / width + 1) * w + i % width + 1] = i64::from(on);
+  }
+  let at = |r: usize, c: usize| r * w + c;
+  let step = |(r, c): (usize, usize), k: usize| {
+    let (dr, dc) = NEIGHBORS[k % 8];
+    ((r as isize + dr) as usize, (c as isize + dc) as usize)
+  };
+  let direction = |from: (usize, usize), to: (usize, usize)| {
+    (0..8).find(|&k| step(from, k) == to).unwrap_or(0)
+  };
+
+  let mut contours: Vec<Contour> = Vec::new();
+  // Border number 1 is the frame, which acts as the hole everything is in
+  let mut hole_of_border = vec![true, true];
+  for r in 1..h - 1 {
+    let mut last_border = 1;
+    for c in 1..w - 1 {
+      let value = f[at(r, c)];
+      let start = if value == 1 && f[at(r, c - 1)] == 0 {
+        Some((false, (r, c - 1)))
+      } else if value >= 1 && f[at(r, c + 1)] == 0 {
+        if value > 1 {
+          last_border = value;
+        }
+        Some((true, (r, c + 1)))
+      } else {
+        None
+      };
+
+      if let Some((hole, from)) = start {
+        let border = hole_of_border.len() as i64;
+        hole_of_border.push(hole);
+        // The border last crossed on this row is either this one's parent
+        // or a sibling sharing its parent
+        let previous = last_border as usize;
+        let enclosing = if hole == hole_of_border[previous] {
+          contours
+            .get(previous.wrapping_sub(2))
+            .and_then(|p| p.parent)
+        } else {
+          (previous >= 2).then(|| previous - 2)
+        };
+        let points = follow(&mut f, w, (r, c), from, border, &step, &direction);
+        contours.push(Contour {
+          points: points.into_iter().map(|(r, c)| (c - 1, r - 1)).collect(),
+          hole,
+          parent: enclosing,
+        });
+      }
+
+      let value = f[at(r, c)];
+      if value != 1 && value != 0 {
+        last_border = value.abs();
+      }
+    }
+  }
+  contours
+}
+
+/// Follows one border from `start`, whose traced neighbor `from` is
+/// unselected, marking it with `border` in `f`, and returns its pixels
+fn follow(
+  f: &mut [i64],
+  w: usize,
+  start: (usize, usize),
+  from: (usize, usize),
+  border: i64,
+  step: &impl Fn((usize, usize), usize) -> (usize, usize),
+  direction: &impl Fn((usize, usize), (usize, usize)) -> usize,
+) -> Vec<(usize, usize)> {
+  let at = |(r, c): (usize, usize)| r * w + c;
+  // Look clockwise from `from` for the first selected neighbor
+  let first = direction(start, from);
+  let Some(k) = (0..8)
+    .map(|k| (first + k) % 8)
+    .find(|&k| f[at(step(start, k))] != 0)
+  else {
+    f[at(start)] = -border;
+    return vec![start];
+  };
+  let second = step(start, k);
+  let (mut previous, mut current) = (second, start);
+  let mut points = Vec::new();
+  loop {
+    points.push(current);
+    // Look counter-clockwise from just past `previous`
+    let back = direction(current, previous);
+    let mut east_is_clear = false;
+    let mut next = current;
+    for k in 1..=8 {
+      let d = (back + 8 - k) % 8;
+      let candidate = step(current, d);
+      if f[at(candidate)] != 0 {
+        next = candidate;
+        break;
+      }
+      if d == 0 {
+        east_is_clear = true;
+      }
+    }
+    if east_is_clear {
+      f[at(current)] = -border;
+    } else if f[at(current)] == 1 {
+      f[at(current)] = border;
+    }
+    if next == start && current == second {
+      break;
+    }
+    (previous, current) = (current, next);
+  }
+  points
+}
+
+/// Simplifies a closed contour with the Ramer-Douglas-Peucker algorithm,
+/// keeping only the points needed for every dropped point to lie within
+/// `epsilon` pixels of the result
+pub fn approx_poly_dp(contour: &[Point], epsilon: f64) -> Vec<Point> {
+  if contour.len() < 3 {
+    return contour.to_vec();
+  }
+  // Split the loop at the point farthest from the first, and simplify
+  // each half as an open curve
+  let far = (1..contour.len())
+    .max_by(|&a, &b| {
+      squared_distance(contour[0], contour[a])
+        .total_cmp(&squared_distance(contour[0], contour[b]))
+    })
+    .unwrap_or(1);
+  let mut first_half = Vec::new();
+  simplify(&contour[..=far], epsilon, &mut first_half);
+  let mut second_half = Vec::new();
+  let rest: Vec<Point> = contour[far..]
+    .iter()
+    .chain(&contour[..1])
+    .copied()
+    .collect();
+  simplify(&rest, epsilon, &mut second_half);
+  first_half.pop();
+  second_half.pop();
+  first_half.extend(second_half);
+  first_half
+}
+
+fn squared_distance(a: Point, b: Point) -> f64 {
+  let (dx, dy) = (a.0 as f64 - b.0 as f64, a.1 as f64 - b.1 as f64);
+  dx * dx + dy * dy
+}
+
+/// Appends the simplification of the open curve `points` to `out`,
+/// including both ends
+fn simplify(points: &[Point], epsilon: f64, out: &mut Vec<Point>) {
+  let (first, last) = (points[0], points[points.len() - 1]);
+  let (dx, dy) = (
+    last.0 as f64 - first.0 as f64,
+    last.1 as f64 - first.1 as f64,
+  );
+  let length = dx.hypot(dy);
+  let distance = |p: Point| {
+    let (px, py) = (p.0 as f64 - first.0 as f64, p.1 as f64 - first.1 as f64);
+    if length == 0.0 {
+      px.hypot(py)
+    } else {
+      (px * dy - py * dx).abs() / length
+    }
+  };
+  let farthest = (1..points.len().saturating_sub(1))
+    .max_by(|&a, &b| distance(points[a]).total_cmp(&distance(points[b])));
+  match farthest {
+    Some(k) if distance(points[k]) > epsilon => {
+      simplify(&points[..=k], epsilon, out);
+      out.pop();
+      simplify(&points[k..], epsilon, out);
+    }
+    _ => out.extend([first, last]),
+  }
+}
+
+/// Area enclosed by a closed contour, by the shoelace formula. Since the
+/// outline runs through pixel centers, this is less than the shape's pixel
+/// count by about half its perimeter.
+pub fn contour_area(contour: &[Point]) -> f64 {
+  let doubled: f64 = (0..contour.len())
+    .map(|i| {
+      let (p, q) = (contour[i], contour[(i + 1) % contour.len()]);
+      p.0 as f64 * q.1 as f64 - q.0 as f64 * p.1 as f64
+    })
+    .sum();
+  doubled.abs() / 2.0
+}
+
+/// The smallest convex polygon containing `points`, clockwise on screen
+/// without collinear points, by Andrew's monotone chain
+pub fn convex_hull(points: &[Point]) -> Vec<Point> {
+  let mut sorted = points.to_vec();
+  sorted.sort_unstable();
+  sorted.dedup();
+  if sorted.len() < 3 {
+    return sorted;
+  }
+  let cross = |o: Point, a: Point, b: Point| {
+    (a.0 as i64 - o.0 as i64) * (b.1 as i64 - o.1 as i64)
+      - (a.1 as i64 - o.1 as i64) * (b.0 as i64 - o.0 as i64)
+  };
+  let mut hull: Vec<Point> = Vec::with_capacity(2 * sorted.len());
+  for pass in [sorted.clone(), sorted.into_iter().rev().collect()] {
+    let start = hull.len();
+    for p in pass {
+      while hull.len() >= start + 2
+        && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0
+      {
+        hull.pop();
+      }
+      hull.push(p);
+    }
+    hull.pop();
+  }
+  hull
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::pixel::PixelContainer;
+
+  #[test]
+  fn contours_nest_and_simplify() {
+    // A frame with a blob in its hole, and a separate square
+    let mask = Mask::empty(24, 16).map_indexed(&mut |x, y, _| {
+      let frame = (2..14).contains(&x) && (2..12).contains(&y);
+      let hole = (5..11).contains(&x) && (5..9).contains(&y);
+      let blob = (7..9).contains(&x) && (6..8).contains(&y);
+      let square = (17..21).contains(&x) && (3..7).contains(&y);
+      [if (frame && !hole) || blob || square {
+        255
+      } else {
+        0
+      }]
+    });
+    let contours = find_contours(&mask);
+    let summary: Vec<_> = contours.iter().map(|c| (c.hole, c.parent)).collect();
+    assert_eq!(
+      summary,
+      [
+        (false, None),
+        (false, None),
+        (true, Some(0)),
+        (false, Some(2)),
+      ]
+    );
+
+    let outer = approx_poly_dp(&contours[0].points, 0.5);
+    assert_eq!(outer, [(2, 2), (2, 11), (13, 11), (13, 2)]);
+    assert_eq!(contour_area(&contours[0].points), 99.0);
+    assert_eq!(contours[1].points.len(), 12);
+    // Tracing around a hole cuts across its corners diagonally
+    assert_eq!(approx_poly_dp(&contours[2].points, 0.5).len(), 8);
+    assert_eq!(contour_area(&contours[3].points), 1.0);
+
+    let l_shape = [(0, 0), (0, 4), (1, 4), (4, 4), (4, 3), (1, 3), (1, 0)];
+    assert_eq!(
+      convex_hull(&l_shape),
+      [(0, 0), (1, 0), (4, 3), (4, 4), (0, 4)]
+    );
+  }
+}
@@ -0,0 +1,304 @@
+//! Depth maps: single-plane `f32` buffers holding the distance to the scene
+//! at each pixel, such as the depth data of portrait-mode photos or the
+//! output of stereo matching.
+//!
+//! Larger values are farther away. Values that are zero, negative or not
+//! finite mark holes where the depth is unknown.
+
+use crate::{
+  error::Result,
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  video::check_dimensions,
+  ImageBuffer,
+};
+
+pub type DepthMap = ImageBuffer<f32, 1, false>;
+
+/// Whether `depth` marks a pixel of unknown depth
+pub fn is_hole(depth: f32) -> bool { !depth.is_finite() || depth <= 0.0 }
+
+/// Rescales the known depths to run from just above 0 at the nearest to 1
+/// at the farthest, so that maps from different sources can be compared.
+/// Holes stay at 0.
+pub fn normalize(depth: &DepthMap) -> DepthMap {
+  let (lo, hi) = depth
+    .components()
+    .iter()
+    .filter(|d| !is_hole(**d))
+    .fold((f32::MAX, f32::MIN), |(lo, hi), &d| (lo.min(d), hi.max(d)));
+  // Keep the nearest depth above zero so that it is not taken for a hole
+  let floor = f32::EPSILON;
+  depth.map(&mut |&[d]| {
+    if is_hole(d) {
+      [0.0]
+    } else if hi > lo {
+      [floor + (1.0 - floor) * (d - lo) / (hi - lo)]
+    } else {
+      [1.0]
+    }
+  })
+}
+
+/// Fills holes from the known depths around them, working inward from
+/// their edges: each pass gives the hole pixels next to known ones the mean
+/// of those neighbors. A map with no known depth is returned unchanged.
+pub fn fill_holes(depth: &DepthMap) -> DepthMap {
+  let (width, height) = (depth.width, depth.height);
+  let mut result = depth.clone();
+  if !result.components().iter().any(|d| !is_hole(*d)) {
+    return result;
+  }
+  loop {
+    let data = result.components();
+    let filled: Vec<(usize, f32)> = (0..width * height)
+      .filter(|&i| is_hole(data[i]))
+      .filter_map(|i| {
+        let (x, y) = ((i % width) as isize, (i / width) as isize);
+        let (sum, count) = (-1..=1)
+          .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+          .filter(|&(nx, ny)| {
+            nx >= 0
+              && ny >= 0
+              && (nx as usize) < width
+              && (ny as usize) < height
+          })
+          .map(|(nx, ny)| data[ny as usize * width + nx as usize])
+          .filter(|d| !is_hole(*d))
+          .fold((0.0, 0), |(sum, count), d| (sum + d, count + 1));
+        (count > 0).then(|| (i, sum / count as f32))
+      })
+      .collect();
+    if filled.is_empty() {
+      return result;
+    }
+    for (i, d) in filled {
+      result.components_mut()[i] = d;
+    }
+  }
+}
+
+/// Upsamples `depth` to the size of `guide`, a higher-resolution image of
+/// the same scene, by joint bilateral upsampling (Kopf et al.): each output
+/// pixel averages nearby depths, weighted by their distance in the depth
+/// map, with a Gaussian of standard deviation `sigma_spatial` in depth-map
+/// pixels, and by how similar the guide's color is there, with a Gaussian
+/// of standard deviation `sigma_range` in normalized component values.
+/// Depth edges then follow the edges of the guide. Holes are skipped and
+/// remain where no known depth is near.
+pub fn bilateral_upsample<T: PixelComponent, const N: usize, const A: bool>(
+  depth: &DepthMap,
+  guide: &ImageBuffer<T, N, A>,
+  sigma_spatial: f64,
+  sigma_range: f64,
+) -> DepthMap {
+  let (width, height) = (guide.width, guide.height);
+  if depth.width == 0 || depth.height == 0 {
+    return DepthMap::empty(width, height);
+  }
+  let scale_x = depth.width as f64 / width.max(1) as f64;
+  let scale_y = depth.height as f64 / height.max(1) as f64;
+  let white = T::WHITE.to_f64().unwrap_or(1.0);
+  let color = |x: usize, y: usize| -> [f64; N] {
+    guide
+      .get_pixel(x, y)
+      .map(|c| c.to_f64().unwrap_or_default() / white)
+  };
+  let radius = (2.0 * sigma_spatial).ceil().max(1.0) as isize;
+  let (two_s2, two_r2) = (
+    2.0 * sigma_spatial.powi(2).max(f64::EPSILON),
+    2.0 * sigma_range.powi(2).max(f64::EPSILON),
+  );
+  DepthMap::empty(width, height).map_indexed(&mut |x, y, _| {
+    let center = color(x, y);
+    // Position in the depth map
+    let (lx, ly) = (
+      (x as f64 + 0.5) * scale_x - 0.5,
+      (y as f64 + 0.5) * scale_y - 0.5,
+    );
+    let (cx, cy) = (lx.round() as isize, ly.round() as isize);
+    let (mut sum, mut total) = (0.0, 0.0);
+    for qy in cy - radius..=cy + radius {
+      for qx in cx - radius..=cx + radius {
+        if qx < 0
+          || qy < 0
+          || qx as usize >= depth.width
+          || qy as usize >= depth.height
+        {
+          continue;
+        }
+        let d = depth.get_pixel(qx as usize, qy as usize)[0];
+        if is_hole(d) {
+          continue;
+        }
+        // The guide pixel at the depth sample's position
+        let gx = (((qx as f64 + 0.5) / scale_x) as usize).min(width - 1);
+        let gy = (((qy as f64 + 0.5) / scale_y) as usize).min(height - 1);
+        let range: f64 = color(gx, gy)
+          .iter()
+          .zip(&center)
+          .map(|(a, b)| (a - b).powi(2))
+          .sum();
+        let spatial = (qx as f64 - lx).powi(2) + (qy as f64 - ly).powi(2);
+        let w = (-spatial / two_s2 - range / two_r2).exp();
+        sum += w * f64::from(d);
+        total += w;
+      }
+    }
+    [if total > 0.0 {
+      (sum / total) as f32
+    } else {
+      0.0
+    }]
+  })
+}
+
+/// Simulates a shallow depth of field: each pixel is blurred over a disk
+/// whose radius grows with its distance from `focus`, by `aperture` pixels
+/// per unit of depth, up to `max_radius`.
+///
+/// Pixels only gather from neighbors whose own blur reaches them, so sharp
+/// subjects do not smear into a blurred background, and a blurred
+/// background does not spill over a nearer sharp subject. Holes are treated
+/// as in focus. Fails if `depth` and `image` differ in size.
+pub fn depth_blur<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  depth: &DepthMap,
+  focus: f32,
+  aperture: f64,
+  max_radius: usize,
+) -> Result<ImageBuffer<T, N, A>> {
+  check_dimensions((image.width, image.height), depth)?;
+  let (width, height) = (image.width, image.height);
+  let radius: Vec<f64> = depth
+    .components()
+    .iter()
+    .map(|&d| {
+      if is_hole(d) {
+        0.0
+      } else {
+        (aperture * f64::from((d - focus).abs())).min(max_radius as f64)
+      }
+    })
+    .collect();
+  let distance_of = |d: f32| if is_hole(d) { focus } else { d };
+  let reach = max_radius as isize;
+  Ok(image.map_indexed(&mut |x, y, pel| {
+    let p = y * width + x;
+    let (x, y) = (x as isize, y as isize);
+    let mut sum = [0.0; N];
+    let mut total = 0.0;
+    for qy in (y - reach).max(0)..=(y + reach).min(height as isize - 1) {
+      for qx in (x - reach).max(0)..=(x + reach).min(width as isize - 1) {
+        let q = qy as usize * width + qx as usize;
+        let distance = (((qx - x).pow(2) + (qy - y).pow(2)) as f64).sqrt();
+        let reaches = distance <= radius[q] + 0.5;
+        // A blurred background stays behind a nearer subject unless that
+        // subject is blurred over it too
+        let occluded = distance_of(depth.components()[q])
+          > distance_of(depth.components()[p])
+          && distance > radius[p] + 0.5;
+        if (!reaches || occluded) && q != p {
+          continue;
+        }
+        // Spread each sample's weight over the area of its disk
+        let w = 1.0 / (radius[q] + 0.5).powi(2);
+        for (s, c) in sum
+          .iter_mut()
+          .zip(image.get_pixel(qx as usize, qy as usize))
+        {
+          *s += w * c.to_f64().unwrap_or_default();
+        }
+        total += w;
+      }
+    }
+    if total > 0.0 {
+      sum.map(|s| component_from_f64(s / total))
+    } else {
+      *pel
+    }
+  }))
+}
+
+/// Combines two layers by depth, showing at each pixel whichever is nearer.
+/// Where their depths are within `softness` of each other the layers are
+/// mixed, which hides jagged edges in coarse depth data. Holes are treated
+/// as infinitely far. Fails unless all four buffers have the same size.
+pub fn composite<T: PixelComponent, const N: usize, const A: bool>(
+  front: (&ImageBuffer<T, N, A>, &DepthMap),
+  back: (&ImageBuffer<T, N, A>, &DepthMap),
+  softness: f32,
+) -> Result<(ImageBuffer<T, N, A>, DepthMap)> {
+  let size = (front.0.width, front.0.height);
+  check_dimensions(size, front.1)?;
+  check_dimensions(size, back.0)?;
+  check_dimensions(size, back.1)?;
+  let depth_of = |d: f32| if is_hole(d) { f32::INFINITY } else { d };
+  let mut image = front.0.clone();
+  let mut depth = front.1.clone();
+  for (i, (pel, d)) in image
+    .components_mut()
+    .chunks_exact_mut(N)
+    .zip(depth.components_mut())
+    .enumerate()
+  {
+    let (a, b) = (depth_of(*d), depth_of(back.1.components()[i]));
+    // Weight of the front layer
+    let t = if softness > 0.0 && (a - b).abs() < f32::INFINITY {
+      ((b - a) / softness * 0.5 + 0.5).clamp(0.0, 1.0)
+    } else if a <= b {
+      1.0
+    } else {
+      0.0
+    };
+    let other = &back.0.components()[i * N..(i + 1) * N];
+    for (c, o) in pel.iter_mut().zip(other) {
+      let mixed = f64::from(t) * c.to_f64().unwrap_or_default()
+        + f64::from(1.0 - t) * o.to_f64().unwrap_or_default();
+      *c = component_from_f64(mixed);
+    }
+    if t < 0.5 {
+      *d = back.1.components()[i];
+    }
+  }
+  Ok((image, depth))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn depth_maps_fill_upsample_and_composite() {
+    let mut depth = DepthMap::empty(4, 4)
+      .map_indexed(&mut |x, _, _| [if x < 2 { 1.0 } else { 3.0 }]);
+    depth.components_mut()[0] = 0.0;
+    depth.components_mut()[15] = f32::NAN;
+    let normal = normalize(&depth);
+    assert_eq!(normal.get_pixel(3, 0), &[1.0]);
+    assert_eq!(normal.get_pixel(0, 0), &[0.0]);
+    let filled = fill_holes(&depth);
+    assert!(filled.components().iter().all(|d| !is_hole(*d)));
+    assert_eq!(filled.get_pixel(3, 3), &[3.0]);
+
+    // The depth edge snaps to the guide's edge, which lies off the depth
+    // map's pixel grid
+    let guide = ImageBuffer::<u8, 3, false>::empty(16, 16)
+      .map_indexed(&mut |x, _, _| if x < 7 { [0; 3] } else { [255; 3] });
+    let up = bilateral_upsample(&filled, &guide, 1.0, 0.1);
+    assert!((up.get_pixel(6, 8)[0] - 1.0).abs() < 0.05);
+    assert!((up.get_pixel(7, 8)[0] - 3.0).abs() < 0.05);
+
+    let stripes = ImageBuffer::<u8, 3, false>::empty(16, 16)
+      .map_indexed(&mut |x, _, _| [(x % 2 * 255) as u8; 3]);
+    let blurred = depth_blur(&stripes, &up, 1.0, 2.0, 4).unwrap();
+    assert_eq!(blurred.get_pixel(3, 8), stripes.get_pixel(3, 8));
+    assert!((100..156).contains(&blurred.get_pixel(12, 8)[0]));
+
+    let white = ImageBuffer::<u8, 3, false>::with_val(&[255; 3], 16, 16);
+    let far = DepthMap::with_val(&[2.0], 16, 16);
+    let (mixed, nearest) =
+      composite((&guide, &up), (&white, &far), 0.0).unwrap();
+    assert_eq!(mixed.get_pixel(2, 2), &[0; 3]);
+    assert_eq!(nearest.get_pixel(12, 2), &[2.0]);
+  }
+}
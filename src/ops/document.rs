@@ -0,0 +1,358 @@
+//! Cleanup for photographed and scanned documents: straightening, flattening
+//! the page, removing uneven lighting and binarizing the text.
+//!
+//! Angles are in degrees, positive counter-clockwise as seen on screen.
+
+use num_traits::{ToPrimitive, Zero};
+
+use super::{matting::box_mean, meter::luminance, register::sample};
+use crate::{
+  pixel::{component_from_f64, Pixel, PixelComponent, PixelContainer},
+  stitch::homography,
+  ImageBuffer,
+};
+
+/// Dynamic range of the standard deviation in Sauvola's threshold, for
+/// luminance between 0 and 1
+const SAUVOLA_RANGE: f64 = 0.5;
+
+fn luma<C: PixelContainer>(image: &C) -> Vec<f64> {
+  image.iter_pixels().map(luminance::<C>).collect()
+}
+
+/// Otsu's threshold for values between 0 and 1
+fn otsu(values: &[f64]) -> f64 {
+  let mut histogram = [0usize; 256];
+  for v in values {
+    histogram[(v.clamp(0.0, 1.0) * 255.0).round() as usize] += 1;
+  }
+  let total = values.len() as f64;
+  let sum: f64 = histogram
+    .iter()
+    .enumerate()
+    .map(|(i, &n)| (i * n) as f64)
+    .sum();
+  let (mut weight, mut below, mut best) = (0.0, 0.0, (0.0, 0));
+  for (i, &n) in histogram.iter().enumerate() {
+    weight += n as f64;
+    below += (i * n) as f64;
+    if weight == 0.0 || weight == total {
+      continue;
+    }
+    let (m0, m1) = (below / weight, (sum - below) / (total - weight));
+    let variance = weight * (total - weight) * (m0 - m1).powi(2);
+    if variance > best.0 {
+      best = (variance, i);
+    }
+  }
+  (best.1 as f64 + 0.5) / 255.0
+}
+
+/// Estimates how far the text lines in `image` are rotated, searching up to
+/// `max_angle` degrees either way.
+///
+/// Uses projection profiles: dark pixels are projected along each candidate
+/// angle, and the angle whose profile has the sharpest peaks and troughs is
+/// the one lining up with the text rows.
+pub fn detect_skew<C: PixelContainer>(image: &C, max_angle: f64) -> f64 {
+  let (width, height) = (image.width(), image.height());
+  let lum = luma(image);
+  let threshold = otsu(&lum);
+  let ink: Vec<(f64, f64)> = (0..lum.len())
+    .filter(|&i| lum[i] < threshold)
+    .map(|i| ((i % width) as f64, (i / width) as f64))
+    .collect();
+  if ink.is_empty() {
+    return 0.0;
+  }
+
+  let diagonal = (width + height) as f64;
+  let score = |angle: f64| {
+    // Along a line rotated counter-clockwise by `angle`, `y + x tan(angle)`
+    // is constant
+    let slope = angle.to_radians().tan();
+    let last = 2 * diagonal as usize;
+    let mut profile = vec![0usize; last + 1];
+    for &(x, y) in &ink {
+      let row = (y + x * slope + diagonal).round() as usize;
+      profile[row.min(last)] += 1;
+    }
+    profile
+      .windows(2)
+      .map(|w| (w[1] as f64 - w[0] as f64).powi(2))
+      .sum::<f64>()
+  };
+  let search = |from: f64, to: f64, step: f64| {
+    let steps = ((to - from) / step).round() as usize;
+    (0..=steps)
+      .map(|i| from + i as f64 * step)
+      .max_by(|a, b| score(*a).total_cmp(&score(*b)))
+      .unwrap_or_default()
+  };
+
+  let max_angle = max_angle.abs();
+  let coarse = search(-max_angle, max_angle, 0.5);
+  search(coarse - 0.5, coarse + 0.5, 0.05)
+}
+
+/// Rotates `image` about its center by `degrees`, filling the uncovered
+/// corners with white
+pub fn rotate<C: PixelContainer + Clone>(image: &C, degrees: f64) -> C {
+  let (width, height) = (image.width(), image.height());
+  let channels = C::NUM_COMPONENTS;
+  let planes = planes(image);
+  let (sin, cos) = degrees.to_radians().sin_cos();
+  let (cx, cy) = ((width as f64 - 1.0) / 2.0, (height as f64 - 1.0) / 2.0);
+  let white = C::Component::WHITE.to_f64().unwrap_or(1.0);
+
+  let mut result = image.clone();
+  for (i, pel) in result
+    .components_mut()
+    .chunks_exact_mut(channels)
+    .enumerate()
+  {
+    let (x, y) = ((i % width) as f64 - cx, (i / width) as f64 - cy);
+    let (sx, sy) = (x * cos - y * sin + cx, x * sin + y * cos + cy);
+    for (c, value) in pel.iter_mut().enumerate() {
+      let v = sample(&planes[c], width, height, sx, sy).unwrap_or(white);
+      *value = component_from_f64(v);
+    }
+  }
+  result
+}
+
+/// Straightens text lines skewed by up to `max_angle` degrees
+pub fn deskew<C: PixelContainer + Clone>(image: &C, max_angle: f64) -> C {
+  rotate(image, -detect_skew(image, max_angle))
+}
+
+fn planes<C: PixelContainer>(image: &C) -> Vec<Vec<f64>> {
+  let channels = C::NUM_COMPONENTS;
+  (0..channels)
+    .map(|c| {
+      image
+        .components()
+        .iter()
+        .skip(c)
+        .step_by(channels)
+        .map(|v| v.to_f64().unwrap_or_default())
+        .collect()
+    })
+    .collect()
+}
+
+/// Finds the corners of a bright page on a darker background, in the order
+/// top-left, top-right, bottom-right, bottom-left. Returns `None` if nothing
+/// stands out from the background.
+pub fn page_corners<C: PixelContainer>(image: &C) -> Option<[(f64, f64); 4]> {
+  let width = image.width();
+  let lum = luma(image);
+  let threshold = otsu(&lum);
+  let page = (0..lum.len())
+    .filter(|&i| lum[i] > threshold)
+    .map(|i| ((i % width) as f64, (i / width) as f64));
+
+  let mut corners = [(0.0, 0.0); 4];
+  // Extremes of x + y and x - y: the page corner nearest each image corner
+  let mut best = [
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+    f64::NEG_INFINITY,
+    f64::INFINITY,
+  ];
+  let mut any = false;
+  for (x, y) in page {
+    any = true;
+    let keys = [x + y, x - y, x + y, x - y];
+    for k in 0..4 {
+      let better = if k == 1 || k == 2 {
+        keys[k] > best[k]
+      } else {
+        keys[k] < best[k]
+      };
+      if better {
+        best[k] = keys[k];
+        corners[k] = (x, y);
+      }
+    }
+  }
+  any.then_some(corners)
+}
+
+/// Flattens the page with the given corners (as returned by
+/// [`page_corners`]) into an upright rectangle, undoing the perspective of a
+/// photo taken at an angle. The output size follows the longest edges of
+/// the page, with the corners landing on the corner pixels.
+pub fn correct_perspective<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  corners: [(f64, f64); 4],
+) -> ImageBuffer<T, N, A> {
+  let distance = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1);
+  let [tl, tr, br, bl] = corners;
+  let width = distance(tl, tr).max(distance(bl, br)).round() as usize + 1;
+  let height = distance(tl, bl).max(distance(tr, br)).round() as usize + 1;
+  let (w, h) = ((width - 1) as f64, (height - 1) as f64);
+  let rectangle = [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)];
+  let pairs: Vec<_> = rectangle.into_iter().zip(corners).collect();
+  let transform = homography::fit(&pairs).unwrap_or(homography::IDENTITY);
+
+  let planes = planes(image);
+  let mut result = ImageBuffer::empty(width, height);
+  for (i, pel) in result.components_mut().chunks_exact_mut(N).enumerate() {
+    let (x, y) = ((i % width) as f64, (i / width) as f64);
+    let Some((sx, sy)) = homography::apply(&transform, x, y) else {
+      continue;
+    };
+    for (c, value) in pel.iter_mut().enumerate() {
+      let v = sample(&planes[c], image.width, image.height, sx, sy);
+      *value = component_from_f64(v.unwrap_or_default());
+    }
+  }
+  result
+}
+
+/// Binarizes text with Sauvola's adaptive threshold over a `window` sized
+/// square around each pixel, which copes with uneven lighting. `k` controls
+/// how much local contrast lowers the threshold; around `0.2` to `0.5` suits
+/// most documents. Ink becomes black and paper white.
+pub fn sauvola<C: PixelContainer>(
+  image: &C,
+  window: usize,
+  k: f64,
+) -> C::OnePlane {
+  let (width, height) = (image.width(), image.height());
+  let radius = window / 2;
+  let lum = luma(image);
+  let squared: Vec<f64> = lum.iter().map(|v| v * v).collect();
+  let mean = box_mean(&lum, width, height, radius);
+  let mean_sq = box_mean(&squared, width, height, radius);
+
+  let mut result = image.new_plane();
+  for (i, pel) in result.iter_pixels_mut().enumerate() {
+    let std = (mean_sq[i] - mean[i] * mean[i]).max(0.0).sqrt();
+    let threshold = mean[i] * (1.0 + k * (std / SAUVOLA_RANGE - 1.0));
+    pel.components_mut()[0] = if lum[i] > threshold {
+      C::Component::WHITE
+    } else {
+      C::Component::zero()
+    };
+  }
+  result
+}
+
+/// Evens out shadows and uneven lighting on a page by dividing each color
+/// channel by an estimate of the paper's brightness: the local maximum over
+/// a `radius` neighborhood, smoothed
+pub fn remove_shadows<C: PixelContainer + Clone>(
+  image: &C,
+  radius: usize,
+) -> C {
+  let (width, height) = (image.width(), image.height());
+  let channels = C::NUM_COMPONENTS;
+  let white = C::Component::WHITE.to_f64().unwrap_or(1.0);
+  let planes = planes(image);
+
+  let max_filter = |plane: &[f64]| {
+    let mut rows = vec![0.0; plane.len()];
+    for y in 0..height {
+      for x in 0..width {
+        let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+        rows[y * width + x] = plane[y * width + x0..y * width + x1]
+          .iter()
+          .copied()
+          .fold(f64::NEG_INFINITY, f64::max);
+      }
+    }
+    let mut out = vec![0.0; plane.len()];
+    for y in 0..height {
+      let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+      for x in 0..width {
+        out[y * width + x] = (y0..y1)
+          .map(|yy| rows[yy * width + x])
+          .fold(f64::NEG_INFINITY, f64::max);
+      }
+    }
+    out
+  };
+
+  let mut result = image.clone();
+  for c in 0..C::NUM_NONALPHA_COMPONENTS {
+    let background = box_mean(&max_filter(&planes[c]), width, height, radius);
+    for (i, pel) in result
+      .components_mut()
+      .chunks_exact_mut(channels)
+      .enumerate()
+    {
+      let v = if background[i] > 0.0 {
+        (planes[c][i] / background[i]).min(1.0) * white
+      } else {
+        planes[c][i]
+      };
+      pel[c] = component_from_f64(v);
+    }
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Rows of dashes, like lines of text, on white paper
+  fn page(width: usize, height: usize) -> ImageBuffer<u8, 1, false> {
+    ImageBuffer::empty(width, height).map_indexed(&mut |x, y, _| {
+      let ink = y % 10 < 3 && x % 12 < 9 && (8..width - 8).contains(&x);
+      [if ink && y > 6 && y < height - 6 {
+        20
+      } else {
+        235
+      }]
+    })
+  }
+
+  #[test]
+  fn detect_skew_finds_rotation() {
+    let skewed = rotate(&page(120, 80), 3.0);
+    let angle = detect_skew(&skewed, 10.0);
+    assert!((angle - 3.0).abs() < 0.3, "{angle}");
+    let straightened = detect_skew(&deskew(&skewed, 10.0), 10.0);
+    assert!(straightened.abs() < 0.3, "{straightened}");
+  }
+
+  #[test]
+  fn sauvola_ignores_lighting_gradient() {
+    // Paper darkening from left to right, with one dark stroke near each side
+    let image =
+      ImageBuffer::<u8, 1, false>::empty(60, 20).map_indexed(&mut |x, y, _| {
+        let paper = 240.0 - 2.0 * x as f64;
+        let ink = (x == 10 || x == 50) && (5..15).contains(&y);
+        [(if ink { paper * 0.4 } else { paper }) as u8]
+      });
+    let binary = sauvola(&image, 15, 0.3);
+    assert_eq!(binary.get_pixel(10, 10)[0], 0);
+    assert_eq!(binary.get_pixel(50, 10)[0], 0);
+    assert_eq!(binary.get_pixel(30, 10)[0], 255);
+    assert_eq!(binary.get_pixel(55, 2)[0], 255);
+  }
+
+  #[test]
+  fn page_corners_and_perspective() {
+    let image =
+      ImageBuffer::<u8, 3, false>::empty(50, 40).map_indexed(&mut |x, y, _| {
+        let on_page = (10..40).contains(&x) && (5..30).contains(&y);
+        if on_page {
+          [250, 250, 240]
+        } else {
+          [30, 30, 30]
+        }
+      });
+    let corners = page_corners(&image).unwrap();
+    assert_eq!(
+      corners,
+      [(10.0, 5.0), (39.0, 5.0), (39.0, 29.0), (10.0, 29.0)]
+    );
+    let flat = correct_perspective(&image, corners);
+    assert_eq!((flat.width, flat.height), (30, 25));
+    assert!(flat.iter_pixels().all(|pel| pel[0] == 250));
+  }
+}
@@ -0,0 +1,448 @@
+//! Per-component arithmetic on images, written as small expressions such as
+//! `"0.5*a + 0.5*b"` or `"clamp(a - b + 128)"` and chosen at runtime.
+//!
+//! An [`Expr`] is parsed once into bytecode for a small stack machine, then
+//! run over every component of its inputs in a single pass. Names in the
+//! expression refer to input images; numbers are in the units of the
+//! components, so `255` is white for 8-bit images.
+//!
+//! Supported are `+`, `-`, `*`, `/`, `^` (power), unary minus, parentheses,
+//! and the functions `abs`, `sqrt`, `floor`, `round`, `min`, `max` and
+//! `clamp`. `clamp(v)` clamps to black and white; `clamp(v, lo, hi)` to the
+//! given range, giving `hi` when `lo` is above it. Results are rounded and
+//! clamped to the component type.
+
+use crate::{
+  error::{Error, Result},
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Func {
+  Abs,
+  Sqrt,
+  Floor,
+  Round,
+  Min,
+  Max,
+  Clamp,
+}
+
+impl Func {
+  fn from_name(name: &str) -> Option<Func> {
+    Some(match name {
+      "abs" => Func::Abs,
+      "sqrt" => Func::Sqrt,
+      "floor" => Func::Floor,
+      "round" => Func::Round,
+      "min" => Func::Min,
+      "max" => Func::Max,
+      "clamp" => Func::Clamp,
+      _ => return None,
+    })
+  }
+
+  fn accepts(self, args: usize) -> bool {
+    match self {
+      Func::Min | Func::Max => args >= 2,
+      Func::Clamp => args == 1 || args == 3,
+      _ => args == 1,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+  Const(f64),
+  Input(usize),
+  Neg,
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Pow,
+  Call(Func, usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  Number(f64),
+  Name(String),
+  Symbol(char),
+}
+
+fn syntax(message: impl std::fmt::Display, at: usize) -> Error {
+  Error::InvalidArgument(format!("Expression error at {at}: {message}"))
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>> {
+  let mut tokens = Vec::new();
+  let chars: Vec<char> = source.chars().collect();
+  let mut i = 0;
+  while i < chars.len() {
+    let start = i;
+    let c = chars[i];
+    if c.is_whitespace() {
+      i += 1;
+      continue;
+    }
+    if c.is_ascii_digit() || c == '.' {
+      while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+        i += 1;
+      }
+      let text: String = chars[start..i].iter().collect();
+      let value = text
+        .parse()
+        .map_err(|_| syntax(format!("bad number `{text}`"), start))?;
+      tokens.push((Token::Number(value), start));
+    } else if c.is_alphabetic() || c == '_' {
+      while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+        i += 1;
+      }
+      tokens.push((Token::Name(chars[start..i].iter().collect()), start));
+    } else if "+-*/^(),".contains(c) {
+      tokens.push((Token::Symbol(c), start));
+      i += 1;
+    } else {
+      return Err(syntax(format!("unexpected `{c}`"), start));
+    }
+  }
+  Ok(tokens)
+}
+
+/// Deepest nesting of parentheses, calls, negations and powers accepted,
+/// so that hostile expressions cannot overflow the stack while parsing
+const MAX_NESTING: usize = 256;
+
+/// Recursive descent parser emitting postfix bytecode
+struct Parser {
+  tokens:  Vec<(Token, usize)>,
+  pos:     usize,
+  end:     usize,
+  code:    Vec<Op>,
+  inputs:  Vec<String>,
+  /// How many `unary`s are being parsed, which every recursion goes through
+  nesting: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> { self.tokens.get(self.pos).map(|(t, _)| t) }
+
+  fn at(&self) -> usize {
+    self.tokens.get(self.pos).map_or(self.end, |&(_, at)| at)
+  }
+
+  fn eat(&mut self, symbol: char) -> bool {
+    if self.peek() == Some(&Token::Symbol(symbol)) {
+      self.pos += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn expect(&mut self, symbol: char) -> Result<()> {
+    if self.eat(symbol) {
+      Ok(())
+    } else {
+      Err(syntax(format!("expected `{symbol}`"), self.at()))
+    }
+  }
+
+  /// `sum := product (('+' | '-') product)*`
+  fn sum(&mut self) -> Result<()> {
+    self.product()?;
+    loop {
+      let op = if self.eat('+') {
+        Op::Add
+      } else if self.eat('-') {
+        Op::Sub
+      } else {
+        return Ok(());
+      };
+      self.product()?;
+      self.code.push(op);
+    }
+  }
+
+  /// `product := unary (('*' | '/') unary)*`
+  fn product(&mut self) -> Result<()> {
+    self.unary()?;
+    loop {
+      let op = if self.eat('*') {
+        Op::Mul
+      } else if self.eat('/') {
+        Op::Div
+      } else {
+        return Ok(());
+      };
+      self.unary()?;
+      self.code.push(op);
+    }
+  }
+
+  /// `unary := '-' unary | power`
+  fn unary(&mut self) -> Result<()> {
+    if self.nesting == MAX_NESTING {
+      return Err(syntax(
+        format!("nested more than {MAX_NESTING} deep"),
+        self.at(),
+      ));
+    }
+    self.nesting += 1;
+    let result = if self.eat('-') {
+      self.unary().map(|()| self.code.push(Op::Neg))
+    } else {
+      self.power()
+    };
+    self.nesting -= 1;
+    result
+  }
+
+  /// `power := atom ('^' unary)?`, binding to the right
+  fn power(&mut self) -> Result<()> {
+    self.atom()?;
+    if self.eat('^') {
+      self.unary()?;
+      self.code.push(Op::Pow);
+    }
+    Ok(())
+  }
+
+  /// `atom := number | name | name '(' sum (',' sum)* ')' | '(' sum ')'`
+  fn atom(&mut self) -> Result<()> {
+    let at = self.at();
+    match self.tokens.get(self.pos).map(|(t, _)| t.clone()) {
+      Some(Token::Number(value)) => {
+        self.pos += 1;
+        self.code.push(Op::Const(value));
+      }
+      Some(Token::Name(name)) => {
+        self.pos += 1;
+        if self.eat('(') {
+          let func = Func::from_name(&name)
+            .ok_or_else(|| syntax(format!("unknown function `{name}`"), at))?;
+          let mut args = 1;
+          self.sum()?;
+          while self.eat(',') {
+            self.sum()?;
+            args += 1;
+          }
+          self.expect(')')?;
+          if !func.accepts(args) {
+            return Err(syntax(
+              format!("`{name}` does not take {args} arguments"),
+              at,
+            ));
+          }
+          self.code.push(Op::Call(func, args));
+        } else {
+          let index = match self.inputs.iter().position(|n| *n == name) {
+            Some(index) => index,
+            None => {
+              self.inputs.push(name);
+              self.inputs.len() - 1
+            }
+          };
+          self.code.push(Op::Input(index));
+        }
+      }
+      Some(Token::Symbol('(')) => {
+        self.pos += 1;
+        self.sum()?;
+        self.expect(')')?;
+      }
+      Some(token) => return Err(syntax(format!("unexpected {token:?}"), at)),
+      None => return Err(syntax("unexpected end", at)),
+    }
+    Ok(())
+  }
+}
+
+/// A parsed expression, ready to be evaluated over images
+#[derive(Clone, Debug)]
+pub struct Expr {
+  code:   Vec<Op>,
+  inputs: Vec<String>,
+  /// Deepest the evaluation stack gets
+  depth:  usize,
+}
+
+impl Expr {
+  /// Parses `source`. Fails with [`Error::InvalidArgument`], naming the
+  /// character position, if it is not a valid expression or nests more than
+  /// 256 deep.
+  pub fn parse(source: &str) -> Result<Expr> {
+    let mut parser = Parser {
+      tokens:  tokenize(source)?,
+      pos:     0,
+      end:     source.chars().count(),
+      code:    Vec::new(),
+      inputs:  Vec::new(),
+      nesting: 0,
+    };
+    parser.sum()?;
+    if parser.pos < parser.tokens.len() {
+      return Err(syntax("unexpected trailing input", parser.at()));
+    }
+    let mut depth = 0usize;
+    let mut max_depth = 0;
+    for op in &parser.code {
+      depth = match *op {
+        Op::Const(_) | Op::Input(_) => depth + 1,
+        Op::Neg => depth,
+        Op::Call(_, args) => depth + 1 - args,
+        _ => depth - 1,
+      };
+      max_depth = max_depth.max(depth);
+    }
+    Ok(Expr {
+      code:   parser.code,
+      inputs: parser.inputs,
+      depth:  max_depth,
+    })
+  }
+
+  /// Names of the input images the expression uses, in order of first use
+  pub fn inputs(&self) -> &[String] { &self.inputs }
+
+  /// Evaluates the expression for every component of `inputs`, which are
+  /// looked up by name and must all have the same size. Fails if an input
+  /// the expression uses is missing or has a different size.
+  pub fn evaluate<T: PixelComponent, const N: usize, const A: bool>(
+    &self,
+    inputs: &[(&str, &ImageBuffer<T, N, A>)],
+  ) -> Result<ImageBuffer<T, N, A>> {
+    let images = self
+      .inputs
+      .iter()
+      .map(|name| {
+        inputs
+          .iter()
+          .find(|(n, _)| n == name)
+          .map(|&(_, image)| image)
+          .ok_or_else(|| {
+            Error::InvalidArgument(format!("No input named `{name}`"))
+          })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    let (width, height) = match (images.first(), inputs.first()) {
+      (Some(image), _) | (None, Some((_, image))) =>
+        (image.width, image.height),
+      (None, None) =>
+        return Err(Error::InvalidArgument(
+          "Expression needs at least one input image".to_string(),
+        )),
+    };
+    if let Some(image) = images
+      .iter()
+      .find(|image| (image.width, image.height) != (width, height))
+    {
+      return Err(Error::DimensionMismatch {
+        expected: (width, height),
+        actual:   (image.width, image.height),
+      });
+    }
+
+    let white = T::WHITE.to_f64().unwrap_or(1.0);
+    let mut result = ImageBuffer::empty(width, height);
+    let mut stack = Vec::with_capacity(self.depth);
+    for (i, out) in result.components_mut().iter_mut().enumerate() {
+      stack.clear();
+      for op in &self.code {
+        match *op {
+          Op::Const(value) => stack.push(value),
+          Op::Input(k) =>
+            stack.push(images[k].components()[i].to_f64().unwrap_or_default()),
+          Op::Neg => {
+            let v = stack.pop().unwrap_or_default();
+            stack.push(-v);
+          }
+          Op::Call(func, args) => {
+            let first = stack.len() - args;
+            let v = call(func, &stack[first..], white);
+            stack.truncate(first);
+            stack.push(v);
+          }
+          binary => {
+            let b = stack.pop().unwrap_or_default();
+            let a = stack.pop().unwrap_or_default();
+            stack.push(match binary {
+              Op::Add => a + b,
+              Op::Sub => a - b,
+              Op::Mul => a * b,
+              Op::Div => a / b,
+              _ => a.powf(b),
+            });
+          }
+        }
+      }
+      *out = component_from_f64(stack.pop().unwrap_or_default());
+    }
+    Ok(result)
+  }
+}
+
+fn call(func: Func, args: &[f64], white: f64) -> f64 {
+  match func {
+    Func::Abs => args[0].abs(),
+    Func::Sqrt => args[0].sqrt(),
+    Func::Floor => args[0].floor(),
+    Func::Round => args[0].round(),
+    Func::Min => args.iter().copied().fold(f64::INFINITY, f64::min),
+    Func::Max => args.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+    Func::Clamp if args.len() == 3 => args[0].max(args[1]).min(args[2]),
+    Func::Clamp => args[0].max(0.0).min(white),
+  }
+}
+
+/// Parses `source` and evaluates it over `inputs`, as [`Expr::evaluate`]
+/// does
+pub fn evaluate<T: PixelComponent, const N: usize, const A: bool>(
+  source: &str,
+  inputs: &[(&str, &ImageBuffer<T, N, A>)],
+) -> Result<ImageBuffer<T, N, A>> {
+  Expr::parse(source)?.evaluate(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_and_evaluates_expressions() {
+    let a = ImageBuffer::<u8, 1, false>::with_val(&[200], 2, 2);
+    let b = ImageBuffer::<u8, 1, false>::with_val(&[100], 2, 2);
+    let inputs = [("a", &a), ("b", &b)];
+    let run = |source: &str| evaluate(source, &inputs).unwrap().components()[0];
+    assert_eq!(run("0.5*a + 0.5*b"), 150);
+    assert_eq!(run("clamp(b - a + 128)"), 28);
+    assert_eq!(run("clamp(a, 200, 100)"), 100);
+    assert_eq!(run("clamp(b, a, b)"), 100);
+    assert_eq!(run("clamp(a, 0/0, 150)"), 150);
+    assert_eq!(run("-(a - b) * 2 + 255"), 55);
+    assert_eq!(run("2 ^ 3 ^ 2 / 2 ^ 8"), 2);
+    assert_eq!(run("max(a, b, 250) - min(sqrt(b), 5)"), 245);
+
+    let expr = Expr::parse("a*b/255 + a").unwrap();
+    assert_eq!(expr.inputs(), &["a".to_string(), "b".to_string()]);
+    for bad in ["a +", "foo(a)", "clamp(a, 1)", "a b", "(a", "3 $ 4"] {
+      assert!(
+        matches!(Expr::parse(bad), Err(Error::InvalidArgument(_))),
+        "{bad}"
+      );
+    }
+    let deep = format!("{}a{}", "(".repeat(5000), ")".repeat(5000));
+    assert!(matches!(Expr::parse(&deep), Err(Error::InvalidArgument(_))));
+    assert!(Expr::parse(&format!("{}a", "-".repeat(5000))).is_err());
+    let nested = format!("{}a{}", "(".repeat(100), ")".repeat(100));
+    assert!(Expr::parse(&nested).is_ok());
+    assert!(evaluate("a + c", &inputs).is_err());
+    let small = ImageBuffer::<u8, 1, false>::empty(1, 1);
+    assert!(matches!(
+      evaluate("a + b", &[("a", &a), ("b", &small)]),
+      Err(Error::DimensionMismatch { .. })
+    ));
+  }
+}
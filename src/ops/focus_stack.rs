@@ -0,0 +1,151 @@
+//! Focus stacking: combining shots of a scene focused at different depths
+//! into one image that is sharp throughout, as in macro photography.
+
+use num_traits::ToPrimitive;
+
+use crate::{
+  error::{Error, Result},
+  ops::{blur::gaussian_blur, meter::luminance},
+  pixel::{component_from_f64, PixelContainer},
+  stitch::blend::{multiband, Plane},
+  video::check_dimensions,
+  ImageBuffer,
+};
+
+/// Blur applied to the sharpness measure, so that each choice of frame
+/// covers a neighborhood rather than single noisy pixels
+const SHARPNESS_SIGMA: f64 = 2.0;
+
+/// Pyramid levels used to blend the frames
+const LEVELS: usize = 5;
+
+/// Combines `frames`, aligned shots focused at different depths, keeping
+/// each part of the scene from the frame where it is sharpest.
+///
+/// Sharpness is the magnitude of the Laplacian of the luminance, averaged
+/// over a small neighborhood. Each pixel is assigned to the sharpest frame,
+/// and the frames are combined by multi-band blending with those
+/// assignments as masks, which hides the transitions between them. Frames
+/// shot handheld should be aligned first, for example with
+/// [`register`](super::register::register). Fails for an empty stack or if
+/// the frames differ in size.
+pub fn focus_stack<C: PixelContainer + Clone>(frames: &[C]) -> Result<C> {
+  let first = frames
+    .first()
+    .ok_or_else(|| Error::InvalidArgument("The stack is empty".to_string()))?;
+  let (width, height) = (first.width(), first.height());
+  for frame in frames {
+    check_dimensions((width, height), frame)?;
+  }
+  if frames.len() == 1 || width == 0 || height == 0 {
+    return Ok(first.clone());
+  }
+
+  let sharpness: Vec<ImageBuffer<f32, 1, false>> = frames
+    .iter()
+    .map(|frame| {
+      let luma: Vec<f64> = frame.iter_pixels().map(luminance::<C>).collect();
+      let at = |x: isize, y: isize| {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        luma[y * width + x]
+      };
+      let laplacian =
+        ImageBuffer::empty(width, height).map_indexed(&mut |x, y, _| {
+          let (x, y) = (x as isize, y as isize);
+          let sum = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1);
+          [(sum - 4.0 * at(x, y)).abs() as f32]
+        });
+      gaussian_blur(&laplacian, SHARPNESS_SIGMA)
+    })
+    .collect();
+
+  let mut masks = vec![Plane::new(width, height); frames.len()];
+  for i in 0..width * height {
+    let sharpest = (0..frames.len())
+      .max_by(|&a, &b| {
+        sharpness[a].components()[i].total_cmp(&sharpness[b].components()[i])
+      })
+      .unwrap_or(0);
+    masks[sharpest].data[i] = 1.0;
+  }
+  let coverage = vec![
+    Plane {
+      width,
+      height,
+      data: vec![1.0; width * height],
+    };
+    frames.len()
+  ];
+
+  let channels = C::NUM_COMPONENTS;
+  let mut result = first.clone();
+  for c in 0..channels {
+    let planes: Vec<Plane> = frames
+      .iter()
+      .map(|frame| {
+        Plane {
+          width,
+          height,
+          data: frame
+            .components()
+            .iter()
+            .skip(c)
+            .step_by(channels)
+            .map(|v| v.to_f32().unwrap_or_default())
+            .collect(),
+        }
+      })
+      .collect();
+    let blended = multiband(&planes, &coverage, &masks, LEVELS);
+    for (pel, &v) in result
+      .components_mut()
+      .chunks_exact_mut(channels)
+      .zip(&blended.data)
+    {
+      pel[c] = component_from_f64(f64::from(v));
+    }
+  }
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn keeps_the_sharp_half_of_each_frame() {
+    let sharp =
+      |x: usize, y: usize| [if (x + y).is_multiple_of(2) { 200u8 } else { 40 }];
+    let texture = ImageBuffer::<u8, 1, false>::empty(32, 32)
+      .map_indexed(&mut |x, y, _| sharp(x, y));
+    let soft = gaussian_blur(&texture, 3.0);
+    // Each frame is in focus on a different side
+    let near = texture.map_indexed(&mut |x, y, pel| {
+      if x < 16 {
+        *pel
+      } else {
+        *soft.get_pixel(x, y)
+      }
+    });
+    let far = texture.map_indexed(&mut |x, y, pel| {
+      if x >= 16 {
+        *pel
+      } else {
+        *soft.get_pixel(x, y)
+      }
+    });
+    let stacked = focus_stack(&[near.clone(), far]).unwrap();
+    let contrast = |image: &ImageBuffer<u8, 1, false>,
+                    xs: std::ops::Range<usize>| {
+      xs.map(|x| {
+        image.get_pixel(x, 10)[0].abs_diff(image.get_pixel(x + 1, 10)[0]) as u32
+      })
+      .sum::<u32>()
+    };
+    assert!(contrast(&stacked, 4..12) > 8 * 120);
+    assert!(contrast(&stacked, 20..28) > 8 * 120);
+    assert!(contrast(&near, 20..28) < 8 * 20);
+    assert!(focus_stack::<ImageBuffer<u8, 1, false>>(&[]).is_err());
+  }
+}
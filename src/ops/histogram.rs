@@ -0,0 +1,112 @@
+use num_traits::ToPrimitive;
+
+use crate::pixel::PixelContainer;
+
+/// Remaps each color channel of `image` so that its distribution of values
+/// matches the corresponding channel of `reference`.
+///
+/// Works by rank: the pixel at quantile `q` of a source channel takes the
+/// value found at quantile `q` of the reference channel, with equal source
+/// values always mapped to the same output. The two images may have different
+/// dimensions. Alpha is left untouched.
+pub fn match_histogram<C, R>(image: &C, reference: &R) -> C
+where
+  C: PixelContainer + Clone,
+  R: PixelContainer<Component = C::Component, OnePixel = C::OnePixel>,
+{
+  let mut result = image.clone();
+  let stride = C::NUM_COMPONENTS;
+  if image.components().is_empty() || reference.components().is_empty() {
+    return result;
+  }
+
+  for channel in 0..C::NUM_NONALPHA_COMPONENTS {
+    let mut source: Vec<(f64, usize)> = image
+      .components()
+      .iter()
+      .skip(channel)
+      .step_by(stride)
+      .enumerate()
+      .map(|(i, c)| (c.to_f64().unwrap_or_default(), i))
+      .collect();
+    source.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut targets: Vec<_> = reference
+      .components()
+      .iter()
+      .skip(channel)
+      .step_by(stride)
+      .copied()
+      .collect();
+    targets.sort_by(|a, b| {
+      let a = a.to_f64().unwrap_or_default();
+      let b = b.to_f64().unwrap_or_default();
+      a.total_cmp(&b)
+    });
+
+    let out = result.components_mut();
+    let mut start = 0;
+    while start < source.len() {
+      let value = source[start].0;
+      let end = start
+        + source[start..]
+          .iter()
+          .take_while(|(v, _)| v.total_cmp(&value).is_eq())
+          .count();
+      let mid_rank = (start + end) as f64 / 2.0;
+      let quantile = mid_rank / source.len() as f64;
+      let target_idx =
+        ((quantile * targets.len() as f64) as usize).min(targets.len() - 1);
+      for &(_, i) in &source[start..end] {
+        out[i * stride + channel] = targets[target_idx];
+      }
+      start = end;
+    }
+  }
+
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ImageBuffer;
+
+  #[test]
+  fn match_histogram_takes_reference_values() {
+    let image =
+      ImageBuffer::<u8, 1, false>::with_data(vec![0, 1, 2, 3], 2, 2).unwrap();
+    let reference =
+      ImageBuffer::<u8, 1, false>::with_data(vec![200, 100, 250, 150], 4, 1)
+        .unwrap();
+    let result = match_histogram(&image, &reference);
+    assert_eq!(result.components(), &[100, 150, 200, 250]);
+  }
+
+  #[test]
+  fn match_histogram_keeps_ties_and_alpha_rgba_u8() {
+    let image =
+      ImageBuffer::<u8, 2, true>::with_data(vec![5, 9, 5, 9, 7, 9, 7, 9], 4, 1)
+        .unwrap();
+    let reference = ImageBuffer::<u8, 2, true>::with_data(
+      vec![10, 0, 20, 0, 30, 0, 40, 0],
+      4,
+      1,
+    )
+    .unwrap();
+    let result = match_histogram(&image, &reference);
+    let values: Vec<_> = result.iter_pixels().map(|p| p[0]).collect();
+    assert_eq!(values[0], values[1]);
+    assert_eq!(values[2], values[3]);
+    assert!(values[0] < values[2]);
+    assert!(result.iter_pixels().all(|p| p[1] == 9));
+  }
+
+  #[test]
+  fn match_histogram_with_itself_is_identity_f32() {
+    let image = ImageBuffer::<f32, 3, false>::empty(3, 3)
+      .map_indexed(&mut |x, y, _| [x as f32, y as f32, (x * y) as f32]);
+    let result = match_histogram(&image, &image);
+    assert_eq!(result.components(), image.components());
+  }
+}
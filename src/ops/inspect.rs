@@ -0,0 +1,127 @@
+//! Views for examining individual pixels, such as in an image inspector or
+//! while debugging an operation.
+
+use crate::{
+  error::{Error, Result},
+  limits::Limits,
+  pixel::{PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// Decorations drawn by [`zoom_nn`], in the pixel format of the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ZoomOptions<T: PixelComponent, const N: usize> {
+  /// Color of one-pixel lines along the top and left of every zoomed pixel
+  pub grid:      Option<[T; N]>,
+  /// Source coordinates of a pixel to outline, and the outline color. The
+  /// outline is drawn over the grid.
+  pub highlight: Option<((usize, usize), [T; N])>,
+}
+
+impl<T: PixelComponent, const N: usize> Default for ZoomOptions<T, N> {
+  fn default() -> Self {
+    ZoomOptions {
+      grid:      None,
+      highlight: None,
+    }
+  }
+}
+
+/// Enlarges `image` by a whole `factor`, repeating every pixel into a
+/// `factor` by `factor` block so that values stay exact, and draws the
+/// decorations in `options`. With a factor below 3 the grid would cover most
+/// of each block, so it is best kept for larger zooms.
+///
+/// Fails if `factor` is zero or the highlighted pixel is outside the image,
+/// and with [`Error::LimitExceeded`] if the zoomed image does not fit in
+/// memory.
+pub fn zoom_nn<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  factor: usize,
+  options: &ZoomOptions<T, N>,
+) -> Result<ImageBuffer<T, N, A>> {
+  if factor == 0 {
+    return Err(Error::InvalidArgument(
+      "Zoom factor must be at least 1".to_string(),
+    ));
+  }
+  if let Some(((x, y), _)) = options.highlight {
+    if x >= image.width || y >= image.height {
+      return Err(Error::InvalidArgument(format!(
+        "Highlighted pixel ({x}, {y}) is outside the {}x{} image",
+        image.width, image.height
+      )));
+    }
+  }
+  let scaled = |length: usize| {
+    length.checked_mul(factor).ok_or_else(|| {
+      Error::LimitExceeded(format!(
+        "A {}x{} image zoomed {factor} times does not fit in memory",
+        image.width, image.height
+      ))
+    })
+  };
+  let mut result = ImageBuffer::try_empty(
+    scaled(image.width)?,
+    scaled(image.height)?,
+    &Limits::none(),
+  )?;
+  let width = result.width;
+  for (i, pel) in result.iter_pixels_mut().enumerate() {
+    let (x, y) = (i % width, i / width);
+    *pel = *image.get_pixel(x / factor, y / factor);
+    if let Some(grid) = options.grid {
+      if x % factor == 0 || y % factor == 0 {
+        *pel = grid;
+      }
+    }
+  }
+  if let Some(((hx, hy), color)) = options.highlight {
+    let (left, top) = (hx * factor, hy * factor);
+    let (right, bottom) = (left + factor - 1, top + factor - 1);
+    for y in top..=bottom {
+      for x in left..=right {
+        if x == left || x == right || y == top || y == bottom {
+          *result.get_pixel_mut(x, y) = color;
+        }
+      }
+    }
+  }
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn zoom_repeats_pixels_and_draws_decorations() {
+    let image = ImageBuffer::<u8, 1, false>::empty(3, 2)
+      .map_indexed(&mut |x, y, _| [(y * 3 + x) as u8 * 10]);
+    let plain = zoom_nn(&image, 2, &ZoomOptions::default()).unwrap();
+    assert_eq!((plain.width, plain.height), (6, 4));
+    assert_eq!(plain.get_pixel(5, 3), &[50]);
+
+    let options = ZoomOptions {
+      grid:      Some([255]),
+      highlight: Some(((1, 1), [128])),
+    };
+    let zoomed = zoom_nn(&image, 4, &options).unwrap();
+    assert_eq!(zoomed.get_pixel(0, 2), &[255]);
+    assert_eq!(zoomed.get_pixel(2, 2), &[0]);
+    assert_eq!(zoomed.get_pixel(4, 4), &[128]);
+    assert_eq!(zoomed.get_pixel(7, 6), &[128]);
+    assert_eq!(zoomed.get_pixel(6, 6), &[40]);
+
+    assert!(zoom_nn(&image, 0, &ZoomOptions::default()).is_err());
+    let outside = ZoomOptions {
+      highlight: Some(((3, 0), [0])),
+      ..options
+    };
+    assert!(zoom_nn(&image, 2, &outside).is_err());
+    assert!(matches!(
+      zoom_nn(&image, usize::MAX / 2, &ZoomOptions::default()),
+      Err(Error::LimitExceeded(_))
+    ));
+  }
+}
@@ -0,0 +1,207 @@
+//! Per-channel lookup tables, the engine behind point operations and tone
+//! curves.
+//!
+//! [`apply_per_channel`] evaluates an adjustment once per possible value into
+//! a table for `u8` and `u16` images, and per component otherwise. [`Lut1d`]
+//! holds sampled curves, such as the "curves" adjustment of photo editors,
+//! and runs them through the same path.
+
+use num_traits::ToPrimitive;
+
+use crate::{
+  error::{Error, Result},
+  pixel::{component_from_f64, is_integer, PixelComponent, PixelContainer},
+};
+
+/// Largest white level for which a lookup table beats direct evaluation
+const MAX_TABLE_WHITE: usize = u16::MAX as usize;
+
+/// Applies `curve` to every color component of `image`. The curve receives
+/// the channel index and the component scaled to `0.0..=1.0`, and returns the
+/// new value on the same scale; results outside that range are clamped for
+/// integer components.
+pub fn apply_per_channel<C, F>(image: &mut C, curve: F)
+where
+  C: PixelContainer,
+  F: Fn(usize, f64) -> f64,
+{
+  let white = C::Component::WHITE.to_f64().unwrap_or(1.0);
+  let channels = C::NUM_COMPONENTS;
+  let table_size = C::Component::WHITE
+    .to_usize()
+    .filter(|&w| is_integer::<C::Component>() && w <= MAX_TABLE_WHITE)
+    .map(|w| w + 1);
+
+  match table_size {
+    Some(size) => {
+      let tables: Vec<Vec<C::Component>> = (0..C::NUM_NONALPHA_COMPONENTS)
+        .map(|c| {
+          (0..size)
+            .map(|v| component_from_f64(curve(c, v as f64 / white) * white))
+            .collect()
+        })
+        .collect();
+      for pel in image.components_mut().chunks_exact_mut(channels) {
+        for (value, table) in pel.iter_mut().zip(&tables) {
+          *value = table[value.to_usize().unwrap_or_default()];
+        }
+      }
+    }
+    None =>
+      for pel in image.components_mut().chunks_exact_mut(channels) {
+        for (c, value) in
+          pel.iter_mut().take(C::NUM_NONALPHA_COMPONENTS).enumerate()
+        {
+          let v = value.to_f64().unwrap_or_default() / white;
+          *value = component_from_f64(curve(c, v) * white);
+        }
+      },
+  }
+}
+
+/// Cubic Hermite interpolation through control points with Catmull-Rom
+/// tangents, flat beyond the first and last points
+fn catmull_rom(points: &[(f64, f64)], x: f64) -> f64 {
+  let last = points.len() - 1;
+  if x <= points[0].0 {
+    return points[0].1;
+  }
+  if x >= points[last].0 {
+    return points[last].1;
+  }
+  let i = points.partition_point(|p| p.0 <= x) - 1;
+  let slope = |a: usize, b: usize| {
+    (points[b].1 - points[a].1) / (points[b].0 - points[a].0)
+  };
+  let tangent = |k: usize| slope(k.saturating_sub(1), (k + 1).min(last));
+  let (x0, x1) = (points[i].0, points[i + 1].0);
+  let (h, t) = (x1 - x0, (x - x0) / (x1 - x0));
+  let (t2, t3) = (t * t, t * t * t);
+  (2.0 * t3 - 3.0 * t2 + 1.0) * points[i].1
+    + (t3 - 2.0 * t2 + t) * h * tangent(i)
+    + (-2.0 * t3 + 3.0 * t2) * points[i + 1].1
+    + (t3 - t2) * h * tangent(i + 1)
+}
+
+/// One or more tone curves sampled evenly over `0.0..=1.0`, applied per
+/// channel
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut1d {
+  curves: Vec<Vec<f32>>,
+}
+
+impl Lut1d {
+  /// Samples per curve when building from control points or functions
+  pub const DEFAULT_SIZE: usize = 4096;
+
+  /// Samples `f(channel, value)` at `size` evenly spaced values for each of
+  /// `channels` curves
+  pub fn from_fn(
+    channels: usize,
+    size: usize,
+    f: impl Fn(usize, f64) -> f64,
+  ) -> Self {
+    let size = size.max(2);
+    let step = (size - 1) as f64;
+    Lut1d {
+      curves: (0..channels.max(1))
+        .map(|c| (0..size).map(|i| f(c, i as f64 / step) as f32).collect())
+        .collect(),
+    }
+  }
+
+  /// A curve through `points`, given as `(input, output)` pairs between 0 and
+  /// 1, applied to every channel
+  pub fn from_curve(points: &[(f64, f64)]) -> Result<Self> {
+    Self::from_curves(&[points])
+  }
+
+  /// One curve per channel, each through its own control points
+  ///
+  /// Every curve needs at least two points with distinct inputs; points may
+  /// be given in any order.
+  pub fn from_curves(curves: &[&[(f64, f64)]]) -> Result<Self> {
+    if curves.is_empty() {
+      return Err(Error::InvalidArgument("No curves given".to_string()));
+    }
+    let mut sorted = Vec::with_capacity(curves.len());
+    for points in curves {
+      let mut points = points.to_vec();
+      points.sort_by(|a, b| a.0.total_cmp(&b.0));
+      if points.len() < 2 || points.windows(2).any(|w| w[0].0 == w[1].0) {
+        return Err(Error::InvalidArgument(
+          "A curve needs at least two points with distinct inputs".to_string(),
+        ));
+      }
+      sorted.push(points);
+    }
+    Ok(Self::from_fn(sorted.len(), Self::DEFAULT_SIZE, |c, x| {
+      catmull_rom(&sorted[c], x).clamp(0.0, 1.0)
+    }))
+  }
+
+  /// Number of curves
+  pub fn channels(&self) -> usize { self.curves.len() }
+
+  /// Looks up `value` on the curve for `channel`, interpolating linearly
+  /// between samples. A single-curve table uses that curve for every
+  /// channel.
+  pub fn evaluate(&self, channel: usize, value: f64) -> f64 {
+    let curve = &self.curves[channel.min(self.curves.len() - 1)];
+    let pos = value.clamp(0.0, 1.0) * (curve.len() - 1) as f64;
+    let i = (pos as usize).min(curve.len() - 2);
+    let t = (pos - i as f64) as f32;
+    f64::from(curve[i] * (1.0 - t) + curve[i + 1] * t)
+  }
+
+  /// Applies the curves to the color channels of `image`, leaving alpha
+  /// untouched. The table must hold one curve, or one per color channel.
+  pub fn apply<C: PixelContainer>(&self, image: &mut C) -> Result<()> {
+    if self.channels() != 1 && self.channels() != C::NUM_NONALPHA_COMPONENTS {
+      return Err(Error::Channel(format!(
+        "Lookup table has {} curves for {} color channels",
+        self.channels(),
+        C::NUM_NONALPHA_COMPONENTS
+      )));
+    }
+    apply_per_channel(image, |c, v| self.evaluate(c, v));
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ImageBuffer;
+
+  #[test]
+  fn lut_curve_passes_through_points() {
+    let lut =
+      Lut1d::from_curve(&[(1.0, 1.0), (0.0, 0.0), (0.25, 0.4), (0.75, 0.8)])
+        .unwrap();
+    for (x, y) in [(0.0, 0.0), (0.25, 0.4), (0.75, 0.8), (1.0, 1.0)] {
+      assert!((lut.evaluate(0, x) - y).abs() < 1e-3);
+    }
+    // Smooth, so the midpoint lies between its neighbors
+    let mid = lut.evaluate(0, 0.5);
+    assert!(mid > 0.4 && mid < 0.8, "{mid}");
+
+    let mut image =
+      ImageBuffer::<u16, 3, false>::empty(1, 1).map(&mut |_| [0, 16384, 65535]);
+    lut.apply(&mut image).unwrap();
+    let [r, g, b] = *image.get_pixel(0, 0);
+    assert_eq!((r, b), (0, 65535));
+    assert!(g.abs_diff(26214) < 70, "{g}");
+  }
+
+  #[test]
+  fn lut_rejects_bad_input() {
+    assert!(matches!(
+      Lut1d::from_curve(&[(0.5, 0.5)]),
+      Err(Error::InvalidArgument(_))
+    ));
+    let two = Lut1d::from_fn(2, 16, |_, v| v);
+    let mut image = ImageBuffer::<u8, 3, false>::empty(1, 1);
+    assert!(matches!(two.apply(&mut image), Err(Error::Channel(_))));
+  }
+}
@@ -0,0 +1,413 @@
+//! Helpers for cleaning up and applying 8-bit masks, such as those produced
+//! by segmentation or matting.
+//!
+//! Masks are single-channel `u8` buffers where `255` selects a pixel fully
+//! and `0` not at all. Operations that need a yes/no answer treat values of
+//! [`THRESHOLD`] and above as selected.
+
+use std::sync::Arc;
+
+use super::{matting::box_mean, transform, ImageOp};
+use crate::{
+  color::RgbaF32,
+  draw::{
+    path::{fill, FillRule, Path},
+    pattern::Pattern,
+  },
+  error::Result,
+  limits::Limits,
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  video::check_dimensions,
+  Image,
+  ImageBuffer,
+};
+
+/// An 8-bit single-channel mask
+pub type Mask = ImageBuffer<u8, 1, false>;
+
+/// Smallest value counted as selected
+pub const THRESHOLD: u8 = 128;
+
+pub(crate) fn selected(mask: &Mask) -> Vec<bool> {
+  mask.components().iter().map(|&v| v >= THRESHOLD).collect()
+}
+
+pub(crate) fn from_values(
+  mask: &Mask,
+  values: impl IntoIterator<Item = u8>,
+) -> Mask {
+  let mut result = mask.clone();
+  for (out, v) in result.components_mut().iter_mut().zip(values) {
+    *out = v;
+  }
+  result
+}
+
+/// Replaces each value with the result of `pick` over the square window of
+/// `radius` around it, clipped to the image
+fn rank_filter(mask: &Mask, radius: usize, pick: fn(u8, u8) -> u8) -> Mask {
+  let (width, height) = (mask.width, mask.height);
+  let data = mask.components();
+  let mut rows = vec![0; data.len()];
+  for y in 0..height {
+    for x in 0..width {
+      let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+      rows[y * width + x] = data[y * width + x0..y * width + x1]
+        .iter()
+        .copied()
+        .reduce(pick)
+        .unwrap_or_default();
+    }
+  }
+  from_values(
+    mask,
+    (0..data.len()).map(|i| {
+      let (x, y) = (i % width, i / width);
+      let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+      (y0..y1)
+        .map(|yy| rows[yy * width + x])
+        .reduce(pick)
+        .unwrap_or_default()
+    }),
+  )
+}
+
+/// Expands the selection by `radius` pixels
+pub fn grow(mask: &Mask, radius: usize) -> Mask {
+  rank_filter(mask, radius, u8::max)
+}
+
+/// Contracts the selection by `radius` pixels
+pub fn shrink(mask: &Mask, radius: usize) -> Mask {
+  rank_filter(mask, radius, u8::min)
+}
+
+/// Softens the edges of the selection over roughly `radius` pixels
+pub fn feather(mask: &Mask, radius: usize) -> Mask {
+  let (width, height) = (mask.width, mask.height);
+  let values: Vec<f64> =
+    mask.components().iter().map(|&v| f64::from(v)).collect();
+  // Two box passes approximate a Gaussian blur
+  let once = box_mean(&values, width, height, radius.div_ceil(2));
+  let twice = box_mean(&once, width, height, radius.div_ceil(2));
+  from_values(mask, twice.into_iter().map(component_from_f64))
+}
+
+/// Swaps selected and unselected
+pub fn invert(mask: &Mask) -> Mask {
+  from_values(mask, mask.components().iter().map(|&v| u8::MAX - v))
+}
+
+/// Selects unselected regions that are entirely enclosed by the selection
+pub fn fill_holes(mask: &Mask) -> Mask {
+  let (width, height) = (mask.width, mask.height);
+  let selected = selected(mask);
+  // Flood the background in from the border; whatever it cannot reach is a
+  // hole
+  let mut outside = vec![false; selected.len()];
+  let mut stack: Vec<usize> = (0..selected.len())
+    .filter(|&i| {
+      let (x, y) = (i % width, i / width);
+      x == 0 || y == 0 || x == width - 1 || y == height - 1
+    })
+    .collect();
+  while let Some(i) = stack.pop() {
+    if selected[i] || outside[i] {
+      continue;
+    }
+    outside[i] = true;
+    let (x, y) = (i % width, i / width);
+    if x > 0 {
+      stack.push(i - 1);
+    }
+    if x + 1 < width {
+      stack.push(i + 1);
+    }
+    if y > 0 {
+      stack.push(i - width);
+    }
+    if y + 1 < height {
+      stack.push(i + width);
+    }
+  }
+  from_values(
+    mask,
+    mask
+      .components()
+      .iter()
+      .zip(&outside)
+      .map(|(&v, &outside)| {
+        if outside || v >= THRESHOLD {
+          v
+        } else {
+          u8::MAX
+        }
+      }),
+  )
+}
+
+/// Keeps only the largest 8-connected selected region, clearing the rest
+pub fn keep_largest_component(mask: &Mask) -> Mask {
+  let (width, height) = (mask.width, mask.height);
+  let selected = selected(mask);
+  let mut label = vec![0usize; selected.len()];
+  let mut sizes = vec![0usize];
+  for start in 0..selected.len() {
+    if !selected[start] || label[start] != 0 {
+      continue;
+    }
+    let id = sizes.len();
+    sizes.push(0);
+    let mut stack = vec![start];
+    label[start] = id;
+    while let Some(i) = stack.pop() {
+      sizes[id] += 1;
+      let (x, y) = (i % width, i / width);
+      for ny in y.saturating_sub(1)..(y + 2).min(height) {
+        for nx in x.saturating_sub(1)..(x + 2).min(width) {
+          let n = ny * width + nx;
+          if selected[n] && label[n] == 0 {
+            label[n] = id;
+            stack.push(n);
+          }
+        }
+      }
+    }
+  }
+  let largest = (1..sizes.len()).max_by_key(|&id| sizes[id]).unwrap_or(0);
+  from_values(
+    mask,
+    mask.components().iter().zip(&label).map(|(&v, &l)| {
+      if l == largest && l != 0 {
+        v
+      } else {
+        0
+      }
+    }),
+  )
+}
+
+fn scale<From: PixelComponent, To: PixelComponent>(value: From) -> To {
+  let from_white = From::WHITE.to_f64().unwrap_or(1.0);
+  let to_white = To::WHITE.to_f64().unwrap_or(1.0);
+  component_from_f64(value.to_f64().unwrap_or_default() / from_white * to_white)
+}
+
+/// Extracts the alpha channel of `image` as a mask
+pub fn from_alpha<T: PixelComponent>(image: &ImageBuffer<T, 4, true>) -> Mask {
+  let mut mask = Mask::empty(image.width, image.height);
+  for (m, pel) in mask.iter_pixels_mut().zip(image.iter_pixels()) {
+    *m = [scale(pel[3])];
+  }
+  mask
+}
+
+/// Replaces the alpha channel of `image` with `mask`
+pub fn set_alpha<T: PixelComponent>(
+  image: &mut ImageBuffer<T, 4, true>,
+  mask: &Mask,
+) -> Result<()> {
+  check_dimensions((image.width, image.height), mask)?;
+  for (pel, m) in image.iter_pixels_mut().zip(mask.iter_pixels()) {
+    pel[3] = scale(m[0]);
+  }
+  Ok(())
+}
+
+/// Combines an RGB image with a mask as its alpha channel
+pub fn with_alpha<T: PixelComponent>(
+  image: &ImageBuffer<T, 3, false>,
+  mask: &Mask,
+) -> Result<ImageBuffer<T, 4, true>> {
+  check_dimensions((image.width, image.height), mask)?;
+  let mut result =
+    ImageBuffer::try_empty(image.width, image.height, &Limits::none())?;
+  for ((out, pel), m) in result
+    .iter_pixels_mut()
+    .zip(image.iter_pixels())
+    .zip(mask.iter_pixels())
+  {
+    *out = [pel[0], pel[1], pel[2], scale(m[0])];
+  }
+  Ok(result)
+}
+
+/// Composites `image` over a checkerboard of `colors`, with squares of
+/// `cell_size` pixels, to show its transparency in previews and thumbnails.
+/// The result is opaque: the alpha of `colors` is ignored.
+pub fn preview_on_checkerboard<T: PixelComponent>(
+  image: &ImageBuffer<T, 4, true>,
+  cell_size: usize,
+  colors: [impl Into<RgbaF32>; 2],
+) -> ImageBuffer<T, 4, true> {
+  let (width, height) = (image.width as f64, image.height as f64);
+  let whole = Path::new()
+    .move_to(0.0, 0.0)
+    .line_to(width, 0.0)
+    .line_to(width, height)
+    .line_to(0.0, height)
+    .close();
+  let [first, second] = colors.map(|c| c.into().with_alpha(1.0));
+  let mut preview = ImageBuffer::empty(image.width, image.height);
+  let checks = Pattern::checkerboard(cell_size, first, second);
+  fill(&mut preview, &whole, checks, FillRule::NonZero);
+  fill(&mut preview, &whole, Pattern::new(image), FillRule::NonZero);
+  preview
+}
+
+/// An [`ImageOp`] confined to a mask; see [`masked`]
+pub struct Masked {
+  name: String,
+  op:   Arc<dyn ImageOp>,
+  mask: Mask,
+}
+
+/// Wraps `op` so that it only changes the pixels selected by `mask`,
+/// blending in proportion to partial selections. A non-zero `feather`
+/// softens the mask edges over that many pixels first.
+///
+/// The mask is stretched to the size of each image the op is applied to, so
+/// the same op works on previews and on full-resolution originals. It fails
+/// if `op` changes the size of the image.
+pub fn masked(op: Arc<dyn ImageOp>, mask: &Mask, feather: usize) -> Masked {
+  Masked {
+    name: format!("masked {}", op.name()),
+    op,
+    mask: if feather > 0 {
+      self::feather(mask, feather)
+    } else {
+      mask.clone()
+    },
+  }
+}
+
+impl ImageOp for Masked {
+  fn name(&self) -> &str { &self.name }
+
+  fn apply(&self, image: &Image) -> Result<Image> {
+    let edited = self.op.apply(image)?;
+    let mut before = image.to_rgba_f32()?;
+    let after = edited.to_rgba_f32()?;
+    check_dimensions((before.width, before.height), &after)?;
+    let mask =
+      if (self.mask.width, self.mask.height) == (before.width, before.height) {
+        self.mask.clone()
+      } else {
+        transform::resize(&self.mask, before.width, before.height)
+      };
+    for ((pel, edit), m) in before
+      .iter_pixels_mut()
+      .zip(after.iter_pixels())
+      .zip(mask.iter_pixels())
+    {
+      let weight = f32::from(m[0]) / 255.0;
+      for (v, e) in pel.iter_mut().zip(edit) {
+        *v += (e - *v) * weight;
+      }
+    }
+    Ok(Image::from_rgba_f32_like(before, &edited))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Error;
+
+  fn mask(rows: &[&str]) -> Mask {
+    let width = rows[0].len();
+    Mask::empty(width, rows.len()).map_indexed(&mut |x, y, _| {
+      [if rows[y].as_bytes()[x] == b'#' {
+        255
+      } else {
+        0
+      }]
+    })
+  }
+
+  #[test]
+  fn mask_cleanup() {
+    let ring =
+      mask(&["#.......", "..####..", "..#..#..", "..####..", "........"]);
+    assert_eq!(
+      fill_holes(&ring).components(),
+      mask(&["#.......", "..####..", "..####..", "..####..", "........"])
+        .components()
+    );
+    let largest = keep_largest_component(&ring);
+    assert_eq!(largest.get_pixel(0, 0), &[0]);
+    assert_eq!(largest.get_pixel(2, 2), &[255]);
+    assert_eq!(shrink(&grow(&ring, 1), 1).get_pixel(3, 2), &[255]);
+    assert_eq!(invert(&ring).get_pixel(3, 2), &[255]);
+
+    let soft = feather(&ring, 2);
+    assert!(soft.get_pixel(3, 1)[0] > 0 && soft.get_pixel(3, 1)[0] < 255);
+  }
+
+  #[test]
+  fn mask_alpha_round_trip() {
+    let rgb = ImageBuffer::<u16, 3, false>::empty(2, 1);
+    let m = mask(&["#."]);
+    let rgba = with_alpha(&rgb, &m).unwrap();
+    assert_eq!(rgba.components(), &[0, 0, 0, 65535, 0, 0, 0, 0]);
+    assert_eq!(from_alpha(&rgba).components(), m.components());
+    let mut rgba = rgba;
+    assert!(matches!(
+      set_alpha(&mut rgba, &Mask::empty(1, 1)),
+      Err(Error::DimensionMismatch { .. })
+    ));
+  }
+
+  #[test]
+  fn previews_transparency_on_a_checkerboard() {
+    let mut image = ImageBuffer::<u8, 4, true>::empty(4, 1);
+    image.components_mut()[8..]
+      .copy_from_slice(&[255, 0, 0, 255, 255, 0, 0, 128]);
+    let colors = [crate::color::Rgba8::WHITE, crate::color::Rgba8::GRAY];
+    let preview = preview_on_checkerboard(&image, 1, colors);
+    assert_eq!(preview.get_pixel(0, 0), &[255, 255, 255, 255]);
+    assert_eq!(preview.get_pixel(1, 0), &[128, 128, 128, 255]);
+    assert_eq!(preview.get_pixel(2, 0), &[255, 0, 0, 255]);
+    assert_eq!(preview.get_pixel(3, 0), &[192, 64, 64, 255]);
+
+    // Translucent checks are drawn opaque, without the image behind them
+    let faint = [
+      crate::color::Rgba8::WHITE.with_alpha(0),
+      crate::color::Rgba8::GRAY.with_alpha(64),
+    ];
+    let preview = preview_on_checkerboard(&image, 100_000, faint);
+    assert_eq!(preview.get_pixel(0, 0), &[255, 255, 255, 255]);
+    assert_eq!(preview.get_pixel(3, 0), &[255, 127, 127, 255]);
+  }
+
+  #[test]
+  fn masked_op_blends_by_selection() {
+    struct Brighten;
+    impl ImageOp for Brighten {
+      fn name(&self) -> &str { "brighten" }
+
+      fn apply(&self, image: &Image) -> Result<Image> {
+        let mut rgba = image.to_rgba_f32()?;
+        rgba.iter_pixels_mut().for_each(|p| p[..3].fill(1.0));
+        Ok(Image::from_rgba_f32_like(rgba, image))
+      }
+    }
+
+    let image = Image::new_u8(crate::color_space::ColorSpace::Rgb(
+      ImageBuffer::empty(4, 2),
+    ));
+    let op = masked(Arc::new(Brighten), &mask(&["##..", "##.."]), 0);
+    assert_eq!(op.name(), "masked brighten");
+    let result = op.apply(&image).unwrap().to_rgba_f32().unwrap();
+    assert_eq!(result.get_pixel(0, 0), &[1.0; 4]);
+    assert_eq!(result.get_pixel(3, 1), &[0.0, 0.0, 0.0, 1.0]);
+
+    // A small mask is stretched over a larger image
+    let big = Image::new_u8(crate::color_space::ColorSpace::Rgb(
+      ImageBuffer::empty(8, 4),
+    ));
+    let result = op.apply(&big).unwrap().to_rgba_f32().unwrap();
+    assert_eq!(result.get_pixel(0, 3)[0], 1.0);
+    assert_eq!(result.get_pixel(7, 0)[0], 0.0);
+  }
+}
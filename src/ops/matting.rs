@@ -0,0 +1,447 @@
+//! Soft foreground extraction from a trimap.
+//!
+//! A trimap is a single-channel plane the size of the image: `0` marks known
+//! background, white marks known foreground, and any other value marks
+//! pixels whose opacity should be estimated. Both matting methods return an
+//! alpha plane with white for opaque foreground, and fail with
+//! [`Error::DimensionMismatch`] for a trimap of another size.
+//!
+//! [`Error::DimensionMismatch`]: crate::Error::DimensionMismatch
+
+use num_traits::ToPrimitive;
+
+use crate::{
+  error::Result,
+  pixel::{component_from_f64, Pixel, PixelComponent, PixelContainer},
+  video::check_dimensions,
+};
+
+/// Weight of the trimap constraints relative to the smoothness term
+const CONSTRAINT_WEIGHT: f64 = 100.0;
+
+/// Regularization of the color model, in normalized units
+const EPSILON: f64 = 1e-5;
+
+fn unit<T: PixelComponent>(value: T) -> f64 {
+  value.to_f64().unwrap_or_default() / T::WHITE.to_f64().unwrap_or(1.0)
+}
+
+/// Color of each pixel, normalized to `[0, 1]`; gray images repeat their
+/// first channel
+fn colors<C: PixelContainer>(image: &C) -> Vec<[f64; 3]> {
+  image
+    .iter_pixels()
+    .map(|pel| {
+      let c = pel.components();
+      if C::NUM_NONALPHA_COMPONENTS >= 3 {
+        [unit(c[0]), unit(c[1]), unit(c[2])]
+      } else {
+        [unit(c[0]); 3]
+      }
+    })
+    .collect()
+}
+
+/// Known alpha per pixel, or `None` where the trimap is unknown
+fn constraints<T: PixelContainer>(trimap: &T) -> Vec<Option<f64>> {
+  trimap
+    .iter_pixels()
+    .map(|pel| {
+      match unit(pel.components()[0]) {
+        v if v <= 0.0 => Some(0.0),
+        v if v >= 1.0 => Some(1.0),
+        _ => None,
+      }
+    })
+    .collect()
+}
+
+fn to_plane<C: PixelContainer>(image: &C, alpha: &[f64]) -> C::OnePlane {
+  let mut result = image.new_plane();
+  let white = C::Component::WHITE.to_f64().unwrap_or(1.0);
+  for (pel, &a) in result.iter_pixels_mut().zip(alpha) {
+    *pel = [component_from_f64(a.clamp(0.0, 1.0) * white)];
+  }
+  result
+}
+
+/// Solves `(L + λD) α = λ b` by conjugate gradients, where `laplacian`
+/// applies `L`, `D` selects the constrained pixels and `b` holds their values
+fn solve(
+  known: &[Option<f64>],
+  iterations: usize,
+  laplacian: impl Fn(&[f64], &mut [f64]),
+) -> Vec<f64> {
+  let n = known.len();
+  let apply = |x: &[f64], out: &mut [f64]| {
+    out.fill(0.0);
+    laplacian(x, out);
+    for ((o, k), xi) in out.iter_mut().zip(known).zip(x) {
+      if k.is_some() {
+        *o += CONSTRAINT_WEIGHT * xi;
+      }
+    }
+  };
+  let rhs: Vec<f64> = known
+    .iter()
+    .map(|k| k.map_or(0.0, |v| CONSTRAINT_WEIGHT * v))
+    .collect();
+
+  let mut x: Vec<f64> = known.iter().map(|k| k.unwrap_or(0.5)).collect();
+  let mut ax = vec![0.0; n];
+  apply(&x, &mut ax);
+  let mut r: Vec<f64> = rhs.iter().zip(&ax).map(|(b, a)| b - a).collect();
+  let mut p = r.clone();
+  let mut rr: f64 = r.iter().map(|v| v * v).sum();
+  let tolerance = 1e-10 * rhs.iter().map(|v| v * v).sum::<f64>().max(1.0);
+
+  for _ in 0..iterations {
+    if rr <= tolerance {
+      break;
+    }
+    apply(&p, &mut ax);
+    let step = rr / p.iter().zip(&ax).map(|(a, b)| a * b).sum::<f64>();
+    for i in 0..n {
+      x[i] += step * p[i];
+      r[i] -= step * ax[i];
+    }
+    let next: f64 = r.iter().map(|v| v * v).sum();
+    for i in 0..n {
+      p[i] = r[i] + next / rr * p[i];
+    }
+    rr = next;
+  }
+  x
+}
+
+fn invert(m: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+  let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+    - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+    + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+  let mut inv = [[0.0; 3]; 3];
+  for (i, row) in inv.iter_mut().enumerate() {
+    for (j, v) in row.iter_mut().enumerate() {
+      let (a, b) = ((j + 1) % 3, (j + 2) % 3);
+      let (c, d) = ((i + 1) % 3, (i + 2) % 3);
+      *v = (m[a][c] * m[b][d] - m[a][d] * m[b][c]) / det;
+    }
+  }
+  inv
+}
+
+/// Estimates alpha with the closed-form matting Laplacian of Levin et al.,
+/// which assumes foreground and background colors are locally linear.
+///
+/// Accurate on smooth regions such as hair against sky, but costs a sparse
+/// solve over the whole image.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(width = image.width(), height = image.height())
+  )
+)]
+pub fn closed_form_matting<C, T>(image: &C, trimap: &T) -> Result<C::OnePlane>
+where
+  C: PixelContainer,
+  T: PixelContainer<Component = C::Component>,
+{
+  let (width, height) = (image.width(), image.height());
+  check_dimensions((width, height), trimap)?;
+  let color = colors(image);
+  let known = constraints(trimap);
+
+  // Mean and inverse regularized covariance of every 3x3 window that
+  // touches an unknown pixel
+  let mut windows = Vec::new();
+  for y in 1..height.saturating_sub(1) {
+    for x in 1..width.saturating_sub(1) {
+      let idx: Vec<usize> = (0..9)
+        .map(|k| (y + k / 3 - 1) * width + x + k % 3 - 1)
+        .collect();
+      if idx.iter().all(|&i| known[i].is_some()) {
+        continue;
+      }
+      let mut mean = [0.0; 3];
+      for &i in &idx {
+        for c in 0..3 {
+          mean[c] += color[i][c] / 9.0;
+        }
+      }
+      let mut cov = [[0.0; 3]; 3];
+      for &i in &idx {
+        for a in 0..3 {
+          for b in 0..3 {
+            cov[a][b] +=
+              (color[i][a] - mean[a]) * (color[i][b] - mean[b]) / 9.0;
+          }
+        }
+      }
+      for (c, row) in cov.iter_mut().enumerate() {
+        row[c] += EPSILON / 9.0;
+      }
+      windows.push((idx, mean, invert(cov)));
+    }
+  }
+
+  let alpha = solve(&known, 4 * known.len(), |x, out| {
+    for (idx, mean, inv) in &windows {
+      let sum: f64 = idx.iter().map(|&j| x[j]).sum();
+      let mut v = [0.0; 3];
+      for &j in idx {
+        for c in 0..3 {
+          v[c] += (color[j][c] - mean[c]) * x[j];
+        }
+      }
+      let w: Vec<f64> = (0..3)
+        .map(|a| (0..3).map(|b| inv[a][b] * v[b]).sum())
+        .collect();
+      for &i in idx {
+        let d: f64 = (0..3).map(|c| (color[i][c] - mean[c]) * w[c]).sum();
+        out[i] += x[i] - (sum + d) / 9.0;
+      }
+    }
+  });
+  Ok(to_plane(image, &alpha))
+}
+
+/// Estimates alpha with KNN matting (Chen et al.), which ties each pixel to
+/// the pixels nearby that are most similar in color and position.
+///
+/// Neighbors are searched within `radius` pixels. Works well for mattes
+/// with holes or disconnected regions, where [`closed_form_matting`]'s local
+/// assumption breaks down.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(width = image.width(), height = image.height(), radius)
+  )
+)]
+pub fn knn_matting<C, T>(
+  image: &C,
+  trimap: &T,
+  radius: usize,
+) -> Result<C::OnePlane>
+where
+  C: PixelContainer,
+  T: PixelContainer<Component = C::Component>,
+{
+  const NEIGHBORS: usize = 10;
+  const SPATIAL_WEIGHT: f64 = 0.5;
+
+  let (width, height) = (image.width(), image.height());
+  check_dimensions((width, height), trimap)?;
+  let color = colors(image);
+  let known = constraints(trimap);
+  let scale = SPATIAL_WEIGHT / width.max(height).max(1) as f64;
+  let feature = |i: usize| {
+    let [r, g, b] = color[i];
+    [
+      r,
+      g,
+      b,
+      (i % width) as f64 * scale,
+      (i / width) as f64 * scale,
+    ]
+  };
+  let max_distance = (3.0 + 2.0 * SPATIAL_WEIGHT * SPATIAL_WEIGHT).sqrt();
+  let r = radius.max(1) as isize;
+
+  let mut edges = Vec::with_capacity(color.len() * NEIGHBORS);
+  let mut candidates = Vec::new();
+  for i in 0..color.len() {
+    let (x, y) = ((i % width) as isize, (i / width) as isize);
+    let fi = feature(i);
+    candidates.clear();
+    for ny in (y - r).max(0)..(y + r + 1).min(height as isize) {
+      for nx in (x - r).max(0)..(x + r + 1).min(width as isize) {
+        let j = ny as usize * width + nx as usize;
+        if j != i {
+          let fj = feature(j);
+          let d: f64 = fi.iter().zip(&fj).map(|(a, b)| (a - b).powi(2)).sum();
+          candidates.push((d.sqrt(), j));
+        }
+      }
+    }
+    let k = NEIGHBORS.min(candidates.len());
+    if k > 0 {
+      candidates.select_nth_unstable_by(k - 1, |a, b| a.0.total_cmp(&b.0));
+    }
+    for &(d, j) in &candidates[..k] {
+      edges.push((i, j, (1.0 - d / max_distance).max(0.0)));
+    }
+  }
+
+  let alpha = solve(&known, 4 * known.len(), |x, out| {
+    for &(i, j, a) in &edges {
+      let d = a * (x[i] - x[j]);
+      out[i] += d;
+      out[j] -= d;
+    }
+  });
+  Ok(to_plane(image, &alpha))
+}
+
+/// Mean of `values` over the `(2 * radius + 1)` square window around each
+/// pixel, clipped to the image
+pub(crate) fn box_mean(
+  values: &[f64],
+  width: usize,
+  height: usize,
+  radius: usize,
+) -> Vec<f64> {
+  let mut integral = vec![0.0; (width + 1) * (height + 1)];
+  for y in 0..height {
+    let mut row = 0.0;
+    for x in 0..width {
+      row += values[y * width + x];
+      integral[(y + 1) * (width + 1) + x + 1] =
+        integral[y * (width + 1) + x + 1] + row;
+    }
+  }
+  let mut result = Vec::with_capacity(values.len());
+  for y in 0..height {
+    let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+    for x in 0..width {
+      let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+      let at = |x: usize, y: usize| integral[y * (width + 1) + x];
+      let sum = at(x1, y1) - at(x0, y1) - at(x1, y0) + at(x0, y0);
+      result.push(sum / ((x1 - x0) * (y1 - y0)) as f64);
+    }
+  }
+  result
+}
+
+/// Refines a rough mask so that its edges follow edges in `image`, using the
+/// guided filter of He et al. with the image's luminance as the guide.
+///
+/// `radius` sets the size of the window edges are matched over; larger
+/// values recover more detail from a coarser mask. Fails if `mask` is not
+/// the size of `image`.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(width = image.width(), height = image.height(), radius)
+  )
+)]
+pub fn refine_mask<C, T>(
+  image: &C,
+  mask: &T,
+  radius: usize,
+) -> Result<C::OnePlane>
+where
+  C: PixelContainer,
+  T: PixelContainer<Component = C::Component>,
+{
+  const GUIDE_EPSILON: f64 = 1e-4;
+
+  let (width, height) = (image.width(), image.height());
+  check_dimensions((width, height), mask)?;
+  let guide: Vec<f64> = colors(image)
+    .iter()
+    .map(|[r, g, b]| 0.2126 * r + 0.7152 * g + 0.0722 * b)
+    .collect();
+  let p: Vec<f64> = mask
+    .iter_pixels()
+    .map(|pel| unit(pel.components()[0]))
+    .collect();
+  let product = |a: &[f64], b: &[f64]| -> Vec<f64> {
+    a.iter().zip(b).map(|(a, b)| a * b).collect()
+  };
+
+  let mean_i = box_mean(&guide, width, height, radius);
+  let mean_p = box_mean(&p, width, height, radius);
+  let mean_ii = box_mean(&product(&guide, &guide), width, height, radius);
+  let mean_ip = box_mean(&product(&guide, &p), width, height, radius);
+
+  let mut a = Vec::with_capacity(p.len());
+  let mut b = Vec::with_capacity(p.len());
+  for i in 0..p.len() {
+    let var = mean_ii[i] - mean_i[i] * mean_i[i];
+    let cov = mean_ip[i] - mean_i[i] * mean_p[i];
+    let ai = cov / (var + GUIDE_EPSILON);
+    a.push(ai);
+    b.push(mean_p[i] - ai * mean_i[i]);
+  }
+  let mean_a = box_mean(&a, width, height, radius);
+  let mean_b = box_mean(&b, width, height, radius);
+
+  let refined: Vec<f64> = (0..p.len())
+    .map(|i| mean_a[i] * guide[i] + mean_b[i])
+    .collect();
+  Ok(to_plane(image, &refined))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ImageBuffer;
+
+  /// Red on the left half, blue on the right, with only the outer columns
+  /// known in the trimap
+  fn scene() -> (ImageBuffer<u8, 3, false>, ImageBuffer<u8, 1, false>) {
+    let image =
+      ImageBuffer::<u8, 3, false>::empty(8, 5).map_indexed(&mut |x, _, _| {
+        if x < 4 {
+          [220, 20, 30]
+        } else {
+          [10, 40, 230]
+        }
+      });
+    let trimap =
+      ImageBuffer::<u8, 1, false>::empty(8, 5).map_indexed(&mut |x, _, _| {
+        match x {
+          0 => [255],
+          7 => [0],
+          _ => [128],
+        }
+      });
+    (image, trimap)
+  }
+
+  fn check_alpha(alpha: &ImageBuffer<u8, 1, false>) {
+    for y in 0..5 {
+      for x in 0..8 {
+        let a = alpha.get_pixel(x, y)[0];
+        if x < 4 {
+          assert!(a > 230, "Alpha at ({x}, {y}) is {a}");
+        } else {
+          assert!(a < 25, "Alpha at ({x}, {y}) is {a}");
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn closed_form_and_knn_matting_separate_colors() {
+    let (image, trimap) = scene();
+    check_alpha(&closed_form_matting(&image, &trimap).unwrap());
+    check_alpha(&knn_matting(&image, &trimap, 4).unwrap());
+    let small = ImageBuffer::<u8, 1, false>::empty(4, 5);
+    assert!(matches!(
+      closed_form_matting(&image, &small),
+      Err(crate::Error::DimensionMismatch { .. })
+    ));
+    assert!(knn_matting(&image, &small, 4).is_err());
+    assert!(refine_mask(&image, &small, 2).is_err());
+  }
+
+  #[test]
+  fn refine_mask_snaps_to_image_edge() {
+    let (image, _) = scene();
+    // Rough mask whose edge is one column off
+    let mask = ImageBuffer::<u8, 1, false>::empty(8, 5)
+      .map_indexed(&mut |x, _, _| [if x < 5 { 255 } else { 0 }]);
+    let refined = refine_mask(&image, &mask, 2).unwrap();
+    // The wrongly included column drops sharply while the true foreground
+    // next to it stays nearly opaque
+    assert!(refined.get_pixel(4, 2)[0] < 128);
+    assert!(refined.get_pixel(3, 2)[0] > 240);
+    assert!(refined.get_pixel(7, 2)[0] < 64);
+  }
+}
@@ -0,0 +1,204 @@
+use num_traits::ToPrimitive;
+
+use crate::pixel::{component_from_f64, Pixel, PixelComponent, PixelContainer};
+
+/// Target exposure for the metered luminance, as a fraction of white
+/// (photographic middle gray)
+pub const MIDDLE_GRAY: f64 = 0.18;
+
+/// Share of the brightest pixels averaged to estimate the white point
+const WHITE_POINT_FRACTION: f64 = 0.05;
+
+/// A weighted rectangle used for spot metering
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeteringRegion {
+  pub x:      usize,
+  pub y:      usize,
+  pub width:  usize,
+  pub height: usize,
+  /// Relative importance of this region when several are metered together
+  pub weight: f64,
+}
+
+/// Result of [`meter`]. Luminance values are relative, with `1.0` being white.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metering {
+  /// Mean luminance over the whole frame
+  pub average:         f64,
+  /// Mean luminance weighted towards the center of the frame
+  pub center_weighted: f64,
+  /// Weighted mean luminance over the metering regions, or over the central
+  /// tenth of the frame when none are given
+  pub spot:            f64,
+  /// Stops of exposure that would bring the spot reading to
+  /// [`MIDDLE_GRAY`]. Zero for an all-black frame.
+  pub ev:              f64,
+  /// Mean of each color channel over the brightest pixels, relative to white
+  pub white_point:     Vec<f64>,
+}
+
+fn normalized<T: PixelComponent>(value: T) -> f64 {
+  value.to_f64().unwrap_or_default() / T::WHITE.to_f64().unwrap_or(1.0)
+}
+
+/// Relative luminance of one pixel, using Rec. 709 weights for color images
+/// and the first channel otherwise
+pub(crate) fn luminance<C: PixelContainer>(pel: &C::OnePixel) -> f64 {
+  let c = pel.components();
+  if C::NUM_NONALPHA_COMPONENTS >= 3 {
+    0.2126 * normalized(c[0])
+      + 0.7152 * normalized(c[1])
+      + 0.0722 * normalized(c[2])
+  } else {
+    normalized(c[0])
+  }
+}
+
+/// Measures the brightness of `image` in the ways a camera meters a scene.
+///
+/// Spot metering uses `regions`, clipped to the image; regions with no area
+/// or weight are ignored.
+pub fn meter<C: PixelContainer>(
+  image: &C,
+  regions: &[MeteringRegion],
+) -> Metering {
+  let (width, height) = (image.width(), image.height());
+  let mut lum = Vec::with_capacity(width * height);
+  let mut total = 0.0;
+  let mut center_sum = 0.0;
+  let mut center_weight = 0.0;
+  let (cx, cy) = ((width as f64 - 1.0) / 2.0, (height as f64 - 1.0) / 2.0);
+  let radius_sq = (cx * cx + cy * cy).max(1.0);
+
+  for (i, pel) in image.iter_pixels().enumerate() {
+    let l = luminance::<C>(pel);
+    let (dx, dy) = ((i % width) as f64 - cx, (i / width) as f64 - cy);
+    let w = 1.0 - (dx * dx + dy * dy) / radius_sq + 0.1;
+    total += l;
+    center_sum += l * w;
+    center_weight += w;
+    lum.push(l);
+  }
+
+  if lum.is_empty() {
+    return Metering {
+      average:         0.0,
+      center_weighted: 0.0,
+      spot:            0.0,
+      ev:              0.0,
+      white_point:     vec![0.0; C::NUM_NONALPHA_COMPONENTS],
+    };
+  }
+
+  let default_spot = [MeteringRegion {
+    x:      width * 9 / 20,
+    y:      height * 9 / 20,
+    width:  (width / 10).max(1),
+    height: (height / 10).max(1),
+    weight: 1.0,
+  }];
+  let regions = if regions.is_empty() {
+    &default_spot[..]
+  } else {
+    regions
+  };
+  let mut spot_sum = 0.0;
+  let mut spot_weight = 0.0;
+  for r in regions.iter().filter(|r| r.weight > 0.0) {
+    let (x1, y1) = ((r.x + r.width).min(width), (r.y + r.height).min(height));
+    let area = x1.saturating_sub(r.x) * y1.saturating_sub(r.y);
+    if area == 0 {
+      continue;
+    }
+    let sum: f64 = (r.y..y1)
+      .flat_map(|y| lum[y * width + r.x..y * width + x1].iter())
+      .sum();
+    spot_sum += r.weight * sum / area as f64;
+    spot_weight += r.weight;
+  }
+  let spot = if spot_weight > 0.0 {
+    spot_sum / spot_weight
+  } else {
+    center_sum / center_weight
+  };
+
+  let mut order: Vec<usize> = (0..lum.len()).collect();
+  order.sort_by(|&a, &b| lum[b].total_cmp(&lum[a]));
+  let brightest =
+    ((lum.len() as f64 * WHITE_POINT_FRACTION).ceil() as usize).max(1);
+  let mut white_point = vec![0.0; C::NUM_NONALPHA_COMPONENTS];
+  for &i in &order[..brightest] {
+    let pel = &image.components()[i * C::NUM_COMPONENTS..];
+    for (acc, &c) in white_point.iter_mut().zip(pel) {
+      *acc += normalized(c) / brightest as f64;
+    }
+  }
+
+  Metering {
+    average: total / lum.len() as f64,
+    center_weighted: center_sum / center_weight,
+    spot,
+    ev: if spot > 0.0 {
+      (MIDDLE_GRAY / spot).log2()
+    } else {
+      0.0
+    },
+    white_point,
+  }
+}
+
+/// Scales the color channels of `image` by `2^ev`, as if the exposure had
+/// been changed by `ev` stops. Integer components saturate at white.
+pub fn apply_ev<C: PixelContainer>(image: &mut C, ev: f64) {
+  let factor = ev.exp2();
+  for pel in image.components_mut().chunks_exact_mut(C::NUM_COMPONENTS) {
+    for c in &mut pel[..C::NUM_NONALPHA_COMPONENTS] {
+      *c = component_from_f64(c.to_f64().unwrap_or_default() * factor);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ImageBuffer;
+
+  #[test]
+  fn meter_spot_region_and_ev_gray_u8() {
+    let image = ImageBuffer::<u8, 1, false>::empty(4, 4)
+      .map_indexed(&mut |x, _, _| [if x < 2 { 0 } else { 255 }]);
+    let left = MeteringRegion {
+      x:      0,
+      y:      0,
+      width:  2,
+      height: 4,
+      weight: 1.0,
+    };
+    let right = MeteringRegion {
+      x: 2,
+      ..left
+    };
+
+    let metering = meter(&image, &[right]);
+    assert_eq!(metering.average, 0.5);
+    assert!((metering.center_weighted - 0.5).abs() < 1e-12);
+    assert_eq!(metering.spot, 1.0);
+    assert_eq!(metering.ev, MIDDLE_GRAY.log2());
+    assert_eq!(metering.white_point, vec![1.0]);
+    assert_eq!(meter(&image, &[left]).ev, 0.0);
+    assert_eq!(meter(&image, &[left, right]).spot, 0.5);
+  }
+
+  #[test]
+  fn apply_ev_saturates_and_keeps_alpha_rgba_u8() {
+    let mut image =
+      ImageBuffer::<u8, 4, true>::with_val(&[10, 100, 200, 7], 1, 1);
+    apply_ev(&mut image, 1.0);
+    assert_eq!(image.get_pixel(0, 0), &[20, 200, 255, 7]);
+
+    let mut image =
+      ImageBuffer::<f32, 3, false>::with_val(&[0.5, 1.0, 2.0], 1, 1);
+    apply_ev(&mut image, -1.0);
+    assert_eq!(image.get_pixel(0, 0), &[0.25, 0.5, 1.0]);
+  }
+}
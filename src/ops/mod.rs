@@ -0,0 +1,55 @@
+//! Image processing operations.
+//!
+//! Operations are free functions generic over [`PixelContainer`], so they work
+//! on every buffer type in the crate. Unless noted otherwise, the alpha
+//! channel is passed through unchanged.
+//!
+//! Operations on [`Image`]s can also be registered by name with
+//! [`register_op`], so that other crates can add filters that are found at
+//! runtime.
+//!
+//! [`PixelContainer`]: crate::PixelContainer
+//! [`Image`]: crate::Image
+
+pub mod histogram;
+pub mod analysis;
+pub mod blend;
+pub mod blur;
+pub mod burst;
+pub mod color_transfer;
+pub mod colormap;
+pub mod compare;
+pub mod contours;
+pub mod depth;
+pub mod document;
+pub mod expr;
+pub mod focus_stack;
+pub mod inspect;
+pub mod meter;
+pub mod lut;
+pub mod mask;
+pub mod matting;
+pub mod morphology;
+pub mod nine_patch;
+pub mod ocr_prep;
+pub mod patch_match;
+pub mod point;
+pub mod progressive;
+pub mod redact;
+pub mod register;
+mod registry;
+pub mod resize;
+pub mod sprites;
+pub mod stereo;
+pub mod style;
+pub mod texture;
+pub mod transform;
+
+pub use burst::burst_merge;
+pub use colormap::{colormap, ColorMap};
+pub use focus_stack::focus_stack;
+pub use inspect::zoom_nn;
+pub use mask::{masked, preview_on_checkerboard};
+pub use nine_patch::nine_patch;
+pub use registry::{find_op, op_names, register_op, ImageOp};
+pub use resize::ResampleOptions;
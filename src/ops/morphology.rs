@@ -0,0 +1,246 @@
+//! Reducing shapes in binary masks to their one-pixel-wide skeletons, for
+//! tracing strokes, roads and other thin structures.
+//!
+//! Masks follow the conventions of [`mask`](super::mask). Pixels outside
+//! the image count as unselected, and skeletons are 8-connected.
+
+use super::mask::{from_values, selected, Mask};
+use crate::{pixel::PixelContainer, ImageBuffer};
+
+/// A skeleton from [`skeletonize`] and the distance transform it was
+/// derived from
+#[derive(Clone, Debug)]
+pub struct MedialAxis {
+  pub skeleton: Mask,
+  /// Euclidean distance from each selected pixel to the nearest unselected
+  /// one, and zero elsewhere. Along the skeleton it is half the width of
+  /// the shape there.
+  pub distance: ImageBuffer<f32, 1, false>,
+}
+
+/// The selection of the 8 neighbors of `i`, clockwise from north:
+/// N, NE, E, SE, S, SW, W, NW
+fn neighbors(
+  pixels: &[bool],
+  width: usize,
+  height: usize,
+  i: usize,
+) -> [bool; 8] {
+  let (x, y) = ((i % width) as isize, (i / width) as isize);
+  [
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+  ]
+  .map(|(dx, dy)| {
+    let (nx, ny) = (x + dx, y + dy);
+    nx >= 0
+      && ny >= 0
+      && (nx as usize) < width
+      && (ny as usize) < height
+      && pixels[ny as usize * width + nx as usize]
+  })
+}
+
+/// Number of unselected to selected transitions going once around
+fn transitions(n: &[bool; 8]) -> usize {
+  (0..8).filter(|&k| !n[k] && n[(k + 1) % 8]).count()
+}
+
+/// Thins the selection to a skeleton with the Zhang-Suen algorithm, which
+/// peels pixels off the boundary in alternating passes from the south-east
+/// and north-west until none can be removed without breaking a shape
+/// apart or shortening a stroke.
+pub fn thin(mask: &Mask) -> Mask {
+  let (width, height) = (mask.width, mask.height);
+  let mut pixels = selected(mask);
+  loop {
+    let mut changed = false;
+    for pass in 0..2 {
+      let removed: Vec<usize> = (0..pixels.len())
+        .filter(|&i| {
+          if !pixels[i] {
+            return false;
+          }
+          let n = neighbors(&pixels, width, height, i);
+          let count = n.iter().filter(|&&v| v).count();
+          let [north, _, east, _, south, _, west, _] = n;
+          let sides = if pass == 0 {
+            !(north && east && south) && !(east && south && west)
+          } else {
+            !(north && east && west) && !(north && south && west)
+          };
+          (2..=6).contains(&count) && transitions(&n) == 1 && sides
+        })
+        .collect();
+      changed |= !removed.is_empty();
+      for i in removed {
+        pixels[i] = false;
+      }
+    }
+    if !changed {
+      break;
+    }
+  }
+  from_values(mask, pixels.iter().map(|&on| if on { u8::MAX } else { 0 }))
+}
+
+/// Reduces the selection to its medial axis: the ridge of its distance
+/// transform, the centers of the largest circles that fit inside it.
+///
+/// Pixels are removed in order of increasing distance from the boundary,
+/// each only if that keeps the shape's topology and it is not the end of a
+/// stroke, so the skeleton stays connected and centered. Unlike [`thin`],
+/// the returned distances give the shape's width along the skeleton.
+pub fn skeletonize(mask: &Mask) -> MedialAxis {
+  let (width, height) = (mask.width, mask.height);
+  let mut pixels = selected(mask);
+  let distance = distance_transform(&pixels, width, height);
+
+  let mut order: Vec<usize> =
+    (0..pixels.len()).filter(|&i| pixels[i]).collect();
+  order.sort_by(|&a, &b| distance[a].total_cmp(&distance[b]));
+  for i in order {
+    let n = neighbors(&pixels, width, height, i);
+    if n.iter().filter(|&&v| v).count() > 1 && is_simple(&n) {
+      pixels[i] = false;
+    }
+  }
+
+  let mut map = ImageBuffer::empty(width, height);
+  for (out, d) in map.components_mut().iter_mut().zip(&distance) {
+    *out = *d as f32;
+  }
+  MedialAxis {
+    skeleton: from_values(
+      mask,
+      pixels.iter().map(|&on| if on { u8::MAX } else { 0 }),
+    ),
+    distance: map,
+  }
+}
+
+/// Whether removing the center pixel keeps the same 8-connected shapes and
+/// 4-connected holes: Yokoi's 8-connectivity number is one
+fn is_simple(n: &[bool; 8]) -> bool {
+  // Starting from east and going counter-clockwise, as Yokoi numbers them
+  let x = [n[2], n[1], n[0], n[7], n[6], n[5], n[4], n[3]].map(|v| !v);
+  let connectivity: i32 = [0, 2, 4, 6]
+    .iter()
+    .map(|&k| i32::from(x[k]) - i32::from(x[k] && x[k + 1] && x[(k + 2) % 8]))
+    .sum();
+  connectivity == 1
+}
+
+/// Exact Euclidean distance from each selected pixel to the nearest
+/// unselected one, counting the outside of the image as unselected, by
+/// Felzenszwalb and Huttenlocher's separable lower-envelope algorithm
+fn distance_transform(
+  pixels: &[bool],
+  width: usize,
+  height: usize,
+) -> Vec<f64> {
+  // Pad with a ring of unselected pixels
+  let (w, h) = (width + 2, height + 2);
+  let mut squared = vec![0.0; w * h];
+  for y in 0..height {
+    for x in 0..width {
+      if pixels[y * width + x] {
+        squared[(y + 1) * w + x + 1] = f64::INFINITY;
+      }
+    }
+  }
+  let mut line = Vec::new();
+  for x in 0..w {
+    line.clear();
+    line.extend((0..h).map(|y| squared[y * w + x]));
+    for (y, d) in lower_envelope(&line).into_iter().enumerate() {
+      squared[y * w + x] = d;
+    }
+  }
+  for y in 0..h {
+    let row = &mut squared[y * w..(y + 1) * w];
+    let envelope = lower_envelope(row);
+    row.copy_from_slice(&envelope);
+  }
+  (0..width * height)
+    .map(|i| squared[(i / width + 1) * w + i % width + 1].sqrt())
+    .collect()
+}
+
+/// The one-dimensional squared distance transform of `f`: for each `q`,
+/// the minimum over `p` of `(q - p)² + f(p)`
+fn lower_envelope(f: &[f64]) -> Vec<f64> {
+  let n = f.len();
+  let mut sites: Vec<usize> = Vec::with_capacity(n);
+  let mut starts: Vec<f64> = Vec::with_capacity(n);
+  let intersection = |p: usize, q: usize| {
+    ((f[q] + (q * q) as f64) - (f[p] + (p * p) as f64))
+      / (2.0 * (q as f64 - p as f64))
+  };
+  for q in (0..n).filter(|&q| f[q].is_finite()) {
+    while let Some(&p) = sites.last() {
+      let s = intersection(p, q);
+      if s <= *starts.last().unwrap_or(&f64::NEG_INFINITY) {
+        sites.pop();
+        starts.pop();
+      } else {
+        sites.push(q);
+        starts.push(s);
+        break;
+      }
+    }
+    if sites.is_empty() {
+      sites.push(q);
+      starts.push(f64::NEG_INFINITY);
+    }
+  }
+  let mut k = 0;
+  (0..n)
+    .map(|q| {
+      if sites.is_empty() {
+        return f64::INFINITY;
+      }
+      while k + 1 < sites.len() && starts[k + 1] < q as f64 {
+        k += 1;
+      }
+      let p = sites[k];
+      (q as f64 - p as f64).powi(2) + f[p]
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ops::mask::keep_largest_component;
+
+  #[test]
+  fn skeletons_are_thin_connected_and_centered() {
+    // A thick L shape
+    let mask = Mask::empty(40, 30).map_indexed(&mut |x, y, _| {
+      let horizontal = (5..35).contains(&x) && (20..27).contains(&y);
+      let vertical = (5..12).contains(&x) && (3..27).contains(&y);
+      [if horizontal || vertical { 255 } else { 0 }]
+    });
+    let medial = skeletonize(&mask);
+    for skeleton in [thin(&mask), medial.skeleton.clone()] {
+      let pixels = selected(&skeleton);
+      // One pixel wide along the straight runs, and through the middle
+      assert_eq!((20..27).filter(|&y| pixels[y * 40 + 25]).count(), 1);
+      assert!(pixels[23 * 40 + 25]);
+      assert_eq!((5..12).filter(|&x| pixels[10 * 40 + x]).count(), 1);
+      assert!(pixels[10 * 40 + 8]);
+      // Still one piece
+      let kept = keep_largest_component(&skeleton);
+      assert_eq!(kept.components(), skeleton.components());
+    }
+    assert_eq!(medial.distance.get_pixel(25, 23), &[4.0]);
+    assert_eq!(medial.distance.get_pixel(0, 0), &[0.0]);
+  }
+}
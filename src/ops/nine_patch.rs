@@ -0,0 +1,206 @@
+//! Nine-patch scaling, for skins of user interface elements: the corners
+//! keep their size, the edges stretch along their length and the center
+//! stretches both ways.
+
+use crate::{
+  error::{Error, Result},
+  limits::Limits,
+  ops::transform::{crop, resize},
+  pixel::{PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// Widths of the borders of a nine-patch, in pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Insets {
+  pub left:   usize,
+  pub top:    usize,
+  pub right:  usize,
+  pub bottom: usize,
+}
+
+/// Scales `image` to `width` by `height`, keeping the corners outside
+/// `insets` at their original size. Fails if the insets overlap within the
+/// image or do not fit in the new size.
+pub fn nine_patch<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  insets: Insets,
+  width: usize,
+  height: usize,
+) -> Result<ImageBuffer<T, N, A>> {
+  let fits = |start: usize, end: usize, len: usize| {
+    start.checked_add(end).is_some_and(|sum| sum <= len)
+  };
+  if !fits(insets.left, insets.right, image.width.min(width))
+    || !fits(insets.top, insets.bottom, image.height.min(height))
+  {
+    return Err(Error::InvalidArgument(format!(
+      "Insets of {insets:?} do not fit a {}x{} image scaled to \
+       {width}x{height}",
+      image.width, image.height
+    )));
+  }
+  // Start and length of each band, in the source and the result
+  let bands = |before: usize, after: usize, from: usize, to: usize| {
+    [
+      ((0, before), (0, before)),
+      (
+        (before, from - before - after),
+        (before, to - before - after),
+      ),
+      ((from - after, after), (to - after, after)),
+    ]
+  };
+  let columns = bands(insets.left, insets.right, image.width, width);
+  let rows = bands(insets.top, insets.bottom, image.height, height);
+
+  let mut result = ImageBuffer::try_empty(width, height, &Limits::none())?;
+  for ((sy, sh), (dy, dh)) in rows {
+    for ((sx, sw), (dx, dw)) in columns {
+      if dw == 0 || dh == 0 {
+        continue;
+      }
+      let piece = crop(image, sx, sy, sw, sh)?;
+      let piece = if (sw, sh) == (dw, dh) {
+        piece
+      } else if sw == 0 || sh == 0 {
+        // Nothing to stretch; leave the band empty
+        continue;
+      } else {
+        resize(&piece, dw, dh)
+      };
+      for (row, src) in piece.components().chunks_exact(dw * N).enumerate() {
+        let start = ((dy + row) * width + dx) * N;
+        result.components_mut()[start..start + dw * N].copy_from_slice(src);
+      }
+    }
+  }
+  Ok(result)
+}
+
+/// An image in the Android `.9.png` format, with its one-pixel frame of
+/// markers read and removed
+#[derive(Clone, Debug)]
+pub struct NinePatch {
+  pub image:   ImageBuffer<u8, 4, true>,
+  /// The borders outside the stretchable area, marked on the top and left
+  /// of the frame
+  pub insets:  Insets,
+  /// The borders outside the area for content, such as a button's label,
+  /// marked on the bottom and right of the frame
+  pub content: Option<Insets>,
+}
+
+impl NinePatch {
+  /// Reads the markers from the frame of `image`. Markers are opaque black
+  /// pixels; where a side marks several stretchable segments, they are
+  /// treated as one running from the first to the last. Fails if the image
+  /// has no room for a frame or the top and left sides have no markers.
+  pub fn from_android(image: &ImageBuffer<u8, 4, true>) -> Result<NinePatch> {
+    if image.width < 3 || image.height < 3 {
+      return Err(Error::InvalidArgument(
+        "A nine-patch needs a one-pixel frame around the image".to_string(),
+      ));
+    }
+    let (width, height) = (image.width - 2, image.height - 2);
+    let marked = |x: usize, y: usize| image.get_pixel(x, y) == &[0, 0, 0, 255];
+    // Insets before and after the marked span of one side of the frame
+    let span = |len: usize, at: &dyn Fn(usize) -> bool| {
+      let first = (0..len).find(|&i| at(i + 1))?;
+      let last = (0..len).rev().find(|&i| at(i + 1))?;
+      Some((first, len - 1 - last))
+    };
+    let horizontal = span(width, &|x| marked(x, 0));
+    let vertical = span(height, &|y| marked(0, y));
+    let (Some((left, right)), Some((top, bottom))) = (horizontal, vertical)
+    else {
+      return Err(Error::InvalidArgument(
+        "The nine-patch marks no stretchable area".to_string(),
+      ));
+    };
+    let content = span(width, &|x| marked(x, height + 1))
+      .zip(span(height, &|y| marked(width + 1, y)))
+      .map(|((left, right), (top, bottom))| {
+        Insets {
+          left,
+          top,
+          right,
+          bottom,
+        }
+      });
+    Ok(NinePatch {
+      image: crop(image, 1, 1, width, height)?,
+      insets: Insets {
+        left,
+        top,
+        right,
+        bottom,
+      },
+      content,
+    })
+  }
+
+  /// [`nine_patch`] of the image to `width` by `height`
+  pub fn scale(
+    &self,
+    width: usize,
+    height: usize,
+  ) -> Result<ImageBuffer<u8, 4, true>> {
+    nine_patch(&self.image, self.insets, width, height)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn corners_keep_their_size() {
+    // A 6x6 frame of 1s, 2 wide, around a center of 9s
+    let image =
+      ImageBuffer::<u8, 1, false>::empty(6, 6).map_indexed(&mut |x, y, _| {
+        [if (2..4).contains(&x) && (2..4).contains(&y) {
+          9
+        } else {
+          1
+        }]
+      });
+    let insets = Insets {
+      left:   2,
+      top:    2,
+      right:  2,
+      bottom: 2,
+    };
+    let scaled = nine_patch(&image, insets, 10, 7).unwrap();
+    assert_eq!((scaled.width, scaled.height), (10, 7));
+    assert_eq!(scaled.get_pixel(1, 1), &[1]);
+    assert_eq!(scaled.get_pixel(8, 5), &[1]);
+    assert_eq!(scaled.get_pixel(5, 3), &[9]);
+    assert_eq!(scaled.get_pixel(5, 1), &[1]);
+    assert!(nine_patch(&image, insets, 3, 8).is_err());
+
+    // Markers on a 5x4 image, stretching column 2 and row 1, with content
+    // in columns 1..4
+    let black = [0, 0, 0, 255];
+    let framed = ImageBuffer::<u8, 4, true>::with_val(&[200; 4], 7, 6)
+      .map_indexed(&mut |x, y, pel| {
+        match (x, y) {
+          (3, 0) | (0, 2) | (2..=4, 5) | (6, 1..=4) => black,
+          _ => *pel,
+        }
+      });
+    let patch = NinePatch::from_android(&framed).unwrap();
+    assert_eq!((patch.image.width, patch.image.height), (5, 4));
+    assert_eq!(
+      patch.insets,
+      Insets {
+        left:   2,
+        top:    1,
+        right:  2,
+        bottom: 2,
+      }
+    );
+    assert_eq!(patch.content.unwrap().left, 1);
+    assert_eq!(patch.scale(9, 9).unwrap().width, 9);
+  }
+}
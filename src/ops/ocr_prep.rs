@@ -0,0 +1,315 @@
+//! Preparing page images for OCR engines such as Tesseract, which read
+//! best from straight, high-contrast, black-on-white text without stray
+//! specks, one line or word at a time.
+//!
+//! Pages here are 8-bit grayscale, with ink dark and paper light. The
+//! straightening and binarizing come from [`document`](super::document);
+//! [`prepare`] runs the whole chain.
+
+use super::{
+  document::{deskew, sauvola},
+  mask::THRESHOLD,
+  meter::luminance,
+  sprites::Rect,
+  transform::crop,
+};
+use crate::{
+  error::Result,
+  pixel::{PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// An 8-bit grayscale page
+pub type Page = ImageBuffer<u8, 1, false>;
+
+/// Fraction of pixels allowed to clip to black and to white when
+/// stretching contrast
+const CLIP_FRACTION: f64 = 0.01;
+
+/// Settings for [`prepare`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OcrOptions {
+  /// Largest skew to correct, in degrees
+  pub max_skew:     f64,
+  /// Side of the window for Sauvola binarization, in pixels. About twice
+  /// the height of a text line works well.
+  pub window:       usize,
+  /// Sauvola's sensitivity to local contrast
+  pub k:            f64,
+  /// Ink specks of this many pixels or fewer are removed
+  pub speckle_size: usize,
+}
+
+impl Default for OcrOptions {
+  fn default() -> Self {
+    OcrOptions {
+      max_skew:     10.0,
+      window:       31,
+      k:            0.3,
+      speckle_size: 4,
+    }
+  }
+}
+
+/// A line of text found by [`segment`], in page coordinates
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextLine {
+  pub rect:  Rect,
+  /// The words on the line, left to right
+  pub words: Vec<Rect>,
+}
+
+/// Converts `image` to grayscale and stretches its contrast so that the
+/// darkest and lightest percent of pixels become black and white. Pages
+/// that are mostly dark are taken to be light text on a dark background
+/// and inverted, since OCR engines expect dark text.
+pub fn normalize_contrast<C: PixelContainer>(image: &C) -> Page {
+  let lum: Vec<f64> = image.iter_pixels().map(luminance::<C>).collect();
+  let mut sorted = lum.clone();
+  sorted.sort_by(f64::total_cmp);
+  let at = |fraction: f64| {
+    let last = sorted.len().saturating_sub(1);
+    sorted
+      .get((fraction * last as f64).round() as usize)
+      .copied()
+      .unwrap_or_default()
+  };
+  let (lo, hi) = (at(CLIP_FRACTION), at(1.0 - CLIP_FRACTION));
+  let invert = at(0.5) < 0.5;
+  let mut page = Page::empty(image.width(), image.height());
+  for (out, v) in page.components_mut().iter_mut().zip(lum) {
+    let v = if hi > lo {
+      ((v - lo) / (hi - lo)).clamp(0.0, 1.0)
+    } else {
+      v
+    };
+    let v = if invert { 1.0 - v } else { v };
+    *out = (v * 255.0).round() as u8;
+  }
+  page
+}
+
+/// Removes connected specks of ink, such as dust and scanner noise, of at
+/// most `max_size` pixels from a binarized page. Ink is anything below
+/// [`THRESHOLD`](super::mask::THRESHOLD), and removed ink becomes white.
+pub fn despeckle(page: &Page, max_size: usize) -> Page {
+  let (width, height) = (page.width, page.height);
+  let ink: Vec<bool> =
+    page.components().iter().map(|&v| v < THRESHOLD).collect();
+  let mut seen = vec![false; ink.len()];
+  let mut result = page.clone();
+  for start in 0..ink.len() {
+    if !ink[start] || seen[start] {
+      continue;
+    }
+    let mut component = vec![start];
+    let mut stack = vec![start];
+    seen[start] = true;
+    while let Some(i) = stack.pop() {
+      let (x, y) = (i % width, i / width);
+      for ny in y.saturating_sub(1)..(y + 2).min(height) {
+        for nx in x.saturating_sub(1)..(x + 2).min(width) {
+          let n = ny * width + nx;
+          if ink[n] && !seen[n] {
+            seen[n] = true;
+            component.push(n);
+            stack.push(n);
+          }
+        }
+      }
+    }
+    if component.len() <= max_size {
+      for i in component {
+        result.components_mut()[i] = u8::MAX;
+      }
+    }
+  }
+  result
+}
+
+/// Contrast normalization, deskewing, Sauvola binarization and despeckling,
+/// in that order, giving a black and white page ready for OCR
+pub fn prepare<C: PixelContainer>(image: &C, options: &OcrOptions) -> Page {
+  let page = deskew(&normalize_contrast(image), options.max_skew);
+  let binary = sauvola(&page, options.window, options.k);
+  despeckle(&binary, options.speckle_size)
+}
+
+/// Runs of consecutive indices whose count is non-zero, as start and end
+fn runs(counts: impl Iterator<Item = usize>) -> Vec<(usize, usize)> {
+  let mut runs = Vec::new();
+  let mut start = None;
+  let mut len = 0;
+  for (i, count) in counts.enumerate() {
+    match (count > 0, start) {
+      (true, None) => start = Some(i),
+      (false, Some(s)) => {
+        runs.push((s, i));
+        start = None;
+      }
+      _ => {}
+    }
+    len = i + 1;
+  }
+  if let Some(s) = start {
+    runs.push((s, len));
+  }
+  runs
+}
+
+/// Finds the lines of text on a binarized, straightened page by projecting
+/// its ink onto the vertical axis, then the words on each line by
+/// projecting onto the horizontal.
+///
+/// Bands much thinner than the typical line, such as the dots of i's or
+/// accents, are joined to the nearest line. Gaps of at least a quarter of
+/// the line's height separate words.
+pub fn segment(page: &Page) -> Vec<TextLine> {
+  let (width, height) = (page.width, page.height);
+  let ink = |x: usize, y: usize| page.components()[y * width + x] < THRESHOLD;
+
+  let mut bands =
+    runs((0..height).map(|y| (0..width).filter(|&x| ink(x, y)).count()));
+  let mut heights: Vec<usize> = bands.iter().map(|(a, b)| b - a).collect();
+  heights.sort_unstable();
+  let typical = heights.get(heights.len() / 2).copied().unwrap_or_default();
+  while let Some(i) = (0..bands.len())
+    .find(|&i| bands.len() > 1 && 2 * (bands[i].1 - bands[i].0) < typical)
+  {
+    let gap_above = i.checked_sub(1).map(|j| bands[i].0 - bands[j].1);
+    let gap_below = bands.get(i + 1).map(|b| b.0 - bands[i].1);
+    let j = match (gap_above, gap_below) {
+      (Some(above), Some(below)) if above <= below => i - 1,
+      (Some(_), None) => i - 1,
+      _ => i + 1,
+    };
+    let (a, b) = (bands[i.min(j)], bands[i.max(j)]);
+    bands[i.min(j)] = (a.0, b.1);
+    bands.remove(i.max(j));
+  }
+
+  bands
+    .into_iter()
+    .filter_map(|(top, bottom)| {
+      let columns =
+        runs((0..width).map(|x| (top..bottom).filter(|&y| ink(x, y)).count()));
+      let min_gap = (bottom - top).div_ceil(4).max(2);
+      let mut words: Vec<(usize, usize)> = Vec::new();
+      for (start, end) in columns {
+        match words.last_mut() {
+          Some(word) if start - word.1 < min_gap => word.1 = end,
+          _ => words.push((start, end)),
+        }
+      }
+      let words: Vec<Rect> = words
+        .into_iter()
+        .map(|(left, right)| {
+          let rows = runs(
+            (top..bottom).map(|y| (left..right).filter(|&x| ink(x, y)).count()),
+          );
+          let (first, last) = (
+            rows.first().map_or(0, |r| r.0),
+            rows.last().map_or(0, |r| r.1),
+          );
+          Rect {
+            x:      left,
+            y:      top + first,
+            width:  right - left,
+            height: last - first,
+          }
+        })
+        .collect();
+      let (left, right) =
+        (words.first()?.x, words.last().map(|w| w.x + w.width)?);
+      Some(TextLine {
+        rect: Rect {
+          x:      left,
+          y:      top,
+          width:  right - left,
+          height: bottom - top,
+        },
+        words,
+      })
+    })
+    .collect()
+}
+
+/// Copies `rect` out of `image` with a `margin` of surrounding pixels,
+/// as far as the image extends, since OCR engines read text poorly when it
+/// touches the edge. Fails if `rect` lies outside the image.
+pub fn crop_text<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  rect: &Rect,
+  margin: usize,
+) -> Result<ImageBuffer<T, N, A>> {
+  let (x, y) = (rect.x.saturating_sub(margin), rect.y.saturating_sub(margin));
+  let right = (rect.x + rect.width + margin)
+    .min(image.width)
+    .max(rect.x + rect.width);
+  let bottom = (rect.y + rect.height + margin)
+    .min(image.height)
+    .max(rect.y + rect.height);
+  crop(image, x, y, right - x, bottom - y)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ops::point::invert;
+
+  /// Two lines of blocky "words" on a gray page, plus a speck of dust
+  fn page() -> Page {
+    let word = |x: usize, y: usize, letters: usize, px: usize, py: usize| {
+      px >= x
+        && px < x + letters * 6 - 2
+        && (px - x) % 6 < 4
+        && py >= y
+        && py < y + 10
+    };
+    Page::empty(120, 60).map_indexed(&mut |x, y, _| {
+      let inked = word(10, 10, 3, x, y)
+        || word(40, 10, 4, x, y)
+        || word(10, 35, 5, x, y)
+        // The dot of an i above the second line
+        || (x == 12 && (31..33).contains(&y))
+        || (x == 100 && y == 52);
+      [if inked { 90 } else { 170 }]
+    })
+  }
+
+  #[test]
+  fn prepares_and_segments_page() {
+    let normalized = normalize_contrast(&page());
+    assert_eq!(normalized.get_pixel(0, 0), &[255]);
+    assert_eq!(normalized.get_pixel(10, 10), &[0]);
+    let inverted = normalize_contrast(&invert(&page()));
+    assert_eq!(inverted.get_pixel(10, 10), &[0]);
+
+    let clean = despeckle(&normalized, 1);
+    assert_eq!(clean.get_pixel(100, 52), &[255]);
+    assert_eq!(clean.get_pixel(12, 31), &[0]);
+
+    let lines = segment(&clean);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].words.len(), 2);
+    assert_eq!(
+      lines[0].words[1],
+      Rect {
+        x:      40,
+        y:      10,
+        width:  22,
+        height: 10,
+      }
+    );
+    assert_eq!(lines[1].rect.y, 31);
+    assert_eq!(lines[1].words.len(), 1);
+
+    let crop = crop_text(&clean, &lines[1].rect, 2).unwrap();
+    assert_eq!((crop.width, crop.height), (32, 18));
+
+    let binary = prepare(&page(), &OcrOptions::default());
+    assert!(binary.components().iter().all(|&v| v == 0 || v == 255));
+    assert_eq!(binary.get_pixel(42, 14), &[0]);
+    assert_eq!(binary.get_pixel(100, 52), &[255]);
+  }
+}
@@ -0,0 +1,439 @@
+use num_traits::{ToPrimitive, Zero};
+
+use crate::{
+  error::Result,
+  ops::progressive::Refinement,
+  pixel::{component_from_f64, Pixel, PixelContainer},
+  video::check_dimensions,
+};
+
+/// Small deterministic generator for the random search, so that fills are
+/// reproducible
+pub(crate) struct XorShift(pub(crate) u64);
+
+impl XorShift {
+  pub(crate) fn next(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+
+  /// Uniform integer in `-radius..=radius`
+  fn offset(&mut self, radius: usize) -> isize {
+    (self.next() % (2 * radius as u64 + 1)) as isize - radius as isize
+  }
+}
+
+struct Field<'a> {
+  data:      &'a [f64],
+  width:     usize,
+  height:    usize,
+  channels:  usize,
+  half:      isize,
+  is_source: &'a [bool],
+}
+
+impl Field<'_> {
+  fn at(&self, x: isize, y: isize) -> Option<usize> {
+    let inside = x >= 0
+      && y >= 0
+      && (x as usize) < self.width
+      && (y as usize) < self.height;
+    inside.then(|| y as usize * self.width + x as usize)
+  }
+
+  /// Sum of squared differences between the patch around target pixel `t`
+  /// and the patch around source pixel `s`, skipping target samples outside
+  /// the image
+  fn distance(&self, t: usize, s: usize) -> f64 {
+    let (tx, ty) = ((t % self.width) as isize, (t / self.width) as isize);
+    let (sx, sy) = ((s % self.width) as isize, (s / self.width) as isize);
+    let mut sum = 0.0;
+    for dy in -self.half..=self.half {
+      for dx in -self.half..=self.half {
+        let Some(ti) = self.at(tx + dx, ty + dy) else {
+          continue;
+        };
+        let si = (sy + dy) as usize * self.width + (sx + dx) as usize;
+        let (a, b) = (ti * self.channels, si * self.channels);
+        for c in 0..self.channels {
+          let d = self.data[a + c] - self.data[b + c];
+          sum += d * d;
+        }
+      }
+    }
+    sum
+  }
+
+  /// The source pixel at `s` shifted by `(dx, dy)`, if it is a valid source
+  fn shifted(&self, s: usize, dx: isize, dy: isize) -> Option<usize> {
+    let (x, y) = ((s % self.width) as isize, (s / self.width) as isize);
+    self.at(x + dx, y + dy).filter(|&i| self.is_source[i])
+  }
+}
+
+/// Fills the pixels where `mask` is nonzero with content copied from the
+/// rest of the image.
+///
+/// Uses PatchMatch (Barnes et al.) to find, for every `patch_size` square
+/// patch touching the hole, the most similar patch lying entirely outside
+/// it, then rebuilds each hole pixel by averaging what the overlapping
+/// matches predict for it. The two steps repeat `iterations` times. Works
+/// best for textures and regular structure; the result is deterministic.
+/// Unlike most operations, alpha is filled along with the color channels.
+///
+/// If no patch fits entirely outside the hole, the image is returned
+/// unchanged. Fails if `mask` is not the size of `image`.
+/// [`patch_match_refinements`] gives the fill after every iteration
+/// instead.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(
+      width = image.width(),
+      height = image.height(),
+      patch_size,
+      iterations,
+    )
+  )
+)]
+pub fn patch_match<C, M>(
+  image: &C,
+  mask: &M,
+  patch_size: usize,
+  iterations: usize,
+) -> Result<C>
+where
+  C: PixelContainer + Clone,
+  M: PixelContainer,
+{
+  Ok(
+    patch_match_refinements(image, mask, patch_size, iterations)?
+      .last()
+      .map_or_else(|| image.clone(), |refinement| refinement.result),
+  )
+}
+
+/// The fills of [`patch_match`] after each of its iterations, so that a
+/// preview can be shown while the later iterations run. The last one is
+/// the result of [`patch_match`]. Fails if `mask` is not the size of
+/// `image`.
+pub fn patch_match_refinements<C, M>(
+  image: &C,
+  mask: &M,
+  patch_size: usize,
+  iterations: usize,
+) -> Result<PatchMatchRefinements<C>>
+where
+  C: PixelContainer + Clone,
+  M: PixelContainer,
+{
+  check_dimensions((image.width(), image.height()), mask)?;
+  Ok(PatchMatchRefinements {
+    image:      image.clone(),
+    state:      State::new(image, mask, patch_size),
+    iteration:  0,
+    iterations: iterations.max(1),
+  })
+}
+
+/// Iterator returned by [`patch_match_refinements`]
+pub struct PatchMatchRefinements<C> {
+  image:      C,
+  /// `None` when there is nothing to fill
+  state:      Option<State>,
+  iteration:  usize,
+  iterations: usize,
+}
+
+impl<C: PixelContainer + Clone> Iterator for PatchMatchRefinements<C> {
+  type Item = Refinement<C>;
+
+  fn next(&mut self) -> Option<Refinement<C>> {
+    let Some(state) = &mut self.state else {
+      // Report the unchanged image once
+      return (self.iteration == 0).then(|| {
+        self.iteration = self.iterations;
+        Refinement {
+          result:  self.image.clone(),
+          quality: 1.0,
+        }
+      });
+    };
+    if self.iteration == self.iterations {
+      return None;
+    }
+    state.iterate(self.iteration);
+    self.iteration += 1;
+    let mut result = self.image.clone();
+    state.write(&mut result);
+    Some(Refinement {
+      result,
+      quality: self.iteration as f32 / self.iterations as f32,
+    })
+  }
+}
+
+/// The fill in progress
+struct State {
+  data:      Vec<f64>,
+  width:     usize,
+  height:    usize,
+  channels:  usize,
+  half:      isize,
+  hole:      Vec<bool>,
+  is_source: Vec<bool>,
+  is_target: Vec<bool>,
+  targets:   Vec<usize>,
+  rng:       XorShift,
+  nnf:       Vec<usize>,
+  distance:  Vec<f64>,
+}
+
+impl State {
+  /// Sets up a fill of `image`, or `None` if there is nothing to fill or
+  /// nothing to fill it from
+  fn new<C: PixelContainer, M: PixelContainer>(
+    image: &C,
+    mask: &M,
+    patch_size: usize,
+  ) -> Option<State> {
+    let (width, height) = (image.width(), image.height());
+    let channels = C::NUM_COMPONENTS;
+    let half = (patch_size.max(1) / 2) as isize;
+    let hole: Vec<bool> = mask
+      .iter_pixels()
+      .map(|pel| !pel.components()[0].is_zero())
+      .collect();
+    if !hole.contains(&true) {
+      return None;
+    }
+
+    let mut data: Vec<f64> = image
+      .components()
+      .iter()
+      .map(|c| c.to_f64().unwrap_or_default())
+      .collect();
+
+    let mut is_source = vec![false; width * height];
+    let mut is_target = vec![false; width * height];
+    for y in 0..height as isize {
+      for x in 0..width as isize {
+        let i = y as usize * width + x as usize;
+        let mut inside = true;
+        let mut touches_hole = false;
+        for dy in -half..=half {
+          for dx in -half..=half {
+            let (px, py) = (x + dx, y + dy);
+            if px < 0 || py < 0 || px >= width as isize || py >= height as isize
+            {
+              inside = false;
+            } else if hole[py as usize * width + px as usize] {
+              touches_hole = true;
+            }
+          }
+        }
+        is_source[i] = inside && !touches_hole;
+        is_target[i] = touches_hole;
+      }
+    }
+    let sources: Vec<usize> =
+      (0..is_source.len()).filter(|&i| is_source[i]).collect();
+    let targets: Vec<usize> =
+      (0..is_target.len()).filter(|&i| is_target[i]).collect();
+    if sources.is_empty() {
+      return None;
+    }
+
+    // Start the hole at the mean of the known pixels
+    let known = hole.iter().filter(|&&h| !h).count().max(1) as f64;
+    for c in 0..channels {
+      let mean = (0..hole.len())
+        .filter(|&i| !hole[i])
+        .map(|i| data[i * channels + c])
+        .sum::<f64>()
+        / known;
+      for i in (0..hole.len()).filter(|&i| hole[i]) {
+        data[i * channels + c] = mean;
+      }
+    }
+
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    let mut nnf = vec![0; width * height];
+    for &t in &targets {
+      nnf[t] = sources[rng.next() as usize % sources.len()];
+    }
+    Some(State {
+      data,
+      width,
+      height,
+      channels,
+      half,
+      hole,
+      is_source,
+      is_target,
+      targets,
+      rng,
+      nnf,
+      distance: vec![f64::INFINITY; width * height],
+    })
+  }
+
+  /// Runs one round of matching and voting
+  fn iterate(&mut self, iteration: usize) {
+    let (width, height, channels, half) =
+      (self.width, self.height, self.channels, self.half);
+    let (nnf, distance, rng) =
+      (&mut self.nnf, &mut self.distance, &mut self.rng);
+    let (hole, is_target, targets) =
+      (&self.hole, &self.is_target, &self.targets);
+    let field = Field {
+      data: &self.data,
+      width,
+      height,
+      channels,
+      half,
+      is_source: &self.is_source,
+    };
+    for &t in targets {
+      distance[t] = field.distance(t, nnf[t]);
+    }
+
+    let forward = iteration.is_multiple_of(2);
+    let step: isize = if forward { 1 } else { -1 };
+    let order: Box<dyn Iterator<Item = &usize>> = if forward {
+      Box::new(targets.iter())
+    } else {
+      Box::new(targets.iter().rev())
+    };
+    for &t in order {
+      let (x, y) = ((t % width) as isize, (t / width) as isize);
+      let try_source = |s: usize, nnf: &mut [usize], distance: &mut [f64]| {
+        let d = field.distance(t, s);
+        if d < distance[t] {
+          nnf[t] = s;
+          distance[t] = d;
+        }
+      };
+
+      // Propagation: a neighbor's match, shifted back by one pixel, is a
+      // likely match here too
+      for (dx, dy) in [(step, 0), (0, step)] {
+        if let Some(n) = field.at(x - dx, y - dy).filter(|&n| is_target[n]) {
+          if let Some(s) = field.shifted(nnf[n], dx, dy) {
+            try_source(s, nnf, distance);
+          }
+        }
+      }
+
+      // Random search in exponentially shrinking windows around the best
+      // match so far
+      let mut radius = width.max(height);
+      while radius >= 1 {
+        let (dx, dy) = (rng.offset(radius), rng.offset(radius));
+        if let Some(s) = field.shifted(nnf[t], dx, dy) {
+          try_source(s, nnf, distance);
+        }
+        radius /= 2;
+      }
+    }
+
+    // Voting: every patch covering a hole pixel proposes a value for it,
+    // weighted by how well the patch matched relative to the median match
+    let mut sorted: Vec<f64> = targets.iter().map(|&t| distance[t]).collect();
+    sorted.sort_by(f64::total_cmp);
+    let scale = sorted[sorted.len() / 2].max(f64::EPSILON);
+    let mut sum = vec![0.0; self.data.len()];
+    let mut weights = vec![0.0; hole.len()];
+    for &t in targets {
+      let weight = (-distance[t] / scale).exp();
+      let (tx, ty) = ((t % width) as isize, (t / width) as isize);
+      let (sx, sy) = ((nnf[t] % width) as isize, (nnf[t] / width) as isize);
+      for dy in -half..=half {
+        for dx in -half..=half {
+          let Some(p) = field.at(tx + dx, ty + dy).filter(|&p| hole[p]) else {
+            continue;
+          };
+          let s = (sy + dy) as usize * width + (sx + dx) as usize;
+          for c in 0..channels {
+            sum[p * channels + c] += weight * self.data[s * channels + c];
+          }
+          weights[p] += weight;
+        }
+      }
+    }
+    for p in (0..hole.len()).filter(|&p| weights[p] > 0.0) {
+      for c in 0..channels {
+        self.data[p * channels + c] = sum[p * channels + c] / weights[p];
+      }
+    }
+  }
+
+  /// Writes the hole pixels into `result`
+  fn write<C: PixelContainer>(&self, result: &mut C) {
+    let channels = self.channels;
+    for (p, pel) in result
+      .components_mut()
+      .chunks_exact_mut(channels)
+      .enumerate()
+      .filter(|(p, _)| self.hole[*p])
+    {
+      for (c, value) in pel.iter_mut().enumerate() {
+        *value = component_from_f64(self.data[p * channels + c]);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ImageBuffer;
+
+  #[test]
+  fn patch_match_continues_stripes_rgb_u8() {
+    let stripes = |x: usize| {
+      if x.is_multiple_of(3) {
+        [200, 40, 40]
+      } else {
+        [20, 20, 90]
+      }
+    };
+    let image = ImageBuffer::<u8, 3, false>::empty(12, 12)
+      .map_indexed(&mut |x, _, _| stripes(x));
+    let mask =
+      ImageBuffer::<u8, 1, false>::empty(12, 12).map_indexed(&mut |x, y, _| {
+        [((4..7).contains(&x) && (5..8).contains(&y)) as u8]
+      });
+    let mut damaged = image.clone();
+    for y in 5..8 {
+      for x in 4..7 {
+        *damaged.get_pixel_mut(x, y) = [0, 255, 0];
+      }
+    }
+
+    let filled = patch_match(&damaged, &mask, 5, 5).unwrap();
+    assert_eq!(filled.components(), image.components());
+
+    let refinements: Vec<_> = patch_match_refinements(&damaged, &mask, 5, 5)
+      .unwrap()
+      .collect();
+    assert_eq!(refinements.len(), 5);
+    assert_eq!(refinements[1].quality, 0.4);
+    assert_eq!(refinements[4].result.components(), image.components());
+    let unmasked = ImageBuffer::<u8, 1, false>::empty(12, 12);
+    assert_eq!(
+      patch_match_refinements(&image, &unmasked, 5, 5)
+        .unwrap()
+        .count(),
+      1
+    );
+    let small = ImageBuffer::<u8, 1, false>::empty(11, 12);
+    assert!(patch_match(&damaged, &small, 5, 5).is_err());
+    let large = ImageBuffer::<u8, 1, false>::empty(13, 12);
+    assert!(patch_match_refinements(&damaged, &large, 5, 5).is_err());
+  }
+}
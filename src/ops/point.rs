@@ -0,0 +1,99 @@
+//! Point operations: adjustments where each output component depends only on
+//! the input component in the same channel.
+//!
+//! They all go through the lookup table engine in [`lut`](super::lut).
+//! Alpha is passed through.
+
+use num_traits::ToPrimitive;
+
+use super::lut::apply_per_channel;
+use crate::pixel::{PixelComponent, PixelContainer};
+
+/// Produces the negative of `image`
+pub fn invert<C: PixelContainer + Clone>(image: &C) -> C {
+  let mut result = image.clone();
+  apply_per_channel(&mut result, |_, v| 1.0 - v);
+  result
+}
+
+/// Reduces each channel to `levels` evenly spaced values. Fewer than two
+/// levels are treated as two.
+pub fn posterize<C: PixelContainer + Clone>(image: &C, levels: usize) -> C {
+  let steps = (levels.max(2) - 1) as f64;
+  let mut result = image.clone();
+  apply_per_channel(&mut result, |_, v| (v * steps).round() / steps);
+  result
+}
+
+/// Inverts components at or above `threshold`, given as a fraction of white,
+/// imitating a print exposed to light during development
+pub fn solarize<C: PixelContainer + Clone>(image: &C, threshold: f64) -> C {
+  let mut result = image.clone();
+  apply_per_channel(
+    &mut result,
+    |_, v| if v >= threshold { 1.0 - v } else { v },
+  );
+  result
+}
+
+/// Equalizes the histogram of each channel independently, spreading its
+/// values evenly over the full range
+pub fn equalize_channels<C: PixelContainer + Clone>(image: &C) -> C {
+  let white = C::Component::WHITE.to_f64().unwrap_or(1.0);
+  let sorted: Vec<Vec<f64>> = (0..C::NUM_NONALPHA_COMPONENTS)
+    .map(|c| {
+      let mut values: Vec<f64> = image
+        .components()
+        .iter()
+        .skip(c)
+        .step_by(C::NUM_COMPONENTS)
+        .map(|v| v.to_f64().unwrap_or_default() / white)
+        .collect();
+      values.sort_by(f64::total_cmp);
+      values
+    })
+    .collect();
+
+  let mut result = image.clone();
+  apply_per_channel(&mut result, |c, v| {
+    // Share of the channel at or below `v`, rescaled so that the darkest
+    // value present maps to black
+    let values = &sorted[c];
+    let below = values.partition_point(|&x| x <= v) as f64;
+    let darkest = values.partition_point(|&x| x <= values[0]) as f64;
+    let n = values.len() as f64;
+    if n > darkest {
+      ((below - darkest) / (n - darkest)).max(0.0)
+    } else {
+      v
+    }
+  });
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ImageBuffer;
+
+  #[test]
+  fn point_ops_u8() {
+    let image = ImageBuffer::<u8, 4, true>::empty(4, 1)
+      .map_indexed(&mut |x, _, _| [x as u8 * 80, 255, 10, 77]);
+    assert_eq!(invert(&image).get_pixel(1, 0), &[175, 0, 245, 77]);
+    assert_eq!(posterize(&image, 2).get_pixel(2, 0), &[255, 255, 0, 77]);
+    assert_eq!(solarize(&image, 0.5).get_pixel(3, 0), &[15, 0, 10, 77]);
+
+    let equalized = equalize_channels(&image);
+    let reds: Vec<u8> = equalized.iter_pixels().map(|p| p[0]).collect();
+    assert_eq!(reds, [0, 85, 170, 255]);
+  }
+
+  #[test]
+  fn point_ops_f32_skip_table() {
+    let image = ImageBuffer::<f32, 1, false>::empty(2, 1)
+      .map_indexed(&mut |x, _, _| [0.25 + x as f32 * 0.5]);
+    assert_eq!(invert(&image).components(), &[0.75, 0.25]);
+    assert_eq!(posterize(&image, 3).components(), &[0.5, 1.0]);
+  }
+}
@@ -0,0 +1,162 @@
+//! Intermediate results of expensive operations at increasing quality, so
+//! that interfaces can show a preview long before the final result is
+//! ready.
+//!
+//! Operations that refine their result step by step, such as
+//! [`patch_match_refinements`](super::patch_match::patch_match_refinements),
+//! yield it after each step. Any other [`ImageOp`] can be refined by
+//! resolution with [`by_resolution`]: it runs first on small copies of the
+//! image and finally on the image itself. Panoramas are refined the same
+//! way by [`stitch_refinements`](crate::stitch::stitch_refinements).
+
+use crate::{
+  error::Result,
+  ops::{transform, ImageOp},
+  progress::Progress,
+  Image,
+};
+
+/// One intermediate result
+#[derive(Clone, Debug)]
+pub struct Refinement<T> {
+  pub result:  T,
+  /// How far along the refinement is, from above 0 to 1 for the final
+  /// result
+  pub quality: f32,
+}
+
+/// Runs `op` on `image` scaled down by 2<sup>`levels` - 1</sup>, then by
+/// each smaller power of two, and finally at full resolution. The smaller
+/// results are scaled up to the size the full-resolution result would have.
+///
+/// `progress` is told the quality of each result, and stops the iteration
+/// with [`Error::Cancelled`](crate::error::Error::Cancelled) once cancelled.
+/// Levels that would not shrink the image are skipped, and `levels` is
+/// clamped to `1..=usize::BITS`.
+pub fn by_resolution<'a, O: ImageOp + ?Sized>(
+  op: &'a O,
+  image: &'a Image,
+  levels: usize,
+  progress: Progress,
+) -> ByResolution<'a, O> {
+  let levels = levels.clamp(1, usize::BITS as usize);
+  ByResolution {
+    op,
+    image,
+    level: levels,
+    levels,
+    progress,
+  }
+}
+
+/// Iterator returned by [`by_resolution`]
+pub struct ByResolution<'a, O: ?Sized> {
+  op:       &'a O,
+  image:    &'a Image,
+  /// Levels still to run; the next runs at 1 / 2<sup>`level` - 1</sup>
+  level:    usize,
+  levels:   usize,
+  progress: Progress,
+}
+
+impl<O: ImageOp + ?Sized> ByResolution<'_, O> {
+  fn run(&self, level: usize) -> Result<Image> {
+    if level == 0 {
+      return self.op.apply(self.image);
+    }
+    let factor = 1usize << level;
+    let (width, height) = (self.image.width(), self.image.height());
+    let proxy = self.image.proxy(width.max(height) / factor)?;
+    let small = self.op.apply(&proxy.image())?.to_rgba_f32()?;
+    let scale = 1.0 / proxy.scale();
+    let size = |len: usize| ((len as f64 * scale).round() as usize).max(1);
+    let result =
+      transform::resize(&small, size(small.width), size(small.height));
+    Ok(Image::from_rgba_f32_like(result, self.image))
+  }
+}
+
+impl<O: ImageOp + ?Sized> Iterator for ByResolution<'_, O> {
+  type Item = Result<Refinement<Image>>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let longest = self.image.width().max(self.image.height());
+    // Skip levels too coarse to shrink the image
+    while self.level > 1 && longest >> (self.level - 1) == 0 {
+      self.level -= 1;
+    }
+    if self.level == 0 {
+      return None;
+    }
+    self.level -= 1;
+    let quality = (self.levels - self.level) as f32 / self.levels as f32;
+    let refinement = self.progress.check().and_then(|()| {
+      let result = self.run(self.level)?;
+      self.progress.report(quality)?;
+      Ok(Refinement {
+        result,
+        quality,
+      })
+    });
+    if refinement.is_err() {
+      self.level = 0;
+    }
+    Some(refinement)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    color_space::ColorSpace,
+    error::Error,
+    ImageBuffer,
+    PixelContainer,
+  };
+
+  struct Brighten;
+
+  impl ImageOp for Brighten {
+    fn name(&self) -> &str { "test-brighten" }
+
+    fn apply(&self, image: &Image) -> Result<Image> {
+      let rgba = image.to_rgba_f32()?;
+      let brighter = rgba.map(&mut |&[r, g, b, a]| [r + 0.5, g, b, a]);
+      Ok(Image::from_rgba_f32_like(brighter, image))
+    }
+  }
+
+  #[test]
+  fn refines_by_resolution_until_cancelled() {
+    let image = Image::new_u8(ColorSpace::Rgb(ImageBuffer::empty(16, 8)));
+    let steps: Vec<_> = Brighten
+      .refine(&image, Progress::new())
+      .collect::<Result<_>>()
+      .unwrap();
+    assert_eq!(steps.len(), 4);
+    assert_eq!(steps[0].quality, 0.25);
+    assert!(steps
+      .iter()
+      .all(|s| (s.result.width(), s.result.height()) == (16, 8)));
+    let last = steps[3].result.to_rgba_f32().unwrap();
+    assert_eq!(last.get_pixel(0, 0), &[128.0 / 255.0, 0.0, 0.0, 1.0]);
+
+    // Levels smaller than a pixel are skipped
+    assert_eq!(
+      by_resolution(&Brighten, &image, 6, Progress::new()).count(),
+      5
+    );
+    assert_eq!(
+      by_resolution(&Brighten, &image, usize::MAX, Progress::new()).count(),
+      5
+    );
+
+    let progress = Progress::new();
+    let mut steps = by_resolution(&Brighten, &image, 3, progress.clone());
+    assert!(steps.next().unwrap().is_ok());
+    progress.cancel();
+    assert!(matches!(steps.next(), Some(Err(Error::Cancelled))));
+    assert!(steps.next().is_none());
+  }
+}
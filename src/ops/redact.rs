@@ -0,0 +1,202 @@
+//! Irreversible removal of regions, for privacy.
+//!
+//! Each operation replaces the original values in the region instead of
+//! covering them, so nothing can be recovered from the output buffer. Of the
+//! three, only [`blackout`] leaves no information at all; pixelation of text
+//! with small blocks can sometimes be guessed by matching candidate text.
+//! Encoded files keep no metadata when written with
+//! [`io::encode`](crate::io::encode), and
+//! [`io::strip_metadata`](crate::io::strip_metadata) cleans existing files.
+
+use rand::seq::SliceRandom;
+
+use crate::{
+  compute::ExecutionContext,
+  error::{Error, Result},
+  ops::blur::gaussian_blur_in,
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// A rectangle of pixels to redact
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+  pub x:      usize,
+  pub y:      usize,
+  pub width:  usize,
+  pub height: usize,
+}
+
+impl Region {
+  /// Fails if the region does not lie within a `width` by `height` image
+  fn check(&self, width: usize, height: usize) -> Result<()> {
+    let fits = |start: usize, len: usize, limit: usize| {
+      start.checked_add(len).is_some_and(|end| end <= limit)
+    };
+    if fits(self.x, self.width, width) && fits(self.y, self.height, height) {
+      Ok(())
+    } else {
+      Err(Error::InvalidArgument(format!(
+        "Region of {}x{} at ({}, {}) exceeds the {width}x{height} image",
+        self.width, self.height, self.x, self.y
+      )))
+    }
+  }
+
+  /// Coordinates of the pixels in the region, row by row
+  fn pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+    (self.y..self.y + self.height)
+      .flat_map(move |y| (self.x..self.x + self.width).map(move |x| (x, y)))
+  }
+}
+
+/// Fills `region` with opaque black
+pub fn blackout<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  region: Region,
+) -> Result<ImageBuffer<T, N, A>> {
+  region.check(image.width, image.height)?;
+  let mut black = [T::zero(); N];
+  if A {
+    black[N - 1] = component_from_f64(image.white());
+  }
+  let mut result = image.clone();
+  for (x, y) in region.pixels() {
+    *result.get_pixel_mut(x, y) = black;
+  }
+  Ok(result)
+}
+
+/// Replaces each `block` by `block` square of `region`, counted from its
+/// top-left corner, with its average. Blocks smaller than about a tenth of
+/// the height of text in the region may leave it guessable.
+pub fn pixelate<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  region: Region,
+  block: usize,
+) -> Result<ImageBuffer<T, N, A>> {
+  region.check(image.width, image.height)?;
+  if block == 0 {
+    return Err(Error::InvalidArgument(
+      "Pixelation blocks must be at least 1 pixel".to_string(),
+    ));
+  }
+  let mut result = image.clone();
+  for block_region in blocks(region, block) {
+    let mut sum = [0.0; N];
+    for (x, y) in block_region.pixels() {
+      for (s, c) in sum.iter_mut().zip(image.get_pixel(x, y)) {
+        *s += c.to_f64().unwrap_or_default();
+      }
+    }
+    let count = (block_region.width * block_region.height) as f64;
+    let mean = sum.map(|s| component_from_f64(s / count));
+    for (x, y) in block_region.pixels() {
+      *result.get_pixel_mut(x, y) = mean;
+    }
+  }
+  Ok(result)
+}
+
+/// `region` split into squares of `block` pixels, smaller at its right and
+/// bottom edges
+fn blocks(region: Region, block: usize) -> impl Iterator<Item = Region> {
+  (0..region.height).step_by(block).flat_map(move |dy| {
+    (0..region.width).step_by(block).map(move |dx| {
+      Region {
+        x:      region.x + dx,
+        y:      region.y + dy,
+        width:  block.min(region.width - dx),
+        height: block.min(region.height - dy),
+      }
+    })
+  })
+}
+
+/// Blurs `region` by `sigma` pixels after shuffling the pixels within each
+/// `block` by `block` square at random. A plain blur can be partly undone
+/// by deconvolution; after shuffling, there is no spatial structure left to
+/// recover within a block, while the blurred result still looks like a
+/// blur. The shuffle comes from [`ExecutionContext::rng`].
+///
+/// The blur reads pixels outside the region but only writes inside it.
+pub fn scrambled_blur<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  region: Region,
+  block: usize,
+  sigma: f64,
+  context: &ExecutionContext,
+) -> Result<ImageBuffer<T, N, A>> {
+  region.check(image.width, image.height)?;
+  if block == 0 {
+    return Err(Error::InvalidArgument(
+      "Scrambling blocks must be at least 1 pixel".to_string(),
+    ));
+  }
+  let mut rng = context.rng();
+  let mut scrambled = image.clone();
+  for block_region in blocks(region, block) {
+    let coordinates: Vec<(usize, usize)> = block_region.pixels().collect();
+    let mut values: Vec<[T; N]> = coordinates
+      .iter()
+      .map(|&(x, y)| *image.get_pixel(x, y))
+      .collect();
+    values.shuffle(&mut rng);
+    for (&(x, y), value) in coordinates.iter().zip(values) {
+      *scrambled.get_pixel_mut(x, y) = value;
+    }
+  }
+  let blurred =
+    gaussian_blur_in(&scrambled, sigma, &Default::default(), context);
+  let mut result = image.clone();
+  for (x, y) in region.pixels() {
+    *result.get_pixel_mut(x, y) = *blurred.get_pixel(x, y);
+  }
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ramp() -> ImageBuffer<u8, 4, true> {
+    ImageBuffer::<u8, 4, true>::empty(8, 8)
+      .map_indexed(&mut |x, y, _| [(x * 30) as u8, (y * 30) as u8, 50, 0])
+  }
+
+  #[test]
+  fn redaction_only_touches_the_region() {
+    let image = ramp();
+    let region = Region {
+      x:      2,
+      y:      2,
+      width:  4,
+      height: 3,
+    };
+    let black = blackout(&image, region).unwrap();
+    assert_eq!(black.get_pixel(2, 2), &[0, 0, 0, 255]);
+    assert_eq!(black.get_pixel(1, 2), image.get_pixel(1, 2));
+
+    let blocky = pixelate(&image, region, 2).unwrap();
+    assert_eq!(blocky.get_pixel(2, 2), &[75, 75, 50, 0]);
+    assert_eq!(blocky.get_pixel(3, 3), &[75, 75, 50, 0]);
+    assert_eq!(blocky.get_pixel(5, 4), &[135, 120, 50, 0]);
+
+    let context = ExecutionContext::deterministic(3);
+    let blurred = scrambled_blur(&image, region, 2, 1.0, &context).unwrap();
+    assert_eq!(blurred.get_pixel(6, 2), image.get_pixel(6, 2));
+    assert_eq!(
+      blurred.components(),
+      scrambled_blur(&image, region, 2, 1.0, &context)
+        .unwrap()
+        .components()
+    );
+
+    let outside = Region {
+      x: 6,
+      ..region
+    };
+    assert!(blackout(&image, outside).is_err());
+    assert!(pixelate(&image, region, 0).is_err());
+  }
+}
@@ -0,0 +1,496 @@
+//! Image registration: estimating the transform that aligns one shot of a
+//! scene with another, as needed before stacking or merging handheld frames.
+//!
+//! Transforms map coordinates in the reference image to coordinates in the
+//! moving image, so [`warp`]ing the moving image by the estimated transform
+//! lines it up with the reference.
+
+use num_traits::ToPrimitive;
+use rustfft::{num_complex::Complex, FftPlanner};
+
+use super::meter::luminance;
+use crate::{
+  error::{Error, Result},
+  pixel::{component_from_f64, PixelContainer},
+  video::check_dimensions,
+};
+
+/// Relative strength below which phase correlation damps a frequency bin
+const PHASE_FLOOR: f64 = 1e-3;
+
+/// Stop ECC refinement once the parameter update is smaller than this
+const ECC_EPSILON: f64 = 1e-6;
+
+/// A 2D affine transform `(x, y) -> (a x + b y + c, d x + e y + f)`, stored
+/// as the two rows `[[a, b, c], [d, e, f]]`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Affine {
+  pub matrix: [[f64; 3]; 2],
+}
+
+impl Default for Affine {
+  fn default() -> Self { Affine::identity() }
+}
+
+impl Affine {
+  pub fn identity() -> Self {
+    Affine {
+      matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+    }
+  }
+
+  pub fn translation(dx: f64, dy: f64) -> Self {
+    Affine {
+      matrix: [[1.0, 0.0, dx], [0.0, 1.0, dy]],
+    }
+  }
+
+  /// The translation part of the transform
+  pub fn offset(&self) -> (f64, f64) { (self.matrix[0][2], self.matrix[1][2]) }
+
+  /// Maps a point through the transform
+  pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+    let [r0, r1] = self.matrix;
+    (r0[0] * x + r0[1] * y + r0[2], r1[0] * x + r1[1] * y + r1[2])
+  }
+}
+
+/// An estimated alignment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Registration {
+  pub transform:  Affine,
+  /// How well the images agree under the transform, from `0.0` (no
+  /// evidence) to `1.0` (a perfect match)
+  pub confidence: f64,
+}
+
+/// Luminance of every pixel, in row-major order
+fn luma<C: PixelContainer>(image: &C) -> Vec<f64> {
+  image.iter_pixels().map(luminance::<C>).collect()
+}
+
+/// Bilinear sample of a row-major plane, or `None` outside it
+pub(crate) fn sample(
+  plane: &[f64],
+  width: usize,
+  height: usize,
+  x: f64,
+  y: f64,
+) -> Option<f64> {
+  if x < 0.0 || y < 0.0 || x > (width - 1) as f64 || y > (height - 1) as f64 {
+    return None;
+  }
+  let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+  let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+  let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+  let at = |x: usize, y: usize| plane[y * width + x];
+  let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+  let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+  Some(top * (1.0 - fy) + bottom * fy)
+}
+
+/// In-place 2D FFT of a row-major `width` x `height` grid
+fn fft_2d(
+  data: &mut [Complex<f64>],
+  width: usize,
+  height: usize,
+  inverse: bool,
+) {
+  let mut planner = FftPlanner::new();
+  let (rows, cols) = if inverse {
+    (
+      planner.plan_fft_inverse(width),
+      planner.plan_fft_inverse(height),
+    )
+  } else {
+    (
+      planner.plan_fft_forward(width),
+      planner.plan_fft_forward(height),
+    )
+  };
+  for row in data.chunks_exact_mut(width) {
+    rows.process(row);
+  }
+  let mut column = vec![Complex::default(); height];
+  for x in 0..width {
+    for (y, c) in column.iter_mut().enumerate() {
+      *c = data[y * width + x];
+    }
+    cols.process(&mut column);
+    for (y, c) in column.iter().enumerate() {
+      data[y * width + x] = *c;
+    }
+  }
+}
+
+/// Estimates the translation between two images of the same size by phase
+/// correlation, to sub-pixel precision.
+///
+/// Content is assumed to wrap around at the edges, so shifts approaching
+/// half the image size become ambiguous. The confidence is the height of
+/// the correlation peak relative to that of an exact shifted copy.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(width = reference.width(), height = reference.height())
+  )
+)]
+pub fn phase_correlation<C: PixelContainer>(
+  reference: &C,
+  moving: &C,
+) -> Result<Registration> {
+  let (width, height) = (reference.width(), reference.height());
+  check_dimensions((width, height), moving)?;
+  if width == 0 || height == 0 {
+    return Err(Error::Unsupported(
+      "Cannot register an empty image".to_string(),
+    ));
+  }
+
+  let spectrum = |image: &C| {
+    let mut data: Vec<Complex<f64>> = luma(image)
+      .into_iter()
+      .map(|l| Complex::new(l, 0.0))
+      .collect();
+    fft_2d(&mut data, width, height, false);
+    data
+  };
+  let (a, b) = (spectrum(reference), spectrum(moving));
+  let mut cross: Vec<Complex<f64>> =
+    a.iter().zip(&b).map(|(a, b)| b * a.conj()).collect();
+  // Whitening amplifies frequencies that carry only quantization noise, so
+  // bins much weaker than the strongest are damped rather than normalized
+  let floor = PHASE_FLOOR * cross.iter().map(|c| c.norm()).fold(0.0, f64::max);
+  // Height the peak would reach for a perfect shifted copy
+  let mut perfect = 0.0;
+  for c in &mut cross {
+    let weight = c.norm() + floor.max(f64::EPSILON);
+    perfect += c.norm() / weight;
+    *c /= weight;
+  }
+  fft_2d(&mut cross, width, height, true);
+
+  let surface: Vec<f64> = cross
+    .iter()
+    .map(|c| c.re / perfect.max(f64::EPSILON))
+    .collect();
+  let (peak, &height_at_peak) = surface
+    .iter()
+    .enumerate()
+    .max_by(|a, b| a.1.total_cmp(b.1))
+    .expect("Image is not empty");
+  let (px, py) = (peak % width, peak / width);
+
+  // Fit a parabola through the peak and its neighbors along each axis
+  let at = |x: usize, y: usize| surface[(y % height) * width + x % width];
+  let refine = |before: f64, after: f64| {
+    let denom = before - 2.0 * height_at_peak + after;
+    if denom.abs() > f64::EPSILON {
+      (0.5 * (before - after) / denom).clamp(-0.5, 0.5)
+    } else {
+      0.0
+    }
+  };
+  let fx = refine(at(px + width - 1, py), at(px + 1, py));
+  let fy = refine(at(px, py + height - 1), at(px, py + 1));
+  let signed = |p: usize, size: usize| {
+    if p > size / 2 {
+      p as f64 - size as f64
+    } else {
+      p as f64
+    }
+  };
+
+  Ok(Registration {
+    transform:  Affine::translation(
+      signed(px, width) + fx,
+      signed(py, height) + fy,
+    ),
+    confidence: height_at_peak.clamp(0.0, 1.0),
+  })
+}
+
+/// Solves the linear system `a x = b` by Gaussian elimination with partial
+/// pivoting, or returns `None` if it is singular
+pub(crate) fn solve<const N: usize>(
+  mut a: [[f64; N]; N],
+  mut b: [f64; N],
+) -> Option<[f64; N]> {
+  for col in 0..N {
+    let pivot =
+      (col..N).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+    if a[pivot][col].abs() < 1e-12 {
+      return None;
+    }
+    a.swap(col, pivot);
+    b.swap(col, pivot);
+    let b_col = b[col];
+    let (upper, lower) = a.split_at_mut(col + 1);
+    let pivot_row = &upper[col];
+    for (row, rhs) in lower.iter_mut().zip(&mut b[col + 1..]) {
+      let factor = row[col] / pivot_row[col];
+      for (v, p) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+        *v -= factor * p;
+      }
+      *rhs -= factor * b_col;
+    }
+  }
+  let mut x = [0.0; N];
+  for row in (0..N).rev() {
+    let tail: f64 = (row + 1..N).map(|k| a[row][k] * x[k]).sum();
+    x[row] = (b[row] - tail) / a[row][row];
+  }
+  Some(x)
+}
+
+/// Refines an affine alignment by maximizing the enhanced correlation
+/// coefficient (Evangelidis & Psarakis), starting from `initial`.
+///
+/// ECC is insensitive to brightness and contrast differences between the
+/// images but only converges from a nearby starting point, so seed it with
+/// [`phase_correlation`] for large shifts. The confidence is the final
+/// correlation coefficient, clamped to be non-negative.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(width = reference.width(), height = reference.height(), iterations)
+  )
+)]
+pub fn ecc<C: PixelContainer>(
+  reference: &C,
+  moving: &C,
+  initial: Affine,
+  iterations: usize,
+) -> Result<Registration> {
+  let (width, height) = (reference.width(), reference.height());
+  check_dimensions((width, height), moving)?;
+  let template = luma(reference);
+  let image = luma(moving);
+
+  let gradient = |dx: isize, dy: isize| {
+    let mut out = vec![0.0; width * height];
+    for y in 0..height {
+      for x in 0..width {
+        let at = |x: isize, y: isize| {
+          let x = x.clamp(0, width as isize - 1) as usize;
+          let y = y.clamp(0, height as isize - 1) as usize;
+          image[y * width + x]
+        };
+        let (x, y) = (x as isize, y as isize);
+        out[y as usize * width + x as usize] =
+          (at(x + dx, y + dy) - at(x - dx, y - dy)) / 2.0;
+      }
+    }
+    out
+  };
+  let (gx, gy) = (gradient(1, 0), gradient(0, 1));
+
+  let mut transform = initial;
+  let mut rho = 0.0;
+  for _ in 0..iterations.max(1) {
+    // Gather the pixels whose warped position lands inside the moving image
+    let mut samples = Vec::new();
+    for y in 0..height {
+      for x in 0..width {
+        let (wx, wy) = transform.apply(x as f64, y as f64);
+        let Some(i) = sample(&image, width, height, wx, wy) else {
+          continue;
+        };
+        let gx = sample(&gx, width, height, wx, wy).unwrap_or_default();
+        let gy = sample(&gy, width, height, wx, wy).unwrap_or_default();
+        let (fx, fy) = (x as f64, y as f64);
+        let jacobian = [gx * fx, gx * fy, gx, gy * fx, gy * fy, gy];
+        samples.push((template[y * width + x], i, jacobian));
+      }
+    }
+    if samples.len() < 6 {
+      return Err(Error::Unsupported(
+        "Images do not overlap under the transform".to_string(),
+      ));
+    }
+
+    let count = samples.len() as f64;
+    let t_mean = samples.iter().map(|s| s.0).sum::<f64>() / count;
+    let i_mean = samples.iter().map(|s| s.1).sum::<f64>() / count;
+    let mut hessian = [[0.0; 6]; 6];
+    let mut image_proj = [0.0; 6];
+    let mut template_proj = [0.0; 6];
+    let (mut correlation, mut t_norm, mut i_norm) = (0.0, 0.0, 0.0);
+    for (t, i, jac) in &samples {
+      let (t, i) = (t - t_mean, i - i_mean);
+      correlation += t * i;
+      t_norm += t * t;
+      i_norm += i * i;
+      for r in 0..6 {
+        image_proj[r] += jac[r] * i;
+        template_proj[r] += jac[r] * t;
+        for c in 0..6 {
+          hessian[r][c] += jac[r] * jac[c];
+        }
+      }
+    }
+    rho = correlation / (t_norm * i_norm).sqrt().max(f64::EPSILON);
+
+    let Some(h_image) = solve(hessian, image_proj) else {
+      break;
+    };
+    let Some(h_template) = solve(hessian, template_proj) else {
+      break;
+    };
+    let dot = |a: &[f64; 6], b: &[f64; 6]| -> f64 {
+      a.iter().zip(b).map(|(a, b)| a * b).sum()
+    };
+    let numerator = i_norm - dot(&image_proj, &h_image);
+    let denominator = correlation - dot(&image_proj, &h_template);
+    if denominator <= 0.0 {
+      return Err(Error::Unsupported(
+        "ECC did not converge; try a closer initial transform".to_string(),
+      ));
+    }
+    let lambda = numerator / denominator;
+
+    let mut error_proj = [0.0; 6];
+    for (t, i, jac) in &samples {
+      let error = lambda * (t - t_mean) - (i - i_mean);
+      for r in 0..6 {
+        error_proj[r] += jac[r] * error;
+      }
+    }
+    let Some(delta) = solve(hessian, error_proj) else {
+      break;
+    };
+    for (p, d) in transform.matrix.iter_mut().flatten().zip(delta) {
+      *p += d;
+    }
+    if delta.iter().map(|d| d * d).sum::<f64>().sqrt() < ECC_EPSILON {
+      break;
+    }
+  }
+
+  Ok(Registration {
+    transform,
+    confidence: rho.max(0.0),
+  })
+}
+
+/// Aligns `moving` with `reference`: a coarse translation from
+/// [`phase_correlation`] refined by up to `iterations` steps of [`ecc`]
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(width = reference.width(), height = reference.height(), iterations)
+  )
+)]
+pub fn register<C: PixelContainer>(
+  reference: &C,
+  moving: &C,
+  iterations: usize,
+) -> Result<Registration> {
+  let coarse = phase_correlation(reference, moving)?;
+  ecc(reference, moving, coarse.transform, iterations)
+}
+
+/// Resamples `image` so that each output pixel `(x, y)` takes the value at
+/// `transform.apply(x, y)`, with bilinear interpolation. Pixels that map
+/// outside the image are left at zero.
+pub fn warp<C: PixelContainer + Clone>(image: &C, transform: &Affine) -> C {
+  let (width, height) = (image.width(), image.height());
+  let channels = C::NUM_COMPONENTS;
+  let planes: Vec<Vec<f64>> = (0..channels)
+    .map(|c| {
+      image
+        .components()
+        .iter()
+        .skip(c)
+        .step_by(channels)
+        .map(|v| v.to_f64().unwrap_or_default())
+        .collect()
+    })
+    .collect();
+
+  let mut result = image.clone();
+  for (i, pel) in result
+    .components_mut()
+    .chunks_exact_mut(channels)
+    .enumerate()
+  {
+    let (x, y) = transform.apply((i % width) as f64, (i / width) as f64);
+    for (c, value) in pel.iter_mut().enumerate() {
+      let v = sample(&planes[c], width, height, x, y).unwrap_or_default();
+      *value = component_from_f64(v);
+    }
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ImageBuffer;
+
+  /// Smooth blobs that are easy to align
+  fn scene(width: usize, height: usize) -> ImageBuffer<f32, 1, false> {
+    let blobs = [(20.0, 14.0, 6.0), (40.0, 36.0, 9.0), (14.0, 44.0, 5.0)];
+    ImageBuffer::empty(width, height).map_indexed(&mut |x, y, _| {
+      let v: f64 = blobs
+        .iter()
+        .map(|(bx, by, r)| {
+          let d = (x as f64 - bx).powi(2) + (y as f64 - by).powi(2);
+          (-d / (2.0 * r * r)).exp()
+        })
+        .sum();
+      [v as f32]
+    })
+  }
+
+  #[test]
+  fn phase_correlation_finds_translation() {
+    let reference = scene(64, 64);
+    let moving = warp(&reference, &Affine::translation(-5.0, 3.0));
+    let found = phase_correlation(&reference, &moving).unwrap();
+    let (dx, dy) = found.transform.offset();
+    assert!(
+      (dx - 5.0).abs() < 0.5 && (dy + 3.0).abs() < 0.5,
+      "{dx} {dy}"
+    );
+    assert!(found.confidence > 0.2);
+    assert!(matches!(
+      phase_correlation(&reference, &scene(32, 64)),
+      Err(Error::DimensionMismatch { .. })
+    ));
+  }
+
+  #[test]
+  fn register_recovers_small_affine() {
+    let reference = scene(64, 64);
+    let truth = Affine {
+      matrix: [[1.02, 0.01, -2.3], [-0.015, 0.99, 1.6]],
+    };
+    // `moving` is `reference` seen through the inverse of `truth`, so
+    // warping it by `truth` realigns it
+    let plane = luma(&reference);
+    let moving = ImageBuffer::<f32, 1, false>::empty(64, 64).map_indexed(
+      &mut |x, y, _| {
+        let [[a, b, c], [d, e, f]] = truth.matrix;
+        let det = a * e - b * d;
+        let (x, y) = (x as f64 - c, y as f64 - f);
+        let (rx, ry) = ((e * x - b * y) / det, (a * y - d * x) / det);
+        let r = sample(&plane, 64, 64, rx, ry).unwrap_or_default();
+        [r as f32]
+      },
+    );
+    let found = register(&reference, &moving, 100).unwrap();
+    for (row, expected) in found.transform.matrix.iter().zip(truth.matrix) {
+      for (v, e) in row.iter().zip(expected) {
+        assert!((v - e).abs() < 0.02, "{:?}", found.transform);
+      }
+    }
+    assert!(found.confidence > 0.99);
+  }
+}
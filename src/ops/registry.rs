@@ -0,0 +1,90 @@
+//! A process-wide registry of named operations, so that filters from other
+//! crates can be discovered and applied at runtime, for example by name
+//! from a command line or a saved recipe.
+
+use std::sync::{Arc, RwLock};
+
+use crate::{
+  error::Result,
+  ops::progressive::{by_resolution, Refinement},
+  progress::Progress,
+  Image,
+};
+
+/// An operation on whole images that can be registered with
+/// [`register_op`]
+pub trait ImageOp: Send + Sync {
+  /// Unique name the operation is looked up by
+  fn name(&self) -> &str;
+
+  /// One-line summary for listings
+  fn description(&self) -> &str { "" }
+
+  fn apply(&self, image: &Image) -> Result<Image>;
+
+  /// Intermediate results at increasing quality, ending with the result of
+  /// [`apply`](Self::apply). By default the operation runs on copies of the
+  /// image at an eighth, a quarter and half the size first; see
+  /// [`by_resolution`](super::progressive::by_resolution). Operations that
+  /// improve their result iteratively can yield it after each iteration
+  /// instead.
+  fn refine<'a>(
+    &'a self,
+    image: &'a Image,
+    progress: Progress,
+  ) -> Box<dyn Iterator<Item = Result<Refinement<Image>>> + 'a> {
+    Box::new(by_resolution(self, image, 4, progress))
+  }
+}
+
+static OPS: RwLock<Vec<Arc<dyn ImageOp>>> = RwLock::new(Vec::new());
+
+/// Makes `op` available through [`find_op`], replacing any operation
+/// registered under the same name
+pub fn register_op(op: Arc<dyn ImageOp>) {
+  let mut ops = OPS.write().unwrap_or_else(|e| e.into_inner());
+  ops.retain(|existing| existing.name() != op.name());
+  ops.push(op);
+}
+
+/// The registered operation called `name`
+pub fn find_op(name: &str) -> Option<Arc<dyn ImageOp>> {
+  let ops = OPS.read().unwrap_or_else(|e| e.into_inner());
+  ops.iter().find(|op| op.name() == name).cloned()
+}
+
+/// Names of all registered operations, in registration order
+pub fn op_names() -> Vec<String> {
+  let ops = OPS.read().unwrap_or_else(|e| e.into_inner());
+  ops.iter().map(|op| op.name().to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{color_space::ColorSpace, ImageBuffer};
+
+  struct Halve;
+
+  impl ImageOp for Halve {
+    fn name(&self) -> &str { "test-halve" }
+
+    fn apply(&self, image: &Image) -> Result<Image> {
+      let rgba = image.to_rgba_f32()?;
+      let half =
+        crate::ops::transform::resize(&rgba, rgba.width / 2, rgba.height / 2);
+      Ok(Image::new_f32(ColorSpace::Rgba(half)))
+    }
+  }
+
+  #[test]
+  fn registered_ops_are_discoverable() {
+    register_op(Arc::new(Halve));
+    register_op(Arc::new(Halve));
+    assert_eq!(op_names().iter().filter(|n| *n == "test-halve").count(), 1);
+    let image = Image::new_u8(ColorSpace::Rgb(ImageBuffer::empty(8, 4)));
+    let halved = find_op("test-halve").unwrap().apply(&image).unwrap();
+    assert_eq!((halved.width(), halved.height()), (4, 2));
+    assert!(find_op("missing").is_none());
+  }
+}
@@ -0,0 +1,52 @@
+//! Resampling algorithms for particular kinds of content. General-purpose
+//! resizing is in [`transform`](crate::ops::transform).
+
+use crate::{
+  color_space::{linear_to_srgb, srgb_to_linear},
+  pixel::{component_from_f64, PixelComponent},
+};
+
+pub mod pixel_art;
+
+/// Settings shared by the filtering operations:
+/// [`resize_with`](crate::ops::transform::resize_with) and
+/// [`gaussian_blur_with`](crate::ops::blur::gaussian_blur_with)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ResampleOptions {
+  /// Filters in linear light, decoding sRGB values first and encoding the
+  /// result again. Averaging encoded values darkens edges between light and
+  /// dark areas and shifts the color of fine detail; linear filtering does
+  /// not. Alpha is filtered as it is.
+  pub linearize: bool,
+}
+
+impl ResampleOptions {
+  /// Component `c` of a pixel, as a value to filter
+  pub(crate) fn decode<T: PixelComponent>(
+    &self,
+    value: T,
+    c: usize,
+    alpha: Option<usize>,
+  ) -> f64 {
+    let v = value.to_f64().unwrap_or_default();
+    if !self.linearize || alpha == Some(c) {
+      return v;
+    }
+    let white = T::WHITE.to_f64().unwrap_or(1.0);
+    srgb_to_linear(v / white) * white
+  }
+
+  /// The inverse of [`decode`](Self::decode) for a filtered value
+  pub(crate) fn encode<T: PixelComponent>(
+    &self,
+    value: f64,
+    c: usize,
+    alpha: Option<usize>,
+  ) -> T {
+    if !self.linearize || alpha == Some(c) {
+      return component_from_f64(value);
+    }
+    let white = T::WHITE.to_f64().unwrap_or(1.0);
+    component_from_f64(linear_to_srgb(value / white) * white)
+  }
+}
@@ -0,0 +1,345 @@
+//! Integer upscaling of pixel art.
+//!
+//! Filtered resizing smears the hard edges of sprites, and nearest-neighbor
+//! scaling keeps their staircases. These algorithms look at each pixel's
+//! neighbors to tell which way an edge runs and fill the enlarged pixel to
+//! follow it. [`scale2x`] and [`scale3x`] only copy existing colors, so they
+//! keep a palette intact; [`similarity_blend`] and [`edge_directed`] blend
+//! colors along edges for smoother results. Pixels past the border repeat
+//! the edge pixel.
+//!
+//! The blending scalers borrow ideas from hqx and xBRZ but are not those
+//! filters, and their output does not match the reference implementations.
+
+use crate::{
+  error::{Error, Result},
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// The pixel at `(x, y)`, clamped to the image
+fn at<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  x: isize,
+  y: isize,
+) -> &[T; N] {
+  let x = x.clamp(0, image.width as isize - 1) as usize;
+  let y = y.clamp(0, image.height as isize - 1) as usize;
+  image.get_pixel(x, y)
+}
+
+/// Enlarges `image` by `factor`, filling the `factor` by `factor` block of
+/// each source pixel with `fill(x, y, block)`. Blocks are row-major.
+fn enlarge<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  factor: usize,
+  mut fill: impl FnMut(isize, isize, &mut [[T; N]]),
+) -> ImageBuffer<T, N, A> {
+  let mut result =
+    ImageBuffer::empty(image.width * factor, image.height * factor);
+  let mut block = vec![[T::zero(); N]; factor * factor];
+  for y in 0..image.height {
+    for x in 0..image.width {
+      fill(x as isize, y as isize, &mut block);
+      for (i, pel) in block.iter().enumerate() {
+        let (bx, by) = (i % factor, i / factor);
+        *result.get_pixel_mut(x * factor + bx, y * factor + by) = *pel;
+      }
+    }
+  }
+  result
+}
+
+/// Luma and chroma of the color channels of `pel`, between 0 and 1. Gray
+/// pixels have no chroma.
+fn yuv<T: PixelComponent, const N: usize>(
+  pel: &[T; N],
+  colors: usize,
+  white: f64,
+) -> [f64; 3] {
+  let unit = |c: usize| pel[c].to_f64().unwrap_or_default() / white;
+  if colors < 3 {
+    return [unit(0), 0.0, 0.0];
+  }
+  let (r, g, b) = (unit(0), unit(1), unit(2));
+  let y = 0.299 * r + 0.587 * g + 0.114 * b;
+  [y, 0.492 * (b - y), 0.877 * (r - y)]
+}
+
+/// Difference between two colors, weighted like hqx's thresholds so that
+/// luma counts more than chroma
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+  48.0 * (a[0] - b[0]).abs()
+    + 7.0 * (a[1] - b[1]).abs()
+    + 6.0 * (a[2] - b[2]).abs()
+}
+
+/// Whether two colors are within hqx's thresholds of 48, 7 and 6 levels of
+/// 255 in luma and chroma
+fn similar(a: [f64; 3], b: [f64; 3]) -> bool {
+  (a[0] - b[0]).abs() <= 48.0 / 255.0
+    && (a[1] - b[1]).abs() <= 7.0 / 255.0
+    && (a[2] - b[2]).abs() <= 6.0 / 255.0
+}
+
+/// `a` moved a fraction `w` of the way towards `b`
+fn mix<T: PixelComponent, const N: usize>(
+  a: &[T; N],
+  b: &[T; N],
+  w: f64,
+) -> [T; N] {
+  std::array::from_fn(|c| {
+    let (a, b) = (
+      a[c].to_f64().unwrap_or_default(),
+      b[c].to_f64().unwrap_or_default(),
+    );
+    component_from_f64(a + (b - a) * w)
+  })
+}
+
+/// Doubles the size of `image` with the Scale2x (EPX) algorithm, rounding
+/// the corners of diagonal staircases without introducing new colors
+pub fn scale2x<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+) -> ImageBuffer<T, N, A> {
+  enlarge(image, 2, |x, y, block| {
+    let e = at(image, x, y);
+    let b = at(image, x, y - 1);
+    let d = at(image, x - 1, y);
+    let f = at(image, x + 1, y);
+    let h = at(image, x, y + 1);
+    block.fill(*e);
+    if b != h && d != f {
+      if d == b {
+        block[0] = *d;
+      }
+      if b == f {
+        block[1] = *f;
+      }
+      if d == h {
+        block[2] = *d;
+      }
+      if h == f {
+        block[3] = *f;
+      }
+    }
+  })
+}
+
+/// Triples the size of `image` with the Scale3x (AdvMAME3x) algorithm, the
+/// counterpart of [`scale2x`] for a factor of three
+pub fn scale3x<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+) -> ImageBuffer<T, N, A> {
+  enlarge(image, 3, |x, y, block| {
+    let p = |dx, dy| at(image, x + dx, y + dy);
+    let (a, b, c) = (p(-1, -1), p(0, -1), p(1, -1));
+    let (d, e, f) = (p(-1, 0), p(0, 0), p(1, 0));
+    let (g, h, i) = (p(-1, 1), p(0, 1), p(1, 1));
+    block.fill(*e);
+    if b == h || d == f {
+      return;
+    }
+    let (top_left, top_right) = (d == b, b == f);
+    let (bottom_left, bottom_right) = (d == h, h == f);
+    if top_left {
+      block[0] = *d;
+    }
+    if (top_left && e != c) || (top_right && e != a) {
+      block[1] = *b;
+    }
+    if top_right {
+      block[2] = *f;
+    }
+    if (top_left && e != g) || (bottom_left && e != a) {
+      block[3] = *d;
+    }
+    if (top_right && e != i) || (bottom_right && e != c) {
+      block[5] = *f;
+    }
+    if bottom_left {
+      block[6] = *d;
+    }
+    if (bottom_left && e != i) || (bottom_right && e != g) {
+      block[7] = *h;
+    }
+    if bottom_right {
+      block[8] = *f;
+    }
+  })
+}
+
+/// Checks that `factor` is between 2 and `max`
+fn check_factor(name: &str, factor: usize, max: usize) -> Result<()> {
+  if (2..=max).contains(&factor) {
+    Ok(())
+  } else {
+    Err(Error::InvalidArgument(format!(
+      "{name} scales by 2 to {max} times, not {factor}"
+    )))
+  }
+}
+
+/// Enlarges `image` by `factor`, between 2 and 4: neighbors are compared
+/// by their difference in luma and chroma rather than exactly, edges that
+/// cut across a corner are smoothed, and pixels blend slightly into similar
+/// neighbors to soften gradients.
+///
+/// The similarity thresholds are hqx's, but the blend is decided from the
+/// neighbors directly rather than through hqx's pattern tables, so this is
+/// not hqx.
+pub fn similarity_blend<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  factor: usize,
+) -> Result<ImageBuffer<T, N, A>> {
+  check_factor("similarity_blend", factor, 4)?;
+  let colors = N - usize::from(A);
+  let white = image.white();
+  let f = factor as f64;
+  Ok(enlarge(image, factor, |x, y, block| {
+    let e = at(image, x, y);
+    let ey = yuv(e, colors, white);
+    for (i, out) in block.iter_mut().enumerate() {
+      // Offset of the subpixel from the center of the source pixel
+      let dx = ((i % factor) as f64 + 0.5) / f - 0.5;
+      let dy = ((i / factor) as f64 + 0.5) / f - 0.5;
+      let (sx, sy) = (dx.signum() as isize, dy.signum() as isize);
+      let across = at(image, x + sx, y);
+      let down = at(image, x, y + sy);
+      let (ay, dny) = (yuv(across, colors, white), yuv(down, colors, white));
+      let mut pel = *e;
+      if dx != 0.0
+        && dy != 0.0
+        && similar(ay, dny)
+        && !similar(ey, ay)
+        && !similar(ey, dny)
+      {
+        let edge = mix(across, down, 0.5);
+        let w = (2.0 * (dx.abs() + dy.abs()) - 0.5).clamp(0.0, 1.0);
+        pel = mix(&pel, &edge, w);
+      } else {
+        if dx != 0.0 && similar(ey, ay) {
+          pel = mix(&pel, across, dx.abs() / 2.0);
+        }
+        if dy != 0.0 && similar(ey, dny) {
+          pel = mix(&pel, down, dy.abs() / 2.0);
+        }
+      }
+      *out = pel;
+    }
+  }))
+}
+
+/// Enlarges `image` by `factor`, between 2 and 6, with the diagonal edge
+/// detection of the xBR family. For each corner of a pixel, the color
+/// differences along and across the diagonal in a 5x5 neighborhood decide
+/// whether an edge cuts it off; the part of the corner beyond the edge is
+/// filled with the closer neighbor, antialiased over a subpixel.
+///
+/// Only 45 degree edges are detected. xBRZ's handling of shallow and steep
+/// lines, and its other rules, are not implemented, so this is not xBRZ.
+pub fn edge_directed<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  factor: usize,
+) -> Result<ImageBuffer<T, N, A>> {
+  check_factor("edge_directed", factor, 6)?;
+  let colors = N - usize::from(A);
+  let white = image.white();
+  let f = factor as f64;
+  Ok(enlarge(image, factor, |x, y, block| {
+    let e = at(image, x, y);
+    block.fill(*e);
+    for (sx, sy) in [(1, 1), (-1, 1), (1, -1), (-1, -1)] {
+      // Neighbors named as for the bottom-right corner, mirrored to the
+      // others
+      let p = |dx: isize, dy: isize| at(image, x + dx * sx, y + dy * sy);
+      let c = |dx, dy| yuv(p(dx, dy), colors, white);
+      let (f_pel, h_pel) = (p(1, 0), p(0, 1));
+      if f_pel == e || h_pel == e {
+        continue;
+      }
+      let (ec, fc, hc, ic) = (c(0, 0), c(1, 0), c(0, 1), c(1, 1));
+      let along = distance(ec, c(1, -1))
+        + distance(ec, c(-1, 1))
+        + distance(ic, c(2, 0))
+        + distance(ic, c(0, 2))
+        + 4.0 * distance(hc, fc);
+      let across = distance(hc, c(-1, 0))
+        + distance(hc, c(1, 2))
+        + distance(fc, c(2, 1))
+        + distance(fc, c(0, -1))
+        + 4.0 * distance(ec, ic);
+      if along >= across {
+        continue;
+      }
+      let fill = if distance(ec, fc) <= distance(ec, hc) {
+        f_pel
+      } else {
+        h_pel
+      };
+      for (i, out) in block.iter_mut().enumerate() {
+        // Position within the pixel measured towards this corner
+        let mut u = ((i % factor) as f64 + 0.5) / f;
+        let mut v = ((i / factor) as f64 + 0.5) / f;
+        if sx < 0 {
+          u = 1.0 - u;
+        }
+        if sy < 0 {
+          v = 1.0 - v;
+        }
+        let w = ((u + v - 1.5) * f + 0.5).clamp(0.0, 1.0);
+        if w > 0.0 {
+          *out = mix(out, fill, w);
+        }
+      }
+    }
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A black diagonal staircase on white
+  fn staircase() -> ImageBuffer<u8, 1, false> {
+    ImageBuffer::<u8, 1, false>::empty(4, 4)
+      .map_indexed(&mut |x, y, _| [if x > y { 255 } else { 0 }])
+  }
+
+  #[test]
+  fn scale2x_rounds_staircases() {
+    let image = staircase();
+    let scaled = scale2x(&image);
+    assert_eq!((scaled.width, scaled.height), (8, 8));
+    // The white pixel at (1, 0) gains a black corner towards the diagonal
+    assert_eq!(scaled.get_pixel(2, 1), &[0]);
+    assert_eq!(scaled.get_pixel(3, 0), &[255]);
+    assert!(scaled.iter_pixels().all(|p| p[0] == 0 || p[0] == 255));
+
+    let tripled = scale3x(&image);
+    assert_eq!((tripled.width, tripled.height), (12, 12));
+    assert_eq!(tripled.get_pixel(3, 2), &[0]);
+    assert!(tripled.iter_pixels().all(|p| p[0] == 0 || p[0] == 255));
+  }
+
+  #[test]
+  fn blending_scalers_smooth_edges() {
+    let image = staircase();
+    for scaled in [
+      similarity_blend(&image, 2).unwrap(),
+      edge_directed(&image, 2).unwrap(),
+    ] {
+      assert_eq!((scaled.width, scaled.height), (8, 8));
+      assert_eq!(scaled.get_pixel(7, 0), &[255]);
+      assert_eq!(scaled.get_pixel(0, 7), &[0]);
+      assert!(scaled.iter_pixels().any(|p| p[0] > 0 && p[0] < 255));
+    }
+    let flat = ImageBuffer::<u8, 3, false>::with_val(&[10, 20, 30], 3, 3);
+    assert!(edge_directed(&flat, 6)
+      .unwrap()
+      .iter_pixels()
+      .all(|p| p == &[10, 20, 30]));
+    assert!(similarity_blend(&flat, 5).is_err());
+    assert!(edge_directed(&flat, 1).is_err());
+  }
+}
@@ -0,0 +1,257 @@
+//! Sprite sheets: cutting frames out of a sheet or an image sequence laid
+//! out on a grid, and packing separate images into one atlas.
+
+use crate::{
+  error::{Error, Result},
+  limits::Limits,
+  ops::transform::crop,
+  pixel::{PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// A rectangle of pixels within a sheet or atlas
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rect {
+  pub x:      usize,
+  pub y:      usize,
+  pub width:  usize,
+  pub height: usize,
+}
+
+impl Rect {
+  fn right(&self) -> usize { self.x + self.width }
+
+  fn bottom(&self) -> usize { self.y + self.height }
+
+  fn contains(&self, other: &Rect) -> bool {
+    other.x >= self.x
+      && other.y >= self.y
+      && other.right() <= self.right()
+      && other.bottom() <= self.bottom()
+  }
+
+  fn intersects(&self, other: &Rect) -> bool {
+    self.x < other.right()
+      && other.x < self.right()
+      && self.y < other.bottom()
+      && other.y < self.bottom()
+  }
+}
+
+/// Cuts `image` into `cols` by `rows` frames of equal size, returned row by
+/// row. Pixels left over on the right and bottom edges, when the size does
+/// not divide evenly, are not part of any frame. Fails if the frames would
+/// be empty.
+pub fn slice_grid<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  cols: usize,
+  rows: usize,
+) -> Result<Vec<ImageBuffer<T, N, A>>> {
+  let width = image.width.checked_div(cols).unwrap_or_default();
+  let height = image.height.checked_div(rows).unwrap_or_default();
+  if width == 0 || height == 0 {
+    return Err(Error::InvalidArgument(format!(
+      "A {}x{} image cannot be cut into {cols}x{rows} frames",
+      image.width, image.height
+    )));
+  }
+  let cells = (0..rows).flat_map(|row| {
+    (0..cols).map(move |col| {
+      Rect {
+        x: col * width,
+        y: row * height,
+        width,
+        height,
+      }
+    })
+  });
+  slice_regions(image, &cells.collect::<Vec<_>>())
+}
+
+/// Copies each of `rects` out of `image`. Fails if any of them extends past
+/// the edges.
+pub fn slice_regions<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  rects: &[Rect],
+) -> Result<Vec<ImageBuffer<T, N, A>>> {
+  rects
+    .iter()
+    .map(|r| crop(image, r.x, r.y, r.width, r.height))
+    .collect()
+}
+
+/// Images packed by [`pack`]
+#[derive(Clone, Debug)]
+pub struct Atlas<T: PixelComponent, const N: usize, const A: bool> {
+  pub image:      ImageBuffer<T, N, A>,
+  /// Where each input image was placed, in the order they were given
+  pub placements: Vec<Rect>,
+}
+
+/// Packs `images` into one atlas no larger than `max_size` in either
+/// direction, without rotating them. The atlas is cropped to the area in
+/// use, and the space between images is zeroed.
+///
+/// Uses the MaxRects algorithm (Jylänki), placing the largest images first,
+/// each where it leaves the least space on its shorter side. Fails if the
+/// images do not all fit.
+pub fn pack<T: PixelComponent, const N: usize, const A: bool>(
+  images: &[ImageBuffer<T, N, A>],
+  max_size: usize,
+) -> Result<Atlas<T, N, A>> {
+  let mut order: Vec<usize> = (0..images.len()).collect();
+  order.sort_by_key(|&i| {
+    let image = &images[i];
+    std::cmp::Reverse((
+      image.width * image.height,
+      image.width.max(image.height),
+    ))
+  });
+
+  let mut free = vec![Rect {
+    x:      0,
+    y:      0,
+    width:  max_size,
+    height: max_size,
+  }];
+  let mut placements = vec![Rect::default(); images.len()];
+  for i in order {
+    let (width, height) = (images[i].width, images[i].height);
+    if width == 0 || height == 0 {
+      continue;
+    }
+    let Some(spot) = free
+      .iter()
+      .filter(|f| f.width >= width && f.height >= height)
+      .min_by_key(|f| {
+        let (dw, dh) = (f.width - width, f.height - height);
+        (dw.min(dh), dw.max(dh))
+      })
+    else {
+      return Err(Error::InvalidArgument(format!(
+        "Image {i} of {width}x{height} does not fit in a \
+         {max_size}x{max_size} atlas"
+      )));
+    };
+    let placed = Rect {
+      x: spot.x,
+      y: spot.y,
+      width,
+      height,
+    };
+    placements[i] = placed;
+    split_free(&mut free, &placed);
+  }
+
+  let width = placements.iter().map(Rect::right).max().unwrap_or(0);
+  let height = placements.iter().map(Rect::bottom).max().unwrap_or(0);
+  let mut atlas = ImageBuffer::try_empty(width, height, &Limits::none())?;
+  for (image, place) in images.iter().zip(&placements) {
+    let row_len = place.width * N;
+    for (row, src) in
+      image.components().chunks_exact(row_len.max(1)).enumerate()
+    {
+      let start = ((place.y + row) * width + place.x) * N;
+      atlas.components_mut()[start..start + row_len].copy_from_slice(src);
+    }
+  }
+  Ok(Atlas {
+    image: atlas,
+    placements,
+  })
+}
+
+/// Removes `used` from the free rectangles, replacing each one it overlaps
+/// with the largest rectangles around it, then drops rectangles contained
+/// in others
+fn split_free(free: &mut Vec<Rect>, used: &Rect) {
+  let mut next = Vec::with_capacity(free.len() + 4);
+  for f in free.iter() {
+    if !f.intersects(used) {
+      next.push(*f);
+      continue;
+    }
+    if used.x > f.x {
+      next.push(Rect {
+        width: used.x - f.x,
+        ..*f
+      });
+    }
+    if used.right() < f.right() {
+      next.push(Rect {
+        x: used.right(),
+        width: f.right() - used.right(),
+        ..*f
+      });
+    }
+    if used.y > f.y {
+      next.push(Rect {
+        height: used.y - f.y,
+        ..*f
+      });
+    }
+    if used.bottom() < f.bottom() {
+      next.push(Rect {
+        y: used.bottom(),
+        height: f.bottom() - used.bottom(),
+        ..*f
+      });
+    }
+  }
+  let mut i = 0;
+  while i < next.len() {
+    let contained = (0..next.len()).any(|j| {
+      j != i && next[j].contains(&next[i]) && (next[j] != next[i] || j < i)
+    });
+    if contained {
+      next.swap_remove(i);
+    } else {
+      i += 1;
+    }
+  }
+  *free = next;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn slices_and_packs_sprites() {
+    let sheet = ImageBuffer::<u8, 1, false>::empty(9, 4)
+      .map_indexed(&mut |x, y, _| [(x / 3 + y / 2 * 3) as u8]);
+    let frames = slice_grid(&sheet, 3, 2).unwrap();
+    assert_eq!(frames.len(), 6);
+    assert!(frames[4].components().iter().all(|&v| v == 4));
+    assert!(slice_grid(&sheet, 10, 1).is_err());
+    let regions = [Rect {
+      x:      2,
+      y:      1,
+      width:  2,
+      height: 2,
+    }];
+    let cut = slice_regions(&sheet, &regions).unwrap();
+    assert_eq!(cut[0].components(), &[0, 1, 3, 4]);
+
+    let sizes = [(4, 4), (2, 6), (6, 2), (3, 3), (1, 1)];
+    let images: Vec<_> = sizes
+      .iter()
+      .enumerate()
+      .map(|(i, &(w, h))| {
+        ImageBuffer::<u8, 1, false>::with_val(&[i as u8 + 1], w, h)
+      })
+      .collect();
+    let atlas = pack(&images, 10).unwrap();
+    assert!(atlas.image.width <= 10 && atlas.image.height <= 10);
+    for (i, place) in atlas.placements.iter().enumerate() {
+      assert_eq!((place.width, place.height), sizes[i]);
+      for b in &atlas.placements[i + 1..] {
+        assert!(!place.intersects(b));
+      }
+      let region = slice_regions(&atlas.image, &[*place]).unwrap();
+      assert!(region[0].components().iter().all(|&v| v == i as u8 + 1));
+    }
+    assert!(pack(&images, 5).is_err());
+  }
+}
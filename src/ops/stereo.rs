@@ -0,0 +1,508 @@
+//! Composing stereo pairs for 3D displays: colored-glasses anaglyphs,
+//! side-by-side frames for 3D TVs and headsets, and interleaved frames for
+//! polarized and lenticular screens. Also matching the views of a
+//! rectified pair to measure disparity, from which depth follows.
+
+use crate::{
+  error::{Error, Result},
+  limits::Limits,
+  ops::{
+    depth::DepthMap,
+    meter::luminance,
+    transform::{crop, resize},
+  },
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  video::check_dimensions,
+  ImageBuffer,
+};
+
+/// How [`anaglyph`] routes the two views to the color channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnaglyphMode {
+  /// Red-cyan glasses, keeping the full color of each view. Brightest and
+  /// most colorful, but saturated reds and cyans show in one eye only.
+  Color,
+  /// Red-cyan glasses, with the left view in gray to reduce retinal rivalry
+  HalfColor,
+  /// Red-cyan glasses, with both views in gray
+  Gray,
+  /// Red-cyan glasses, using Dubois' least-squares projection to reduce
+  /// ghosting at some cost to color fidelity
+  Dubois,
+  /// Green-magenta glasses
+  GreenMagenta,
+  /// Amber-blue glasses, as in ColorCode 3-D
+  AmberBlue,
+}
+
+type Matrix = [[f64; 3]; 3];
+
+const GRAY: [f64; 3] = [0.299, 0.587, 0.114];
+const ZERO: [f64; 3] = [0.0; 3];
+
+impl AnaglyphMode {
+  /// The matrices mapping the left and right views' RGB to the output's
+  fn matrices(self) -> (Matrix, Matrix) {
+    match self {
+      Self::Color =>
+        (
+          [[1.0, 0.0, 0.0], ZERO, ZERO],
+          [ZERO, [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        ),
+      Self::HalfColor =>
+        ([GRAY, ZERO, ZERO], [ZERO, [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]),
+      Self::Gray => ([GRAY, ZERO, ZERO], [ZERO, GRAY, GRAY]),
+      Self::Dubois =>
+        (
+          [
+            [0.456, 0.500, 0.176],
+            [-0.040, -0.038, -0.016],
+            [-0.015, -0.021, -0.005],
+          ],
+          [
+            [-0.043, -0.088, -0.002],
+            [0.378, 0.734, -0.018],
+            [-0.072, -0.113, 1.226],
+          ],
+        ),
+      Self::GreenMagenta =>
+        (
+          [ZERO, [0.0, 1.0, 0.0], ZERO],
+          [[1.0, 0.0, 0.0], ZERO, [0.0, 0.0, 1.0]],
+        ),
+      Self::AmberBlue =>
+        ([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], ZERO], [ZERO, ZERO, GRAY]),
+    }
+  }
+}
+
+/// How [`interleaved`] alternates between the two views
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interleave {
+  /// Even rows from the left view, odd rows from the right, for
+  /// line-polarized screens
+  Rows,
+  /// Even columns from the left view, odd columns from the right, for
+  /// lenticular and parallax-barrier screens
+  Columns,
+  /// Alternating pixels in a checkerboard, for DLP 3D projectors
+  Checkerboard,
+}
+
+fn check_pair<T: PixelComponent, const N: usize, const A: bool>(
+  left: &ImageBuffer<T, N, A>,
+  right: &ImageBuffer<T, N, A>,
+) -> Result<()> {
+  check_dimensions((left.width, left.height), right)
+}
+
+/// Shifts the plane that appears at screen depth by moving the views
+/// `offset` pixels apart horizontally: positive offsets bring the scene
+/// nearer the viewer and negative ones push it back. Both views are cropped
+/// to the region they still share, so they stay the same size.
+pub fn converge<T: PixelComponent, const N: usize, const A: bool>(
+  left: &ImageBuffer<T, N, A>,
+  right: &ImageBuffer<T, N, A>,
+  offset: isize,
+) -> Result<(ImageBuffer<T, N, A>, ImageBuffer<T, N, A>)> {
+  check_pair(left, right)?;
+  let shift = offset.unsigned_abs();
+  if shift >= left.width {
+    return Err(Error::InvalidArgument(format!(
+      "Convergence offset {offset} leaves no overlap between {} pixel wide \
+       views",
+      left.width
+    )));
+  }
+  let width = left.width - shift;
+  // Moving the left view right and the right view left brings points
+  // nearer, so the left view keeps its left part
+  let (left_x, right_x) = if offset >= 0 { (0, shift) } else { (shift, 0) };
+  Ok((
+    crop(left, left_x, 0, width, left.height)?,
+    crop(right, right_x, 0, width, right.height)?,
+  ))
+}
+
+/// Combines a stereo pair into a single image to be seen through colored
+/// glasses, the left eye's filter being the first named color in `mode`.
+/// Alpha is taken from the left view. Fails unless the views are the same
+/// size with at least three color channels.
+pub fn anaglyph<T: PixelComponent, const N: usize, const A: bool>(
+  left: &ImageBuffer<T, N, A>,
+  right: &ImageBuffer<T, N, A>,
+  mode: AnaglyphMode,
+) -> Result<ImageBuffer<T, N, A>> {
+  check_pair(left, right)?;
+  let colors = if A { N - 1 } else { N };
+  if colors < 3 {
+    return Err(Error::InvalidArgument(format!(
+      "Anaglyphs need RGB views, not {colors} color channels"
+    )));
+  }
+  let (l, r) = mode.matrices();
+  let white = T::WHITE.to_f64().unwrap_or(1.0);
+  let rgb = |pel: &[T; N]| -> [f64; 3] {
+    [0, 1, 2].map(|c| pel[c].to_f64().unwrap_or_default() / white)
+  };
+  Ok(left.map_indexed(&mut |x, y, pel| {
+    let (a, b) = (rgb(pel), rgb(right.get_pixel(x, y)));
+    let mut out = *pel;
+    for c in 0..3 {
+      let dot = |m: &Matrix, v: [f64; 3]| -> f64 {
+        m[c].iter().zip(v).map(|(w, v)| w * v).sum()
+      };
+      out[c] = component_from_f64((dot(&l, a) + dot(&r, b)) * white);
+    }
+    out
+  }))
+}
+
+/// Places the views next to each other, left view on the left. With
+/// `half_width` each view is first squeezed to half its width, so the frame
+/// keeps the views' size as half side-by-side 3D TV formats expect.
+pub fn side_by_side<T: PixelComponent, const N: usize, const A: bool>(
+  left: &ImageBuffer<T, N, A>,
+  right: &ImageBuffer<T, N, A>,
+  half_width: bool,
+) -> Result<ImageBuffer<T, N, A>> {
+  check_pair(left, right)?;
+  let (left, right) = if half_width {
+    let width = (left.width / 2).max(1);
+    (
+      resize(left, width, left.height),
+      resize(right, width, right.height),
+    )
+  } else {
+    (left.clone(), right.clone())
+  };
+  let width = left.width;
+  Ok(
+    ImageBuffer::try_empty(width * 2, left.height, &Limits::none())?
+      .map_indexed(&mut |x, y, _| {
+        if x < width {
+          *left.get_pixel(x, y)
+        } else {
+          *right.get_pixel(x - width, y)
+        }
+      }),
+  )
+}
+
+/// Alternates between the views' pixels in the given pattern, starting
+/// with the left view at the top-left corner
+pub fn interleaved<T: PixelComponent, const N: usize, const A: bool>(
+  left: &ImageBuffer<T, N, A>,
+  right: &ImageBuffer<T, N, A>,
+  pattern: Interleave,
+) -> Result<ImageBuffer<T, N, A>> {
+  check_pair(left, right)?;
+  Ok(left.map_indexed(&mut |x, y, pel| {
+    let from_right = match pattern {
+      Interleave::Rows => y % 2 == 1,
+      Interleave::Columns => x % 2 == 1,
+      Interleave::Checkerboard => (x + y) % 2 == 1,
+    };
+    if from_right {
+      *right.get_pixel(x, y)
+    } else {
+      *pel
+    }
+  }))
+}
+
+/// How [`disparity`] matches pixels between the views
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Matching {
+  /// Each pixel takes the disparity whose window matches best. Fast, but
+  /// noisy in flat or repetitive regions.
+  Block,
+  /// Hirschmüller's semi-global matching: window costs are aggregated along
+  /// eight directions with penalties for disparity changes, giving smooth
+  /// surfaces with sharp edges
+  SemiGlobal,
+}
+
+/// Settings for [`disparity`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisparityOptions {
+  /// The matching algorithm
+  pub matching:        Matching,
+  /// Largest disparity searched, in pixels
+  pub max_disparity:   usize,
+  /// Radius of the square window whose mean absolute difference in
+  /// luminance is the cost of a match
+  pub block_radius:    usize,
+  /// Semi-global penalty for a disparity change of one pixel between
+  /// neighbors, in the same units as the cost
+  pub small_penalty:   f32,
+  /// Semi-global penalty for a larger disparity change between neighbors
+  pub large_penalty:   f32,
+  /// How far the disparities found matching left to right and right to left
+  /// may differ before a pixel is marked invalid, or `None` to skip the
+  /// check. Catches occlusions and mismatches.
+  pub max_lr_mismatch: Option<f32>,
+}
+
+impl Default for DisparityOptions {
+  fn default() -> Self {
+    DisparityOptions {
+      matching:        Matching::SemiGlobal,
+      max_disparity:   64,
+      block_radius:    2,
+      small_penalty:   0.03,
+      large_penalty:   0.12,
+      max_lr_mismatch: Some(1.0),
+    }
+  }
+}
+
+/// Measures the disparity of each pixel of `left`: how many pixels to the
+/// left the same point appears in `right`. The views must be rectified, so
+/// that matching points share a row. Disparity is inversely proportional
+/// to depth, which is the focal length times the baseline over the
+/// disparity.
+///
+/// Disparities are refined to subpixel precision by fitting a parabola to
+/// the costs around the best match. Pixels that fail the left-right check
+/// are NaN, which [`depth`](crate::ops::depth) treats as holes. Fails if
+/// the views differ in size.
+pub fn disparity<T: PixelComponent, const N: usize, const A: bool>(
+  left: &ImageBuffer<T, N, A>,
+  right: &ImageBuffer<T, N, A>,
+  options: &DisparityOptions,
+) -> Result<DepthMap> {
+  check_pair(left, right)?;
+  let (width, height) = (left.width, left.height);
+  let levels = options.max_disparity.min(width.saturating_sub(1)) + 1;
+  let mut costs = block_costs(left, right, levels, options.block_radius);
+  if options.matching == Matching::SemiGlobal {
+    costs = aggregate(&costs, width, height, levels, options);
+  }
+
+  let mut result = DepthMap::try_empty(width, height, &Limits::none())?;
+  let right_best: Vec<usize> = (0..width * height)
+    .map(|i| {
+      let x = i % width;
+      // The right pixel at x matches the left pixel at x + d
+      (0..levels.min(width - x))
+        .min_by(|&a, &b| {
+          costs[(i + a) * levels + a].total_cmp(&costs[(i + b) * levels + b])
+        })
+        .unwrap_or(0)
+    })
+    .collect();
+  for (i, out) in result.components_mut().iter_mut().enumerate() {
+    let c = &costs[i * levels..(i + 1) * levels];
+    let valid = levels.min(i % width + 1);
+    let best = (0..valid)
+      .min_by(|&a, &b| c[a].total_cmp(&c[b]))
+      .unwrap_or(0);
+    let mut d = best as f32;
+    if best > 0 && best + 1 < valid {
+      let (before, at, after) = (c[best - 1], c[best], c[best + 1]);
+      let curvature = before - 2.0 * at + after;
+      if curvature > 0.0 {
+        d += (before - after) / (2.0 * curvature);
+      }
+    }
+    *out = match options.max_lr_mismatch {
+      Some(limit)
+        if (right_best[i - best] as f32 - best as f32).abs() > limit =>
+        f32::NAN,
+      _ => d,
+    };
+  }
+  Ok(result)
+}
+
+/// The mean absolute difference in luminance between each left window and
+/// the right window `d` pixels to its left, for each disparity `d` below
+/// `levels`, indexed by pixel then disparity. Windows reaching past the
+/// right view's left edge cost the most.
+fn block_costs<T: PixelComponent, const N: usize, const A: bool>(
+  left: &ImageBuffer<T, N, A>,
+  right: &ImageBuffer<T, N, A>,
+  levels: usize,
+  radius: usize,
+) -> Vec<f32> {
+  let (width, height) = (left.width, left.height);
+  let luma = |image: &ImageBuffer<T, N, A>| -> Vec<f64> {
+    image
+      .iter_pixels()
+      .map(luminance::<ImageBuffer<T, N, A>>)
+      .collect()
+  };
+  let (l, r) = (luma(left), luma(right));
+  let mut costs = vec![0.0; width * height * levels];
+  // Summed-area table of the differences at one disparity
+  let mut table = vec![0.0f64; (width + 1) * (height + 1)];
+  for d in 0..levels {
+    for y in 0..height {
+      let mut row = 0.0;
+      for x in 0..width {
+        let i = y * width + x;
+        row += if x >= d { (l[i] - r[i - d]).abs() } else { 1.0 };
+        table[(y + 1) * (width + 1) + x + 1] =
+          table[y * (width + 1) + x + 1] + row;
+      }
+    }
+    for y in 0..height {
+      let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+      for x in 0..width {
+        let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+        let sum = table[y1 * (width + 1) + x1]
+          - table[y0 * (width + 1) + x1]
+          - table[y1 * (width + 1) + x0]
+          + table[y0 * (width + 1) + x0];
+        let area = ((x1 - x0) * (y1 - y0)) as f64;
+        costs[(y * width + x) * levels + d] = (sum / area) as f32;
+      }
+    }
+  }
+  costs
+}
+
+/// Sums the costs along paths in eight directions, each path adding the
+/// penalties for disparity changes between neighbors (Hirschmüller, 2008)
+fn aggregate(
+  costs: &[f32],
+  width: usize,
+  height: usize,
+  levels: usize,
+  options: &DisparityOptions,
+) -> Vec<f32> {
+  let (p1, p2) = (
+    options.small_penalty,
+    options.large_penalty.max(options.small_penalty),
+  );
+  let mut total = vec![0.0f32; costs.len()];
+  for (dx, dy) in [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (-1, 1),
+    (1, -1),
+    (-1, -1),
+  ] {
+    // Path costs of the previous and current rows in this direction
+    let mut previous = vec![0.0f32; width * levels];
+    let mut current = vec![0.0f32; width * levels];
+    let rows: Vec<usize> = if dy >= 0 {
+      (0..height).collect()
+    } else {
+      (0..height).rev().collect()
+    };
+    let columns: Vec<usize> = if dx >= 0 {
+      (0..width).collect()
+    } else {
+      (0..width).rev().collect()
+    };
+    for (n, &y) in rows.iter().enumerate() {
+      for (m, &x) in columns.iter().enumerate() {
+        let cost = &costs[(y * width + x) * levels..][..levels];
+        // The previous pixel on the path, if it is inside the image
+        let before = if dy == 0 {
+          (m > 0)
+            .then(|| &current[(x as isize - dx) as usize * levels..][..levels])
+        } else {
+          let px = x as isize - dx;
+          (n > 0 && px >= 0 && (px as usize) < width)
+            .then(|| &previous[px as usize * levels..][..levels])
+        };
+        let path: Vec<f32> = match before {
+          None => cost.to_vec(),
+          Some(before) => {
+            let lowest = before.iter().copied().fold(f32::INFINITY, f32::min);
+            (0..levels)
+              .map(|d| {
+                let mut best = before[d].min(lowest + p2);
+                if d > 0 {
+                  best = best.min(before[d - 1] + p1);
+                }
+                if d + 1 < levels {
+                  best = best.min(before[d + 1] + p1);
+                }
+                cost[d] + best - lowest
+              })
+              .collect()
+          }
+        };
+        current[x * levels..][..levels].copy_from_slice(&path);
+        for (t, p) in total[(y * width + x) * levels..][..levels]
+          .iter_mut()
+          .zip(&path)
+        {
+          *t += p;
+        }
+      }
+      std::mem::swap(&mut previous, &mut current);
+    }
+  }
+  total
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn stereo_pairs_compose() {
+    let left = ImageBuffer::<u8, 3, false>::empty(4, 2)
+      .map_indexed(&mut |x, _, _| [x as u8 * 10, 100, 200]);
+    let right = ImageBuffer::<u8, 3, false>::with_val(&[50, 60, 70], 4, 2);
+
+    let red_cyan = anaglyph(&left, &right, AnaglyphMode::Color).unwrap();
+    assert_eq!(red_cyan.get_pixel(2, 0), &[20, 60, 70]);
+    let gray = anaglyph(&left, &right, AnaglyphMode::Gray).unwrap();
+    assert_eq!(gray.get_pixel(0, 0)[1], gray.get_pixel(0, 0)[2]);
+
+    let (l, r) = converge(&left, &right, 1).unwrap();
+    assert_eq!((l.width, r.width), (3, 3));
+    assert_eq!(l.get_pixel(2, 0)[0], 20);
+    let (l, _) = converge(&left, &right, -1).unwrap();
+    assert_eq!(l.get_pixel(0, 0)[0], 10);
+    assert!(converge(&left, &right, 4).is_err());
+
+    let full = side_by_side(&left, &right, false).unwrap();
+    assert_eq!((full.width, full.height), (8, 2));
+    assert_eq!(full.get_pixel(5, 1), right.get_pixel(1, 1));
+    let half = side_by_side(&left, &right, true).unwrap();
+    assert_eq!((half.width, half.height), (4, 2));
+
+    let rows = interleaved(&left, &right, Interleave::Rows).unwrap();
+    assert_eq!(rows.get_pixel(1, 0), left.get_pixel(1, 0));
+    assert_eq!(rows.get_pixel(1, 1), right.get_pixel(1, 1));
+    let checker = interleaved(&left, &right, Interleave::Checkerboard).unwrap();
+    assert_eq!(checker.get_pixel(1, 0), right.get_pixel(1, 0));
+
+    let gray = ImageBuffer::<u8, 1, false>::empty(4, 2);
+    assert!(anaglyph(&gray, &gray, AnaglyphMode::Color).is_err());
+  }
+
+  #[test]
+  fn disparity_finds_shifted_texture() {
+    // A random texture seen with a disparity of 3
+    let mut seed = 7u32;
+    let mut noise = || {
+      seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+      (seed >> 24) as u8
+    };
+    let texture: Vec<u8> = (0..40 * 12).map(|_| noise()).collect();
+    let right = ImageBuffer::<u8, 1, false>::empty(32, 12)
+      .map_indexed(&mut |x, y, _| [texture[y * 40 + x + 3]]);
+    let left = ImageBuffer::<u8, 1, false>::empty(32, 12)
+      .map_indexed(&mut |x, y, _| [texture[y * 40 + x]]);
+    for matching in [Matching::Block, Matching::SemiGlobal] {
+      let options = DisparityOptions {
+        matching,
+        max_disparity: 8,
+        ..Default::default()
+      };
+      let map = disparity(&left, &right, &options).unwrap();
+      assert!((map.get_pixel(20, 6)[0] - 3.0).abs() < 0.25);
+    }
+  }
+}
@@ -0,0 +1,322 @@
+//! Stylization by mapping brightness through color ramps, and simulation of
+//! print screens.
+//!
+//! Colors are sRGB-encoded, between 0 and 1. Ramps are interpolated in
+//! linear light, so blends between saturated colors do not darken in the
+//! middle. Integer outputs of 8 bits or fewer are dithered to avoid banding in
+//! smooth gradients. Alpha is passed through.
+
+use std::f64::consts::PI;
+
+use crate::{
+  color_space::{cmyk_to_rgb, linear_to_srgb, rgb_to_cmyk, srgb_to_linear},
+  error::{Error, Result},
+  pixel::{component_from_f64, is_integer, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// 4x4 Bayer matrix for ordered dithering
+const BAYER: [[f64; 4]; 4] = [
+  [0.0, 8.0, 2.0, 10.0],
+  [12.0, 4.0, 14.0, 6.0],
+  [3.0, 11.0, 1.0, 9.0],
+  [15.0, 7.0, 13.0, 5.0],
+];
+
+/// Replaces the color of each pixel with the color at its brightness along
+/// `colors`, which are spread evenly from black to white. Brightness is the
+/// sRGB encoding of the pixel's relative luminance, so the stops fall at
+/// perceptually even steps.
+///
+/// Fails if `colors` is empty or `image` does not have three color
+/// channels.
+pub fn gradient_map<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  colors: &[[f64; 3]],
+) -> Result<ImageBuffer<T, N, A>> {
+  if colors.is_empty() {
+    return Err(Error::InvalidArgument(
+      "A gradient map needs at least one color".to_string(),
+    ));
+  }
+  if N - usize::from(A) != 3 {
+    return Err(Error::Channel(format!(
+      "A gradient map needs three color channels, not {}",
+      N - usize::from(A)
+    )));
+  }
+  let stops: Vec<[f64; 3]> =
+    colors.iter().map(|c| c.map(srgb_to_linear)).collect();
+  let white = image.white();
+  let dither = is_integer::<T>() && white <= 255.0;
+
+  let mut result = image.clone();
+  let width = image.width;
+  for (i, pel) in result.iter_pixels_mut().enumerate() {
+    let [r, g, b] = [0, 1, 2]
+      .map(|c| srgb_to_linear(pel[c].to_f64().unwrap_or_default() / white));
+    let t = linear_to_srgb(0.2126 * r + 0.7152 * g + 0.0722 * b);
+    let position = t * (stops.len() - 1) as f64;
+    let lower = (position.floor() as usize).min(stops.len() - 1);
+    let upper = (lower + 1).min(stops.len() - 1);
+    let f = position - lower as f64;
+    let offset = if dither {
+      (BAYER[i / width % 4][i % width % 4] + 0.5) / 16.0 - 0.5
+    } else {
+      0.0
+    };
+    for c in 0..3 {
+      let linear = stops[lower][c] + (stops[upper][c] - stops[lower][c]) * f;
+      pel[c] = component_from_f64(linear_to_srgb(linear) * white + offset);
+    }
+  }
+  Ok(result)
+}
+
+/// Maps shadows to `dark` and highlights to `light`, blending between them
+/// in the midtones, like a print made with two inks
+pub fn duotone<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  dark: [f64; 3],
+  light: [f64; 3],
+) -> Result<ImageBuffer<T, N, A>> {
+  gradient_map(image, &[dark, light])
+}
+
+/// Conventional screen angles in degrees for cyan, magenta, yellow and
+/// black, which keep the inks from forming moiré and give the rosette
+/// pattern of offset printing
+pub const CMYK_ANGLES: [f64; 4] = [15.0, 75.0, 0.0, 45.0];
+
+/// Shape of the dots in a [`halftone`] screen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DotShape {
+  /// Circles that grow until they touch their neighbors, then leave shrinking
+  /// holes
+  #[default]
+  Round,
+  /// Squares rotated by 45 degrees, which join at their corners in the
+  /// midtones
+  Diamond,
+  /// Parallel lines along the screen angle, like an engraving
+  Line,
+}
+
+/// Samples for [`round_radius`], spanning coverage from 0 to 1
+const ROUND_STEPS: usize = 256;
+
+impl DotShape {
+  /// Distance-like measure of an offset from the center of a cell, in cell
+  /// widths, which grows by about one per cell width
+  fn spot(self, u: f64, v: f64) -> f64 {
+    match self {
+      DotShape::Round => u.hypot(v),
+      DotShape::Diamond => (u.abs() + v.abs()) / 2f64.sqrt(),
+      DotShape::Line => v.abs(),
+    }
+  }
+
+  /// The spot value below which a fraction `coverage` of a cell is inked
+  fn threshold(self, coverage: f64, round: &[f64]) -> f64 {
+    match self {
+      DotShape::Round => {
+        let position = coverage * ROUND_STEPS as f64;
+        let i = (position as usize).min(ROUND_STEPS - 1);
+        round[i] + (round[i + 1] - round[i]) * (position - i as f64)
+      }
+      DotShape::Diamond => {
+        let half = if coverage <= 0.5 {
+          (coverage / 2.0).sqrt()
+        } else {
+          1.0 - ((1.0 - coverage) / 2.0).sqrt()
+        };
+        half / 2f64.sqrt()
+      }
+      DotShape::Line => coverage / 2.0,
+    }
+  }
+}
+
+/// Area of a unit cell within `r` of its center
+fn round_area(r: f64) -> f64 {
+  if r <= 0.5 {
+    PI * r * r
+  } else if r >= 0.5f64.sqrt() {
+    1.0
+  } else {
+    let segment = r * r * (0.5 / r).acos() - 0.5 * (r * r - 0.25).sqrt();
+    PI * r * r - 4.0 * segment
+  }
+}
+
+/// Radii of round dots covering evenly spaced fractions of a cell
+fn round_radius() -> Vec<f64> {
+  (0..=ROUND_STEPS)
+    .map(|i| {
+      let coverage = i as f64 / ROUND_STEPS as f64;
+      let (mut low, mut high) = (0.0, 0.5f64.sqrt());
+      for _ in 0..40 {
+        let mid = (low + high) / 2.0;
+        if round_area(mid) < coverage {
+          low = mid;
+        } else {
+          high = mid;
+        }
+      }
+      high
+    })
+    .collect()
+}
+
+/// Renders `image` as a halftone screen of dots spaced `cell_size` pixels
+/// apart, with one screen per ink at the angle in degrees given for it in
+/// `angles`.
+///
+/// Gray images are screened as a single black ink, like newspaper
+/// photographs. RGB images are separated into cyan, magenta, yellow and
+/// black inks with [`rgb_to_cmyk`] and printed back to RGB, which with
+/// [`CMYK_ANGLES`] gives the rosettes of offset printing. Four channels
+/// without alpha are treated as CMYK ink coverage and screened directly.
+///
+/// Fails if `cell_size` is not positive, the number of angles does not
+/// match the number of inks, or the channels are not one of those layouts.
+pub fn halftone<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  cell_size: f64,
+  angles: &[f64],
+  shape: DotShape,
+) -> Result<ImageBuffer<T, N, A>> {
+  let colors = N - usize::from(A);
+  let inks = match colors {
+    1 => 1,
+    3 | 4 => 4,
+    _ =>
+      return Err(Error::Channel(format!(
+        "Halftones need gray, RGB or CMYK images, not {colors} color channels"
+      ))),
+  };
+  if cell_size.is_nan() || cell_size <= 0.0 {
+    return Err(Error::InvalidArgument(format!(
+      "Halftone cells must be larger than {cell_size} pixels"
+    )));
+  }
+  if angles.len() != inks {
+    return Err(Error::InvalidArgument(format!(
+      "Expected {inks} screen angles, got {}",
+      angles.len()
+    )));
+  }
+
+  // Ink coverage of every pixel before screening
+  let white = image.white();
+  let coverage: Vec<[f64; 4]> = image
+    .iter_pixels()
+    .map(|pel| {
+      let unit = |c: usize| pel[c].to_f64().unwrap_or_default() / white;
+      match colors {
+        1 => [1.0 - unit(0), 0.0, 0.0, 0.0],
+        3 => rgb_to_cmyk::<f64, f64>(&[unit(0), unit(1), unit(2)]),
+        _ => [unit(0), unit(1), unit(2), unit(3)],
+      }
+    })
+    .collect();
+  let round = round_radius();
+  let (width, height) = (image.width, image.height);
+  let screens: Vec<(f64, f64)> = angles
+    .iter()
+    .map(|angle| angle.to_radians().sin_cos())
+    .collect();
+
+  let mut result = image.clone();
+  for (i, pel) in result.iter_pixels_mut().enumerate() {
+    let (x, y) = ((i % width) as f64 + 0.5, (i / width) as f64 + 0.5);
+    let mut screened = [0.0; 4];
+    for (ink, &(sin, cos)) in screens.iter().enumerate() {
+      // Position in cells along the rotated screen
+      let u = (x * cos + y * sin) / cell_size;
+      let v = (y * cos - x * sin) / cell_size;
+      let (cu, cv) = (u.floor() + 0.5, v.floor() + 0.5);
+      // The dot's size comes from the pixel under the center of its cell
+      let sx = ((cu * cos - cv * sin) * cell_size).floor();
+      let sy = ((cu * sin + cv * cos) * cell_size).floor();
+      let sx = sx.clamp(0.0, (width - 1) as f64) as usize;
+      let sy = sy.clamp(0.0, (height - 1) as f64) as usize;
+      let amount = coverage[sy * width + sx][ink].clamp(0.0, 1.0);
+      let threshold = shape.threshold(amount, &round);
+      let spot = shape.spot(u - cu, v - cv);
+      // Soften the edge of the dot over about one pixel
+      let edge = if amount <= 0.0 {
+        0.0
+      } else if amount >= 1.0 {
+        1.0
+      } else {
+        ((threshold - spot) * cell_size + 0.5).clamp(0.0, 1.0)
+      };
+      screened[ink] = edge;
+    }
+    let values = match colors {
+      1 => vec![1.0 - screened[0]],
+      3 => cmyk_to_rgb::<f64, f64>(&screened).to_vec(),
+      _ => screened.to_vec(),
+    };
+    for (c, value) in values.into_iter().enumerate() {
+      pel[c] = component_from_f64(value * white);
+    }
+  }
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn gradient_map_follows_brightness() {
+    let mut image = ImageBuffer::<f32, 4, true>::empty(3, 1);
+    *image.get_pixel_mut(0, 0) = [0.0, 0.0, 0.0, 0.5];
+    *image.get_pixel_mut(1, 0) = [0.5, 0.5, 0.5, 1.0];
+    *image.get_pixel_mut(2, 0) = [1.0, 1.0, 1.0, 1.0];
+    let red = [1.0, 0.0, 0.0];
+    let blue = [0.0, 0.0, 1.0];
+    let toned = duotone(&image, red, blue).unwrap();
+    let close = |a: &[f32; 4], b: [f32; 4]| {
+      a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-5)
+    };
+    assert!(close(toned.get_pixel(0, 0), [1.0, 0.0, 0.0, 0.5]));
+    assert!(close(toned.get_pixel(2, 0), [0.0, 0.0, 1.0, 1.0]));
+    // Halfway in linear light is brighter than halfway in sRGB
+    let mid = toned.get_pixel(1, 0);
+    assert!((mid[0] - mid[2]).abs() < 1e-3 && mid[0] > 0.7);
+
+    assert!(gradient_map(&image, &[]).is_err());
+    let gray = ImageBuffer::<u8, 1, false>::empty(1, 1);
+    assert!(duotone(&gray, red, blue).is_err());
+  }
+
+  #[test]
+  fn eight_bit_output_is_dithered() {
+    let image = ImageBuffer::<u8, 3, false>::with_val(&[100, 100, 100], 4, 4);
+    let toned = duotone(&image, [0.0; 3], [0.3, 0.3, 0.3]).unwrap();
+    let values: Vec<u8> = toned.iter_pixels().map(|p| p[0]).collect();
+    assert!(values.iter().min() != values.iter().max());
+    assert!(values.iter().max().unwrap() - values.iter().min().unwrap() <= 1);
+  }
+
+  #[test]
+  fn halftone_keeps_average_tone() {
+    let gray = ImageBuffer::<u8, 1, false>::with_val(&[191], 64, 64);
+    for shape in [DotShape::Round, DotShape::Diamond, DotShape::Line] {
+      let screened = halftone(&gray, 8.0, &[45.0], shape).unwrap();
+      let mean = screened.iter_pixels().map(|p| p[0] as f64).sum::<f64>()
+        / (64.0 * 64.0);
+      assert!((mean - 191.0).abs() < 12.0, "{shape:?}: {mean}");
+      assert!(screened.iter_pixels().any(|p| p[0] == 0));
+    }
+
+    let red = ImageBuffer::<u8, 3, false>::with_val(&[255, 0, 0], 32, 32);
+    let print = halftone(&red, 6.0, &CMYK_ANGLES, DotShape::Round).unwrap();
+    assert_eq!(print.get_pixel(5, 5), &[255, 0, 0]);
+    assert!(halftone(&red, 6.0, &[45.0], DotShape::Round).is_err());
+    assert!(halftone(&gray, 0.0, &[45.0], DotShape::Round).is_err());
+  }
+}
@@ -0,0 +1,119 @@
+//! Seamless textures: blending away the seams of an image so that it tiles,
+//! shifting it with wrap-around to check the result, and repeating it.
+
+use crate::{
+  error::{Error, Result},
+  image_buffer::BorderMode,
+  limits::Limits,
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// Crossfades the opposite edges of `image` into each other over
+/// `blend_width` pixels, so that copies placed side by side or above one
+/// another meet without a visible seam.
+///
+/// The blended strip replaces the last `blend_width` columns and rows, so the
+/// result is that much smaller in each direction. Fails unless
+/// `blend_width` is less than both the width and the height.
+pub fn make_tileable<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  blend_width: usize,
+) -> Result<ImageBuffer<T, N, A>> {
+  if blend_width >= image.width || blend_width >= image.height {
+    return Err(Error::InvalidArgument(format!(
+      "A blend width of {blend_width} does not fit the {}x{} image",
+      image.width, image.height
+    )));
+  }
+  let (width, height) = (image.width - blend_width, image.height - blend_width);
+  let value = |x: usize, y: usize, c: usize| {
+    image.get_pixel(x, y)[c].to_f64().unwrap_or_default()
+  };
+  // Near the start of an axis, fade in from the strip past its end, which
+  // continues the other edge
+  let weight = |i: usize| (i as f64 + 0.5) / blend_width as f64;
+  let horizontal = |x: usize, y: usize, c: usize| {
+    if x < blend_width {
+      let t = weight(x);
+      t * value(x, y, c) + (1.0 - t) * value(width + x, y, c)
+    } else {
+      value(x, y, c)
+    }
+  };
+  Ok(
+    ImageBuffer::try_empty(width, height, &Limits::none())?.map_indexed(
+      &mut |x, y, _| {
+        std::array::from_fn(|c| {
+          let v = if y < blend_width {
+            let t = weight(y);
+            t * horizontal(x, y, c) + (1.0 - t) * horizontal(x, height + y, c)
+          } else {
+            horizontal(x, y, c)
+          };
+          component_from_f64(v)
+        })
+      },
+    ),
+  )
+}
+
+/// Shifts `image` right by `dx` and down by `dy` pixels, wrapping what
+/// leaves one edge around to the opposite one. Shifting a texture by half
+/// its size brings its seams to the middle, where they are easy to inspect.
+pub fn offset_wrap<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  dx: isize,
+  dy: isize,
+) -> ImageBuffer<T, N, A> {
+  if image.width == 0 || image.height == 0 {
+    return image.clone();
+  }
+  image.map_indexed(&mut |x, y, _| {
+    let sx = BorderMode::Wrap.resolve(x as isize - dx, image.width);
+    let sy = BorderMode::Wrap.resolve(y as isize - dy, image.height);
+    *image.get_pixel(sx, sy)
+  })
+}
+
+/// Repeats `image` `nx` times across and `ny` times down
+pub fn tile<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  nx: usize,
+  ny: usize,
+) -> ImageBuffer<T, N, A> {
+  let (width, height) = (image.width, image.height);
+  ImageBuffer::empty(width * nx, height * ny).map_indexed(&mut |x, y, _| {
+    *image.get_pixel(
+      BorderMode::Wrap.resolve(x as isize, width),
+      BorderMode::Wrap.resolve(y as isize, height),
+    )
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tileable_texture_has_no_seam() {
+    // A ramp jumps from 90 back to 0 where copies meet
+    let ramp = ImageBuffer::<u8, 1, false>::empty(10, 8)
+      .map_indexed(&mut |x, _, _| [x as u8 * 10]);
+    let seam = |image: &ImageBuffer<u8, 1, false>| {
+      let tiled = tile(image, 2, 1);
+      let x = image.width;
+      tiled.get_pixel(x - 1, 0)[0].abs_diff(tiled.get_pixel(x, 0)[0])
+    };
+    assert_eq!(seam(&ramp), 90);
+    let tileable = make_tileable(&ramp, 4).unwrap();
+    assert_eq!((tileable.width, tileable.height), (6, 4));
+    assert!(seam(&tileable) <= 15, "{}", seam(&tileable));
+    assert!(make_tileable(&ramp, 8).is_err());
+
+    let shifted = offset_wrap(&ramp, 3, -1);
+    assert_eq!(shifted.get_pixel(0, 0), &[70]);
+    assert_eq!(shifted.get_pixel(3, 2), &[0]);
+    assert_eq!(tile(&ramp, 3, 2).width, 30);
+  }
+}
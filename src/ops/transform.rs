@@ -0,0 +1,347 @@
+//! Changes to the extent of an image: cropping, padding and resampling to a
+//! new size.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+  color::RgbaF32,
+  compute::ExecutionContext,
+  error::{Error, Result},
+  limits::Limits,
+  ops::resize::ResampleOptions,
+  pixel::{PixelComponent, PixelContainer},
+  progress::Progress,
+  Image,
+  ImageBuffer,
+};
+
+/// Copies the `width` by `height` region whose top-left corner is at
+/// `(x, y)`. Fails if the region does not lie within the image.
+pub fn crop<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  x: usize,
+  y: usize,
+  width: usize,
+  height: usize,
+) -> Result<ImageBuffer<T, N, A>> {
+  let fits = |start: usize, len: usize, limit: usize| {
+    start.checked_add(len).is_some_and(|end| end <= limit)
+  };
+  if !fits(x, width, image.width) || !fits(y, height, image.height) {
+    return Err(Error::InvalidArgument(format!(
+      "Crop of {width}x{height} at ({x}, {y}) exceeds the {}x{} image",
+      image.width, image.height
+    )));
+  }
+  let mut result = ImageBuffer::try_empty(width, height, &Limits::none())?;
+  let row_len = width * N;
+  for (row, out) in result
+    .components_mut()
+    .chunks_exact_mut(row_len.max(1))
+    .enumerate()
+  {
+    let start = ((y + row) * image.width + x) * N;
+    out.copy_from_slice(&image.components()[start..start + row_len]);
+  }
+  Ok(result)
+}
+
+/// Adds `left`, `top`, `right` and `bottom` pixels of `color` around the
+/// edges of `image`, converted to its channels as
+/// [`RgbaF32::to_pixel`] describes
+pub fn pad<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  [left, top, right, bottom]: [usize; 4],
+  color: impl Into<RgbaF32>,
+) -> ImageBuffer<T, N, A> {
+  let (width, height) =
+    (left + image.width + right, top + image.height + bottom);
+  let pixel = color.into().to_pixel(image);
+  let mut result = ImageBuffer::with_val(&pixel, width, height);
+  let row_len = image.width * N;
+  for y in 0..image.height {
+    let start = ((top + y) * width + left) * N;
+    result.components_mut()[start..start + row_len]
+      .copy_from_slice(&image.components()[y * row_len..(y + 1) * row_len]);
+  }
+  result
+}
+
+/// Source indices and weights for each output position when resampling an
+/// axis of `from` samples to `to` samples with a triangle filter. The filter
+/// widens when shrinking so that every input sample contributes; indices
+/// past the ends repeat the edge sample.
+pub(crate) fn weights(from: usize, to: usize) -> Vec<Vec<(usize, f64)>> {
+  let scale = from as f64 / to as f64;
+  let support = scale.max(1.0);
+  (0..to)
+    .map(|i| {
+      let center = (i as f64 + 0.5) * scale - 0.5;
+      let first = (center - support).floor() as isize;
+      let last = (center + support).ceil() as isize;
+      let mut taps: Vec<(usize, f64)> = (first..=last)
+        .map(|j| {
+          let w = (1.0 - (j as f64 - center).abs() / support).max(0.0);
+          (j.clamp(0, from as isize - 1) as usize, w)
+        })
+        .filter(|&(_, w)| w > 0.0)
+        .collect();
+      let total: f64 = taps.iter().map(|&(_, w)| w).sum();
+      taps.iter_mut().for_each(|(_, w)| *w /= total);
+      taps
+    })
+    .collect()
+}
+
+/// Resamples `image` to `width` by `height` pixels with a separable
+/// triangle filter: bilinear when enlarging, and averaging over the covered
+/// area when shrinking, which avoids aliasing. Alpha is resampled like the
+/// other channels.
+///
+/// Panics if the result does not fit in memory;
+/// [`resize_with_progress`] fails instead.
+pub fn resize<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  width: usize,
+  height: usize,
+) -> ImageBuffer<T, N, A> {
+  resize_with(image, width, height, &ResampleOptions::default())
+}
+
+/// [`resize`] with the given settings, such as resampling in linear light
+pub fn resize_with<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  width: usize,
+  height: usize,
+  options: &ResampleOptions,
+) -> ImageBuffer<T, N, A> {
+  let context = ExecutionContext::default();
+  match resize_in(image, width, height, options, &context, &Progress::new()) {
+    Ok(result) => result,
+    Err(e) => panic!("{e}"),
+  }
+}
+
+/// [`resize_with`], reporting to `progress` as rows are done and failing
+/// with [`Error::Cancelled`] if it is cancelled, or
+/// [`Error::LimitExceeded`] if the result does not fit in memory
+pub fn resize_with_progress<
+  T: PixelComponent,
+  const N: usize,
+  const A: bool,
+>(
+  image: &ImageBuffer<T, N, A>,
+  width: usize,
+  height: usize,
+  options: &ResampleOptions,
+  progress: &Progress,
+) -> Result<ImageBuffer<T, N, A>> {
+  let context = ExecutionContext::default();
+  let result = resize_in(image, width, height, options, &context, progress)?;
+  progress.check()?;
+  Ok(result)
+}
+
+/// [`resize_with`], splitting the work into bands of rows as `context` says.
+/// Bands started after `progress` is cancelled are skipped, leaving the
+/// result unfinished. Fails if the result does not fit in memory.
+pub(crate) fn resize_in<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  width: usize,
+  height: usize,
+  options: &ResampleOptions,
+  context: &ExecutionContext,
+  progress: &Progress,
+) -> Result<ImageBuffer<T, N, A>> {
+  let mut result = ImageBuffer::try_empty(width, height, &Limits::none())?;
+  if image.width == 0 || image.height == 0 || width == 0 || height == 0 {
+    return Ok(result);
+  }
+  // Rows of both passes done, across all threads
+  let done = AtomicUsize::new(0);
+  let advance = |rows: usize| {
+    let done = done.fetch_add(rows, Ordering::Relaxed) + rows;
+    // Cancellation is picked up by the check before each band
+    let _ = progress.report(done as f32 / (image.height + height) as f32);
+  };
+  let source_width = image.width;
+  let alpha = A.then_some(N - 1);
+  let source: Vec<f64> = image
+    .components()
+    .iter()
+    .enumerate()
+    .map(|(i, &v)| options.decode(v, i % N, alpha))
+    .collect();
+
+  let columns = weights(image.width, width);
+  // The first pass, resampled across but not yet down
+  let mut rows = ImageBuffer::<f64, N, false>::try_empty(
+    width,
+    image.height,
+    &Limits::none(),
+  )?
+  .into_components();
+  context.for_each_band(&mut rows, width * N, |first, band| {
+    if progress.is_cancelled() {
+      return;
+    }
+    for (i, row) in band.chunks_exact_mut(width * N).enumerate() {
+      let y = first + i;
+      for (x, taps) in columns.iter().enumerate() {
+        for c in 0..N {
+          row[x * N + c] = taps
+            .iter()
+            .map(|&(j, w)| w * source[(y * source_width + j) * N + c])
+            .sum();
+        }
+      }
+    }
+    advance(band.len() / (width * N));
+  });
+
+  let lines = weights(image.height, height);
+  context.for_each_band(result.components_mut(), width * N, |first, band| {
+    if progress.is_cancelled() {
+      return;
+    }
+    for (i, out) in band.chunks_exact_mut(width * N).enumerate() {
+      let taps = &lines[first + i];
+      for x in 0..width {
+        for c in 0..N {
+          let v: f64 = taps
+            .iter()
+            .map(|&(j, w)| w * rows[(j * width + x) * N + c])
+            .sum();
+          out[x * N + c] = options.encode(v, c, alpha);
+        }
+      }
+    }
+    advance(band.len() / (width * N));
+  });
+  Ok(result)
+}
+
+/// Pixels needed to cover `length_mm` at `dpi` dots per inch, at least one
+pub fn pixels_for_length(length_mm: f64, dpi: f64) -> usize {
+  (length_mm / 25.4 * dpi).round().max(1.0) as usize
+}
+
+impl Image {
+  /// Resamples the image to print `width_mm` wide at `dpi`, keeping its
+  /// aspect ratio, and records that resolution. Fails for HSV and CIELAB
+  /// images that cannot be resampled as RGB, and for a width or resolution
+  /// that is not positive.
+  pub fn resize_to_physical(&self, width_mm: f64, dpi: f64) -> Result<Image> {
+    if width_mm.is_nan() || width_mm <= 0.0 || dpi.is_nan() || dpi <= 0.0 {
+      return Err(Error::InvalidArgument(format!(
+        "Cannot print {width_mm} mm wide at {dpi} dpi"
+      )));
+    }
+    let width = pixels_for_length(width_mm, dpi);
+    let height = (self.height() as f64 * width as f64
+      / self.width().max(1) as f64)
+      .round()
+      .max(1.0) as usize;
+    let rgba = self.to_rgba_f32()?;
+    let resized = resize_with_progress(
+      &rgba,
+      width,
+      height,
+      &ResampleOptions::default(),
+      &Progress::new(),
+    )?;
+    let mut result = Image::from_rgba_f32_like(resized, self);
+    result.set_dpi(Some((dpi, dpi)));
+    Ok(result)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::color::Rgba8;
+
+  #[test]
+  fn crop_and_resize() {
+    let image = ImageBuffer::<u8, 1, false>::empty(4, 4)
+      .map_indexed(&mut |x, y, _| [(y * 4 + x) as u8 * 10]);
+    let cropped = crop(&image, 1, 2, 2, 2).unwrap();
+    assert_eq!(cropped.components(), &[90, 100, 130, 140]);
+    assert!(matches!(
+      crop(&image, 3, 0, 2, 1),
+      Err(Error::InvalidArgument(_))
+    ));
+
+    let padded = pad(&cropped, [1, 0, 0, 1], Rgba8::WHITE);
+    assert_eq!(
+      padded.components(),
+      &[255, 90, 100, 255, 130, 140, 255, 255, 255]
+    );
+    let rgba = ImageBuffer::<f32, 4, true>::empty(1, 1);
+    let padded = pad(
+      &rgba,
+      [0, 0, 1, 0],
+      "hsl(120 100% 50% / 0.5)".parse::<RgbaF32>().unwrap(),
+    );
+    assert_eq!(padded.get_pixel(1, 0), &[0.0, 1.0, 0.0, 0.5]);
+
+    // Shrinking averages fine stripes away instead of aliasing them
+    let stripes = ImageBuffer::<u8, 1, false>::empty(8, 1)
+      .map_indexed(&mut |x, _, _| [if x % 2 == 0 { 0 } else { 200 }]);
+    let half = resize(&stripes, 4, 1);
+    assert_eq!(&half.components()[1..3], &[100, 100]);
+    let flat = ImageBuffer::<u16, 3, false>::with_val(&[7, 700, 7000], 3, 2);
+    let big = resize(&flat, 7, 5);
+    assert!(big.iter_pixels().all(|p| p == &[7, 700, 7000]));
+
+    // In linear light, black and white stripes average to a lighter gray
+    let linear = resize_with(
+      &stripes,
+      4,
+      1,
+      &ResampleOptions {
+        linearize: true
+      },
+    );
+    assert!(linear.components()[1] > 130);
+
+    let last = std::sync::Arc::new(std::sync::Mutex::new(0.0));
+    let progress = Progress::with_callback({
+      let last = std::sync::Arc::clone(&last);
+      move |fraction| *last.lock().unwrap() = fraction
+    });
+    let options = ResampleOptions::default();
+    let resized = resize_with_progress(&flat, 7, 5, &options, &progress);
+    assert_eq!(resized.unwrap().components(), big.components());
+    assert_eq!(*last.lock().unwrap(), 1.0);
+    progress.cancel();
+    assert!(matches!(
+      resize_with_progress(&flat, 7, 5, &options, &progress),
+      Err(Error::Cancelled)
+    ));
+    assert!(matches!(
+      resize_with_progress(
+        &flat,
+        usize::MAX / 2,
+        3,
+        &options,
+        &Progress::new()
+      ),
+      Err(Error::LimitExceeded(_))
+    ));
+  }
+
+  #[test]
+  fn resize_to_print_size() {
+    let image = Image::new_u8(crate::color_space::ColorSpace::Rgb(
+      ImageBuffer::empty(400, 300),
+    ));
+    // 4 inches at 50 dpi
+    let print = image.resize_to_physical(101.6, 50.0).unwrap();
+    assert_eq!((print.width(), print.height()), (200, 150));
+    assert_eq!(print.dpi(), Some((50.0, 50.0)));
+    let (width_mm, _) = print.physical_size_mm().unwrap();
+    assert!((width_mm - 101.6).abs() < 1e-9);
+    assert!(image.resize_to_physical(0.0, 300.0).is_err());
+  }
+}
@@ -1,34 +1,121 @@
 use num_traits::{Num, Zero, ToPrimitive, NumCast};
 
-pub trait PixelComponent: Num + Copy + Clone + Zero + Sized + ToPrimitive + NumCast + Default {
+use crate::image_buffer::{BorderMode, Origin};
+
+pub trait PixelComponent: Num + Copy + Clone + Zero + Sized + ToPrimitive + NumCast + Default + Send + Sync {
   type Container: Num;
+
+  /// Value of a fully-saturated component: the maximum for integer types,
+  /// `1.0` for floating point
+  const WHITE: Self;
 }
 impl PixelComponent for u8 {
   type Container = u8;
+
+  const WHITE: Self = u8::MAX;
 }
 impl PixelComponent for u16 {
   type Container = u16;
+
+  const WHITE: Self = u16::MAX;
 }
 impl PixelComponent for u32 {
   type Container = u32;
+
+  const WHITE: Self = u32::MAX;
 }
 impl PixelComponent for u64 {
   type Container = u64;
+
+  const WHITE: Self = u64::MAX;
 }
 impl PixelComponent for u128 {
   type Container = u128;
+
+  const WHITE: Self = u128::MAX;
 }
 impl PixelComponent for f32 {
   type Container = f32;
+
+  const WHITE: Self = 1.0;
 }
 impl PixelComponent for f64 {
   type Container = f64;
+
+  const WHITE: Self = 1.0;
+}
+
+/// Whether `T` holds whole numbers only
+pub(crate) fn is_integer<T: PixelComponent>() -> bool {
+  <T as NumCast>::from(0.5).is_some_and(|h: T| h.is_zero())
+}
+
+/// Converts `value` to a component, rounding to the nearest integer and
+/// saturating for integer types
+pub(crate) fn component_from_f64<T: PixelComponent>(value: f64) -> T {
+  let value = if is_integer::<T>() {
+    value.round().clamp(0.0, T::WHITE.to_f64().unwrap_or(f64::MAX))
+  } else {
+    value
+  };
+  <T as NumCast>::from(value).unwrap_or_default()
 }
 
+/// One pixel: a fixed-size group of components, stored contiguously
+pub trait Pixel: Copy {
+  type Component: PixelComponent;
+  const NUM_COMPONENTS: usize;
+
+  /// Views exactly `NUM_COMPONENTS` components as a pixel
+  ///
+  /// Panics if the slice has the wrong length.
+  fn from_components(components: &[Self::Component]) -> &Self;
+
+  /// Views exactly `NUM_COMPONENTS` components as a mutable pixel
+  ///
+  /// Panics if the slice has the wrong length.
+  fn from_components_mut(components: &mut [Self::Component]) -> &mut Self;
+
+  fn components(&self) -> &[Self::Component];
+
+  fn components_mut(&mut self) -> &mut [Self::Component];
+}
+
+impl<Component: PixelComponent, const N: usize> Pixel for [Component; N] {
+  type Component = Component;
+
+  const NUM_COMPONENTS: usize = N;
+
+  fn from_components(components: &[Component]) -> &Self {
+    components
+      .try_into()
+      .expect("Pixel slice has the wrong number of components")
+  }
+
+  fn from_components_mut(components: &mut [Component]) -> &mut Self {
+    components
+      .try_into()
+      .expect("Pixel slice has the wrong number of components")
+  }
+
+  fn components(&self) -> &[Component] { self }
+
+  fn components_mut(&mut self) -> &mut [Component] { self }
+}
+
+/// A two-dimensional buffer of pixels, stored row-major with interleaved
+/// components.
+///
+/// Implementors provide access to their components; the pixel operations
+/// below are shared by every container type.
 pub trait PixelContainer {
-  type OnePixel;
+  type Component: PixelComponent;
+  type OnePixel: Pixel<Component = Self::Component>;
   type PixelBuffer;
-  type OnePlane;
+  type OnePlane: PixelContainer<
+    Component = Self::Component,
+    OnePixel = [Self::Component; 1],
+  >;
   const HAS_ALPHA: bool;
   const NUM_COMPONENTS: usize;
   const ALPHA_IDX: Option<usize>;
@@ -38,4 +125,296 @@ pub trait PixelContainer {
   fn pixels_mut(&mut self) -> &mut Self::PixelBuffer;
   fn width(&self) -> usize;
   fn height(&self) -> usize;
+
+  /// All components of all pixels, row-major
+  fn components(&self) -> &[Self::Component];
+
+  /// All components of all pixels, row-major
+  fn components_mut(&mut self) -> &mut [Self::Component];
+
+  /// Returns a zero-filled single-component plane with the same dimensions
+  fn new_plane(&self) -> Self::OnePlane;
+
+  fn iter_pixels(&self) -> impl Iterator<Item = &Self::OnePixel> {
+    self
+      .components()
+      .chunks_exact(Self::NUM_COMPONENTS)
+      .map(Self::OnePixel::from_components)
+  }
+
+  fn iter_pixels_mut(&mut self) -> impl Iterator<Item = &mut Self::OnePixel> {
+    self
+      .components_mut()
+      .chunks_exact_mut(Self::NUM_COMPONENTS)
+      .map(Self::OnePixel::from_components_mut)
+  }
+
+  /// Returns the pixel at the given coordinates
+  ///
+  /// Panics if the coordinates are outside the image.
+  fn get_pixel(&self, x: usize, y: usize) -> &Self::OnePixel {
+    assert!(x < self.width() && y < self.height(), "Pixel out of bounds");
+    let start = (y * self.width() + x) * Self::NUM_COMPONENTS;
+    Self::OnePixel::from_components(
+      &self.components()[start..start + Self::NUM_COMPONENTS],
+    )
+  }
+
+  /// Returns a mutable reference to the pixel at the given coordinates
+  ///
+  /// Panics if the coordinates are outside the image.
+  fn get_pixel_mut(&mut self, x: usize, y: usize) -> &mut Self::OnePixel {
+    assert!(x < self.width() && y < self.height(), "Pixel out of bounds");
+    let start = (y * self.width() + x) * Self::NUM_COMPONENTS;
+    Self::OnePixel::from_components_mut(
+      &mut self.components_mut()[start..start + Self::NUM_COMPONENTS],
+    )
+  }
+
+  /// Applies the given pixel mapping function and returns a new container of
+  /// the same type, with the result.
+  ///
+  /// ```F``` is a function that operates on all channels of one pixel at a
+  /// time.
+  fn map<F>(&self, map_fn: &mut F) -> Self
+  where
+    Self: Clone,
+    F: FnMut(&Self::OnePixel) -> Self::OnePixel, {
+    let mut result = self.clone();
+    result.apply(map_fn);
+    result
+  }
+
+  /// Applies the given pixel mapping function in place, on the current mutable
+  /// instance
+  ///
+  /// ```F``` is a function that operates on all channels of one pixel at a
+  /// time.
+  fn apply<F>(&mut self, map_fn: &mut F)
+  where F: FnMut(&Self::OnePixel) -> Self::OnePixel {
+    for pel in self.iter_pixels_mut() {
+      *pel = map_fn(pel);
+    }
+  }
+
+  /// Applies the given pixel mapping function and returns a new container of
+  /// the same type, with the result.
+  ///
+  /// ```F``` receives the `x` and `y` coordinates of the pixel along with all
+  /// of its channels.
+  fn map_indexed<F>(&self, map_fn: &mut F) -> Self
+  where
+    Self: Clone,
+    F: FnMut(usize, usize, &Self::OnePixel) -> Self::OnePixel, {
+    let mut result = self.clone();
+    result.apply_indexed(map_fn);
+    result
+  }
+
+  /// Applies the given pixel mapping function in place, on the current mutable
+  /// instance
+  ///
+  /// ```F``` receives the `x` and `y` coordinates of the pixel along with all
+  /// of its channels.
+  fn apply_indexed<F>(&mut self, map_fn: &mut F)
+  where F: FnMut(usize, usize, &Self::OnePixel) -> Self::OnePixel {
+    let width = self.width().max(1);
+    for (i, pel) in self.iter_pixels_mut().enumerate() {
+      *pel = map_fn(i % width, i / width, pel);
+    }
+  }
+
+  /// Applies the given neighborhood function and returns a new container of
+  /// the same type, with the result.
+  ///
+  /// The function receives a `W`x`H` window of pixels, indexed as
+  /// `window[row][column]`, centered on the pixel being computed. Samples that
+  /// fall outside the image are resolved using `border`. For even window sizes
+  /// the extra row or column lies before the center.
+  fn map_window<const W: usize, const H: usize>(
+    &self,
+    border: BorderMode,
+    map_fn: &mut impl FnMut(&[[Self::OnePixel; W]; H]) -> Self::OnePixel,
+  ) -> Self
+  where
+    Self: Clone,
+  {
+    let mut result = self.clone();
+    let (width, height) = (self.width(), self.height());
+    if width == 0 || height == 0 {
+      return result;
+    }
+    let mut window = [[*self.get_pixel(0, 0); W]; H];
+
+    for y in 0..height {
+      for x in 0..width {
+        for (wy, row) in window.iter_mut().enumerate() {
+          let sy =
+            border.resolve(y as isize + wy as isize - (H / 2) as isize, height);
+          for (wx, pel) in row.iter_mut().enumerate() {
+            let sx =
+              border.resolve(x as isize + wx as isize - (W / 2) as isize, width);
+            *pel = *self.get_pixel(sx, sy);
+          }
+        }
+        *result.get_pixel_mut(x, y) = map_fn(&window);
+      }
+    }
+
+    result
+  }
+
+  /// Reverses the order of the rows in place
+  fn flip_vertical(&mut self) {
+    let row_len = self.width() * Self::NUM_COMPONENTS;
+    let height = self.height();
+    if row_len == 0 {
+      return;
+    }
+    let data = self.components_mut();
+    for y in 0..height / 2 {
+      let (upper, lower) = data.split_at_mut((height - 1 - y) * row_len);
+      upper[y * row_len..(y + 1) * row_len]
+        .swap_with_slice(&mut lower[..row_len]);
+    }
+  }
+
+  /// Reorders rows stored starting from `origin` into the top-down order used
+  /// throughout this crate
+  fn flip_to_top_down(&mut self, origin: Origin) {
+    if origin == Origin::BottomLeft {
+      self.flip_vertical();
+    }
+  }
+
+  fn get_plane_const<const I: usize>(&self) -> Self::OnePlane {
+    let mut result = self.new_plane();
+    copy_strided(
+      self.components(),
+      I,
+      Self::NUM_COMPONENTS,
+      result.components_mut(),
+    );
+    result
+  }
+
+  fn get_alpha(&self) -> Option<Self::OnePlane> {
+    self.get_plane(Self::ALPHA_IDX?).ok()
+  }
+
+  fn get_plane(&self, i: usize) -> Result<Self::OnePlane, &'static str> {
+    if i >= Self::NUM_COMPONENTS {
+      return Err("Plane index out of bounds");
+    }
+
+    let mut result = self.new_plane();
+    copy_strided(
+      self.components(),
+      i,
+      Self::NUM_COMPONENTS,
+      result.components_mut(),
+    );
+    Ok(result)
+  }
+
+  fn put_plane_const<const I: usize>(&mut self, plane: &Self::OnePlane) {
+    self.put_plane(I, plane);
+  }
+
+  fn put_plane(&mut self, i: usize, plane: &Self::OnePlane) {
+    let n = Self::NUM_COMPONENTS;
+    let data = self.components_mut();
+    if n == 1 {
+      let len = data.len().min(plane.components().len());
+      data[..len].copy_from_slice(&plane.components()[..len]);
+      return;
+    }
+    for (pel, &value) in data.chunks_exact_mut(n).zip(plane.components()) {
+      pel[i] = value;
+    }
+  }
+}
+
+/// Copies every `stride`th value of `src`, starting at `offset`, into `dst`
+fn copy_strided<T: Copy>(
+  src: &[T],
+  offset: usize,
+  stride: usize,
+  dst: &mut [T],
+) {
+  if stride == 1 {
+    let len = dst.len().min(src.len());
+    dst[..len].copy_from_slice(&src[..len]);
+    return;
+  }
+  for (d, pel) in dst.iter_mut().zip(src.chunks_exact(stride)) {
+    *d = pel[offset];
+  }
+}
+
+/// Fills `data` with copies of `pattern`, doubling the filled part with each
+/// copy instead of writing one pattern at a time
+pub(crate) fn fill_repeating<T: Copy>(data: &mut [T], pattern: &[T]) {
+  let mut filled = pattern.len().min(data.len());
+  data[..filled].copy_from_slice(&pattern[..filled]);
+  if filled == 0 {
+    return;
+  }
+  while filled < data.len() {
+    let len = filled.min(data.len() - filled);
+    data.copy_within(..len, filled);
+    filled += len;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ImageBuffer, StackImageBuffer};
+
+  fn brightest<C: PixelContainer<OnePixel = [u8; 3]>>(image: &C) -> u8 {
+    image.iter_pixels().flatten().copied().max().unwrap_or(0)
+  }
+
+  #[test]
+  fn shared_ops_on_heap_and_stack_buffers() {
+    let heap = ImageBuffer::<u8, 3, false>::with_val(&[1, 2, 3], 2, 2)
+      .map_indexed(&mut |x, y, pel| [pel[0], pel[1], (x + y) as u8 * 10]);
+    let stack = StackImageBuffer::<u8, 2, 2, 3>::with_val(&[1, 2, 3])
+      .map_indexed(&mut |x, y, pel| [pel[0], pel[1], (x + y) as u8 * 10]);
+    assert_eq!(heap.components(), stack.components());
+    assert_eq!(brightest(&heap), 20);
+    assert_eq!(brightest(&stack), 20);
+  }
+
+  #[test]
+  fn get_and_put_plane_rgba_u8() {
+    let mut image = ImageBuffer::<u8, 4, true>::with_val(&[1, 2, 3, 4], 2, 1);
+    let alpha = image.get_alpha().unwrap();
+    assert_eq!(alpha.components(), &[4, 4]);
+    assert!(image.get_plane(4).is_err());
+    image.put_plane_const::<0>(&alpha);
+    assert_eq!(image.get_pixel(1, 0), &[4, 2, 3, 4]);
+    assert!(ImageBuffer::<u8, 3, false>::empty(1, 1).get_alpha().is_none());
+
+    // Single-channel planes are copied whole
+    let mut gray = ImageBuffer::<u8, 1, false>::with_val(&[7], 3, 1);
+    assert_eq!(gray.get_plane(0).unwrap().components(), &[7, 7, 7]);
+    gray.put_plane(0, &alpha.map_indexed(&mut |x, _, _| [x as u8]));
+    assert_eq!(gray.components(), &[0, 1, 7]);
+
+    let mut data = [0u16; 7];
+    fill_repeating(&mut data, &[1, 2, 3]);
+    assert_eq!(data, [1, 2, 3, 1, 2, 3, 1]);
+  }
+
+  #[test]
+  fn flip_to_top_down_odd_height() {
+    let mut image = StackImageBuffer::<u8, 1, 3, 2>::empty()
+      .map_indexed(&mut |_, y, _| [y as u8, 9]);
+    image.flip_to_top_down(Origin::TopLeft);
+    assert_eq!(image.components(), &[0, 9, 1, 9, 2, 9]);
+    image.flip_to_top_down(Origin::BottomLeft);
+    assert_eq!(image.components(), &[2, 9, 1, 9, 0, 9]);
+  }
 }
@@ -0,0 +1,195 @@
+//! Progress reporting and cancellation for long-running operations.
+//!
+//! A [`Progress`] is a cheap handle that can be cloned and sent to other
+//! threads. Operations that accept one report how far along they are
+//! through its callback and check it regularly for cancellation; once
+//! [`Progress::cancel`] is called from anywhere, they stop at the next check
+//! and return [`Error::Cancelled`].
+//!
+//! Operations made of distinct steps, such as
+//! [`stitch`](crate::stitch::stitch), name each as a stage, which a callback
+//! given to [`Progress::with_stage_callback`] is told about.
+
+use std::{
+  fmt,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
+
+use crate::error::{Error, Result};
+
+type StageCallback = Box<dyn Fn(&str, f32) + Send + Sync>;
+
+enum Callback {
+  Overall(Box<dyn Fn(f32) + Send + Sync>),
+  Staged(StageCallback),
+}
+
+struct Shared {
+  cancelled: AtomicBool,
+  callback:  Option<Callback>,
+}
+
+/// A progress callback paired with a cancellation flag
+#[derive(Clone)]
+pub struct Progress {
+  shared: Arc<Shared>,
+  /// Share of the overall work covered by this handle, so that steps of a
+  /// larger operation can report their own progress from 0 to 1
+  start:  f32,
+  end:    f32,
+  /// Name of the stage being worked on and its share of the overall work
+  stage:  (&'static str, f32, f32),
+}
+
+impl Default for Progress {
+  fn default() -> Self { Self::new() }
+}
+
+impl fmt::Debug for Progress {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Progress")
+      .field("cancelled", &self.is_cancelled())
+      .field("range", &(self.start..self.end))
+      .field("stage", &self.stage.0)
+      .finish()
+  }
+}
+
+impl Progress {
+  fn with(callback: Option<Callback>) -> Self {
+    Progress {
+      shared: Arc::new(Shared {
+        cancelled: AtomicBool::new(false),
+        callback,
+      }),
+      start:  0.0,
+      end:    1.0,
+      stage:  ("", 0.0, 1.0),
+    }
+  }
+
+  /// A handle that only tracks cancellation
+  pub fn new() -> Self { Self::with(None) }
+
+  /// A handle that passes the fraction of work completed, between 0 and 1,
+  /// to `callback`. The callback runs on whichever thread does the work.
+  pub fn with_callback(callback: impl Fn(f32) + Send + Sync + 'static) -> Self {
+    Self::with(Some(Callback::Overall(Box::new(callback))))
+  }
+
+  /// A handle that passes the name of the current [`stage`](Self::stage)
+  /// and the fraction of it completed to `callback`. Work reported outside
+  /// any stage belongs to one with an empty name that spans everything.
+  pub fn with_stage_callback(
+    callback: impl Fn(&str, f32) + Send + Sync + 'static,
+  ) -> Self {
+    Self::with(Some(Callback::Staged(Box::new(callback))))
+  }
+
+  /// Asks every operation using this handle, or a clone of it, to stop
+  pub fn cancel(&self) { self.shared.cancelled.store(true, Ordering::Relaxed); }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.shared.cancelled.load(Ordering::Relaxed)
+  }
+
+  /// Fails with [`Error::Cancelled`] if cancellation was requested
+  pub fn check(&self) -> Result<()> {
+    if self.is_cancelled() {
+      Err(Error::Cancelled)
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Reports that `fraction` of the work is done, then checks for
+  /// cancellation
+  pub fn report(&self, fraction: f32) -> Result<()> {
+    let overall =
+      self.start + (self.end - self.start) * fraction.clamp(0.0, 1.0);
+    match &self.shared.callback {
+      Some(Callback::Overall(callback)) => callback(overall),
+      Some(Callback::Staged(callback)) => {
+        let (name, start, end) = self.stage;
+        let within = if end > start {
+          (overall - start) / (end - start)
+        } else {
+          1.0
+        };
+        callback(name, within.clamp(0.0, 1.0));
+      }
+      None => (),
+    }
+    self.check()
+  }
+
+  /// A handle for a step covering `start..end` of this handle's work. The
+  /// step reports its own progress from 0 to 1, and shares cancellation.
+  pub fn step(&self, start: f32, end: f32) -> Progress {
+    let span = self.end - self.start;
+    Progress {
+      shared: Arc::clone(&self.shared),
+      start:  self.start + span * start.clamp(0.0, 1.0),
+      end:    self.start + span * end.clamp(0.0, 1.0),
+      stage:  self.stage,
+    }
+  }
+
+  /// A [`step`](Self::step) that is also a stage named `name`, which
+  /// callbacks given to [`with_stage_callback`](Self::with_stage_callback)
+  /// are told about along with how much of the step is done
+  pub fn stage(&self, name: &'static str, start: f32, end: f32) -> Progress {
+    let mut step = self.step(start, end);
+    step.stage = (name, step.start, step.end);
+    step
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Mutex;
+
+  use super::*;
+
+  #[test]
+  fn progress_steps_and_cancellation() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let progress = Progress::with_callback({
+      let seen = Arc::clone(&seen);
+      move |fraction| seen.lock().unwrap().push(fraction)
+    });
+    let second_half = progress.step(0.5, 1.0);
+    second_half.report(0.5).unwrap();
+    progress.report(1.0).unwrap();
+    assert_eq!(*seen.lock().unwrap(), [0.75, 1.0]);
+
+    let worker = second_half.clone();
+    std::thread::spawn(move || worker.cancel()).join().unwrap();
+    assert!(matches!(progress.report(1.0), Err(Error::Cancelled)));
+    assert!(Progress::new().check().is_ok());
+
+    let stages = Arc::new(Mutex::new(Vec::new()));
+    let progress = Progress::with_stage_callback({
+      let stages = Arc::clone(&stages);
+      move |name, fraction| {
+        stages.lock().unwrap().push((name.to_string(), fraction))
+      }
+    });
+    let load = progress.stage("load", 0.0, 0.2);
+    load.step(0.5, 1.0).report(0.5).unwrap();
+    progress.stage("save", 0.2, 1.0).report(1.0).unwrap();
+    progress.report(0.5).unwrap();
+    let stages = stages.lock().unwrap();
+    assert_eq!(
+      *stages,
+      [
+        ("load".to_string(), 0.75),
+        ("save".to_string(), 1.0),
+        (String::new(), 0.5)
+      ]
+    );
+  }
+}
@@ -0,0 +1,380 @@
+//! Edit pipelines described as data.
+//!
+//! A [`Recipe`] is a list of parameterized [`Step`]s. It can be stored
+//! alongside an image (with the `serde` feature), previewed quickly on a
+//! downscaled copy and applied to the full-resolution image on export,
+//! without the original ever being modified.
+//!
+//! Steps run on RGBA `f32` pixels; the result has the component type and
+//! channels of the input image.
+//!
+//! Interactive editors can work on a [`Proxy`], a smaller copy of an image
+//! linked to the original: edits are previewed on the proxy and the final
+//! output is rendered from the original with the same recipe.
+
+use crate::{
+  color_space::ColorSpace,
+  compute::{self, ExecutionContext},
+  error::{Error, Result},
+  limits::Limits,
+  ops::{find_op, point, transform},
+  Image,
+  ImageBuffer,
+};
+
+type Rgba = ImageBuffer<f32, 4, true>;
+
+/// One operation in a [`Recipe`]. Sizes and distances are in pixels of the
+/// full-resolution image.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(tag = "op", rename_all = "snake_case")
+)]
+pub enum Step {
+  Resize {
+    width:  usize,
+    height: usize,
+  },
+  Crop {
+    x:      usize,
+    y:      usize,
+    width:  usize,
+    height: usize,
+  },
+  GaussianBlur {
+    sigma: f64,
+  },
+  /// See [`compute::color_matrix`]
+  ColorMatrix {
+    matrix: [[f64; 4]; 3],
+  },
+  Invert,
+  Posterize {
+    levels: usize,
+  },
+  Solarize {
+    threshold: f64,
+  },
+  /// An operation registered with [`register_op`](crate::ops::register_op).
+  /// It runs at whatever resolution it is given.
+  Registered {
+    name: String,
+  },
+}
+
+/// A chain of steps applied in order
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Recipe {
+  pub steps:  Vec<Step>,
+  /// Caps on the images the steps may produce, so that a recipe from an
+  /// untrusted source cannot ask for an enormous resize. Not serialized;
+  /// [`Limits::default`] unless set.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  pub limits: Limits,
+}
+
+/// `length` in pixels at `scale` times the full resolution, at least one
+/// pixel
+fn scaled(length: usize, scale: f64) -> usize {
+  ((length as f64 * scale).round() as usize).max(1)
+}
+
+impl Step {
+  /// Runs the step on `image`, which is `scale` times the size of the image
+  /// the recipe was written for, failing if it would produce an image over
+  /// `limits`
+  fn run(
+    &self,
+    image: &Rgba,
+    scale: f64,
+    limits: &Limits,
+    context: &ExecutionContext,
+  ) -> Result<Rgba> {
+    match self {
+      Step::Resize {
+        width,
+        height,
+      } => {
+        let (width, height) = (scaled(*width, scale), scaled(*height, scale));
+        limits.check_image(width, height, std::mem::size_of::<[f32; 4]>())?;
+        compute::resize(image, width, height, context)
+      }
+      Step::Crop {
+        x,
+        y,
+        width,
+        height,
+      } if scale < 1.0 => {
+        // Rounding can push a scaled region past the edge of a preview
+        let x = ((*x as f64 * scale).round() as usize).min(image.width - 1);
+        let y = ((*y as f64 * scale).round() as usize).min(image.height - 1);
+        let width = scaled(*width, scale).min(image.width - x);
+        let height = scaled(*height, scale).min(image.height - y);
+        transform::crop(image, x, y, width, height)
+      }
+      Step::Crop {
+        x,
+        y,
+        width,
+        height,
+      } => transform::crop(image, *x, *y, *width, *height),
+      Step::GaussianBlur {
+        sigma,
+      } => compute::gaussian_blur(image, sigma * scale, context),
+      Step::ColorMatrix {
+        matrix,
+      } => compute::color_matrix(image, matrix, context),
+      Step::Invert => Ok(point::invert(image)),
+      Step::Posterize {
+        levels,
+      } => Ok(point::posterize(image, *levels)),
+      Step::Solarize {
+        threshold,
+      } => Ok(point::solarize(image, *threshold)),
+      Step::Registered {
+        name,
+      } => {
+        let op = find_op(name).ok_or_else(|| {
+          Error::InvalidArgument(format!("No operation named {name}"))
+        })?;
+        op.apply(&Image::new_f32(ColorSpace::Rgba(image.clone())))?
+          .to_rgba_f32()
+      }
+    }
+  }
+}
+
+impl Recipe {
+  pub fn new() -> Self { Self::default() }
+
+  /// Appends `step`
+  pub fn then(mut self, step: Step) -> Self {
+    self.steps.push(step);
+    self
+  }
+
+  fn run(
+    &self,
+    mut pixels: Rgba,
+    scale: f64,
+    context: &ExecutionContext,
+  ) -> Result<Rgba> {
+    for step in &self.steps {
+      if pixels.width == 0 || pixels.height == 0 {
+        break;
+      }
+      pixels = step.run(&pixels, scale, &self.limits, context)?;
+    }
+    Ok(pixels)
+  }
+
+  /// Applies every step to `image` at full resolution. Fails with
+  /// [`Error::LimitExceeded`] if a step would make an image over the
+  /// recipe's [`limits`](Self::limits).
+  pub fn apply(
+    &self,
+    image: &Image,
+    context: &ExecutionContext,
+  ) -> Result<Image> {
+    let pixels = self.run(image.to_rgba_f32()?, 1.0, context)?;
+    Ok(Image::from_rgba_f32_like(pixels, image))
+  }
+
+  /// Applies the recipe to a copy of `image` shrunk to fit within
+  /// `max_size` pixels on its longer side, scaling sizes and distances in
+  /// the steps to match. Images that already fit are processed as by
+  /// [`apply`](Self::apply).
+  ///
+  /// To preview several recipes, make a [`Proxy`] once instead.
+  pub fn preview(
+    &self,
+    image: &Image,
+    max_size: usize,
+    context: &ExecutionContext,
+  ) -> Result<Image> {
+    image
+      .proxy_in(max_size, context)?
+      .apply_recipe(self, context)
+  }
+}
+
+/// A lower-resolution copy of an image, linked to the original, for
+/// previewing recipes quickly. Made by [`Image::proxy`].
+pub struct Proxy<'a> {
+  original: &'a Image,
+  pixels:   Rgba,
+  scale:    f64,
+}
+
+impl Image {
+  /// A proxy of this image fitting within `max_dim` pixels on its longer
+  /// side. Images that already fit, and a `max_dim` of zero, give a proxy at
+  /// full resolution.
+  pub fn proxy(&self, max_dim: usize) -> Result<Proxy<'_>> {
+    self.proxy_in(max_dim, &ExecutionContext::default())
+  }
+
+  /// [`proxy`](Self::proxy), resizing as `context` says
+  pub fn proxy_in(
+    &self,
+    max_dim: usize,
+    context: &ExecutionContext,
+  ) -> Result<Proxy<'_>> {
+    let longest = self.width().max(self.height());
+    let pixels = self.to_rgba_f32()?;
+    if longest <= max_dim || max_dim == 0 {
+      return Ok(Proxy {
+        original: self,
+        pixels,
+        scale: 1.0,
+      });
+    }
+    let scale = max_dim as f64 / longest as f64;
+    let pixels = compute::resize(
+      &pixels,
+      scaled(self.width(), scale),
+      scaled(self.height(), scale),
+      context,
+    )?;
+    Ok(Proxy {
+      original: self,
+      pixels,
+      scale,
+    })
+  }
+}
+
+impl<'a> Proxy<'a> {
+  /// The full-resolution image this is a proxy of
+  pub fn original(&self) -> &'a Image { self.original }
+
+  /// Size of the proxy relative to the original
+  pub fn scale(&self) -> f64 { self.scale }
+
+  pub fn width(&self) -> usize { self.pixels.width }
+
+  pub fn height(&self) -> usize { self.pixels.height }
+
+  /// The proxy's pixels, with the component type and channels of the
+  /// original
+  pub fn image(&self) -> Image {
+    Image::from_rgba_f32_like(self.pixels.clone(), self.original)
+  }
+
+  /// Position in the original of the proxy pixel at `x`, `y`, for turning
+  /// positions picked on a preview into recipe parameters
+  pub fn to_original(&self, x: usize, y: usize) -> (usize, usize) {
+    let map = |v: usize, len: usize| {
+      ((v as f64 / self.scale).round() as usize).min(len.saturating_sub(1))
+    };
+    (
+      map(x, self.original.width()),
+      map(y, self.original.height()),
+    )
+  }
+
+  /// Previews `recipe` on the proxy, scaling sizes and distances in its
+  /// steps to match
+  pub fn apply_recipe(
+    &self,
+    recipe: &Recipe,
+    context: &ExecutionContext,
+  ) -> Result<Image> {
+    let pixels = recipe.run(self.pixels.clone(), self.scale, context)?;
+    Ok(Image::from_rgba_f32_like(pixels, self.original))
+  }
+
+  /// Renders `recipe` from the original, for final output
+  pub fn apply_recipe_at_full_res(
+    &self,
+    recipe: &Recipe,
+    context: &ExecutionContext,
+  ) -> Result<Image> {
+    recipe.apply(self.original, context)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::PixelContainer;
+
+  fn recipe() -> Recipe {
+    Recipe::new()
+      .then(Step::Crop {
+        x:      100,
+        y:      40,
+        width:  600,
+        height: 400,
+      })
+      .then(Step::GaussianBlur {
+        sigma: 4.0
+      })
+      .then(Step::Resize {
+        width:  300,
+        height: 200,
+      })
+      .then(Step::Invert)
+  }
+
+  #[test]
+  fn preview_scales_steps() {
+    let image = Image::new_u8(ColorSpace::Rgb(ImageBuffer::empty(800, 500)));
+    let context = ExecutionContext::default();
+    let full = recipe().apply(&image, &context).unwrap();
+    assert_eq!((full.width(), full.height()), (300, 200));
+    assert_eq!(full.color_space_name(), "RGB");
+    let preview = recipe().preview(&image, 200, &context).unwrap();
+    assert_eq!((preview.width(), preview.height()), (75, 50));
+    let white = preview.to_rgba_f32().unwrap();
+    assert_eq!(white.get_pixel(10, 10), &[1.0; 4]);
+
+    let missing = Recipe::new().then(Step::Registered {
+      name: "not-registered".to_string(),
+    });
+    assert!(missing.apply(&image, &context).is_err());
+
+    let huge = Recipe::new().then(Step::Resize {
+      width:  1_000_000_000,
+      height: 1_000_000_000,
+    });
+    assert!(matches!(
+      huge.apply(&image, &context),
+      Err(Error::LimitExceeded(_))
+    ));
+  }
+
+  #[test]
+  fn proxies_preview_and_render_from_original() {
+    let image = Image::new_u8(ColorSpace::Rgb(
+      ImageBuffer::empty(800, 500)
+        .map_indexed(&mut |x, y, _| [(x / 4) as u8, (y / 2) as u8, 60]),
+    ));
+    let context = ExecutionContext::default();
+    let proxy = image.proxy(200).unwrap();
+    assert_eq!((proxy.width(), proxy.height()), (200, 125));
+    assert_eq!(proxy.image().color_space_name(), "RGB");
+    assert_eq!(proxy.to_original(100, 124), (400, 496));
+
+    let preview = proxy.apply_recipe(&recipe(), &context).unwrap();
+    let expected = recipe().preview(&image, 200, &context).unwrap();
+    assert_eq!(
+      preview.to_rgba_f32().unwrap().components(),
+      expected.to_rgba_f32().unwrap().components()
+    );
+    let full = proxy.apply_recipe_at_full_res(&recipe(), &context).unwrap();
+    assert_eq!((full.width(), full.height()), (300, 200));
+    assert_eq!(image.proxy(0).unwrap().scale(), 1.0);
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn recipes_round_trip_through_json() {
+    let json = serde_json::to_string(&recipe()).unwrap();
+    assert!(json.contains(r#""op":"gaussian_blur","sigma":4.0"#));
+    assert_eq!(serde_json::from_str::<Recipe>(&json).unwrap(), recipe());
+  }
+}
@@ -0,0 +1,166 @@
+use crate::{
+  error::{Error, Result},
+  pixel::{PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// An image buffer with dimensions fixed at compile time, storing its pixels
+/// inline rather than on the heap.
+///
+/// Intended for small kernels, icons, and sprites. Pixels are stored as
+/// `data[row][column]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StackImageBuffer<
+  Component: PixelComponent,
+  const W: usize,
+  const H: usize,
+  const COMPONENTS_PER_PEL: usize,
+  const HAS_ALPHA: bool = false,
+> {
+  data: [[[Component; COMPONENTS_PER_PEL]; W]; H],
+}
+
+impl<
+    Component: PixelComponent,
+    const W: usize,
+    const H: usize,
+    const COMPONENTS_PER_PEL: usize,
+    const HAS_ALPHA: bool,
+  > PixelContainer
+  for StackImageBuffer<Component, W, H, COMPONENTS_PER_PEL, HAS_ALPHA>
+{
+  type Component = Component;
+  type OnePixel = [Component; COMPONENTS_PER_PEL];
+  type OnePlane = StackImageBuffer<Component, W, H, 1, false>;
+  type PixelBuffer = [[[Component; COMPONENTS_PER_PEL]; W]; H];
+
+  const ALPHA_IDX: Option<usize> = if HAS_ALPHA {
+    Some(COMPONENTS_PER_PEL - 1)
+  } else {
+    None
+  };
+  const HAS_ALPHA: bool = HAS_ALPHA;
+  const NUM_COMPONENTS: usize = COMPONENTS_PER_PEL;
+  const NUM_NONALPHA_COMPONENTS: usize = if HAS_ALPHA {
+    COMPONENTS_PER_PEL - 1
+  } else {
+    COMPONENTS_PER_PEL
+  };
+
+  fn pixels(&self) -> &Self::PixelBuffer { &self.data }
+
+  fn pixels_mut(&mut self) -> &mut Self::PixelBuffer { &mut self.data }
+
+  fn width(&self) -> usize { W }
+
+  fn height(&self) -> usize { H }
+
+  fn components(&self) -> &[Component] {
+    self.data.as_flattened().as_flattened()
+  }
+
+  fn components_mut(&mut self) -> &mut [Component] {
+    self.data.as_flattened_mut().as_flattened_mut()
+  }
+
+  fn new_plane(&self) -> Self::OnePlane { StackImageBuffer::empty() }
+}
+
+impl<
+    Component: PixelComponent,
+    const W: usize,
+    const H: usize,
+    const COMPONENTS_PER_PEL: usize,
+    const HAS_ALPHA: bool,
+  > Default
+  for StackImageBuffer<Component, W, H, COMPONENTS_PER_PEL, HAS_ALPHA>
+{
+  fn default() -> Self { Self::empty() }
+}
+
+impl<
+    Component: PixelComponent,
+    const W: usize,
+    const H: usize,
+    const COMPONENTS_PER_PEL: usize,
+    const HAS_ALPHA: bool,
+  > StackImageBuffer<Component, W, H, COMPONENTS_PER_PEL, HAS_ALPHA>
+{
+  pub fn empty() -> Self {
+    Self::with_val(&[Component::zero(); COMPONENTS_PER_PEL])
+  }
+
+  pub fn with_val(one_pel: &<Self as PixelContainer>::OnePixel) -> Self {
+    StackImageBuffer {
+      data: [[*one_pel; W]; H],
+    }
+  }
+
+  pub fn with_data(data: <Self as PixelContainer>::PixelBuffer) -> Self {
+    StackImageBuffer {
+      data,
+    }
+  }
+
+  /// Copies the pixels into a heap-allocated [`ImageBuffer`]
+  pub fn to_image_buffer(
+    &self,
+  ) -> ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA> {
+    ImageBuffer::with_data(self.components().to_vec(), W, H)
+      .expect("Stack buffer holds exactly W * H pixels")
+  }
+}
+
+impl<
+    Component: PixelComponent,
+    const W: usize,
+    const H: usize,
+    const COMPONENTS_PER_PEL: usize,
+    const HAS_ALPHA: bool,
+  > TryFrom<&ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>>
+  for StackImageBuffer<Component, W, H, COMPONENTS_PER_PEL, HAS_ALPHA>
+{
+  type Error = Error;
+
+  fn try_from(
+    buffer: &ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>,
+  ) -> Result<Self> {
+    if (buffer.width, buffer.height) != (W, H) {
+      return Err(Error::DimensionMismatch {
+        expected: (W, H),
+        actual:   (buffer.width, buffer.height),
+      });
+    }
+    let mut result = Self::empty();
+    result.components_mut().copy_from_slice(buffer.components());
+    Ok(result)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn with_val_and_apply_rgb_u8() {
+    let mut sprite = StackImageBuffer::<u8, 3, 2, 3>::with_val(&[1, 2, 3]);
+    sprite.apply(&mut |pel| [pel[0] * 2, pel[1], pel[2]]);
+    *sprite.get_pixel_mut(2, 1) = [9, 9, 9];
+    assert_eq!(sprite.get_pixel(0, 0), &[2, 2, 3]);
+    assert_eq!(sprite.get_pixel(2, 1), &[9, 9, 9]);
+    assert_eq!(sprite.iter_pixels().count(), 6);
+  }
+
+  #[test]
+  fn image_buffer_round_trip_rgba_u8() {
+    let sprite =
+      StackImageBuffer::<u8, 2, 2, 4, true>::with_val(&[1, 2, 3, 255]);
+    let heap = sprite.to_image_buffer();
+    assert_eq!((heap.width, heap.height), (2, 2));
+    assert_eq!(StackImageBuffer::try_from(&heap).unwrap(), sprite);
+    assert!(matches!(
+      StackImageBuffer::<u8, 3, 2, 4, true>::try_from(&heap),
+      Err(Error::DimensionMismatch { .. })
+    ));
+  }
+}
@@ -0,0 +1,200 @@
+//! Seam selection and multi-band blending of images warped onto a shared
+//! canvas.
+
+/// A single-channel image used while blending
+#[derive(Clone)]
+pub(crate) struct Plane {
+  pub width:  usize,
+  pub height: usize,
+  pub data:   Vec<f32>,
+}
+
+impl Plane {
+  pub fn new(width: usize, height: usize) -> Self {
+    Plane {
+      width,
+      height,
+      data: vec![0.0; width * height],
+    }
+  }
+
+  fn at(&self, x: isize, y: isize) -> f32 {
+    let x = x.clamp(0, self.width as isize - 1) as usize;
+    let y = y.clamp(0, self.height as isize - 1) as usize;
+    self.data[y * self.width + x]
+  }
+
+  fn zip_with(&self, other: &Plane, f: impl Fn(f32, f32) -> f32) -> Plane {
+    Plane {
+      data: self
+        .data
+        .iter()
+        .zip(&other.data)
+        .map(|(&a, &b)| f(a, b))
+        .collect(),
+      ..*self
+    }
+  }
+
+  /// Blurs with the 5-tap binomial kernel and halves the resolution
+  fn reduce(&self) -> Plane {
+    const KERNEL: [f32; 5] = [1.0, 4.0, 6.0, 4.0, 1.0];
+    let (w, h) = (self.width.div_ceil(2), self.height.div_ceil(2));
+    let mut rows = Plane::new(w, self.height);
+    for y in 0..self.height {
+      for x in 0..w {
+        let sum: f32 = (0..5)
+          .map(|k| {
+            KERNEL[k] * self.at(2 * x as isize + k as isize - 2, y as isize)
+          })
+          .sum();
+        rows.data[y * w + x] = sum / 16.0;
+      }
+    }
+    let mut out = Plane::new(w, h);
+    for y in 0..h {
+      for x in 0..w {
+        let sum: f32 = (0..5)
+          .map(|k| {
+            KERNEL[k] * rows.at(x as isize, 2 * y as isize + k as isize - 2)
+          })
+          .sum();
+        out.data[y * w + x] = sum / 16.0;
+      }
+    }
+    out
+  }
+
+  /// Bilinearly upsamples to `width` x `height`, the inverse of [`reduce`]
+  fn expand(&self, width: usize, height: usize) -> Plane {
+    let mut out = Plane::new(width, height);
+    for y in 0..height {
+      let sy = (y as f32 - 0.5) / 2.0;
+      let (y0, fy) = (sy.floor(), sy - sy.floor());
+      for x in 0..width {
+        let sx = (x as f32 - 0.5) / 2.0;
+        let (x0, fx) = (sx.floor(), sx - sx.floor());
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let top = self.at(x0, y0) * (1.0 - fx) + self.at(x0 + 1, y0) * fx;
+        let bottom =
+          self.at(x0, y0 + 1) * (1.0 - fx) + self.at(x0 + 1, y0 + 1) * fx;
+        out.data[y * width + x] = top * (1.0 - fy) + bottom * fy;
+      }
+    }
+    out
+  }
+}
+
+/// Gaussian pyramid of `levels` planes, full resolution first
+fn gaussian(plane: Plane, levels: usize) -> Vec<Plane> {
+  let mut pyramid = vec![plane];
+  while pyramid.len() < levels {
+    let next = pyramid.last().expect("Pyramid is not empty").reduce();
+    pyramid.push(next);
+  }
+  pyramid
+}
+
+/// Laplacian pyramid of an image that only covers part of the canvas. Each
+/// Gaussian level is divided by the blurred coverage, so the image is
+/// smoothly extended past its edges instead of fading to black.
+fn laplacian(image: &Plane, coverage: &Plane, levels: usize) -> Vec<Plane> {
+  let weighted = gaussian(image.zip_with(coverage, |v, c| v * c), levels);
+  let coverage = gaussian(coverage.clone(), levels);
+  let normalized: Vec<Plane> = weighted
+    .iter()
+    .zip(&coverage)
+    .map(|(w, c)| w.zip_with(c, |w, c| if c > 1e-6 { w / c } else { 0.0 }))
+    .collect();
+
+  let mut pyramid: Vec<Plane> = normalized
+    .windows(2)
+    .map(|pair| {
+      let up = pair[1].expand(pair[0].width, pair[0].height);
+      pair[0].zip_with(&up, |a, b| a - b)
+    })
+    .collect();
+  pyramid.push(normalized.last().expect("Pyramid is not empty").clone());
+  pyramid
+}
+
+/// Assigns every canvas pixel to the covering image whose warped center is
+/// nearest, giving straight seams midway between overlapping images. Returns
+/// one 0/1 mask per image.
+pub(super) fn seams(coverage: &[Plane], centers: &[(f64, f64)]) -> Vec<Plane> {
+  let (width, height) = (coverage[0].width, coverage[0].height);
+  let mut masks = vec![Plane::new(width, height); coverage.len()];
+  for i in 0..width * height {
+    let (x, y) = ((i % width) as f64, (i / width) as f64);
+    let nearest = (0..coverage.len())
+      .filter(|&k| coverage[k].data[i] > 0.5)
+      .min_by(|&a, &b| {
+        let d =
+          |k: usize| (centers[k].0 - x).powi(2) + (centers[k].1 - y).powi(2);
+        d(a).total_cmp(&d(b))
+      });
+    if let Some(k) = nearest {
+      masks[k].data[i] = 1.0;
+    }
+  }
+  masks
+}
+
+/// Blends one channel of several images with Burt-Adelson multi-band
+/// blending: low frequencies are mixed over wide regions around the seams
+/// and fine detail over narrow ones
+pub(crate) fn multiband(
+  images: &[Plane],
+  coverage: &[Plane],
+  masks: &[Plane],
+  levels: usize,
+) -> Plane {
+  let (width, height) = (images[0].width, images[0].height);
+  let max_levels = (width.min(height).max(1).ilog2() as usize).max(1);
+  let levels = levels.clamp(1, max_levels);
+
+  let mut sum: Option<Vec<Plane>> = None;
+  let mut weight: Option<Vec<Plane>> = None;
+  for ((image, coverage), mask) in images.iter().zip(coverage).zip(masks) {
+    let bands = laplacian(image, coverage, levels);
+    let weights = gaussian(mask.clone(), levels);
+    let weighted: Vec<Plane> = bands
+      .iter()
+      .zip(&weights)
+      .map(|(b, w)| b.zip_with(w, |b, w| b * w))
+      .collect();
+    sum = Some(match sum {
+      None => weighted,
+      Some(s) =>
+        s.iter()
+          .zip(&weighted)
+          .map(|(a, b)| a.zip_with(b, |a, b| a + b))
+          .collect(),
+    });
+    weight = Some(match weight {
+      None => weights,
+      Some(s) =>
+        s.iter()
+          .zip(&weights)
+          .map(|(a, b)| a.zip_with(b, |a, b| a + b))
+          .collect(),
+    });
+  }
+
+  let bands: Vec<Plane> = sum
+    .unwrap_or_default()
+    .iter()
+    .zip(&weight.unwrap_or_default())
+    .map(|(s, w)| s.zip_with(w, |s, w| if w > 1e-6 { s / w } else { 0.0 }))
+    .collect();
+  let mut result = bands
+    .last()
+    .cloned()
+    .unwrap_or_else(|| Plane::new(width, height));
+  for band in bands.iter().rev().skip(1) {
+    result = result
+      .expand(band.width, band.height)
+      .zip_with(band, |a, b| a + b);
+  }
+  result
+}
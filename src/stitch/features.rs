@@ -0,0 +1,164 @@
+//! Corner detection and patch descriptors for matching overlapping images.
+
+use super::homography::Match;
+use crate::ops::register::sample;
+
+/// Half the side of the window a descriptor is sampled from
+const WINDOW: f64 = 8.0;
+/// Harris detector sensitivity
+const HARRIS_K: f64 = 0.04;
+/// Corners weaker than this fraction of the strongest are ignored
+const RESPONSE_FRACTION: f64 = 1e-3;
+
+/// A corner and the normalized 8x8 patch around it
+pub(super) struct Feature {
+  pub x:          f64,
+  pub y:          f64,
+  pub descriptor: [f32; 64],
+}
+
+fn box_blur(plane: &[f64], width: usize, height: usize) -> Vec<f64> {
+  let mut out = vec![0.0; plane.len()];
+  for y in 0..height {
+    for x in 0..width {
+      let mut sum = 0.0;
+      let mut count = 0.0;
+      for yy in y.saturating_sub(1)..(y + 2).min(height) {
+        for xx in x.saturating_sub(1)..(x + 2).min(width) {
+          sum += plane[yy * width + xx];
+          count += 1.0;
+        }
+      }
+      out[y * width + x] = sum / count;
+    }
+  }
+  out
+}
+
+/// Finds up to `max` Harris corners in a luminance plane, strongest first,
+/// skipping those too close to the border to describe
+pub(super) fn detect(
+  luma: &[f64],
+  width: usize,
+  height: usize,
+  max: usize,
+) -> Vec<Feature> {
+  let margin = WINDOW as usize + 1;
+  if width <= 2 * margin || height <= 2 * margin {
+    return Vec::new();
+  }
+  let at = |x: usize, y: usize| luma[y * width + x];
+
+  let mut tensor = vec![[0.0; 3]; width * height];
+  for y in 1..height - 1 {
+    for x in 1..width - 1 {
+      let gx = (at(x + 1, y) - at(x - 1, y)) / 2.0;
+      let gy = (at(x, y + 1) - at(x, y - 1)) / 2.0;
+      tensor[y * width + x] = [gx * gx, gx * gy, gy * gy];
+    }
+  }
+  let mut response = vec![0.0; width * height];
+  for y in margin..height - margin {
+    for x in margin..width - margin {
+      let mut m = [0.0; 3];
+      for yy in y - 2..=y + 2 {
+        for xx in x - 2..=x + 2 {
+          for (m, t) in m.iter_mut().zip(tensor[yy * width + xx]) {
+            *m += t;
+          }
+        }
+      }
+      let trace = m[0] + m[2];
+      response[y * width + x] =
+        m[0] * m[2] - m[1] * m[1] - HARRIS_K * trace * trace;
+    }
+  }
+
+  let threshold =
+    RESPONSE_FRACTION * response.iter().copied().fold(0.0, f64::max);
+  let mut corners = Vec::new();
+  for y in margin..height - margin {
+    for x in margin..width - margin {
+      let r = response[y * width + x];
+      // Ties go to the first pixel in raster order
+      let is_max = r > threshold
+        && (y - 2..=y + 2).all(|yy| {
+          (x - 2..=x + 2).all(|xx| {
+            let other = response[yy * width + xx];
+            other < r || (other == r && (yy, xx) >= (y, x))
+          })
+        });
+      if is_max {
+        corners.push((r, x, y));
+      }
+    }
+  }
+  corners.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+  let blurred = box_blur(luma, width, height);
+  corners
+    .into_iter()
+    .filter_map(|(_, x, y)| describe(&blurred, width, height, x, y))
+    .take(max)
+    .collect()
+}
+
+/// Samples an 8x8 grid over the window around `(x, y)` and normalizes it for
+/// brightness and contrast. Flat patches cannot be matched and are dropped.
+fn describe(
+  blurred: &[f64],
+  width: usize,
+  height: usize,
+  x: usize,
+  y: usize,
+) -> Option<Feature> {
+  let mut values = [0.0; 64];
+  for (i, v) in values.iter_mut().enumerate() {
+    let sx = x as f64 + ((i % 8) as f64 - 3.5) * WINDOW / 4.0;
+    let sy = y as f64 + ((i / 8) as f64 - 3.5) * WINDOW / 4.0;
+    *v = sample(blurred, width, height, sx, sy)?;
+  }
+  let mean = values.iter().sum::<f64>() / 64.0;
+  let std =
+    (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / 64.0).sqrt();
+  if std < 1e-6 {
+    return None;
+  }
+  Some(Feature {
+    x:          x as f64,
+    y:          y as f64,
+    descriptor: values.map(|v| ((v - mean) / std) as f32),
+  })
+}
+
+fn distance(a: &[f32; 64], b: &[f32; 64]) -> f32 {
+  a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// Pairs each feature in `a` with its nearest neighbor in `b`, keeping only
+/// pairs that pass Lowe's ratio test, as `(point in a, point in b)`
+pub(super) fn match_features(
+  a: &[Feature],
+  b: &[Feature],
+  ratio: f64,
+) -> Vec<Match> {
+  let ratio_sq = (ratio * ratio) as f32;
+  a.iter()
+    .filter_map(|fa| {
+      let (mut best, mut second) = ((f32::INFINITY, 0), f32::INFINITY);
+      for (j, fb) in b.iter().enumerate() {
+        let d = distance(&fa.descriptor, &fb.descriptor);
+        if d < best.0 {
+          second = best.0;
+          best = (d, j);
+        } else if d < second {
+          second = d;
+        }
+      }
+      (best.0 < ratio_sq * second).then(|| {
+        let fb = &b[best.1];
+        ((fa.x, fa.y), (fb.x, fb.y))
+      })
+    })
+    .collect()
+}
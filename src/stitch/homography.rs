@@ -0,0 +1,170 @@
+//! Projective transforms between overlapping images, estimated robustly
+//! from feature matches.
+
+use crate::ops::{patch_match::XorShift, register::solve};
+
+/// A point in one image and the corresponding point in another
+pub(crate) type Match = ((f64, f64), (f64, f64));
+
+/// A 3x3 projective transform acting on `(x, y, 1)`
+pub(crate) type Homography = [[f64; 3]; 3];
+
+pub(crate) const IDENTITY: Homography =
+  [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Maps a point, or returns `None` if it lands at infinity
+pub(crate) fn apply(h: &Homography, x: f64, y: f64) -> Option<(f64, f64)> {
+  let w = h[2][0] * x + h[2][1] * y + h[2][2];
+  if w.abs() < 1e-12 {
+    return None;
+  }
+  Some((
+    (h[0][0] * x + h[0][1] * y + h[0][2]) / w,
+    (h[1][0] * x + h[1][1] * y + h[1][2]) / w,
+  ))
+}
+
+pub(crate) fn multiply(a: &Homography, b: &Homography) -> Homography {
+  let mut out = [[0.0; 3]; 3];
+  for (r, row) in out.iter_mut().enumerate() {
+    for (c, v) in row.iter_mut().enumerate() {
+      *v = (0..3).map(|k| a[r][k] * b[k][c]).sum();
+    }
+  }
+  out
+}
+
+pub(crate) fn invert(h: &Homography) -> Option<Homography> {
+  let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+    h[r0][c0] * h[r1][c1] - h[r0][c1] * h[r1][c0]
+  };
+  let det = h[0][0] * cofactor(1, 2, 1, 2) - h[0][1] * cofactor(1, 2, 0, 2)
+    + h[0][2] * cofactor(1, 2, 0, 1);
+  if det.abs() < 1e-12 {
+    return None;
+  }
+  let adjugate = [
+    [
+      cofactor(1, 2, 1, 2),
+      -cofactor(0, 2, 1, 2),
+      cofactor(0, 1, 1, 2),
+    ],
+    [
+      -cofactor(1, 2, 0, 2),
+      cofactor(0, 2, 0, 2),
+      -cofactor(0, 1, 0, 2),
+    ],
+    [
+      cofactor(1, 2, 0, 1),
+      -cofactor(0, 2, 0, 1),
+      cofactor(0, 1, 0, 1),
+    ],
+  ];
+  Some(adjugate.map(|row| row.map(|v| v / det)))
+}
+
+/// Similarity transform moving the points' centroid to the origin with a
+/// mean distance of sqrt(2), which keeps the DLT well conditioned
+fn normalization(
+  points: impl Iterator<Item = (f64, f64)> + Clone,
+) -> Homography {
+  let n = points.clone().count().max(1) as f64;
+  let (cx, cy) = points
+    .clone()
+    .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+  let (cx, cy) = (cx / n, cy / n);
+  let mean = points
+    .map(|(x, y)| ((x - cx).powi(2) + (y - cy).powi(2)).sqrt())
+    .sum::<f64>()
+    / n;
+  let s = if mean > 1e-12 {
+    2f64.sqrt() / mean
+  } else {
+    1.0
+  };
+  [[s, 0.0, -s * cx], [0.0, s, -s * cy], [0.0, 0.0, 1.0]]
+}
+
+/// Least-squares homography mapping the first point of each pair onto the
+/// second, from at least four pairs
+pub(crate) fn fit(pairs: &[Match]) -> Option<Homography> {
+  if pairs.len() < 4 {
+    return None;
+  }
+  let from = normalization(pairs.iter().map(|p| p.0));
+  let to = normalization(pairs.iter().map(|p| p.1));
+
+  // Normal equations of the DLT with h33 fixed to 1
+  let mut ata = [[0.0; 8]; 8];
+  let mut atb = [0.0; 8];
+  for &(a, b) in pairs {
+    let (x, y) = apply(&from, a.0, a.1)?;
+    let (u, v) = apply(&to, b.0, b.1)?;
+    for (row, rhs) in [
+      ([x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y], u),
+      ([0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y], v),
+    ] {
+      for r in 0..8 {
+        atb[r] += row[r] * rhs;
+        for c in 0..8 {
+          ata[r][c] += row[r] * row[c];
+        }
+      }
+    }
+  }
+  let h = solve(ata, atb)?;
+  let normalized = [[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], 1.0]];
+  let h = multiply(&invert(&to)?, &multiply(&normalized, &from));
+  let scale = h[2][2];
+  (scale.abs() > 1e-12).then(|| h.map(|row| row.map(|v| v / scale)))
+}
+
+/// Estimates a homography with RANSAC, rejecting pairs whose reprojection
+/// error exceeds `threshold` pixels, and refits it to all inliers. Returns the
+/// transform and the number of inliers.
+pub(crate) fn ransac(
+  pairs: &[Match],
+  threshold: f64,
+  iterations: usize,
+  rng: &mut XorShift,
+) -> Option<(Homography, usize)> {
+  let inliers = |h: &Homography| -> Vec<Match> {
+    pairs
+      .iter()
+      .filter(|(a, b)| {
+        apply(h, a.0, a.1).is_some_and(|(x, y)| {
+          (x - b.0).powi(2) + (y - b.1).powi(2) < threshold * threshold
+        })
+      })
+      .copied()
+      .collect()
+  };
+
+  let mut best: Option<(Homography, usize)> = None;
+  for _ in 0..iterations {
+    if pairs.len() < 4 {
+      break;
+    }
+    let mut pick = [0; 4];
+    for i in 0..4 {
+      pick[i] = loop {
+        let candidate = rng.next() as usize % pairs.len();
+        if !pick[..i].contains(&candidate) {
+          break candidate;
+        }
+      };
+    }
+    let Some(h) = fit(&pick.map(|i| pairs[i])) else {
+      continue;
+    };
+    let count = inliers(&h).len();
+    if best.is_none_or(|(_, c)| count > c) {
+      best = Some((h, count));
+    }
+  }
+
+  let (h, _) = best?;
+  let inliers = inliers(&h);
+  let refined = fit(&inliers).unwrap_or(h);
+  Some((refined, inliers.len()))
+}
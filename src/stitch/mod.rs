@@ -0,0 +1,602 @@
+//! Panorama stitching: one call that turns a sequence of overlapping photos
+//! into a single image.
+//!
+//! [`stitch`] chains the whole pipeline: Harris corners with normalized
+//! patch descriptors, ratio-test matching, RANSAC homography estimation,
+//! projective warping onto a shared canvas, seam selection and multi-band
+//! blending. [`stitch_refinements`] yields previews stitched from smaller
+//! copies of the photos before the full-resolution panorama.
+
+pub(crate) mod blend;
+mod features;
+pub(crate) mod homography;
+
+use blend::Plane;
+use homography::Homography;
+
+use crate::{
+  color_space::{cmyk_to_rgb, ColorSpace},
+  error::{Error, Result},
+  image::Implementation,
+  limits::Limits,
+  ops::{
+    meter::luminance,
+    patch_match::XorShift,
+    progressive::Refinement,
+    register::sample,
+    transform,
+  },
+  pixel::{PixelComponent, PixelContainer},
+  progress::Progress,
+  Image,
+  ImageBuffer,
+};
+
+/// A step of the stitching pipeline, reported by [`name`](Stage::name) as
+/// a stage of [`StitchOptions::progress`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+  /// Detecting and describing corners in each image
+  Features,
+  /// Matching neighboring images and estimating their homographies
+  Alignment,
+  /// Resampling each image onto the panorama canvas
+  Warping,
+  /// Choosing which image each canvas pixel comes from
+  Seams,
+  /// Blending the images across the seams
+  Blending,
+}
+
+impl Stage {
+  /// Name of the stage given to
+  /// [`Progress::with_stage_callback`] callbacks
+  pub fn name(self) -> &'static str {
+    match self {
+      Stage::Features => "features",
+      Stage::Alignment => "alignment",
+      Stage::Warping => "warping",
+      Stage::Seams => "seams",
+      Stage::Blending => "blending",
+    }
+  }
+
+  /// Rough share of the total work done before and by the end of the stage
+  fn span(self) -> (f32, f32) {
+    match self {
+      Stage::Features => (0.0, 0.3),
+      Stage::Alignment => (0.3, 0.4),
+      Stage::Warping => (0.4, 0.7),
+      Stage::Seams => (0.7, 0.75),
+      Stage::Blending => (0.75, 1.0),
+    }
+  }
+}
+
+/// Settings for [`stitch`]
+pub struct StitchOptions {
+  /// Most corners kept per image
+  pub max_features:      usize,
+  /// Lowe's ratio: a match is kept only if it is this much closer than the
+  /// second-best candidate
+  pub match_ratio:       f64,
+  /// Reprojection error, in pixels, below which a match counts as an inlier
+  pub ransac_threshold:  f64,
+  pub ransac_iterations: usize,
+  /// Inlier matches required to accept the alignment of two images
+  pub min_inliers:       usize,
+  /// Frequency bands used for blending; more bands hide exposure
+  /// differences over wider areas
+  pub bands:             usize,
+  /// Largest canvas, in pixels, before stitching is abandoned. Guards
+  /// against degenerate alignments blowing up the output.
+  pub max_pixels:        usize,
+  /// Told how far the pipeline is, overall or through each [`Stage`], and
+  /// checked for cancellation, which makes the stitch fail with
+  /// [`Error::Cancelled`]
+  pub progress:          Progress,
+}
+
+impl Default for StitchOptions {
+  fn default() -> Self {
+    StitchOptions {
+      max_features:      500,
+      match_ratio:       0.8,
+      ransac_threshold:  3.0,
+      ransac_iterations: 500,
+      min_inliers:       8,
+      bands:             5,
+      max_pixels:        100_000_000,
+      progress:          Progress::new(),
+    }
+  }
+}
+
+impl StitchOptions {
+  fn report(&self, stage: Stage, done: usize, total: usize) -> Result<()> {
+    let (start, end) = stage.span();
+    self
+      .progress
+      .stage(stage.name(), start, end)
+      .report(done as f32 / total.max(1) as f32)
+  }
+}
+
+fn unit<T: PixelComponent>(value: T) -> f32 {
+  (value.to_f64().unwrap_or_default() / T::WHITE.to_f64().unwrap_or(1.0)) as f32
+}
+
+fn rgb_from<T: PixelComponent>(
+  data: &ColorSpace<T>,
+) -> Result<ImageBuffer<f32, 3, false>> {
+  match data {
+    ColorSpace::Rgb(buf) => Ok(buf.as_other_scaled()),
+    ColorSpace::Rgba(buf) => {
+      let mut rgb =
+        ImageBuffer::try_empty(buf.width, buf.height, &Limits::none())?;
+      for (out, pel) in rgb.iter_pixels_mut().zip(buf.iter_pixels()) {
+        *out = [unit(pel[0]), unit(pel[1]), unit(pel[2])];
+      }
+      Ok(rgb)
+    }
+    ColorSpace::Cmyk(buf) => {
+      let mut rgb =
+        ImageBuffer::try_empty(buf.width, buf.height, &Limits::none())?;
+      for (out, pel) in rgb.iter_pixels_mut().zip(buf.iter_pixels()) {
+        *out = cmyk_to_rgb::<T, f32>(pel);
+      }
+      Ok(rgb)
+    }
+    ColorSpace::Hsv(_) | ColorSpace::Cielab(_) =>
+      Err(Error::Unsupported(
+        "Stitching needs RGB, RGBA or CMYK images".to_string(),
+      )),
+  }
+}
+
+/// The image as RGB with components between 0 and 1
+fn to_rgb(image: &Image) -> Result<ImageBuffer<f32, 3, false>> {
+  match &image.imp {
+    Implementation::U8(imp) => rgb_from(&imp.data),
+    Implementation::U16(imp) => rgb_from(&imp.data),
+    Implementation::U32(imp) => rgb_from(&imp.data),
+    Implementation::F32(imp) => rgb_from(&imp.data),
+    Implementation::F64(imp) => rgb_from(&imp.data),
+  }
+}
+
+/// Homographies taking each image into the frame of the middle one, from
+/// the pairwise homographies between neighbors
+fn chain(pairwise: &[Homography], count: usize) -> Result<Vec<Homography>> {
+  let reference = count / 2;
+  let mut to_reference = vec![homography::IDENTITY; count];
+  for k in reference + 1..count {
+    // `pairwise[k - 1]` maps image k into image k - 1
+    to_reference[k] =
+      homography::multiply(&to_reference[k - 1], &pairwise[k - 1]);
+  }
+  for k in (0..reference).rev() {
+    let inverse = homography::invert(&pairwise[k]).ok_or_else(|| {
+      Error::Unsupported(format!("Degenerate alignment of image {}", k + 1))
+    })?;
+    to_reference[k] = homography::multiply(&to_reference[k + 1], &inverse);
+  }
+  Ok(to_reference)
+}
+
+/// Stitches overlapping photos into a panorama.
+///
+/// The images must be given in order, each overlapping the next, as when
+/// panning across a scene. The result is an RGBA `f32` image in the frame
+/// of the middle photo, transparent where no photo covers the canvas.
+///
+/// Fails if two neighboring images cannot be aligned, or if the panorama
+/// would exceed [`StitchOptions::max_pixels`].
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(images = images.len(), width, height)
+  )
+)]
+pub fn stitch(images: &[Image], options: StitchOptions) -> Result<Image> {
+  if images.is_empty() {
+    return Err(Error::Unsupported(
+      "Stitching needs at least one image".to_string(),
+    ));
+  }
+  let rgb: Vec<ImageBuffer<f32, 3, false>> =
+    images.iter().map(to_rgb).collect::<Result<_>>()?;
+  stitch_rgb(&rgb, &options)
+}
+
+/// [`stitch`] on images already converted to RGB
+fn stitch_rgb(
+  rgb: &[ImageBuffer<f32, 3, false>],
+  options: &StitchOptions,
+) -> Result<Image> {
+  if rgb
+    .iter()
+    .any(|image| image.width == 0 || image.height == 0)
+  {
+    return Err(Error::Unsupported(
+      "Cannot stitch an empty image".to_string(),
+    ));
+  }
+
+  let mut features = Vec::with_capacity(rgb.len());
+  for (i, image) in rgb.iter().enumerate() {
+    let luma: Vec<f64> = image
+      .iter_pixels()
+      .map(luminance::<ImageBuffer<f32, 3, false>>)
+      .collect();
+    features.push(features::detect(
+      &luma,
+      image.width,
+      image.height,
+      options.max_features,
+    ));
+    options.report(Stage::Features, i + 1, rgb.len())?;
+  }
+
+  let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+  let mut pairwise = Vec::with_capacity(rgb.len().saturating_sub(1));
+  for i in 1..rgb.len() {
+    let pairs = features::match_features(
+      &features[i],
+      &features[i - 1],
+      options.match_ratio,
+    );
+    let (h, inliers) = homography::ransac(
+      &pairs,
+      options.ransac_threshold,
+      options.ransac_iterations,
+      &mut rng,
+    )
+    .unwrap_or((homography::IDENTITY, 0));
+    if inliers < options.min_inliers.max(4) {
+      return Err(Error::Unsupported(format!(
+        "Could not align images {} and {}: {inliers} consistent matches",
+        i - 1,
+        i
+      )));
+    }
+    pairwise.push(h);
+    options.report(Stage::Alignment, i, rgb.len() - 1)?;
+  }
+  let to_reference = chain(&pairwise, rgb.len())?;
+
+  // Canvas bounds from the warped corners of every image
+  let (mut min, mut max) = (
+    (f64::INFINITY, f64::INFINITY),
+    (f64::NEG_INFINITY, f64::NEG_INFINITY),
+  );
+  for (image, h) in rgb.iter().zip(&to_reference) {
+    let (w, ht) = ((image.width - 1) as f64, (image.height - 1) as f64);
+    for (x, y) in [(0.0, 0.0), (w, 0.0), (0.0, ht), (w, ht)] {
+      let (x, y) = homography::apply(h, x, y).ok_or_else(|| {
+        Error::Unsupported("Image corner maps to infinity".to_string())
+      })?;
+      min = (min.0.min(x), min.1.min(y));
+      max = (max.0.max(x), max.1.max(y));
+    }
+  }
+  let width = (max.0 - min.0).floor() as usize + 1;
+  let height = (max.1 - min.1).floor() as usize + 1;
+  if width.saturating_mul(height) > options.max_pixels {
+    return Err(Error::Unsupported(format!(
+      "Panorama of {width}x{height} exceeds the pixel limit"
+    )));
+  }
+  #[cfg(feature = "tracing")]
+  tracing::Span::current()
+    .record("width", width)
+    .record("height", height);
+  let offset = [[1.0, 0.0, -min.0], [0.0, 1.0, -min.1], [0.0, 0.0, 1.0]];
+
+  let mut warped = Vec::with_capacity(rgb.len());
+  let mut coverage = Vec::with_capacity(rgb.len());
+  let mut centers = Vec::with_capacity(rgb.len());
+  for (i, (image, h)) in rgb.iter().zip(&to_reference).enumerate() {
+    let to_canvas = homography::multiply(&offset, h);
+    let from_canvas = homography::invert(&to_canvas).ok_or_else(|| {
+      Error::Unsupported(format!("Degenerate alignment of image {i}"))
+    })?;
+    let planes: Vec<Vec<f64>> = (0..3)
+      .map(|c| image.iter_pixels().map(|p| f64::from(p[c])).collect())
+      .collect();
+    let mut channels = vec![Plane::new(width, height); 3];
+    let mut covered = Plane::new(width, height);
+    for j in 0..width * height {
+      let Some((x, y)) =
+        homography::apply(&from_canvas, (j % width) as f64, (j / width) as f64)
+      else {
+        continue;
+      };
+      for (channel, plane) in channels.iter_mut().zip(&planes) {
+        if let Some(v) = sample(plane, image.width, image.height, x, y) {
+          channel.data[j] = v as f32;
+          covered.data[j] = 1.0;
+        }
+      }
+    }
+    let center = (
+      (image.width - 1) as f64 / 2.0,
+      (image.height - 1) as f64 / 2.0,
+    );
+    centers.push(
+      homography::apply(&to_canvas, center.0, center.1).unwrap_or_default(),
+    );
+    warped.push(channels);
+    coverage.push(covered);
+    options.report(Stage::Warping, i + 1, rgb.len())?;
+  }
+
+  let masks = blend::seams(&coverage, &centers);
+  options.report(Stage::Seams, 1, 1)?;
+
+  let mut panorama =
+    ImageBuffer::<f32, 4, true>::try_empty(width, height, &Limits::none())?;
+  for c in 0..3 {
+    let channel: Vec<Plane> = warped.iter().map(|w| w[c].clone()).collect();
+    let blended = blend::multiband(&channel, &coverage, &masks, options.bands);
+    for (pel, v) in panorama.iter_pixels_mut().zip(&blended.data) {
+      pel[c] = v.clamp(0.0, 1.0);
+    }
+    options.report(Stage::Blending, c + 1, 3)?;
+  }
+  for (i, pel) in panorama.iter_pixels_mut().enumerate() {
+    if coverage.iter().any(|c| c.data[i] > 0.5) {
+      pel[3] = 1.0;
+    } else {
+      *pel = [0.0; 4];
+    }
+  }
+  Ok(Image::new_f32(ColorSpace::Rgba(panorama)))
+}
+
+/// Stitches `images` as [`stitch`] does, first from copies scaled down by
+/// 2<sup>`levels` - 1</sup>, then by each smaller power of two, and finally
+/// at full resolution. Each smaller panorama is scaled back up, so that
+/// interfaces can show a preview of the panorama early.
+///
+/// Levels too small to align are skipped; the full-resolution stitch fails
+/// as [`stitch`] would. `levels` is clamped to `1..=usize::BITS`, and
+/// cancelling [`StitchOptions::progress`] ends the iteration with
+/// [`Error::Cancelled`].
+pub fn stitch_refinements(
+  images: &[Image],
+  levels: usize,
+  options: StitchOptions,
+) -> StitchRefinements<'_> {
+  let levels = levels.clamp(1, usize::BITS as usize);
+  StitchRefinements {
+    images,
+    rgb: None,
+    level: levels,
+    levels,
+    options,
+  }
+}
+
+/// Iterator returned by [`stitch_refinements`]
+pub struct StitchRefinements<'a> {
+  images:  &'a [Image],
+  /// The images converted to RGB, once the first level needs them
+  rgb:     Option<Vec<ImageBuffer<f32, 3, false>>>,
+  /// Levels still to run; the next runs at 1 / 2<sup>`level` - 1</sup>
+  level:   usize,
+  levels:  usize,
+  options: StitchOptions,
+}
+
+impl StitchRefinements<'_> {
+  fn run(&mut self, level: usize, quality: f32) -> Result<Image> {
+    let rgb = match &self.rgb {
+      Some(rgb) => rgb,
+      None => {
+        let rgb = self.images.iter().map(to_rgb).collect::<Result<_>>()?;
+        self.rgb.insert(rgb)
+      }
+    };
+    let done = quality - 1.0 / self.levels as f32;
+    let options = StitchOptions {
+      max_features:      self.options.max_features,
+      match_ratio:       self.options.match_ratio,
+      ransac_threshold:  self.options.ransac_threshold,
+      ransac_iterations: self.options.ransac_iterations,
+      min_inliers:       self.options.min_inliers,
+      bands:             self.options.bands,
+      max_pixels:        self.options.max_pixels,
+      progress:          self.options.progress.step(done, quality),
+    };
+    if level == 0 {
+      return stitch_rgb(rgb, &options);
+    }
+    let factor = 1usize << level;
+    let small: Vec<_> = rgb
+      .iter()
+      .map(|image| {
+        transform::resize(
+          image,
+          (image.width / factor).max(1),
+          (image.height / factor).max(1),
+        )
+      })
+      .collect();
+    let panorama = stitch_rgb(&small, &options)?.to_rgba_f32()?;
+    let (width, height) = (
+      panorama.width.saturating_mul(factor),
+      panorama.height.saturating_mul(factor),
+    );
+    if width.saturating_mul(height) > options.max_pixels {
+      return Err(Error::Unsupported(format!(
+        "Panorama of {width}x{height} exceeds the pixel limit"
+      )));
+    }
+    let scaled = transform::resize(&panorama, width, height);
+    Ok(Image::new_f32(ColorSpace::Rgba(scaled)))
+  }
+}
+
+impl Iterator for StitchRefinements<'_> {
+  type Item = Result<Refinement<Image>>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    // Every image must keep a few pixels to align, at least 8 on a side
+    let shortest = self
+      .images
+      .iter()
+      .map(|image| image.width().min(image.height()))
+      .min()
+      .unwrap_or(0);
+    while self.level > 1 && shortest >> (self.level - 1) < 8 {
+      self.level -= 1;
+    }
+    while self.level > 0 {
+      self.level -= 1;
+      let quality = (self.levels - self.level) as f32 / self.levels as f32;
+      let refinement = self.options.progress.check().and_then(|()| {
+        Ok(Refinement {
+          result: self.run(self.level, quality)?,
+          quality,
+        })
+      });
+      match refinement {
+        // A coarse level may lack the detail to align; try a finer one
+        Err(Error::Unsupported(_)) if self.level > 0 => continue,
+        Err(error) => {
+          self.level = 0;
+          return Some(Err(error));
+        }
+        Ok(refinement) => return Some(Ok(refinement)),
+      }
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use super::*;
+
+  /// Blocks of pseudo-random brightness, rich in distinctive corners
+  fn scene(width: usize, height: usize) -> ImageBuffer<f32, 3, false> {
+    ImageBuffer::empty(width, height).map_indexed(&mut |x, y, _| {
+      let mut h = ((x / 8) as u64).wrapping_mul(0x9e37_79b9)
+        ^ ((y / 8) as u64).wrapping_mul(0x85eb_ca6b);
+      h ^= h >> 13;
+      h = h.wrapping_mul(0xc2b2_ae35);
+      h ^= h >> 16;
+      let v = (h % 1000) as f32 / 1000.0;
+      [v, 1.0 - v, 0.5 * v]
+    })
+  }
+
+  fn crop(
+    image: &ImageBuffer<f32, 3, false>,
+    x0: usize,
+    width: usize,
+  ) -> Image {
+    let cropped = ImageBuffer::empty(width, image.height)
+      .map_indexed(&mut |x, y, _| *image.get_pixel(x0 + x, y));
+    Image::new_f32(ColorSpace::Rgb(cropped))
+  }
+
+  #[test]
+  fn stitch_two_overlapping_crops() {
+    let scene = scene(140, 60);
+    let images = [crop(&scene, 0, 90), crop(&scene, 50, 90)];
+    let stages = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&stages);
+    let options = StitchOptions {
+      progress: Progress::with_stage_callback(move |stage, _| {
+        seen.lock().unwrap().push(stage.to_string())
+      }),
+      ..Default::default()
+    };
+
+    let panorama = stitch(&images, options).unwrap();
+    assert!(
+      (139..=141).contains(&panorama.width()),
+      "{}",
+      panorama.width()
+    );
+    assert!((59..=61).contains(&panorama.height()));
+    assert_eq!(
+      stages.lock().unwrap().last().map(String::as_str),
+      Some(Stage::Blending.name())
+    );
+
+    let progress = Progress::new();
+    progress.cancel();
+    let cancelled = StitchOptions {
+      progress,
+      ..Default::default()
+    };
+    assert!(matches!(stitch(&images, cancelled), Err(Error::Cancelled)));
+
+    let Implementation::F32(imp) = &panorama.imp else {
+      panic!("Wrong component type");
+    };
+    let ColorSpace::Rgba(buf) = &imp.data else {
+      panic!("Wrong color space");
+    };
+    // The panorama is in the frame of the second image
+    let expected = scene.get_pixel(70, 30);
+    let actual = buf.get_pixel(70, 30);
+    for c in 0..3 {
+      assert!(
+        (expected[c] - actual[c]).abs() < 0.05,
+        "{expected:?} {actual:?}"
+      );
+    }
+    assert_eq!(actual[3], 1.0);
+  }
+
+  #[test]
+  fn stitch_refines_from_small_copies() {
+    let scene = scene(280, 120);
+    let images = [crop(&scene, 0, 180), crop(&scene, 100, 180)];
+    let steps: Vec<_> =
+      stitch_refinements(&images, usize::MAX, StitchOptions::default())
+        .collect::<Result<_>>()
+        .unwrap();
+    assert!(steps.len() > 1);
+    let last = steps.last().unwrap();
+    assert_eq!(last.quality, 1.0);
+    assert!((279..=281).contains(&last.result.width()));
+    assert!(steps.windows(2).all(|w| w[0].quality < w[1].quality));
+    for step in &steps {
+      assert!(
+        (270..=290).contains(&step.result.width()),
+        "{} at {}",
+        step.result.width(),
+        step.quality
+      );
+    }
+
+    let progress = Progress::new();
+    progress.cancel();
+    let cancelled = StitchOptions {
+      progress,
+      ..Default::default()
+    };
+    let mut steps = stitch_refinements(&images, 2, cancelled);
+    assert!(matches!(steps.next(), Some(Err(Error::Cancelled))));
+    assert!(steps.next().is_none());
+  }
+
+  #[test]
+  fn stitch_rejects_unrelated_images() {
+    let a = crop(&scene(90, 60), 0, 90);
+    let b = Image::new_f32(ColorSpace::Rgb(ImageBuffer::empty(90, 60)));
+    assert!(matches!(
+      stitch(&[a, b], StitchOptions::default()),
+      Err(Error::Unsupported(_))
+    ));
+  }
+}
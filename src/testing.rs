@@ -0,0 +1,282 @@
+//! Helpers for testing code built on [`ImageBuffer`]: proptest strategies for
+//! random buffers, approximate comparison with a readable failure report, and
+//! golden-image checks against PNGs checked in next to the tests.
+//!
+//! Available to this crate's own tests and, for dependents, behind the
+//! `testing` feature.
+
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+};
+
+use image::ExtendedColorType;
+use proptest::{arbitrary::any, collection::vec, prelude::*};
+
+use crate::{
+  pixel::{PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// Strategy producing buffers between 1x1 and `max_width`x`max_height` pixels,
+/// with every component drawn from `component`.
+pub fn arb_image_buffer_with<
+  Component: PixelComponent + fmt::Debug,
+  const COMPONENTS_PER_PEL: usize,
+  const HAS_ALPHA: bool,
+>(
+  component: impl Strategy<Value = Component> + Clone,
+  max_width: usize,
+  max_height: usize,
+) -> impl Strategy<Value = ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>>
+{
+  (1..=max_width.max(1), 1..=max_height.max(1)).prop_flat_map(
+    move |(width, height)| {
+      vec(component.clone(), width * height * COMPONENTS_PER_PEL).prop_map(
+        move |data| {
+          ImageBuffer::with_data(data, width, height)
+            .expect("Strategy generates exactly one image worth of data")
+        },
+      )
+    },
+  )
+}
+
+/// Strategy producing buffers between 1x1 and `max_width`x`max_height` pixels,
+/// with arbitrary component values.
+///
+/// For float components this includes NaN and infinities; use
+/// [`arb_image_buffer_with`] to restrict the range.
+pub fn arb_image_buffer<
+  Component: PixelComponent + Arbitrary + fmt::Debug,
+  const COMPONENTS_PER_PEL: usize,
+  const HAS_ALPHA: bool,
+>(
+  max_width: usize,
+  max_height: usize,
+) -> impl Strategy<Value = ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>>
+where
+  Component::Strategy: Clone,
+{
+  arb_image_buffer_with(any::<Component>(), max_width, max_height)
+}
+
+/// Summary of the per-component differences between two buffers
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageDiff {
+  /// Largest absolute difference between corresponding components
+  pub max_diff:    f64,
+  /// `(x, y, component)` of the first component with the largest difference
+  pub max_diff_at: Option<(usize, usize, usize)>,
+  /// Number of components differing by more than the tolerance
+  pub mismatched:  usize,
+  /// Total number of components compared
+  pub compared:    usize,
+}
+
+impl fmt::Display for ImageDiff {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} of {} components differ, max difference {}",
+      self.mismatched, self.compared, self.max_diff
+    )?;
+    if let Some((x, y, c)) = self.max_diff_at {
+      write!(f, " at pixel ({x}, {y}) component {c}")?;
+    }
+    Ok(())
+  }
+}
+
+/// Compares two buffers component by component, counting differences larger
+/// than `tolerance`.
+///
+/// Panics if the buffers have different dimensions.
+pub fn diff<
+  Component: PixelComponent,
+  const COMPONENTS_PER_PEL: usize,
+  const HAS_ALPHA: bool,
+>(
+  actual: &ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>,
+  expected: &ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>,
+  tolerance: f64,
+) -> ImageDiff {
+  assert_eq!(
+    (actual.width, actual.height),
+    (expected.width, expected.height),
+    "Image dimensions differ"
+  );
+
+  let mut result = ImageDiff {
+    compared: actual.pixels().len(),
+    ..Default::default()
+  };
+  let width = actual.width.max(1);
+
+  for (i, (a, e)) in actual
+    .pixels()
+    .iter()
+    .zip(expected.pixels().iter())
+    .enumerate()
+  {
+    let a = a.to_f64().unwrap_or(f64::NAN);
+    let e = e.to_f64().unwrap_or(f64::NAN);
+    let d = if a == e { 0.0 } else { (a - e).abs() };
+    if d > tolerance || d.is_nan() {
+      result.mismatched += 1;
+    }
+    if d > result.max_diff || (d.is_nan() && !result.max_diff.is_nan()) {
+      let pel = i / COMPONENTS_PER_PEL;
+      result.max_diff = d;
+      result.max_diff_at =
+        Some((pel % width, pel / width, i % COMPONENTS_PER_PEL));
+    }
+  }
+
+  result
+}
+
+/// Panics with a difference report unless every component of `actual` is
+/// within `tolerance` of `expected`.
+#[track_caller]
+pub fn assert_approx_eq<
+  Component: PixelComponent,
+  const COMPONENTS_PER_PEL: usize,
+  const HAS_ALPHA: bool,
+>(
+  actual: &ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>,
+  expected: &ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>,
+  tolerance: f64,
+) {
+  let result = diff(actual, expected, tolerance);
+  assert!(
+    result.mismatched == 0,
+    "Images differ beyond tolerance {tolerance}: {result}"
+  );
+}
+
+/// Path of the golden image `name` within the `tests/golden` directory of the
+/// crate under test
+pub fn golden_path(name: &str) -> PathBuf {
+  let root = std::env::var_os("CARGO_MANIFEST_DIR")
+    .map(PathBuf::from)
+    .unwrap_or_default();
+  root
+    .join("tests")
+    .join("golden")
+    .join(format!("{name}.png"))
+}
+
+fn color_type<const COMPONENTS_PER_PEL: usize, const HAS_ALPHA: bool>(
+) -> ExtendedColorType {
+  match (COMPONENTS_PER_PEL, HAS_ALPHA) {
+    (1, false) => ExtendedColorType::L8,
+    (2, true) => ExtendedColorType::La8,
+    (3, false) => ExtendedColorType::Rgb8,
+    (4, true) => ExtendedColorType::Rgba8,
+    _ => panic!("Golden images must be gray, gray+alpha, RGB, or RGBA"),
+  }
+}
+
+fn load_golden<const COMPONENTS_PER_PEL: usize, const HAS_ALPHA: bool>(
+  path: &Path,
+) -> ImageBuffer<u8, COMPONENTS_PER_PEL, HAS_ALPHA> {
+  let decoded = image::open(path).unwrap_or_else(|e| {
+    panic!("Failed to open golden image {}: {e}", path.display())
+  });
+  let (width, height) = (decoded.width() as usize, decoded.height() as usize);
+  let data = match color_type::<COMPONENTS_PER_PEL, HAS_ALPHA>() {
+    ExtendedColorType::L8 => decoded.into_luma8().into_raw(),
+    ExtendedColorType::La8 => decoded.into_luma_alpha8().into_raw(),
+    ExtendedColorType::Rgb8 => decoded.into_rgb8().into_raw(),
+    _ => decoded.into_rgba8().into_raw(),
+  };
+  ImageBuffer::with_data(data, width, height)
+    .expect("Decoded golden image has the requested layout")
+}
+
+/// Compares an 8-bit buffer against the golden PNG `name`, allowing each
+/// component to differ by up to `tolerance`.
+///
+/// When the `UPDATE_GOLDEN` environment variable is set, the buffer is
+/// written out as the new golden image instead. Otherwise a missing golden
+/// image fails the assertion, so that a test can only pass against a golden
+/// image someone chose to record.
+#[track_caller]
+pub fn assert_matches_golden<
+  const COMPONENTS_PER_PEL: usize,
+  const HAS_ALPHA: bool,
+>(
+  actual: &ImageBuffer<u8, COMPONENTS_PER_PEL, HAS_ALPHA>,
+  name: &str,
+  tolerance: u8,
+) {
+  let path = golden_path(name);
+  if std::env::var_os("UPDATE_GOLDEN").is_some() {
+    if let Some(dir) = path.parent() {
+      std::fs::create_dir_all(dir).expect("Failed to create golden directory");
+    }
+    image::save_buffer(
+      &path,
+      actual.pixels(),
+      actual.width as u32,
+      actual.height as u32,
+      color_type::<COMPONENTS_PER_PEL, HAS_ALPHA>(),
+    )
+    .unwrap_or_else(|e| {
+      panic!("Failed to write golden image {}: {e}", path.display())
+    });
+    return;
+  }
+  assert!(
+    path.exists(),
+    "Golden image {} is missing; run with UPDATE_GOLDEN=1 to record it",
+    path.display()
+  );
+
+  let expected = load_golden::<COMPONENTS_PER_PEL, HAS_ALPHA>(&path);
+  let result = diff(actual, &expected, f64::from(tolerance));
+  assert!(
+    result.mismatched == 0,
+    "Image differs from golden {}: {result}",
+    path.display()
+  );
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  proptest! {
+    #[test]
+    fn diff_of_identical_buffers_is_zero(
+      image in arb_image_buffer::<u8, 3, false>(8, 8)
+    ) {
+      let result = diff(&image, &image, 0.0);
+      prop_assert_eq!(result.mismatched, 0);
+      prop_assert_eq!(result.compared, image.width * image.height * 3);
+    }
+  }
+
+  #[test]
+  fn diff_reports_largest_difference() {
+    let a = ImageBuffer::<u8, 2, false>::with_data(vec![0; 8], 2, 2).unwrap();
+    let b = ImageBuffer::<u8, 2, false>::with_data(
+      vec![0, 0, 1, 0, 0, 0, 0, 9],
+      2,
+      2,
+    )
+    .unwrap();
+    let result = diff(&a, &b, 1.0);
+    assert_eq!(result.max_diff, 9.0);
+    assert_eq!(result.max_diff_at, Some((1, 1, 1)));
+    assert_eq!(result.mismatched, 1);
+  }
+
+  #[test]
+  fn gradient_matches_golden() {
+    let image = ImageBuffer::<u8, 3, false>::empty(16, 16)
+      .map_indexed(&mut |x, y, _| [(x * 16) as u8, (y * 16) as u8, 128]);
+    assert_matches_golden(&image, "gradient_rgb_u8", 0);
+  }
+}
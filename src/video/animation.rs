@@ -0,0 +1,442 @@
+//! Preparing frames for animated GIF and WebP files so that they encode
+//! small.
+//!
+//! [`optimize`] turns full frames into the partial frames such formats
+//! store: each frame covers only the rectangle that changed, with the
+//! disposal of the frame before chosen to make that rectangle as small as
+//! possible, pixels that did not change left transparent so they compress
+//! well, and one global palette where every frame fits in it. The result
+//! maps directly onto a GIF encoder; WebP encoders ignore the palettes.
+
+use std::{
+  collections::{HashMap, HashSet},
+  time::Duration,
+};
+
+use super::{check_dimensions, Frame};
+use crate::{error::Result, ops::sprites::Rect, ImageBuffer, PixelContainer};
+
+type Rgba8 = ImageBuffer<u8, 4, true>;
+
+/// Colors a palette can hold, leaving one index for transparency
+const MAX_COLORS: usize = 255;
+
+/// Display time of a lone frame, or of the last frame when there is no
+/// interval to repeat
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+const CLEAR: [u8; 4] = [0; 4];
+
+/// What happens to a frame's rectangle before the next frame is drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Disposal {
+  /// Leave the frame in place
+  #[default]
+  Keep,
+  /// Clear the rectangle to transparent
+  Background,
+  /// Restore what was there before the frame was drawn
+  Previous,
+}
+
+/// One partial frame of an [`Animation`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationFrame {
+  /// Part of the canvas the frame covers
+  pub rect:        Rect,
+  /// Palette index of each pixel in `rect`, row by row
+  pub indices:     Vec<u8>,
+  /// The frame's own palette, or `None` to use the global one
+  pub palette:     Option<Vec<[u8; 3]>>,
+  /// Index that leaves the pixel below unchanged, if the frame uses one
+  pub transparent: Option<u8>,
+  pub disposal:    Disposal,
+  pub delay:       Duration,
+}
+
+/// Frames ready for an animation encoder
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+  pub width:          usize,
+  pub height:         usize,
+  /// Palette shared by every frame that has none of its own
+  pub global_palette: Option<Vec<[u8; 3]>>,
+  pub frames:         Vec<AnimationFrame>,
+}
+
+/// Reduces `frames` to what an animated file has to store. Frames that
+/// repeat the one before are merged into it, extending its delay.
+///
+/// Pixels are opaque or, with alpha below half, fully transparent. A global
+/// palette is used when all frames together have at most 255 colors;
+/// otherwise each frame gets its own, reduced by median cut where the frame
+/// alone has more. Fails unless all frames have the same size.
+pub fn optimize(frames: &[Frame<Rgba8>]) -> Result<Animation> {
+  let (width, height) = frames
+    .first()
+    .map_or((0, 0), |f| (f.image.width, f.image.height));
+  for frame in frames {
+    check_dimensions((width, height), &frame.image)?;
+  }
+  let targets: Vec<Vec<[u8; 4]>> = frames
+    .iter()
+    .map(|f| {
+      f.image
+        .iter_pixels()
+        .map(|p| {
+          if p[3] < 128 {
+            CLEAR
+          } else {
+            [p[0], p[1], p[2], 255]
+          }
+        })
+        .collect()
+    })
+    .collect();
+
+  let all: HashSet<[u8; 3]> = targets.iter().flat_map(|t| colors(t)).collect();
+  let (global_palette, targets, palettes) = if all.len() <= MAX_COLORS {
+    let mut palette: Vec<_> = all.into_iter().collect();
+    palette.sort_unstable();
+    (Some(palette), targets, vec![None; frames.len()])
+  } else {
+    let palettes: Vec<_> = targets.iter().map(|t| frame_palette(t)).collect();
+    let targets = targets
+      .iter()
+      .zip(&palettes)
+      .map(|(t, p)| remap(t, p))
+      .collect();
+    (None, targets, palettes.into_iter().map(Some).collect())
+  };
+
+  let full = Rect {
+    x: 0,
+    y: 0,
+    width,
+    height,
+  };
+  let mut out: Vec<AnimationFrame> = Vec::new();
+  // What was displayed before the last emitted frame was drawn, and after
+  let mut before = vec![CLEAR; width * height];
+  let mut shown = before.clone();
+  let mut last_rect = full;
+  // Input frame the last emitted frame was made from
+  let mut last_input = 0;
+  let palette = |i: usize| {
+    match &palettes[i] {
+      Some(local) => (local.as_slice(), true),
+      None => (global_palette.as_deref().unwrap_or_default(), false),
+    }
+  };
+  for (i, target) in targets.iter().enumerate() {
+    let delay = match frames.get(i + 1) {
+      Some(next) => next.timestamp.saturating_sub(frames[i].timestamp),
+      None if i > 0 =>
+        frames[i].timestamp.saturating_sub(frames[i - 1].timestamp),
+      None => DEFAULT_DELAY,
+    };
+    if let Some(last) = out.last_mut().filter(|_| *target == shown) {
+      last.delay += delay;
+      continue;
+    }
+
+    let mut candidates = vec![(Disposal::Keep, shown.clone())];
+    if !out.is_empty() {
+      let mut cleared = shown.clone();
+      for y in last_rect.y..last_rect.y + last_rect.height {
+        cleared[y * width + last_rect.x..][..last_rect.width].fill(CLEAR);
+      }
+      candidates.push((Disposal::Background, cleared));
+      candidates.push((Disposal::Previous, before.clone()));
+    }
+    let best = candidates
+      .into_iter()
+      .filter_map(|(d, canvas)| {
+        changed_rect(&canvas, target, width).map(|rect| (d, canvas, rect))
+      })
+      .min_by_key(|(_, _, rect)| rect.width * rect.height);
+    let (disposal, canvas, rect) = match best {
+      Some(best) => best,
+      None => {
+        // Transparency reappears where an earlier frame was kept: redraw
+        // the last frame whole and clear it all afterwards
+        let last = out.len() - 1;
+        let delay = out[last].delay;
+        out[last] = encode(&shown, &before, full, width, palette(last_input));
+        out[last].delay = delay;
+        let canvas = vec![CLEAR; width * height];
+        let rect = changed_rect(&canvas, target, width).unwrap_or(full);
+        (Disposal::Background, canvas, rect)
+      }
+    };
+    if let Some(last) = out.last_mut() {
+      last.disposal = disposal;
+    }
+    let mut frame = encode(target, &canvas, rect, width, palette(i));
+    frame.delay = delay;
+    out.push(frame);
+    before = canvas;
+    shown.clone_from(target);
+    last_rect = rect;
+    last_input = i;
+  }
+  Ok(Animation {
+    width,
+    height,
+    global_palette,
+    frames: out,
+  })
+}
+
+/// Distinct opaque colors of `pixels`
+fn colors(pixels: &[[u8; 4]]) -> HashSet<[u8; 3]> {
+  pixels
+    .iter()
+    .filter(|p| p[3] != 0)
+    .map(|p| [p[0], p[1], p[2]])
+    .collect()
+}
+
+/// The colors of one frame, reduced if there are too many
+fn frame_palette(pixels: &[[u8; 4]]) -> Vec<[u8; 3]> {
+  let distinct = colors(pixels);
+  if distinct.len() <= MAX_COLORS {
+    let mut palette: Vec<_> = distinct.into_iter().collect();
+    palette.sort_unstable();
+    return palette;
+  }
+  let opaque: Vec<[u8; 3]> = pixels
+    .iter()
+    .filter(|p| p[3] != 0)
+    .map(|p| [p[0], p[1], p[2]])
+    .collect();
+  median_cut(opaque, MAX_COLORS)
+}
+
+/// Splits the colors into `max` boxes, each time halving the box with the
+/// widest range in one channel at its median, and returns the mean of each
+fn median_cut(colors: Vec<[u8; 3]>, max: usize) -> Vec<[u8; 3]> {
+  let widest = |colors: &[[u8; 3]]| {
+    (0..3)
+      .map(|c| {
+        let (lo, hi) = colors
+          .iter()
+          .fold((u8::MAX, 0), |(lo, hi), p| (lo.min(p[c]), hi.max(p[c])));
+        (c, hi.saturating_sub(lo))
+      })
+      .max_by_key(|&(_, range)| range)
+      .unwrap_or((0, 0))
+  };
+  let mut boxes = vec![colors];
+  while boxes.len() < max {
+    let Some((i, (channel, _))) = boxes
+      .iter()
+      .map(|b| widest(b))
+      .enumerate()
+      .filter(|(_, (_, range))| *range > 0)
+      .max_by_key(|(_, (_, range))| *range)
+    else {
+      break;
+    };
+    let mut lower = boxes.swap_remove(i);
+    lower.sort_unstable_by_key(|p| p[channel]);
+    let upper = lower.split_off(lower.len() / 2);
+    boxes.push(lower);
+    boxes.push(upper);
+  }
+  boxes
+    .iter()
+    .filter(|b| !b.is_empty())
+    .map(|b| {
+      std::array::from_fn(|c| {
+        let sum: usize = b.iter().map(|p| usize::from(p[c])).sum();
+        (sum / b.len()) as u8
+      })
+    })
+    .collect()
+}
+
+/// Replaces each opaque pixel with the nearest color of `palette`
+fn remap(pixels: &[[u8; 4]], palette: &[[u8; 3]]) -> Vec<[u8; 4]> {
+  let mut nearest = HashMap::new();
+  pixels
+    .iter()
+    .map(|&p| {
+      if p[3] == 0 {
+        return CLEAR;
+      }
+      let [r, g, b] = *nearest.entry(p).or_insert_with(|| {
+        palette
+          .iter()
+          .copied()
+          .min_by_key(|q| {
+            (0..3)
+              .map(|c| (i32::from(p[c]) - i32::from(q[c])).pow(2))
+              .sum::<i32>()
+          })
+          .unwrap_or_default()
+      });
+      [r, g, b, 255]
+    })
+    .collect()
+}
+
+/// The smallest rectangle holding every pixel where `target` differs from
+/// `canvas`, or `None` if drawing over `canvas` cannot produce `target`
+/// because it clears a pixel the canvas shows. Identical images give a
+/// one-pixel rectangle, since formats need at least one.
+fn changed_rect(
+  canvas: &[[u8; 4]],
+  target: &[[u8; 4]],
+  width: usize,
+) -> Option<Rect> {
+  let (mut x0, mut y0, mut x1, mut y1) = (usize::MAX, usize::MAX, 0, 0);
+  for (i, (c, t)) in canvas.iter().zip(target).enumerate() {
+    if c == t {
+      continue;
+    }
+    if *t == CLEAR {
+      return None;
+    }
+    let (x, y) = (i % width, i / width);
+    (x0, y0, x1, y1) = (x0.min(x), y0.min(y), x1.max(x + 1), y1.max(y + 1));
+  }
+  Some(if x0 == usize::MAX {
+    Rect {
+      x:      0,
+      y:      0,
+      width:  1.min(width),
+      height: 1.min(target.len()),
+    }
+  } else {
+    Rect {
+      x:      x0,
+      y:      y0,
+      width:  x1 - x0,
+      height: y1 - y0,
+    }
+  })
+}
+
+/// Indexes the pixels of `target` in `rect`, leaving those already shown on
+/// `canvas` transparent. The transparent index follows the palette.
+fn encode(
+  target: &[[u8; 4]],
+  canvas: &[[u8; 4]],
+  rect: Rect,
+  width: usize,
+  (palette, local): (&[[u8; 3]], bool),
+) -> AnimationFrame {
+  let lookup: HashMap<[u8; 3], u8> = palette
+    .iter()
+    .enumerate()
+    .map(|(i, &color)| (color, i as u8))
+    .collect();
+  let clear = palette.len() as u8;
+  let mut transparent = false;
+  let mut indices = Vec::with_capacity(rect.width * rect.height);
+  for y in rect.y..rect.y + rect.height {
+    for i in y * width + rect.x..y * width + rect.x + rect.width {
+      let (t, c) = (target[i], canvas[i]);
+      indices.push(if t == c || t == CLEAR {
+        transparent = true;
+        clear
+      } else {
+        lookup[&[t[0], t[1], t[2]]]
+      });
+    }
+  }
+  AnimationFrame {
+    rect,
+    indices,
+    palette: local.then(|| palette.to_vec()),
+    transparent: transparent.then_some(clear),
+    disposal: Disposal::Keep,
+    delay: DEFAULT_DELAY,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn frame(pixel: impl Fn(usize, usize) -> [u8; 4], ms: u64) -> Frame<Rgba8> {
+    Frame {
+      image:     Rgba8::empty(8, 6).map_indexed(&mut |x, y, _| pixel(x, y)),
+      timestamp: Duration::from_millis(ms),
+    }
+  }
+
+  #[test]
+  fn frames_shrink_to_what_changed() {
+    let background = |_, _| [20, 40, 60, 255];
+    let square = |at: usize| {
+      move |x: usize, y: usize| {
+        if (at..at + 2).contains(&x) && (2..4).contains(&y) {
+          [250, 0, 0, 255]
+        } else {
+          background(x, y)
+        }
+      }
+    };
+    let frames = [
+      frame(square(0), 0),
+      frame(square(1), 50),
+      frame(square(1), 100),
+      frame(square(3), 200),
+    ];
+    let animation = optimize(&frames).unwrap();
+    assert_eq!(animation.global_palette.as_ref().unwrap().len(), 2);
+    assert_eq!(animation.frames.len(), 3);
+    assert_eq!(animation.frames[0].rect.width, 8);
+    // The repeated frame extends the delay of the one before
+    assert_eq!(animation.frames[1].delay, Duration::from_millis(150));
+    let moved = &animation.frames[1];
+    assert_eq!(
+      (moved.rect.x, moved.rect.width, moved.rect.height),
+      (0, 3, 2)
+    );
+    // The square's overlapping column is left to show through
+    assert_eq!(moved.transparent, Some(2));
+    assert_eq!(moved.indices[1], 2);
+
+    // Clearing pixels forces a disposal of the frame before
+    let hole = |x: usize, y: usize| {
+      if x < 2 {
+        [0; 4]
+      } else {
+        background(x, y)
+      }
+    };
+    let animation = optimize(&[frame(background, 0), frame(hole, 10)]).unwrap();
+    assert_ne!(animation.frames[0].disposal, Disposal::Keep);
+    let frames = [frame(background, 0), frame(square(2), 10), frame(hole, 20)];
+    let animation = optimize(&frames).unwrap();
+    let last = &animation.frames[2];
+    assert_eq!(animation.frames[1].disposal, Disposal::Background);
+    // The square was kept over opaque pixels, so it is redrawn whole to be
+    // cleared with them
+    assert_eq!(animation.frames[1].rect.width, 8);
+    assert_eq!((last.rect.x, last.rect.width), (2, 6));
+
+    // Too many colors for one palette
+    let ramp = |c: usize| {
+      Frame {
+        image:     Rgba8::empty(16, 16).map_indexed(&mut |x, y, _| {
+          let mut pel = [0, 0, 0, 255];
+          pel[c] = (y * 16 + x) as u8;
+          pel
+        }),
+        timestamp: Duration::ZERO,
+      }
+    };
+    let animation = optimize(&[ramp(0), ramp(1)]).unwrap();
+    assert!(animation.global_palette.is_none());
+    let palette = animation.frames[1].palette.as_ref().unwrap();
+    assert!(palette.len() <= MAX_COLORS);
+    assert!(animation.frames[1]
+      .indices
+      .iter()
+      .all(|&i| usize::from(i) <= palette.len()));
+  }
+}
@@ -0,0 +1,174 @@
+use num_traits::ToPrimitive;
+
+use crate::{
+  error::{Error, Result},
+  limits::Limits,
+  pixel::{component_from_f64, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// One of the two fields of an interlaced frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+  /// The even rows, starting with the first
+  Top,
+  /// The odd rows
+  Bottom,
+}
+
+/// How [`deinterlace`] fills the rows belonging to the discarded field
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deinterlace {
+  /// Repeats each row of the kept field
+  Bob,
+  /// Averages the kept rows above and below
+  Linear,
+}
+
+/// Splits an interlaced frame into its top (even rows) and bottom (odd rows)
+/// fields
+///
+/// For frames with an odd number of rows the top field is one row taller.
+pub fn split_fields<
+  Component: PixelComponent,
+  const COMPONENTS_PER_PEL: usize,
+  const HAS_ALPHA: bool,
+>(
+  frame: &ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>,
+) -> (
+  ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>,
+  ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>,
+) {
+  let row_len = (frame.width * COMPONENTS_PER_PEL).max(1);
+  let field = |parity: usize| {
+    let data: Vec<_> = frame
+      .components()
+      .chunks_exact(row_len)
+      .skip(parity)
+      .step_by(2)
+      .flatten()
+      .copied()
+      .collect();
+    let height = (frame.height + 1 - parity) / 2;
+    ImageBuffer::with_data(data, frame.width, height)
+      .expect("A field holds every other row of the frame")
+  };
+  (field(0), field(1))
+}
+
+/// Interleaves two fields into a full frame, the inverse of [`split_fields`]
+///
+/// The top field must have the same height as the bottom field, or one more
+/// row.
+pub fn weave<
+  Component: PixelComponent,
+  const COMPONENTS_PER_PEL: usize,
+  const HAS_ALPHA: bool,
+>(
+  top: &ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>,
+  bottom: &ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>,
+) -> Result<ImageBuffer<Component, COMPONENTS_PER_PEL, HAS_ALPHA>> {
+  if top.width != bottom.width
+    || !(top.height == bottom.height || top.height == bottom.height + 1)
+  {
+    return Err(Error::DimensionMismatch {
+      expected: (top.width, top.height),
+      actual:   (bottom.width, bottom.height),
+    });
+  }
+
+  let mut result = ImageBuffer::try_empty(
+    top.width,
+    top.height + bottom.height,
+    &Limits::none(),
+  )?;
+  let row_len = top.width * COMPONENTS_PER_PEL;
+  if row_len == 0 {
+    return Ok(result);
+  }
+  let fields = [top.components(), bottom.components()];
+  for (y, row) in result
+    .components_mut()
+    .chunks_exact_mut(row_len)
+    .enumerate()
+  {
+    let start = (y / 2) * row_len;
+    row.copy_from_slice(&fields[y % 2][start..start + row_len]);
+  }
+  Ok(result)
+}
+
+/// Converts an interlaced frame to a progressive one by keeping `field` and
+/// reconstructing the other field's rows with `method`
+pub fn deinterlace<C: PixelContainer + Clone>(
+  frame: &C,
+  field: Field,
+  method: Deinterlace,
+) -> C {
+  let mut result = frame.clone();
+  let (width, height) = (frame.width(), frame.height());
+  let row_len = width * C::NUM_COMPONENTS;
+  if row_len == 0 || height < 2 {
+    return result;
+  }
+
+  let kept = match field {
+    Field::Top => 0,
+    Field::Bottom => 1,
+  };
+  let row = |y: usize| &frame.components()[y * row_len..(y + 1) * row_len];
+  // Nearest kept row on either side of `y`, staying inside the frame
+  let above = |y: usize| if y > kept { y - 1 } else { y + 1 };
+  let below = |y: usize| if y + 1 < height { y + 1 } else { y - 1 };
+
+  let out = result.components_mut();
+  for y in (0..height).filter(|y| y % 2 != kept) {
+    let dst = &mut out[y * row_len..(y + 1) * row_len];
+    match method {
+      Deinterlace::Bob => dst.copy_from_slice(row(above(y))),
+      Deinterlace::Linear =>
+        for ((d, &a), &b) in
+          dst.iter_mut().zip(row(above(y))).zip(row(below(y)))
+        {
+          let a = a.to_f64().unwrap_or_default();
+          let b = b.to_f64().unwrap_or_default();
+          *d = component_from_f64((a + b) / 2.0);
+        },
+    }
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rows(height: usize) -> ImageBuffer<u8, 1, false> {
+    ImageBuffer::empty(2, height).map_indexed(&mut |_, y, _| [y as u8 * 10])
+  }
+
+  #[test]
+  fn split_fields_and_weave_round_trip() {
+    let frame = rows(5);
+    let (top, bottom) = split_fields(&frame);
+    assert_eq!(top.components(), &[0, 0, 20, 20, 40, 40]);
+    assert_eq!(bottom.components(), &[10, 10, 30, 30]);
+    assert_eq!(
+      weave(&top, &bottom).unwrap().components(),
+      frame.components()
+    );
+    assert!(matches!(
+      weave(&bottom, &top),
+      Err(Error::DimensionMismatch { .. })
+    ));
+  }
+
+  #[test]
+  fn deinterlace_bob_and_linear() {
+    let frame = rows(4);
+    let bob = deinterlace(&frame, Field::Top, Deinterlace::Bob);
+    assert_eq!(bob.components(), &[0, 0, 0, 0, 20, 20, 20, 20]);
+    let linear = deinterlace(&frame, Field::Bottom, Deinterlace::Linear);
+    assert_eq!(linear.components(), &[10, 10, 10, 10, 20, 20, 30, 30]);
+  }
+}
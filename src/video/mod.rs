@@ -0,0 +1,104 @@
+//! Utilities for sequences of frames, such as decoded video or camera streams.
+//!
+//! A [`FrameSequence`] pairs each image with its presentation timestamp.
+//! Operations that keep state across frames live in [`temporal`]; like
+//! [`ops`](crate::ops), they are generic over
+//! [`PixelContainer`](crate::PixelContainer).
+
+use std::time::Duration;
+
+use crate::{
+  error::{Error, Result},
+  Image,
+  PixelContainer,
+};
+
+pub mod animation;
+pub mod interlace;
+pub mod temporal;
+
+/// One image of a sequence, with the time at which it should be presented
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame<T = Image> {
+  pub image:     T,
+  pub timestamp: Duration,
+}
+
+/// An iterator of timestamped frames
+///
+/// Wraps any iterator of `(image, timestamp)` pairs, or of bare images
+/// captured at a fixed rate via [`FrameSequence::with_frame_rate`].
+#[derive(Clone, Debug)]
+pub struct FrameSequence<I> {
+  frames: I,
+}
+
+impl<T, I: Iterator<Item = (T, Duration)>> FrameSequence<I> {
+  pub fn new(frames: impl IntoIterator<IntoIter = I>) -> Self {
+    FrameSequence {
+      frames: frames.into_iter(),
+    }
+  }
+}
+
+impl<T> FrameSequence<std::vec::IntoIter<(T, Duration)>> {
+  /// Timestamps `images` as if captured at `fps` frames per second, starting
+  /// at zero
+  ///
+  /// Panics if `fps` is not positive and finite.
+  pub fn with_frame_rate(
+    images: impl IntoIterator<Item = T>,
+    fps: f64,
+  ) -> Self {
+    assert!(fps > 0.0 && fps.is_finite(), "Frame rate must be positive");
+    let frames: Vec<_> = images
+      .into_iter()
+      .enumerate()
+      .map(|(i, image)| (image, Duration::from_secs_f64(i as f64 / fps)))
+      .collect();
+    FrameSequence::new(frames)
+  }
+}
+
+impl<T, I: Iterator<Item = (T, Duration)>> Iterator for FrameSequence<I> {
+  type Item = Frame<T>;
+
+  fn next(&mut self) -> Option<Frame<T>> {
+    let (image, timestamp) = self.frames.next()?;
+    Some(Frame {
+      image,
+      timestamp,
+    })
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) { self.frames.size_hint() }
+}
+
+/// Checks that `frame` has the dimensions of the sequence it belongs to
+pub(crate) fn check_dimensions<C: PixelContainer>(
+  expected: (usize, usize),
+  frame: &C,
+) -> Result<()> {
+  let actual = (frame.width(), frame.height());
+  if actual != expected {
+    return Err(Error::DimensionMismatch {
+      expected,
+      actual,
+    });
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn frame_sequence_with_frame_rate() {
+    let frames: Vec<_> =
+      FrameSequence::with_frame_rate(["a", "b", "c"], 4.0).collect();
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[2].image, "c");
+    assert_eq!(frames[2].timestamp, Duration::from_millis(500));
+  }
+}
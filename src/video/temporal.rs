@@ -0,0 +1,233 @@
+use super::check_dimensions;
+use crate::{
+  error::Result,
+  pixel::{component_from_f64, Pixel, PixelComponent, PixelContainer},
+};
+
+fn to_f64<T: PixelComponent>(value: T) -> f64 {
+  value.to_f64().unwrap_or_default()
+}
+
+fn white<T: PixelComponent>() -> f64 { T::WHITE.to_f64().unwrap_or(1.0) }
+
+/// Smallest standard deviation a [`BackgroundModel`] assumes, as a fraction
+/// of white. Without it the variance of a static scene decays toward zero,
+/// until sensor noise alone is flagged as foreground.
+const NOISE_FLOOR: f64 = 0.005;
+
+/// Running mean of every frame pushed so far, for denoising a static scene
+#[derive(Clone, Debug)]
+pub struct TemporalAverage<C> {
+  template: Option<C>,
+  sums:     Vec<f64>,
+  count:    usize,
+}
+
+impl<C: PixelContainer + Clone> Default for TemporalAverage<C> {
+  fn default() -> Self { Self::new() }
+}
+
+impl<C: PixelContainer + Clone> TemporalAverage<C> {
+  pub fn new() -> Self {
+    TemporalAverage {
+      template: None,
+      sums:     Vec::new(),
+      count:    0,
+    }
+  }
+
+  /// Adds a frame to the average. Every frame must have the dimensions of
+  /// the first.
+  pub fn push(&mut self, frame: &C) -> Result<()> {
+    match &self.template {
+      Some(t) => check_dimensions((t.width(), t.height()), frame)?,
+      None => {
+        self.template = Some(frame.clone());
+        self.sums = vec![0.0; frame.components().len()];
+      }
+    }
+    for (sum, &c) in self.sums.iter_mut().zip(frame.components()) {
+      *sum += to_f64(c);
+    }
+    self.count += 1;
+    Ok(())
+  }
+
+  /// Number of frames pushed so far
+  pub fn len(&self) -> usize { self.count }
+
+  pub fn is_empty(&self) -> bool { self.count == 0 }
+
+  /// The mean of all frames pushed so far, or `None` before the first
+  pub fn average(&self) -> Option<C> {
+    let mut result = self.template.clone()?;
+    for (c, sum) in result.components_mut().iter_mut().zip(&self.sums) {
+      *c = component_from_f64(sum / self.count as f64);
+    }
+    Some(result)
+  }
+}
+
+/// Marks the pixels that changed between two frames.
+///
+/// A pixel is set to white in the returned mask when any of its color
+/// channels differs by more than `threshold`, given as a fraction of white.
+/// Alpha is ignored.
+pub fn frame_difference<C: PixelContainer>(
+  previous: &C,
+  current: &C,
+  threshold: f64,
+) -> Result<C::OnePlane> {
+  check_dimensions((previous.width(), previous.height()), current)?;
+  let limit = threshold * white::<C::Component>();
+  let mut mask = current.new_plane();
+  let pels = previous.iter_pixels().zip(current.iter_pixels());
+  for ((a, b), m) in pels.zip(mask.iter_pixels_mut()) {
+    let moved = (0..C::NUM_NONALPHA_COMPONENTS).any(|i| {
+      (to_f64(a.components()[i]) - to_f64(b.components()[i])).abs() > limit
+    });
+    if moved {
+      *m = [C::Component::WHITE];
+    }
+  }
+  Ok(mask)
+}
+
+/// Background model keeping a running Gaussian average per component.
+///
+/// Each [`update`](Self::update) classifies the pixels of a frame as
+/// foreground or background, then blends the frame into the model.
+#[derive(Clone, Debug)]
+pub struct BackgroundModel<C> {
+  template:      Option<C>,
+  mean:          Vec<f64>,
+  variance:      Vec<f64>,
+  learning_rate: f64,
+  deviations:    f64,
+}
+
+impl<C: PixelContainer + Clone> BackgroundModel<C> {
+  /// Creates an empty model.
+  ///
+  /// `learning_rate` is the weight in `[0, 1]` given to each new frame, and a
+  /// component is foreground when it lies more than `deviations` standard
+  /// deviations from the mean.
+  pub fn new(learning_rate: f64, deviations: f64) -> Self {
+    BackgroundModel {
+      template: None,
+      mean: Vec::new(),
+      variance: Vec::new(),
+      learning_rate: learning_rate.clamp(0.0, 1.0),
+      deviations,
+    }
+  }
+
+  /// Classifies `frame` against the model and then learns from it
+  ///
+  /// Returns a mask that is white where the frame differs from the
+  /// background. The first frame initializes the model and is entirely
+  /// background.
+  pub fn update(&mut self, frame: &C) -> Result<C::OnePlane> {
+    let mut mask = frame.new_plane();
+    let Some(template) = &self.template else {
+      self.mean = frame.components().iter().map(|&c| to_f64(c)).collect();
+      // Start with a spread of a few percent of white, so that sensor noise
+      // in the first frames is not flagged as motion
+      let spread = 0.02 * white::<C::Component>();
+      self.variance = vec![spread * spread; self.mean.len()];
+      self.template = Some(frame.clone());
+      return Ok(mask);
+    };
+    check_dimensions((template.width(), template.height()), frame)?;
+
+    let n = C::NUM_COMPONENTS;
+    let rate = self.learning_rate;
+    let floor = (NOISE_FLOOR * white::<C::Component>()).powi(2);
+    let pels = frame.components().chunks_exact(n).enumerate();
+    for ((i, pel), m) in pels.zip(mask.iter_pixels_mut()) {
+      let mut foreground = false;
+      for (j, &c) in pel.iter().enumerate() {
+        let k = i * n + j;
+        let d = to_f64(c) - self.mean[k];
+        if j < C::NUM_NONALPHA_COMPONENTS
+          && d * d > self.deviations * self.deviations * self.variance[k]
+        {
+          foreground = true;
+        }
+        self.mean[k] += rate * d;
+        self.variance[k] =
+          ((1.0 - rate) * self.variance[k] + rate * d * d).max(floor);
+      }
+      if foreground {
+        *m = [C::Component::WHITE];
+      }
+    }
+    Ok(mask)
+  }
+
+  /// The current estimate of the background, or `None` before the first
+  /// frame
+  pub fn background(&self) -> Option<C> {
+    let mut result = self.template.clone()?;
+    for (c, &mean) in result.components_mut().iter_mut().zip(&self.mean) {
+      *c = component_from_f64(mean);
+    }
+    Some(result)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{error::Error, ImageBuffer};
+
+  #[test]
+  fn temporal_average_and_difference_gray_u8() {
+    let a =
+      ImageBuffer::<u8, 1, false>::with_data(vec![0, 10, 20], 3, 1).unwrap();
+    let b =
+      ImageBuffer::<u8, 1, false>::with_data(vec![2, 10, 80], 3, 1).unwrap();
+    let mut average = TemporalAverage::new();
+    assert!(average.average().is_none());
+    average.push(&a).unwrap();
+    average.push(&b).unwrap();
+    assert_eq!(average.average().unwrap().components(), &[1, 10, 50]);
+    assert!(matches!(
+      average.push(&ImageBuffer::empty(1, 1)),
+      Err(Error::DimensionMismatch { .. })
+    ));
+
+    let mask = frame_difference(&a, &b, 0.1).unwrap();
+    assert_eq!(mask.components(), &[0, 0, 255]);
+  }
+
+  #[test]
+  fn background_model_flags_new_object_rgba_f32() {
+    let scene =
+      ImageBuffer::<f32, 4, true>::with_val(&[0.5, 0.5, 0.5, 1.0], 2, 1);
+    let mut model = BackgroundModel::new(0.1, 3.0);
+    for _ in 0..5 {
+      let mask = model.update(&scene).unwrap();
+      assert_eq!(mask.components(), &[0.0, 0.0]);
+    }
+    let mut moved = scene.clone();
+    *moved.get_pixel_mut(1, 0) = [0.9, 0.1, 0.5, 1.0];
+    assert_eq!(model.update(&moved).unwrap().components(), &[0.0, 1.0]);
+    assert_eq!(
+      model.background().unwrap().get_pixel(0, 0),
+      &[0.5, 0.5, 0.5, 1.0]
+    );
+  }
+
+  #[test]
+  fn background_model_variance_has_a_floor() {
+    let scene = ImageBuffer::<u8, 1, false>::with_val(&[100], 2, 1);
+    let mut model = BackgroundModel::new(0.5, 3.0);
+    for _ in 0..200 {
+      model.update(&scene).unwrap();
+    }
+    // A change of one level is noise, however long the scene was still
+    let noisy = ImageBuffer::with_data(vec![101, 140], 2, 1).unwrap();
+    assert_eq!(model.update(&noisy).unwrap().components(), &[0, 255]);
+  }
+}
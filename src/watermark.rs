@@ -0,0 +1,403 @@
+//! Marking images as belonging to someone, visibly or not.
+//!
+//! - [`embed_lsb`] hides arbitrary bytes in the least significant bits of the
+//!   color channels. It holds a lot of data but does not survive any change to
+//!   the pixels, including lossy compression.
+//! - [`embed_dct`] hides a short payload in the relation between two
+//!   low-frequency DCT coefficients of every 8x8 block of luminance, repeated
+//!   across the image, so it survives mild JPEG recompression and noise.
+//! - [`overlay_logo`] blends a visible logo over the image.
+
+use crate::{
+  error::{Error, Result},
+  pixel::{component_from_f64, is_integer, PixelComponent, PixelContainer},
+  ImageBuffer,
+};
+
+/// Bytes before an LSB payload that hold its length
+const LENGTH_BYTES: usize = 4;
+
+fn color_channels<const N: usize, const A: bool>() -> usize {
+  N - usize::from(A)
+}
+
+fn bits(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+  bytes
+    .iter()
+    .flat_map(|byte| (0..8).rev().map(move |i| byte >> i & 1 == 1))
+}
+
+fn bytes(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+  let bits: Vec<bool> = bits.collect();
+  bits
+    .chunks_exact(8)
+    .map(|byte| byte.iter().fold(0, |acc, &bit| acc << 1 | u8::from(bit)))
+    .collect()
+}
+
+/// Hides `payload` in the lowest bit of each color component of `image`,
+/// after its length. Alpha is left alone, since fully transparent pixels
+/// are often rewritten by encoders.
+///
+/// Fails for floating-point images, which have no least significant bit to
+/// spare, and if the payload does not fit.
+pub fn embed_lsb<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  payload: &[u8],
+) -> Result<ImageBuffer<T, N, A>> {
+  if !is_integer::<T>() {
+    return Err(Error::Unsupported(
+      "LSB embedding needs integer components".to_string(),
+    ));
+  }
+  let colors = color_channels::<N, A>();
+  let capacity = image.width * image.height * colors / 8;
+  let length = u32::try_from(payload.len())
+    .ok()
+    .filter(|_| payload.len() + LENGTH_BYTES <= capacity);
+  let Some(length) = length else {
+    return Err(Error::InvalidArgument(format!(
+      "A payload of {} bytes does not fit in the {capacity} bytes available",
+      payload.len() + LENGTH_BYTES
+    )));
+  };
+  let header = length.to_be_bytes();
+  let mut message = bits(&header).chain(bits(payload));
+  let mut result = image.clone();
+  'pixels: for pel in result.iter_pixels_mut() {
+    for component in &mut pel[..colors] {
+      let Some(bit) = message.next() else {
+        break 'pixels;
+      };
+      let value = component.to_u64().unwrap_or_default() & !1 | u64::from(bit);
+      *component = T::from(value).unwrap_or(*component);
+    }
+  }
+  Ok(result)
+}
+
+/// Reads a payload hidden by [`embed_lsb`]. Fails if `image` does not hold
+/// one; images that were never embedded into usually fail, but may return
+/// garbage.
+pub fn extract_lsb<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+) -> Result<Vec<u8>> {
+  if !is_integer::<T>() {
+    return Err(Error::Unsupported(
+      "LSB extraction needs integer components".to_string(),
+    ));
+  }
+  let colors = color_channels::<N, A>();
+  let mut message = image.iter_pixels().flat_map(|pel| {
+    pel[..colors]
+      .iter()
+      .map(|c| c.to_u64().unwrap_or_default() & 1 == 1)
+  });
+  let header = bytes(message.by_ref().take(LENGTH_BYTES * 8));
+  let capacity = image.width * image.height * colors / 8;
+  let length = match header.try_into() {
+    Ok(header) => u32::from_be_bytes(header) as usize,
+    Err(_) => capacity,
+  };
+  if length + LENGTH_BYTES > capacity {
+    return Err(Error::Decode("Image holds no LSB payload".to_string()));
+  }
+  Ok(bytes(message.take(length * 8)))
+}
+
+/// Side of the blocks that carry one bit each in [`embed_dct`]
+const BLOCK: usize = 8;
+/// Frequencies, horizontal then vertical, of the coefficients whose
+/// difference carries the bit. Low frequencies are quantized least by JPEG
+/// but are visible sooner; these are a compromise.
+const CARRIERS: [(usize, usize); 2] = [(2, 1), (1, 2)];
+
+/// Orthonormal DCT-II basis function `k` at sample `n` of a block
+fn basis(k: usize, n: usize) -> f64 {
+  let scale = if k == 0 { (1.0 / 8.0f64).sqrt() } else { 0.5 };
+  scale * (std::f64::consts::PI * (2 * n + 1) as f64 * k as f64 / 16.0).cos()
+}
+
+/// Luminance of the color channels of `pel`, with white at 1
+fn luma<T: PixelComponent, const N: usize>(
+  pel: &[T; N],
+  colors: usize,
+  white: f64,
+) -> f64 {
+  let unit = |c: usize| pel[c].to_f64().unwrap_or_default() / white;
+  if colors >= 3 {
+    0.2126 * unit(0) + 0.7152 * unit(1) + 0.0722 * unit(2)
+  } else {
+    unit(0)
+  }
+}
+
+/// Checks that `image` is gray or RGB and has room for `payload_bits`,
+/// returning the number of whole blocks across and down
+fn dct_blocks<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  payload_bits: usize,
+) -> Result<(usize, usize)> {
+  if !matches!(color_channels::<N, A>(), 1 | 3) {
+    return Err(Error::Channel(
+      "DCT watermarks need gray or RGB images".to_string(),
+    ));
+  }
+  let blocks = (image.width / BLOCK, image.height / BLOCK);
+  if payload_bits == 0 || blocks.0 * blocks.1 < payload_bits {
+    return Err(Error::InvalidArgument(format!(
+      "A {}x{} image holds between 1 and {} payload bits, not {payload_bits}",
+      image.width,
+      image.height,
+      blocks.0 * blocks.1
+    )));
+  }
+  Ok(blocks)
+}
+
+/// The carrier coefficients of the block at `(bx, by)`
+fn carriers<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  bx: usize,
+  by: usize,
+) -> [f64; 2] {
+  let (colors, white) = (color_channels::<N, A>(), image.white());
+  CARRIERS.map(|(u, v)| {
+    let mut sum = 0.0;
+    for y in 0..BLOCK {
+      for x in 0..BLOCK {
+        let pel = image.get_pixel(bx * BLOCK + x, by * BLOCK + y);
+        sum += luma(pel, colors, white) * basis(u, x) * basis(v, y);
+      }
+    }
+    sum
+  })
+}
+
+/// Hides `payload` in the DCT of the luminance. Each 8x8 block carries one
+/// bit, as which of two coefficients is larger by at least `strength` (with
+/// white at 1), and the payload repeats over all blocks so that it can be
+/// recovered by majority. A `strength` around 0.05 survives JPEG
+/// recompression at quality 80 while staying hard to see; larger values
+/// are more robust and more visible. The same change is made to every
+/// color channel, leaving the hue alone.
+///
+/// Fails unless `image` is gray or RGB and has at least one block per
+/// payload bit.
+pub fn embed_dct<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  payload: &[u8],
+  strength: f64,
+) -> Result<ImageBuffer<T, N, A>> {
+  let message: Vec<bool> = bits(payload).collect();
+  let (across, down) = dct_blocks(image, message.len())?;
+  let (colors, white) = (color_channels::<N, A>(), image.white());
+  let mut result = image.clone();
+  for by in 0..down {
+    for bx in 0..across {
+      let bit = message[(by * across + bx) % message.len()];
+      let [a, b] = carriers(image, bx, by);
+      let sign = if bit { 1.0 } else { -1.0 };
+      if (a - b) * sign >= strength {
+        continue;
+      }
+      let mid = (a + b) / 2.0;
+      let delta = [
+        mid + sign * strength / 2.0 - a,
+        mid - sign * strength / 2.0 - b,
+      ];
+      for y in 0..BLOCK {
+        for x in 0..BLOCK {
+          let change: f64 = CARRIERS
+            .iter()
+            .zip(delta)
+            .map(|(&(u, v), d)| d * basis(u, x) * basis(v, y))
+            .sum();
+          let pel = result.get_pixel_mut(bx * BLOCK + x, by * BLOCK + y);
+          for component in &mut pel[..colors] {
+            let value = component.to_f64().unwrap_or_default() + change * white;
+            *component = component_from_f64(value);
+          }
+        }
+      }
+    }
+  }
+  Ok(result)
+}
+
+/// Reads `length` bytes hidden by [`embed_dct`], letting every block that
+/// carries a bit vote on it. Any image gives some answer, so payloads
+/// should include a checksum or known signature if that matters.
+pub fn extract_dct<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  length: usize,
+) -> Result<Vec<u8>> {
+  let (across, down) = dct_blocks(image, length * 8)?;
+  let mut votes = vec![0.0; length * 8];
+  for by in 0..down {
+    for bx in 0..across {
+      let [a, b] = carriers(image, bx, by);
+      votes[(by * across + bx) % (length * 8)] += a - b;
+    }
+  }
+  Ok(bytes(votes.into_iter().map(|v| v > 0.0)))
+}
+
+/// Where [`overlay_logo`] puts the logo
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+  /// With its top-left corner at the given pixel
+  At(usize, usize),
+  /// In the bottom-right corner, `margin` pixels from the edges
+  BottomRight { margin: usize },
+  /// Repeated over the whole image with `spacing` pixels between copies,
+  /// offsetting alternate rows by half a step so that it cannot be cropped
+  /// out
+  Tiled { spacing: usize },
+}
+
+/// Blends `logo` over `image` at `opacity`, between 0 and 1, using the
+/// logo's alpha. Parts of the logo beyond the image are cut off. Fails
+/// unless `image` has three color channels.
+pub fn overlay_logo<T: PixelComponent, const N: usize, const A: bool>(
+  image: &ImageBuffer<T, N, A>,
+  logo: &ImageBuffer<T, 4, true>,
+  placement: Placement,
+  opacity: f64,
+) -> Result<ImageBuffer<T, N, A>> {
+  if color_channels::<N, A>() != 3 {
+    return Err(Error::Channel(
+      "Logos can only be placed on RGB images".to_string(),
+    ));
+  }
+  let (width, height) = (image.width as isize, image.height as isize);
+  let (lw, lh) = (logo.width as isize, logo.height as isize);
+  let origins: Vec<(isize, isize)> = match placement {
+    Placement::At(x, y) => vec![(x as isize, y as isize)],
+    Placement::BottomRight {
+      margin,
+    } => vec![(width - lw - margin as isize, height - lh - margin as isize)],
+    Placement::Tiled {
+      spacing,
+    } => {
+      let (step_x, step_y) = (lw + spacing as isize, lh + spacing as isize);
+      let mut origins = Vec::new();
+      for (row, y) in (0..height).step_by(step_y.max(1) as usize).enumerate() {
+        let shift = if row % 2 == 1 { step_x / 2 } else { 0 };
+        let mut x = shift - step_x;
+        while x < width {
+          origins.push((x, y));
+          x += step_x.max(1);
+        }
+      }
+      origins
+    }
+  };
+
+  let scale = image.white() / logo.white();
+  let opacity = opacity.clamp(0.0, 1.0);
+  let mut result = image.clone();
+  for (ox, oy) in origins {
+    for ly in 0..lh {
+      for lx in 0..lw {
+        let (x, y) = (ox + lx, oy + ly);
+        if x < 0 || y < 0 || x >= width || y >= height {
+          continue;
+        }
+        let mark = logo.get_pixel(lx as usize, ly as usize);
+        let alpha =
+          mark[3].to_f64().unwrap_or_default() / logo.white() * opacity;
+        let pel = result.get_pixel_mut(x as usize, y as usize);
+        for c in 0..3 {
+          let base = pel[c].to_f64().unwrap_or_default();
+          let over = mark[c].to_f64().unwrap_or_default() * scale;
+          pel[c] = component_from_f64(base + (over - base) * alpha);
+        }
+      }
+    }
+  }
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Smooth content with some texture, like a photograph
+  fn photo() -> ImageBuffer<u8, 3, false> {
+    ImageBuffer::<u8, 3, false>::empty(128, 96).map_indexed(&mut |x, y, _| {
+      let v = 100.0 + 60.0 * (x as f64 / 13.0).sin() * (y as f64 / 9.0).cos();
+      [v as u8, (v * 0.8) as u8, (255.0 - v) as u8]
+    })
+  }
+
+  #[test]
+  fn lsb_round_trip() {
+    let image = photo();
+    let marked = embed_lsb(&image, b"owned by someone").unwrap();
+    assert_eq!(extract_lsb(&marked).unwrap(), b"owned by someone");
+    let changed = marked
+      .components()
+      .iter()
+      .zip(image.components())
+      .all(|(a, b)| a.abs_diff(*b) <= 1);
+    assert!(changed);
+    assert!(embed_lsb(&image, &vec![0; 128 * 96 * 3 / 8]).is_err());
+  }
+
+  #[test]
+  fn dct_mark_survives_noise() {
+    let image = photo();
+    let marked = embed_dct(&image, b"id:42", 0.05).unwrap();
+    // Perturb every pixel a little, as recompression would
+    let mut noisy = marked.clone();
+    for (i, c) in noisy.components_mut().iter_mut().enumerate() {
+      *c = c.saturating_add((i * 7 % 5) as u8).saturating_sub(2);
+    }
+    assert_eq!(extract_dct(&noisy, 5).unwrap(), b"id:42");
+    assert!(extract_dct(&image, 1000).is_err());
+  }
+
+  #[cfg(feature = "jpeg")]
+  #[test]
+  fn dct_mark_survives_jpeg() {
+    use crate::{color_space::ColorSpace, io, Image};
+
+    let marked = embed_dct(&photo(), b"id:42", 0.05).unwrap();
+    let image = Image::new_u8(ColorSpace::Rgb(marked));
+    let jpeg = io::encode(&image, io::ImageFormat::Jpeg).unwrap();
+    let decoded = io::decode(&jpeg).unwrap().to_rgba_f32().unwrap();
+    assert_eq!(extract_dct(&decoded, 5).unwrap(), b"id:42");
+  }
+
+  #[test]
+  fn logo_is_blended_and_tiled() {
+    let image = ImageBuffer::<u8, 3, false>::empty(10, 10);
+    let logo =
+      ImageBuffer::<u8, 4, true>::with_val(&[255, 255, 255, 255], 2, 2);
+    let corner = overlay_logo(
+      &image,
+      &logo,
+      Placement::BottomRight {
+        margin: 1
+      },
+      0.5,
+    )
+    .unwrap();
+    assert_eq!(corner.get_pixel(8, 8), &[128; 3]);
+    assert_eq!(corner.get_pixel(9, 9), &[0; 3]);
+    let tiled = overlay_logo(
+      &image,
+      &logo,
+      Placement::Tiled {
+        spacing: 2
+      },
+      1.0,
+    )
+    .unwrap();
+    assert_eq!(tiled.get_pixel(0, 0), &[255; 3]);
+    assert_eq!(tiled.get_pixel(4, 0), &[255; 3]);
+    assert_eq!(tiled.get_pixel(2, 0), &[0; 3]);
+    assert_eq!(tiled.get_pixel(2, 4), &[255; 3]);
+  }
+}
@@ -0,0 +1,223 @@
+//! 8-bit Y'CbCr 4:2:0 frames in the layouts that video decoders and
+//! cameras produce.
+//!
+//! Chroma is stored at half the width and height of luma, rounded up. Pixels
+//! use the limited range of BT.601, as in
+//! [`ycbcr_to_rgb`](crate::color_space::ycbcr_to_rgb).
+
+use crate::{
+  color_space::{rgb_to_ycbcr, ycbcr_to_rgb},
+  error::{Error, Result},
+  limits::Limits,
+  pixel::PixelContainer,
+  ImageBuffer,
+};
+
+/// One 8-bit plane
+pub type Plane = ImageBuffer<u8, 1, false>;
+
+/// A planar frame, I420: separate luma, Cb and Cr planes
+#[derive(Clone, Debug)]
+pub struct Yuv420Image {
+  pub y:  Plane,
+  pub cb: Plane,
+  pub cr: Plane,
+}
+
+/// A semi-planar frame: a luma plane followed by one plane of interleaved
+/// chroma pairs. `CR_FIRST` says which of the pair comes first; use the
+/// [`Nv12Image`] and [`Nv21Image`] aliases.
+#[derive(Clone, Debug)]
+pub struct SemiPlanarImage<const CR_FIRST: bool> {
+  pub y:      Plane,
+  /// Chroma pairs in storage order: Cb then Cr for NV12, the reverse for
+  /// NV21
+  pub chroma: ImageBuffer<u8, 2, false>,
+}
+
+/// Semi-planar 4:2:0 with Cb first, as from most hardware decoders
+pub type Nv12Image = SemiPlanarImage<false>;
+/// Semi-planar 4:2:0 with Cr first, the default of Android cameras
+pub type Nv21Image = SemiPlanarImage<true>;
+
+fn chroma_size(width: usize, height: usize) -> (usize, usize) {
+  (width.div_ceil(2), height.div_ceil(2))
+}
+
+impl<const CR_FIRST: bool> SemiPlanarImage<CR_FIRST> {
+  pub fn width(&self) -> usize { self.y.width }
+
+  pub fn height(&self) -> usize { self.y.height }
+
+  /// Cb and Cr at chroma position `(cx, cy)`
+  fn cb_cr(&self, cx: usize, cy: usize) -> (u8, u8) {
+    let [a, b] = *self.chroma.get_pixel(cx, cy);
+    if CR_FIRST {
+      (b, a)
+    } else {
+      (a, b)
+    }
+  }
+
+  /// Reads a frame from `bytes`, in which both planes have rows `stride`
+  /// bytes apart and the chroma plane starts right after `height` rows of
+  /// luma. Fails if `stride` is too small or `bytes` too short.
+  pub fn from_bytes(
+    bytes: &[u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+  ) -> Result<Self> {
+    let (cw, ch) = chroma_size(width, height);
+    if stride < cw * 2 || stride < width {
+      return Err(Error::InvalidArgument(format!(
+        "A stride of {stride} bytes is too small for a width of {width}"
+      )));
+    }
+    let chroma_start = stride * height;
+    let expected = match ch {
+      0 => 0,
+      _ => chroma_start + stride * (ch - 1) + cw * 2,
+    };
+    if bytes.len() < expected {
+      return Err(Error::Decode(format!(
+        "Semi-planar frame is {} bytes, expected {expected}",
+        bytes.len()
+      )));
+    }
+    let y = Plane::try_empty(width, height, &Limits::none())?
+      .map_indexed(&mut |x, y, _| [bytes[y * stride + x]]);
+    let chroma = ImageBuffer::try_empty(cw, ch, &Limits::none())?.map_indexed(
+      &mut |x, y, _| {
+        let i = chroma_start + y * stride + x * 2;
+        [bytes[i], bytes[i + 1]]
+      },
+    );
+    Ok(SemiPlanarImage {
+      y,
+      chroma,
+    })
+  }
+
+  /// The frame as tightly packed bytes, luma then chroma
+  pub fn to_bytes(&self) -> Vec<u8> {
+    [self.y.components(), self.chroma.components()].concat()
+  }
+
+  pub fn to_rgb(&self) -> ImageBuffer<u8, 3, false> {
+    ImageBuffer::empty(self.width(), self.height()).map_indexed(
+      &mut |x, y, _| {
+        let (cb, cr) = self.cb_cr(x / 2, y / 2);
+        ycbcr_to_rgb::<u8, u8>(&[self.y.get_pixel(x, y)[0], cb, cr])
+      },
+    )
+  }
+
+  /// Converts `rgb`, averaging the chroma of each 2x2 block
+  pub fn from_rgb(rgb: &ImageBuffer<u8, 3, false>) -> Self {
+    Self::from_yuv420(&Yuv420Image::from_rgb(rgb))
+  }
+
+  /// Splits the chroma into separate planes
+  pub fn to_yuv420(&self) -> Yuv420Image {
+    let (cw, ch) = (self.chroma.width, self.chroma.height);
+    let cb =
+      Plane::empty(cw, ch).map_indexed(&mut |x, y, _| [self.cb_cr(x, y).0]);
+    let cr =
+      Plane::empty(cw, ch).map_indexed(&mut |x, y, _| [self.cb_cr(x, y).1]);
+    Yuv420Image {
+      y: self.y.clone(),
+      cb,
+      cr,
+    }
+  }
+
+  /// Interleaves the chroma of a planar frame
+  pub fn from_yuv420(planar: &Yuv420Image) -> Self {
+    let chroma = ImageBuffer::empty(planar.cb.width, planar.cb.height)
+      .map_indexed(&mut |x, y, _| {
+        let (cb, cr) =
+          (planar.cb.get_pixel(x, y)[0], planar.cr.get_pixel(x, y)[0]);
+        if CR_FIRST {
+          [cr, cb]
+        } else {
+          [cb, cr]
+        }
+      });
+    SemiPlanarImage {
+      y: planar.y.clone(),
+      chroma,
+    }
+  }
+}
+
+impl Yuv420Image {
+  /// Converts `rgb`, averaging the chroma of each 2x2 block
+  pub fn from_rgb(rgb: &ImageBuffer<u8, 3, false>) -> Self {
+    let (width, height) = (rgb.width, rgb.height);
+    let ycbcr: ImageBuffer<u8, 3, false> =
+      rgb.map_into(&mut |pel| rgb_to_ycbcr(pel));
+    let (cw, ch) = chroma_size(width, height);
+    let average = |c: usize, cx: usize, cy: usize| {
+      let (mut sum, mut count) = (0u32, 0u32);
+      for y in cy * 2..(cy * 2 + 2).min(height) {
+        for x in cx * 2..(cx * 2 + 2).min(width) {
+          sum += u32::from(ycbcr.get_pixel(x, y)[c]);
+          count += 1;
+        }
+      }
+      [((sum + count / 2) / count) as u8]
+    };
+    Yuv420Image {
+      y:  ycbcr.map_into(&mut |pel| [pel[0]]),
+      cb: Plane::empty(cw, ch).map_indexed(&mut |x, y, _| average(1, x, y)),
+      cr: Plane::empty(cw, ch).map_indexed(&mut |x, y, _| average(2, x, y)),
+    }
+  }
+
+  pub fn to_rgb(&self) -> ImageBuffer<u8, 3, false> {
+    Nv12Image::from_yuv420(self).to_rgb()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn nv12_and_nv21_differ_only_in_chroma_order() {
+    let rgb = ImageBuffer::<u8, 3, false>::empty(3, 3)
+      .map_indexed(&mut |x, y, _| [(x * 100) as u8, (y * 100) as u8, 50]);
+    let nv12 = Nv12Image::from_rgb(&rgb);
+    let nv21 = Nv21Image::from_rgb(&rgb);
+    assert_eq!((nv12.chroma.width, nv12.chroma.height), (2, 2));
+    let [cb, cr] = *nv12.chroma.get_pixel(1, 1);
+    assert_eq!(nv21.chroma.get_pixel(1, 1), &[cr, cb]);
+    assert_eq!(nv12.to_rgb().components(), nv21.to_rgb().components());
+
+    // Bytes from a decoder, with one byte of padding per row
+    let mut bytes = Vec::new();
+    for row in nv12.y.components().chunks(3) {
+      bytes.extend_from_slice(row);
+      bytes.push(0);
+    }
+    for row in nv12.chroma.components().chunks(4) {
+      bytes.extend_from_slice(row);
+    }
+    let read = Nv12Image::from_bytes(&bytes, 3, 3, 4).unwrap();
+    assert_eq!(read.to_bytes(), nv12.to_bytes());
+    assert!(Nv12Image::from_bytes(&bytes[..15], 3, 3, 4).is_err());
+
+    let planar = nv21.to_yuv420();
+    assert_eq!(planar.cb.get_pixel(1, 1), &[cb]);
+    assert_eq!(Nv21Image::from_yuv420(&planar).to_bytes(), nv21.to_bytes());
+    // A flat color survives the chroma subsampling
+    let flat = ImageBuffer::<u8, 3, false>::with_val(&[30, 160, 90], 4, 2);
+    let back = Yuv420Image::from_rgb(&flat).to_rgb();
+    assert!(back.iter_pixels().all(|p| {
+      p.iter()
+        .zip([30u8, 160, 90])
+        .all(|(a, b)| a.abs_diff(b) <= 2)
+    }));
+  }
+}